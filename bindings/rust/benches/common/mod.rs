@@ -0,0 +1,49 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generators of the inputs of benchmarks.
+
+fn push_leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn push_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    push_leb128(out, contents.len());
+    out.extend_from_slice(contents);
+}
+
+/// A module of `func_count` functions of type [] -> [i32], each with `body_size` instructions.
+pub fn large_module(func_count: usize, body_size: usize) -> Vec<u8> {
+    let mut module = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    push_section(&mut module, 1, &[0x01, 0x60, 0x00, 0x01, 0x7f]);
+
+    let mut functions = Vec::new();
+    push_leb128(&mut functions, func_count);
+    functions.resize(functions.len() + func_count, 0x00);
+    push_section(&mut module, 3, &functions);
+
+    // (i32.const 1) (drop) repeated, then (i32.const 42).
+    let mut body = vec![0x00];
+    for _ in 0..body_size {
+        body.extend_from_slice(&[0x41, 0x01, 0x1a]);
+    }
+    body.extend_from_slice(&[0x41, 0x2a, 0x0b]);
+    let mut code = Vec::new();
+    push_leb128(&mut code, func_count);
+    for _ in 0..func_count {
+        push_leb128(&mut code, body.len());
+        code.extend_from_slice(&body);
+    }
+    push_section(&mut module, 10, &code);
+    module
+}
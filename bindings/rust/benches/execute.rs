@@ -0,0 +1,70 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fizzy::{Caller, ImportsBuilder, Trap, TypedValue};
+
+fn execute(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (func (export "foo") (result i32) (i32.const 42))
+      (func (export "bar") (param i32) (param i64) (result i32) (local.get 0) (i32.wrap_i64 (local.get 1)) (i32.add))
+    )
+    */
+    let input = hex::decode("0061736d01000000010b026000017f60027f7e017f0303020001070d0203666f6f00000362617200010a0f020400412a0b080020002001a76a0b").unwrap();
+    let module = fizzy::parse(&input).unwrap();
+    let mut instance = module.instantiate().unwrap();
+
+    // The exported function is looked up by name only once, then found in the export cache.
+    c.bench_function("execute foo", |b| {
+        b.iter(|| instance.execute(black_box("foo"), &[]).unwrap())
+    });
+    #[cfg(feature = "raw-api")]
+    {
+        let func_idx = instance.find_exported_function_index("foo").unwrap();
+        c.bench_function("execute_unchecked foo", |b| {
+            b.iter(|| unsafe { instance.execute_unchecked(black_box(func_idx), &[]) })
+        });
+    }
+    let args = [TypedValue::U32(1), TypedValue::U64(2)];
+    c.bench_function("execute bar", |b| {
+        b.iter(|| {
+            instance
+                .execute(black_box("bar"), black_box(&args))
+                .unwrap()
+        })
+    });
+}
+
+fn host_call(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (func $add (import "env" "add") (param i32 i32) (result i32))
+      (func (export "call_add") (param i32 i32) (result i32)
+        (call $add (local.get 0) (local.get 1)))
+    )
+    */
+    let input = hex::decode("0061736d0100000001070160027f7f017f020b0103656e7603616464000003020100070c010863616c6c5f61646400010a0a0108002000200110000b").unwrap();
+    let module = fizzy::parse(&input).unwrap();
+    let mut imports = ImportsBuilder::new();
+    imports.func(
+        "env",
+        "add",
+        |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a.wrapping_add(b)) },
+    );
+    let mut instance = module.instantiate_with_imports(imports).unwrap();
+
+    // The round-trip of calling the Rust closure from the module.
+    let args = [TypedValue::U32(1), TypedValue::U32(2)];
+    c.bench_function("execute call_add (host function)", |b| {
+        b.iter(|| {
+            instance
+                .execute(black_box("call_add"), black_box(&args))
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, execute, host_call);
+criterion_main!(benches);
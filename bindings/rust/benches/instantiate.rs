@@ -0,0 +1,99 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::large_module;
+use criterion::{criterion_group, criterion_main, Criterion};
+use fizzy::{Caller, ImportsBuilder, Trap};
+
+fn imports() -> ImportsBuilder {
+    let mut imports = ImportsBuilder::new();
+    for name in &["f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7"] {
+        imports.func(
+            "env",
+            name,
+            |_: &mut Caller, a: u32, b: u64| -> Result<u64, Trap> { Ok(u64::from(a) + b) },
+        );
+    }
+    imports
+}
+
+fn instantiate(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (memory 1)
+      (global (mut i32) (i32.const 0))
+      (func (export "foo") (result i32) (i32.const 42))
+    )
+    */
+    let input = hex::decode("0061736d010000000105016000017f0302010005030100010606017f0141000b07070103666f6f00000a06010400412a0b").unwrap();
+    let module = fizzy::parse(&input).unwrap();
+    c.bench_function("instantiate", |b| b.iter(|| module.instantiate().unwrap()));
+}
+
+fn instantiate_large(c: &mut Criterion) {
+    // About 2 MB.
+    let input = large_module(2000, 350);
+    let module = fizzy::parse(&input).unwrap();
+    c.bench_function("instantiate 2 MB module 100 times", |b| {
+        b.iter(|| {
+            for _ in 0..100 {
+                module.instantiate().unwrap();
+            }
+        })
+    });
+}
+
+fn instantiate_with_imports(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (func (import "env" "f0") (param i32 i64) (result i64))
+      (func (import "env" "f1") (param i32 i64) (result i64))
+      (func (import "env" "f2") (param i32 i64) (result i64))
+      (func (import "env" "f3") (param i32 i64) (result i64))
+      (func (import "env" "f4") (param i32 i64) (result i64))
+      (func (import "env" "f5") (param i32 i64) (result i64))
+      (func (import "env" "f6") (param i32 i64) (result i64))
+      (func (import "env" "f7") (param i32 i64) (result i64))
+    )
+    */
+    let input = hex::decode("0061736d0100000001070160027f7e017e02490803656e76026630000003656e76026631000003656e76026632000003656e76026633000003656e76026634000003656e76026635000003656e76026636000003656e760266370000").unwrap();
+    let module = fizzy::parse(&input).unwrap();
+
+    c.bench_function("instantiate with imports", |b| {
+        b.iter(|| module.instantiate_with_imports(imports()).unwrap())
+    });
+    c.bench_function("instantiate with imports (builder prepared)", |b| {
+        b.iter_batched(
+            imports,
+            |imports| module.instantiate_with_imports(imports).unwrap(),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    let pre = module.clone().pre_instantiate(imports()).unwrap();
+    c.bench_function("instantiate pre-instantiated", |b| {
+        b.iter(|| pre.instantiate().unwrap())
+    });
+
+    let options = fizzy::InstantiateOptions::new().imports(imports());
+    c.bench_function("instantiate with imports 100 times", |b| {
+        b.iter(|| {
+            (0..100)
+                .map(|_| module.instantiate_with(&options).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    c.bench_function("instantiate_many 100 with imports", |b| {
+        b.iter(|| module.instantiate_many(100, &options).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    instantiate,
+    instantiate_large,
+    instantiate_with_imports
+);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fizzy::{ImportsBuilder, InstantiateOptions};
+
+fn grow(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (memory 1 200)
+      ;; Grows the memory by a single page until the maximum is reached.
+      (func (export "grow")
+        (loop $loop
+          (br_if $loop (i32.ne (memory.grow (i32.const 1)) (i32.const -1)))
+        )
+      )
+    )
+    */
+    let input = hex::decode("0061736d01000000010401600000030201000505010101c8010708010467726f7700000a10010e00034041014000417f470d000b0b").unwrap();
+    let module = fizzy::parse(&input).unwrap();
+
+    for preallocate in &[false, true] {
+        let options = InstantiateOptions::new().preallocate_max_memory(*preallocate);
+        let name = format!(
+            "grow to 200 pages (preallocate_max_memory: {})",
+            preallocate
+        );
+        c.bench_function(&name, |b| {
+            b.iter(|| {
+                let mut instance = module
+                    .instantiate_with_options(ImportsBuilder::new(), &options)
+                    .unwrap();
+                instance.execute("grow", &[]).unwrap()
+            })
+        });
+    }
+}
+
+fn get_set(c: &mut Criterion) {
+    /* wat2wasm
+    (module (memory 16))
+    */
+    let input = hex::decode("0061736d010000000503010010").unwrap();
+    let mut instance = fizzy::parse(&input).unwrap().instantiate().unwrap();
+
+    for (size, name) in &[(1024, "1 KiB"), (1024 * 1024, "1 MiB")] {
+        let mut buffer = vec![0; *size];
+        c.bench_function(&format!("memory_get {}", name), |b| {
+            b.iter(|| instance.memory_get(black_box(0), &mut buffer).unwrap())
+        });
+        c.bench_function(&format!("memory_set {}", name), |b| {
+            b.iter(|| instance.memory_set(black_box(0), &buffer).unwrap())
+        });
+    }
+}
+
+/// The peak resident set size of the process, where available.
+fn peak_rss() -> Option<String> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    Some(line["VmHWM:".len()..].trim().to_string())
+}
+
+fn stream(c: &mut Criterion) {
+    /* wat2wasm
+    (module (memory 1024))
+    */
+    let input = hex::decode("0061736d01000000050401008008").unwrap();
+    let instance = fizzy::parse(&input).unwrap().instantiate().unwrap();
+    let size = instance.memory_size();
+
+    // The streaming benchmark runs first, because the peak RSS never decreases.
+    c.bench_function("memory_read_into_writer 64 MiB to sink", |b| {
+        b.iter(|| {
+            instance
+                .memory_read_into_writer(0, size, &mut std::io::sink(), 64 * 1024)
+                .unwrap()
+        })
+    });
+    if let Some(rss) = peak_rss() {
+        println!("peak RSS after streaming: {}", rss);
+    }
+    c.bench_function("memory_get 64 MiB to sink", |b| {
+        b.iter(|| {
+            let mut buffer = vec![0; size];
+            instance.memory_get(0, &mut buffer).unwrap();
+            std::io::Write::write_all(&mut std::io::sink(), &buffer).unwrap()
+        })
+    });
+    if let Some(rss) = peak_rss() {
+        println!("peak RSS after copying: {}", rss);
+    }
+}
+
+criterion_group!(benches, grow, get_set, stream);
+criterion_main!(benches);
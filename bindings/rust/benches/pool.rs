@@ -0,0 +1,44 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fizzy::{InstancePool, ResetPolicy, TypedValue};
+
+fn request(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (memory 1 1)
+      (data (i32.const 0) "\2a")
+      ;; Handles a request by storing the argument.
+      (func (export "handle") (param i32) (result i32)
+        (i32.load8_u (i32.const 0))
+        (i32.store8 (i32.const 0) (local.get 0)))
+    )
+    */
+    let input = hex::decode("0061736d0100000001060160017f017f03020100050401010101070a010668616e646c6500000a10010e0041002d0000410020003a00000b0b07010041000b012a").unwrap();
+    let module = fizzy::parse(&input).unwrap();
+    let args = [TypedValue::U32(1)];
+
+    c.bench_function("request with fresh instance", |b| {
+        b.iter(|| {
+            let mut instance = module.instantiate().unwrap();
+            instance.execute("handle", &args).unwrap()
+        })
+    });
+    for reset in &[ResetPolicy::MemoryFromSnapshot, ResetPolicy::Reinstantiate] {
+        let pool = InstancePool::new(module.clone(), 1, *reset).unwrap();
+        c.bench_function(
+            &format!("request with pooled instance ({:?})", reset),
+            |b| {
+                b.iter(|| {
+                    let mut instance = pool.get().unwrap();
+                    instance.execute("handle", &args).unwrap()
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(benches, request);
+criterion_main!(benches);
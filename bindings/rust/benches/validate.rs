@@ -0,0 +1,53 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::large_module;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+fn validate(c: &mut Criterion) {
+    /* wat2wasm
+    (module
+      (func (export "foo") (result i32) (i32.const 42))
+      (func (export "bar") (param i32) (param i64) (result i32) (local.get 0) (i32.wrap_i64 (local.get 1)) (i32.add))
+    )
+    */
+    let input = hex::decode("0061736d01000000010b026000017f60027f7e017f0303020001070d0203666f6f00000362617200010a0f020400412a0b080020002001a76a0b").unwrap();
+    c.bench_function("parse small module", |b| {
+        b.iter(|| fizzy::parse(&input).unwrap())
+    });
+
+    // About 1 MB.
+    let input = large_module(1000, 350);
+
+    c.bench_function("parse 1 MB", |b| b.iter(|| fizzy::parse(&input).unwrap()));
+    c.bench_function("from_validated 1 MB", |b| {
+        b.iter_batched(
+            || fizzy::validate_owned(input.clone()).unwrap(),
+            fizzy::Module::from_validated,
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn validate_batch(c: &mut Criterion) {
+    // 64 modules of about 100 kB.
+    let inputs: Vec<Vec<u8>> = (0..64).map(|i| large_module(100 + i, 350)).collect();
+
+    c.bench_function("validate 64 x 100 kB", |b| {
+        b.iter(|| {
+            for input in &inputs {
+                fizzy::validate(input).unwrap();
+            }
+        })
+    });
+    // Runs in parallel with the rayon feature.
+    c.bench_function("validate_batch 64 x 100 kB", |b| {
+        b.iter(|| fizzy::validate_batch(inputs.iter().map(Vec::as_slice)))
+    });
+}
+
+criterion_group!(benches, validate, validate_batch);
+criterion_main!(benches);
@@ -8,20 +8,48 @@ extern crate cmake;
 use cmake::Config;
 
 use std::env;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The way the C++ standard library is linked into the final artifact.
+enum CppStdlib {
+    /// Nothing to link (e.g. MSVC links it implicitly).
+    None,
+    /// Link the shared library of the given name.
+    Dynamic(&'static str),
+    /// Link the static archive of the given name.
+    Static(&'static str),
+}
 
 fn main() {
-    let dst = Config::new("fizzy").define("FIZZY_TESTING", "OFF").build();
+    // Set by cargo when the `test-oom` feature is enabled.
+    let test_oom = env::var_os("CARGO_FEATURE_TEST_OOM").is_some();
 
-    println!("cargo:rustc-link-lib=static=fizzy");
-    println!("cargo:rustc-link-search=native={}/lib", dst.display());
+    let dst = Config::new("fizzy")
+        .define("FIZZY_TESTING", "OFF")
+        .define("FIZZY_TEST_OOM", if test_oom { "ON" } else { "OFF" })
+        .build();
+
+    let lib_dir = dst.join("lib");
+    println!("cargo:rustc-link-search=native={}", lib_dir.display());
 
-    // We need to link against C++ std lib
-    if let Some(cpp_stdlib) = get_cpp_stdlib() {
-        println!("cargo:rustc-link-lib={}", cpp_stdlib);
+    // We need to link against C++ std lib. This must come after the fizzy archive, because
+    // linkers resolve symbols of static archives in the order they appear on the command line.
+    match get_cpp_stdlib() {
+        CppStdlib::None => println!("cargo:rustc-link-lib=static=fizzy"),
+        CppStdlib::Dynamic(name) => {
+            println!("cargo:rustc-link-lib=static=fizzy");
+            println!("cargo:rustc-link-lib={}", name);
+        }
+        CppStdlib::Static(name) => link_static_cpp_stdlib(&lib_dir, name),
     }
 
-    let bindings = bindgen::Builder::default()
+    let mut builder = bindgen::Builder::default();
+    if test_oom {
+        builder = builder.clang_arg("-DFIZZY_TEST_OOM");
+    }
+    let bindings = builder
         .header("fizzy/include/fizzy/fizzy.h")
         .generate_comments(true)
         .whitelist_function("fizzy_.*")
@@ -38,17 +66,118 @@ fn main() {
         .expect("Could not write bindings");
 }
 
-// See https://github.com/alexcrichton/gcc-rs/blob/88ac58e25/src/lib.rs#L1197
-fn get_cpp_stdlib() -> Option<String> {
-    env::var("TARGET").ok().and_then(|target| {
-        if target.contains("msvc") {
-            None
-        } else if target.contains("darwin") || target.contains("freebsd") {
-            Some("c++".to_string())
-        } else if target.contains("musl") {
-            Some("static=stdc++".to_string())
-        } else {
-            Some("stdc++".to_string())
+/// Links the fizzy archive together with the static C++ standard library `name`.
+///
+/// The search paths and libraries are passed on to every crate linking this one, but rustc decides
+/// where they appear on the command line, and arguments like `cargo:rustc-link-arg` only reach
+/// the targets of this package. Therefore the objects of the C++ standard library are folded into
+/// a single archive with the fizzy objects, within which the linker resolves the symbols in any
+/// order.
+fn link_static_cpp_stdlib(lib_dir: &Path, name: &str) {
+    // The archive usually lives in the compiler's private library directory, which is not
+    // known to the linker invoked by rustc (especially with musl cross toolchains).
+    let stdlib = find_static_lib_dir(name).map(|dir| dir.join(format!("lib{}.a", name)));
+    if let Some(stdlib) = stdlib {
+        let combined = format!("fizzy-{}", name);
+        if merge_archives(
+            &lib_dir.join(format!("lib{}.a", combined)),
+            &[&lib_dir.join("libfizzy.a"), &stdlib],
+        ) {
+            println!("cargo:rustc-link-lib=static={}", combined);
+            return;
         }
-    })
+        println!(
+            "cargo:warning=cannot merge {} into the fizzy archive, the link may fail",
+            stdlib.display()
+        );
+        if let Some(dir) = stdlib.parent() {
+            println!("cargo:rustc-link-search=native={}", dir.display());
+        }
+    }
+    println!("cargo:rustc-link-lib=static=fizzy");
+    println!("cargo:rustc-link-lib=static={}", name);
+}
+
+/// Writes the members of the `inputs` archives to the archive `output`, with the MRI script mode
+/// of GNU ar. Returns false if the archiver has failed.
+fn merge_archives(output: &Path, inputs: &[&Path]) -> bool {
+    let mut script = format!("create {}\n", output.display());
+    for input in inputs {
+        script += &format!("addlib {}\n", input.display());
+    }
+    script += "save\nend\n";
+
+    let child = Command::new(get_archiver())
+        .arg("-M")
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    let written = child.stdin.take().map_or(false, |mut stdin| {
+        stdin.write_all(script.as_bytes()).is_ok()
+    });
+    child.wait().map_or(false, |status| status.success()) && written
+}
+
+// See https://github.com/alexcrichton/gcc-rs/blob/88ac58e25/src/lib.rs#L1197
+fn get_cpp_stdlib() -> CppStdlib {
+    let target = match env::var("TARGET") {
+        Ok(target) => target,
+        Err(_) => return CppStdlib::None,
+    };
+    // Set by cargo when the `static-cxx` feature is enabled.
+    let static_cxx = env::var_os("CARGO_FEATURE_STATIC_CXX").is_some();
+
+    if target.contains("msvc") {
+        CppStdlib::None
+    } else if target.contains("darwin") || target.contains("freebsd") {
+        // There is no static libc++ shipped with these systems.
+        CppStdlib::Dynamic("c++")
+    } else if target.contains("musl") || static_cxx {
+        CppStdlib::Static("stdc++")
+    } else {
+        CppStdlib::Dynamic("stdc++")
+    }
+}
+
+/// Finds a tool of the target the same way as the cc crate (used by cmake) does, from the
+/// variables `<tool>_<target>`, `TARGET_<tool>` and `<tool>`.
+fn get_target_tool(tool: &str, default: &str) -> String {
+    let target = env::var("TARGET").unwrap_or_default();
+    let candidates = [
+        format!("{}_{}", tool, target),
+        format!("{}_{}", tool, target.replace("-", "_")),
+        format!("TARGET_{}", tool),
+        tool.to_string(),
+    ];
+    candidates
+        .iter()
+        .find_map(|var| env::var(var).ok())
+        .unwrap_or_else(|| default.to_string())
+}
+
+fn get_cxx_compiler() -> String {
+    get_target_tool("CXX", "c++")
+}
+
+fn get_archiver() -> String {
+    get_target_tool("AR", "ar")
+}
+
+/// Asks the C++ compiler where the static archive `lib<name>.a` is located.
+fn find_static_lib_dir(name: &str) -> Option<PathBuf> {
+    let compiler = get_cxx_compiler();
+    let output = Command::new(compiler)
+        .arg(format!("-print-file-name=lib{}.a", name))
+        .output()
+        .ok()?;
+    let path = PathBuf::from(String::from_utf8(output.stdout).ok()?.trim());
+    // When the archive is not found the compiler prints back the bare file name.
+    if path.is_absolute() && path.exists() {
+        path.parent().map(Path::to_path_buf)
+    } else {
+        None
+    }
 }
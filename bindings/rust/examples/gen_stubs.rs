@@ -0,0 +1,31 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Print stubs of the host functions imported by a wasm module.
+//!
+//! ```sh
+//! cargo run --example gen_stubs -- module.wasm > src/host.rs
+//! ```
+
+use std::process::exit;
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: gen_stubs <module.wasm>");
+            exit(2);
+        }
+    };
+    let module = std::fs::read(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|input| fizzy::parse(&input).map_err(|err| err.to_string()));
+    match module {
+        Ok(module) => print!("{}", fizzy::codegen::host_stubs(&module)),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            exit(1);
+        }
+    }
+}
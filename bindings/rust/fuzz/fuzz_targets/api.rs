@@ -0,0 +1,119 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The public API driven by arbitrary modules, names, arguments and memory ranges, which must
+//! return errors instead of panicking.
+//!
+//! The executions are metered with a gas limit, therefore modules with infinite loops trap instead
+//! of timing out.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use fizzy::{CostSchedule, ExecutionOptions, TypedValue};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Arg {
+    I32(u32),
+    I64(u64),
+    F32(u32),
+    F64(u64),
+}
+
+impl From<&Arg> for TypedValue {
+    fn from(arg: &Arg) -> Self {
+        match *arg {
+            Arg::I32(v) => TypedValue::U32(v),
+            Arg::I64(v) => TypedValue::U64(v),
+            Arg::F32(bits) => TypedValue::F32(f32::from_bits(bits)),
+            Arg::F64(bits) => TypedValue::F64(f64::from_bits(bits)),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Lookup {
+        name: String,
+    },
+    Execute {
+        name: String,
+        args: Vec<Arg>,
+        coerce: bool,
+    },
+    SetGlobal {
+        name: String,
+        value: Arg,
+    },
+    Get {
+        offset: u32,
+        len: u16,
+    },
+    Set {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    ReadIntoWriter {
+        offset: u32,
+        len: u16,
+        chunk: u8,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    wasm: Vec<u8>,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let _ = fizzy::validate(&input.wasm);
+    let module = match fizzy::parse_metered(&input.wasm) {
+        Ok(module) => module,
+        Err(_) => return,
+    };
+    let _ = module.exports();
+    let mut instance = match module.instantiate() {
+        Ok(instance) => instance,
+        Err(_) => return,
+    };
+    let options = ExecutionOptions::new()
+        .cost_schedule(&CostSchedule::uniform(1))
+        .gas_limit(100_000);
+    for op in input.ops {
+        match op {
+            Op::Lookup { name } => {
+                let _ = instance.find_exported_function_index(&name);
+                let _ = instance.find_exported_function_index_bytes(name.as_bytes());
+                let _ = instance.global_value(&name);
+                let _ = instance.exported_memory(&name);
+                let _ = instance.exported_table(&name);
+            }
+            Op::Execute { name, args, coerce } => {
+                let args: Vec<TypedValue> = args.iter().map(TypedValue::from).collect();
+                let options = options.clone().coerce_arguments(coerce);
+                let _ = instance.execute_with_options(&name, &args, &options);
+            }
+            Op::SetGlobal { name, value } => {
+                let _ = instance.set_global_value(&name, TypedValue::from(&value));
+            }
+            Op::Get { offset, len } => {
+                let mut target = vec![0; len as usize];
+                let _ = instance.memory_get(offset, &mut target);
+            }
+            Op::Set { offset, data } => {
+                let _ = instance.memory_set(offset, &data);
+            }
+            Op::ReadIntoWriter { offset, len, chunk } => {
+                let _ = instance.memory_read_into_writer(
+                    offset,
+                    len as usize,
+                    &mut std::io::sink(),
+                    chunk as usize,
+                );
+            }
+        }
+    }
+});
@@ -0,0 +1,45 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Instantiation of arbitrary modules, and execution of their first exported function without inputs.
+//!
+//! Modules with imports fail to instantiate and are skipped. The execution is not limited,
+//! therefore infinite loops are reported as timeouts.
+
+#![no_main]
+
+use fizzy::{Error, ExternalKind};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let module = match fizzy::parse(&data) {
+        Ok(module) => module,
+        Err(_) => return,
+    };
+    let mut instance = match module.instantiate() {
+        Ok(instance) => instance,
+        Err(Error::InstantiationFailed(_))
+        | Err(Error::MissingImport { .. })
+        | Err(Error::MemoryAllocationFailed(_)) => return,
+        Err(err) => panic!("unexpected instantiation error: {}", err),
+    };
+    let memory_size = instance.memory_size();
+    for export in module.exports() {
+        if export.kind() != ExternalKind::Function {
+            continue;
+        }
+        match instance.execute(export.name(), &[]) {
+            Ok(result) => {
+                if !result.trapped() {
+                    let _ = result.value();
+                }
+                break;
+            }
+            Err(Error::ArgumentCountMismatch) => continue,
+            Err(err) => panic!("unexpected execution error: {}", err),
+        }
+    }
+    // The memory can only grow.
+    assert!(instance.memory_size() >= memory_size);
+});
@@ -0,0 +1,186 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sequences of memory accesses and growths, checked against a model of the memory.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use fizzy::{Error, ImportsBuilder, Instance, InstantiateOptions, TypedValue};
+use libfuzzer_sys::fuzz_target;
+use std::ops::Range;
+
+const PAGE_SIZE: usize = 65536;
+const MAX_PAGES: usize = 4;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Grow {
+        pages: u8,
+    },
+    Get {
+        offset: u32,
+        len: u16,
+    },
+    Set {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    ReadIntoWriter {
+        offset: u32,
+        len: u16,
+        chunk: u8,
+    },
+    WriteFromReader {
+        offset: u32,
+        data: Vec<u8>,
+        chunk: u8,
+    },
+    Slice {
+        offset: u32,
+        len: u16,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    preallocate_max_memory: bool,
+    ops: Vec<Op>,
+}
+
+fn instance(preallocate_max_memory: bool) -> Instance {
+    /* wat2wasm
+    (module
+      (memory 1 4)
+      (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+    )
+    */
+    let input = hex::decode("0061736d0100000001060160017f017f030201000504010101040708010467726f7700000a08010600200040000b").unwrap();
+    let options = InstantiateOptions::new().preallocate_max_memory(preallocate_max_memory);
+    fizzy::parse(&input)
+        .unwrap()
+        .instantiate_with_options(ImportsBuilder::new(), &options)
+        .unwrap()
+}
+
+/// Map half of the `raw` offsets into the memory, as almost all others are out of bounds.
+fn offset(raw: u32, model: &[u8]) -> u32 {
+    if raw & 1 == 0 {
+        raw
+    } else {
+        (raw >> 1) % (model.len() as u32 + 1)
+    }
+}
+
+/// The range of `len` bytes at `offset` in the model, or None if it is out of bounds.
+fn range(offset: u32, len: usize, model: &[u8]) -> Option<Range<usize>> {
+    let end = (offset as usize).checked_add(len)?;
+    if end <= model.len() {
+        Some(offset as usize..end)
+    } else {
+        None
+    }
+}
+
+/// Check that `result` is an error exactly if the range is out of bounds.
+fn check(result: Result<(), Error>, range: &Option<Range<usize>>) -> bool {
+    match (result, range) {
+        (Ok(()), Some(_)) => true,
+        (Err(Error::InvalidMemoryOffsetOrSize), None) => false,
+        (result, range) => panic!("unexpected result {:?} for the range {:?}", result, range),
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let mut instance = instance(input.preallocate_max_memory);
+    let mut model = vec![0u8; PAGE_SIZE];
+
+    for op in input.ops {
+        match op {
+            Op::Grow { pages } => {
+                let pages = pages as usize % (MAX_PAGES + 1);
+                let result = instance
+                    .execute("grow", &[TypedValue::U32(pages as u32)])
+                    .unwrap();
+                assert!(!result.trapped());
+                let previous = result.value().unwrap().as_i32().unwrap();
+                if model.len() + pages * PAGE_SIZE <= MAX_PAGES * PAGE_SIZE {
+                    assert_eq!(previous as usize, model.len() / PAGE_SIZE);
+                    model.resize(model.len() + pages * PAGE_SIZE, 0);
+                } else {
+                    assert_eq!(previous, -1);
+                }
+            }
+            Op::Get { offset: raw, len } => {
+                let offset = offset(raw, &model);
+                let range = range(offset, len as usize, &model);
+                let mut target = vec![0; len as usize];
+                if check(instance.memory_get(offset, &mut target), &range) {
+                    assert_eq!(target, model[range.unwrap()]);
+                }
+            }
+            Op::Set { offset: raw, data } => {
+                let offset = offset(raw, &model);
+                let range = range(offset, data.len(), &model);
+                if check(instance.memory_set(offset, &data), &range) {
+                    model[range.unwrap()].copy_from_slice(&data);
+                }
+            }
+            Op::ReadIntoWriter {
+                offset: raw,
+                len,
+                chunk,
+            } => {
+                let offset = offset(raw, &model);
+                let range = range(offset, len as usize, &model);
+                let mut writer = Vec::new();
+                let result = instance.memory_read_into_writer(
+                    offset,
+                    len as usize,
+                    &mut writer,
+                    chunk.max(1) as usize,
+                );
+                if check(result, &range) {
+                    assert_eq!(writer, model[range.unwrap()]);
+                } else {
+                    assert!(writer.is_empty());
+                }
+            }
+            Op::WriteFromReader {
+                offset: raw,
+                data,
+                chunk,
+            } => {
+                let offset = offset(raw, &model);
+                let range = range(offset, data.len(), &model);
+                let result = instance.memory_write_from_reader(
+                    offset,
+                    data.len(),
+                    &mut &data[..],
+                    chunk.max(1) as usize,
+                );
+                if check(result, &range) {
+                    model[range.unwrap()].copy_from_slice(&data);
+                }
+            }
+            Op::Slice { offset: raw, len } => {
+                let offset = offset(raw, &model);
+                let range = range(offset, len as usize, &model);
+                let slice = unsafe { instance.checked_memory_slice(offset, len as usize) };
+                match (slice, &range) {
+                    (Ok(slice), Some(range)) => assert_eq!(slice, &model[range.clone()]),
+                    (Err(Error::InvalidMemoryOffsetOrSize), None) => {}
+                    (slice, range) => {
+                        panic!("unexpected slice {:?} for the range {:?}", slice, range)
+                    }
+                }
+            }
+        }
+        assert_eq!(instance.memory_size(), model.len());
+    }
+
+    let mut memory = vec![0; model.len()];
+    instance.memory_get(0, &mut memory).unwrap();
+    assert!(memory == model, "memory differs from the model");
+});
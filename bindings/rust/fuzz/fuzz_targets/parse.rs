@@ -0,0 +1,21 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parsing of arbitrary bytes.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashSet;
+
+fuzz_target!(|data: &[u8]| {
+    let valid = fizzy::validate(data).is_ok();
+    let module = fizzy::parse(&data);
+    assert_eq!(module.is_ok(), valid, "parsing and validation disagree");
+    if let Ok(module) = module {
+        let exports = module.exports();
+        let names: HashSet<&str> = exports.iter().map(|export| export.name()).collect();
+        assert_eq!(names.len(), exports.len(), "duplicate export names");
+    }
+});
@@ -0,0 +1,44 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deserialization of values and types from arbitrary JSON, which must round-trip exactly.
+
+#![no_main]
+
+use fizzy::{FunctionType, TypedValue};
+use libfuzzer_sys::fuzz_target;
+
+/// Compare values by their bits, as NaNs are not equal to themselves.
+fn same_bits(a: TypedValue, b: TypedValue) -> bool {
+    match (a, b) {
+        (TypedValue::F32(a), TypedValue::F32(b)) => a.to_bits() == b.to_bits(),
+        (TypedValue::F64(a), TypedValue::F64(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    if let Ok(value) = serde_json::from_str::<TypedValue>(text) {
+        let json = serde_json::to_string(&value).unwrap();
+        let restored = serde_json::from_str::<TypedValue>(&json).unwrap();
+        assert!(
+            same_bits(restored, value),
+            "{} restored as {:?}",
+            json,
+            restored
+        );
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    }
+    if let Ok(func_type) = serde_json::from_str::<FunctionType>(text) {
+        let json = serde_json::to_string(&func_type).unwrap();
+        assert_eq!(
+            serde_json::from_str::<FunctionType>(&json).unwrap(),
+            func_type
+        );
+    }
+});
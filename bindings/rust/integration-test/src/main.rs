@@ -5,6 +5,33 @@
 extern crate fizzy;
 
 fn main() {
-    assert!(fizzy::validate(&[]).is_ok());
+    assert!(fizzy::validate(&[]).is_err());
+
+    // The built-in self-test covers the arithmetic, the memory and traps.
+    let report = fizzy::self_check().expect("self check failed");
+    println!(
+        "Fizzy {} self check: parsed in {:?}, instantiated in {:?}, executed in {:?}",
+        report.version, report.parse_time, report.instantiate_time, report.execute_time
+    );
+
+    // This wasm binary exports a single sum(u32, u32) -> u32 function.
+    // Parsing and executing it makes sure the C++ runtime (including static initialization)
+    // works in the final binary, which is not the case for a broken static link.
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f,
+        0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x73, 0x75, 0x6d, 0x00, 0x00,
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+    ];
+    let module = fizzy::parse(&wasm).expect("parsing failed");
+    let mut instance = module.instantiate().expect("instantiation failed");
+    let result = instance
+        .execute(
+            "sum",
+            &[fizzy::TypedValue::U32(42), fizzy::TypedValue::U32(24)],
+        )
+        .expect("execution failed");
+    assert!(!result.trapped());
+    assert_eq!(result.value().and_then(|v| v.as_u32()), Some(66));
+
     println!("Fizzy works!");
 }
@@ -0,0 +1,404 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Allocating the memory of instances by the allocators exported by the programs.
+
+use crate::{Error, FunctionType, Instance, TrapInfo, TypedValue, ValueType};
+use std::convert::TryFrom;
+
+/// The alignment requested from the allocators taking one.
+const ALIGN: u32 = 8;
+
+/// The allocator exported by a program, used by [`Instance::guest_alloc`] and
+/// [`Instance::guest_free`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllocStrategy {
+    /// Detect the first pair of functions exported by the program of:
+    ///
+    /// - `malloc(size: i32) -> i32` and `free(ptr: i32)` of C,
+    /// - `__wbindgen_malloc(size: i32[, align: i32]) -> i32` and
+    ///   `__wbindgen_free(ptr: i32, size: i32[, align: i32])` of wasm-bindgen,
+    /// - `canonical_abi_realloc(old_ptr: i32, old_size: i32, align: i32, new_size: i32) -> i32`
+    ///   and `canonical_abi_free(ptr: i32, size: i32, align: i32)` of the canonical ABI.
+    Auto,
+    /// Use the functions `alloc(size: i32[, align: i32]) -> i32` and
+    /// `free(ptr: i32[, size: i32[, align: i32]])` exported by the program.
+    Custom { alloc: String, free: String },
+}
+
+impl Default for AllocStrategy {
+    fn default() -> Self {
+        AllocStrategy::Auto
+    }
+}
+
+/// How the arguments are passed to an allocating function.
+#[derive(Clone, Copy)]
+enum AllocArgs {
+    Size,
+    SizeAlign,
+    Realloc,
+}
+
+/// How the arguments are passed to a freeing function.
+#[derive(Clone, Copy)]
+enum FreeArgs {
+    Ptr,
+    PtrSize,
+    PtrSizeAlign,
+}
+
+const FAMILIES: [(&str, &[AllocArgs], &str, &[FreeArgs]); 3] = [
+    ("malloc", &[AllocArgs::Size], "free", &[FreeArgs::Ptr]),
+    (
+        "__wbindgen_malloc",
+        &[AllocArgs::Size, AllocArgs::SizeAlign],
+        "__wbindgen_free",
+        &[FreeArgs::PtrSize, FreeArgs::PtrSizeAlign],
+    ),
+    (
+        "canonical_abi_realloc",
+        &[AllocArgs::Realloc],
+        "canonical_abi_free",
+        &[FreeArgs::PtrSizeAlign],
+    ),
+];
+
+const CUSTOM_ALLOC: &[AllocArgs] = &[AllocArgs::Size, AllocArgs::SizeAlign];
+const CUSTOM_FREE: &[FreeArgs] = &[FreeArgs::Ptr, FreeArgs::PtrSize, FreeArgs::PtrSizeAlign];
+
+impl AllocArgs {
+    fn func_type(self) -> FunctionType {
+        let inputs = match self {
+            AllocArgs::Size => 1,
+            AllocArgs::SizeAlign => 2,
+            AllocArgs::Realloc => 4,
+        };
+        FunctionType::new(vec![ValueType::I32; inputs], Some(ValueType::I32))
+    }
+
+    fn args(self, len: u32) -> Vec<TypedValue> {
+        match self {
+            AllocArgs::Size => vec![TypedValue::U32(len)],
+            AllocArgs::SizeAlign => vec![TypedValue::U32(len), TypedValue::U32(ALIGN)],
+            AllocArgs::Realloc => vec![
+                TypedValue::U32(0),
+                TypedValue::U32(0),
+                TypedValue::U32(ALIGN),
+                TypedValue::U32(len),
+            ],
+        }
+    }
+
+    fn signature(args: &[AllocArgs]) -> &'static str {
+        match args {
+            [AllocArgs::Size] => "(i32) -> i32",
+            [AllocArgs::Realloc] => "(i32, i32, i32, i32) -> i32",
+            _ => "(i32[, i32]) -> i32",
+        }
+    }
+}
+
+impl FreeArgs {
+    fn func_type(self) -> FunctionType {
+        let inputs = match self {
+            FreeArgs::Ptr => 1,
+            FreeArgs::PtrSize => 2,
+            FreeArgs::PtrSizeAlign => 3,
+        };
+        FunctionType::new(vec![ValueType::I32; inputs], None)
+    }
+
+    fn args(self, ptr: u32, len: u32) -> Vec<TypedValue> {
+        let mut args = vec![
+            TypedValue::U32(ptr),
+            TypedValue::U32(len),
+            TypedValue::U32(ALIGN),
+        ];
+        args.truncate(self.func_type().inputs.len());
+        args
+    }
+
+    fn signature(args: &[FreeArgs]) -> &'static str {
+        match args {
+            [FreeArgs::Ptr] => "(i32)",
+            [FreeArgs::PtrSizeAlign] => "(i32, i32, i32)",
+            [FreeArgs::PtrSize, FreeArgs::PtrSizeAlign] => "(i32, i32[, i32])",
+            _ => "(i32[, i32[, i32]])",
+        }
+    }
+}
+
+impl Instance {
+    /// Use `strategy` to find the allocator of the program, [`AllocStrategy::Auto`] by default.
+    pub fn set_alloc_strategy(&mut self, strategy: AllocStrategy) {
+        self.alloc_strategy = strategy;
+    }
+
+    /// Allocate `len` bytes of the memory by the allocator exported by the program, see
+    /// [`AllocStrategy`], and return their address.
+    ///
+    /// Fails with [`Error::Other`] listing the functions looked for if the program exports no
+    /// allocator, or if the allocator returns a null pointer or an address such that the bytes
+    /// would not end within the 32-bit address space, and with [`Error::Trapped`] if it traps.
+    pub fn guest_alloc(&mut self, len: u32) -> Result<u32, Error> {
+        let (name, args) = self.find_alloc()?;
+        let result = self.execute(&name, &args.args(len))?;
+        if result.trapped() {
+            return Err(Error::Trapped(TrapInfo::new(&name, self.take_host_trap())));
+        }
+        match result.value().and_then(|value| value.as_u32()) {
+            Some(ptr) if ptr != 0 && ptr.checked_add(len).is_some() => Ok(ptr),
+            Some(ptr) => Err(Error::Other(format!(
+                "cannot allocate {} bytes: {} has returned {}",
+                len, name, ptr
+            ))),
+            None => unreachable!("the allocator returns an i32"),
+        }
+    }
+
+    /// Free `len` bytes of the memory at `ptr`, allocated by [`Instance::guest_alloc`], by the
+    /// function exported by the program along with the allocator.
+    ///
+    /// Fails with [`Error::Other`] listing the functions looked for if the program exports no
+    /// such function, and with [`Error::Trapped`] if it traps.
+    pub fn guest_free(&mut self, ptr: u32, len: u32) -> Result<(), Error> {
+        let (name, args) = self.find_free()?;
+        let result = self.execute(&name, &args.args(ptr, len))?;
+        if result.trapped() {
+            return Err(Error::Trapped(TrapInfo::new(&name, self.take_host_trap())));
+        }
+        Ok(())
+    }
+
+    /// Allocate the memory for `data` by [`Instance::guest_alloc`], copy it there and return its
+    /// address. The memory is owned by the program, therefore it is freed by
+    /// [`Instance::guest_free`] with the length of `data`, or by the program itself.
+    pub fn copy_in(&mut self, data: &[u8]) -> Result<u32, Error> {
+        let len = u32::try_from(data.len()).map_err(|_| {
+            Error::Other(format!(
+                "cannot allocate {} bytes in the 32-bit address space",
+                data.len()
+            ))
+        })?;
+        let ptr = self.guest_alloc(len)?;
+        self.memory_set(ptr, data)?;
+        Ok(ptr)
+    }
+
+    /// Find the allocating function of the strategy and how it takes the arguments.
+    fn find_alloc(&self) -> Result<(String, AllocArgs), Error> {
+        let candidates: Vec<(&str, &[AllocArgs])> = match &self.alloc_strategy {
+            AllocStrategy::Auto => FAMILIES
+                .iter()
+                .map(|(alloc, args, _, _)| (*alloc, *args))
+                .collect(),
+            AllocStrategy::Custom { alloc, .. } => vec![(alloc.as_str(), CUSTOM_ALLOC)],
+        };
+        for (name, args) in &candidates {
+            if let Some(args) = args
+                .iter()
+                .find(|args| self.exports_function(name, &args.func_type()))
+            {
+                return Ok((name.to_string(), *args));
+            }
+        }
+        Err(Error::Other(format!(
+            "the module exports no allocator, looked for {}",
+            candidates
+                .iter()
+                .map(|(name, args)| format!("{}{}", name, AllocArgs::signature(args)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+
+    /// Find the freeing function matching the allocating function of the strategy, and how it
+    /// takes the arguments.
+    fn find_free(&self) -> Result<(String, FreeArgs), Error> {
+        let (name, candidates) = match &self.alloc_strategy {
+            AllocStrategy::Auto => {
+                let (alloc, _) = self.find_alloc()?;
+                let &(_, _, free, args) = FAMILIES
+                    .iter()
+                    .find(|(name, _, _, _)| *name == alloc)
+                    .expect("the allocator is of a family");
+                (free.to_string(), args)
+            }
+            AllocStrategy::Custom { free, .. } => (free.clone(), CUSTOM_FREE),
+        };
+        match candidates
+            .iter()
+            .find(|args| self.exports_function(&name, &args.func_type()))
+        {
+            Some(args) => Ok((name, *args)),
+            None => Err(Error::Other(format!(
+                "the module exports no function to free the memory, looked for {}{}",
+                name,
+                FreeArgs::signature(candidates)
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn fnv1a(data: &[u8]) -> u32 {
+        data.iter().fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    #[test]
+    fn malloc() {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (global $heap (mut i32) (i32.const 1024))
+          (func (export "malloc") (param $size i32) (result i32)
+            (global.get $heap)
+            (global.set $heap (i32.add (global.get $heap) (local.get $size))))
+          (func (export "free") (param $ptr i32) (i32.store (i32.const 0) (local.get $ptr)))
+          (func (export "hash") (param $ptr i32) (param $len i32) (result i32)
+            (local $hash i32) (local $end i32)
+            (local.set $hash (i32.const 0x811c9dc5))
+            (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+            (block $done
+              (loop $next
+                (br_if $done (i32.ge_u (local.get $ptr) (local.get $end)))
+                (local.set $hash
+                  (i32.mul (i32.xor (local.get $hash) (i32.load8_u (local.get $ptr)))
+                    (i32.const 0x01000193)))
+                (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                (br $next)))
+            (local.get $hash))
+        )
+        */
+        let input = hex::decode("0061736d0100000001100360017f017f60017f0060027f7f017f03040300010205030100010607017f014180080b071803066d616c6c6f63000004667265650001046861736800020a53030b002300230020006a24000b0900410020003602000b3b01027f41c5bbf288782102200020016a210302400340200020034f0d01200220002d00007341938380086c2102200041016a21000c000b0b20020b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        // The guest hashes the buffer copied in, which can be read back.
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let ptr = instance.copy_in(data).unwrap();
+        assert_eq!(ptr, 1024);
+        let result = instance
+            .execute(
+                "hash",
+                &[TypedValue::U32(ptr), TypedValue::U32(data.len() as u32)],
+            )
+            .unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(fnv1a(data))));
+        let mut copy = vec![0; data.len()];
+        instance.memory_get(ptr, &mut copy).unwrap();
+        assert_eq!(copy, data);
+
+        instance.guest_free(ptr, data.len() as u32).unwrap();
+        let mut freed = [0; 4];
+        instance.memory_get(0, &mut freed).unwrap();
+        assert_eq!(u32::from_le_bytes(freed), ptr);
+
+        assert_eq!(instance.guest_alloc(16), Ok(1024 + data.len() as u32));
+        assert_eq!(
+            instance.guest_alloc(u32::MAX),
+            Err(Error::Other(
+                "cannot allocate 4294967295 bytes: malloc has returned 1083".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn conventions() {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (global $heap (mut i32) (i32.const 1001))
+          (func $alloc (param $size i32) (param $align i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr
+              (i32.and
+                (i32.add (global.get $heap) (i32.sub (local.get $align) (i32.const 1)))
+                (i32.sub (i32.const 0) (local.get $align))))
+            (global.set $heap (i32.add (local.get $ptr) (local.get $size)))
+            (local.get $ptr))
+          (func $free (param $ptr i32) (param $size i32)
+            (i32.store (i32.const 0) (local.get $ptr))
+            (i32.store (i32.const 4) (local.get $size)))
+          (func (export "canonical_abi_realloc") (param i32 i32 i32 i32) (result i32)
+            (call $alloc (local.get 3) (local.get 2)))
+          (func (export "canonical_abi_free") (param i32 i32 i32)
+            (call $free (local.get 0) (local.get 1)))
+          (func (export "my_alloc") (param i32 i32) (result i32)
+            (call $alloc (local.get 0) (local.get 1)))
+          (func (export "my_free") (param i32 i32) (call $free (local.get 0) (local.get 1)))
+        )
+        */
+        let input = hex::decode("0061736d01000000011a0460027f7f017f60027f7f0060047f7f7f7f017f60037f7f7f0003070600010203000105030100010607017f0141e9070b0743041563616e6f6e6963616c5f6162695f7265616c6c6f6300021263616e6f6e6963616c5f6162695f667265650003086d795f616c6c6f630004076d795f6672656500050a54061d01017f2300200141016b6a410020016b712102200220006a240020020b100041002000360200410420013602000b08002003200210000b08002000200110010b08002000200110000b08002000200110010b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let freed = |instance: &Instance| {
+            let mut freed = [0; 8];
+            instance.memory_get(0, &mut freed).unwrap();
+            (
+                u32::from_le_bytes([freed[0], freed[1], freed[2], freed[3]]),
+                u32::from_le_bytes([freed[4], freed[5], freed[6], freed[7]]),
+            )
+        };
+
+        // The canonical ABI allocator is detected, and the memory is aligned.
+        let ptr = instance.copy_in(&[1, 2, 3]).unwrap();
+        assert_eq!(ptr, 1008);
+        instance.guest_free(ptr, 3).unwrap();
+        assert_eq!(freed(&instance), (1008, 3));
+
+        instance.set_alloc_strategy(AllocStrategy::Custom {
+            alloc: "my_alloc".to_string(),
+            free: "my_free".to_string(),
+        });
+        let ptr = instance.guest_alloc(10).unwrap();
+        assert_eq!(ptr, 1016);
+        instance.guest_free(ptr, 10).unwrap();
+        assert_eq!(freed(&instance), (1016, 10));
+
+        instance.set_alloc_strategy(AllocStrategy::Custom {
+            alloc: "canonical_abi_realloc".to_string(),
+            free: "missing".to_string(),
+        });
+        assert_eq!(
+            instance.guest_alloc(1),
+            Err(Error::Other(
+                "the module exports no allocator, looked for \
+                 canonical_abi_realloc(i32[, i32]) -> i32"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            instance.guest_free(ptr, 10),
+            Err(Error::Other(
+                "the module exports no function to free the memory, looked for \
+                 missing(i32[, i32[, i32]])"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn no_allocator() {
+        /* wat2wasm
+        (module (memory 1) (func (export "main") (param i32 i32) (result i32) (local.get 0)))
+        */
+        let input = hex::decode("0061736d0100000001070160027f7f017f030201000503010001070801046d61696e00000a0601040020000b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let expected = Err(Error::Other(
+            "the module exports no allocator, looked for malloc(i32) -> i32, \
+             __wbindgen_malloc(i32[, i32]) -> i32, \
+             canonical_abi_realloc(i32, i32, i32, i32) -> i32"
+                .to_string(),
+        ));
+        assert_eq!(instance.guest_alloc(1), expected);
+        assert_eq!(instance.copy_in(b"data"), expected);
+        assert_eq!(instance.guest_free(1024, 4), expected.map(|_| ()));
+    }
+}
@@ -0,0 +1,248 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caching parsed modules by the digest of their binaries.
+
+use crate::{parse_shared, Error, Module};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+struct CacheEntry {
+    module: Arc<Module>,
+    /// The binary of the module, shared with it.
+    bytes: Arc<[u8]>,
+    /// The value of [`CacheState::clock`] when the module was last returned.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<u64, CacheEntry>,
+    /// The total size of the binaries of the cached modules.
+    bytes: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    /// The cached module of `bytes` of the digest `digest`, marked as the most recently used.
+    fn lookup(&mut self, digest: u64, bytes: &[u8]) -> Option<Arc<Module>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(&digest)?;
+        if *entry.bytes != *bytes {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.module.clone())
+    }
+}
+
+/// A cache of parsed modules shared by the components parsing the same binaries, e.g. those of
+/// contracts, which can be used from multiple threads.
+///
+/// The modules are looked up by [`Module::digest`] and the binary is compared on a hit, therefore
+/// a collision of digests is a miss. The least recently used modules are evicted when the number
+/// of modules or the total size of their binaries exceeds the capacity.
+///
+/// Eviction only drops the reference held by the cache: the modules and their instances remain
+/// valid as long as they are referenced elsewhere, as they share the parsed module.
+///
+/// ```
+/// let cache = fizzy::ModuleCache::new(16);
+/// let wasm = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+/// let module = cache.parse_or_get(&wasm).expect("parsing failed");
+/// let same = cache.parse_or_get(&wasm).expect("parsing failed");
+/// assert!(std::sync::Arc::ptr_eq(&module, &same));
+/// assert_eq!((cache.hits(), cache.misses()), (1, 1));
+/// ```
+pub struct ModuleCache {
+    max_modules: usize,
+    max_bytes: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ModuleCache {
+    /// Create a cache of at most `capacity` modules.
+    pub fn new(capacity: usize) -> Self {
+        ModuleCache::with_limits(capacity, usize::MAX)
+    }
+
+    /// Create a cache of modules whose binaries total at most `max_bytes`.
+    ///
+    /// A binary larger than `max_bytes` is parsed but not cached.
+    pub fn with_max_bytes(max_bytes: usize) -> Self {
+        ModuleCache::with_limits(usize::MAX, max_bytes)
+    }
+
+    fn with_limits(max_modules: usize, max_bytes: usize) -> Self {
+        ModuleCache {
+            max_modules,
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The module of the binary `bytes`, parsed and cached unless it is cached already.
+    ///
+    /// The binary is parsed without holding the lock of the cache, therefore threads parsing
+    /// different binaries do not wait for each other. The errors of parsing are not cached.
+    pub fn parse_or_get(&self, bytes: &[u8]) -> Result<Arc<Module>, Error> {
+        let digest = crate::digest(bytes);
+        if let Some(module) = self.lock().lookup(digest, bytes) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(module);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let shared: Arc<[u8]> = bytes.into();
+        let module = Arc::new(parse_shared(shared.clone())?);
+
+        let mut state = self.lock();
+        // Another thread may have cached the same binary in the meantime.
+        if let Some(module) = state.lookup(digest, bytes) {
+            return Ok(module);
+        }
+        if self.max_modules == 0 || bytes.len() > self.max_bytes {
+            return Ok(module);
+        }
+        if let Some(colliding) = state.entries.remove(&digest) {
+            state.bytes -= colliding.bytes.len();
+        }
+        while state.entries.len() >= self.max_modules || state.bytes + bytes.len() > self.max_bytes
+        {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(digest, _)| *digest)
+                .expect("the limits are exceeded only by cached modules");
+            let evicted = state.entries.remove(&oldest).unwrap();
+            state.bytes -= evicted.bytes.len();
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.bytes += bytes.len();
+        state.entries.insert(
+            digest,
+            CacheEntry {
+                module: module.clone(),
+                bytes: shared,
+                last_used,
+            },
+        );
+        Ok(module)
+    }
+
+    /// The number of calls of [`ModuleCache::parse_or_get`] which returned a cached module.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of calls of [`ModuleCache::parse_or_get`] which parsed the binary.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of cached modules.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// True if no module is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop all cached modules, keeping the counters.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.entries.clear();
+        state.bytes = 0;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = "0061736d01000000";
+    /* wat2wasm
+    (module (func (export "add") (param i64 i64) (result i64) (i64.add (local.get 0) (local.get 1))))
+    */
+    const ADD: &str =
+        "0061736d0100000001070160027e7e017e030201000707010361646400000a09010700200020017c0b";
+    /* wat2wasm
+    (module (func (export "sum") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1))))
+    */
+    const SUM: &str =
+        "0061736d0100000001070160027f7f017f030201000707010373756d00000a09010700200020016a0b";
+
+    #[test]
+    fn hit() {
+        let cache = ModuleCache::new(4);
+        let module = cache.parse_or_get(&hex::decode(ADD).unwrap()).unwrap();
+        let same = cache.parse_or_get(&hex::decode(ADD).unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&module, &same));
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.len(), 1);
+
+        // The errors are not cached.
+        assert!(cache.parse_or_get(&[0x00]).is_err());
+        assert!(cache.parse_or_get(&[0x00]).is_err());
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn eviction_by_count() {
+        let cache = ModuleCache::new(2);
+        let empty = hex::decode(EMPTY).unwrap();
+        let add = hex::decode(ADD).unwrap();
+        let sum = hex::decode(SUM).unwrap();
+        let first = cache.parse_or_get(&empty).unwrap();
+        cache.parse_or_get(&add).unwrap();
+        // The empty module becomes the most recently used, therefore add is evicted.
+        cache.parse_or_get(&empty).unwrap();
+        cache.parse_or_get(&sum).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&first, &cache.parse_or_get(&empty).unwrap()));
+        assert_eq!((cache.hits(), cache.misses()), (2, 3));
+        cache.parse_or_get(&add).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 4));
+
+        // The evicted modules remain usable.
+        let instance = first.instantiate().unwrap();
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(instance.memory_size(), 0);
+    }
+
+    #[test]
+    fn eviction_by_bytes() {
+        let empty = hex::decode(EMPTY).unwrap();
+        let add = hex::decode(ADD).unwrap();
+        let sum = hex::decode(SUM).unwrap();
+        let cache = ModuleCache::with_max_bytes(add.len() + sum.len());
+        cache.parse_or_get(&add).unwrap();
+        cache.parse_or_get(&sum).unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.parse_or_get(&empty).unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.parse_or_get(&sum).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+
+        // The binaries larger than the capacity are not cached.
+        let cache = ModuleCache::with_max_bytes(empty.len());
+        cache.parse_or_get(&add).unwrap();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generation of Rust source code for the host side of a module.
+
+use crate::{ExternalType, FunctionType, Module, ValueType};
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Generate Rust source code of stubs of the host functions imported by `module`.
+///
+/// A function is declared for each imported function, with the signature accepted by
+/// [`ImportsBuilder::func`](crate::ImportsBuilder::func) and the body `todo!()`, followed by
+/// `register_all(builder: &mut ImportsBuilder)` registering all of them. The imports of other kinds
+/// are listed in comments, as host functions are the only imports supported by `ImportsBuilder`.
+/// Long lines are not wrapped, so the code may need to be formatted by rustfmt.
+///
+/// ```
+/// /* (module (func (import "env" "add") (param i32 i32) (result i32))) */
+/// let input = [
+///     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+///     0x7f, 0x01, 0x7f, 0x02, 0x0b, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x03, 0x61, 0x64, 0x64,
+///     0x00, 0x00,
+/// ];
+/// let module = fizzy::parse(&input).unwrap();
+/// let stubs = fizzy::codegen::host_stubs(&module);
+/// assert!(stubs.contains(
+///     "pub fn env_add(_caller: &mut Caller, _arg0: i32, _arg1: i32) -> Result<i32, Trap> {"
+/// ));
+/// ```
+pub fn host_stubs(module: &Module) -> String {
+    let mut functions = Vec::new();
+    let mut other_imports = Vec::new();
+    let mut registered = HashSet::new();
+    let mut identifiers = HashSet::new();
+    for import in module.imports() {
+        let func_type = match import.ty() {
+            ExternalType::Function(func_type) => func_type,
+            ty => {
+                other_imports.push(format!(
+                    "// The {:?} import {:?}.{:?} is not supported by ImportsBuilder.",
+                    ty.kind(),
+                    import.module(),
+                    import.name()
+                ));
+                continue;
+            }
+        };
+        // A function imported multiple times is registered once.
+        if !registered.insert((import.module().to_string(), import.name().to_string())) {
+            continue;
+        }
+        let identifier = unique_identifier(
+            &mut identifiers,
+            &format!("{}_{}", import.module(), import.name()),
+        );
+        functions.push((
+            import.module().to_string(),
+            import.name().to_string(),
+            identifier,
+            func_type.clone(),
+        ));
+    }
+
+    let mut code = String::new();
+    code.push_str("// Host functions of the imports, generated by fizzy::codegen::host_stubs.\n\n");
+    if functions.is_empty() {
+        code.push_str("use fizzy::ImportsBuilder;\n");
+    } else {
+        code.push_str("use fizzy::{Caller, ImportsBuilder, Trap};\n");
+    }
+    if !other_imports.is_empty() {
+        code.push('\n');
+        for line in &other_imports {
+            code.push_str(line);
+            code.push('\n');
+        }
+    }
+    for (module_name, name, identifier, func_type) in &functions {
+        code.push('\n');
+        write_stub(&mut code, module_name, name, identifier, func_type);
+    }
+    code.push_str("\n/// Register the host functions of all imports.\n");
+    if functions.is_empty() {
+        code.push_str("pub fn register_all(_builder: &mut ImportsBuilder) {}\n");
+    } else {
+        code.push_str("pub fn register_all(builder: &mut ImportsBuilder) {\n");
+        for (module_name, name, identifier, _) in &functions {
+            writeln!(
+                code,
+                "    builder.func({:?}, {:?}, {});",
+                module_name, name, identifier
+            )
+            .unwrap();
+        }
+        code.push_str("}\n");
+    }
+    code
+}
+
+fn write_stub(
+    code: &mut String,
+    module_name: &str,
+    name: &str,
+    identifier: &str,
+    func_type: &FunctionType,
+) {
+    let mut params = vec!["_caller: &mut Caller".to_string()];
+    params.extend(
+        func_type
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, value_type)| format!("_arg{}: {}", index, rust_type(*value_type))),
+    );
+    let output = func_type.output.map_or("()", rust_type);
+    writeln!(
+        code,
+        "/// The host function of the import {:?}.{:?}.",
+        module_name, name
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub fn {}({}) -> Result<{}, Trap> {{",
+        identifier,
+        params.join(", "),
+        output
+    )
+    .unwrap();
+    code.push_str("    todo!()\n}\n");
+}
+
+/// The Rust type of `value_type` in host functions.
+fn rust_type(value_type: ValueType) -> &'static str {
+    match value_type {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+        // The modules using the reference types fail to parse.
+        _ => unreachable!("unsupported value type"),
+    }
+}
+
+/// Make a Rust identifier of `name`, distinct from the `used` ones, and add it to them.
+fn unique_identifier(used: &mut HashSet<String>, name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) || identifier == "register_all" {
+        identifier.insert(0, '_');
+    }
+    let mut candidate = identifier.clone();
+    let mut suffix = 1;
+    while !used.insert(candidate.clone()) {
+        suffix += 1;
+        candidate = format!("{}_{}", identifier, suffix);
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_identifier(&mut used, "env_add"), "env_add");
+        assert_eq!(unique_identifier(&mut used, "env_add"), "env_add_2");
+        assert_eq!(unique_identifier(&mut used, "env.add"), "env_add_3");
+        assert_eq!(unique_identifier(&mut used, "1_π"), "_1__");
+        assert_eq!(
+            unique_identifier(&mut used, "register_all"),
+            "_register_all"
+        );
+    }
+
+    #[test]
+    fn other_imports() {
+        /* wat2wasm
+        (module
+          (memory (import "env" "memory") 1)
+          (global (import "env" "g") i32)
+        )
+        */
+        let input =
+            hex::decode("0061736d0100000002180203656e76066d656d6f727902000103656e760167037f00")
+                .unwrap();
+        let stubs = host_stubs(&crate::parse(&input).unwrap());
+        assert!(stubs.contains(
+            "\n// The Memory import \"env\".\"memory\" is not supported by ImportsBuilder.\n\
+             // The Global import \"env\".\"g\" is not supported by ImportsBuilder.\n"
+        ));
+        assert!(stubs.ends_with("pub fn register_all(_builder: &mut ImportsBuilder) {}\n"));
+    }
+
+    #[test]
+    fn no_imports() {
+        let module = crate::parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(
+            host_stubs(&module),
+            "// Host functions of the imports, generated by fizzy::codegen::host_stubs.\n\n\
+             use fizzy::ImportsBuilder;\n\n\
+             /// Register the host functions of all imports.\n\
+             pub fn register_all(_builder: &mut ImportsBuilder) {}\n"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The stable numeric codes of errors and traps, for clients in other languages, see
+//! [`Error::stable_code`] and [`TrapKind::stable_code`].
+//!
+//! The registry only grows: a code is never reassigned, and the code of a removed variant stays
+//! reserved. Error codes and trap codes are separate, a trapped execution has the error code
+//! [`TRAPPED`] and the trap code of its [`TrapKind`].
+//!
+//! With the `serde` feature, [`coded`] serializes errors by their codes.
+
+use crate::{Error, TrapInfo, TrapKind};
+
+use std::sync::Arc;
+
+/// [`Error::MalformedModule`].
+pub const MALFORMED_MODULE: u32 = 1;
+/// [`Error::TextFormat`].
+pub const TEXT_FORMAT: u32 = 2;
+/// [`Error::InvalidModule`].
+pub const INVALID_MODULE: u32 = 3;
+/// [`Error::InstantiationFailed`].
+pub const INSTANTIATION_FAILED: u32 = 4;
+/// [`Error::MissingImport`].
+pub const MISSING_IMPORT: u32 = 5;
+/// [`Error::MemoryAllocationFailed`].
+pub const MEMORY_ALLOCATION_FAILED: u32 = 6;
+/// [`Error::FunctionNotFound`].
+pub const FUNCTION_NOT_FOUND: u32 = 7;
+/// [`Error::ArgumentCountMismatch`].
+pub const ARGUMENT_COUNT_MISMATCH: u32 = 8;
+/// [`Error::ArgumentTypeMismatch`].
+pub const ARGUMENT_TYPE_MISMATCH: u32 = 9;
+/// [`Error::ArgumentOutOfRange`].
+pub const ARGUMENT_OUT_OF_RANGE: u32 = 10;
+/// [`Error::NoMemoryAvailable`].
+pub const NO_MEMORY_AVAILABLE: u32 = 11;
+/// [`Error::InvalidMemoryOffsetOrSize`].
+pub const INVALID_MEMORY_OFFSET_OR_SIZE: u32 = 12;
+/// [`Error::Busy`].
+pub const BUSY: u32 = 13;
+/// [`Error::Trapped`].
+pub const TRAPPED: u32 = 14;
+/// [`Error::Io`].
+pub const IO: u32 = 15;
+/// [`Error::UnsupportedType`].
+pub const UNSUPPORTED_TYPE: u32 = 16;
+/// [`Error::LimitExceeded`].
+pub const LIMIT_EXCEEDED: u32 = 17;
+/// [`Error::DivergenceDetected`].
+pub const DIVERGENCE_DETECTED: u32 = 18;
+/// [`Error::OutOfTicks`].
+pub const OUT_OF_TICKS: u32 = 19;
+/// [`Error::Other`].
+pub const OTHER: u32 = 20;
+/// [`Error::UnsupportedFeature`].
+pub const UNSUPPORTED_FEATURE: u32 = 21;
+
+/// [`TrapKind::Wasm`].
+pub const TRAP_WASM: u32 = 1;
+/// [`TrapKind::Host`].
+pub const TRAP_HOST: u32 = 2;
+/// [`TrapKind::CallDepthExceeded`].
+pub const TRAP_CALL_DEPTH_EXCEEDED: u32 = 3;
+
+impl Error {
+    /// The stable code of the kind of the error, see [`codes`](crate::codes).
+    pub fn stable_code(&self) -> u32 {
+        // Without a catch-all arm, a new variant fails to compile until it is assigned a code.
+        match self {
+            Error::MalformedModule(_) => MALFORMED_MODULE,
+            Error::TextFormat(_) => TEXT_FORMAT,
+            Error::InvalidModule(_) => INVALID_MODULE,
+            Error::InstantiationFailed(_) => INSTANTIATION_FAILED,
+            Error::MissingImport { .. } => MISSING_IMPORT,
+            Error::MemoryAllocationFailed(_) => MEMORY_ALLOCATION_FAILED,
+            Error::FunctionNotFound => FUNCTION_NOT_FOUND,
+            Error::ArgumentCountMismatch => ARGUMENT_COUNT_MISMATCH,
+            Error::ArgumentTypeMismatch => ARGUMENT_TYPE_MISMATCH,
+            Error::ArgumentOutOfRange { .. } => ARGUMENT_OUT_OF_RANGE,
+            Error::NoMemoryAvailable => NO_MEMORY_AVAILABLE,
+            Error::InvalidMemoryOffsetOrSize => INVALID_MEMORY_OFFSET_OR_SIZE,
+            Error::Busy => BUSY,
+            Error::Trapped(_) => TRAPPED,
+            Error::Io(_) => IO,
+            Error::UnsupportedType(_) => UNSUPPORTED_TYPE,
+            Error::LimitExceeded { .. } => LIMIT_EXCEEDED,
+            Error::DivergenceDetected { .. } => DIVERGENCE_DETECTED,
+            Error::OutOfTicks => OUT_OF_TICKS,
+            Error::Other(_) => OTHER,
+            Error::UnsupportedFeature { .. } => UNSUPPORTED_FEATURE,
+        }
+    }
+
+    /// Reconstruct the error of the stable code `code` and the message `message`, e.g. of an
+    /// error received from another process.
+    ///
+    /// The errors consisting of a message, or of nothing, are restored exactly, I/O errors only
+    /// with their message, and [`Error::UnsupportedType`] with the name of the type in its message. The fields of the other errors, e.g. of [`Error::MissingImport`],
+    /// cannot be restored from the message, therefore they are restored as [`Error::Other`], as
+    /// are the unknown codes. Their fields are preserved by the serialization of the `serde`
+    /// feature.
+    pub fn from_stable_code(code: u32, message: &str) -> Error {
+        let message = message.to_string();
+        match code {
+            MALFORMED_MODULE => Error::MalformedModule(message),
+            TEXT_FORMAT => Error::TextFormat(message),
+            INVALID_MODULE => Error::InvalidModule(message),
+            INSTANTIATION_FAILED => Error::InstantiationFailed(message),
+            MEMORY_ALLOCATION_FAILED => Error::MemoryAllocationFailed(message),
+            FUNCTION_NOT_FOUND => Error::FunctionNotFound,
+            ARGUMENT_COUNT_MISMATCH => Error::ArgumentCountMismatch,
+            ARGUMENT_TYPE_MISMATCH => Error::ArgumentTypeMismatch,
+            NO_MEMORY_AVAILABLE => Error::NoMemoryAvailable,
+            INVALID_MEMORY_OFFSET_OR_SIZE => Error::InvalidMemoryOffsetOrSize,
+            BUSY => Error::Busy,
+            IO => Error::Io(Arc::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                message,
+            ))),
+            UNSUPPORTED_TYPE => match unsupported_type_name(&message) {
+                Some(name) => Error::UnsupportedType(name.to_string()),
+                None => Error::Other(message),
+            },
+            OUT_OF_TICKS => Error::OutOfTicks,
+            _ => Error::Other(message),
+        }
+    }
+}
+
+/// The name of the type in the message of [`Error::UnsupportedType`].
+fn unsupported_type_name(message: &str) -> Option<&str> {
+    message
+        .strip_prefix("the value type ")?
+        .strip_suffix(" is not supported")
+}
+
+impl TrapKind {
+    /// The stable code of the kind of the trap, see [`codes`](crate::codes).
+    pub fn stable_code(&self) -> u32 {
+        match self {
+            TrapKind::Wasm => TRAP_WASM,
+            TrapKind::Host(_) => TRAP_HOST,
+            TrapKind::CallDepthExceeded => TRAP_CALL_DEPTH_EXCEEDED,
+        }
+    }
+}
+
+impl TrapInfo {
+    /// The stable code of the kind of the trap, see [`TrapKind::stable_code`].
+    pub fn stable_code(&self) -> u32 {
+        self.kind.stable_code()
+    }
+}
+
+/// The serialization of errors by their stable codes, for `#[serde(with = "fizzy::codes::coded")]`,
+/// e.g. `{"code":7,"message":"function not found"}`, with the `trap_code` of the trapped
+/// executions. The errors are deserialized by [`Error::from_stable_code`].
+#[cfg(feature = "serde")]
+pub mod coded {
+    use crate::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(crate = "serde")]
+    struct CodedError {
+        code: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trap_code: Option<u32>,
+        message: String,
+    }
+
+    /// Serialize `err` by its code and message.
+    pub fn serialize<S: Serializer>(err: &Error, serializer: S) -> Result<S::Ok, S::Error> {
+        CodedError {
+            code: err.stable_code(),
+            trap_code: match err {
+                Error::Trapped(info) => Some(info.stable_code()),
+                _ => None,
+            },
+            message: err.to_string(),
+        }
+        .serialize(serializer)
+    }
+
+    /// Deserialize an error from its code and message.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Error, D::Error> {
+        let coded = CodedError::deserialize(deserializer)?;
+        Ok(Error::from_stable_code(coded.code, &coded.message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ExternalKind, Trap};
+    use std::collections::HashSet;
+
+    /// An error of each kind, in the order of their codes, which must be extended with each new
+    /// kind.
+    fn errors() -> Vec<Error> {
+        vec![
+            Error::MalformedModule("invalid magic".to_string()),
+            Error::TextFormat("unexpected token".to_string()),
+            Error::InvalidModule("invalid type index".to_string()),
+            Error::InstantiationFailed("start function failed".to_string()),
+            Error::MissingImport {
+                module: "env".to_string(),
+                name: "f".to_string(),
+                kind: ExternalKind::Function,
+            },
+            Error::MemoryAllocationFailed("memory growth failed".to_string()),
+            Error::FunctionNotFound,
+            Error::ArgumentCountMismatch,
+            Error::ArgumentTypeMismatch,
+            Error::ArgumentOutOfRange { index: 1 },
+            Error::NoMemoryAvailable,
+            Error::InvalidMemoryOffsetOrSize,
+            Error::Busy,
+            Error::Trapped(TrapInfo::new("run", Some(Trap::new("failed")))),
+            Error::Io(Arc::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "broken pipe",
+            ))),
+            Error::UnsupportedType("funcref".to_string()),
+            Error::LimitExceeded {
+                which: "max_functions".to_string(),
+                limit: 10,
+                actual: 11,
+            },
+            Error::DivergenceDetected {
+                ours: "returned i32:1".to_string(),
+                theirs: "trapped".to_string(),
+            },
+            Error::OutOfTicks,
+            Error::Other("unknown".to_string()),
+            Error::UnsupportedFeature { feature: "simd" },
+        ]
+    }
+
+    #[test]
+    fn registry() {
+        let errors = errors();
+        let kinds: HashSet<ErrorKind> = errors.iter().map(Error::kind).collect();
+        assert_eq!(kinds.len(), errors.len());
+        // Each kind has its own code, and the codes are assigned in order without gaps.
+        let codes: Vec<u32> = errors.iter().map(Error::stable_code).collect();
+        assert_eq!(codes, (1..=21).collect::<Vec<_>>());
+        assert_eq!(
+            (errors[0].stable_code(), errors[13].stable_code()),
+            (MALFORMED_MODULE, TRAPPED)
+        );
+        assert_eq!(errors[19].stable_code(), OTHER);
+
+        assert_eq!(TrapKind::Wasm.stable_code(), TRAP_WASM);
+        assert_eq!(
+            TrapKind::Host("failed".to_string()).stable_code(),
+            TRAP_HOST
+        );
+        assert_eq!(
+            TrapKind::CallDepthExceeded.stable_code(),
+            TRAP_CALL_DEPTH_EXCEEDED
+        );
+        assert_eq!(
+            TrapInfo::new("run", Some(Trap::call_depth_exceeded())).stable_code(),
+            TRAP_CALL_DEPTH_EXCEEDED
+        );
+    }
+
+    #[test]
+    fn from_stable_code() {
+        for err in errors() {
+            let restored = Error::from_stable_code(err.stable_code(), &err.to_string());
+            assert_eq!(restored.to_string(), err.to_string());
+            match err {
+                Error::MissingImport { .. }
+                | Error::ArgumentOutOfRange { .. }
+                | Error::Trapped(_)
+                | Error::LimitExceeded { .. }
+                | Error::DivergenceDetected { .. }
+                | Error::UnsupportedFeature { .. } => {
+                    assert_eq!(restored, Error::Other(err.to_string()))
+                }
+                _ => assert_eq!(restored, err),
+            }
+        }
+        assert_eq!(
+            Error::from_stable_code(1000, "from the future"),
+            Error::Other("from the future".to_string())
+        );
+        assert_eq!(
+            Error::from_stable_code(UNSUPPORTED_TYPE, "funcref"),
+            Error::Other("funcref".to_string())
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn coded() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        #[serde(crate = "serde")]
+        struct Response {
+            #[serde(with = "crate::codes::coded")]
+            error: Error,
+        }
+
+        let response = Response {
+            error: Error::FunctionNotFound,
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            json,
+            r#"{"error":{"code":7,"message":"function not found"}}"#
+        );
+        let restored: Response = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.error, Error::FunctionNotFound);
+
+        let response = Response {
+            error: Error::Trapped(TrapInfo::new("run", Some(Trap::new("failed")))),
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["error"]["code"], TRAPPED);
+        assert_eq!(json["error"]["trap_code"], TRAP_HOST);
+    }
+}
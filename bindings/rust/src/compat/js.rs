@@ -0,0 +1,383 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A layer shaped after the [JavaScript API](https://webassembly.github.io/spec/js-api/) of
+//! WebAssembly, for porting code structured around `WebAssembly.Module` and `WebAssembly.Instance`.
+//!
+//! The objects of an instance share it, as in JavaScript, so they are not `Send`. Only functions
+//! can be provided in the imports object, as [`ImportsBuilder`] supports only host functions.
+//!
+//! ```
+//! use fizzy::compat::js::{Instance, Module, Val};
+//!
+//! // This wasm binary exports a single sum(u32, u32) -> u32 function.
+//! let wasm = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+//!     0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x73, 0x75, 0x6d,
+//!     0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+//! ];
+//! // const instance = new WebAssembly.Instance(new WebAssembly.Module(wasm), {});
+//! let instance = Instance::new(&Module::new(&wasm)?, Default::default())?;
+//! // instance.exports.sum(42, 24)
+//! let sum = instance.exports().get_function("sum")?;
+//! assert_eq!(sum.call(&[Val::I32(42), Val::I32(24)])?, Some(Val::I32(66)));
+//! # Ok::<(), fizzy::Error>(())
+//! ```
+
+use crate::engine::InstanceApi;
+use crate::{Error, Export, ExternalKind, FunctionType, Import, ImportsBuilder, Trap, TypedValue};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A value passed to or returned from a function, with the signedness of JavaScript integers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Val {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    /// The reserved reference types, which are not supported yet.
+    FuncRef,
+    ExternRef,
+}
+
+impl From<TypedValue> for Val {
+    fn from(value: TypedValue) -> Self {
+        match value {
+            TypedValue::U32(v) => Val::I32(v as i32),
+            TypedValue::U64(v) => Val::I64(v as i64),
+            TypedValue::F32(v) => Val::F32(v),
+            TypedValue::F64(v) => Val::F64(v),
+            TypedValue::FuncRef => Val::FuncRef,
+            TypedValue::ExternRef => Val::ExternRef,
+            _ => unreachable!("unsupported value"),
+        }
+    }
+}
+
+impl From<Val> for TypedValue {
+    fn from(value: Val) -> Self {
+        match value {
+            Val::I32(v) => TypedValue::U32(v as u32),
+            Val::I64(v) => TypedValue::U64(v as u64),
+            Val::F32(v) => TypedValue::F32(v),
+            Val::F64(v) => TypedValue::F64(v),
+            Val::FuncRef => TypedValue::FuncRef,
+            Val::ExternRef => TypedValue::ExternRef,
+        }
+    }
+}
+
+/// The closure of a [`HostFunction`].
+type HostFn = Box<dyn FnMut(&[Val]) -> Result<Option<Val>, Trap> + Send>;
+
+/// A function provided in an [`ImportsObject`].
+pub struct HostFunction {
+    ty: FunctionType,
+    func: HostFn,
+}
+
+impl HostFunction {
+    /// Create a function of the type `ty` calling `func`.
+    ///
+    /// Returning a value not matching the output of `ty` results in a trap.
+    pub fn new<F>(ty: FunctionType, func: F) -> Self
+    where
+        F: FnMut(&[Val]) -> Result<Option<Val>, Trap> + Send + 'static,
+    {
+        HostFunction {
+            ty,
+            func: Box::new(func),
+        }
+    }
+}
+
+/// The imports of a module by the names of the module and of the import, as the `importObject`
+/// of `new WebAssembly.Instance(module, importObject)`.
+pub type ImportsObject = HashMap<String, HashMap<String, HostFunction>>;
+
+/// A compiled module, as `WebAssembly.Module`.
+pub struct Module {
+    module: crate::Module,
+}
+
+impl Module {
+    /// Parse and validate a module, as `new WebAssembly.Module(bytes)`.
+    pub fn new(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(Module {
+            module: crate::parse(&bytes)?,
+        })
+    }
+
+    /// The exports of the module, as `WebAssembly.Module.exports(module)`.
+    pub fn exports(&self) -> Vec<Export> {
+        self.module.exports()
+    }
+
+    /// The imports of the module, as `WebAssembly.Module.imports(module)`.
+    pub fn imports(&self) -> Vec<Import> {
+        self.module.imports()
+    }
+}
+
+/// An instance of a module, as `WebAssembly.Instance`.
+pub struct Instance {
+    exports: Exports,
+}
+
+impl Instance {
+    /// Instantiate `module` with the functions of `imports`, as `new WebAssembly.Instance(module, imports)`.
+    pub fn new(module: &Module, imports: ImportsObject) -> Result<Self, Error> {
+        let mut builder = ImportsBuilder::new();
+        for (module_name, functions) in imports {
+            for (name, HostFunction { ty, mut func }) in functions {
+                builder.func_with_type(&module_name, &name, ty, move |_, args| {
+                    let args: Vec<Val> = args.iter().map(|arg| Val::from(*arg)).collect();
+                    Ok(func(&args)?.map(TypedValue::from))
+                });
+            }
+        }
+        let instance = module.module.instantiate_with_imports(builder)?;
+        Ok(Instance {
+            exports: Exports {
+                instance: Rc::new(RefCell::new(instance)),
+                exports: module.module.exports(),
+            },
+        })
+    }
+
+    /// The exports of the instance, as `instance.exports`.
+    pub fn exports(&self) -> &Exports {
+        &self.exports
+    }
+}
+
+/// The exports of an instance, as `instance.exports`.
+pub struct Exports {
+    instance: Rc<RefCell<crate::Instance>>,
+    exports: Vec<Export>,
+}
+
+impl Exports {
+    fn find(&self, name: &str, kind: ExternalKind) -> Option<&Export> {
+        self.exports
+            .iter()
+            .find(|export| export.name() == name && export.kind() == kind)
+    }
+
+    /// The names of the exports, in the order of the export section.
+    pub fn names(&self) -> Vec<&str> {
+        self.exports.iter().map(Export::name).collect()
+    }
+
+    /// The exported function `name`, as `instance.exports[name]`.
+    pub fn get_function(&self, name: &str) -> Result<Function, Error> {
+        self.find(name, ExternalKind::Function)
+            .ok_or(Error::FunctionNotFound)?;
+        let ty = self
+            .instance
+            .borrow()
+            .function_type(name)
+            .ok_or(Error::FunctionNotFound)?;
+        Ok(Function {
+            instance: Rc::clone(&self.instance),
+            name: name.to_string(),
+            ty,
+        })
+    }
+
+    /// The exported memory `name`, as `instance.exports[name]`.
+    pub fn get_memory(&self, name: &str) -> Result<Memory, Error> {
+        self.find(name, ExternalKind::Memory)
+            .ok_or(Error::NoMemoryAvailable)?;
+        Ok(Memory {
+            instance: Rc::clone(&self.instance),
+        })
+    }
+}
+
+/// An exported function.
+pub struct Function {
+    instance: Rc<RefCell<crate::Instance>>,
+    name: String,
+    ty: FunctionType,
+}
+
+impl Function {
+    /// The type of the function.
+    pub fn ty(&self) -> &FunctionType {
+        &self.ty
+    }
+
+    /// The number of inputs of the function, as `func.length`.
+    pub fn length(&self) -> usize {
+        self.ty.inputs.len()
+    }
+
+    /// Call the function, as `func(...args)`. Traps are reported as [`Error::Trapped`], as
+    /// `WebAssembly.RuntimeError` is thrown in JavaScript.
+    ///
+    /// The arguments must match the type of the function, they are not converted as in JavaScript.
+    pub fn call(&self, args: &[Val]) -> Result<Option<Val>, Error> {
+        let args: Vec<TypedValue> = args.iter().map(|arg| TypedValue::from(*arg)).collect();
+        let result = self.instance.borrow_mut().call(&self.name, &args)?;
+        Ok(result.map(Val::from))
+    }
+}
+
+/// An exported memory, as `WebAssembly.Memory`.
+pub struct Memory {
+    instance: Rc<RefCell<crate::Instance>>,
+}
+
+impl Memory {
+    /// The size of the memory in bytes, as `memory.buffer.byteLength`.
+    pub fn byte_length(&self) -> usize {
+        self.instance.borrow().memory_size()
+    }
+
+    /// A copy of the contents of the memory, in place of `memory.buffer`.
+    ///
+    /// Unlike an `ArrayBuffer`, the copy does not reflect later changes of the memory, and
+    /// changing it does not change the memory. Use [`Memory::write`] to change the memory.
+    pub fn buffer(&self) -> Vec<u8> {
+        let instance = self.instance.borrow();
+        let mut buffer = vec![0; instance.memory_size()];
+        instance
+            .memory_get(0, &mut buffer)
+            .expect("memory is smaller than its size");
+        buffer
+    }
+
+    /// Copy the memory at `offset` to `target`, as `target.set(new Uint8Array(memory.buffer, offset, target.length))`.
+    pub fn read(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+        self.instance.borrow().memory_get(offset, target)
+    }
+
+    /// Copy `source` to the memory at `offset`, as `new Uint8Array(memory.buffer).set(source, offset)`.
+    pub fn write(&self, offset: u32, source: &[u8]) -> Result<(), Error> {
+        self.instance.borrow_mut().memory_set(offset, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ValueType;
+
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn ported_snippet() {
+        /* wat2wasm
+        (module
+          (func $log (import "env" "log") (param i32))
+          (memory (export "memory") 1)
+          (func (export "store") (param i32 i32)
+            (i32.store8 (local.get 0) (local.get 1))
+            (call $log (local.get 1))
+          )
+          (func (export "load") (param i32) (result i32) (i32.load8_s (local.get 0)))
+        )
+        */
+        let bytes = hex::decode("0061736d01000000010f0360017f0060027f7f0060017f017f020b0103656e76036c6f67000003030201020503010001071903066d656d6f727902000573746f72650001046c6f616400020a17020d00200020013a0000200110000b070020002c00000b").unwrap();
+
+        // const logged = [];
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        // const module = new WebAssembly.Module(bytes);
+        let module = Module::new(&bytes).unwrap();
+        // const instance = new WebAssembly.Instance(module, { env: { log: (x) => logged.push(x) } });
+        let mut env = HashMap::new();
+        let log = Arc::clone(&logged);
+        env.insert(
+            "log".to_string(),
+            HostFunction::new(FunctionType::new(vec![ValueType::I32], None), move |args| {
+                log.lock().unwrap().push(args[0]);
+                Ok(None)
+            }),
+        );
+        let mut imports = ImportsObject::new();
+        imports.insert("env".to_string(), env);
+        let instance = Instance::new(&module, imports).unwrap();
+        // instance.exports.store(7, -2);
+        let store = instance.exports().get_function("store").unwrap();
+        assert_eq!(store.length(), 2);
+        assert_eq!(store.call(&[Val::I32(7), Val::I32(-2)]).unwrap(), None);
+        // assert(logged[0] === -2);
+        assert_eq!(*logged.lock().unwrap(), [Val::I32(-2)]);
+        // const memory = instance.exports.memory;
+        let memory = instance.exports().get_memory("memory").unwrap();
+        // assert(new Uint8Array(memory.buffer)[7] === 254);
+        assert_eq!(memory.byte_length(), 65536);
+        assert_eq!(memory.buffer()[7], 254);
+        // new Uint8Array(memory.buffer).set([0x80], 8);
+        memory.write(8, &[0x80]).unwrap();
+        // assert(instance.exports.load(8) === -128);
+        let load = instance.exports().get_function("load").unwrap();
+        assert_eq!(load.call(&[Val::I32(8)]).unwrap(), Some(Val::I32(-128)));
+
+        let mut bytes = [0; 2];
+        memory.read(7, &mut bytes).unwrap();
+        assert_eq!(bytes, [254, 0x80]);
+        assert_eq!(instance.exports().names(), ["memory", "store", "load"]);
+        assert_eq!(module.imports().len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        /* wat2wasm
+        (module
+          (func $f (import "env" "f") (result i32))
+          (func (export "f") (result i32) (call $f))
+          (func (export "trap") (unreachable))
+          (global (export "g") i32 (i32.const 0))
+        )
+        */
+        let bytes = hex::decode("0061736d010000000108026000017f60000002090103656e760166000003030200010606017f0041000b0710030166000104747261700002016703000a0a02040010000b0300000b").unwrap();
+        let module = Module::new(&bytes).unwrap();
+
+        assert_eq!(
+            Module::new(&[0x00]).err().unwrap(),
+            Error::MalformedModule("invalid wasm module prefix".to_string())
+        );
+        assert!(Instance::new(&module, ImportsObject::new())
+            .err()
+            .unwrap()
+            .is_missing_import());
+
+        let mut env = HashMap::new();
+        env.insert(
+            "f".to_string(),
+            HostFunction::new(FunctionType::new(vec![], Some(ValueType::I32)), |_| {
+                Ok(Some(Val::I64(0)))
+            }),
+        );
+        let mut imports = ImportsObject::new();
+        imports.insert("env".to_string(), env);
+        let instance = Instance::new(&module, imports).unwrap();
+        let exports = instance.exports();
+        assert!(matches!(
+            exports.get_function("f").unwrap().call(&[]),
+            Err(Error::Trapped(_))
+        ));
+        assert!(matches!(
+            exports.get_function("trap").unwrap().call(&[]),
+            Err(Error::Trapped(_))
+        ));
+        assert_eq!(
+            exports.get_function("g").err().unwrap(),
+            Error::FunctionNotFound
+        );
+        assert_eq!(
+            exports.get_memory("memory").err().unwrap(),
+            Error::NoMemoryAvailable
+        );
+        assert_eq!(
+            exports.get_function("trap").unwrap().call(&[Val::I32(1)]),
+            Err(Error::ArgumentCountMismatch)
+        );
+    }
+}
@@ -0,0 +1,7 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compatibility layers shaped after the APIs of other WebAssembly embeddings.
+
+pub mod js;
@@ -0,0 +1,202 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The defaults applied to the modules parsed and the instances created through a
+//! [`RuntimeConfig`].
+
+use crate::{parse_with, Error, Instance, InstantiateOptions, Module, ParseOptions};
+
+use std::sync::Arc;
+
+/// The defaults of parsing and instantiation shared by all the modules and instances of e.g. a
+/// service, configured once instead of at every call:
+///
+/// ```ignore
+/// let config = fizzy::RuntimeConfig::new().memory_pages_limit(64).max_call_depth(256);
+/// let module = config.parse(&wasm)?;
+/// let mut instance = config.instantiate(&module)?;
+/// ```
+///
+/// The options given to [`RuntimeConfig::instantiate_with`] override the defaults. The modules
+/// parsed and the instances created by the free functions, e.g. [`parse`](crate::parse) and
+/// [`Module::instantiate`], are not affected.
+///
+/// The clones share the defaults, therefore cloning is cheap, and the configuration can be
+/// shared between threads.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig(Arc<Defaults>);
+
+#[derive(Clone, Debug, Default)]
+struct Defaults {
+    parse_options: ParseOptions,
+    memory_pages_limit: Option<u32>,
+    max_call_depth: Option<u32>,
+}
+
+impl RuntimeConfig {
+    /// Create the configuration of the same defaults as the free functions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the modules within the limits of `options`, see [`parse_with`].
+    pub fn parse_options(mut self, options: ParseOptions) -> Self {
+        Arc::make_mut(&mut self.0).parse_options = options;
+        self
+    }
+
+    /// Limit the memory of the instances, see [`InstantiateOptions::memory_pages_limit`].
+    pub fn memory_pages_limit(mut self, max_pages: u32) -> Self {
+        Arc::make_mut(&mut self.0).memory_pages_limit = Some(max_pages);
+        self
+    }
+
+    /// Limit the call depth of the executions of the instances, see
+    /// [`InstantiateOptions::max_call_depth`].
+    pub fn max_call_depth(mut self, max_depth: u32) -> Self {
+        Arc::make_mut(&mut self.0).max_call_depth = Some(max_depth);
+        self
+    }
+
+    /// Parse and validate the input within the limits of [`RuntimeConfig::parse_options`].
+    pub fn parse<T: AsRef<[u8]>>(&self, input: &T) -> Result<Module, Error> {
+        parse_with(input, &self.0.parse_options)
+    }
+
+    /// Create an instance of `module` with the defaults, like [`Module::instantiate`].
+    pub fn instantiate(&self, module: &Module) -> Result<Instance, Error> {
+        self.instantiate_with(module, &InstantiateOptions::new())
+    }
+
+    /// Create an instance of `module` with `options`, like [`Module::instantiate_with`], taking
+    /// the defaults for the limits not set by `options`.
+    pub fn instantiate_with(
+        &self,
+        module: &Module,
+        options: &InstantiateOptions,
+    ) -> Result<Instance, Error> {
+        let mut options = options.clone();
+        if options.memory_pages_limit.is_none() {
+            options.memory_pages_limit = self.0.memory_pages_limit;
+        }
+        if options.max_call_depth.is_none() {
+            options.max_call_depth = self.0.max_call_depth;
+        }
+        module.instantiate_with(&options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, TypedValue};
+
+    fn module() -> Module {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+          (func $rec (export "rec") (param i32)
+            (if (local.get 0) (then (call $rec (i32.sub (local.get 0) (i32.const 1))))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260017f017f60017f0003030200010503010001070e020467726f7700000372656300010a17020600200040000b0e0020000440200041016b10010b0b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn traps(instance: &mut Instance, func: &str, arg: u32) -> bool {
+        instance
+            .execute(func, &[TypedValue::U32(arg)])
+            .unwrap()
+            .trapped()
+    }
+
+    #[test]
+    fn memory_pages_limit() {
+        let config = RuntimeConfig::new().memory_pages_limit(2);
+        let module = module();
+        let mut instance = config.instantiate(&module).unwrap();
+        assert_eq!(instance.resource_usage().memory_max_bytes, 2 * 65536);
+        let result = instance.execute("grow", &[TypedValue::U32(2)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(u32::MAX)));
+
+        // The free functions are not affected.
+        let mut instance = module.instantiate().unwrap();
+        let result = instance.execute("grow", &[TypedValue::U32(2)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+
+        // The options override the defaults.
+        let options = InstantiateOptions::new().memory_pages_limit(4);
+        let instance = config.instantiate_with(&module, &options).unwrap();
+        assert_eq!(instance.resource_usage().memory_max_bytes, 4 * 65536);
+        let options = InstantiateOptions::new().max_call_depth(5);
+        let instance = config.instantiate_with(&module, &options).unwrap();
+        assert_eq!(instance.resource_usage().memory_max_bytes, 2 * 65536);
+
+        /* wat2wasm
+        (module (memory 3))
+        */
+        let input = hex::decode("0061736d010000000503010003").unwrap();
+        let module = config.parse(&input).unwrap();
+        assert!(matches!(
+            config.instantiate(&module),
+            Err(Error::InstantiationFailed(_))
+        ));
+        assert!(module.instantiate().is_ok());
+    }
+
+    #[test]
+    fn max_call_depth() {
+        let config = RuntimeConfig::new().max_call_depth(10);
+        let module = module();
+        // The execution of rec(n) stacks up n + 1 calls.
+        let mut instance = config.instantiate(&module).unwrap();
+        assert!(!traps(&mut instance, "rec", 9));
+        assert!(traps(&mut instance, "rec", 10));
+
+        let mut instance = module.instantiate().unwrap();
+        assert!(!traps(&mut instance, "rec", 100));
+
+        let options = InstantiateOptions::new().max_call_depth(20);
+        let mut instance = config.instantiate_with(&module, &options).unwrap();
+        assert!(!traps(&mut instance, "rec", 19));
+        assert!(traps(&mut instance, "rec", 20));
+    }
+
+    #[test]
+    fn parse_options() {
+        let config = RuntimeConfig::new().parse_options(ParseOptions {
+            max_functions: 1,
+            ..ParseOptions::default()
+        });
+        let module = module();
+        assert!(module.instantiate().is_ok());
+        assert_eq!(
+            config.parse(&module.bytes()).err(),
+            Some(Error::LimitExceeded {
+                which: "max_functions".to_string(),
+                limit: 1,
+                actual: 2,
+            })
+        );
+        assert!(RuntimeConfig::new().parse(&module.bytes()).is_ok());
+    }
+
+    #[test]
+    fn shared() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RuntimeConfig>();
+
+        let config = RuntimeConfig::new().memory_pages_limit(2);
+        let clone = config.clone();
+        assert!(Arc::ptr_eq(&config.0, &clone.0));
+        // Changing a clone does not change the others.
+        let changed = clone.memory_pages_limit(3);
+        assert_eq!(config.0.memory_pages_limit, Some(2));
+        assert_eq!(changed.0.memory_pages_limit, Some(3));
+        let module = module();
+        let instance = std::thread::spawn(move || config.instantiate(&module).is_ok());
+        assert!(instance.join().unwrap());
+    }
+}
@@ -0,0 +1,240 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The functions of the module `env` by which programs abort:
+//!
+//! - `abort(message: i32, file: i32, line: i32, column: i32)` of AssemblyScript, taking the
+//!   addresses of its UTF-16 strings, or 0 for none,
+//! - `panic(message: i32, len: i32)` of Rust programs with a custom panic hook, taking the
+//!   address and the length of a UTF-8 message.
+//!
+//! ```
+//! use fizzy::contrib::abort::{self, AbortInfo};
+//! use fizzy::ImportsBuilder;
+//!
+//! let mut imports = ImportsBuilder::new();
+//! abort::add_to(&mut imports, |info: &AbortInfo| eprintln!("{}", info));
+//! ```
+
+use crate::{Caller, HostError, ImportsBuilder, Trap};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// The reason of an abort, carried by the trap ending the execution, see
+/// [`Error::host_error_downcast`](crate::Error::host_error_downcast).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbortInfo {
+    message: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    column: Option<u32>,
+}
+
+impl AbortInfo {
+    /// The message, with the invalid sequences replaced by U+FFFD, or `None` if not given.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// The source file, or `None` if not given.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The line in the source file, or `None` if not given, e.g. by `panic`.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The column in the source file, or `None` if not given, e.g. by `panic`.
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+}
+
+impl fmt::Display for AbortInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message().unwrap_or("abort"))?;
+        if let Some(file) = &self.file {
+            write!(f, " in {}", file)?;
+        }
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "({}:{})", line, column)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AbortInfo {}
+
+impl HostError for AbortInfo {}
+
+/// Register `abort` and `panic` in the module `env` of `builder`, calling `handler` with the
+/// reason of each abort, then trapping with it.
+///
+/// Functions registered explicitly in `builder` under the same names override these. A string
+/// not within the memory traps the execution without calling `handler`.
+pub fn add_to(builder: &mut ImportsBuilder, handler: impl FnMut(&AbortInfo) + Send + 'static) {
+    let handler = Arc::new(Mutex::new(handler));
+    let abort_handler = handler.clone();
+    builder.provide("abort", |builder| {
+        builder.func(
+            "env",
+            "abort",
+            move |caller: &mut Caller,
+                  message: u32,
+                  file: u32,
+                  line: u32,
+                  column: u32|
+                  -> Result<(), Trap> {
+                let info = AbortInfo {
+                    message: read_utf16(caller, message)?,
+                    file: read_utf16(caller, file)?,
+                    line: Some(line),
+                    column: Some(column),
+                };
+                (*abort_handler.lock().unwrap())(&info);
+                Err(Trap::from_host_error(info))
+            },
+        );
+        builder.func(
+            "env",
+            "panic",
+            move |caller: &mut Caller, message: u32, len: u32| -> Result<(), Trap> {
+                let info = AbortInfo {
+                    message: Some(
+                        String::from_utf8_lossy(&read(caller, message, len)?).into_owned(),
+                    ),
+                    file: None,
+                    line: None,
+                    column: None,
+                };
+                (*handler.lock().unwrap())(&info);
+                Err(Trap::from_host_error(info))
+            },
+        );
+    });
+}
+
+/// Read `len` bytes at `ptr`, checking the bounds before allocating them.
+fn read(caller: &Caller, ptr: u32, len: u32) -> Result<Vec<u8>, Trap> {
+    let out_of_bounds = || {
+        Trap::new(format!(
+            "the string of {} bytes at {} is out of bounds of the memory",
+            len, ptr
+        ))
+    };
+    if u64::from(ptr) + u64::from(len) > caller.memory_size() as u64 {
+        return Err(out_of_bounds());
+    }
+    let mut bytes = vec![0; len as usize];
+    caller
+        .memory_get(ptr, &mut bytes)
+        .map_err(|_| out_of_bounds())?;
+    Ok(bytes)
+}
+
+/// Read the AssemblyScript string at `ptr`, whose length in bytes precedes it, or `None` for the
+/// null pointer.
+fn read_utf16(caller: &Caller, ptr: u32) -> Result<Option<String>, Trap> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    let size = read(caller, ptr.wrapping_sub(4), 4).map_err(|_| {
+        Trap::new(format!(
+            "the string at {} is out of bounds of the memory",
+            ptr
+        ))
+    })?;
+    let size = u32::from_le_bytes(<[u8; 4]>::try_from(&size[..]).unwrap());
+    let units = read(caller, ptr, size)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect::<Vec<_>>();
+    Ok(Some(String::from_utf16_lossy(&units)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Error, Instance, WasmInstance};
+
+    /* wat2wasm
+    (module
+      (func $abort (import "env" "abort") (param i32 i32 i32 i32))
+      (func $panic (import "env" "panic") (param i32 i32))
+      (memory 1)
+      (data (i32.const 16) "\0a\00\00\00h\00\e9\00l\00l\00o\00")
+      (data (i32.const 36) "\10\00\00\00i\00n\00d\00e\00x\00.\00t\00s\00")
+      (data (i32.const 60) "\ff\ff\ff\ff")
+      (data (i32.const 100) "boom!")
+      (func (export "run")
+        (call $abort (i32.const 20) (i32.const 40) (i32.const 12) (i32.const 5)))
+      (func (export "null_message")
+        (call $abort (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+      (func (export "out_of_bounds")
+        (call $abort (i32.const 64) (i32.const 40) (i32.const 1) (i32.const 1)))
+      (func (export "panic") (call $panic (i32.const 100) (i32.const 5)))
+    )
+    */
+    const ABORT_WASM: &str = "0061736d0100000001100360047f7f7f7f0060027f7f0060000002190203656e760561626f7274000003656e760570616e69630001030504020202020503010001072e040372756e00020c6e756c6c5f6d65737361676500030d6f75745f6f665f626f756e647300040570616e696300050a33040c0041144128410c410510000b0c00410041004100410010000b0d0041c00041284101410110000b090041e400410510010b0b41040041100b0e0a0000006800e9006c006c006f000041240b141000000069006e006400650078002e007400730000413c0b04ffffffff0041e4000b05626f6f6d21";
+
+    /// Instantiate the fixture, with the reasons of aborts collected in the returned vector.
+    fn instantiate() -> (Instance, Arc<Mutex<Vec<AbortInfo>>>) {
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let handled = infos.clone();
+        let mut imports = ImportsBuilder::new();
+        add_to(&mut imports, move |info: &AbortInfo| {
+            handled.lock().unwrap().push(info.clone())
+        });
+        let module = parse(&hex::decode(ABORT_WASM).unwrap()).unwrap();
+        (module.instantiate_with_imports(imports).unwrap(), infos)
+    }
+
+    #[test]
+    fn abort() {
+        let (mut instance, infos) = instantiate();
+        let err = instance.call("run", &[]).unwrap_err();
+        assert!(matches!(err, Error::Trapped(_)));
+        let expected = AbortInfo {
+            message: Some("h\u{e9}llo".to_string()),
+            file: Some("index.ts".to_string()),
+            line: Some(12),
+            column: Some(5),
+        };
+        assert_eq!(err.host_error_downcast::<AbortInfo>(), Some(&expected));
+        assert_eq!(expected.to_string(), "h\u{e9}llo in index.ts(12:5)");
+
+        let err = instance.call("null_message", &[]).unwrap_err();
+        let info = err.host_error_downcast::<AbortInfo>().unwrap();
+        assert_eq!(info.message(), None);
+        assert_eq!(info.file(), None);
+        assert_eq!(info.to_string(), "abort(0:0)");
+
+        let err = instance.call("panic", &[]).unwrap_err();
+        let info = err.host_error_downcast::<AbortInfo>().unwrap();
+        assert_eq!(info.message(), Some("boom!"));
+        assert_eq!((info.line(), info.column()), (None, None));
+        assert_eq!(info.to_string(), "boom!");
+
+        let infos = infos.lock().unwrap();
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[0], expected);
+        assert_eq!(infos[2].message(), Some("boom!"));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let (mut instance, infos) = instantiate();
+        let err = instance.call("out_of_bounds", &[]).unwrap_err();
+        assert!(matches!(err, Error::Trapped(_)));
+        assert_eq!(err.host_error_downcast::<AbortInfo>(), None);
+        assert!(err
+            .to_string()
+            .contains("the string of 4294967295 bytes at 64 is out of bounds of the memory"));
+        assert!(infos.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Leveled logging for programs, by the imported functions `log_debug`, `log_info`, `log_warn`
+//! and `log_error` of the module `env` taking the address and the length of a UTF-8 message in
+//! the memory.
+//!
+//! ```
+//! use fizzy::contrib::logging::{self, Record, Sink};
+//! use fizzy::ImportsBuilder;
+//!
+//! let mut imports = ImportsBuilder::new();
+//! logging::add_to(
+//!     &mut imports,
+//!     Sink::Closure(Box::new(|record: &Record| {
+//!         println!("{:?}: {}", record.level(), record.message())
+//!     })),
+//! );
+//! ```
+
+use crate::{Caller, ImportsBuilder, Trap};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// The severity of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// A message logged by a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    level: Level,
+    message: String,
+    truncated: bool,
+}
+
+impl Record {
+    /// The level of the function called by the program.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The message, with the invalid UTF-8 sequences replaced by U+FFFD.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// True if the message was longer than [`LoggingConfig::max_len`], and therefore cut.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// The destination of the messages logged by programs.
+pub enum Sink {
+    /// Call the closure with each message.
+    Closure(Box<dyn FnMut(&Record) + Send>),
+    /// Log each message with the macros of `log` of its level in the target `fizzy::guest`.
+    #[cfg(feature = "log")]
+    Log,
+    /// Emit each message as a `tracing` event of its level in the target `fizzy::guest`.
+    #[cfg(feature = "tracing")]
+    Tracing,
+}
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sink::Closure(_) => f.write_str("Sink::Closure"),
+            #[cfg(feature = "log")]
+            Sink::Log => f.write_str("Sink::Log"),
+            #[cfg(feature = "tracing")]
+            Sink::Tracing => f.write_str("Sink::Tracing"),
+        }
+    }
+}
+
+impl Sink {
+    fn log(&mut self, record: &Record) {
+        match self {
+            Sink::Closure(func) => func(record),
+            #[cfg(feature = "log")]
+            Sink::Log => {
+                let level = match record.level {
+                    Level::Error => log::Level::Error,
+                    Level::Warn => log::Level::Warn,
+                    Level::Info => log::Level::Info,
+                    Level::Debug => log::Level::Debug,
+                    Level::Trace => log::Level::Trace,
+                };
+                log::log!(target: "fizzy::guest", level, "{}", record.message);
+            }
+            #[cfg(feature = "tracing")]
+            Sink::Tracing => match record.level {
+                Level::Error => tracing::error!(target: "fizzy::guest", "{}", record.message),
+                Level::Warn => tracing::warn!(target: "fizzy::guest", "{}", record.message),
+                Level::Info => tracing::info!(target: "fizzy::guest", "{}", record.message),
+                Level::Debug => tracing::debug!(target: "fizzy::guest", "{}", record.message),
+                Level::Trace => tracing::trace!(target: "fizzy::guest", "{}", record.message),
+            },
+        }
+    }
+}
+
+/// The names and levels of the logging functions, and the limit of the length of messages.
+#[derive(Clone, Debug)]
+pub struct LoggingConfig {
+    module: String,
+    functions: Vec<(String, Level)>,
+    max_len: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            module: "env".to_string(),
+            functions: vec![
+                ("log_debug".to_string(), Level::Debug),
+                ("log_info".to_string(), Level::Info),
+                ("log_warn".to_string(), Level::Warn),
+                ("log_error".to_string(), Level::Error),
+            ],
+            max_len: 4096,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Create the configuration of the functions `log_debug`, `log_info`, `log_warn` and
+    /// `log_error` of the module `env`, with messages limited to 4096 bytes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the functions in the module `module`.
+    pub fn module(mut self, module: &str) -> Self {
+        self.module = module.to_string();
+        self
+    }
+
+    /// Register the functions of the given names and levels, instead of the default ones.
+    pub fn functions(mut self, functions: &[(&str, Level)]) -> Self {
+        self.functions = functions
+            .iter()
+            .map(|(name, level)| (name.to_string(), *level))
+            .collect();
+        self
+    }
+
+    /// Cut the messages to at most `max_len` bytes, so that a program cannot make the host copy
+    /// large parts of the memory. A character cut in the middle is left out.
+    pub fn max_len(mut self, max_len: u32) -> Self {
+        self.max_len = max_len;
+        self
+    }
+}
+
+/// Register the logging functions of the default [`LoggingConfig`] in `builder`, forwarding the
+/// messages to `sink`.
+pub fn add_to(builder: &mut ImportsBuilder, sink: Sink) {
+    add_to_with_config(builder, &LoggingConfig::new(), sink)
+}
+
+/// Register the logging functions configured by `config` in `builder`, forwarding the messages to
+/// `sink`.
+///
+/// Functions registered explicitly in `builder` under the same names override the logging
+/// functions. A message not within the memory traps the execution.
+pub fn add_to_with_config(builder: &mut ImportsBuilder, config: &LoggingConfig, sink: Sink) {
+    let sink = Arc::new(Mutex::new(sink));
+    builder.provide("logging", |builder| {
+        for (name, level) in &config.functions {
+            let sink = sink.clone();
+            let level = *level;
+            let max_len = config.max_len;
+            builder.func(
+                &config.module,
+                name,
+                move |caller: &mut Caller, ptr: u32, len: u32| -> Result<(), Trap> {
+                    let record = read_record(caller, level, ptr, len, max_len)?;
+                    sink.lock().unwrap().log(&record);
+                    Ok(())
+                },
+            );
+        }
+    });
+}
+
+fn read_record(
+    caller: &Caller,
+    level: Level,
+    ptr: u32,
+    len: u32,
+    max_len: u32,
+) -> Result<Record, Trap> {
+    let truncated = len > max_len;
+    let mut bytes = vec![0; len.min(max_len) as usize];
+    caller.memory_get(ptr, &mut bytes).map_err(|_| {
+        Trap::new(format!(
+            "the message of {} bytes at {} is out of bounds of the memory",
+            len, ptr
+        ))
+    })?;
+    if truncated {
+        // Leave out a character cut by the limit, rather than replacing it.
+        if let Some(start) = (bytes.len().saturating_sub(3)..bytes.len())
+            .rev()
+            .find(|i| bytes[*i] & 0xc0 != 0x80)
+        {
+            if matches!(std::str::from_utf8(&bytes[start..]), Err(err) if err.error_len().is_none())
+            {
+                bytes.truncate(start);
+            }
+        }
+    }
+    Ok(Record {
+        level,
+        message: String::from_utf8_lossy(&bytes).into_owned(),
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /* wat2wasm
+    (module
+      (func $debug (import "env" "log_debug") (param i32 i32))
+      (func $info (import "env" "log_info") (param i32 i32))
+      (func $warn (import "env" "log_warn") (param i32 i32))
+      (func $error (import "env" "log_error") (param i32 i32))
+      (memory 1)
+      (data (i32.const 0) "starting")
+      (data (i32.const 16) "value: 42\ff")
+      (data (i32.const 32) "something went wrong badly")
+      (data (i32.const 64) "caf\c3\a9 au lait")
+      (func (export "run")
+        (call $debug (i32.const 0) (i32.const 8))
+        (call $info (i32.const 16) (i32.const 10))
+        (call $error (i32.const 32) (i32.const 26)))
+      (func (export "cafe") (call $warn (i32.const 64) (i32.const 13)))
+      (func (export "out_of_bounds") (call $info (i32.const 65530) (i32.const 10)))
+    )
+    */
+    const LOGGING_WASM: &str = "0061736d0100000001090260027f7f00600000023f0403656e76096c6f675f6465627567000003656e76086c6f675f696e666f000003656e76086c6f675f7761726e000003656e76096c6f675f6572726f7200000304030101010503010001071e030372756e0004046361666500050d6f75745f6f665f626f756e647300060a2b0314004100410810004110410a10014120411a10030b090041c000410d10020b0a0041faff03410a10010b0b4f040041000b087374617274696e670041100b0a76616c75653a203432ff0041200b1a736f6d657468696e672077656e742077726f6e67206261646c790041c0000b0d636166c3a9206175206c616974";
+
+    /// Execute `func` logging with `config`, and return the records.
+    fn run(config: &LoggingConfig, func: &str) -> Vec<Record> {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let captured = records.clone();
+        let mut imports = ImportsBuilder::new();
+        add_to_with_config(
+            &mut imports,
+            config,
+            Sink::Closure(Box::new(move |record: &Record| {
+                captured.lock().unwrap().push(record.clone())
+            })),
+        );
+        let module = parse(&hex::decode(LOGGING_WASM).unwrap()).unwrap();
+        let mut instance = module.instantiate_with_imports(imports).unwrap();
+        assert!(!instance.execute(func, &[]).unwrap().trapped());
+        let records = records.lock().unwrap().clone();
+        records
+    }
+
+    fn record(level: Level, message: &str, truncated: bool) -> Record {
+        Record {
+            level,
+            message: message.to_string(),
+            truncated,
+        }
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(
+            run(&LoggingConfig::new(), "run"),
+            [
+                record(Level::Debug, "starting", false),
+                record(Level::Info, "value: 42\u{fffd}", false),
+                record(Level::Error, "something went wrong badly", false),
+            ]
+        );
+        assert_eq!(
+            run(&LoggingConfig::new(), "cafe"),
+            [record(Level::Warn, "caf\u{e9} au lait", false)]
+        );
+
+        let config = LoggingConfig::new().functions(&[
+            ("log_debug", Level::Trace),
+            ("log_info", Level::Info),
+            ("log_warn", Level::Warn),
+            ("log_error", Level::Warn),
+        ]);
+        assert_eq!(
+            run(&config, "run")
+                .iter()
+                .map(Record::level)
+                .collect::<Vec<_>>(),
+            [Level::Trace, Level::Info, Level::Warn]
+        );
+
+        // The functions are not registered in the imported module.
+        let mut imports = ImportsBuilder::new();
+        add_to_with_config(
+            &mut imports,
+            &LoggingConfig::new().module("host"),
+            Sink::Closure(Box::new(|_: &Record| {})),
+        );
+        let module = parse(&hex::decode(LOGGING_WASM).unwrap()).unwrap();
+        assert!(module.instantiate_with_imports(imports).is_err());
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(
+            run(&LoggingConfig::new().max_len(10), "run"),
+            [
+                record(Level::Debug, "starting", false),
+                record(Level::Info, "value: 42\u{fffd}", false),
+                record(Level::Error, "something ", true),
+            ]
+        );
+        // The character cut by the limit is left out.
+        assert_eq!(
+            run(&LoggingConfig::new().max_len(4), "cafe"),
+            [record(Level::Warn, "caf", true)]
+        );
+        assert_eq!(
+            run(&LoggingConfig::new().max_len(5), "cafe"),
+            [record(Level::Warn, "caf\u{e9}", true)]
+        );
+        assert_eq!(
+            run(&LoggingConfig::new().max_len(0), "cafe"),
+            [record(Level::Warn, "", true)]
+        );
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut imports = ImportsBuilder::new();
+        add_to(
+            &mut imports,
+            Sink::Closure(Box::new(|_: &Record| panic!("logged"))),
+        );
+        let module = parse(&hex::decode(LOGGING_WASM).unwrap()).unwrap();
+        let mut instance = module.instantiate_with_imports(imports).unwrap();
+        assert!(instance.execute("out_of_bounds", &[]).unwrap().trapped());
+        assert_eq!(
+            instance
+                .take_host_trap()
+                .map(|trap| trap.message().to_string()),
+            Some("the message of 10 bytes at 65530 is out of bounds of the memory".to_string())
+        );
+    }
+}
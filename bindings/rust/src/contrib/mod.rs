@@ -0,0 +1,8 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Ready-made packages of host functions for common needs of programs.
+
+pub mod abort;
+pub mod logging;
@@ -0,0 +1,312 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The coverage of the functions of a module by executions, see
+//! [`ExecutionOptions::coverage`](crate::ExecutionOptions::coverage).
+
+use crate::stats::CallDepth;
+use crate::{sys, ExecutionResult, ExternalKind, Instance, Module, Trap, Value};
+
+use std::fmt;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The set of the functions of a module which have been entered by executions with
+/// [`ExecutionOptions::coverage`](crate::ExecutionOptions::coverage).
+///
+/// The clones of the map share the set. It is written with `{}` as the ratio of the covered
+/// functions, followed by a line per function, e.g. `  [x] 1 $fac` for a covered function, with
+/// the names of functions from the name section of the module or its exports.
+#[derive(Clone, Debug)]
+pub struct CoverageMap(Arc<Coverage>);
+
+#[derive(Debug)]
+struct Coverage {
+    /// A bit per function, set when the function is entered.
+    bits: Vec<AtomicU64>,
+    function_count: u32,
+    names: Vec<Option<String>>,
+}
+
+impl CoverageMap {
+    /// Create an empty map of the functions of `module`, including the imported functions, which
+    /// are covered when they are called.
+    pub fn new(module: &Module) -> Self {
+        let function_count = unsafe { sys::fizzy_get_function_count(module.as_ptr()) };
+        let mut names: Vec<Option<String>> = (0..function_count)
+            .map(|func_idx| module.function_name(func_idx).map(str::to_string))
+            .collect();
+        for export in module.exports() {
+            if export.kind == ExternalKind::Function {
+                let name = &mut names[export.index as usize];
+                if name.is_none() {
+                    *name = Some(export.name);
+                }
+            }
+        }
+        let words = function_count as usize / 64 + 1;
+        CoverageMap(Arc::new(Coverage {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            function_count,
+            names,
+        }))
+    }
+
+    /// Mark the function `func_idx` as covered. The indices out of the module are ignored.
+    fn mark(&self, func_idx: u32) {
+        if let Some(word) = self.0.bits.get(func_idx as usize / 64) {
+            word.fetch_or(1 << (func_idx % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// True if the function `func_idx` has been entered.
+    pub fn is_covered(&self, func_idx: u32) -> bool {
+        match self.0.bits.get(func_idx as usize / 64) {
+            Some(word) => word.load(Ordering::Relaxed) & (1 << (func_idx % 64)) != 0,
+            None => false,
+        }
+    }
+
+    /// The indices of the covered functions, in ascending order.
+    pub fn covered(&self) -> Vec<u32> {
+        (0..self.0.function_count)
+            .filter(|func_idx| self.is_covered(*func_idx))
+            .collect()
+    }
+
+    /// The ratio of the covered functions to all functions, from 0.0 to 1.0. It is 1.0 for
+    /// a module without functions.
+    pub fn ratio(&self) -> f64 {
+        if self.0.function_count == 0 {
+            return 1.0;
+        }
+        self.covered().len() as f64 / f64::from(self.0.function_count)
+    }
+
+    /// The name of the function `func_idx` in the name section of the module, or otherwise its
+    /// export name.
+    pub fn function_name(&self, func_idx: u32) -> Option<&str> {
+        self.0.names.get(func_idx as usize)?.as_deref()
+    }
+
+    /// Clear the set of the covered functions.
+    pub fn reset(&self) {
+        for word in &self.0.bits {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl fmt::Display for CoverageMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}/{} functions covered ({:.1}%)",
+            self.covered().len(),
+            self.0.function_count,
+            self.ratio() * 100.0
+        )?;
+        for func_idx in 0..self.0.function_count {
+            let mark = if self.is_covered(func_idx) { 'x' } else { ' ' };
+            write!(f, "  [{}] {}", mark, func_idx)?;
+            if let Some(name) = self.function_name(func_idx) {
+                write!(f, " ${}", name)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// The context of the hooks of an execution with coverage or statistics, but not traced.
+struct Context<'a> {
+    instance: *mut sys::FizzyInstance,
+    coverage: Option<&'a CoverageMap>,
+    call_depth: Option<&'a mut CallDepth>,
+}
+
+/// Mark the function `func_idx` of `instance` as covered, unless it is of another instance
+/// called via an imported table.
+pub(crate) fn enter(
+    coverage: &CoverageMap,
+    traced: *mut sys::FizzyInstance,
+    instance: *mut sys::FizzyInstance,
+    func_idx: u32,
+) {
+    if instance == traced {
+        coverage.mark(func_idx);
+    }
+}
+
+unsafe extern "C" fn enter_hook(
+    context: *mut c_void,
+    instance: *mut sys::FizzyInstance,
+    func_idx: u32,
+    _args: *const sys::FizzyValue,
+) {
+    let context = &mut *(context as *mut Context);
+    if let Some(call_depth) = context.call_depth.as_mut() {
+        call_depth.enter();
+    }
+    if let Some(coverage) = context.coverage {
+        enter(coverage, context.instance, instance, func_idx);
+    }
+}
+
+unsafe extern "C" fn leave_hook(
+    context: *mut c_void,
+    _instance: *mut sys::FizzyInstance,
+    _func_idx: u32,
+    _result: sys::FizzyExecutionResult,
+) {
+    let context = &mut *(context as *mut Context);
+    if let Some(call_depth) = context.call_depth.as_mut() {
+        call_depth.leave();
+    }
+}
+
+/// Execute the function `func_idx` of `instance` like [`Instance::execute_unchecked`], marking the
+/// entered functions in `coverage` if given, and counting the depth of calls in `call_depth` if
+/// given.
+///
+/// # Safety
+/// This function expects a valid `func_idx` and appropriate number of `args`.
+pub(crate) unsafe fn execute(
+    instance: &mut Instance,
+    func_idx: u32,
+    args: &[Value],
+    coverage: Option<&CoverageMap>,
+    call_depth: Option<&mut CallDepth>,
+    max_depth: u32,
+) -> ExecutionResult {
+    instance.host_trap.clear();
+    let mut context = Context {
+        instance: instance.instance.as_ptr(),
+        coverage,
+        call_depth,
+    };
+    let hooks = sys::FizzyTraceHooks {
+        enter: Some(enter_hook),
+        leave: Some(leave_hook),
+        context: &mut context as *mut Context as *mut c_void,
+    };
+    let mut call_depth_exceeded = false;
+    let result = sys::fizzy_execute_traced(
+        instance.instance.as_ptr(),
+        func_idx,
+        args.as_ptr(),
+        &hooks,
+        max_depth,
+        &mut call_depth_exceeded,
+    );
+    if call_depth_exceeded {
+        instance.host_trap.set(Trap::call_depth_exceeded());
+    }
+    ExecutionResult(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse, Caller, ExecutionOptions, ImportsBuilder, TraceEvent, TraceSink, Trap, TypedValue,
+    };
+
+    fn instantiate() -> Instance {
+        /* wat2wasm --debug-names
+        (module
+          (func $double (import "env" "double") (param i32) (result i32))
+          (table 1 funcref)
+          (elem (i32.const 0) $indirect)
+          (func $fac (param i32) (result i32)
+            (if (result i32) (i32.eqz (local.get 0))
+              (then (i32.const 1))
+              (else (i32.mul (local.get 0) (call $fac (i32.sub (local.get 0) (i32.const 1)))))))
+          (func $indirect (result i32) (i32.const 7))
+          (func $unreachable (param i32) (result i32) (call $fac (local.get 0)))
+          (func (export "run") (param i32) (result i32) (call $double (call $fac (local.get 0))))
+          (func (export "table") (result i32) (call_indirect (result i32) (i32.const 0)))
+          (func (export "unused"))
+        )
+        */
+        let input = hex::decode("0061736d01000000010d0360017f017f6000017f600000020e0103656e7606646f75626c6500000307060001000001020404017000010718030372756e0004057461626c65000506756e7573656400060907010041000b01020a37061500200045047f4101052000200041016b10016c0b0b040041070b0600200010010b08002000100110000b070041001101000b02000b003d046e616d650125040006646f75626c6501036661630208696e646972656374030b756e726561636861626c65020f070000010002000300040005000600").unwrap();
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "double",
+            |_: &mut Caller, x: u32| -> Result<u32, Trap> { Ok(x * 2) },
+        );
+        parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn union_of_executions() {
+        let mut instance = instantiate();
+        let coverage = CoverageMap::new(&instance.module);
+        assert!(coverage.covered().is_empty());
+        assert_eq!(coverage.ratio(), 0.0);
+
+        let options = ExecutionOptions::new().coverage(&coverage);
+        let outcome = instance
+            .execute_with_options("run", &[TypedValue::U32(3)], &options)
+            .unwrap();
+        assert_eq!(outcome.value(), Some(TypedValue::U32(12)));
+        assert_eq!(coverage.covered(), [0, 1, 4]);
+
+        instance
+            .execute_with_options("table", &[], &options)
+            .unwrap();
+        assert_eq!(coverage.covered(), [0, 1, 2, 4, 5]);
+        assert!(!coverage.is_covered(3));
+        assert!(!coverage.is_covered(6));
+        assert!(!coverage.is_covered(100));
+        assert_eq!(coverage.ratio(), 5.0 / 7.0);
+
+        // The executions without the option are not covered.
+        instance.execute("unused", &[]).unwrap();
+        assert!(!coverage.is_covered(6));
+
+        assert_eq!(
+            coverage.to_string(),
+            "\
+5/7 functions covered (71.4%)
+  [x] 0 $double
+  [x] 1 $fac
+  [x] 2 $indirect
+  [ ] 3 $unreachable
+  [x] 4 $run
+  [x] 5 $table
+  [ ] 6 $unused
+"
+        );
+
+        coverage.reset();
+        assert!(coverage.covered().is_empty());
+    }
+
+    #[test]
+    fn with_trace() {
+        let mut instance = instantiate();
+        let coverage = CoverageMap::new(&instance.module);
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        let options = ExecutionOptions::new()
+            .coverage(&coverage)
+            .trace(TraceSink::Callback(Box::new(move |event| {
+                if let TraceEvent::Enter { .. } = event {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            })));
+        instance
+            .execute_with_options("run", &[TypedValue::U32(1)], &options)
+            .unwrap();
+        assert_eq!(coverage.covered(), [0, 1, 4]);
+        // run, fac(1), fac(0) and double.
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+}
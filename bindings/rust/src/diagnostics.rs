@@ -0,0 +1,226 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Validation with detailed diagnostics by [wasmparser](https://github.com/bytecodealliance/wasm-tools).
+
+use crate::{validate, Error};
+
+/// An error of [`validate_detailed`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum DetailedError {
+    /// The module is rejected by both wasmparser and Fizzy. The error is the one of wasmparser.
+    Invalid { offset: usize, message: String },
+    /// The module is rejected by only one of wasmparser and Fizzy, which is likely a bug in either.
+    ///
+    /// If wasmparser rejects the module, `offset` and `message` describe its error, and `fizzy` is
+    /// `None`. Otherwise `fizzy` is the error of Fizzy, `message` is its description, and `offset` is
+    /// 0, as Fizzy does not report the offsets.
+    Disagreement {
+        offset: usize,
+        message: String,
+        fizzy: Option<Error>,
+    },
+}
+
+impl DetailedError {
+    /// The offset of the error in the input, in bytes.
+    pub fn offset(&self) -> usize {
+        match self {
+            DetailedError::Invalid { offset, .. } | DetailedError::Disagreement { offset, .. } => {
+                *offset
+            }
+        }
+    }
+
+    /// The description of the error.
+    pub fn message(&self) -> &str {
+        match self {
+            DetailedError::Invalid { message, .. }
+            | DetailedError::Disagreement { message, .. } => message,
+        }
+    }
+}
+
+impl std::fmt::Display for DetailedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DetailedError::Invalid { offset, message } => {
+                write!(f, "{} (at offset {:#x})", message, offset)
+            }
+            DetailedError::Disagreement {
+                offset,
+                message,
+                fizzy: None,
+            } => write!(
+                f,
+                "accepted by fizzy, but rejected by wasmparser: {} (at offset {:#x})",
+                message, offset
+            ),
+            DetailedError::Disagreement { message, .. } => write!(
+                f,
+                "accepted by wasmparser, but rejected by fizzy: {}",
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DetailedError {}
+
+/// Validate the input according to WebAssembly 1.0 rules by wasmparser, which reports the offset
+/// of an error, and confirm the outcome by [`validate`].
+///
+/// ```
+/// let err = fizzy::validate_detailed(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x0d])
+///     .unwrap_err();
+/// println!("{}", err);
+/// ```
+pub fn validate_detailed<T: AsRef<[u8]>>(input: T) -> Result<(), DetailedError> {
+    let input = input.as_ref();
+    let mut validator = wasmparser::Validator::new();
+    validator.wasm_features(mvp_features());
+    let detailed = validator
+        .validate_all(input)
+        .map_err(|err| (err.offset(), err.message().to_string()));
+    reconcile(detailed, validate(input))
+}
+
+/// The features of wasmparser limited to WebAssembly 1.0, as supported by Fizzy.
+fn mvp_features() -> wasmparser::WasmFeatures {
+    wasmparser::WasmFeatures {
+        reference_types: false,
+        multi_value: false,
+        bulk_memory: false,
+        module_linking: false,
+        simd: false,
+        threads: false,
+        tail_call: false,
+        multi_memory: false,
+        exceptions: false,
+        memory64: false,
+        ..Default::default()
+    }
+}
+
+fn reconcile(
+    detailed: Result<(), (usize, String)>,
+    fizzy: Result<(), Error>,
+) -> Result<(), DetailedError> {
+    match (detailed, fizzy) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err((offset, message)), Err(_)) => Err(DetailedError::Invalid { offset, message }),
+        (Err((offset, message)), Ok(())) => Err(DetailedError::Disagreement {
+            offset,
+            message,
+            fizzy: None,
+        }),
+        (Ok(()), Err(err)) => Err(DetailedError::Disagreement {
+            offset: 0,
+            message: err.to_string(),
+            fizzy: Some(err),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(input: &[u8], offsets: std::ops::Range<usize>) {
+        match validate_detailed(input) {
+            Err(DetailedError::Invalid { offset, message }) => assert!(
+                offsets.contains(&offset),
+                "unexpected offset {} of error: {}",
+                offset,
+                message
+            ),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        // Invalid magic.
+        assert_invalid(&[0x00, 0x61, 0x73, 0x6c, 0x01, 0x00, 0x00, 0x00], 0..1);
+        // Unknown section id, reported after the section header.
+        assert_invalid(
+            &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x20, 0x00],
+            8..11,
+        );
+        // Type section of 5 bytes truncated after 2.
+        assert_invalid(
+            &[
+                0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60,
+            ],
+            8..13,
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        /* wat2wasm --no-check
+        (module (func (result i32) (i64.const 0)))
+        */
+        let wasm = hex::decode("0061736d010000000105016000017f030201000a0601040042000b").unwrap();
+        // At the end of the function.
+        assert_invalid(&wasm, 26..27);
+
+        /* wat2wasm --no-check
+        (module (func (drop (i32.const 0)) (drop)))
+        */
+        let wasm = hex::decode("0061736d01000000010401600000030201000a0801060041001a1a0b").unwrap();
+        // At the second drop.
+        let err = validate_detailed(&wasm).unwrap_err();
+        assert_eq!(err.offset(), 26);
+        assert!(!err.message().is_empty());
+        assert!(err.to_string().ends_with("(at offset 0x1a)"));
+
+        /* wat2wasm
+        (module (func (param i32) (result i32) (i32.add (local.get 0) (i32.const 1))))
+        */
+        let wasm =
+            hex::decode("0061736d0100000001060160017f017f030201000a09010700200041016a0b").unwrap();
+        assert_eq!(validate_detailed(&wasm), Ok(()));
+    }
+
+    #[test]
+    fn disagreement() {
+        assert_eq!(reconcile(Ok(()), Ok(())), Ok(()));
+        assert_eq!(
+            reconcile(
+                Err((8, "type mismatch".to_string())),
+                Err(Error::InvalidModule("stack underflow".to_string()))
+            ),
+            Err(DetailedError::Invalid {
+                offset: 8,
+                message: "type mismatch".to_string()
+            })
+        );
+
+        let err = reconcile(Err((8, "type mismatch".to_string())), Ok(())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "accepted by fizzy, but rejected by wasmparser: type mismatch (at offset 0x8)"
+        );
+        assert_eq!(err.offset(), 8);
+
+        let err = reconcile(
+            Ok(()),
+            Err(Error::InvalidModule("stack underflow".to_string())),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            DetailedError::Disagreement {
+                offset: 0,
+                message: "stack underflow".to_string(),
+                fizzy: Some(Error::InvalidModule("stack underflow".to_string()))
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "accepted by wasmparser, but rejected by fizzy: stack underflow"
+        );
+    }
+}
@@ -0,0 +1,482 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Differential execution of modules in Fizzy and in [wasmi](https://github.com/paritytech/wasmi).
+//!
+//! The invocations are run in both engines, and their outcomes are compared: returned values by
+//! their bits, and traps only by the fact that the execution has trapped, as Fizzy does not report
+//! the cause. Errors other than traps are only compared by their presence, as the messages differ.
+//!
+//! ```
+//! use fizzy::differential::{compare, Invocation};
+//! use fizzy::TypedValue;
+//!
+//! // This wasm binary exports a single sum(u32, u32) -> u32 function.
+//! let wasm = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+//!     0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x73, 0x75, 0x6d,
+//!     0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+//! ];
+//! let invocations = [Invocation::new("sum", &[TypedValue::U32(42), TypedValue::U32(24)])];
+//! if let Err(divergence) = compare(&wasm, &invocations) {
+//!     panic!("{}", divergence);
+//! }
+//! ```
+
+use crate::engine::{Engine, FizzyEngine, InstanceApi, ModuleApi};
+use crate::{Error, ExternalKind, FunctionType, TrapInfo, TypedValue, ValueType};
+
+/// A call of an exported function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invocation {
+    pub name: String,
+    pub args: Vec<TypedValue>,
+}
+
+impl Invocation {
+    /// Create a call of the exported function `name` with `args`.
+    pub fn new(name: &str, args: &[TypedValue]) -> Self {
+        Invocation {
+            name: name.to_string(),
+            args: args.to_vec(),
+        }
+    }
+}
+
+/// The step at which the engines have diverged.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    Parse,
+    Instantiate,
+    /// The invocation of the index, counting from 0.
+    Invoke(usize, Invocation),
+}
+
+/// The outcome of a step in an engine.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// The step has succeeded, with the optional output of an invocation.
+    Value(Option<TypedValue>),
+    /// The execution has trapped.
+    Trapped,
+    /// The step has failed with an error other than a trap.
+    Failed(Error),
+}
+
+impl Outcome {
+    fn of(result: Result<Option<TypedValue>, Error>) -> Self {
+        match result {
+            Ok(value) => Outcome::Value(value),
+            Err(Error::Trapped(_)) => Outcome::Trapped,
+            Err(err) => Outcome::Failed(err),
+        }
+    }
+
+    /// Whether the outcomes are the same, comparing values by their bits.
+    pub(crate) fn same(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Value(Some(a)), Outcome::Value(Some(b))) => same_bits(a, b),
+            (Outcome::Value(None), Outcome::Value(None))
+            | (Outcome::Trapped, Outcome::Trapped)
+            | (Outcome::Failed(_), Outcome::Failed(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+fn same_bits(a: &TypedValue, b: &TypedValue) -> bool {
+    match (a, b) {
+        (TypedValue::F32(a), TypedValue::F32(b)) => a.to_bits() == b.to_bits(),
+        (TypedValue::F64(a), TypedValue::F64(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    }
+}
+
+/// Format `value` with its type and, for floating-point values, their bits.
+fn format_value(value: &TypedValue) -> String {
+    match value {
+        TypedValue::U32(v) => format!("i32:{}", v),
+        TypedValue::U64(v) => format!("i64:{}", v),
+        TypedValue::F32(v) => format!("f32:{} ({:#010x})", v, v.to_bits()),
+        TypedValue::F64(v) => format!("f64:{} ({:#018x})", v, v.to_bits()),
+        other => other.to_string(),
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Outcome::Value(Some(value)) => write!(f, "returned {}", format_value(value)),
+            Outcome::Value(None) => write!(f, "succeeded"),
+            Outcome::Trapped => write!(f, "trapped"),
+            Outcome::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// The first step at which the outcomes of two engines differ.
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    pub step: Step,
+    /// The name of each engine, with its outcome.
+    pub outcomes: [(String, Outcome); 2],
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.step {
+            Step::Parse => write!(f, "divergence in parsing")?,
+            Step::Instantiate => write!(f, "divergence in instantiation")?,
+            Step::Invoke(index, invocation) => {
+                let args: Vec<String> = invocation.args.iter().map(format_value).collect();
+                write!(
+                    f,
+                    "divergence in invocation {}: {}({})",
+                    index,
+                    invocation.name,
+                    args.join(", ")
+                )?
+            }
+        }
+        for (name, outcome) in &self.outcomes {
+            write!(f, "\n  {}: {}", name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `invocations` of the module `wasm` in the engines `a` and `b`, in a single instance each,
+/// and compare the outcomes of each step.
+///
+/// Returns the first divergence, if any. The comparison ends when both engines fail a step.
+pub fn compare_engines(
+    a: &dyn Engine,
+    b: &dyn Engine,
+    wasm: &[u8],
+    invocations: &[Invocation],
+) -> Result<(), Box<Divergence>> {
+    let check = |step: Step, outcome_a: Outcome, outcome_b: Outcome| {
+        if outcome_a.same(&outcome_b) {
+            return Ok(());
+        }
+        Err(Box::new(Divergence {
+            step,
+            outcomes: [
+                (a.name().to_string(), outcome_a),
+                (b.name().to_string(), outcome_b),
+            ],
+        }))
+    };
+
+    let (module_a, module_b) = match (a.parse(wasm), b.parse(wasm)) {
+        (Ok(module_a), Ok(module_b)) => (module_a, module_b),
+        (result_a, result_b) => {
+            return check(
+                Step::Parse,
+                Outcome::of(result_a.map(|_| None)),
+                Outcome::of(result_b.map(|_| None)),
+            )
+        }
+    };
+    let (mut instance_a, mut instance_b) = match (module_a.instantiate(), module_b.instantiate()) {
+        (Ok(instance_a), Ok(instance_b)) => (instance_a, instance_b),
+        (result_a, result_b) => {
+            return check(
+                Step::Instantiate,
+                Outcome::of(result_a.map(|_| None)),
+                Outcome::of(result_b.map(|_| None)),
+            )
+        }
+    };
+    for (index, invocation) in invocations.iter().enumerate() {
+        check(
+            Step::Invoke(index, invocation.clone()),
+            Outcome::of(instance_a.call(&invocation.name, &invocation.args)),
+            Outcome::of(instance_b.call(&invocation.name, &invocation.args)),
+        )?;
+    }
+    Ok(())
+}
+
+/// Run `invocations` of the module `wasm` in Fizzy and in wasmi, see [`compare_engines`].
+pub fn compare(wasm: &[u8], invocations: &[Invocation]) -> Result<(), Box<Divergence>> {
+    compare_engines(&FizzyEngine, &WasmiEngine, wasm, invocations)
+}
+
+/// Convert `value` to the representation of wasmi.
+pub fn to_wasmi(value: &TypedValue) -> wasmi::RuntimeValue {
+    (*value).into()
+}
+
+/// Convert `value` from the representation of wasmi.
+pub fn from_wasmi(value: wasmi::RuntimeValue) -> TypedValue {
+    value.into()
+}
+
+/// Convert `value_type` from the representation of wasmi.
+pub fn value_type_from_wasmi(value_type: wasmi::ValueType) -> ValueType {
+    value_type.into()
+}
+
+/// The [`Engine`] implementation of wasmi.
+///
+/// Its instances access the memory only if it is exported.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WasmiEngine;
+
+struct WasmiModule {
+    module: wasmi::Module,
+    /// The name of the exported memory, if any.
+    memory_export: Option<String>,
+}
+
+struct WasmiInstance {
+    instance: wasmi::ModuleRef,
+    memory: Option<wasmi::MemoryRef>,
+}
+
+fn wasmi_error(err: wasmi::Error) -> Error {
+    Error::Other(err.to_string())
+}
+
+impl Engine for WasmiEngine {
+    fn name(&self) -> &str {
+        "wasmi"
+    }
+
+    fn parse(&self, wasm: &[u8]) -> Result<Box<dyn ModuleApi>, Error> {
+        let module = wasmi::Module::from_buffer(wasm).map_err(wasmi_error)?;
+        // wasmi does not list the exports, therefore the memory is found by parsing the module in Fizzy.
+        let memory_export = crate::parse(&wasm).ok().and_then(|module| {
+            module
+                .exports()
+                .into_iter()
+                .find(|export| export.kind() == ExternalKind::Memory)
+                .map(|export| export.name().to_string())
+        });
+        Ok(Box::new(WasmiModule {
+            module,
+            memory_export,
+        }))
+    }
+}
+
+impl ModuleApi for WasmiModule {
+    fn instantiate(&self) -> Result<Box<dyn InstanceApi>, Error> {
+        let instance = wasmi::ModuleInstance::new(&self.module, &wasmi::ImportsBuilder::default())
+            .map_err(|err| Error::InstantiationFailed(err.to_string()))?
+            .run_start(&mut wasmi::NopExternals)
+            .map_err(|_| {
+                Error::InstantiationFailed("start function failed to execute".to_string())
+            })?;
+        let memory = self
+            .memory_export
+            .as_ref()
+            .and_then(|name| instance.export_by_name(name))
+            .and_then(|export| export.as_memory().cloned());
+        Ok(Box::new(WasmiInstance { instance, memory }))
+    }
+}
+
+impl InstanceApi for WasmiInstance {
+    fn call(&mut self, name: &str, args: &[TypedValue]) -> Result<Option<TypedValue>, Error> {
+        let args: Vec<wasmi::RuntimeValue> = args.iter().map(to_wasmi).collect();
+        match self
+            .instance
+            .invoke_export(name, &args, &mut wasmi::NopExternals)
+        {
+            Ok(value) => Ok(value.map(from_wasmi)),
+            // The arguments not matching the signature are reported as a trap by wasmi.
+            Err(wasmi::Error::Trap(trap))
+                if matches!(trap.kind(), wasmi::TrapKind::UnexpectedSignature) =>
+            {
+                Err(wasmi_error(trap.into()))
+            }
+            Err(wasmi::Error::Trap(_)) => Err(Error::Trapped(TrapInfo::new(name, None))),
+            Err(err) => Err(wasmi_error(err)),
+        }
+    }
+
+    fn function_type(&self, name: &str) -> Option<FunctionType> {
+        let func = self.instance.export_by_name(name)?.as_func()?.clone();
+        let signature = func.signature();
+        Some(FunctionType::new(
+            signature
+                .params()
+                .iter()
+                .map(|value_type| value_type_from_wasmi(*value_type))
+                .collect(),
+            signature.return_type().map(value_type_from_wasmi),
+        ))
+    }
+
+    fn memory_size(&self) -> usize {
+        self.memory
+            .as_ref()
+            .map_or(0, |memory| memory.current_size().0 * 65536)
+    }
+
+    fn memory_read(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+        let memory = self.memory.as_ref().ok_or(Error::NoMemoryAvailable)?;
+        memory
+            .get_into(offset, target)
+            .map_err(|_| Error::InvalidMemoryOffsetOrSize)
+    }
+
+    fn memory_write(&mut self, offset: u32, source: &[u8]) -> Result<(), Error> {
+        let memory = self.memory.as_ref().ok_or(Error::NoMemoryAvailable)?;
+        memory
+            .set(offset, source)
+            .map_err(|_| Error::InvalidMemoryOffsetOrSize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An engine returning wrong results of `neg`, to check the reporting.
+    struct WrongNeg;
+
+    impl Engine for WrongNeg {
+        fn name(&self) -> &str {
+            "wrong"
+        }
+
+        fn parse(&self, wasm: &[u8]) -> Result<Box<dyn ModuleApi>, Error> {
+            Ok(Box::new(WrongNegModule(FizzyEngine.parse(wasm)?)))
+        }
+    }
+
+    struct WrongNegModule(Box<dyn ModuleApi>);
+
+    impl ModuleApi for WrongNegModule {
+        fn instantiate(&self) -> Result<Box<dyn InstanceApi>, Error> {
+            Ok(Box::new(WrongNegInstance(self.0.instantiate()?)))
+        }
+    }
+
+    struct WrongNegInstance(Box<dyn InstanceApi>);
+
+    impl InstanceApi for WrongNegInstance {
+        fn call(&mut self, name: &str, args: &[TypedValue]) -> Result<Option<TypedValue>, Error> {
+            match (name, args) {
+                ("neg", [value]) => Ok(Some(*value)),
+                _ => self.0.call(name, args),
+            }
+        }
+
+        fn function_type(&self, name: &str) -> Option<FunctionType> {
+            self.0.function_type(name)
+        }
+
+        fn memory_size(&self) -> usize {
+            self.0.memory_size()
+        }
+
+        fn memory_read(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+            self.0.memory_read(offset, target)
+        }
+
+        fn memory_write(&mut self, offset: u32, source: &[u8]) -> Result<(), Error> {
+            self.0.memory_write(offset, source)
+        }
+    }
+
+    /// The module and the invocations where engines may plausibly differ.
+    fn tricky_cases() -> (Vec<u8>, Vec<Invocation>) {
+        /* wat2wasm
+        (module
+          (memory (export "memory") 1)
+          (func (export "div_s") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+          (func (export "rem_s") (param i32 i32) (result i32) (i32.rem_s (local.get 0) (local.get 1)))
+          (func (export "trunc_f32_s") (param f32) (result i32) (i32.trunc_f32_s (local.get 0)))
+          (func (export "trunc_f64_u") (param f64) (result i32) (i32.trunc_f64_u (local.get 0)))
+          (func (export "neg") (param f32) (result f32) (f32.neg (local.get 0)))
+          (func (export "store") (param i32 i64) (i64.store (local.get 0) (local.get 1)))
+        )
+        */
+        let wasm = hex::decode("0061736d01000000011b0560027f7f017f60017d017f60017c017f60017d017d60027f7e000307060000010203040503010001074407066d656d6f72790200056469765f7300000572656d5f7300010b7472756e635f6633325f7300020b7472756e635f6636345f750003036e656700040573746f726500050a2d060700200020016d0b0700200020016f0b05002000a80b05002000ab0b050020008c0b0900200020013703000b").unwrap();
+        let min = TypedValue::U32(0x8000_0000);
+        let minus_one = TypedValue::U32(u32::MAX);
+        let invocations = vec![
+            // Overflow traps in division, but not in the remainder.
+            Invocation::new("div_s", &[min, minus_one]),
+            Invocation::new("rem_s", &[min, minus_one]),
+            Invocation::new("div_s", &[TypedValue::U32(1), TypedValue::U32(0)]),
+            // Truncation traps for NaNs and out of range values, instead of saturating.
+            Invocation::new("trunc_f32_s", &[TypedValue::F32(f32::NAN)]),
+            Invocation::new("trunc_f32_s", &[TypedValue::F32(2147483648.0)]),
+            Invocation::new("trunc_f32_s", &[TypedValue::F32(-2147483648.0)]),
+            Invocation::new("trunc_f32_s", &[TypedValue::F32(-2147483904.0)]),
+            Invocation::new("trunc_f64_u", &[TypedValue::F64(-0.9)]),
+            Invocation::new("trunc_f64_u", &[TypedValue::F64(-1.0)]),
+            Invocation::new("trunc_f64_u", &[TypedValue::F64(4294967295.9)]),
+            // Only the sign of a NaN is flipped.
+            Invocation::new("neg", &[TypedValue::F32(f32::from_bits(0x7fa0_0001))]),
+            Invocation::new("store", &[TypedValue::U32(65529), TypedValue::U64(1)]),
+            Invocation::new("missing", &[]),
+            Invocation::new("div_s", &[TypedValue::U64(1), TypedValue::U32(1)]),
+        ];
+        (wasm, invocations)
+    }
+
+    #[test]
+    fn same_engines() {
+        let (wasm, invocations) = tricky_cases();
+        assert!(compare_engines(&FizzyEngine, &FizzyEngine, &wasm, &invocations).is_ok());
+        assert!(compare_engines(&FizzyEngine, &FizzyEngine, &[0], &invocations).is_ok());
+    }
+
+    #[test]
+    fn first_divergence() {
+        let (wasm, invocations) = tricky_cases();
+        let divergence = compare_engines(&FizzyEngine, &WrongNeg, &wasm, &invocations).unwrap_err();
+        match &divergence.step {
+            Step::Invoke(index, invocation) => {
+                assert_eq!((*index, invocation.name.as_str()), (10, "neg"))
+            }
+            step => panic!("unexpected step: {:?}", step),
+        }
+        assert_eq!(
+            divergence.to_string(),
+            "divergence in invocation 10: neg(f32:NaN (0x7fa00001))\n  \
+             fizzy: returned f32:NaN (0xffa00001)\n  \
+             wrong: returned f32:NaN (0x7fa00001)"
+        );
+
+        /* wat2wasm
+        (module (func (export "neg") (result f32) (f32.const 0)) (func $trap (unreachable)) (start $trap))
+        */
+        let trapping_start = hex::decode("0061736d010000000108026000017d6000000303020001070701036e656700000801010a0d02070043000000000b0300000b").unwrap();
+        assert!(compare_engines(&FizzyEngine, &WrongNeg, &trapping_start, &[]).is_ok());
+    }
+
+    #[test]
+    fn fizzy_and_wasmi() {
+        assert!(compare(&[0, 0x61, 0x73, 0x6d], &[]).is_ok());
+        let (wasm, invocations) = tricky_cases();
+        if let Err(divergence) = compare(&wasm, &invocations) {
+            panic!("{}", divergence);
+        }
+
+        let mut instance = WasmiEngine.parse(&wasm).unwrap().instantiate().unwrap();
+        assert_eq!(
+            instance.function_type("div_s"),
+            Some(FunctionType::new(
+                vec![ValueType::I32, ValueType::I32],
+                Some(ValueType::I32)
+            ))
+        );
+        assert_eq!(instance.memory_size(), 65536);
+        instance.memory_write(8, &[1, 2, 3]).unwrap();
+        let mut bytes = [0; 4];
+        instance.memory_read(7, &mut bytes).unwrap();
+        assert_eq!(bytes, [0, 1, 2, 3]);
+        assert_eq!(
+            instance.memory_read(65534, &mut bytes),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+    }
+}
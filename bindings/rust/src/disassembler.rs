@@ -0,0 +1,511 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The disassembler of function bodies, decoded from the binary of the module.
+
+use crate::instrument::{self, Reader};
+use crate::opcodes::{self, Immediates};
+use crate::{dump, CostSchedule, Error, Module};
+
+use std::fmt::Write;
+
+const IMPORT_SECTION: u8 = 2;
+const CODE_SECTION: u8 = 10;
+
+const CALL: u8 = 0x10;
+const CALL_INDIRECT: u8 = 0x11;
+const MEMORY_GROW: u8 = 0x40;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+
+impl Module {
+    /// Disassemble the body of the function `func_idx`, with an instruction per line, indented
+    /// by the nesting of blocks, and the counts of locals by their types in the first line if
+    /// there are any, e.g. `locals: 2 i32, 1 f64`.
+    ///
+    /// The immediates are written as they are encoded, e.g. the branch targets as relative
+    /// depths and the alignments of memory instructions as their logarithms, except that
+    /// the called functions are written as `$name` if named in the name section. The output is
+    /// not meant to be parsed as the text format.
+    ///
+    /// Fails if there is no function `func_idx`, or if it is imported and has no body.
+    pub fn disassemble(&self, func_idx: u32) -> Result<String, Error> {
+        let body = self.function_body(func_idx)?;
+        disassemble_body(body, |func_idx| self.function_name(func_idx))
+    }
+
+    /// Disassemble the body of the function `func_idx` like [`Module::disassemble`], with the cost
+    /// of each instruction by `schedule`, and a line before each basic block with the sum of the
+    /// costs of its instructions, e.g. `;; block 1: cost 4`.
+    ///
+    /// The basic blocks are those charged at once by [`parse_metered`](crate::parse_metered), and
+    /// include the `end` of the body, which is listed too. The blocks with calls or `memory.grow`
+    /// are marked as `dynamic`, as only the costs of these instructions are charged with the block,
+    /// but not those of the called functions, nor the growth of the memory. The module is listed
+    /// as it is parsed, therefore a module parsed by `parse_metered` is listed with its
+    /// instrumentation.
+    ///
+    /// Fails if there is no function `func_idx`, or if it is imported and has no body.
+    pub fn disassemble_with_costs(
+        &self,
+        func_idx: u32,
+        schedule: &CostSchedule,
+    ) -> Result<String, Error> {
+        let body = self.function_body(func_idx)?;
+        let (mut out, lines) = disassemble_lines(body, |func_idx| self.function_name(func_idx))?;
+        let mut block = 0;
+        let mut start = 0;
+        while start < lines.len() {
+            let end = lines[start..]
+                .iter()
+                .position(|line| instrument::ends_segment(line.code))
+                .map_or(lines.len(), |offset| start + offset + 1);
+            let costs: Vec<u64> = lines[start..end]
+                .iter()
+                .map(|line| {
+                    schedule
+                        .cost(line.name)
+                        .expect("cost schedule does not cover an instruction")
+                })
+                .collect();
+            write!(
+                out,
+                ";; block {}: cost {}",
+                block,
+                costs.iter().sum::<u64>()
+            )
+            .unwrap();
+            if lines[start..end]
+                .iter()
+                .any(|line| matches!(line.code, CALL | CALL_INDIRECT | MEMORY_GROW))
+            {
+                out.push_str(", dynamic");
+            }
+            out.push('\n');
+            for (line, cost) in lines[start..end].iter().zip(costs) {
+                writeln!(out, "{} ;; {}", line.text, cost).unwrap();
+            }
+            block += 1;
+            start = end;
+        }
+        Ok(out)
+    }
+
+    /// The body of the function `func_idx` in the code section.
+    fn function_body(&self, func_idx: u32) -> Result<&[u8], Error> {
+        let mut imported = 0;
+        let mut reader = Reader::new(&self.bytes()[8..]);
+        while !reader.is_empty() {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let mut section = Reader::new(reader.bytes(size)?);
+            match id {
+                IMPORT_SECTION => {
+                    for _ in 0..section.u32()? {
+                        section.name()?;
+                        section.name()?;
+                        match section.u8()? {
+                            0x00 => {
+                                section.u32()?;
+                                imported += 1;
+                            }
+                            0x01 => {
+                                section.u8()?;
+                                section.limits()?;
+                            }
+                            0x02 => section.limits()?,
+                            _ => {
+                                section.u8()?;
+                                section.u8()?;
+                            }
+                        }
+                    }
+                    if func_idx < imported {
+                        return Err(Error::Other(format!("function {} is imported", func_idx)));
+                    }
+                }
+                CODE_SECTION => {
+                    let count = section.u32()?;
+                    if func_idx - imported >= count {
+                        break;
+                    }
+                    for _ in 0..func_idx - imported {
+                        let size = section.u32()? as usize;
+                        section.bytes(size)?;
+                    }
+                    let size = section.u32()? as usize;
+                    return section.bytes(size);
+                }
+                _ => {}
+            }
+        }
+        Err(Error::Other(format!("function {} not found", func_idx)))
+    }
+}
+
+/// Disassemble the function `body`, with the names of functions by `function_name`.
+fn disassemble_body<'a>(
+    body: &[u8],
+    function_name: impl Fn(u32) -> Option<&'a str>,
+) -> Result<String, Error> {
+    let (mut out, mut lines) = disassemble_lines(body, function_name)?;
+    // The end of the body.
+    lines.pop();
+    for line in lines {
+        out.push_str(&line.text);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// A disassembled instruction.
+struct Line {
+    code: u8,
+    name: &'static str,
+    /// The instruction with its immediates, indented by the nesting of blocks.
+    text: String,
+}
+
+/// Disassemble the function `body` into the header of the counts of locals, empty if there are
+/// none, and the lines of the instructions, including the end of the body.
+fn disassemble_lines<'a>(
+    body: &[u8],
+    function_name: impl Fn(u32) -> Option<&'a str>,
+) -> Result<(String, Vec<Line>), Error> {
+    let mut reader = Reader::new(body);
+    let mut header = String::new();
+    let mut lines = Vec::new();
+
+    let mut locals = Vec::new();
+    for _ in 0..reader.u32()? {
+        let count = reader.u32()?;
+        locals.push(format!("{} {}", count, dump::value_type(&mut reader)?));
+    }
+    if !locals.is_empty() {
+        writeln!(header, "locals: {}", locals.join(", ")).unwrap();
+    }
+
+    let mut depth: usize = 0;
+    loop {
+        let code = reader.u8()?;
+        let opcode = opcodes::by_code(code)
+            .ok_or_else(|| Error::MalformedModule(format!("invalid opcode 0x{:02x}", code)))?;
+        if code == END || code == ELSE {
+            if depth == 0 {
+                if code == END {
+                    lines.push(Line {
+                        code,
+                        name: opcode.name,
+                        text: opcode.name.to_string(),
+                    });
+                    break;
+                }
+                return Err(Error::MalformedModule("unexpected else".to_string()));
+            }
+            if code == END {
+                depth -= 1;
+            }
+        }
+        let indent = if code == ELSE { depth - 1 } else { depth };
+        let mut text = String::new();
+        write!(text, "{:1$}{2}", "", indent * 2, opcode.name).unwrap();
+        match opcode.immediates {
+            Immediates::None => {}
+            Immediates::BlockType => {
+                depth += 1;
+                if reader.rest().first() == Some(&0x40) {
+                    reader.u8()?;
+                } else {
+                    write!(text, " (result {})", dump::value_type(&mut reader)?).unwrap();
+                }
+            }
+            Immediates::Index => {
+                let index = reader.u32()?;
+                match function_name(index) {
+                    Some(name) if code == CALL => write!(text, " ${}", name).unwrap(),
+                    _ => write!(text, " {}", index).unwrap(),
+                }
+            }
+            Immediates::BrTable => {
+                let count = reader.u32()?;
+                // Including the default target.
+                for _ in 0..=count {
+                    write!(text, " {}", reader.u32()?).unwrap();
+                }
+            }
+            Immediates::CallIndirect => {
+                write!(text, " (type {})", reader.u32()?).unwrap();
+                reader.u8()?;
+            }
+            Immediates::MemArg => {
+                let align = reader.u32()?;
+                let offset = reader.u32()?;
+                write!(text, " offset={} align={}", offset, align).unwrap();
+            }
+            Immediates::MemoryIndex => {
+                reader.u8()?;
+            }
+            Immediates::I32 => write!(text, " {}", reader.signed()? as i32).unwrap(),
+            Immediates::I64 => write!(text, " {}", reader.signed()?).unwrap(),
+            Immediates::F32 => {
+                let mut bits = [0; 4];
+                bits.copy_from_slice(reader.bytes(4)?);
+                write!(text, " {:?}", f32::from_bits(u32::from_le_bytes(bits))).unwrap();
+            }
+            Immediates::F64 => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(reader.bytes(8)?);
+                write!(text, " {:?}", f64::from_bits(u64::from_le_bytes(bits))).unwrap();
+            }
+        }
+        lines.push(Line {
+            code,
+            name: opcode.name,
+            text,
+        });
+    }
+    if !reader.is_empty() {
+        return Err(Error::MalformedModule(
+            "unexpected bytes after the end of the body".to_string(),
+        ));
+    }
+    Ok((header, lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn module() -> Module {
+        /* wat2wasm --debug-names
+        (module
+          (func $log (import "env" "log") (param i32))
+          (memory 1)
+          (table 1 funcref)
+          (func $loop (param i32) (result i32) (local i32 i32 i64)
+            (block $exit
+              (loop $continue
+                (br_if $exit (i32.eqz (local.get 0)))
+                (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                (br $continue)))
+            (if (result i32) (local.get 1)
+              (then (call $log (local.get 1)) (i32.const -1))
+              (else (i32.const 0))))
+          (func $memory (param i32)
+            (i64.store offset=8 (local.get 0) (i64.const 0x7fffffffffffffff))
+            (i32.store8 align=1 (i32.const 0) (i32.load16_u offset=2 align=1 (local.get 0)))
+            (f64.store (i32.const 16) (f64.const -0.5))
+            (f32.store (i32.const 24) (f32.const 1.5))
+            (drop (memory.grow (memory.size))))
+          (func $switch (param i32) (result i32)
+            (block (block (block
+              (br_table 0 1 2 (local.get 0)))
+              (return (i32.const 10)))
+              (return (call_indirect (param i32) (result i32) (i32.const 1) (i32.const 0))))
+            (call 2 (i32.const 0))
+            (i32.const 12))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260017f0060017f017f020b0103656e76036c6f67000003040301000104040170000105030100010a8f01032f02027f017e024003402000450d01200120006a2101200041016b21000c000b0b2001047f20011000417f0541000b0b3900200042ffffffffffffffffff00370308410020002f00023a0000411044000000000000e0bf3903004118430000c03f3802003f0040001a0b230002400240024020000e020001020b410a0f0b410141001101000f0b41001002410c0b002e046e616d65011c0400036c6f6701046c6f6f7002066d656d6f727903067377697463680209040000010002000300").unwrap();
+        parse(&input).unwrap()
+    }
+
+    #[test]
+    fn control_flow() {
+        let expected = "\
+locals: 2 i32, 1 i64
+block
+  loop
+    local.get 0
+    i32.eqz
+    br_if 1
+    local.get 1
+    local.get 0
+    i32.add
+    local.set 1
+    local.get 0
+    i32.const 1
+    i32.sub
+    local.set 0
+    br 0
+  end
+end
+local.get 1
+if (result i32)
+  local.get 1
+  call $log
+  i32.const -1
+else
+  i32.const 0
+end
+";
+        assert_eq!(module().disassemble(1).unwrap(), expected);
+    }
+
+    #[test]
+    fn memory() {
+        let expected = "\
+local.get 0
+i64.const 9223372036854775807
+i64.store offset=8 align=3
+i32.const 0
+local.get 0
+i32.load16_u offset=2 align=0
+i32.store8 offset=0 align=0
+i32.const 16
+f64.const -0.5
+f64.store offset=0 align=3
+i32.const 24
+f32.const 1.5
+f32.store offset=0 align=2
+memory.size
+memory.grow
+drop
+";
+        assert_eq!(module().disassemble(2).unwrap(), expected);
+    }
+
+    #[test]
+    fn br_table() {
+        let expected = "\
+block
+  block
+    block
+      local.get 0
+      br_table 0 1 2
+    end
+    i32.const 10
+    return
+  end
+  i32.const 1
+  i32.const 0
+  call_indirect (type 1)
+  return
+end
+i32.const 0
+call $memory
+i32.const 12
+";
+        assert_eq!(module().disassemble(3).unwrap(), expected);
+    }
+
+    #[test]
+    fn costs() {
+        let schedule = CostSchedule::builder()
+            .default_cost(1)
+            .cost("br_if", 3)
+            .cost("call", 10)
+            .build()
+            .unwrap();
+        let expected = "\
+locals: 2 i32, 1 i64
+;; block 0: cost 1
+block ;; 1
+;; block 1: cost 1
+  loop ;; 1
+;; block 2: cost 5
+    local.get 0 ;; 1
+    i32.eqz ;; 1
+    br_if 1 ;; 3
+;; block 3: cost 9
+    local.get 1 ;; 1
+    local.get 0 ;; 1
+    i32.add ;; 1
+    local.set 1 ;; 1
+    local.get 0 ;; 1
+    i32.const 1 ;; 1
+    i32.sub ;; 1
+    local.set 0 ;; 1
+    br 0 ;; 1
+;; block 4: cost 1
+  end ;; 1
+;; block 5: cost 1
+end ;; 1
+;; block 6: cost 2
+local.get 1 ;; 1
+if (result i32) ;; 1
+;; block 7: cost 13, dynamic
+  local.get 1 ;; 1
+  call $log ;; 10
+  i32.const -1 ;; 1
+else ;; 1
+;; block 8: cost 2
+  i32.const 0 ;; 1
+end ;; 1
+;; block 9: cost 1
+end ;; 1
+";
+        assert_eq!(
+            module().disassemble_with_costs(1, &schedule).unwrap(),
+            expected
+        );
+
+        let expected = "\
+;; block 0: cost 17, dynamic
+local.get 0 ;; 1
+i64.const 9223372036854775807 ;; 1
+i64.store offset=8 align=3 ;; 1
+i32.const 0 ;; 1
+local.get 0 ;; 1
+i32.load16_u offset=2 align=0 ;; 1
+i32.store8 offset=0 align=0 ;; 1
+i32.const 16 ;; 1
+f64.const -0.5 ;; 1
+f64.store offset=0 align=3 ;; 1
+i32.const 24 ;; 1
+f32.const 1.5 ;; 1
+f32.store offset=0 align=2 ;; 1
+memory.size ;; 1
+memory.grow ;; 1
+drop ;; 1
+end ;; 1
+";
+        assert_eq!(
+            module()
+                .disassemble_with_costs(2, &CostSchedule::uniform(1))
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            module().disassemble_with_costs(0, &schedule).err(),
+            Some(Error::Other("function 0 is imported".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let module = module();
+        assert_eq!(
+            module.disassemble(0).err(),
+            Some(Error::Other("function 0 is imported".to_string()))
+        );
+        assert_eq!(
+            module.disassemble(4).err(),
+            Some(Error::Other("function 4 not found".to_string()))
+        );
+
+        let no_names = |_| None;
+        assert_eq!(disassemble_body(&[0x00, 0x0b], no_names).unwrap(), "");
+        assert_eq!(
+            disassemble_body(&[0x00, 0x41], no_names).err(),
+            Some(Error::MalformedModule("unexpected EOF".to_string()))
+        );
+        assert_eq!(
+            disassemble_body(&[0x00, 0xff, 0x0b], no_names).err(),
+            Some(Error::MalformedModule("invalid opcode 0xff".to_string()))
+        );
+        assert_eq!(
+            disassemble_body(&[0x00, 0x05, 0x0b], no_names).err(),
+            Some(Error::MalformedModule("unexpected else".to_string()))
+        );
+        assert_eq!(
+            disassemble_body(&[0x00, 0x0b, 0x0b], no_names).err(),
+            Some(Error::MalformedModule(
+                "unexpected bytes after the end of the body".to_string()
+            ))
+        );
+    }
+}
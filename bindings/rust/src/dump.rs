@@ -0,0 +1,385 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The overview of the structure of a module, decoded from its binary.
+
+use crate::instrument::{self, Reader};
+use crate::{Error, Module};
+
+use std::fmt;
+
+/// An item of a module in the order of the text format, as a line without the indentation.
+type Line = String;
+
+/// The lines of the items of a module, by their kinds.
+#[derive(Default)]
+struct Items {
+    types: Vec<Line>,
+    imports: Vec<Line>,
+    functions: Vec<Line>,
+    tables: Vec<Line>,
+    memories: Vec<Line>,
+    globals: Vec<Line>,
+    exports: Vec<Line>,
+    start: Vec<Line>,
+    elements: Vec<Line>,
+    data: Vec<Line>,
+    custom: Vec<Line>,
+}
+
+impl Module {
+    /// Write the overview of the module, in a form inspired by the text format: the types,
+    /// the imports, the functions with their types and the sizes of their bodies, the tables and
+    /// memories with their limits, the globals with their initializers, the exports, the start
+    /// function, the sizes of the element and data segments at their offsets, and the names and
+    /// sizes of the custom sections.
+    ///
+    /// The indices are written in comments, e.g. `(func (;1;) $fac (type 0))`, where the names of
+    /// functions are of the name section.
+    ///
+    /// Fails only if writing to `w` fails.
+    pub fn dump(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        let mut items = Items::default();
+        // The sections are of a validated module, but an item which cannot be decoded is written
+        // as a comment rather than failing, which `Display` is not allowed to.
+        if let Err(err) = self.items(&mut items) {
+            items
+                .custom
+                .push(format!(";; cannot be decoded further: {}", err));
+        }
+        for (name, size) in self.custom_sections() {
+            items
+                .custom
+                .push(format!(";; custom section {:?}, {} bytes", name, size));
+        }
+        writeln!(w, "(module")?;
+        for line in items
+            .types
+            .iter()
+            .chain(&items.imports)
+            .chain(&items.functions)
+            .chain(&items.tables)
+            .chain(&items.memories)
+            .chain(&items.globals)
+            .chain(&items.exports)
+            .chain(&items.start)
+            .chain(&items.elements)
+            .chain(&items.data)
+            .chain(&items.custom)
+        {
+            writeln!(w, "  {}", line)?;
+        }
+        writeln!(w, ")")
+    }
+
+    /// The adapter writing [`Module::dump`] with `{}`.
+    pub fn display(&self) -> ModuleDisplay<'_> {
+        ModuleDisplay(self)
+    }
+
+    /// Decode the items of the sections of the module into `items`, except the custom sections.
+    fn items(&self, items: &mut Items) -> Result<(), Error> {
+        let mut function_count = 0;
+        let mut function_types = Vec::new();
+        let mut table_count = 0;
+        let mut memory_count = 0;
+        let mut global_count = 0;
+
+        let mut reader = Reader::new(&self.bytes()[8..]);
+        while !reader.is_empty() {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let mut section = Reader::new(reader.bytes(size)?);
+            let count = if id == 0 || id == 8 {
+                0
+            } else {
+                section.u32()?
+            };
+            for index in 0..count {
+                match id {
+                    1 => {
+                        section.u8()?;
+                        let signature = signature(&mut section)?;
+                        items
+                            .types
+                            .push(format!("(type (;{};) (func{}))", index, signature));
+                    }
+                    2 => {
+                        let module = String::from_utf8_lossy(section.name()?).into_owned();
+                        let name = String::from_utf8_lossy(section.name()?).into_owned();
+                        let desc = match section.u8()? {
+                            0x00 => {
+                                let desc = self.function(function_count, section.u32()?);
+                                function_count += 1;
+                                desc
+                            }
+                            0x01 => {
+                                section.u8()?;
+                                let limits = limits(&mut section)?;
+                                table_count += 1;
+                                format!("table (;{};) {} funcref", table_count - 1, limits)
+                            }
+                            0x02 => {
+                                let limits = limits(&mut section)?;
+                                memory_count += 1;
+                                format!("memory (;{};) {}", memory_count - 1, limits)
+                            }
+                            _ => {
+                                let global_type = global_type(&mut section)?;
+                                global_count += 1;
+                                format!("global (;{};) {}", global_count - 1, global_type)
+                            }
+                        };
+                        items
+                            .imports
+                            .push(format!("(import {:?} {:?} ({}))", module, name, desc));
+                    }
+                    3 => function_types.push(section.u32()?),
+                    4 => {
+                        section.u8()?;
+                        items.tables.push(format!(
+                            "(table (;{};) {} funcref)",
+                            table_count,
+                            limits(&mut section)?
+                        ));
+                        table_count += 1;
+                    }
+                    5 => {
+                        items.memories.push(format!(
+                            "(memory (;{};) {})",
+                            memory_count,
+                            limits(&mut section)?
+                        ));
+                        memory_count += 1;
+                    }
+                    6 => {
+                        let global_type = global_type(&mut section)?;
+                        items.globals.push(format!(
+                            "(global (;{};) {} {})",
+                            global_count,
+                            global_type,
+                            const_expr(&mut section)?
+                        ));
+                        global_count += 1;
+                    }
+                    7 => {
+                        let name = String::from_utf8_lossy(section.name()?).into_owned();
+                        let kind = match section.u8()? {
+                            0x00 => "func",
+                            0x01 => "table",
+                            0x02 => "memory",
+                            _ => "global",
+                        };
+                        let index = section.u32()?;
+                        if !instrument::is_metering_export(&name) {
+                            items
+                                .exports
+                                .push(format!("(export {:?} ({} {}))", name, kind, index));
+                        }
+                    }
+                    9 => {
+                        section.u32()?;
+                        let offset = const_expr(&mut section)?;
+                        let functions = section.u32()?;
+                        for _ in 0..functions {
+                            section.u32()?;
+                        }
+                        items.elements.push(format!(
+                            "(elem (;{};) {}) ;; {} functions",
+                            index, offset, functions
+                        ));
+                    }
+                    10 => {
+                        let size = section.u32()?;
+                        section.bytes(size as usize)?;
+                        let type_idx = *function_types.get(index as usize).ok_or_else(|| {
+                            Error::MalformedModule("invalid function index".to_string())
+                        })?;
+                        items.functions.push(format!(
+                            "({}) ;; {} bytes",
+                            self.function(function_count + index, type_idx),
+                            size
+                        ));
+                    }
+                    11 => {
+                        section.u32()?;
+                        let offset = const_expr(&mut section)?;
+                        let size = section.name()?.len();
+                        items
+                            .data
+                            .push(format!("(data (;{};) {}) ;; {} bytes", index, offset, size));
+                    }
+                    _ => return Err(Error::MalformedModule(format!("unexpected section {}", id))),
+                }
+            }
+            if id == 8 {
+                items.start.push(format!("(start {})", section.u32()?));
+            }
+        }
+        Ok(())
+    }
+
+    /// The function `func_idx` of the type `type_idx`, with its name if known.
+    fn function(&self, func_idx: u32, type_idx: u32) -> String {
+        match self.function_name(func_idx) {
+            Some(name) => format!("func (;{};) ${} (type {})", func_idx, name, type_idx),
+            None => format!("func (;{};) (type {})", func_idx, type_idx),
+        }
+    }
+}
+
+/// Writes [`Module::dump`] with `{}`, see [`Module::display`].
+pub struct ModuleDisplay<'a>(&'a Module);
+
+impl fmt::Display for ModuleDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.dump(f)
+    }
+}
+
+pub(crate) fn value_type(reader: &mut Reader) -> Result<&'static str, Error> {
+    match reader.u8()? {
+        0x7f => Ok("i32"),
+        0x7e => Ok("i64"),
+        0x7d => Ok("f32"),
+        0x7c => Ok("f64"),
+        _ => Err(Error::MalformedModule("invalid value type".to_string())),
+    }
+}
+
+/// The params and results of a function type, e.g. ` (param i32 i32) (result i32)`.
+fn signature(reader: &mut Reader) -> Result<String, Error> {
+    let mut out = String::new();
+    for kind in &["param", "result"] {
+        let count = reader.u32()?;
+        if count == 0 {
+            continue;
+        }
+        out.push_str(&format!(" ({}", kind));
+        for _ in 0..count {
+            out.push(' ');
+            out.push_str(value_type(reader)?);
+        }
+        out.push(')');
+    }
+    Ok(out)
+}
+
+fn limits(reader: &mut Reader) -> Result<String, Error> {
+    let has_max = reader.u8()? == 0x01;
+    let min = reader.u32()?;
+    if has_max {
+        Ok(format!("{} {}", min, reader.u32()?))
+    } else {
+        Ok(min.to_string())
+    }
+}
+
+fn global_type(reader: &mut Reader) -> Result<String, Error> {
+    let value_type = value_type(reader)?;
+    if reader.u8()? == 0x01 {
+        Ok(format!("(mut {})", value_type))
+    } else {
+        Ok(value_type.to_string())
+    }
+}
+
+/// The constant expression, e.g. `(i32.const 42)`.
+fn const_expr(reader: &mut Reader) -> Result<String, Error> {
+    let expr = match reader.u8()? {
+        0x41 => format!("(i32.const {})", reader.signed()? as i32),
+        0x42 => format!("(i64.const {})", reader.signed()?),
+        0x43 => {
+            let bits = u32::from_le_bytes([reader.u8()?, reader.u8()?, reader.u8()?, reader.u8()?]);
+            format!("(f32.const {:?})", f32::from_bits(bits))
+        }
+        0x44 => {
+            let mut bits = [0; 8];
+            bits.copy_from_slice(reader.bytes(8)?);
+            format!("(f64.const {:?})", f64::from_bits(u64::from_le_bytes(bits)))
+        }
+        0x23 => format!("(global.get {})", reader.u32()?),
+        _ => {
+            return Err(Error::MalformedModule(
+                "invalid constant expression".to_string(),
+            ))
+        }
+    };
+    if reader.u8()? != 0x0b {
+        return Err(Error::MalformedModule(
+            "invalid constant expression".to_string(),
+        ));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, parse_shared};
+
+    #[test]
+    fn dump() {
+        /* wat2wasm
+        (module
+          (func $log (import "env" "log") (param i64 f64))
+          (global (import "env" "base") i32)
+          (table 2 funcref)
+          (memory 1 16)
+          (global $counter (mut i64) (i64.const -7))
+          (global f32 (f32.const 1.5))
+          (global i32 (global.get 0))
+          (func $init (global.set $counter (i64.const 0)))
+          (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+          (export "memory" (memory 0))
+          (start $init)
+          (elem (i32.const 0) $init 2)
+          (data (i32.const 16) "fizzy")
+          (data (global.get 0) "")
+        )
+        */
+        let input = hex::decode("0061736d01000000010f0360027e7c0060000060027f7f017f02170203656e76036c6f67000003656e760462617365037f0003030201020404017000020504010101100613037e0142790b7d00430000c03f0b7f0023000b071002036164640002066d656d6f727902000801010908010041000b0201020a10020600420024010b0700200020016a0b0b10020041100b0566697a7a790023000b00").unwrap();
+        // Append the name section of the functions 0 "log" and 1 "init".
+        let input = [
+            input,
+            hex::decode("0013046e616d65010c0200036c6f670104696e6974").unwrap(),
+        ]
+        .concat();
+        let module = parse(&input).unwrap();
+        let expected = r#"(module
+  (type (;0;) (func (param i64 f64)))
+  (type (;1;) (func))
+  (type (;2;) (func (param i32 i32) (result i32)))
+  (import "env" "log" (func (;0;) $log (type 0)))
+  (import "env" "base" (global (;0;) i32))
+  (func (;1;) $init (type 1)) ;; 6 bytes
+  (func (;2;) (type 2)) ;; 7 bytes
+  (table (;0;) 2 funcref)
+  (memory (;0;) 1 16)
+  (global (;1;) (mut i64) (i64.const -7))
+  (global (;2;) f32 (f32.const 1.5))
+  (global (;3;) i32 (global.get 0))
+  (export "add" (func 2))
+  (export "memory" (memory 0))
+  (start 1)
+  (elem (;0;) (i32.const 0)) ;; 2 functions
+  (data (;0;) (i32.const 16)) ;; 5 bytes
+  (data (;1;) (global.get 0)) ;; 0 bytes
+  ;; custom section "name", 14 bytes
+)
+"#;
+        let mut out = String::new();
+        module.dump(&mut out).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(module.display().to_string(), expected);
+
+        // The custom sections are listed also when the binary is shared with the module.
+        let shared = parse_shared(input.into()).unwrap();
+        assert_eq!(shared.display().to_string(), expected);
+        /* wat2wasm
+        (module)
+        */
+        let empty = parse(&hex::decode("0061736d01000000").unwrap()).unwrap();
+        assert_eq!(empty.display().to_string(), "(module\n)\n");
+    }
+}
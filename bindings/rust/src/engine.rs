@@ -0,0 +1,312 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Traits abstracting a WebAssembly engine, so that Fizzy can be swapped with other interpreters,
+//! e.g. to compare their results.
+//!
+//! The traits are object-safe, so that engines can be selected at runtime as `Box<dyn Engine>`.
+//! Other engines map their errors to [`Error`], and their traps to [`Error::Trapped`].
+//!
+//! The generic conveniences, which would make the traits not object-safe, are provided by
+//! [`InstanceApiExt`] for all instances, including `dyn InstanceApi`.
+//!
+//! ```
+//! use fizzy::engine::{Engine, FizzyEngine};
+//! use fizzy::TypedValue;
+//!
+//! // This wasm binary exports a single sum(u32, u32) -> u32 function.
+//! let wasm = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+//!     0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x73, 0x75, 0x6d,
+//!     0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+//! ];
+//! let engines: Vec<Box<dyn Engine>> = vec![Box::new(FizzyEngine)];
+//! for engine in &engines {
+//!     let module = engine.parse(&wasm).expect("parsing failed");
+//!     let mut instance = module.instantiate().expect("instantiation failed");
+//!     let result = instance
+//!         .call("sum", &[TypedValue::U32(42), TypedValue::U32(24)])
+//!         .expect("execution failed");
+//!     assert_eq!(result, Some(TypedValue::U32(66)), "{}", engine.name());
+//! }
+//! ```
+
+use crate::{
+    Error, FunctionType, Instance, Module, PooledInstance, TrapInfo, TypedValue, Value, WasmType,
+};
+use std::io::Write;
+
+/// A WebAssembly engine.
+pub trait Engine {
+    /// The name of the engine, e.g. for reporting which engine a result comes from.
+    fn name(&self) -> &str;
+
+    /// Parse and validate the module `wasm`.
+    fn parse(&self, wasm: &[u8]) -> Result<Box<dyn ModuleApi>, Error>;
+}
+
+/// A parsed module of an [`Engine`].
+pub trait ModuleApi {
+    /// Create an instance of the module without imports.
+    fn instantiate(&self) -> Result<Box<dyn InstanceApi>, Error>;
+}
+
+/// An instance of a module of an [`Engine`].
+pub trait InstanceApi {
+    /// Call the exported function `name` with `args`, returning its optional output.
+    ///
+    /// Traps are reported as [`Error::Trapped`].
+    fn call(&mut self, name: &str, args: &[TypedValue]) -> Result<Option<TypedValue>, Error>;
+
+    /// The type of the exported function `name`, if found.
+    fn function_type(&self, name: &str) -> Option<FunctionType>;
+
+    /// The current memory size, in bytes.
+    fn memory_size(&self) -> usize;
+
+    /// Copy memory from `offset` to `target`, for the length of `target.len()`.
+    fn memory_read(&self, offset: u32, target: &mut [u8]) -> Result<(), Error>;
+
+    /// Copy `source` to memory at `offset`.
+    fn memory_write(&mut self, offset: u32, source: &[u8]) -> Result<(), Error>;
+}
+
+/// The generic conveniences of [`InstanceApi`], implemented for all instances.
+pub trait InstanceApiExt: InstanceApi {
+    /// Call the exported function `name` with `args`, returning its output as `R`.
+    ///
+    /// Returns [`Error::Other`] if the function has no output, or an output of another type,
+    /// without calling it.
+    fn call_as<R: WasmType>(&mut self, name: &str, args: &[TypedValue]) -> Result<R, Error> {
+        let mismatch = || {
+            Error::Other(format!(
+                "the function {} does not return {}",
+                name,
+                R::VALUE_TYPE
+            ))
+        };
+        // A function which is not found is reported by the call.
+        if let Some(func_type) = self.function_type(name) {
+            if func_type.output != Some(R::VALUE_TYPE) {
+                return Err(mismatch());
+            }
+        }
+        match self.call(name, args)? {
+            Some(value) if value.value_type() == R::VALUE_TYPE => {
+                Ok(R::from_value(Value::from(&value)))
+            }
+            _ => Err(mismatch()),
+        }
+    }
+
+    /// Write the memory from `offset`, for the length of `len`, to `writer`.
+    fn memory_read_into<W: Write + ?Sized>(
+        &self,
+        offset: u32,
+        len: usize,
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        let mut buffer = vec![0; len];
+        self.memory_read(offset, &mut buffer)?;
+        writer.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+impl<T: InstanceApi + ?Sized> InstanceApiExt for T {}
+
+/// The [`Engine`] implementation of Fizzy.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FizzyEngine;
+
+impl Engine for FizzyEngine {
+    fn name(&self) -> &str {
+        "fizzy"
+    }
+
+    fn parse(&self, wasm: &[u8]) -> Result<Box<dyn ModuleApi>, Error> {
+        Ok(Box::new(crate::parse(&wasm)?))
+    }
+}
+
+impl ModuleApi for Module {
+    fn instantiate(&self) -> Result<Box<dyn InstanceApi>, Error> {
+        Ok(Box::new(Module::instantiate(self)?))
+    }
+}
+
+impl InstanceApi for Instance {
+    fn call(&mut self, name: &str, args: &[TypedValue]) -> Result<Option<TypedValue>, Error> {
+        let result = self.execute(name, args)?;
+        if result.trapped() {
+            return Err(Error::Trapped(TrapInfo::new(name, self.take_host_trap())));
+        }
+        Ok(result.value())
+    }
+
+    fn function_type(&self, name: &str) -> Option<FunctionType> {
+        self.with_exported_function(name, |_, func_type| func_type.clone())
+    }
+
+    fn memory_size(&self) -> usize {
+        Instance::memory_size(self)
+    }
+
+    fn memory_read(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+        self.memory_get(offset, target)
+    }
+
+    fn memory_write(&mut self, offset: u32, source: &[u8]) -> Result<(), Error> {
+        self.memory_set(offset, source)
+    }
+}
+
+impl InstanceApi for PooledInstance<'_> {
+    fn call(&mut self, name: &str, args: &[TypedValue]) -> Result<Option<TypedValue>, Error> {
+        InstanceApi::call(&mut **self, name, args)
+    }
+
+    fn function_type(&self, name: &str) -> Option<FunctionType> {
+        InstanceApi::function_type(&**self, name)
+    }
+
+    fn memory_size(&self) -> usize {
+        Instance::memory_size(self)
+    }
+
+    fn memory_read(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+        self.memory_get(offset, target)
+    }
+
+    fn memory_write(&mut self, offset: u32, source: &[u8]) -> Result<(), Error> {
+        self.memory_set(offset, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TrapKind, ValueType};
+
+    /* wat2wasm
+    (module
+      (memory 1)
+      (func (export "store") (param i32 i64) (i64.store (local.get 0) (local.get 1)))
+      (func (export "load") (param i32) (result i64) (i64.load (local.get 0)))
+      (func (export "trap") (unreachable))
+    )
+    */
+    const STORE_LOAD: &str = "0061736d01000000010e0360027f7e0060017f017e60000003040300010205030100010717030573746f72650000046c6f61640001047472617000020a17030900200020013703000b070020002903000b0300000b";
+
+    #[test]
+    fn through_trait_objects() {
+        let input = hex::decode(STORE_LOAD).unwrap();
+
+        let engine: Box<dyn Engine> = Box::new(FizzyEngine);
+        assert_eq!(engine.name(), "fizzy");
+        let module = engine.parse(&input).unwrap();
+        let mut instance = module.instantiate().unwrap();
+
+        assert_eq!(
+            instance.function_type("load"),
+            Some(FunctionType::new(
+                vec![ValueType::I32],
+                Some(ValueType::I64)
+            ))
+        );
+        assert_eq!(instance.function_type("memory"), None);
+
+        assert_eq!(
+            instance.call(
+                "store",
+                &[TypedValue::U32(8), TypedValue::U64(0x0102_0304_0506_0708)]
+            ),
+            Ok(None)
+        );
+        let mut bytes = [0; 8];
+        instance.memory_read(8, &mut bytes).unwrap();
+        assert_eq!(bytes, [8, 7, 6, 5, 4, 3, 2, 1]);
+        instance
+            .memory_write(16, &[1, 0, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(
+            instance.call("load", &[TypedValue::U32(16)]),
+            Ok(Some(TypedValue::U64(1)))
+        );
+        assert_eq!(instance.memory_size(), 65536);
+        assert_eq!(
+            instance.memory_read(65535, &mut bytes),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+
+        match instance.call("trap", &[]) {
+            Err(Error::Trapped(info)) => {
+                assert_eq!(info.function(), Some("trap"));
+                assert_eq!(info.kind(), &TrapKind::Wasm);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(instance.call("missing", &[]), Err(Error::FunctionNotFound));
+
+        // Instances of the same module are independent.
+        let mut other = module.instantiate().unwrap();
+        assert_eq!(
+            other.call("load", &[TypedValue::U32(8)]),
+            Ok(Some(TypedValue::U64(0)))
+        );
+
+        assert!(matches!(
+            engine.parse(&[0, 0x61, 0x73, 0x6d]),
+            Err(Error::MalformedModule(_))
+        ));
+    }
+
+    #[test]
+    fn plugin_host() {
+        // A host storing the instances of its plugins without knowing their types.
+        struct Host<'a> {
+            plugins: Vec<Box<dyn InstanceApi + 'a>>,
+        }
+
+        fn store_and_load(instance: &mut dyn InstanceApi, value: u64) -> Result<u64, Error> {
+            instance.call("store", &[TypedValue::U32(0), TypedValue::U64(value)])?;
+            instance.call_as::<u64>("load", &[TypedValue::U32(0)])
+        }
+
+        let module = crate::parse(&hex::decode(STORE_LOAD).unwrap()).unwrap();
+        let pool = crate::InstancePool::new(module.clone(), 1, crate::ResetPolicy::None).unwrap();
+        let mut host = Host {
+            plugins: vec![
+                Box::new(module.instantiate().unwrap()),
+                Box::new(pool.get().unwrap()),
+            ],
+        };
+        for (i, plugin) in host.plugins.iter_mut().enumerate() {
+            assert_eq!(
+                store_and_load(plugin.as_mut(), i as u64 + 1),
+                Ok(i as u64 + 1)
+            );
+            let mut bytes = Vec::new();
+            plugin.memory_read_into(0, 2, &mut bytes).unwrap();
+            assert_eq!(bytes, [i as u8 + 1, 0]);
+            assert_eq!(
+                plugin.call_as::<u32>("load", &[TypedValue::U32(0)]),
+                Err(Error::Other(
+                    "the function load does not return i32".to_string()
+                ))
+            );
+            assert_eq!(
+                plugin.call_as::<u64>("store", &[TypedValue::U32(0), TypedValue::U64(0)]),
+                Err(Error::Other(
+                    "the function store does not return i64".to_string()
+                ))
+            );
+        }
+        drop(host);
+
+        // The pooled instance was returned to the pool, keeping its memory.
+        let mut pooled = pool.get().unwrap();
+        assert_eq!(pooled.call_as::<u64>("load", &[TypedValue::U32(0)]), Ok(2));
+    }
+}
@@ -0,0 +1,252 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running the entry functions of programs following the WASI and Emscripten conventions.
+
+use crate::{Error, FunctionType, Instance, TrapInfo, TypedValue, ValueType};
+use std::convert::TryFrom;
+
+impl Instance {
+    /// Run the program by the entry function it exports, and return its exit status:
+    ///
+    /// - `_start() -> ()` of WASI commands, returning 0 or the code passed to `proc_exit`,
+    /// - `_initialize() -> ()` of WASI reactors, returning 0,
+    /// - `main(argc: i32, argv: i32) -> i32` or `__main_argc_argv` with the same signature,
+    ///   receiving `args` copied to the memory allocated by the exported `malloc` or
+    ///   `__wbindgen_malloc`,
+    /// - `main() -> i32` or `__main_void() -> i32`.
+    ///
+    /// `_initialize` is executed before `main` if the program exports both. The arguments of
+    /// `_start` are provided by the WASI functions instead, e.g. by `WasiConfig` with the
+    /// `wasi` feature, therefore `args` are not used for it.
+    ///
+    /// Fails if the program exports none of these functions, if `args` cannot be passed to
+    /// `main` because no allocator is exported, or if the execution traps other than by an exit.
+    pub fn run_main(&mut self, args: &[&str]) -> Result<i32, Error> {
+        let void = FunctionType::new(Vec::new(), None);
+        if self.exports_function("_start", &void) {
+            return self.run_entry("_start", &[]);
+        }
+        let initialized = self.exports_function("_initialize", &void);
+        if initialized {
+            self.run_entry("_initialize", &[])?;
+        }
+
+        let main_argc_argv = FunctionType::new(vec![ValueType::I32; 2], Some(ValueType::I32));
+        if let Some(name) = ["main", "__main_argc_argv"]
+            .iter()
+            .find(|name| self.exports_function(name, &main_argc_argv))
+        {
+            let (argc, argv) = self.copy_args(args)?;
+            return self.run_entry(name, &[TypedValue::U32(argc), TypedValue::U32(argv)]);
+        }
+        let main_void = FunctionType::new(Vec::new(), Some(ValueType::I32));
+        if let Some(name) = ["main", "__main_void"]
+            .iter()
+            .find(|name| self.exports_function(name, &main_void))
+        {
+            return self.run_entry(name, &[]);
+        }
+
+        if initialized {
+            return Ok(0);
+        }
+        Err(Error::Other(
+            "no entry function: the module exports none of _start() -> (), _initialize() -> (), \
+             main(i32, i32) -> i32, main() -> i32 and __main_void() -> i32"
+                .to_string(),
+        ))
+    }
+
+    pub(crate) fn exports_function(&self, name: &str, func_type: &FunctionType) -> bool {
+        self.with_exported_function(name, |_, export_type| export_type == func_type)
+            .unwrap_or(false)
+    }
+
+    /// Execute the entry function `name`, and return its result or the code passed to an exit.
+    fn run_entry(&mut self, name: &str, args: &[TypedValue]) -> Result<i32, Error> {
+        let result = self.execute(name, args)?;
+        if result.trapped() {
+            let trap = self.take_host_trap();
+            return match trap.as_ref().and_then(|trap| trap.exit_code()) {
+                Some(code) => Ok(code as i32),
+                None => Err(Error::Trapped(TrapInfo::new(name, trap))),
+            };
+        }
+        Ok(result.value().and_then(|value| value.as_i32()).unwrap_or(0))
+    }
+
+    /// Copy `args` to the memory as the `argv` array of pointers to null-terminated strings,
+    /// terminated by a null pointer, and return `argc` and `argv`.
+    fn copy_args(&mut self, args: &[&str]) -> Result<(u32, u32), Error> {
+        let array_size = (args.len() + 1) * 4;
+        let size = array_size + args.iter().map(|arg| arg.len() + 1).sum::<usize>();
+        let argv = self.allocate(size)?;
+
+        let mut array = Vec::with_capacity(array_size);
+        let mut strings = Vec::with_capacity(size - array_size);
+        for arg in args {
+            let address = argv as usize + array_size + strings.len();
+            array.extend_from_slice(&(address as u32).to_le_bytes());
+            strings.extend_from_slice(arg.as_bytes());
+            strings.push(0);
+        }
+        array.extend_from_slice(&0u32.to_le_bytes());
+        array.extend_from_slice(&strings);
+        self.memory_set(argv, &array)?;
+        Ok((args.len() as u32, argv))
+    }
+
+    /// Allocate `size` bytes of the memory by the allocator exported by the program.
+    fn allocate(&mut self, size: usize) -> Result<u32, Error> {
+        let malloc = FunctionType::new(vec![ValueType::I32], Some(ValueType::I32));
+        // The allocator of wasm-bindgen also takes the alignment.
+        let aligned_malloc = FunctionType::new(vec![ValueType::I32; 2], Some(ValueType::I32));
+        let failed = || {
+            Error::Other(format!(
+                "cannot allocate {} bytes for the arguments of main",
+                size
+            ))
+        };
+        let size = u32::try_from(size).map_err(|_| failed())?;
+        for name in &["malloc", "__wbindgen_malloc"] {
+            let args = if self.exports_function(name, &malloc) {
+                vec![TypedValue::U32(size)]
+            } else if self.exports_function(name, &aligned_malloc) {
+                vec![TypedValue::U32(size), TypedValue::U32(4)]
+            } else {
+                continue;
+            };
+            let result = self.execute(name, &args)?;
+            if result.trapped() {
+                return Err(Error::Trapped(TrapInfo::new(name, self.take_host_trap())));
+            }
+            // The arguments must end within the 32-bit address space.
+            return match result.value().and_then(|value| value.as_u32()) {
+                Some(address) if address != 0 && address.checked_add(size).is_some() => Ok(address),
+                _ => Err(failed()),
+            };
+        }
+        Err(Error::Other(
+            "cannot pass the arguments to main(argc, argv): the module exports no allocator, \
+             malloc(i32) -> i32 or __wbindgen_malloc"
+                .to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Caller, ImportsBuilder, Trap};
+
+    #[test]
+    fn start() {
+        /* wat2wasm
+        (module
+          (func $exit (import "env" "exit") (param i32))
+          (func (export "_start") (call $exit (i32.const 3)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001080260017f00600000020c0103656e760465786974000003020101070a01065f737461727400010a08010600410310000b").unwrap();
+        let module = parse(&input).unwrap();
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "exit",
+            |_: &mut Caller, code: u32| -> Result<(), Trap> { Err(Trap::exit(code)) },
+        );
+        let mut instance = module.instantiate_with_imports(imports).unwrap();
+        assert_eq!(instance.run_main(&["ignored"]), Ok(3));
+
+        /* wat2wasm
+        (module (func (export "_start")) (func (export "main") (result i32) (i32.const 1)))
+        */
+        let input = hex::decode("0061736d010000000108026000006000017f0303020001071102065f73746172740000046d61696e00010a090202000b040041010b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(instance.run_main(&[]), Ok(0));
+    }
+
+    #[test]
+    fn main_with_args() {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (global $heap (mut i32) (i32.const 1024))
+          (func (export "malloc") (param $size i32) (result i32)
+            (global.get $heap)
+            (global.set $heap (i32.add (global.get $heap) (local.get $size))))
+          (func (export "main") (param $argc i32) (param $argv i32) (result i32)
+            (local $end i32)
+            (local.set $end (i32.add (local.get $argv) (i32.shl (local.get $argc) (i32.const 2))))
+            (if (i32.load (local.get $end)) (then (unreachable)))
+            (i32.add
+              (i32.mul (local.get $argc) (i32.const 1000))
+              (i32.load8_u (i32.load (i32.sub (local.get $end) (i32.const 4))))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010c0260017f017f60027f7f017f030302000105030100010607017f014180080b071102066d616c6c6f630000046d61696e00010a37020b002300230020006a24000b2901017f200120004102746a210220022802000440000b200041e8076c200241046b2802002d00006a0b").unwrap();
+        let module = parse(&input).unwrap();
+        // main returns argc * 1000 + the first character of the last argument, and traps unless
+        // argv is terminated by a null pointer.
+        let mut instance = module.instantiate().unwrap();
+        assert_eq!(
+            instance.run_main(&["prog", "xyz"]),
+            Ok(2000 + i32::from(b'x'))
+        );
+        let mut instance = module.instantiate().unwrap();
+        assert_eq!(
+            instance.run_main(&["prog", "a", "bc"]),
+            Ok(3000 + i32::from(b'b'))
+        );
+
+        /* wat2wasm
+        (module (memory 1) (func (export "main") (param i32 i32) (result i32) (local.get 0)))
+        */
+        let input = hex::decode("0061736d0100000001070160027f7f017f030201000503010001070801046d61696e00000a0601040020000b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(
+            instance.run_main(&["prog"]),
+            Err(Error::Other(
+                "cannot pass the arguments to main(argc, argv): the module exports no allocator, \
+                 malloc(i32) -> i32 or __wbindgen_malloc"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn reactor() {
+        /* wat2wasm
+        (module
+          (global $status (mut i32) (i32.const 0))
+          (func (export "_initialize") (global.set $status (i32.const 5)))
+          (func (export "__main_void") (result i32) (global.get $status))
+        )
+        */
+        let input = hex::decode("0061736d010000000108026000006000017f03030200010606017f0141000b071d020b5f696e697469616c697a6500000b5f5f6d61696e5f766f696400010a0d020600410524000b040023000b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(instance.run_main(&[]), Ok(5));
+    }
+
+    #[test]
+    fn no_entry_function() {
+        /* wat2wasm
+        (module (func (export "main") (param i64) (result i32) (i32.const 0)))
+        */
+        let input = hex::decode(
+            "0061736d0100000001060160017e017f03020100070801046d61696e00000a0601040041000b",
+        )
+        .unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(
+            instance.run_main(&[]),
+            Err(Error::Other(
+                "no entry function: the module exports none of _start() -> (), _initialize() -> (), \
+                 main(i32, i32) -> i32, main() -> i32 and __main_void() -> i32"
+                    .to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,404 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Execution of Ethereum contracts following the [eWASM](https://github.com/ewasm/design) conventions.
+//!
+//! A contract exports `main` and `memory`, and imports the Ethereum Environment Interface from the
+//! `ethereum` module. The supported subset of it is:
+//!
+//! - `useGas(i64)`, `getGasLeft() -> i64`,
+//! - `storageStore(keyOffset: i32, valueOffset: i32)`, `storageLoad(keyOffset: i32, resultOffset: i32)`,
+//! - `getCallDataSize() -> i32`, `callDataCopy(resultOffset: i32, dataOffset: i32, length: i32)`,
+//! - `finish(dataOffset: i32, length: i32)`, `revert(dataOffset: i32, length: i32)`.
+//!
+//! The storage and the call data are provided by an [`EthereumHost`], while the gas and the return
+//! data are accounted for by the adapter.
+
+use crate::{parse, Caller, Error, ImportsBuilder, Trap, TrapInfo};
+
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+/// A 256-bit word used for the storage keys and values.
+pub type Bytes32 = [u8; 32];
+
+/// The host of the execution of a contract, providing its storage and the call data.
+pub trait EthereumHost: Send {
+    /// The value stored under `key` in the storage of the contract, zero if none.
+    fn storage_load(&mut self, key: &Bytes32) -> Bytes32;
+
+    /// Store `value` under `key` in the storage of the contract.
+    fn storage_store(&mut self, key: &Bytes32, value: &Bytes32);
+
+    /// The input data of the call.
+    fn call_data(&self) -> &[u8];
+}
+
+/// The way the execution of a contract has ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EthereumOutcome {
+    /// `main` has returned or `finish` has been called.
+    Finished,
+    /// `revert` has been called.
+    Reverted,
+    /// The gas has been exhausted by `useGas`.
+    OutOfGas,
+    /// The execution has trapped, either in WebAssembly code or in a host function.
+    Trapped(TrapInfo),
+}
+
+/// The result of executing a contract with [`execute`].
+pub struct EthereumRun<H> {
+    outcome: EthereumOutcome,
+    return_data: Vec<u8>,
+    gas_left: i64,
+    host: H,
+}
+
+impl<H> EthereumRun<H> {
+    /// The way the execution has ended.
+    pub fn outcome(&self) -> &EthereumOutcome {
+        &self.outcome
+    }
+
+    /// The data passed to `finish` or `revert`, empty otherwise.
+    pub fn return_data(&self) -> &[u8] {
+        &self.return_data
+    }
+
+    /// The gas left after the execution, 0 if it has been exhausted.
+    pub fn gas_left(&self) -> i64 {
+        self.gas_left
+    }
+
+    /// The host, including the changes of the storage made by the contract.
+    pub fn into_host(self) -> H {
+        self.host
+    }
+}
+
+/// The state shared by the host functions of an execution.
+struct ExecutionState<H> {
+    host: H,
+    gas_left: i64,
+    /// The outcome, if the execution has been ended by a host function.
+    outcome: Option<EthereumOutcome>,
+    return_data: Vec<u8>,
+}
+
+type SharedState<H> = Arc<Mutex<ExecutionState<H>>>;
+
+fn read_bytes32(caller: &Caller, offset: u32) -> Result<Bytes32, Trap> {
+    let mut word = [0; 32];
+    caller
+        .memory_get(offset, &mut word)
+        .map_err(|err| Trap::new(err.to_string()))?;
+    Ok(word)
+}
+
+fn write_bytes(caller: &mut Caller, offset: u32, bytes: &[u8]) -> Result<(), Trap> {
+    caller
+        .memory_set(offset, bytes)
+        .map_err(|err| Trap::new(err.to_string()))
+}
+
+/// End the execution with `outcome`, returning the memory at `offset` of `length`.
+fn end<H>(
+    state: &SharedState<H>,
+    caller: &Caller,
+    offset: u32,
+    length: u32,
+    outcome: EthereumOutcome,
+) -> Result<(), Trap> {
+    // The range is checked before allocating the data, whose length is controlled by the module.
+    if u64::from(offset) + u64::from(length) > caller.memory_size() as u64 {
+        return Err(Trap::new(Error::InvalidMemoryOffsetOrSize.to_string()));
+    }
+    let mut return_data = vec![0; length as usize];
+    caller
+        .memory_get(offset, &mut return_data)
+        .map_err(|err| Trap::new(err.to_string()))?;
+    let mut state = state.lock().unwrap();
+    state.return_data = return_data;
+    state.outcome = Some(outcome);
+    Err(Trap::new("execution ended"))
+}
+
+fn add_functions<H: EthereumHost + 'static>(builder: &mut ImportsBuilder, state: &SharedState<H>) {
+    let s = Arc::clone(state);
+    builder.func("ethereum", "useGas", move |_: &mut Caller, amount: i64| {
+        let mut state = s.lock().unwrap();
+        if amount < 0 || amount > state.gas_left {
+            state.gas_left = 0;
+            state.outcome = Some(EthereumOutcome::OutOfGas);
+            return Err(Trap::new("out of gas"));
+        }
+        state.gas_left -= amount;
+        Ok(())
+    });
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "getGasLeft",
+        move |_: &mut Caller| -> Result<i64, Trap> { Ok(s.lock().unwrap().gas_left) },
+    );
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "storageStore",
+        move |caller: &mut Caller, key_offset: u32, value_offset: u32| {
+            let key = read_bytes32(caller, key_offset)?;
+            let value = read_bytes32(caller, value_offset)?;
+            s.lock().unwrap().host.storage_store(&key, &value);
+            Ok(())
+        },
+    );
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "storageLoad",
+        move |caller: &mut Caller, key_offset: u32, result_offset: u32| {
+            let key = read_bytes32(caller, key_offset)?;
+            let value = s.lock().unwrap().host.storage_load(&key);
+            write_bytes(caller, result_offset, &value)
+        },
+    );
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "getCallDataSize",
+        move |_: &mut Caller| -> Result<u32, Trap> {
+            u32::try_from(s.lock().unwrap().host.call_data().len())
+                .map_err(|_| Trap::new("call data is too large"))
+        },
+    );
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "callDataCopy",
+        move |caller: &mut Caller, result_offset: u32, data_offset: u32, length: u32| {
+            let state = s.lock().unwrap();
+            let data = state
+                .host
+                .call_data()
+                .get(data_offset as usize..)
+                .and_then(|data| data.get(..length as usize))
+                .ok_or_else(|| Trap::new("call data access out of bounds"))?;
+            write_bytes(caller, result_offset, data)
+        },
+    );
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "finish",
+        move |caller: &mut Caller, offset: u32, length: u32| {
+            end(&s, caller, offset, length, EthereumOutcome::Finished)
+        },
+    );
+    let s = Arc::clone(state);
+    builder.func(
+        "ethereum",
+        "revert",
+        move |caller: &mut Caller, offset: u32, length: u32| {
+            end(&s, caller, offset, length, EthereumOutcome::Reverted)
+        },
+    );
+}
+
+/// Execute the `main` function of the contract `wasm` with `host`, limited to `gas_limit`.
+///
+/// Fails if the contract cannot be parsed, instantiated or executed, e.g. when it imports
+/// functions not supported by the adapter.
+pub fn execute<H: EthereumHost + 'static>(
+    wasm: &[u8],
+    host: H,
+    gas_limit: i64,
+) -> Result<EthereumRun<H>, Error> {
+    let state = Arc::new(Mutex::new(ExecutionState {
+        host,
+        gas_left: gas_limit,
+        outcome: None,
+        return_data: Vec::new(),
+    }));
+    let mut imports = ImportsBuilder::new();
+    add_functions(&mut imports, &state);
+    let mut instance = parse(&wasm)?.instantiate_with_imports(imports)?;
+    let result = instance.execute("main", &[])?;
+    let trap = if result.trapped() {
+        Some(instance.take_host_trap())
+    } else {
+        None
+    };
+    // The host functions, holding the other references to the state, are dropped with the instance.
+    drop(instance);
+    let state = match Arc::try_unwrap(state) {
+        Ok(state) => state.into_inner().unwrap(),
+        Err(_) => unreachable!("the host functions are dropped"),
+    };
+    let outcome = match (state.outcome, trap) {
+        (Some(outcome), _) => outcome,
+        (None, None) => EthereumOutcome::Finished,
+        (None, Some(trap)) => EthereumOutcome::Trapped(TrapInfo::new("main", trap)),
+    };
+    Ok(EthereumRun {
+        outcome,
+        return_data: state.return_data,
+        gas_left: state.gas_left,
+        host: state.host,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct TestHost {
+        storage: HashMap<Bytes32, Bytes32>,
+        call_data: Vec<u8>,
+    }
+
+    impl EthereumHost for TestHost {
+        fn storage_load(&mut self, key: &Bytes32) -> Bytes32 {
+            self.storage.get(key).copied().unwrap_or([0; 32])
+        }
+
+        fn storage_store(&mut self, key: &Bytes32, value: &Bytes32) {
+            self.storage.insert(*key, *value);
+        }
+
+        fn call_data(&self) -> &[u8] {
+            &self.call_data
+        }
+    }
+
+    /// Execute the counter contract.
+    fn execute_counter(host: TestHost, gas_limit: i64) -> EthereumRun<TestHost> {
+        /* wat2wasm
+        (module
+          (func $useGas (import "ethereum" "useGas") (param i64))
+          (func $getCallDataSize (import "ethereum" "getCallDataSize") (result i32))
+          (func $callDataCopy (import "ethereum" "callDataCopy") (param i32 i32 i32))
+          (func $storageLoad (import "ethereum" "storageLoad") (param i32 i32))
+          (func $storageStore (import "ethereum" "storageStore") (param i32 i32))
+          (func $finish (import "ethereum" "finish") (param i32 i32))
+          (func $revert (import "ethereum" "revert") (param i32 i32))
+          (memory (export "memory") 1)
+          ;; Increments the last byte of the value stored under the key given as the call data,
+          ;; and returns the previous value.
+          (func (export "main")
+            (call $useGas (i64.const 100))
+            (if (i32.ne (call $getCallDataSize) (i32.const 32))
+              (then (call $revert (i32.const 0) (i32.const 0))))
+            (call $callDataCopy (i32.const 0) (i32.const 0) (i32.const 32))
+            (call $storageLoad (i32.const 0) (i32.const 32))
+            (i64.store offset=64 (i32.const 0) (i64.load offset=32 (i32.const 0)))
+            (i64.store offset=72 (i32.const 0) (i64.load offset=40 (i32.const 0)))
+            (i64.store offset=80 (i32.const 0) (i64.load offset=48 (i32.const 0)))
+            (i64.store offset=88 (i32.const 0) (i64.load offset=56 (i32.const 0)))
+            (i32.store8 (i32.const 95) (i32.add (i32.load8_u (i32.const 95)) (i32.const 1)))
+            (call $storageStore (i32.const 0) (i32.const 64))
+            (call $finish (i32.const 32) (i32.const 32))
+          )
+        )
+        */
+        let wasm = hex::decode("0061736d0100000001170560017e006000017f60037f7f7f0060027f7f006000000299010708657468657265756d06757365476173000008657468657265756d0f67657443616c6c4461746153697a65000108657468657265756d0c63616c6c44617461436f7079000208657468657265756d0b73746f726167654c6f6164000308657468657265756d0c73746f7261676553746f7265000308657468657265756d0666696e697368000308657468657265756d067265766572740003030201040503010001071102066d656d6f72790200046d61696e00070a6901670042e4001000100141204704404100410010060b41004100412010024100412010034100410029032037034041004100290328370348410041002903303703504100410029033837035841df0041df002d000041016a3a0000410041c00010044120412010050b").unwrap();
+        execute(&wasm, host, gas_limit).unwrap()
+    }
+
+    fn word(last: u8) -> Bytes32 {
+        let mut word = [0; 32];
+        word[31] = last;
+        word
+    }
+
+    #[test]
+    fn storage() {
+        let host = TestHost {
+            call_data: word(7).to_vec(),
+            ..TestHost::default()
+        };
+        let run = execute_counter(host, 1000);
+        assert_eq!(*run.outcome(), EthereumOutcome::Finished);
+        assert_eq!(run.return_data(), word(0));
+        assert_eq!(run.gas_left(), 900);
+
+        let run = execute_counter(run.into_host(), 1000);
+        assert_eq!(*run.outcome(), EthereumOutcome::Finished);
+        assert_eq!(run.return_data(), word(1));
+        let host = run.into_host();
+        assert_eq!(host.storage.len(), 1);
+        assert_eq!(host.storage[&word(7)], word(2));
+    }
+
+    #[test]
+    fn revert_and_gas() {
+        let run = execute_counter(TestHost::default(), 1000);
+        assert_eq!(*run.outcome(), EthereumOutcome::Reverted);
+        assert!(run.return_data().is_empty());
+        assert!(run.into_host().storage.is_empty());
+
+        let host = TestHost {
+            call_data: word(7).to_vec(),
+            ..TestHost::default()
+        };
+        let run = execute_counter(host, 99);
+        assert_eq!(*run.outcome(), EthereumOutcome::OutOfGas);
+        assert_eq!(run.gas_left(), 0);
+        assert!(run.into_host().storage.is_empty());
+    }
+
+    #[test]
+    fn traps() {
+        /* wat2wasm
+        (module
+          (func $callDataCopy (import "ethereum" "callDataCopy") (param i32 i32 i32))
+          (memory (export "memory") 1)
+          (func (export "main") (call $callDataCopy (i32.const 0) (i32.const 1) (i32.const 32)))
+        )
+        */
+        let wasm = hex::decode("0061736d01000000010a0260037f7f7f0060000002190108657468657265756d0c63616c6c44617461436f70790000030201010503010001071102066d656d6f72790200046d61696e00010a0c010a0041004101412010000b").unwrap();
+        let host = TestHost {
+            call_data: word(7).to_vec(),
+            ..TestHost::default()
+        };
+        let run = execute(&wasm, host, 1000).unwrap();
+        assert_eq!(
+            *run.outcome(),
+            EthereumOutcome::Trapped(TrapInfo::new(
+                "main",
+                Some(Trap::new("call data access out of bounds"))
+            ))
+        );
+
+        /* wat2wasm
+        (module
+          (func $getGasLeft (import "ethereum" "getGasLeft") (result i64))
+          (func (export "main") (if (i64.ne (call $getGasLeft) (i64.const 5)) (then unreachable)))
+        )
+        */
+        let wasm = hex::decode("0061736d010000000108026000017e60000002170108657468657265756d0a6765744761734c656674000003020101070801046d61696e00010a0d010b0010004205520440000b0b").unwrap();
+        let run = execute(&wasm, TestHost::default(), 5).unwrap();
+        assert_eq!(*run.outcome(), EthereumOutcome::Finished);
+        let run = execute(&wasm, TestHost::default(), 6).unwrap();
+        assert_eq!(
+            *run.outcome(),
+            EthereumOutcome::Trapped(TrapInfo::new("main", None))
+        );
+
+        /* wat2wasm
+        (module (func (import "ethereum" "call") (param i64 i32 i32 i32 i32) (result i32)))
+        */
+        let wasm = hex::decode(
+            "0061736d01000000010a0160057e7f7f7f7f017f02110108657468657265756d0463616c6c0000",
+        )
+        .unwrap();
+        assert!(execute(&wasm, TestHost::default(), 5)
+            .err()
+            .unwrap()
+            .is_missing_import());
+    }
+}
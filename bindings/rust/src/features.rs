@@ -0,0 +1,370 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The detection of the features beyond WebAssembly 1.0 in the modules rejected by the engine.
+
+use crate::instrument::{
+    Reader, CODE_SECTION, DATA_SECTION, GLOBAL_SECTION, IMPORT_SECTION, MEMORY_SECTION,
+    TYPE_SECTION,
+};
+use crate::opcodes::{self, Immediates};
+use crate::{Error, Module};
+
+const ELEMENT_SECTION: u8 = 9;
+const DATA_COUNT_SECTION: u8 = 12;
+
+const MULTIPLE_MEMORIES: &str = "multiple memories";
+const BULK_MEMORY: &str = "bulk-memory";
+const SIMD: &str = "simd";
+const MULTI_VALUE: &str = "multi-value";
+const SIGN_EXTENSION: &str = "sign-extension";
+const NONTRAPPING_FLOAT_TO_INT: &str = "nontrapping-float-to-int";
+const REFERENCE_TYPES: &str = "reference-types";
+const THREADS: &str = "threads";
+
+/// The names of the detected features, see [`Module::detect_features`].
+#[cfg(feature = "serde")]
+const FEATURES: &[&str] = &[
+    MULTIPLE_MEMORIES,
+    BULK_MEMORY,
+    SIMD,
+    MULTI_VALUE,
+    SIGN_EXTENSION,
+    NONTRAPPING_FLOAT_TO_INT,
+    REFERENCE_TYPES,
+    THREADS,
+];
+
+/// The static name of the feature `name`, e.g. of a deserialized [`Error::UnsupportedFeature`].
+#[cfg(feature = "serde")]
+pub(crate) fn feature_name(name: &str) -> Option<&'static str> {
+    FEATURES.iter().copied().find(|feature| *feature == name)
+}
+
+impl Module {
+    /// The features beyond WebAssembly 1.0 used by the binary `input`, in the order they are
+    /// found, which the engine does not support: `"multiple memories"`, `"bulk-memory"`,
+    /// `"simd"`, `"multi-value"`, `"sign-extension"`, `"nontrapping-float-to-int"`,
+    /// `"reference-types"` and `"threads"`.
+    ///
+    /// The parsing and validation run it when they fail, to report the first feature found as
+    /// [`Error::UnsupportedFeature`]. The scan stops in a section at the first construct it
+    /// cannot skip, therefore the features after it are not found, and nothing is found in an
+    /// input which is not a binary module of version 1.
+    pub fn detect_features(input: &[u8]) -> Vec<&'static str> {
+        let mut found = Vec::new();
+        if input.starts_with(b"\0asm\x01\0\0\0") {
+            // The features found before a malformed section are still reported.
+            scan(&input[8..], &mut found).ok();
+        }
+        found
+    }
+}
+
+fn add(found: &mut Vec<&'static str>, feature: &'static str) {
+    if !found.contains(&feature) {
+        found.push(feature);
+    }
+}
+
+fn scan(input: &[u8], found: &mut Vec<&'static str>) -> Result<(), Error> {
+    let mut reader = Reader::new(input);
+    let mut memories = 0;
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader::new(reader.bytes(size)?);
+        // A section which cannot be scanned to its end does not stop the scan of the others.
+        let scanned = match id {
+            TYPE_SECTION => scan_types(&mut section, found),
+            IMPORT_SECTION => scan_imports(&mut section, found).map(|count| memories += count),
+            MEMORY_SECTION => scan_memories(&mut section, found).map(|count| memories += count),
+            GLOBAL_SECTION => scan_globals(&mut section, found),
+            ELEMENT_SECTION => scan_elements(&mut section, found),
+            DATA_COUNT_SECTION => {
+                add(found, BULK_MEMORY);
+                Ok(())
+            }
+            CODE_SECTION => scan_code(&mut section, found),
+            DATA_SECTION => scan_data(&mut section, found),
+            _ => Ok(()),
+        };
+        scanned.ok();
+        if memories > 1 {
+            add(found, MULTIPLE_MEMORIES);
+        }
+    }
+    Ok(())
+}
+
+fn value_type(raw: u8, found: &mut Vec<&'static str>) {
+    match raw {
+        0x7b => add(found, SIMD),
+        0x70 | 0x6f => add(found, REFERENCE_TYPES),
+        _ => {}
+    }
+}
+
+fn scan_types(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<(), Error> {
+    for _ in 0..section.u32()? {
+        section.u8()?;
+        for results in &[false, true] {
+            let count = section.u32()?;
+            if *results && count > 1 {
+                add(found, MULTI_VALUE);
+            }
+            for raw in section.bytes(count as usize)? {
+                value_type(*raw, found);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Scan the limits of a table or a memory.
+fn scan_limits(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<(), Error> {
+    let flags = section.u8()?;
+    if flags & 0x02 != 0 {
+        add(found, THREADS);
+    }
+    section.u32()?;
+    if flags & 0x01 != 0 {
+        section.u32()?;
+    }
+    Ok(())
+}
+
+/// Scan the imports, returning the number of imported memories.
+fn scan_imports(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<u32, Error> {
+    let mut memories = 0;
+    for _ in 0..section.u32()? {
+        section.name()?;
+        section.name()?;
+        match section.u8()? {
+            0x00 => {
+                section.u32()?;
+            }
+            0x01 => {
+                value_type(section.u8()?, found);
+                scan_limits(section, found)?;
+            }
+            0x02 => {
+                scan_limits(section, found)?;
+                memories += 1;
+            }
+            0x03 => {
+                value_type(section.u8()?, found);
+                section.u8()?;
+            }
+            // An import of a later proposal, e.g. of an exception tag, which cannot be skipped.
+            _ => break,
+        }
+    }
+    Ok(memories)
+}
+
+/// Scan the defined memories, returning their number.
+fn scan_memories(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<u32, Error> {
+    let count = section.u32()?;
+    for _ in 0..count {
+        scan_limits(section, found)?;
+    }
+    Ok(count)
+}
+
+fn scan_globals(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<(), Error> {
+    for _ in 0..section.u32()? {
+        value_type(section.u8()?, found);
+        section.u8()?;
+        if !scan_instructions(section, found, true)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn scan_elements(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<(), Error> {
+    for _ in 0..section.u32()? {
+        // The other encodings of the segments are introduced by the bulk memory operations.
+        if section.u32()? != 0 {
+            add(found, BULK_MEMORY);
+            break;
+        }
+        if !scan_instructions(section, found, true)? {
+            break;
+        }
+        for _ in 0..section.u32()? {
+            section.u32()?;
+        }
+    }
+    Ok(())
+}
+
+fn scan_data(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<(), Error> {
+    for _ in 0..section.u32()? {
+        match section.u32()? {
+            0 => {}
+            // An active segment of an explicit memory.
+            2 if section.u32()? != 0 => {
+                add(found, MULTIPLE_MEMORIES);
+                break;
+            }
+            // A passive segment, or an active one of the memory 0 in the encoding of the bulk
+            // memory operations.
+            _ => {
+                add(found, BULK_MEMORY);
+                break;
+            }
+        }
+        if !scan_instructions(section, found, true)? {
+            break;
+        }
+        section.name()?;
+    }
+    Ok(())
+}
+
+fn scan_code(section: &mut Reader, found: &mut Vec<&'static str>) -> Result<(), Error> {
+    for _ in 0..section.u32()? {
+        let size = section.u32()? as usize;
+        let mut body = Reader::new(section.bytes(size)?);
+        for _ in 0..body.u32()? {
+            body.u32()?;
+            value_type(body.u8()?, found);
+        }
+        scan_instructions(&mut body, found, false)?;
+    }
+    Ok(())
+}
+
+/// Scan the instructions of a function body, or of a constant expression up to its `end` if
+/// `const_expr`. Returns false if stopped at an instruction which cannot be skipped.
+fn scan_instructions(
+    reader: &mut Reader,
+    found: &mut Vec<&'static str>,
+    const_expr: bool,
+) -> Result<bool, Error> {
+    while !reader.is_empty() {
+        let code = reader.u8()?;
+        if let Some(opcode) = opcodes::by_code(code) {
+            if const_expr && code == 0x0b {
+                return Ok(true);
+            }
+            if opcode.immediates != Immediates::BlockType {
+                reader.immediates(opcode.immediates)?;
+                continue;
+            }
+            match reader.u8()? {
+                0x40 | 0x7c..=0x7f => {}
+                0x7b => add(found, SIMD),
+                0x70 | 0x6f => add(found, REFERENCE_TYPES),
+                // The index of a function type.
+                _ => {
+                    add(found, MULTI_VALUE);
+                    return Ok(false);
+                }
+            }
+            continue;
+        }
+        match code {
+            0xc0..=0xc4 => {
+                add(found, SIGN_EXTENSION);
+                continue;
+            }
+            0xfc => match reader.u32()? {
+                0..=7 => {
+                    add(found, NONTRAPPING_FLOAT_TO_INT);
+                    continue;
+                }
+                8..=14 => add(found, BULK_MEMORY),
+                _ => add(found, REFERENCE_TYPES),
+            },
+            0xfd => add(found, SIMD),
+            0xfe => add(found, THREADS),
+            // select with types, table.get, table.set, ref.null, ref.is_null and ref.func
+            0x1c | 0x25 | 0x26 | 0xd0..=0xd2 => add(found, REFERENCE_TYPES),
+            _ => {}
+        }
+        return Ok(false);
+    }
+    Ok(!const_expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, validate};
+
+    /* wat2wasm --enable-multi-memory
+    (module (memory 1) (memory 1))
+    */
+    const MULTIPLE_MEMORIES_WASM: &str = "0061736d0100000005050200010001";
+
+    /* wat2wasm
+    (module
+      (memory 1)
+      (func (export "clear") (memory.fill (i32.const 0) (i32.const 0) (i32.const 16)))
+    )
+    */
+    const BULK_MEMORY_WASM: &str = "0061736d0100000001040160000003020100050301000107090105636c65617200000a0d010b00410041004110fc0b000b";
+
+    /* wat2wasm
+    (module
+      (func (export "splat") (param i32) (result i32)
+        (i32x4.extract_lane 3 (i32x4.splat (local.get 0))))
+    )
+    */
+    const SIMD_WASM: &str =
+        "0061736d0100000001060160017f017f030201000709010573706c617400000a0b0109002000fd11fd1b030b";
+
+    #[test]
+    fn unsupported_features() {
+        for (input, feature) in &[
+            (MULTIPLE_MEMORIES_WASM, "multiple memories"),
+            (BULK_MEMORY_WASM, "bulk-memory"),
+            (SIMD_WASM, "simd"),
+        ] {
+            let input = hex::decode(input).unwrap();
+            let unsupported = Err(Error::UnsupportedFeature { feature: *feature });
+            assert_eq!(parse(&input).map(|_| ()), unsupported);
+            assert_eq!(validate(&input), unsupported);
+            assert_eq!(Module::detect_features(&input), [*feature]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn feature_name() {
+        assert_eq!(super::feature_name("sign-extension"), Some(SIGN_EXTENSION));
+        assert_eq!(super::feature_name("gc"), None);
+    }
+
+    #[test]
+    fn detect_features() {
+        /* wat2wasm
+        (module
+          (func (param i32) (result i32 i32)
+            (local.get 0)
+            (i32.extend8_s (local.get 0)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001070160017f027f7f030201000a0901070020002000c00b")
+            .unwrap();
+        assert_eq!(
+            Module::detect_features(&input),
+            ["multi-value", "sign-extension"]
+        );
+        /* wat2wasm
+        (module
+          (memory 1)
+          (func (export "clear") (i32.store (i32.const 0) (i32.const 0)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001040160000003020100050301000107090105636c65617200000a0b010900410041003602000b").unwrap();
+        // A module of WebAssembly 1.0 and the malformed inputs use no features.
+        assert!(Module::detect_features(&input).is_empty());
+        assert!(Module::detect_features(&[]).is_empty());
+        assert!(Module::detect_features(b"\0asm\x02\0\0\0\x0c\x01\x00").is_empty());
+    }
+}
@@ -0,0 +1,670 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Thin wrappers of the structures and the instantiation functions of the C API.
+//!
+//! These manage the ownership and the lifetimes of what is passed to and returned from the C API,
+//! without the conversions and the host function closures of [`Module`] and [`crate::Instance`],
+//! for building a different abstraction, e.g. with another calling convention of host functions.
+//!
+//! The lifetime `'a` of the wrappers is that of everything the instance refers to: the host
+//! function contexts, the globals, and the instances whose exports are imported. The C API
+//! validates the imports against the module, e.g. their count and types, during instantiation.
+
+use crate::raw::{GlobalTypeExt, LimitsExt};
+use crate::{sys, Error, ExecutionResult, FizzyErrorBox, GlobalType, Limits, Module, Value};
+
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::ptr::NonNull;
+
+#[cfg(feature = "raw-api")]
+pub use crate::sys::{
+    FizzyExecutionContext, FizzyExecutionResult, FizzyExternalFn, FizzyInstance, FizzyValue,
+    FizzyValueType, FizzyValueTypeF32, FizzyValueTypeF64, FizzyValueTypeI32, FizzyValueTypeI64,
+    FizzyValueTypeVoid,
+};
+#[cfg(not(feature = "raw-api"))]
+use crate::sys::{
+    FizzyExecutionContext, FizzyExecutionResult, FizzyInstance, FizzyValue, FizzyValueType,
+};
+
+/// The default hard limit of memory growth, in pages.
+pub const DEFAULT_MEMORY_PAGES_LIMIT: u32 = sys::FizzyMemoryPagesLimitDefault;
+
+/// The default limit of the call depth of an execution, which cannot be raised.
+pub const DEFAULT_CALL_DEPTH_LIMIT: u32 = sys::FizzyCallStackLimit;
+
+/// A function provided to an instance, wrapping `FizzyExternalFunction`.
+///
+/// The output type is referenced by the instance from within this struct, therefore
+/// [`RawInstance::instantiate`] borrows the slice of functions for the lifetime of the instance.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct ExternalFunction<'a> {
+    raw: sys::FizzyExternalFunction,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> ExternalFunction<'a> {
+    /// Create a function of the type of `inputs` and `output`, where `output` is
+    /// [`FizzyValueTypeVoid`] for no output, calling `function` with `context`.
+    ///
+    /// # Safety
+    /// `context` must be valid for the calls of `function` during the lifetime `'a`, i.e. until
+    /// all instances using this function are freed. `function` must return a value of the type of
+    /// `output`, if any, and must not unwind.
+    pub unsafe fn new(
+        inputs: &'a [FizzyValueType],
+        output: FizzyValueType,
+        function: unsafe extern "C" fn(
+            *mut c_void,
+            *mut FizzyInstance,
+            *const FizzyValue,
+            *mut FizzyExecutionContext,
+        ) -> FizzyExecutionResult,
+        context: *mut c_void,
+    ) -> Self {
+        ExternalFunction {
+            raw: sys::FizzyExternalFunction {
+                type_: sys::FizzyFunctionType {
+                    output,
+                    inputs: inputs.as_ptr(),
+                    inputs_size: inputs.len(),
+                },
+                function: Some(function),
+                context,
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// The raw struct.
+    pub fn as_raw(&self) -> &sys::FizzyExternalFunction {
+        &self.raw
+    }
+}
+
+/// A function exported by an instance, found by [`RawInstance::find_exported_function`].
+///
+/// The context of the function is allocated by the C API and freed when this is dropped, therefore
+/// the instances importing the function must be freed first.
+pub struct ExportedFunction<'a> {
+    function: ExternalFunction<'a>,
+}
+
+impl<'a> ExportedFunction<'a> {
+    /// The function to provide to other instances, which must not outlive this.
+    pub fn as_external(&self) -> ExternalFunction<'_> {
+        self.function
+    }
+}
+
+impl Drop for ExportedFunction<'_> {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_exported_function(&mut self.function.raw) }
+    }
+}
+
+/// A function provided by name to [`RawInstance::resolve_instantiate`], wrapping
+/// `FizzyImportedFunction`.
+///
+/// The names and the type are copied during instantiation, only the context of the function must
+/// outlive the instance.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct ImportedFunction<'a> {
+    raw: sys::FizzyImportedFunction,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> ImportedFunction<'a> {
+    /// Name `function` as `module`.`name`.
+    pub fn new(module: &'a CStr, name: &'a CStr, function: ExternalFunction<'a>) -> Self {
+        ImportedFunction {
+            raw: sys::FizzyImportedFunction {
+                module: module.as_ptr(),
+                name: name.as_ptr(),
+                external_function: function.raw,
+            },
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A global provided to an instance, wrapping `FizzyExternalGlobal`.
+///
+/// The instance reads and writes the value in place.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct ExternalGlobal<'a> {
+    raw: sys::FizzyExternalGlobal,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> ExternalGlobal<'a> {
+    /// Create a global of `value` of the given type, which can be modified by the instance if
+    /// `mutable`.
+    pub fn new(value: &'a Cell<Value>, value_type: FizzyValueType, mutable: bool) -> Self {
+        ExternalGlobal {
+            raw: sys::FizzyExternalGlobal {
+                value: value.as_ptr(),
+                type_: sys::FizzyGlobalType {
+                    value_type,
+                    is_mutable: mutable,
+                },
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// The current value.
+    pub fn value(&self) -> Value {
+        unsafe { *self.raw.value }
+    }
+
+    /// The type of the global.
+    pub fn global_type(&self) -> GlobalType {
+        GlobalType::from_raw(&self.raw.type_)
+    }
+
+    /// The raw struct.
+    pub fn as_raw(&self) -> &sys::FizzyExternalGlobal {
+        &self.raw
+    }
+
+    pub(crate) fn from_raw(raw: sys::FizzyExternalGlobal) -> Self {
+        ExternalGlobal {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A global provided by name to [`RawInstance::resolve_instantiate`], wrapping
+/// `FizzyImportedGlobal`.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct ImportedGlobal<'a> {
+    raw: sys::FizzyImportedGlobal,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> ImportedGlobal<'a> {
+    /// Name `global` as `module`.`name`.
+    pub fn new(module: &'a CStr, name: &'a CStr, global: ExternalGlobal<'a>) -> Self {
+        ImportedGlobal {
+            raw: sys::FizzyImportedGlobal {
+                module: module.as_ptr(),
+                name: name.as_ptr(),
+                external_global: global.raw,
+            },
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A memory exported by an instance, wrapping `FizzyExternalMemory`.
+///
+/// The memory is owned by the exporting instance, which must outlive the instances importing it.
+#[derive(Clone, Copy)]
+pub struct ExternalMemory<'a> {
+    raw: sys::FizzyExternalMemory,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl ExternalMemory<'_> {
+    /// The limits of the memory, in pages.
+    pub fn limits(&self) -> Limits {
+        Limits::from_raw(&self.raw.limits)
+    }
+
+    /// The raw struct.
+    pub fn as_raw(&self) -> &sys::FizzyExternalMemory {
+        &self.raw
+    }
+
+    pub(crate) fn from_raw(raw: sys::FizzyExternalMemory) -> Self {
+        ExternalMemory {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A table exported by an instance, wrapping `FizzyExternalTable`.
+///
+/// The table is owned by the exporting instance, which must outlive the instances importing it.
+#[derive(Clone, Copy)]
+pub struct ExternalTable<'a> {
+    raw: sys::FizzyExternalTable,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl ExternalTable<'_> {
+    /// The limits of the table.
+    pub fn limits(&self) -> Limits {
+        Limits::from_raw(&self.raw.limits)
+    }
+
+    /// The raw struct.
+    pub fn as_raw(&self) -> &sys::FizzyExternalTable {
+        &self.raw
+    }
+
+    pub(crate) fn from_raw(raw: sys::FizzyExternalTable) -> Self {
+        ExternalTable {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// An instance created by the C API, which is freed when dropped.
+///
+/// The instance is not `Send` nor `Sync`, as it may use imports which are not, therefore it is
+/// executed by one thread at a time through a shared reference.
+pub struct RawInstance<'a> {
+    instance: NonNull<sys::FizzyInstance>,
+    /// The module of an instance created by [`RawInstance::instantiate`], freed after the instance.
+    #[allow(dead_code)]
+    module: Option<Module>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> RawInstance<'a> {
+    /// Instantiate `module` with imports in the order in which the module imports them, by
+    /// `fizzy_instantiate_shared`.
+    ///
+    /// `memory_pages_limit` is the hard limit of memory growth, see [`DEFAULT_MEMORY_PAGES_LIMIT`].
+    pub fn instantiate(
+        module: &Module,
+        functions: &'a [ExternalFunction<'a>],
+        table: Option<ExternalTable<'a>>,
+        memory: Option<ExternalMemory<'a>>,
+        globals: &[ExternalGlobal<'a>],
+        memory_pages_limit: u32,
+    ) -> Result<Self, Error> {
+        let mut err = FizzyErrorBox::new();
+        let ptr = unsafe {
+            sys::fizzy_instantiate_shared(
+                module.as_ptr(),
+                functions.as_ptr() as *const sys::FizzyExternalFunction,
+                functions.len(),
+                table.as_ref().map_or(std::ptr::null(), |table| &table.raw),
+                memory
+                    .as_ref()
+                    .map_or(std::ptr::null(), |memory| &memory.raw),
+                globals.as_ptr() as *const sys::FizzyExternalGlobal,
+                globals.len(),
+                memory_pages_limit,
+                err.as_mut_ptr(),
+            )
+        };
+        RawInstance::from_ptr(ptr, Some(module.clone()), err)
+    }
+
+    /// Instantiate `module` with imports matched by name, in any order, by
+    /// `fizzy_resolve_instantiate`.
+    ///
+    /// The instance owns a copy of the parsed module, made by `fizzy_clone_module`, as the C API
+    /// takes the ownership of the module.
+    pub fn resolve_instantiate(
+        module: &Module,
+        functions: &[ImportedFunction<'a>],
+        table: Option<ExternalTable<'a>>,
+        memory: Option<ExternalMemory<'a>>,
+        globals: &[ImportedGlobal<'a>],
+        memory_pages_limit: u32,
+    ) -> Result<Self, Error> {
+        let copy = unsafe { sys::fizzy_clone_module(module.as_ptr()) };
+        if copy.is_null() {
+            return Err(Error::MemoryAllocationFailed(
+                "module copy failed".to_string(),
+            ));
+        }
+        let mut err = FizzyErrorBox::new();
+        // The copy is freed by the C API in case of failure, too.
+        let ptr = unsafe {
+            sys::fizzy_resolve_instantiate(
+                copy,
+                functions.as_ptr() as *const sys::FizzyImportedFunction,
+                functions.len(),
+                table.as_ref().map_or(std::ptr::null(), |table| &table.raw),
+                memory
+                    .as_ref()
+                    .map_or(std::ptr::null(), |memory| &memory.raw),
+                globals.as_ptr() as *const sys::FizzyImportedGlobal,
+                globals.len(),
+                memory_pages_limit,
+                err.as_mut_ptr(),
+            )
+        };
+        RawInstance::from_ptr(ptr, None, err)
+    }
+
+    fn from_ptr(
+        ptr: *mut sys::FizzyInstance,
+        module: Option<Module>,
+        err: FizzyErrorBox,
+    ) -> Result<Self, Error> {
+        match NonNull::new(ptr) {
+            Some(instance) => {
+                debug_assert!(err.code() == 0);
+                Ok(RawInstance {
+                    instance,
+                    module,
+                    _marker: PhantomData,
+                })
+            }
+            None => {
+                debug_assert!(err.code() != 0);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// The pointer to the instance, for the functions of the C API.
+    pub fn as_ptr(&self) -> *mut sys::FizzyInstance {
+        self.instance.as_ptr()
+    }
+
+    /// Execute the function of `func_idx` with `args` by `fizzy_execute`.
+    ///
+    /// # Safety
+    /// `func_idx` must be a valid index of a function, and `args` must be values of its inputs.
+    pub unsafe fn execute(&self, func_idx: u32, args: &[Value]) -> ExecutionResult {
+        ExecutionResult(sys::fizzy_execute(
+            self.instance.as_ptr(),
+            func_idx,
+            args.as_ptr(),
+        ))
+    }
+
+    /// Find the exported function `name`, to provide to other instances.
+    pub fn find_exported_function(&self, name: &str) -> Option<ExportedFunction<'_>> {
+        let mut raw = std::mem::MaybeUninit::<sys::FizzyExternalFunction>::uninit();
+        let found = crate::find_by_c_str(name, |name| unsafe {
+            sys::fizzy_find_exported_function(self.as_ptr(), name.as_ptr(), raw.as_mut_ptr())
+        });
+        if found {
+            Some(ExportedFunction {
+                function: ExternalFunction {
+                    raw: unsafe { raw.assume_init() },
+                    _marker: PhantomData,
+                },
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Find the exported table `name`, to provide to other instances.
+    pub fn find_exported_table(&self, name: &str) -> Option<ExternalTable<'_>> {
+        let mut raw = std::mem::MaybeUninit::<sys::FizzyExternalTable>::uninit();
+        let found = crate::find_by_c_str(name, |name| unsafe {
+            sys::fizzy_find_exported_table(self.as_ptr(), name.as_ptr(), raw.as_mut_ptr())
+        });
+        if found {
+            Some(ExternalTable {
+                raw: unsafe { raw.assume_init() },
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Find the exported memory `name`, to provide to other instances.
+    pub fn find_exported_memory(&self, name: &str) -> Option<ExternalMemory<'_>> {
+        let mut raw = std::mem::MaybeUninit::<sys::FizzyExternalMemory>::uninit();
+        let found = crate::find_by_c_str(name, |name| unsafe {
+            sys::fizzy_find_exported_memory(self.as_ptr(), name.as_ptr(), raw.as_mut_ptr())
+        });
+        if found {
+            Some(ExternalMemory {
+                raw: unsafe { raw.assume_init() },
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Find the exported global `name`, to provide to other instances.
+    pub fn find_exported_global(&self, name: &str) -> Option<ExternalGlobal<'_>> {
+        let mut raw = std::mem::MaybeUninit::<sys::FizzyExternalGlobal>::uninit();
+        let found = crate::find_by_c_str(name, |name| unsafe {
+            sys::fizzy_find_exported_global(self.as_ptr(), name.as_ptr(), raw.as_mut_ptr())
+        });
+        if found {
+            Some(ExternalGlobal {
+                raw: unsafe { raw.assume_init() },
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for RawInstance<'_> {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_instance(self.instance.as_ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::sys::FizzyValueTypeI32;
+
+    use std::ffi::CString;
+
+    const I32: &[FizzyValueType] = &[FizzyValueTypeI32];
+
+    /// Add the input to the counter in the context, returning the new value.
+    unsafe extern "C" fn add(
+        context: *mut c_void,
+        _instance: *mut FizzyInstance,
+        args: *const FizzyValue,
+        _ctx: *mut FizzyExecutionContext,
+    ) -> FizzyExecutionResult {
+        let counter = &*(context as *const Cell<u32>);
+        counter.set(counter.get() + (*args).i32);
+        FizzyExecutionResult {
+            trapped: false,
+            has_value: true,
+            value: FizzyValue { i32: counter.get() },
+        }
+    }
+
+    fn importing_module() -> Module {
+        /* wat2wasm
+        (module
+          (func $add (import "env" "add") (param i32) (result i32))
+          (global $base (import "env" "base") (mut i32))
+          (func (export "run") (param i32) (result i32)
+            (global.set $base (i32.add (global.get $base) (i32.const 1)))
+            (call $add (i32.add (local.get 0) (global.get $base)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f02170203656e7603616464000003656e760462617365037f01030201000707010372756e00010a12011000230041016a2400200023006a10000b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    #[test]
+    fn instantiate_host_function() {
+        let module = importing_module();
+        let counter = Cell::new(100u32);
+        let functions = [unsafe {
+            ExternalFunction::new(
+                I32,
+                FizzyValueTypeI32,
+                add,
+                &counter as *const Cell<u32> as *mut c_void,
+            )
+        }];
+        let base = Cell::new(Value::from(10u32));
+        let globals = [ExternalGlobal::new(&base, FizzyValueTypeI32, true)];
+        let instance = RawInstance::instantiate(
+            &module,
+            &functions,
+            None,
+            None,
+            &globals,
+            DEFAULT_MEMORY_PAGES_LIMIT,
+        )
+        .unwrap();
+
+        let result = unsafe { instance.execute(1, &[Value::from(5u32)]) };
+        assert!(!result.trapped());
+        assert_eq!(result.value().unwrap().as_u32(), 116);
+        let result = unsafe { instance.execute(1, &[Value::from(1u32)]) };
+        assert_eq!(result.value().unwrap().as_u32(), 129);
+        assert_eq!(counter.get(), 129);
+        // The global is modified in place.
+        assert_eq!(base.get().as_u32(), 12);
+
+        // The imports are checked by the C API.
+        assert_eq!(
+            RawInstance::instantiate(
+                &module,
+                &functions,
+                None,
+                None,
+                &[],
+                DEFAULT_MEMORY_PAGES_LIMIT
+            )
+            .err(),
+            Some(Error::InstantiationFailed(
+                "module requires 1 imported globals, 0 provided".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn resolve_instantiate() {
+        let module = importing_module();
+        let counter = Cell::new(0u32);
+        let env = CString::new("env").unwrap();
+        let add_name = CString::new("add").unwrap();
+        let base_name = CString::new("base").unwrap();
+        let unused_name = CString::new("unused").unwrap();
+        let function = unsafe {
+            ExternalFunction::new(
+                I32,
+                FizzyValueTypeI32,
+                add,
+                &counter as *const Cell<u32> as *mut c_void,
+            )
+        };
+        let base = Cell::new(Value::from(0u32));
+        let global = ExternalGlobal::new(&base, FizzyValueTypeI32, true);
+        let instance = RawInstance::resolve_instantiate(
+            &module,
+            &[
+                ImportedFunction::new(&env, &unused_name, function),
+                ImportedFunction::new(&env, &add_name, function),
+            ],
+            None,
+            None,
+            &[ImportedGlobal::new(&env, &base_name, global)],
+            DEFAULT_MEMORY_PAGES_LIMIT,
+        )
+        .unwrap();
+        drop(module);
+
+        let result = unsafe { instance.execute(1, &[Value::from(2u32)]) };
+        assert_eq!(result.value().unwrap().as_u32(), 3);
+        assert_eq!(global.value().as_u32(), 1);
+        assert_eq!(
+            global.global_type(),
+            GlobalType::new(crate::ValueType::I32, true)
+        );
+
+        assert_eq!(
+            RawInstance::resolve_instantiate(
+                &importing_module(),
+                &[],
+                None,
+                None,
+                &[ImportedGlobal::new(&env, &base_name, global)],
+                DEFAULT_MEMORY_PAGES_LIMIT,
+            )
+            .err(),
+            Some(Error::InstantiationFailed(
+                "imported function env.add is required".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn link_instances() {
+        /* wat2wasm
+        (module
+          (memory (export "memory") 1 2)
+          (table (export "table") 1 funcref)
+          (global (export "scale") i32 (i32.const 3))
+          (func $get (export "get") (param i32) (result i32) (i32.load (local.get 0)))
+          (elem (i32.const 0) $get)
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f030201000404017000010504010101020606017f0041030b072004066d656d6f72790200057461626c650100057363616c6503000367657400000907010041000b01000a0901070020002802000b").unwrap();
+        let provider = RawInstance::instantiate(
+            &parse(&input).unwrap(),
+            &[],
+            None,
+            None,
+            &[],
+            DEFAULT_MEMORY_PAGES_LIMIT,
+        )
+        .unwrap();
+
+        /* wat2wasm
+        (module
+          (func $get (import "provider" "get") (param i32) (result i32))
+          (table (import "provider" "table") 1 funcref)
+          (memory (import "provider" "memory") 1)
+          (global $scale (import "provider" "scale") i32)
+          (type $get_type (func (param i32) (result i32)))
+          (func (export "run") (param i32) (result i32)
+            (i32.store (i32.const 8) (i32.mul (local.get 0) (global.get $scale)))
+            (i32.add
+              (call $get (i32.const 8))
+              (call_indirect (type $get_type) (i32.const 8) (i32.const 0)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f0248040870726f76696465720367657400000870726f7669646572057461626c65017000010870726f7669646572066d656d6f72790200010870726f7669646572057363616c65037f00030201000707010372756e00010a1a0118004108200023006c36020041081000410841001100006a0b").unwrap();
+        let get = provider.find_exported_function("get").unwrap();
+        let functions = [get.as_external()];
+        let table = provider.find_exported_table("table").unwrap();
+        assert_eq!(table.limits(), Limits::new(1, None));
+        let memory = provider.find_exported_memory("memory").unwrap();
+        assert_eq!(memory.limits(), Limits::new(1, Some(2)));
+        let scale = provider.find_exported_global("scale").unwrap();
+        assert_eq!(scale.value().as_u32(), 3);
+        assert!(provider.find_exported_function("none").is_none());
+
+        let instance = RawInstance::instantiate(
+            &parse(&input).unwrap(),
+            &functions,
+            Some(table),
+            Some(memory),
+            &[scale],
+            DEFAULT_MEMORY_PAGES_LIMIT,
+        )
+        .unwrap();
+        let result = unsafe { instance.execute(1, &[Value::from(7u32)]) };
+        assert_eq!(result.value().unwrap().as_u32(), 42);
+        // The memory is shared.
+        let result = unsafe { provider.execute(0, &[Value::from(8u32)]) };
+        assert_eq!(result.value().unwrap().as_u32(), 21);
+    }
+}
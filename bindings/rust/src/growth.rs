@@ -0,0 +1,213 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Notifying the growth of the memory of an instance, and tracking its peak size.
+
+use crate::{sys, Instance};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+const PAGE_SIZE: usize = 65536;
+
+/// The callback notified of the growth of the memory, and the peak number of pages, shared by an
+/// instance with its host functions.
+#[derive(Default)]
+pub(crate) struct MemoryGrowth {
+    watch: Mutex<Option<Watch>>,
+    /// The largest number of pages observed.
+    peak_pages: AtomicU32,
+}
+
+struct Watch {
+    /// The number of pages when last checked.
+    pages: u32,
+    callback: Box<dyn FnMut(u32, u32) + Send>,
+}
+
+impl MemoryGrowth {
+    /// Call the callback if the memory of `instance` has grown since the last check.
+    pub(crate) fn check(&self, instance: *mut sys::FizzyInstance) {
+        let mut watch = self.watch.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(watch) = watch.as_mut() {
+            let pages = unsafe { pages(instance) };
+            self.observe(pages);
+            let old_pages = std::mem::replace(&mut watch.pages, pages);
+            if pages > old_pages {
+                (watch.callback)(old_pages, pages);
+            }
+        }
+    }
+
+    /// Record `pages` as the size of the memory, known without asking the engine.
+    pub(crate) fn observe(&self, pages: u32) {
+        self.peak_pages.fetch_max(pages, Ordering::Relaxed);
+    }
+}
+
+unsafe fn pages(instance: *mut sys::FizzyInstance) -> u32 {
+    (sys::fizzy_get_instance_memory_size(instance) / PAGE_SIZE) as u32
+}
+
+impl Instance {
+    /// Call `callback` with the old and the new number of pages after the memory has grown,
+    /// e.g. to invalidate the pointers to the memory cached by the host, replacing any previous
+    /// callback. The growth cannot be refused, which is what the memory pages limit is for.
+    ///
+    /// The growth is noticed after each execution by the instance, and before and after each call
+    /// of a host function, therefore a single notification may cover multiple `memory.grow`
+    /// instructions. The growth by another instance sharing the memory, see
+    /// [`InstantiateOptions::with_memory_from`](crate::InstantiateOptions::with_memory_from), is
+    /// noticed by the next execution.
+    pub fn on_memory_grow(&mut self, callback: impl FnMut(u32, u32) + Send + 'static) {
+        let pages = (self.memory_size() / PAGE_SIZE) as u32;
+        self.memory_growth.observe(pages);
+        *self
+            .memory_growth
+            .watch
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Watch {
+            pages,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// The largest size of the memory in pages since instantiation, e.g. for billing, or 0 if the
+    /// instance has no memory.
+    ///
+    /// The size is recorded where it is known without asking the engine: on instantiation, on the
+    /// growth by [`Caller::grow_memory`](crate::Caller::grow_memory) and when restoring a state,
+    /// and after each execution and call of a host function while a callback of
+    /// [`Instance::on_memory_grow`] is set. The size when queried is included, which covers the
+    /// executions in between, as the engine never shrinks memories.
+    pub fn peak_memory_pages(&self) -> u32 {
+        let pages = (self.memory_size() / PAGE_SIZE) as u32;
+        self.memory_growth.observe(pages);
+        self.memory_growth.peak_pages.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, Caller, ImportsBuilder, Instance, Trap, TypedValue};
+    use std::sync::{Arc, Mutex};
+
+    /// The old and the new number of pages of each notified growth.
+    type Growths = Arc<Mutex<Vec<(u32, u32)>>>;
+
+    fn instantiate() -> (Instance, Growths) {
+        /* wat2wasm
+        (module
+          (func $host (import "env" "host"))
+          (memory 1 8)
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+          (func (export "grow_twice") (param i32)
+            (drop (memory.grow (local.get 0)))
+            (call $host)
+            (drop (memory.grow (local.get 0))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010d0360000060017f017f60017f00020c0103656e7604686f7374000003030201020504010101080715020467726f7700010a67726f775f747769636500020a17020600200040000b0e00200040001a1000200040001a0b").unwrap();
+        let growths = Arc::new(Mutex::new(Vec::new()));
+        let mut imports = ImportsBuilder::new();
+        let recorded = growths.clone();
+        // The calls of the host function are recorded as growths from 0 to 0 pages.
+        imports.func("env", "host", move |_: &mut Caller| -> Result<(), Trap> {
+            recorded.lock().unwrap().push((0, 0));
+            Ok(())
+        });
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let recorded = growths.clone();
+        instance.on_memory_grow(move |old_pages, new_pages| {
+            recorded.lock().unwrap().push((old_pages, new_pages))
+        });
+        (instance, growths)
+    }
+
+    #[test]
+    fn grow() {
+        let (mut instance, growths) = instantiate();
+        let result = instance.execute("grow", &[TypedValue::U32(2)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+        assert_eq!(*growths.lock().unwrap(), [(1, 3)]);
+
+        // Neither growing by 0 pages nor failing to grow is notified.
+        instance.execute("grow", &[TypedValue::U32(0)]).unwrap();
+        let result = instance.execute("grow", &[TypedValue::U32(100)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(u32::MAX)));
+        assert_eq!(*growths.lock().unwrap(), [(1, 3)]);
+    }
+
+    #[test]
+    fn host_function() {
+        let (mut instance, growths) = instantiate();
+        // The growth before the call of the host function is notified before it is called.
+        instance
+            .execute("grow_twice", &[TypedValue::U32(1)])
+            .unwrap();
+        assert_eq!(*growths.lock().unwrap(), [(1, 2), (0, 0), (2, 3)]);
+    }
+
+    #[test]
+    fn host_grows_memory() {
+        /* wat2wasm
+        (module
+          (func $produce (import "env" "produce") (param i32) (result i32))
+          (memory 1 2)
+          (func (export "run") (param i32) (result i32) (i32.load (call $produce (local.get 0))))
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f020f0103656e760770726f647563650000030201000504010101020707010372756e00010a0b010900200010002802000b").unwrap();
+        let growths = Arc::new(Mutex::new(Vec::new()));
+        let mut imports = ImportsBuilder::new();
+        let recorded = growths.clone();
+        // The host function makes room for its result after the original memory, and returns its
+        // offset.
+        imports.func(
+            "env",
+            "produce",
+            move |caller: &mut Caller, pages: u32| -> Result<u32, Trap> {
+                let old_pages = caller
+                    .grow_memory(pages)
+                    .map_err(|err| Trap::new(err.to_string()))?;
+                // The growth is notified before the function returns.
+                assert_eq!(
+                    recorded.lock().unwrap().last(),
+                    Some(&(old_pages, old_pages + pages))
+                );
+                let offset = old_pages * 65536;
+                caller
+                    .memory_set(offset, &[1, 2, 3, 4])
+                    .map_err(|err| Trap::new(err.to_string()))?;
+                Ok(offset)
+            },
+        );
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let recorded = growths.clone();
+        instance.on_memory_grow(move |old_pages, new_pages| {
+            recorded.lock().unwrap().push((old_pages, new_pages))
+        });
+
+        let result = instance.execute("run", &[TypedValue::U32(1)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(0x0403_0201)));
+        assert_eq!(instance.memory_size(), 2 * 65536);
+        assert_eq!(*growths.lock().unwrap(), [(1, 2)]);
+
+        // The memory cannot grow beyond its maximum.
+        let result = instance.execute("run", &[TypedValue::U32(1)]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new("memory cannot grow by 1 pages"))
+        );
+        assert_eq!(instance.memory_size(), 2 * 65536);
+        assert_eq!(*growths.lock().unwrap(), [(1, 2)]);
+        assert_eq!(instance.peak_memory_pages(), 2);
+    }
+}
@@ -0,0 +1,108 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The self-test of the linked engine, e.g. at the startup of a service.
+
+use crate::{parse, Error, TypedValue};
+
+use std::time::{Duration, Instant};
+
+/* wat2wasm
+(module
+  (memory 1)
+  (func (export "check") (param i32 f64) (result i32)
+    (i32.store (i32.const 8) (i32.mul (local.get 0) (i32.const 3)))
+    (i32.add
+      (i32.load (i32.const 8))
+      (i32.trunc_f64_s (f64.mul (local.get 1) (f64.const 2.5)))))
+  (func (export "trap") (unreachable))
+)
+*/
+const SELF_CHECK_WASM: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0a, 0x02, 0x60, 0x02, 0x7f, 0x7c, 0x01,
+    0x7f, 0x60, 0x00, 0x00, 0x03, 0x03, 0x02, 0x00, 0x01, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x10,
+    0x02, 0x05, 0x63, 0x68, 0x65, 0x63, 0x6b, 0x00, 0x00, 0x04, 0x74, 0x72, 0x61, 0x70, 0x00, 0x01,
+    0x0a, 0x25, 0x02, 0x1f, 0x00, 0x41, 0x08, 0x20, 0x00, 0x41, 0x03, 0x6c, 0x36, 0x02, 0x00, 0x41,
+    0x08, 0x28, 0x02, 0x00, 0x20, 0x01, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x40, 0xa2,
+    0xaa, 0x6a, 0x0b, 0x03, 0x00, 0x00, 0x0b,
+];
+
+/// The outcome of a successful [`self_check`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SelfCheckReport {
+    /// The version of the crate, which builds the engine of the same version from its sources.
+    pub version: &'static str,
+    /// The time of parsing the module.
+    pub parse_time: Duration,
+    /// The time of instantiating the module.
+    pub instantiate_time: Duration,
+    /// The time of the executions, including the trap.
+    pub execute_time: Duration,
+}
+
+/// Parse, instantiate and execute a tiny built-in module with integer and floating-point
+/// arithmetic, memory accesses and a trap, to check at startup that the engine linked into the
+/// binary works, e.g. that the static initialization of the C++ runtime has not been broken by the
+/// static linking. It takes a few microseconds.
+///
+/// Fails with [`Error::Other`] describing the wrong result, or with the error of the engine.
+///
+/// ```
+/// let report = fizzy::self_check().expect("the engine is broken");
+/// println!("fizzy {} in {:?}", report.version, report.execute_time);
+/// ```
+pub fn self_check() -> Result<SelfCheckReport, Error> {
+    let start = Instant::now();
+    let module = parse(&SELF_CHECK_WASM)?;
+    let parsed = Instant::now();
+    let mut instance = module.instantiate()?;
+    let instantiated = Instant::now();
+
+    // 14 * 3 stored to the memory and loaded back, plus 4.0 * 2.5 truncated.
+    let result = instance.execute("check", &[TypedValue::U32(14), TypedValue::F64(4.0)])?;
+    if result.trapped() || result.value() != Some(TypedValue::U32(52)) {
+        return Err(Error::Other(format!(
+            "self check failed: the arithmetic has resulted in {}",
+            describe(result.trapped(), result.value())
+        )));
+    }
+    let result = instance.execute("trap", &[])?;
+    if !result.trapped() {
+        return Err(Error::Other(
+            "self check failed: unreachable has not trapped".to_string(),
+        ));
+    }
+    let executed = Instant::now();
+
+    Ok(SelfCheckReport {
+        version: env!("CARGO_PKG_VERSION"),
+        parse_time: parsed - start,
+        instantiate_time: instantiated - parsed,
+        execute_time: executed - instantiated,
+    })
+}
+
+fn describe(trapped: bool, value: Option<TypedValue>) -> String {
+    match (trapped, value) {
+        (true, _) => "a trap".to_string(),
+        (false, Some(value)) => value.to_string(),
+        (false, None) => "no value".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_check_report() {
+        let report = self_check().unwrap();
+        assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+        assert!(!report.version.is_empty());
+        // Generous bounds for slow and shared machines.
+        let total = report.parse_time + report.instantiate_time + report.execute_time;
+        assert!(total < Duration::from_secs(1));
+    }
+}
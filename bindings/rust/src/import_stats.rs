@@ -0,0 +1,212 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The statistics of the calls of the imported functions of an instance.
+
+use crate::Instance;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The calls of an imported function by an instance, see [`Instance::import_stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportStat {
+    /// The module of the import.
+    pub module: String,
+    /// The name of the import.
+    pub name: String,
+    /// The number of calls.
+    pub calls: u64,
+    /// The time spent in the function by the executions with
+    /// [`ExecutionOptions::time_imports`](crate::ExecutionOptions::time_imports).
+    pub total_time: Duration,
+    /// The ticks charged for the calls by metered executions, or `None` if the function has no
+    /// cost, see [`ImportsBuilder::func_with_cost`](crate::ImportsBuilder::func_with_cost).
+    pub total_ticks: Option<u64>,
+}
+
+/// The counters of the calls of a host function by an instance, updated by its trampoline.
+#[derive(Default)]
+pub(crate) struct ImportCounters {
+    calls: AtomicU64,
+    nanos: AtomicU64,
+    ticks: AtomicU64,
+}
+
+impl ImportCounters {
+    /// Count a call, which has taken `elapsed` if timed.
+    pub(crate) fn record_call(&self, elapsed: Option<Duration>) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if let Some(elapsed) = elapsed {
+            self.nanos
+                .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count the ticks charged for a call.
+    pub(crate) fn record_ticks(&self, ticks: u64) {
+        self.ticks.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reset(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.nanos.store(0, Ordering::Relaxed);
+        self.ticks.store(0, Ordering::Relaxed);
+    }
+
+    /// The statistics of the function `module.name`, which has a cost if `has_cost`.
+    pub(crate) fn stat(&self, module: &str, name: &str, has_cost: bool) -> ImportStat {
+        ImportStat {
+            module: module.to_string(),
+            name: name.to_string(),
+            calls: self.calls.load(Ordering::Relaxed),
+            total_time: Duration::from_nanos(self.nanos.load(Ordering::Relaxed)),
+            total_ticks: if has_cost {
+                Some(self.ticks.load(Ordering::Relaxed))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl Instance {
+    /// The statistics of the calls of each imported function since instantiation or the last
+    /// [`Instance::reset_import_stats`], in the order of the imports, e.g. to find the pressure
+    /// on the host boundary.
+    ///
+    /// The calls are always counted, the time only by the executions with
+    /// [`ExecutionOptions::time_imports`](crate::ExecutionOptions::time_imports). The calls
+    /// refused before calling the function, for running out of ticks or for a recursive call,
+    /// are not counted.
+    pub fn import_stats(&self) -> Vec<ImportStat> {
+        self.host_functions
+            .iter()
+            .map(|context| context.import_stat())
+            .collect()
+    }
+
+    /// Set the statistics of all imported functions to zero.
+    pub fn reset_import_stats(&mut self) {
+        for context in &self.host_functions {
+            context.counters.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse_metered, Caller, CostSchedule, ExecutionOptions, ImportsBuilder, Trap, TypedValue,
+    };
+
+    /* wat2wasm
+    (module
+      (func $add (import "env" "add") (param i32 i32) (result i32))
+      (func $log (import "env" "log") (param i32))
+      (func (export "run") (param i32) (result i32)
+        (local $sum i32)
+        (block $done
+          (loop $next
+            (br_if $done (i32.eqz (local.get 0)))
+            (local.set $sum (call $add (local.get $sum) (local.get 0)))
+            (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+            (br $next)))
+        (call $log (local.get $sum))
+        (local.get $sum))
+    )
+    */
+    const IMPORTS_WASM: &str = "0061736d0100000001100360027f7f017f60017f0060017f017f02150203656e7603616464000003656e76036c6f670001030201020707010372756e00020a28012601017f024003402000450d012001200010002101200041016b21000c000b0b2001100120010b";
+
+    /// Instantiate the fixture, where each call of `add` costs 5 ticks and sleeps for `delay`.
+    fn instantiate(delay: Duration) -> Instance {
+        let mut imports = ImportsBuilder::new();
+        imports.func_with_cost(
+            "env",
+            "add",
+            5,
+            move |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> {
+                std::thread::sleep(delay);
+                Ok(a + b)
+            },
+        );
+        imports.func("env", "log", |_: &mut Caller, _: u32| -> Result<(), Trap> {
+            Ok(())
+        });
+        parse_metered(&hex::decode(IMPORTS_WASM).unwrap())
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    fn counts(instance: &Instance) -> Vec<(String, String, u64, Option<u64>)> {
+        instance
+            .import_stats()
+            .into_iter()
+            .map(|stat| (stat.module, stat.name, stat.calls, stat.total_ticks))
+            .collect()
+    }
+
+    fn expected(
+        add_calls: u64,
+        add_ticks: u64,
+        log_calls: u64,
+    ) -> Vec<(String, String, u64, Option<u64>)> {
+        vec![
+            (
+                "env".to_string(),
+                "add".to_string(),
+                add_calls,
+                Some(add_ticks),
+            ),
+            ("env".to_string(), "log".to_string(), log_calls, None),
+        ]
+    }
+
+    #[test]
+    fn import_stats() {
+        let mut instance = instantiate(Duration::from_secs(0));
+        assert_eq!(counts(&instance), expected(0, 0, 0));
+
+        let result = instance.execute("run", &[TypedValue::U32(3)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(6)));
+        assert_eq!(counts(&instance), expected(3, 0, 1));
+
+        // The statistics accumulate across executions, with the ticks of the metered ones.
+        let options = ExecutionOptions::new().cost_schedule(&CostSchedule::uniform(1));
+        let outcome = instance
+            .execute_with_options("run", &[TypedValue::U32(4)], &options)
+            .unwrap();
+        assert_eq!(outcome.value(), Some(TypedValue::U32(10)));
+        assert_eq!(counts(&instance), expected(7, 4 * 5, 2));
+        // Without the timing, no time is spent.
+        assert!(instance
+            .import_stats()
+            .iter()
+            .all(|stat| stat.total_time == Duration::from_secs(0)));
+
+        instance.reset_import_stats();
+        assert_eq!(counts(&instance), expected(0, 0, 0));
+    }
+
+    #[test]
+    fn time_imports() {
+        let mut instance = instantiate(Duration::from_millis(1));
+        let options = ExecutionOptions::new().time_imports(true);
+        instance
+            .execute_with_options("run", &[TypedValue::U32(2)], &options)
+            .unwrap();
+        let stats = instance.import_stats();
+        assert_eq!(stats[0].calls, 2);
+        assert!(stats[0].total_time >= Duration::from_millis(2));
+
+        // The timing applies only to the executions with the option.
+        let total_time = stats[0].total_time;
+        instance.execute("run", &[TypedValue::U32(2)]).unwrap();
+        let stats = instance.import_stats();
+        assert_eq!(stats[0].calls, 4);
+        assert_eq!(stats[0].total_time, total_time);
+    }
+}
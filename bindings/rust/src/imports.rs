@@ -0,0 +1,2290 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host functions provided to modules as imports.
+
+use crate::growth::MemoryGrowth;
+use crate::import_stats::ImportCounters;
+use crate::metering::ActiveGas;
+use crate::raw::{FunctionTypeExt, TypedValueExt, ValueTypeExt};
+use crate::{
+    sys, Error, ExecutionResult, ExternalKind, FunctionType, ImportStat, Instance, TrapInfo,
+    TypedValue, Value, ValueType,
+};
+
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A trap raised by a host function. It terminates the execution of the calling module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trap {
+    message: String,
+    /// The exit code, if the trap is used to exit a WASI program.
+    exit_code: Option<u32>,
+    /// True if the trap is raised by the engine for exceeding the limit of the call depth.
+    call_depth_exceeded: bool,
+    /// The error of the host function, if the trap is created from one.
+    host_error: Option<SharedHostError>,
+}
+
+impl Trap {
+    /// Create a trap with the given `message`.
+    pub fn new<T: Into<String>>(message: T) -> Self {
+        Trap {
+            message: message.into(),
+            exit_code: None,
+            call_depth_exceeded: false,
+            host_error: None,
+        }
+    }
+
+    /// Create a trap carrying `error`, with its message, which the embedder can retrieve after the
+    /// execution has trapped with [`Error::host_error_downcast`].
+    ///
+    /// The error is kept until the execution started by the embedder ends. If a host function
+    /// traps with an error in an execution nested by [`Caller::execute`], which is returned to the
+    /// calling host function, only the error of the trap ending the outer execution is preserved.
+    pub fn from_host_error<E: HostError>(error: E) -> Self {
+        Trap {
+            message: error.to_string(),
+            exit_code: None,
+            call_depth_exceeded: false,
+            host_error: Some(SharedHostError(Arc::new(error))),
+        }
+    }
+
+    /// Create a trap terminating the program with the exit `code`.
+    #[cfg_attr(not(feature = "wasi"), allow(dead_code))]
+    pub(crate) fn exit(code: u32) -> Self {
+        Trap {
+            message: format!("exit with code {}", code),
+            exit_code: Some(code),
+            call_depth_exceeded: false,
+            host_error: None,
+        }
+    }
+
+    /// Create the trap of an execution which has exceeded the limit of the call depth.
+    pub(crate) fn call_depth_exceeded() -> Self {
+        Trap {
+            message: "call depth exceeded".to_string(),
+            exit_code: None,
+            call_depth_exceeded: true,
+            host_error: None,
+        }
+    }
+
+    /// True if this trap has been raised for exceeding the limit of the call depth.
+    pub(crate) fn is_call_depth_exceeded(&self) -> bool {
+        self.call_depth_exceeded
+    }
+
+    /// The exit code, if this trap terminates the program via exit.
+    #[cfg_attr(not(feature = "wasi"), allow(dead_code))]
+    pub(crate) fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    /// The message of the trap.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The error of the host function, if the trap is created from one.
+    pub(crate) fn host_error(&self) -> Option<&SharedHostError> {
+        self.host_error.as_ref()
+    }
+}
+
+impl std::fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Trap {}
+
+/// An error of a host function with a typed payload, carried by its trap, see
+/// [`Trap::from_host_error`].
+pub trait HostError: std::error::Error + Send + Sync + 'static {}
+
+/// The error of a host function shared by the copies of its trap, which are compared by identity.
+#[derive(Clone)]
+pub(crate) struct SharedHostError(Arc<dyn std::error::Error + Send + Sync>);
+
+impl SharedHostError {
+    pub(crate) fn get(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self.0.as_ref()
+    }
+}
+
+impl fmt::Debug for SharedHostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl PartialEq for SharedHostError {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const u8 == Arc::as_ptr(&other.0) as *const u8
+    }
+}
+
+impl Eq for SharedHostError {}
+
+/// The result of a host function with dynamically typed inputs and output.
+pub type HostResult = Result<Option<TypedValue>, Trap>;
+
+/// A host function with dynamically typed inputs and output, see [`ImportsBuilder::func_dyn`].
+pub type DynHostFn = Box<dyn FnMut(&mut Caller, &[TypedValue]) -> HostResult + Send>;
+
+/// The continuation of a middleware, calling the next middleware or the host function itself.
+pub type NextHostFn<'a> = dyn FnMut(&mut Caller, &[TypedValue]) -> HostResult + 'a;
+
+/// A middleware wrapping all host functions, see [`ImportsBuilder::middleware`].
+type Middleware = Arc<
+    dyn Fn(&ImportMeta, &mut Caller, &[TypedValue], &mut NextHostFn) -> HostResult + Send + Sync,
+>;
+
+/// The module, name and type of a host function, given to the middlewares wrapping it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportMeta {
+    module: String,
+    name: String,
+    func_type: FunctionType,
+}
+
+impl ImportMeta {
+    /// The module name of the function.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// The name of the function.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The type of the function.
+    pub fn func_type(&self) -> &FunctionType {
+        &self.func_type
+    }
+}
+
+/// The untyped form all host functions are converted to.
+pub(crate) type RawHostFn =
+    Box<dyn FnMut(&mut Caller, &[Value]) -> Result<Option<Value>, Trap> + Send>;
+
+/// The hook called with warnings about the registered host functions.
+type WarningHook = Box<dyn FnMut(&str)>;
+
+/// The destination of the log of the calls of host functions, see [`ImportsBuilder::log_calls`].
+pub enum LogSink {
+    /// Write each call as a line of text. The errors of writing are ignored.
+    Writer(Box<dyn Write + Send>),
+    /// Log each call with [`log::debug!`] in the target `fizzy::imports`.
+    #[cfg(feature = "log")]
+    Log,
+}
+
+impl fmt::Debug for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogSink::Writer(_) => f.write_str("LogSink::Writer"),
+            #[cfg(feature = "log")]
+            LogSink::Log => f.write_str("LogSink::Log"),
+        }
+    }
+}
+
+impl LogSink {
+    fn log(&mut self, line: &str) {
+        match self {
+            LogSink::Writer(out) => {
+                let _ = writeln!(out, "{}", line);
+            }
+            #[cfg(feature = "log")]
+            LogSink::Log => log::debug!(target: "fizzy::imports", "{}", line),
+        }
+    }
+}
+
+/// The access to the calling instance passed to host functions.
+pub struct Caller {
+    instance: *mut sys::FizzyInstance,
+    /// The context of the execution calling the host function.
+    ctx: *mut sys::FizzyExecutionContext,
+    /// The trap slot of the calling instance.
+    trap_slot: *const TrapSlot,
+    /// The gas of the metered execution of the calling instance.
+    active_gas: *const ActiveGas,
+    /// The callback notified of the growth of the memory of the calling instance.
+    memory_growth: *const MemoryGrowth,
+}
+
+impl Caller {
+    /// Returns the current memory size of the calling instance, in bytes, see
+    /// [`Instance::memory_size`].
+    pub fn memory_size(&self) -> usize {
+        unsafe { sys::fizzy_get_instance_memory_size(self.instance) }
+    }
+
+    /// Copies memory from `offset` to `target`, for the length of `target.len()`.
+    pub fn memory_get(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+        let slice =
+            unsafe { Instance::checked_instance_memory(self.instance, offset, target.len())? };
+        target.copy_from_slice(slice);
+        Ok(())
+    }
+
+    /// Copies memory from `source` to `offset`, for the length of `source.len()`.
+    pub fn memory_set(&mut self, offset: u32, source: &[u8]) -> Result<(), Error> {
+        let slice =
+            unsafe { Instance::checked_instance_memory(self.instance, offset, source.len())? };
+        slice.copy_from_slice(source);
+        Ok(())
+    }
+
+    /// Grow the memory of the calling instance by `delta_pages` like the `memory.grow`
+    /// instruction, e.g. to make room for a result of variable size, and return the previous
+    /// size in pages. The callback of [`Instance::on_memory_grow`] is notified immediately.
+    ///
+    /// The memory is accessed by offsets, which remain valid after the growth, unlike pointers
+    /// into it. Fails with [`Error::NoMemoryAvailable`] if the instance has no memory, and with
+    /// [`Error::MemoryAllocationFailed`] if the memory would exceed its maximum size or the
+    /// memory pages limit of the instance, or cannot be allocated.
+    pub fn grow_memory(&mut self, delta_pages: u32) -> Result<u32, Error> {
+        let module = unsafe { sys::fizzy_get_instance_module(self.instance) };
+        if !unsafe { sys::fizzy_module_has_memory(module) } {
+            return Err(Error::NoMemoryAvailable);
+        }
+        let old_pages = unsafe { sys::fizzy_grow_instance_memory(self.instance, delta_pages) };
+        if old_pages == u32::MAX {
+            return Err(Error::MemoryAllocationFailed(format!(
+                "memory cannot grow by {} pages",
+                delta_pages
+            )));
+        }
+        unsafe {
+            (*self.memory_growth).observe(old_pages + delta_pages);
+            (*self.memory_growth).check(self.instance);
+        }
+        Ok(old_pages)
+    }
+
+    /// Execute the function `name` exported by the calling instance, within the execution which
+    /// has called the host function, therefore sharing its limit of the call depth.
+    ///
+    /// A trap is returned as [`Error::Trapped`], which terminates the calling execution only if
+    /// the host function returns a trap. A host function which is executing cannot be called again,
+    /// therefore such a call traps. This is the only way to execute the calling instance again
+    /// before the host function returns, other executions fail with [`Error::Busy`].
+    pub fn execute(
+        &mut self,
+        name: &str,
+        args: &[TypedValue],
+    ) -> Result<Option<TypedValue>, Error> {
+        let module = unsafe { sys::fizzy_get_instance_module(self.instance) };
+        let func_idx =
+            crate::find_exported_function_index(module, name).ok_or(Error::FunctionNotFound)?;
+        let func_type =
+            unsafe { FunctionType::from_raw(&sys::fizzy_get_function_type(module, func_idx)) };
+        crate::check_args(&func_type, args, false)?;
+        let values: Vec<Value> = args.iter().map(|v| v.into()).collect();
+        let mut call_depth_exceeded = false;
+        let result = unsafe {
+            sys::fizzy_execute_in_context(
+                self.instance,
+                func_idx,
+                values.as_ptr(),
+                self.ctx,
+                &mut call_depth_exceeded,
+            )
+        };
+        if result.trapped {
+            let trap = if call_depth_exceeded {
+                Some(Trap::call_depth_exceeded())
+            } else {
+                unsafe { (*self.trap_slot).take() }
+            };
+            return Err(Error::Trapped(TrapInfo::new(name, trap)));
+        }
+        ExecutionResult(result).into_typed(&func_type)
+    }
+
+    /// The number of wasm functions stacked up in the execution calling the host function, e.g.
+    /// 1 if the executed function has called it, including those executed by [`Caller::execute`]
+    /// and those of other instances. The depth does not depend on the limit of the call depth.
+    pub fn depth(&self) -> u32 {
+        unsafe { sys::fizzy_get_execution_depth(self.ctx) }
+    }
+
+    /// The ticks left to the metered execution of the calling instance, see
+    /// [`ExecutionOptions::cost_schedule`](crate::ExecutionOptions::cost_schedule), or `None` if
+    /// the execution is not metered. Without a gas limit, the ticks are counted down from
+    /// `u64::MAX`.
+    ///
+    /// The ticks of a block of instructions are charged when it is entered, therefore those of the
+    /// calling block, including the call, have been charged already, as has the cost of the host
+    /// function, see [`ImportsBuilder::func_with_cost`].
+    pub fn remaining_ticks(&self) -> Option<u64> {
+        unsafe { (*self.active_gas).remaining() }
+    }
+}
+
+/// A Rust type representing a WebAssembly value type.
+pub trait WasmType: Copy {
+    /// The WebAssembly type.
+    const VALUE_TYPE: ValueType;
+
+    /// Convert from an untyped value.
+    fn from_value(value: Value) -> Self;
+
+    /// Convert to an untyped value.
+    fn into_value(self) -> Value;
+}
+
+macro_rules! impl_wasm_type {
+    ($t:ty, $value_type:ident, $as:ident) => {
+        impl WasmType for $t {
+            const VALUE_TYPE: ValueType = ValueType::$value_type;
+
+            fn from_value(value: Value) -> Self {
+                value.$as()
+            }
+
+            fn into_value(self) -> Value {
+                self.into()
+            }
+        }
+    };
+}
+
+impl_wasm_type!(i32, I32, as_i32);
+impl_wasm_type!(u32, I32, as_u32);
+impl_wasm_type!(i64, I64, as_i64);
+impl_wasm_type!(u64, I64, as_u64);
+impl_wasm_type!(f32, F32, as_f32);
+impl_wasm_type!(f64, F64, as_f64);
+
+/// A tuple of Rust types representing the inputs of a function.
+pub trait WasmParams {
+    /// The WebAssembly types of the inputs.
+    fn value_types() -> Vec<ValueType>;
+}
+
+/// The output of a host function: either `()` or a single [`WasmType`].
+pub trait WasmResult {
+    /// The WebAssembly type of the output.
+    const OUTPUT: Option<ValueType>;
+
+    /// Convert to an optional untyped value.
+    fn into_output(self) -> Option<Value>;
+}
+
+impl WasmResult for () {
+    const OUTPUT: Option<ValueType> = None;
+
+    fn into_output(self) -> Option<Value> {
+        None
+    }
+}
+
+impl<T: WasmType> WasmResult for T {
+    const OUTPUT: Option<ValueType> = Some(T::VALUE_TYPE);
+
+    fn into_output(self) -> Option<Value> {
+        Some(self.into_value())
+    }
+}
+
+/// A Rust closure usable as a host function, with the WebAssembly type inferred from its signature.
+///
+/// It is implemented for closures `FnMut(&mut Caller, A1, A2, ...) -> Result<R, Trap>` where
+/// the arguments are [`WasmType`]s and `R` is either `()` or a [`WasmType`].
+pub trait IntoHostFunction<Params, Output> {
+    #[doc(hidden)]
+    fn into_host_function(self) -> (FunctionType, RawHostFn);
+}
+
+macro_rules! impl_into_host_function {
+    ($($param:ident),*) => {
+        impl<$($param: WasmType),*> WasmParams for ($($param,)*) {
+            fn value_types() -> Vec<ValueType> {
+                vec![$($param::VALUE_TYPE),*]
+            }
+        }
+
+        impl<F, R, $($param),*> IntoHostFunction<($($param,)*), R> for F
+        where
+            F: FnMut(&mut Caller, $($param),*) -> Result<R, Trap> + Send + 'static,
+            R: WasmResult,
+            $($param: WasmType),*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn into_host_function(mut self) -> (FunctionType, RawHostFn) {
+                let func_type = FunctionType::new(<($($param,)*)>::value_types(), R::OUTPUT);
+                let func = move |caller: &mut Caller, args: &[Value]| {
+                    let mut index = 0;
+                    $(
+                        let $param = $param::from_value(args[index]);
+                        index += 1;
+                    )*
+                    self(caller, $($param),*).map(WasmResult::into_output)
+                };
+                (func_type, Box::new(func))
+            }
+        }
+    };
+}
+
+impl_into_host_function!();
+impl_into_host_function!(A1);
+impl_into_host_function!(A1, A2);
+impl_into_host_function!(A1, A2, A3);
+impl_into_host_function!(A1, A2, A3, A4);
+impl_into_host_function!(A1, A2, A3, A4, A5);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6, A7);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6, A7, A8);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6, A7, A8, A9);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10);
+
+/// A host function registered in [`ImportsBuilder`].
+struct HostFunction {
+    module: String,
+    name: String,
+    func_type: FunctionType,
+    func: RawHostFn,
+    /// The provider of a default registration, which an explicit registration of the same name overrides.
+    provider: Option<&'static str>,
+    /// The ticks charged for each call, or `None` for the default cost of the builder.
+    cost: Option<u64>,
+}
+
+/// A collection of host functions to be resolved by name against the imports of a module.
+///
+/// If a name is registered multiple times, the first registration is used. Registrations provided by
+/// this crate, such as the WASI functions added by `wasi::add_to_builder`, are overridden by explicit
+/// registrations of the same name regardless of the order.
+///
+/// ```
+/// use fizzy::{Caller, ImportsBuilder, Trap};
+///
+/// let mut imports = ImportsBuilder::new();
+/// imports.func("env", "add", |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> {
+///     Ok(a.wrapping_add(b))
+/// });
+/// ```
+#[derive(Default)]
+pub struct ImportsBuilder {
+    functions: Vec<HostFunction>,
+    /// The provider of the functions currently being registered.
+    provider: Option<&'static str>,
+    warning_hook: Option<WarningHook>,
+    log_sink: Option<Arc<Mutex<LogSink>>>,
+    /// The middlewares wrapping all host functions, the first one outermost.
+    middlewares: Vec<Middleware>,
+    extra_imports: ExtraImports,
+    /// The module names whose imports are also looked up under another module name.
+    aliases: Vec<(String, String)>,
+    /// The ticks charged for each call of the functions without their own costs.
+    default_cost: u64,
+}
+
+/// Whether the host functions registered but not imported by a module fail its instantiation,
+/// see [`ImportsBuilder::extra_imports`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtraImports {
+    /// The functions not imported are ignored.
+    Ignore,
+    /// The instantiation fails if a function not provided by this crate is not imported.
+    Error,
+}
+
+impl Default for ExtraImports {
+    fn default() -> Self {
+        ExtraImports::Ignore
+    }
+}
+
+/// The result of matching the registered host functions with the imported functions of a module,
+/// see [`ImportsBuilder::check_imports`]. The functions are written as `module.name`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    missing: Vec<String>,
+    mismatched: Vec<String>,
+    unused: Vec<String>,
+}
+
+impl ImportReport {
+    /// The imported functions which are not registered.
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    /// The imported functions registered with another type.
+    pub fn mismatched(&self) -> &[String] {
+        &self.mismatched
+    }
+
+    /// The registered functions, other than those provided by this crate, which are not imported.
+    pub fn unused(&self) -> &[String] {
+        &self.unused
+    }
+}
+
+impl ImportsBuilder {
+    /// Create an empty collection.
+    pub fn new() -> Self {
+        ImportsBuilder::default()
+    }
+
+    /// Register a host function of `module` and `name`, with the type inferred from the closure signature.
+    pub fn func<Params, Output, F>(&mut self, module: &str, name: &str, func: F) -> &mut Self
+    where
+        F: IntoHostFunction<Params, Output>,
+    {
+        let (func_type, func) = func.into_host_function();
+        self.functions.push(HostFunction {
+            module: module.to_string(),
+            name: name.to_string(),
+            func_type,
+            func,
+            provider: self.provider,
+            cost: None,
+        });
+        self
+    }
+
+    /// Register a host function of `module` and `name` like [`ImportsBuilder::func`], charging
+    /// `cost` ticks for each call to the metered execution calling it, see
+    /// [`ExecutionOptions::cost_schedule`](crate::ExecutionOptions::cost_schedule).
+    ///
+    /// The ticks are charged before the function is called, which is not called if fewer ticks
+    /// are left: the execution traps as if its instructions exceeded the gas limit, see
+    /// [`ExecutionOutcome::gas_exhausted`](crate::ExecutionOutcome::gas_exhausted). The charged
+    /// ticks are included in the ticks used, and are seen by [`Caller::remaining_ticks`].
+    pub fn func_with_cost<Params, Output, F>(
+        &mut self,
+        module: &str,
+        name: &str,
+        cost: u64,
+        func: F,
+    ) -> &mut Self
+    where
+        F: IntoHostFunction<Params, Output>,
+    {
+        self.func(module, name, func);
+        self.functions.last_mut().unwrap().cost = Some(cost);
+        self
+    }
+
+    /// Set the ticks charged for each call of the host functions registered without their own
+    /// costs, including those provided by this crate, like those of
+    /// [`ImportsBuilder::func_with_cost`]. By default, the calls are free.
+    pub fn default_import_cost(&mut self, cost: u64) -> &mut Self {
+        self.default_cost = cost;
+        self
+    }
+
+    /// Register a host function of `module` and `name` of the explicit type `func_type`.
+    ///
+    /// The closure receives arguments matching the inputs of `func_type`, and must return a value
+    /// matching its output. Returning a mismatching value results in a trap. If `func_type` uses
+    /// a reference type, the instantiation fails with [`Error::UnsupportedType`].
+    pub fn func_with_type<F>(
+        &mut self,
+        module: &str,
+        name: &str,
+        func_type: FunctionType,
+        mut func: F,
+    ) -> &mut Self
+    where
+        F: FnMut(&mut Caller, &[TypedValue]) -> HostResult + Send + 'static,
+    {
+        let inputs = func_type.inputs.clone();
+        let output = func_type.output;
+        let func = move |caller: &mut Caller, args: &[Value]| {
+            let args: Vec<TypedValue> = args
+                .iter()
+                .zip(inputs.iter())
+                .map(|(value, value_type)| TypedValue::from_value(*value, *value_type))
+                .collect();
+            let result = func(caller, &args)?;
+            if result.as_ref().map(TypedValue::value_type) != output {
+                return Err(Trap::new(
+                    "host function returned a value of mismatching type",
+                ));
+            }
+            Ok(result.as_ref().map(Value::from))
+        };
+        self.functions.push(HostFunction {
+            module: module.to_string(),
+            name: name.to_string(),
+            func_type,
+            func: Box::new(func),
+            provider: self.provider,
+            cost: None,
+        });
+        self
+    }
+
+    /// Register a host function of `module` and `name` like [`ImportsBuilder::func_with_type`],
+    /// of the type written as `signature`, e.g. `(i32, i32) -> i64` or `I(ii)`, see the
+    /// [`FromStr`](std::str::FromStr) implementation of [`FunctionType`]. This suits the imports
+    /// known only at runtime, e.g. from the manifest of a plugin.
+    ///
+    /// Fails if `signature` is malformed or uses a reference type, which is not supported, in
+    /// which case nothing is registered.
+    pub fn func_dyn(
+        &mut self,
+        module: &str,
+        name: &str,
+        signature: &str,
+        func: DynHostFn,
+    ) -> Result<&mut Self, Error> {
+        let func_type: FunctionType = signature.parse()?;
+        for value_type in func_type.inputs.iter().chain(&func_type.output) {
+            value_type.check_supported()?;
+        }
+        Ok(self.func_with_type(module, name, func_type, func))
+    }
+
+    /// Set whether the registered functions not imported by the module, other than those provided
+    /// by this crate, fail the instantiation. By default, they are ignored.
+    pub fn extra_imports(&mut self, mode: ExtraImports) -> &mut Self {
+        self.extra_imports = mode;
+        self
+    }
+
+    /// Resolve the imported functions of the module `alias` which are not registered under it by
+    /// the functions registered under the module `target`, e.g. `alias_module("env", "wasi_unstable")`.
+    /// The aliases of the same module are looked up in the order they are added.
+    pub fn alias_module(&mut self, alias: &str, target: &str) -> &mut Self {
+        self.aliases.push((alias.to_string(), target.to_string()));
+        self
+    }
+
+    /// Match the registered functions with the imported functions of `module` by name and type,
+    /// as the instantiation does, and report the differences.
+    pub fn check_imports(&self, module: &crate::Module) -> ImportReport {
+        let explicit = self.explicit_names();
+        let functions: Vec<&HostFunction> = self
+            .functions
+            .iter()
+            .filter(|function| !function.is_overridden(&explicit))
+            .collect();
+        let mut used = vec![false; functions.len()];
+        let mut report = ImportReport::default();
+        for import in module.imports() {
+            let func_type = match import.ty() {
+                crate::ExternalType::Function(func_type) => func_type,
+                _ => continue,
+            };
+            let full_name = format!("{}.{}", import.module(), import.name());
+            let registered = functions
+                .iter()
+                .map(|function| (function.module.as_bytes(), function.name.as_bytes()));
+            match find_registered(
+                registered,
+                &self.aliases,
+                import.module().as_bytes(),
+                import.name().as_bytes(),
+            ) {
+                Some(index) => {
+                    used[index] = true;
+                    if functions[index].func_type != *func_type {
+                        report.mismatched.push(full_name);
+                    }
+                }
+                None => report.missing.push(full_name),
+            }
+        }
+        report.unused = functions
+            .iter()
+            .zip(used)
+            .filter(|(function, used)| !used && function.provider.is_none())
+            .map(|(function, _)| format!("{}.{}", function.module, function.name))
+            .collect();
+        report
+    }
+
+    /// Set the hook called with a warning message when an explicit registration overrides a
+    /// registration provided by this crate. The hook is called during instantiation.
+    pub fn on_warning<F: FnMut(&str) + 'static>(&mut self, hook: F) -> &mut Self {
+        self.warning_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Log the calls of all host functions to `sink`, including those registered after this call
+    /// and those provided by this crate, e.g.
+    /// `env.add(i32:1, i32:2) -> i32:3 (1.2µs)` or `env.log(i64:7) -> trap: message (850ns)`.
+    ///
+    /// The functions behave the same as without logging, also when they panic, in which case the
+    /// logged trap is the one the panic is turned into.
+    pub fn log_calls(&mut self, sink: LogSink) -> &mut Self {
+        self.log_sink = Some(Arc::new(Mutex::new(sink)));
+        self
+    }
+
+    /// Wrap all host functions, including those provided by this crate, e.g. the WASI functions,
+    /// with `middleware`, which is called with the function's [`ImportMeta`] and arguments instead
+    /// of the function. It calls the function by its last argument, `next`, possibly with other
+    /// arguments, or returns without calling it, e.g. to deny the call with a trap.
+    ///
+    /// The middlewares are called in the order they are added, each one by the `next` of the
+    /// previous one. The arguments passed to `next` and the result returned must match the type of
+    /// the function, otherwise the call traps. The calls are logged by [`ImportsBuilder::log_calls`]
+    /// as seen by the module, i.e. with the arguments and the result of the first middleware.
+    ///
+    /// ```
+    /// use fizzy::{ImportsBuilder, Trap};
+    ///
+    /// let mut imports = ImportsBuilder::new();
+    /// imports.middleware(|meta, caller, args, next| {
+    ///     if meta.name() == "exit" {
+    ///         return Err(Trap::new("exit is not allowed"));
+    ///     }
+    ///     next(caller, args)
+    /// });
+    /// ```
+    pub fn middleware<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Fn(&ImportMeta, &mut Caller, &[TypedValue], &mut NextHostFn) -> HostResult
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Register a host function of `module` and `name` of the type `func_type` in its untyped form.
+    #[cfg_attr(not(feature = "replay"), allow(dead_code))]
+    pub(crate) fn raw_func(
+        &mut self,
+        module: &str,
+        name: &str,
+        func_type: FunctionType,
+        func: RawHostFn,
+    ) -> &mut Self {
+        self.functions.push(HostFunction {
+            module: module.to_string(),
+            name: name.to_string(),
+            func_type,
+            func,
+            provider: self.provider,
+            cost: None,
+        });
+        self
+    }
+
+    /// Replace the closures of the registered host functions by the results of `wrap` with their
+    /// module, name and type.
+    #[cfg_attr(not(feature = "replay"), allow(dead_code))]
+    pub(crate) fn wrap_functions(
+        mut self,
+        mut wrap: impl FnMut(&str, &str, &FunctionType, RawHostFn) -> RawHostFn,
+    ) -> Self {
+        self.functions = self
+            .functions
+            .into_iter()
+            .map(|function| HostFunction {
+                func: wrap(
+                    &function.module,
+                    &function.name,
+                    &function.func_type,
+                    function.func,
+                ),
+                ..function
+            })
+            .collect();
+        self
+    }
+
+    /// Register the default functions of `provider` with `register`.
+    pub(crate) fn provide(&mut self, provider: &'static str, register: impl FnOnce(&mut Self)) {
+        self.provider = Some(provider);
+        register(self);
+        self.provider = None;
+    }
+
+    /// Resolve the imported functions of `module` by name and type, in the order of its imports.
+    ///
+    /// The errors are the same as those of resolving them with `fizzy_resolve_instantiate`.
+    pub(crate) fn resolve(
+        self,
+        module: *const sys::FizzyModule,
+    ) -> Result<Vec<Arc<SharedHostFunction>>, Error> {
+        resolve_shared(&self.into_shared()?, module)
+    }
+
+    /// The module and name of the functions registered explicitly, not provided by this crate.
+    fn explicit_names(&self) -> HashSet<(String, String)> {
+        self.functions
+            .iter()
+            .filter(|function| function.provider.is_none())
+            .map(|function| (function.module.clone(), function.name.clone()))
+            .collect()
+    }
+
+    /// Convert to low-level host functions, leaving out the provided ones which are overridden.
+    pub(crate) fn into_shared(self) -> Result<SharedImports, Error> {
+        let explicit = self.explicit_names();
+        let mut warning_hook = self.warning_hook;
+        let log_sink = self.log_sink;
+        let middlewares = self.middlewares;
+        let default_cost = self.default_cost;
+        let functions = self
+            .functions
+            .into_iter()
+            .filter(|function| {
+                let overridden = function.is_overridden(&explicit);
+                if let (true, Some(hook), Some(provider)) =
+                    (overridden, warning_hook.as_mut(), function.provider)
+                {
+                    hook(&format!(
+                        "host function {}.{} overrides the function provided by {}",
+                        function.module, function.name, provider
+                    ));
+                }
+                !overridden
+            })
+            .map(|function| {
+                for value_type in function
+                    .func_type
+                    .inputs
+                    .iter()
+                    .chain(&function.func_type.output)
+                {
+                    value_type.check_supported()?;
+                }
+                let to_c_string = |s: String| {
+                    CString::new(s).map_err(|_| {
+                        Error::InstantiationFailed("import name contains a NUL byte".to_string())
+                    })
+                };
+                let inputs = function
+                    .func_type
+                    .inputs
+                    .iter()
+                    .map(|value_type| ValueType::to_raw(Some(*value_type)))
+                    .collect();
+                let func = if middlewares.is_empty() {
+                    function.func
+                } else {
+                    let meta = ImportMeta {
+                        module: function.module.clone(),
+                        name: function.name.clone(),
+                        func_type: function.func_type.clone(),
+                    };
+                    wrapped(meta, function.func, middlewares.clone())
+                };
+                let func = match &log_sink {
+                    Some(sink) => logged(
+                        &function.module,
+                        &function.name,
+                        &function.func_type,
+                        func,
+                        sink.clone(),
+                    ),
+                    None => func,
+                };
+                Ok(Arc::new(SharedHostFunction {
+                    module: to_c_string(function.module)?,
+                    name: to_c_string(function.name)?,
+                    inputs,
+                    output: function.func_type.output,
+                    func: Mutex::new(func),
+                    provided: function.provider.is_some(),
+                    cost: function.cost.unwrap_or(default_cost),
+                }))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(SharedImports {
+            functions,
+            aliases: self.aliases,
+            extra_imports: self.extra_imports,
+        })
+    }
+}
+
+impl HostFunction {
+    /// True if the function is provided by this crate and registered explicitly as well.
+    fn is_overridden(&self, explicit: &HashSet<(String, String)>) -> bool {
+        self.provider.is_some() && explicit.contains(&(self.module.clone(), self.name.clone()))
+    }
+}
+
+/// The index of the function registered for the import `module.name` among the `registered`
+/// modules and names, by `module` or otherwise by the modules it is an alias of in `aliases`.
+fn find_registered<'a, I>(
+    registered: I,
+    aliases: &[(String, String)],
+    module: &[u8],
+    name: &[u8],
+) -> Option<usize>
+where
+    I: Iterator<Item = (&'a [u8], &'a [u8])> + Clone,
+{
+    let find = |module: &[u8]| {
+        registered
+            .clone()
+            .position(|registered| registered == (module, name))
+    };
+    find(module).or_else(|| {
+        aliases
+            .iter()
+            .filter(|(alias, _)| alias.as_bytes() == module)
+            .find_map(|(_, target)| find(target.as_bytes()))
+    })
+}
+
+/// The host functions of an [`ImportsBuilder`] converted for resolution, which can be shared by
+/// multiple instances.
+#[derive(Clone, Default)]
+pub(crate) struct SharedImports {
+    pub(crate) functions: Vec<Arc<SharedHostFunction>>,
+    aliases: Vec<(String, String)>,
+    extra_imports: ExtraImports,
+}
+
+/// Wrap the closure `func` of the host function of `meta` with `middlewares`, the first one
+/// outermost.
+fn wrapped(meta: ImportMeta, mut func: RawHostFn, middlewares: Vec<Middleware>) -> RawHostFn {
+    Box::new(move |caller: &mut Caller, args: &[Value]| {
+        let args: Vec<TypedValue> = args
+            .iter()
+            .zip(&meta.func_type.inputs)
+            .map(|(value, value_type)| TypedValue::from_value(*value, *value_type))
+            .collect();
+        let mut call = |caller: &mut Caller, args: &[TypedValue]| -> HostResult {
+            if !args
+                .iter()
+                .map(TypedValue::value_type)
+                .eq(meta.func_type.inputs.iter().copied())
+            {
+                return Err(Trap::new(
+                    "middleware passed arguments of mismatching types",
+                ));
+            }
+            let args: Vec<Value> = args.iter().map(Value::from).collect();
+            let result = func(caller, &args)?;
+            Ok(match (result, meta.func_type.output) {
+                (Some(value), Some(value_type)) => Some(TypedValue::from_value(value, value_type)),
+                _ => None,
+            })
+        };
+        let result = call_middlewares(&middlewares, &meta, caller, &args, &mut call)?;
+        if result.as_ref().map(TypedValue::value_type) != meta.func_type.output {
+            return Err(Trap::new("middleware returned a value of mismatching type"));
+        }
+        Ok(result.as_ref().map(Value::from))
+    })
+}
+
+/// Call the first of `middlewares` with the rest of them as its continuation, ending with `func`.
+fn call_middlewares(
+    middlewares: &[Middleware],
+    meta: &ImportMeta,
+    caller: &mut Caller,
+    args: &[TypedValue],
+    func: &mut NextHostFn,
+) -> HostResult {
+    match middlewares.split_first() {
+        Some((middleware, rest)) => middleware(
+            meta,
+            caller,
+            args,
+            &mut |caller: &mut Caller, args: &[TypedValue]| {
+                call_middlewares(rest, meta, caller, args, &mut *func)
+            },
+        ),
+        None => func(caller, args),
+    }
+}
+
+/// Wrap the closure `func` of the host function `module.name` with logging its calls to `sink`.
+///
+/// A panic is resumed after logging it, to be turned into a trap by the trampoline.
+fn logged(
+    module: &str,
+    name: &str,
+    func_type: &FunctionType,
+    mut func: RawHostFn,
+    sink: Arc<Mutex<LogSink>>,
+) -> RawHostFn {
+    let full_name = format!("{}.{}", module, name);
+    let func_type = func_type.clone();
+    Box::new(move |caller: &mut Caller, args: &[Value]| {
+        let args_text: Vec<String> = args
+            .iter()
+            .zip(&func_type.inputs)
+            .map(|(value, value_type)| TypedValue::from_value(*value, *value_type).to_string())
+            .collect();
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| func(caller, args)));
+        let elapsed = start.elapsed();
+
+        let outcome = match (&result, func_type.output) {
+            (Ok(Ok(Some(value))), Some(value_type)) => {
+                format!("-> {}", TypedValue::from_value(*value, value_type))
+            }
+            (Ok(Ok(_)), _) => "-> void".to_string(),
+            (Ok(Err(trap)), _) => format!("-> trap: {}", trap),
+            (Err(payload), _) => format!("-> trap: {}", panic_trap(payload.as_ref())),
+        };
+        let line = format!(
+            "{}({}) {} ({:?})",
+            full_name,
+            args_text.join(", "),
+            outcome,
+            elapsed
+        );
+        sink.lock().unwrap_or_else(|e| e.into_inner()).log(&line);
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+/// The trap a panic of a host function is turned into.
+pub(crate) fn panic_trap(payload: &(dyn Any + Send)) -> Trap {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    Trap::new(format!("host function panicked: {}", message))
+}
+
+/// The place where host functions of an instance store their traps.
+#[derive(Default)]
+pub(crate) struct TrapSlot(Mutex<Option<Trap>>);
+
+impl TrapSlot {
+    pub(crate) fn set(&self, trap: Trap) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(trap);
+    }
+
+    pub(crate) fn take(&self) -> Option<Trap> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.take();
+    }
+
+    /// Call `f` with the trap, keeping it.
+    pub(crate) fn with<R>(&self, f: impl FnOnce(Option<&Trap>) -> R) -> R {
+        f(self.0.lock().unwrap_or_else(|e| e.into_inner()).as_ref())
+    }
+}
+
+/// Resolve the imported functions of `module` by name and type from `imports`, in the order of
+/// its imports.
+///
+/// The errors are the same as those of resolving them with `fizzy_resolve_instantiate`.
+pub(crate) fn resolve_shared(
+    imports: &SharedImports,
+    module: *const sys::FizzyModule,
+) -> Result<Vec<Arc<SharedHostFunction>>, Error> {
+    let functions = &imports.functions;
+    let import_count = unsafe { sys::fizzy_get_import_count(module) };
+    let mut resolved = Vec::new();
+    for import_idx in 0..import_count {
+        let import = unsafe { sys::fizzy_get_import_description(module, import_idx) };
+        if import.kind != sys::FizzyExternalKind_FizzyExternalKindFunction {
+            continue;
+        }
+        let (module_name, name) =
+            unsafe { (CStr::from_ptr(import.module), CStr::from_ptr(import.name)) };
+        let full_name = format!(
+            "{}.{}",
+            module_name.to_string_lossy(),
+            name.to_string_lossy()
+        );
+        let registered = functions
+            .iter()
+            .map(|function| (function.module.as_bytes(), function.name.as_bytes()));
+        let function = find_registered(
+            registered,
+            &imports.aliases,
+            module_name.to_bytes(),
+            name.to_bytes(),
+        )
+        .map(|index| &functions[index])
+        .ok_or_else(|| Error::missing_import(module_name, name, ExternalKind::Function))?;
+
+        let func_type = unsafe { import.desc.function_type };
+        let inputs = if func_type.inputs_size == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(func_type.inputs, func_type.inputs_size) }
+        };
+        if inputs != function.inputs.as_slice() {
+            return Err(Error::InstantiationFailed(format!(
+                "function {} input types don't match imported function in module",
+                full_name
+            )));
+        }
+        let output = ValueType::to_raw(function.output);
+        if func_type.output == sys::FizzyValueTypeVoid && output != sys::FizzyValueTypeVoid {
+            return Err(Error::InstantiationFailed(format!(
+                "function {} has output but is defined void in module",
+                full_name
+            )));
+        }
+        if func_type.output != output {
+            return Err(Error::InstantiationFailed(format!(
+                "function {} output type doesn't match imported function in module",
+                full_name
+            )));
+        }
+        resolved.push(function.clone());
+    }
+    if imports.extra_imports == ExtraImports::Error {
+        if let Some(function) = functions.iter().find(|function| {
+            !function.provided && !resolved.iter().any(|used| Arc::ptr_eq(used, function))
+        }) {
+            return Err(Error::InstantiationFailed(format!(
+                "host function {}.{} is not imported by the module",
+                function.module.to_string_lossy(),
+                function.name.to_string_lossy()
+            )));
+        }
+    }
+    Ok(resolved)
+}
+
+/// A low-level host function, which can be shared by multiple instances.
+///
+/// The closure is called by one instance at a time.
+pub(crate) struct SharedHostFunction {
+    module: CString,
+    name: CString,
+    inputs: Vec<sys::FizzyValueType>,
+    output: Option<ValueType>,
+    func: Mutex<RawHostFn>,
+    /// True if the function is provided by this crate, see [`ExtraImports`].
+    provided: bool,
+    /// The ticks charged for each call, see [`ImportsBuilder::func_with_cost`].
+    cost: u64,
+}
+
+/// The context of a low-level host function, referenced by the instance.
+pub(crate) struct HostContext {
+    function: Arc<SharedHostFunction>,
+    trap_slot: Arc<TrapSlot>,
+    memory_growth: Arc<MemoryGrowth>,
+    active_gas: Arc<ActiveGas>,
+    /// True while the execution in progress times the calls, see
+    /// [`ExecutionOptions::time_imports`](crate::ExecutionOptions::time_imports).
+    import_timing: Arc<AtomicBool>,
+    /// The statistics of the calls, see [`Instance::import_stats`].
+    pub(crate) counters: ImportCounters,
+    /// True while the function is called, which cannot be called again until it returns.
+    entered: Cell<bool>,
+}
+
+impl HostContext {
+    /// Create the context of `function` for an instance which stores its traps in `trap_slot`,
+    /// notifies the growth of its memory by `memory_growth`, and shares the gas of its metered
+    /// executions by `active_gas`, and times the calls while `import_timing` is set.
+    pub(crate) fn new(
+        function: Arc<SharedHostFunction>,
+        trap_slot: &Arc<TrapSlot>,
+        memory_growth: &Arc<MemoryGrowth>,
+        active_gas: &Arc<ActiveGas>,
+        import_timing: &Arc<AtomicBool>,
+    ) -> Box<Self> {
+        Box::new(HostContext {
+            function,
+            trap_slot: trap_slot.clone(),
+            memory_growth: memory_growth.clone(),
+            active_gas: active_gas.clone(),
+            import_timing: import_timing.clone(),
+            counters: ImportCounters::default(),
+            entered: Cell::new(false),
+        })
+    }
+
+    /// The statistics of the calls of the function.
+    pub(crate) fn import_stat(&self) -> ImportStat {
+        self.counters.stat(
+            &self.function.module.to_string_lossy(),
+            &self.function.name.to_string_lossy(),
+            self.function.cost > 0,
+        )
+    }
+
+    /// Describe the function for instantiation.
+    ///
+    /// The returned struct points into this context, which therefore must outlive the instance.
+    pub(crate) fn external_function(&self) -> sys::FizzyExternalFunction {
+        sys::FizzyExternalFunction {
+            type_: sys::FizzyFunctionType {
+                output: ValueType::to_raw(self.function.output),
+                inputs: self.function.inputs.as_ptr(),
+                inputs_size: self.function.inputs.len(),
+            },
+            function: Some(host_function_trampoline),
+            context: self as *const HostContext as *mut std::os::raw::c_void,
+        }
+    }
+}
+
+const TRAPPED: sys::FizzyExecutionResult = sys::FizzyExecutionResult {
+    trapped: true,
+    has_value: false,
+    value: sys::FizzyValue { i64: 0 },
+};
+
+/// The low-level host function calling the Rust closure stored in the context.
+///
+/// A panic of the closure must not unwind into C++, therefore it is turned into a trap.
+unsafe extern "C" fn host_function_trampoline(
+    context: *mut std::os::raw::c_void,
+    instance: *mut sys::FizzyInstance,
+    args: *const sys::FizzyValue,
+    ctx: *mut sys::FizzyExecutionContext,
+) -> sys::FizzyExecutionResult {
+    let context = &*(context as *const HostContext);
+    // The function is locked while it is called, therefore it cannot be called by an execution
+    // of Caller::execute.
+    if context.entered.get() {
+        context.trap_slot.set(Trap::new(format!(
+            "host function {}.{} called recursively",
+            context.function.module.to_string_lossy(),
+            context.function.name.to_string_lossy()
+        )));
+        return TRAPPED;
+    }
+    // Running out of ticks is not a trap of the host function, like running out of them in wasm.
+    let metered = context.active_gas.remaining().is_some();
+    if !context.active_gas.charge(context.function.cost) {
+        return TRAPPED;
+    }
+    if metered && context.function.cost > 0 {
+        context.counters.record_ticks(context.function.cost);
+    }
+    let args = if context.function.inputs.is_empty() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(args, context.function.inputs.len())
+    };
+    let mut caller = Caller {
+        instance,
+        ctx,
+        trap_slot: &*context.trap_slot,
+        active_gas: &*context.active_gas,
+        memory_growth: &*context.memory_growth,
+    };
+    let mut func = context
+        .function
+        .func
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let start = if context.import_timing.load(Ordering::Relaxed) {
+        Some(Instant::now())
+    } else {
+        None
+    };
+    context.entered.set(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        context.memory_growth.check(instance);
+        let result = (*func)(&mut caller, args);
+        context.memory_growth.check(instance);
+        result
+    }));
+    context.entered.set(false);
+    drop(func);
+    context
+        .counters
+        .record_call(start.map(|start| start.elapsed()));
+
+    let trap = match result {
+        Ok(Ok(value)) => {
+            return sys::FizzyExecutionResult {
+                trapped: false,
+                has_value: value.is_some(),
+                value: value.unwrap_or(sys::FizzyValue { i64: 0 }),
+            }
+        }
+        Ok(Err(trap)) => trap,
+        Err(payload) => panic_trap(payload.as_ref()),
+    };
+    context.trap_slot.set(trap);
+    TRAPPED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn add_log_module() -> crate::Module {
+        /* wat2wasm
+        (module
+          (func $add (import "env" "add") (param i32 i32) (result i32))
+          (func $log (import "env" "log") (param i64))
+          (func (export "run") (param i32 i32) (result i32)
+            (call $log (i64.const 7))
+            (call $add (local.get 0) (local.get 1))
+          )
+          (memory (export "mem") 1)
+        )
+        */
+        let input = hex::decode("0061736d01000000010b0260027f7f017f60017e0002150203656e7603616464000003656e76036c6f670001030201000503010001070d020372756e0002036d656d02000a0e010c00420710012000200110000b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    #[test]
+    fn typed_host_functions() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let logged_clone = logged.clone();
+
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func(
+                "env",
+                "add",
+                |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+            )
+            .func(
+                "env",
+                "log",
+                move |_: &mut Caller, v: u64| -> Result<(), Trap> {
+                    logged_clone.lock().unwrap().push(v);
+                    Ok(())
+                },
+            );
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(42), TypedValue::U32(24)])
+            .unwrap();
+        assert!(!result.trapped());
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 66);
+        assert_eq!(*logged.lock().unwrap(), [7]);
+    }
+
+    #[test]
+    fn dynamic_host_functions() {
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func_with_type(
+                "env",
+                "add",
+                FunctionType::new(vec![ValueType::I32, ValueType::I32], Some(ValueType::I32)),
+                |_, args| {
+                    Ok(Some(TypedValue::U32(
+                        args[0].as_u32().unwrap() * args[1].as_u32().unwrap(),
+                    )))
+                },
+            )
+            .func_with_type(
+                "env",
+                "log",
+                FunctionType::new(vec![ValueType::I64], None),
+                |_, args| {
+                    assert_eq!(args, [TypedValue::U64(7)]);
+                    Ok(None)
+                },
+            );
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(6), TypedValue::U32(7)])
+            .unwrap();
+        assert!(!result.trapped());
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 42);
+    }
+
+    #[test]
+    fn host_function_memory_access() {
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func(
+                "env",
+                "add",
+                |caller: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> {
+                    assert_eq!(caller.memory_size(), 65536);
+                    caller.memory_set(a, &b.to_le_bytes()).unwrap();
+                    let mut bytes = [0u8; 4];
+                    caller.memory_get(a, &mut bytes).unwrap();
+                    assert_eq!(
+                        caller.memory_get(65535, &mut bytes).err().unwrap(),
+                        Error::InvalidMemoryOffsetOrSize
+                    );
+                    Ok(u32::from_le_bytes(bytes))
+                },
+            )
+            .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                Ok(())
+            });
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(16), TypedValue::U32(0xaabbccdd)])
+            .unwrap();
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 0xaabbccdd);
+        let mut bytes = [0u8; 4];
+        instance.memory_get(16, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xdd, 0xcc, 0xbb, 0xaa]);
+    }
+
+    #[test]
+    fn host_function_traps() {
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func(
+                "env",
+                "add",
+                |_: &mut Caller, a: u32, _: u32| -> Result<u32, Trap> {
+                    match a {
+                        0 => Err(Trap::new("zero")),
+                        1 => panic!("one"),
+                        _ => Ok(a),
+                    }
+                },
+            )
+            .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                Ok(())
+            });
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(0), TypedValue::U32(0)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(instance.take_host_trap(), Some(Trap::new("zero")));
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(1), TypedValue::U32(0)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new("host function panicked: one"))
+        );
+
+        // The instance stays usable after a trap.
+        let result = instance
+            .execute("run", &[TypedValue::U32(2), TypedValue::U32(0)])
+            .unwrap();
+        assert!(!result.trapped());
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 2);
+    }
+
+    #[test]
+    fn log_calls() {
+        /// The buffer of the written log, which stays readable after it is given to the sink.
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let mut imports = ImportsBuilder::new();
+        imports
+            .log_calls(LogSink::Writer(Box::new(buffer.clone())))
+            .func(
+                "env",
+                "add",
+                |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> {
+                    match a {
+                        0 => Err(Trap::new("zero")),
+                        1 => panic!("one"),
+                        _ => Ok(a + b),
+                    }
+                },
+            )
+            .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                Ok(())
+            });
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(2), TypedValue::U32(3)])
+            .unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(5)));
+        let result = instance
+            .execute("run", &[TypedValue::U32(0), TypedValue::U32(3)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(instance.take_host_trap(), Some(Trap::new("zero")));
+        // The panic is turned into the same trap as without logging.
+        let result = instance
+            .execute("run", &[TypedValue::U32(1), TypedValue::U32(3)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new("host function panicked: one"))
+        );
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let calls: Vec<&str> = log
+            .lines()
+            .map(|line| {
+                let (call, elapsed) = line.split_at(line.rfind(" (").unwrap());
+                assert!(elapsed.ends_with("s)"), "{}", line);
+                call
+            })
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                "env.log(i64:7) -> void",
+                "env.add(i32:2, i32:3) -> i32:5",
+                "env.log(i64:7) -> void",
+                "env.add(i32:0, i32:3) -> trap: zero",
+                "env.log(i64:7) -> void",
+                "env.add(i32:1, i32:3) -> trap: host function panicked: one",
+            ]
+        );
+    }
+
+    #[test]
+    fn middlewares() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls_clone = calls.clone();
+        let build = |deny: bool| {
+            let calls = calls_clone.clone();
+            let mut imports = ImportsBuilder::new();
+            imports
+                .middleware(move |meta, caller, args, next| {
+                    calls.lock().unwrap().push((
+                        format!("{}.{}", meta.module(), meta.name()),
+                        meta.func_type().inputs.len(),
+                    ));
+                    next(caller, args)
+                })
+                .func(
+                    "env",
+                    "add",
+                    |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+                )
+                .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                    Ok(())
+                });
+            if deny {
+                imports.middleware(|meta, caller, args, next| {
+                    if meta.name() == "log" {
+                        return Err(Trap::new("denied"));
+                    }
+                    next(caller, args)
+                });
+            }
+            imports
+        };
+
+        let mut instance = add_log_module()
+            .instantiate_with_imports(build(false))
+            .unwrap();
+        let result = instance
+            .execute("run", &[TypedValue::U32(2), TypedValue::U32(3)])
+            .unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(5)));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [("env.log".to_string(), 1), ("env.add".to_string(), 2)]
+        );
+
+        // The counting middleware sees the call denied by the next one.
+        calls.lock().unwrap().clear();
+        let mut instance = add_log_module()
+            .instantiate_with_imports(build(true))
+            .unwrap();
+        let result = instance
+            .execute("run", &[TypedValue::U32(2), TypedValue::U32(3)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(instance.take_host_trap(), Some(Trap::new("denied")));
+        assert_eq!(*calls.lock().unwrap(), [("env.log".to_string(), 1)]);
+
+        // The arguments passed on must match the type of the function.
+        let mut imports = build(false);
+        imports.middleware(|_, caller, _, next| next(caller, &[TypedValue::F32(1.0)]));
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+        let result = instance
+            .execute("run", &[TypedValue::U32(2), TypedValue::U32(3)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new(
+                "middleware passed arguments of mismatching types"
+            ))
+        );
+    }
+
+    #[test]
+    fn host_function_mismatching_result() {
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func_with_type(
+                "env",
+                "add",
+                FunctionType::new(vec![ValueType::I32, ValueType::I32], Some(ValueType::I32)),
+                |_, _| Ok(Some(TypedValue::U64(0))),
+            )
+            .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                Ok(())
+            });
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+
+        let result = instance
+            .execute("run", &[TypedValue::U32(0), TypedValue::U32(0)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new(
+                "host function returned a value of mismatching type"
+            ))
+        );
+    }
+
+    #[test]
+    fn missing_and_mismatching_imports() {
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "add",
+            |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+        );
+        assert_eq!(
+            add_log_module()
+                .instantiate_with_imports(imports)
+                .err()
+                .unwrap(),
+            Error::MissingImport {
+                module: "env".to_string(),
+                name: "log".to_string(),
+                kind: ExternalKind::Function,
+            }
+        );
+
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func(
+                "env",
+                "add",
+                |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+            )
+            .func("env", "log", |_: &mut Caller, _: u32| -> Result<(), Trap> {
+                Ok(())
+            });
+        assert_eq!(
+            add_log_module()
+                .instantiate_with_imports(imports)
+                .err()
+                .unwrap(),
+            Error::InstantiationFailed(
+                "function env.log input types don't match imported function in module".to_string()
+            )
+        );
+
+        let mut imports = ImportsBuilder::new();
+        imports.func("env\0", "add", |_: &mut Caller| -> Result<(), Trap> {
+            Ok(())
+        });
+        assert_eq!(
+            add_log_module()
+                .instantiate_with_imports(imports)
+                .err()
+                .unwrap(),
+            Error::InstantiationFailed("import name contains a NUL byte".to_string())
+        );
+    }
+
+    /// The imports of the cases checked against both direct instantiation and pre-instantiation.
+    fn imports_case(case: usize) -> ImportsBuilder {
+        let mut imports = ImportsBuilder::new();
+        match case {
+            0 => {
+                imports
+                    .func(
+                        "env",
+                        "add",
+                        |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+                    )
+                    .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                        Ok(())
+                    });
+            }
+            1 => {
+                imports.func(
+                    "env",
+                    "add",
+                    |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+                );
+            }
+            2 => {
+                imports
+                    .func(
+                        "env",
+                        "add",
+                        |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+                    )
+                    .func("env", "log", |_: &mut Caller, _: u32| -> Result<(), Trap> {
+                        Ok(())
+                    });
+            }
+            3 => {
+                imports
+                    .func(
+                        "env",
+                        "add",
+                        |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+                    )
+                    .func(
+                        "env",
+                        "log",
+                        |_: &mut Caller, _: u64| -> Result<u32, Trap> { Ok(0) },
+                    );
+            }
+            4 => {
+                imports
+                    .func(
+                        "env",
+                        "add",
+                        |_: &mut Caller, a: u32, b: u32| -> Result<u64, Trap> {
+                            Ok((a + b).into())
+                        },
+                    )
+                    .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                        Ok(())
+                    });
+            }
+            5 => {
+                imports
+                    .func(
+                        "env",
+                        "add",
+                        |_: &mut Caller, _: u32, _: u32| -> Result<(), Trap> { Ok(()) },
+                    )
+                    .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                        Ok(())
+                    });
+            }
+            6 => {
+                imports.func("env\0", "add", |_: &mut Caller| -> Result<(), Trap> {
+                    Ok(())
+                });
+            }
+            _ => unreachable!(),
+        }
+        imports
+    }
+
+    #[test]
+    fn pre_instantiate_same_as_instantiate() {
+        for case in 0..7 {
+            let direct = add_log_module()
+                .instantiate_with_imports(imports_case(case))
+                .err();
+            let pre = add_log_module()
+                .pre_instantiate(imports_case(case))
+                .and_then(|pre| pre.instantiate())
+                .err();
+            assert_eq!(pre, direct, "case {}", case);
+        }
+        assert_eq!(
+            add_log_module()
+                .pre_instantiate(imports_case(5))
+                .err()
+                .unwrap(),
+            Error::InstantiationFailed(
+                "function env.add output type doesn't match imported function in module"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn pre_instantiate_shared_host_functions() {
+        let calls = Arc::new(Mutex::new(0));
+        let calls_clone = calls.clone();
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func(
+                "env",
+                "add",
+                |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> {
+                    if a == 0 {
+                        return Err(Trap::new("zero"));
+                    }
+                    Ok(a + b)
+                },
+            )
+            .func(
+                "env",
+                "log",
+                move |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                    *calls_clone.lock().unwrap() += 1;
+                    Ok(())
+                },
+            );
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+        let pre = Arc::new(add_log_module().pre_instantiate(imports).unwrap());
+        assert_send_sync(&pre);
+
+        let mut first = pre.instantiate().unwrap();
+        let mut second = pre.instantiate().unwrap();
+        let result = first
+            .execute("run", &[TypedValue::U32(0), TypedValue::U32(1)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(first.take_host_trap(), Some(Trap::new("zero")));
+        let result = second
+            .execute("run", &[TypedValue::U32(2), TypedValue::U32(3)])
+            .unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(5)));
+        assert_eq!(second.take_host_trap(), None);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let pre = pre.clone();
+                std::thread::spawn(move || {
+                    let mut instance = pre.instantiate().unwrap();
+                    for _ in 0..100 {
+                        let result = instance
+                            .execute("run", &[TypedValue::U32(1), TypedValue::U32(1)])
+                            .unwrap();
+                        assert_eq!(result.value(), Some(TypedValue::U32(2)));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*calls.lock().unwrap(), 402);
+    }
+
+    #[test]
+    fn caller_execute() {
+        /* wat2wasm
+        (module
+          (func $reenter (import "env" "reenter") (param i32))
+          (func $rec (export "rec") (param i32)
+            (if (local.get 0) (then (call $rec (i32.sub (local.get 0) (i32.const 1))))))
+          (func (export "via_host") (param i32) (call $reenter (local.get 0)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001050160017f00020f0103656e76077265656e74657200000303020000071202037265630001087669615f686f737400020a17020e0020000440200041016b10010b0b0600200010000b").unwrap();
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let mut imports = ImportsBuilder::new();
+        let log = errors.clone();
+        // The host function executes rec(n), or itself again for 0.
+        imports.func(
+            "env",
+            "reenter",
+            move |caller: &mut Caller, n: u32| -> Result<(), Trap> {
+                let name = if n == 0 { "via_host" } else { "rec" };
+                match caller.execute(name, &[TypedValue::U32(n)]) {
+                    Ok(value) => {
+                        assert_eq!(value, None);
+                        Ok(())
+                    }
+                    Err(err) => {
+                        log.lock().unwrap().push(err.clone());
+                        Err(Trap::new(err.to_string()))
+                    }
+                }
+            },
+        );
+        let options = crate::InstantiateOptions::new()
+            .imports(imports)
+            .max_call_depth(10);
+        let mut instance = parse(&input).unwrap().instantiate_with(&options).unwrap();
+
+        // The calling function and rec(n) stack up n + 2 calls.
+        let result = instance.execute("via_host", &[TypedValue::U32(8)]).unwrap();
+        assert!(!result.trapped());
+        let result = instance.execute("via_host", &[TypedValue::U32(9)]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            errors.lock().unwrap().pop(),
+            Some(Error::Trapped(TrapInfo::new(
+                "rec",
+                Some(Trap::call_depth_exceeded())
+            )))
+        );
+        // The trap of the host function is reported.
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new("trap in function rec: call depth exceeded"))
+        );
+
+        let result = instance.execute("via_host", &[TypedValue::U32(0)]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            errors.lock().unwrap().pop().unwrap().to_string(),
+            "trap in function via_host: host function env.reenter called recursively"
+        );
+    }
+
+    #[test]
+    fn caller_depth() {
+        /* wat2wasm
+        (module
+          (func $record (import "env" "record"))
+          (func $nest (import "env" "nest"))
+          (func $direct (export "direct") (call $record))
+          (func (export "nested") (call $direct))
+          (func (export "reenter") (call $nest))
+        )
+        */
+        let input = hex::decode("0061736d0100000001040160000002190203656e76067265636f7264000003656e76046e6573740000030403000000071d03066469726563740002066e65737465640003077265656e74657200040a1003040010000b040010020b040010010b").unwrap();
+        let depths = Arc::new(Mutex::new(Vec::new()));
+        let mut imports = ImportsBuilder::new();
+        let recorded = depths.clone();
+        imports.func(
+            "env",
+            "record",
+            move |caller: &mut Caller| -> Result<(), Trap> {
+                recorded.lock().unwrap().push(caller.depth());
+                Ok(())
+            },
+        );
+        let recorded = depths.clone();
+        // The host function records its depth, then executes nested() within its execution.
+        imports.func(
+            "env",
+            "nest",
+            move |caller: &mut Caller| -> Result<(), Trap> {
+                recorded.lock().unwrap().push(caller.depth());
+                caller
+                    .execute("nested", &[])
+                    .map_err(|err| Trap::new(err.to_string()))?;
+                Ok(())
+            },
+        );
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let mut depths_of = |name: &str, options: &crate::ExecutionOptions| {
+            let result = instance.execute_with_options(name, &[], options).unwrap();
+            assert!(!result.trapped());
+            std::mem::take(&mut *depths.lock().unwrap())
+        };
+
+        let options = crate::ExecutionOptions::new();
+        assert_eq!(depths_of("direct", &options), [1]);
+        assert_eq!(depths_of("nested", &options), [2]);
+        assert_eq!(depths_of("reenter", &options), [1, 3]);
+        // The depth does not depend on the limit, nor on the tracing of the execution.
+        let options = crate::ExecutionOptions::new().max_call_depth(4);
+        assert_eq!(depths_of("reenter", &options), [1, 3]);
+        let options = options.collect_stats(true);
+        assert_eq!(depths_of("nested", &options), [2]);
+    }
+
+    #[test]
+    fn dynamic_imports_from_manifest() {
+        /* wat2wasm
+        (module
+          (func $add (import "env" "add") (param i32 i32) (result i32))
+          (func $scale (import "env" "scale") (param f64 f32) (result f64))
+          (func $log (import "log" "record") (param i64))
+          (func (export "run") (param i32) (result f64)
+            (call $log (i64.const -1))
+            (call $scale
+              (f64.convert_i32_s (call $add (local.get 0) (i32.const 2)))
+              (f32.const 0.5)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001160460027f7f017f60027c7d017c60017e0060017f017c02240303656e7603616464000003656e76057363616c650001036c6f67067265636f72640002030201030707010372756e00030a16011400427f1002200041021000b7430000003f10010b").unwrap();
+        let manifest = "env add (i32, i32) -> i32\nenv scale F(Ff)\nlog record v(I)";
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut imports = ImportsBuilder::new();
+        for line in manifest.lines() {
+            let fields: Vec<&str> = line.splitn(3, ' ').collect();
+            let recorded = records.clone();
+            // The arguments are typed by the signature in the manifest.
+            let func: DynHostFn = match fields[1] {
+                "add" => Box::new(|_, args| match args {
+                    [TypedValue::U32(a), TypedValue::U32(b)] => Ok(Some(TypedValue::U32(a + b))),
+                    _ => Err(Trap::new("unexpected arguments")),
+                }),
+                "scale" => Box::new(|_, args| match args {
+                    [TypedValue::F64(a), TypedValue::F32(b)] => {
+                        Ok(Some(TypedValue::F64(a * f64::from(*b))))
+                    }
+                    _ => Err(Trap::new("unexpected arguments")),
+                }),
+                _ => Box::new(move |_, args| {
+                    recorded.lock().unwrap().extend_from_slice(args);
+                    Ok(None)
+                }),
+            };
+            imports
+                .func_dyn(fields[0], fields[1], fields[2], func)
+                .unwrap();
+        }
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let result = instance.execute("run", &[TypedValue::U32(5)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::F64(3.5)));
+        assert_eq!(*records.lock().unwrap(), [TypedValue::U64(u64::MAX)]);
+
+        let mut imports = ImportsBuilder::new();
+        let result = imports.func_dyn("env", "add", "i(ix)", Box::new(|_, _| Ok(None)));
+        assert_eq!(
+            result.err(),
+            Some(Error::Other(
+                "invalid function signature \"i(ix)\": unknown value type 'x'".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn busy() {
+        /* wat2wasm
+        (module
+          (func $host (import "env" "host") (result i32))
+          (func (export "run") (result i32) (call $host))
+          (func (export "answer") (result i32) (i32.const 42))
+        )
+        */
+        let input = hex::decode("0061736d010000000105016000017f020c0103656e7604686f7374000003030200000710020372756e000106616e7377657200020a0b02040010000b0400412a0b").unwrap();
+        let instance_ptr = Arc::new(std::sync::atomic::AtomicPtr::<Instance>::default());
+        let mut imports = ImportsBuilder::new();
+        let ptr = instance_ptr.clone();
+        imports.func(
+            "env",
+            "host",
+            move |caller: &mut Caller| -> Result<u32, Trap> {
+                // The instance reached by a pointer stashed by the host, which is aliased unsoundly.
+                let instance = unsafe { &mut *ptr.load(std::sync::atomic::Ordering::SeqCst) };
+                assert_eq!(instance.execute("answer", &[]).err(), Some(Error::Busy));
+                // The nested execution is allowed.
+                match caller.execute("answer", &[]) {
+                    Ok(Some(TypedValue::U32(value))) => Ok(value + 1),
+                    other => panic!("unexpected result: {:?}", other),
+                }
+            },
+        );
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        instance_ptr.store(&mut instance, std::sync::atomic::Ordering::SeqCst);
+
+        let result = instance.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(43)));
+        // The instance is not busy anymore.
+        let result = instance.execute("answer", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(42)));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Rejected {
+        code: u32,
+        retryable: bool,
+    }
+
+    impl fmt::Display for Rejected {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "rejected with code {}", self.code)
+        }
+    }
+
+    impl std::error::Error for Rejected {}
+
+    impl HostError for Rejected {}
+
+    #[test]
+    fn host_errors() {
+        /* wat2wasm
+        (module
+          (func $check (import "env" "check") (param i32))
+          (func $outer (import "env" "outer") (param i32))
+          (func (export "run") (param i32) (call $check (local.get 0)))
+          (func (export "nested") (param i32) (call $outer (local.get 0)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001050160017f0002190203656e7605636865636b000003656e76056f75746572000003030200000710020372756e0002066e657374656400030a0f020600200010000b0600200010010b").unwrap();
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "check",
+            |_: &mut Caller, code: u32| -> Result<(), Trap> {
+                if code == 0 {
+                    return Ok(());
+                }
+                Err(Trap::from_host_error(Rejected {
+                    code,
+                    retryable: code < 10,
+                }))
+            },
+        );
+        // The host function traps with its own error after the nested execution has trapped.
+        imports.func(
+            "env",
+            "outer",
+            |caller: &mut Caller, code: u32| -> Result<(), Trap> {
+                let err = caller.execute("run", &[TypedValue::U32(code)]).unwrap_err();
+                let inner = err.host_error_downcast::<Rejected>().unwrap();
+                Err(Trap::from_host_error(Rejected {
+                    code: inner.code + 100,
+                    retryable: false,
+                }))
+            },
+        );
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let mut call = |name: &str, code: u32| {
+            crate::engine::InstanceApi::call(&mut instance, name, &[TypedValue::U32(code)])
+        };
+
+        assert_eq!(call("run", 0), Ok(None));
+        let err = call("run", 4).unwrap_err();
+        assert_eq!(
+            err.host_error_downcast::<Rejected>(),
+            Some(&Rejected {
+                code: 4,
+                retryable: true
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "trap in function run: rejected with code 4"
+        );
+        assert_eq!(
+            std::error::Error::source(&err).map(|source| source.to_string()),
+            Some("rejected with code 4".to_string())
+        );
+
+        let err = call("nested", 3).unwrap_err();
+        assert_eq!(
+            err.host_error_downcast::<Rejected>(),
+            Some(&Rejected {
+                code: 103,
+                retryable: false
+            })
+        );
+
+        // The traps without host errors carry none.
+        let err = Error::Trapped(TrapInfo::new("run", Some(Trap::new("plain"))));
+        assert!(err.host_error_downcast::<Rejected>().is_none());
+    }
+
+    #[test]
+    fn extra_imports() {
+        let build = |mode: ExtraImports| {
+            let mut imports = ImportsBuilder::new();
+            imports
+                .extra_imports(mode)
+                .func(
+                    "env",
+                    "add",
+                    |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+                )
+                .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                    Ok(())
+                });
+            for i in 0..8 {
+                imports.func(
+                    "env",
+                    &format!("unused{}", i),
+                    |_: &mut Caller| -> Result<(), Trap> { Ok(()) },
+                );
+            }
+            imports
+        };
+
+        let report = build(ExtraImports::Ignore).check_imports(&add_log_module());
+        assert!(report.missing().is_empty());
+        assert!(report.mismatched().is_empty());
+        let unused: Vec<String> = (0..8).map(|i| format!("env.unused{}", i)).collect();
+        assert_eq!(report.unused(), &unused[..]);
+
+        let mut instance = add_log_module()
+            .instantiate_with_imports(build(ExtraImports::Ignore))
+            .unwrap();
+        let result = instance
+            .execute("run", &[TypedValue::U32(1), TypedValue::U32(2)])
+            .unwrap();
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 3);
+
+        assert_eq!(
+            add_log_module()
+                .instantiate_with_imports(build(ExtraImports::Error))
+                .err()
+                .unwrap(),
+            Error::InstantiationFailed(
+                "host function env.unused0 is not imported by the module".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn aliased_modules() {
+        let mut imports = ImportsBuilder::new();
+        imports
+            .alias_module("env", "wasi_unstable")
+            .func(
+                "wasi_unstable",
+                "add",
+                |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+            )
+            // The functions registered under the module itself take precedence.
+            .func(
+                "wasi_unstable",
+                "log",
+                |_: &mut Caller, _: u32| -> Result<(), Trap> { Ok(()) },
+            )
+            .func("env", "log", |_: &mut Caller, _: u64| -> Result<(), Trap> {
+                Ok(())
+            });
+
+        let report = imports.check_imports(&add_log_module());
+        assert!(report.missing().is_empty());
+        assert!(report.mismatched().is_empty());
+        assert_eq!(report.unused(), ["wasi_unstable.log".to_string()]);
+
+        let mut instance = add_log_module().instantiate_with_imports(imports).unwrap();
+        let result = instance
+            .execute("run", &[TypedValue::U32(20), TypedValue::U32(22)])
+            .unwrap();
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 42);
+
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "wasi_unstable",
+            "add",
+            |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+        );
+        let report = imports.check_imports(&add_log_module());
+        assert_eq!(
+            report.missing(),
+            ["env.add".to_string(), "env.log".to_string()]
+        );
+    }
+}
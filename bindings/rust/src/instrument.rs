@@ -0,0 +1,881 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rewriting of module binaries to meter their execution.
+//!
+//! The instrumented module charges the costs of instructions to a gas global at the start of each
+//! basic block, i.e. of each sequence of instructions which are either all executed or none of them
+//! (unless one of them traps), and traps when they exceed the remaining gas. The costs of blocks are
+//! charged before executing them, therefore the execution stops at the first block which does not fit.
+//!
+//! The globals and a local of each function are appended, so the indices of the existing ones do not
+//! change.
+
+use crate::opcodes::{self, Immediates};
+use crate::{validate, CostSchedule, Error};
+
+use std::collections::{BTreeMap, HashMap};
+
+const CUSTOM_SECTION: u8 = 0;
+pub(crate) const TYPE_SECTION: u8 = 1;
+pub(crate) const IMPORT_SECTION: u8 = 2;
+pub(crate) const FUNCTION_SECTION: u8 = 3;
+pub(crate) const TABLE_SECTION: u8 = 4;
+pub(crate) const MEMORY_SECTION: u8 = 5;
+pub(crate) const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+pub(crate) const START_SECTION: u8 = 8;
+pub(crate) const CODE_SECTION: u8 = 10;
+pub(crate) const DATA_SECTION: u8 = 11;
+
+/// The exported mutable i64 global of the remaining ticks.
+pub(crate) const GAS_EXPORT: &str = "fizzy:metering:gas";
+/// The exported mutable i32 global set to 1 when the execution has run out of ticks.
+pub(crate) const EXHAUSTED_EXPORT: &str = "fizzy:metering:exhausted";
+/// The prefix of the exported mutable i64 globals of the costs of instructions, followed by their names.
+pub(crate) const COST_EXPORT_PREFIX: &str = "fizzy:metering:cost:";
+
+/// True if `name` is an export added by [`add_dynamic_metering`].
+pub(crate) fn is_metering_export(name: &str) -> bool {
+    name.starts_with("fizzy:metering:")
+}
+
+/// The names of functions in the name section of a valid module, by their indices.
+///
+/// The name section is optional and not validated by the engine, therefore it is ignored if malformed.
+pub(crate) fn function_names(input: &[u8]) -> HashMap<u32, String> {
+    let read = || -> Result<HashMap<u32, String>, Error> {
+        let mut names = HashMap::new();
+        let mut reader = Reader::new(input.get(8..).unwrap_or_default());
+        while !reader.is_empty() {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let mut section = Reader::new(reader.bytes(size)?);
+            if id != CUSTOM_SECTION || section.name()? != b"name" {
+                continue;
+            }
+            while !section.is_empty() {
+                let subsection_id = section.u8()?;
+                let size = section.u32()? as usize;
+                let mut subsection = Reader::new(section.bytes(size)?);
+                // The subsection of function names.
+                if subsection_id != 1 {
+                    continue;
+                }
+                for _ in 0..subsection.u32()? {
+                    let func_idx = subsection.u32()?;
+                    if let Ok(name) = std::str::from_utf8(subsection.name()?) {
+                        names.insert(func_idx, name.to_string());
+                    }
+                }
+            }
+        }
+        Ok(names)
+    };
+    read().unwrap_or_default()
+}
+
+/// Call `f` with the id, the whole encoding and the content of each section of a valid module.
+fn for_each_section(input: &[u8], mut f: impl FnMut(u8, &[u8], &[u8])) {
+    let body = input.get(8..).unwrap_or_default();
+    let mut reader = Reader::new(body);
+    let mut next = |reader: &mut Reader| -> Result<(), Error> {
+        let start = reader.position();
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let content = reader.bytes(size)?;
+        f(id, &body[start..reader.position()], content);
+        Ok(())
+    };
+    // The module has been validated, therefore the sections are well-formed.
+    while !reader.is_empty() && next(&mut reader).is_ok() {}
+}
+
+/// The binary of a valid module without its custom sections.
+pub(crate) fn strip_custom_sections(input: &[u8]) -> Vec<u8> {
+    let mut stripped = input[..input.len().min(8)].to_vec();
+    for_each_section(input, |id, section, _| {
+        if id != CUSTOM_SECTION {
+            stripped.extend_from_slice(section);
+        }
+    });
+    stripped
+}
+
+/// The names and the sizes of the payloads of the custom sections of a valid module, in their
+/// order.
+pub(crate) fn custom_sections(input: &[u8]) -> Vec<(String, usize)> {
+    let mut custom = Vec::new();
+    for_each_section(input, |id, _, content| {
+        if id == CUSTOM_SECTION {
+            let mut content = Reader::new(content);
+            if let Ok(name) = content.name() {
+                let name = String::from_utf8_lossy(name).into_owned();
+                custom.push((name, content.rest().len()));
+            }
+        }
+    });
+    custom
+}
+
+/// Validate the input according to WebAssembly 1.0 rules, and rewrite it to charge the costs of
+/// instructions in `schedule` to the mutable i64 global exported as `gas`.
+///
+/// The gas is an unsigned number of ticks, initially 0, which the host has to set before executing
+/// a function. The execution traps with `unreachable` when the costs of a block exceed the remaining
+/// gas, which is not changed then. The instrumented module does not depend on Fizzy, and runs in any
+/// engine supporting exported mutable globals.
+///
+/// Fails if the module already exports `gas`.
+pub fn add_metering(input: &[u8], schedule: &CostSchedule) -> Result<Vec<u8>, Error> {
+    validate(input)?;
+    let module = RawModule::read(input)?;
+    if module.export_names()?.iter().any(|name| *name == b"gas") {
+        return Err(Error::Other("the module already exports gas".to_string()));
+    }
+
+    let functions = module.function_bodies()?;
+    let gas_global = module.global_count()?;
+    // (global (mut i64) (i64.const 0))
+    let new_globals = [0x7e, 0x01, 0x42, 0x00, 0x0b];
+    let mut new_exports = Vec::new();
+    write_global_export(&mut new_exports, "gas", gas_global);
+
+    let code = rewrite_code(&functions, gas_global, None, |body, segment| {
+        let cost = segment.counts.iter().fold(0u64, |sum, (opcode, count)| {
+            let name = opcodes::by_code(*opcode)
+                .expect("decoded opcode is unknown")
+                .name;
+            let cost = schedule
+                .cost(name)
+                .expect("cost schedule does not cover an instruction");
+            sum.saturating_add(cost.saturating_mul(u64::from(*count)))
+        });
+        if cost == 0 {
+            return false;
+        }
+        body.push(0x42); // i64.const
+        write_i64(body, cost as i64);
+        true
+    });
+    assemble(
+        input,
+        module.sections.clone(),
+        (1, &new_globals),
+        (1, &new_exports),
+        &code,
+    )
+}
+
+/// Rewrite `input` to charge the costs of instructions to the gas global, exported as [`GAS_EXPORT`].
+///
+/// The costs are read from the globals of the instructions used by the module, exported with
+/// [`COST_EXPORT_PREFIX`], which are zero until set by the host. The gas is initially `u64::MAX`,
+/// and the global exported as [`EXHAUSTED_EXPORT`] is set to 1 before trapping when exhausted.
+pub(crate) fn add_dynamic_metering(input: &[u8]) -> Result<Vec<u8>, Error> {
+    let module = RawModule::read(input)?;
+
+    let functions = module.function_bodies()?;
+    let mut used = BTreeMap::new();
+    for function in &functions {
+        for segment in &function.segments {
+            for opcode in segment.counts.keys() {
+                used.insert(*opcode, 0);
+            }
+        }
+    }
+    let globals = module.global_count()?;
+    let gas_global = globals;
+    let exhausted_global = globals + 1;
+    for (index, cost_global) in used.values_mut().enumerate() {
+        *cost_global = globals + 2 + index as u32;
+    }
+
+    let mut new_globals = Vec::new();
+    // (global (mut i64) (i64.const -1))
+    new_globals.extend_from_slice(&[0x7e, 0x01, 0x42, 0x7f, 0x0b]);
+    // (global (mut i32) (i32.const 0))
+    new_globals.extend_from_slice(&[0x7f, 0x01, 0x41, 0x00, 0x0b]);
+    for _ in 0..used.len() {
+        // (global (mut i64) (i64.const 0))
+        new_globals.extend_from_slice(&[0x7e, 0x01, 0x42, 0x00, 0x0b]);
+    }
+
+    let mut new_exports = Vec::new();
+    write_global_export(&mut new_exports, GAS_EXPORT, gas_global);
+    write_global_export(&mut new_exports, EXHAUSTED_EXPORT, exhausted_global);
+    for (opcode, cost_global) in &used {
+        let name = opcodes::by_code(*opcode)
+            .expect("decoded opcode is unknown")
+            .name;
+        write_global_export(
+            &mut new_exports,
+            &format!("{}{}", COST_EXPORT_PREFIX, name),
+            *cost_global,
+        );
+    }
+
+    let code = rewrite_code(
+        &functions,
+        gas_global,
+        Some(exhausted_global),
+        |body, segment| {
+            let mut first = true;
+            for (opcode, count) in &segment.counts {
+                write_global_get(body, used[opcode]);
+                if *count > 1 {
+                    body.push(0x42); // i64.const
+                    write_i64(body, i64::from(*count));
+                    body.push(0x7e); // i64.mul
+                }
+                if !first {
+                    body.push(0x7c); // i64.add
+                }
+                first = false;
+            }
+            !first
+        },
+    );
+    let count = 2 + used.len() as u32;
+    assemble(
+        input,
+        module.sections.clone(),
+        (count, &new_globals),
+        (count, &new_exports),
+        &code,
+    )
+}
+
+/// Encode the code section of `functions`, with the instructions of `write_cost` pushing the cost
+/// of each segment followed by the charge of it. Segments for which `write_cost` returns false
+/// are not charged.
+fn rewrite_code(
+    functions: &[FunctionBody],
+    gas_global: u32,
+    exhausted_global: Option<u32>,
+    mut write_cost: impl FnMut(&mut Vec<u8>, &Segment) -> bool,
+) -> Vec<u8> {
+    let mut code = Vec::new();
+    write_u32(&mut code, functions.len() as u32);
+    for function in functions {
+        let mut body = Vec::new();
+        write_u32(&mut body, function.local_entries + 1);
+        body.extend_from_slice(function.locals);
+        // The temporary local of the costs of blocks.
+        body.extend_from_slice(&[0x01, 0x7e]);
+        for segment in &function.segments {
+            if write_cost(&mut body, segment) {
+                write_charge(&mut body, function.cost_local, gas_global, exhausted_global);
+            }
+            body.extend_from_slice(segment.code);
+        }
+        write_u32(&mut code, body.len() as u32);
+        code.extend_from_slice(&body);
+    }
+    code
+}
+
+/// Encode the module of `sections` with the counts and encodings of appended globals and exports,
+/// and the rewritten `code` section.
+fn assemble(
+    input: &[u8],
+    mut sections: Vec<(u8, Vec<u8>)>,
+    (global_count, globals): (u32, &[u8]),
+    (export_count, exports): (u32, &[u8]),
+    code: &[u8],
+) -> Result<Vec<u8>, Error> {
+    append_entries(&mut sections, GLOBAL_SECTION, global_count, globals)?;
+    append_entries(&mut sections, EXPORT_SECTION, export_count, exports)?;
+    let mut output = input[..8].to_vec();
+    for (id, payload) in &sections {
+        if *id == CODE_SECTION {
+            write_section(&mut output, *id, code);
+        } else {
+            write_section(&mut output, *id, payload);
+        }
+    }
+    Ok(output)
+}
+
+/// Write the instructions subtracting the cost on the stack from the gas global, or trapping
+/// if it exceeds the gas, after setting the exhausted global if any.
+fn write_charge(
+    out: &mut Vec<u8>,
+    cost_local: u32,
+    gas_global: u32,
+    exhausted_global: Option<u32>,
+) {
+    out.push(0x22); // local.tee
+    write_u32(out, cost_local);
+    write_global_get(out, gas_global);
+    out.push(0x56); // i64.gt_u
+    out.extend_from_slice(&[0x04, 0x40]); // if
+    if let Some(exhausted_global) = exhausted_global {
+        out.extend_from_slice(&[0x41, 0x01]); // i32.const 1
+        out.push(0x24); // global.set
+        write_u32(out, exhausted_global);
+    }
+    out.push(0x00); // unreachable
+    out.push(0x0b); // end
+    write_global_get(out, gas_global);
+    out.push(0x20); // local.get
+    write_u32(out, cost_local);
+    out.push(0x7d); // i64.sub
+    out.push(0x24); // global.set
+    write_u32(out, gas_global);
+}
+
+fn write_global_get(out: &mut Vec<u8>, global: u32) {
+    out.push(0x23);
+    write_u32(out, global);
+}
+
+fn write_global_export(out: &mut Vec<u8>, name: &str, global: u32) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+    out.push(0x03);
+    write_u32(out, global);
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    out.push(id);
+    write_u32(out, payload.len() as u32);
+    out.extend_from_slice(payload);
+}
+
+/// Append `count` encoded `entries` to the vector of the section `id`, adding the section in order
+/// if it is missing.
+fn append_entries(
+    sections: &mut Vec<(u8, Vec<u8>)>,
+    id: u8,
+    count: u32,
+    entries: &[u8],
+) -> Result<(), Error> {
+    let (index, existing, rest) = match sections.iter().position(|(section, _)| *section == id) {
+        Some(index) => {
+            let mut reader = Reader::new(&sections[index].1);
+            let existing = reader.u32()?;
+            (index, existing, reader.rest().to_vec())
+        }
+        None => {
+            // Custom sections are not ordered.
+            let index = sections
+                .iter()
+                .position(|(section, _)| *section != 0 && *section > id)
+                .unwrap_or(sections.len());
+            sections.insert(index, (id, Vec::new()));
+            (index, 0, Vec::new())
+        }
+    };
+    let mut payload = Vec::new();
+    write_u32(
+        &mut payload,
+        existing
+            .checked_add(count)
+            .ok_or_else(|| Error::MalformedModule("too many entries".to_string()))?,
+    );
+    payload.extend_from_slice(&rest);
+    payload.extend_from_slice(entries);
+    sections[index].1 = payload;
+    Ok(())
+}
+
+/// The sections of a module, by their ids and payloads.
+struct RawModule {
+    sections: Vec<(u8, Vec<u8>)>,
+}
+
+/// A function body split into segments of straight-line code.
+struct FunctionBody<'a> {
+    local_entries: u32,
+    /// The encoded entries of locals, without their count.
+    locals: &'a [u8],
+    /// The index of the appended local.
+    cost_local: u32,
+    segments: Vec<Segment<'a>>,
+}
+
+/// Instructions which are either all executed or none of them, unless one of them traps.
+struct Segment<'a> {
+    code: &'a [u8],
+    /// The number of times each opcode occurs in `code`.
+    counts: BTreeMap<u8, u32>,
+}
+
+impl RawModule {
+    fn read(input: &[u8]) -> Result<Self, Error> {
+        if input.len() < 8 || input[..4] != [0x00, 0x61, 0x73, 0x6d] {
+            return Err(Error::MalformedModule(
+                "invalid wasm module prefix".to_string(),
+            ));
+        }
+        let mut reader = Reader::new(&input[8..]);
+        let mut sections = Vec::new();
+        while !reader.is_empty() {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            sections.push((id, reader.bytes(size)?.to_vec()));
+        }
+        Ok(RawModule { sections })
+    }
+
+    fn section(&self, id: u8) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|(section, _)| *section == id)
+            .map(|(_, payload)| payload.as_slice())
+    }
+
+    /// The names of the exports.
+    fn export_names(&self) -> Result<Vec<&[u8]>, Error> {
+        let mut names = Vec::new();
+        if let Some(payload) = self.section(EXPORT_SECTION) {
+            let mut reader = Reader::new(payload);
+            for _ in 0..reader.u32()? {
+                names.push(reader.name()?);
+                reader.u8()?;
+                reader.u32()?;
+            }
+        }
+        Ok(names)
+    }
+
+    /// The number of imported and defined globals.
+    fn global_count(&self) -> Result<u32, Error> {
+        let mut count = 0;
+        if let Some(payload) = self.section(IMPORT_SECTION) {
+            let mut reader = Reader::new(payload);
+            for _ in 0..reader.u32()? {
+                reader.name()?;
+                reader.name()?;
+                match reader.u8()? {
+                    0x00 => {
+                        reader.u32()?;
+                    }
+                    0x01 => {
+                        reader.u8()?;
+                        reader.limits()?;
+                    }
+                    0x02 => reader.limits()?,
+                    0x03 => {
+                        reader.bytes(2)?;
+                        count += 1;
+                    }
+                    kind => {
+                        return Err(Error::MalformedModule(format!(
+                            "invalid import kind {}",
+                            kind
+                        )))
+                    }
+                }
+            }
+        }
+        if let Some(payload) = self.section(GLOBAL_SECTION) {
+            count += Reader::new(payload).u32()?;
+        }
+        Ok(count)
+    }
+
+    /// The numbers of parameters of the defined functions.
+    fn param_counts(&self) -> Result<Vec<u32>, Error> {
+        let mut types = Vec::new();
+        if let Some(payload) = self.section(TYPE_SECTION) {
+            let mut reader = Reader::new(payload);
+            for _ in 0..reader.u32()? {
+                reader.u8()?;
+                let params = reader.u32()?;
+                reader.bytes(params as usize)?;
+                let results = reader.u32()?;
+                reader.bytes(results as usize)?;
+                types.push(params);
+            }
+        }
+        let mut params = Vec::new();
+        if let Some(payload) = self.section(FUNCTION_SECTION) {
+            let mut reader = Reader::new(payload);
+            for _ in 0..reader.u32()? {
+                let type_idx = reader.u32()? as usize;
+                params.push(
+                    *types
+                        .get(type_idx)
+                        .ok_or_else(|| Error::MalformedModule("invalid type index".to_string()))?,
+                );
+            }
+        }
+        Ok(params)
+    }
+
+    fn function_bodies(&self) -> Result<Vec<FunctionBody<'_>>, Error> {
+        let params = self.param_counts()?;
+        let payload = match self.section(CODE_SECTION) {
+            Some(payload) => payload,
+            None => return Ok(Vec::new()),
+        };
+        let mut reader = Reader::new(payload);
+        let count = reader.u32()? as usize;
+        if count != params.len() {
+            return Err(Error::MalformedModule(
+                "function and code section have inconsistent lengths".to_string(),
+            ));
+        }
+        params
+            .iter()
+            .map(|params| {
+                let size = reader.u32()? as usize;
+                FunctionBody::read(reader.bytes(size)?, *params)
+            })
+            .collect()
+    }
+}
+
+impl<'a> FunctionBody<'a> {
+    fn read(body: &'a [u8], params: u32) -> Result<Self, Error> {
+        let mut reader = Reader::new(body);
+        let local_entries = reader.u32()?;
+        let locals_start = reader.position();
+        let mut cost_local = params;
+        for _ in 0..local_entries {
+            cost_local = cost_local
+                .checked_add(reader.u32()?)
+                .ok_or_else(|| Error::MalformedModule("too many locals".to_string()))?;
+            reader.u8()?;
+        }
+        let locals = &body[locals_start..reader.position()];
+
+        let mut segments = Vec::new();
+        let mut start = reader.position();
+        let mut counts = BTreeMap::new();
+        while !reader.is_empty() {
+            let code = reader.u8()?;
+            let opcode = opcodes::by_code(code)
+                .ok_or_else(|| Error::MalformedModule(format!("invalid opcode {:#x}", code)))?;
+            reader.immediates(opcode.immediates)?;
+            *counts.entry(code).or_insert(0) += 1;
+            if ends_segment(code) || reader.is_empty() {
+                segments.push(Segment {
+                    code: &body[start..reader.position()],
+                    counts: std::mem::take(&mut counts),
+                });
+                start = reader.position();
+            }
+        }
+        Ok(FunctionBody {
+            local_entries,
+            locals,
+            cost_local,
+            segments,
+        })
+    }
+}
+
+/// True if the instruction of `opcode` may be followed by an instruction other than the next one,
+/// or be the target of a branch.
+pub(crate) fn ends_segment(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        // unreachable, block, loop, if, else
+        0x00 | 0x02..=0x05
+        // end, br, br_if, br_table, return
+        | 0x0b..=0x0f
+    )
+}
+
+/// The reader of the binary encoding of modules.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() - self.position < len {
+            return Err(Error::MalformedModule("unexpected EOF".to_string()));
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Error> {
+        let mut value: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            value |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::MalformedModule(
+            "invalid LEB128 encoding: too many bytes".to_string(),
+        ))
+    }
+
+    /// Read a signed LEB128 number, of at most 10 bytes.
+    pub(crate) fn signed(&mut self) -> Result<i64, Error> {
+        let mut value: i64 = 0;
+        for shift in (0..70).step_by(7) {
+            let byte = self.u8()?;
+            value |= i64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                if shift < 57 && byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return Ok(value);
+            }
+        }
+        Err(Error::MalformedModule(
+            "invalid LEB128 encoding: too many bytes".to_string(),
+        ))
+    }
+
+    /// Skip a signed LEB128 number, of at most 10 bytes.
+    fn skip_signed(&mut self) -> Result<(), Error> {
+        for _ in 0..10 {
+            if self.u8()? & 0x80 == 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::MalformedModule(
+            "invalid LEB128 encoding: too many bytes".to_string(),
+        ))
+    }
+
+    pub(crate) fn name(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub(crate) fn limits(&mut self) -> Result<(), Error> {
+        let has_max = self.u8()? == 0x01;
+        self.u32()?;
+        if has_max {
+            self.u32()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn immediates(&mut self, immediates: Immediates) -> Result<(), Error> {
+        match immediates {
+            Immediates::None => {}
+            Immediates::BlockType | Immediates::MemoryIndex => {
+                self.u8()?;
+            }
+            Immediates::Index => {
+                self.u32()?;
+            }
+            Immediates::BrTable => {
+                let count = self.u32()?;
+                for _ in 0..=count {
+                    self.u32()?;
+                }
+            }
+            Immediates::CallIndirect => {
+                self.u32()?;
+                self.u8()?;
+            }
+            Immediates::MemArg => {
+                self.u32()?;
+                self.u32()?;
+            }
+            Immediates::I32 | Immediates::I64 => self.skip_signed()?,
+            Immediates::F32 => {
+                self.bytes(4)?;
+            }
+            Immediates::F64 => {
+                self.bytes(8)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TypedValue;
+
+    #[test]
+    fn leb128() {
+        let encode_u32 = |value| {
+            let mut out = Vec::new();
+            write_u32(&mut out, value);
+            out
+        };
+        let encode_i64 = |value| {
+            let mut out = Vec::new();
+            write_i64(&mut out, value);
+            out
+        };
+        assert_eq!(encode_u32(0), [0x00]);
+        assert_eq!(encode_u32(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(encode_u32(u32::MAX), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert_eq!(Reader::new(&encode_u32(624485)).u32(), Ok(624485));
+        assert_eq!(encode_i64(-1), [0x7f]);
+        assert_eq!(encode_i64(64), [0xc0, 0x00]);
+        assert_eq!(encode_i64(-123456), [0xc0, 0xbb, 0x78]);
+        assert_eq!(Reader::new(&encode_i64(-123456)).signed(), Ok(-123456));
+        assert_eq!(Reader::new(&encode_i64(i64::MIN)).signed(), Ok(i64::MIN));
+        assert_eq!(Reader::new(&encode_i64(i64::MAX)).signed(), Ok(i64::MAX));
+        assert_eq!(Reader::new(&[0x3f]).signed(), Ok(63));
+        assert!(Reader::new(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00])
+            .u32()
+            .is_err());
+    }
+
+    #[test]
+    fn segments() {
+        /* wat2wasm
+        (module
+          (func (param i32) (result i32)
+            (local i64 i64)
+            (block
+              (br_if 0 (local.get 0))
+              (local.set 0 (i32.add (local.get 0) (i32.const 1)))
+            )
+            (local.get 0)
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f030201000a16011401027e024020000d00200041016a21000b20000b").unwrap();
+        let module = RawModule::read(&input).unwrap();
+        let functions = module.function_bodies().unwrap();
+        assert_eq!(functions.len(), 1);
+        let function = &functions[0];
+        assert_eq!(function.cost_local, 3);
+        let counts: Vec<Vec<(u8, u32)>> = function
+            .segments
+            .iter()
+            .map(|segment| segment.counts.clone().into_iter().collect())
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                vec![(0x02, 1)],
+                vec![(0x0d, 1), (0x20, 1)],
+                vec![(0x0b, 1), (0x20, 1), (0x21, 1), (0x41, 1), (0x6a, 1)],
+                vec![(0x0b, 1), (0x20, 1)],
+            ]
+        );
+
+        let output = add_dynamic_metering(&input).unwrap();
+        assert_eq!(crate::validate(&output), Ok(()));
+    }
+
+    #[test]
+    fn static_metering() {
+        /* wat2wasm
+        (module
+          (global $iterations (export "iterations") (mut i32) (i32.const 0))
+          (func (export "spin")
+            (loop $next
+              (global.set $iterations (i32.add (global.get $iterations) (i32.const 1)))
+              (br $next)
+            )
+          )
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000030201000606017f0141000b0715020a697465726174696f6e730300047370696e00000a10010e000340230041016a24000c000b0b").unwrap();
+        let spin = |schedule: &CostSchedule, gas: u64| {
+            let output = add_metering(&input, schedule).unwrap();
+            assert_eq!(validate(&output), Ok(()));
+            let mut instance = crate::parse(&output).unwrap().instantiate().unwrap();
+            instance
+                .set_global_value("gas", TypedValue::U64(gas))
+                .unwrap();
+            assert!(instance.execute("spin", &[]).unwrap().trapped());
+            (
+                instance.global_value("iterations").unwrap(),
+                instance.global_value("gas").unwrap(),
+            )
+        };
+
+        // The loop instruction, then 5 instructions of each iteration.
+        assert_eq!(
+            spin(&CostSchedule::uniform(1), 1 + 10 * 5 + 4),
+            (TypedValue::U32(10), TypedValue::U64(4))
+        );
+        assert_eq!(
+            spin(&CostSchedule::uniform(1), 1 + 11 * 5),
+            (TypedValue::U32(11), TypedValue::U64(0))
+        );
+        let schedule = CostSchedule::builder()
+            .default_cost(1)
+            .cost("i32.add", 6)
+            .build()
+            .unwrap();
+        assert_eq!(
+            spin(&schedule, 1 + 10 * 10 + 9),
+            (TypedValue::U32(10), TypedValue::U64(9))
+        );
+        assert_eq!(
+            spin(&CostSchedule::uniform(1), 0),
+            (TypedValue::U32(0), TypedValue::U64(0))
+        );
+    }
+
+    #[test]
+    fn static_metering_errors() {
+        let schedule = CostSchedule::uniform(1);
+        assert_eq!(
+            add_metering(
+                &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x0d],
+                &schedule
+            ),
+            Err(Error::MalformedModule("unexpected EOF".to_string()))
+        );
+
+        /* wat2wasm
+        (module (global (export "gas") i32 (i32.const 0)))
+        */
+        let input = hex::decode("0061736d010000000606017f0041000b070701036761730300").unwrap();
+        assert_eq!(
+            add_metering(&input, &schedule),
+            Err(Error::Other("the module already exports gas".to_string()))
+        );
+    }
+}
@@ -0,0 +1,427 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checking the exports of modules against an expected interface.
+
+use crate::instrument::Reader;
+use crate::raw::{FunctionTypeExt, GlobalTypeExt};
+use crate::{
+    sys, Error, ExternalKind, ExternalType, FunctionType, GlobalType, Limits, Module, ValueType,
+};
+
+use std::fmt;
+
+/// The exports expected of a module, by their names and types, see [`Interface::check`].
+///
+/// With the `serde` feature, the interface can be read from a description, e.g. in JSON
+/// `{"functions":[["add",{"inputs":["i32","i32"],"output":"i32"}]],"memories":[["memory",{"min":1,"max":null}]],"globals":[]}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Interface {
+    pub functions: Vec<(String, FunctionType)>,
+    pub memories: Vec<(String, Limits)>,
+    pub globals: Vec<(String, GlobalType)>,
+}
+
+/// A difference between the exports of a module and an [`Interface`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterfaceViolation {
+    /// No item is exported under the name.
+    Missing { name: String, kind: ExternalKind },
+    /// An item of another kind is exported under the name.
+    WrongKind {
+        name: String,
+        expected: ExternalKind,
+        actual: ExternalKind,
+    },
+    /// The exported function has another type.
+    FunctionMismatch {
+        name: String,
+        expected: FunctionType,
+        actual: FunctionType,
+    },
+    /// The exported memory has other limits.
+    MemoryMismatch {
+        name: String,
+        expected: Limits,
+        actual: Limits,
+    },
+    /// The exported global has another type.
+    GlobalMismatch {
+        name: String,
+        expected: GlobalType,
+        actual: GlobalType,
+    },
+}
+
+impl fmt::Display for InterfaceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceViolation::Missing { name, kind } => {
+                write!(f, "missing {} export {:?}", kind_name(*kind), name)
+            }
+            InterfaceViolation::WrongKind {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "export {:?} is a {} instead of a {}",
+                name,
+                kind_name(*actual),
+                kind_name(*expected)
+            ),
+            InterfaceViolation::FunctionMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "function {:?} has the type {} instead of {}",
+                name,
+                function_type(actual),
+                function_type(expected)
+            ),
+            InterfaceViolation::MemoryMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "memory {:?} has the limits {} instead of {}",
+                name,
+                limits(actual),
+                limits(expected)
+            ),
+            InterfaceViolation::GlobalMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "global {:?} has the type {} instead of {}",
+                name,
+                global_type(actual),
+                global_type(expected)
+            ),
+        }
+    }
+}
+
+fn kind_name(kind: ExternalKind) -> &'static str {
+    match kind {
+        ExternalKind::Function => "function",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+    }
+}
+
+/// The function type written as `(i32, i32) -> i32`.
+fn function_type(func_type: &FunctionType) -> String {
+    let inputs: Vec<String> = func_type.inputs.iter().map(ValueType::to_string).collect();
+    match func_type.output {
+        Some(output) => format!("({}) -> {}", inputs.join(", "), output),
+        None => format!("({})", inputs.join(", ")),
+    }
+}
+
+/// The limits written as in the text format, e.g. `1 16`.
+fn limits(limits: &Limits) -> String {
+    match limits.max {
+        Some(max) => format!("{} {}", limits.min, max),
+        None => limits.min.to_string(),
+    }
+}
+
+fn global_type(global_type: &GlobalType) -> String {
+    if global_type.mutable {
+        format!("(mut {})", global_type.value_type)
+    } else {
+        global_type.value_type.to_string()
+    }
+}
+
+impl Interface {
+    /// Check that `module` exports every item of the interface under its name, with the same
+    /// type, or the same limits for memories, returning all the differences found: the missing
+    /// exports and the exports of other kinds first, then the mismatching types.
+    ///
+    /// The module may export more items than the interface, so that adding an export is not a
+    /// breaking change of the interface.
+    pub fn check(&self, module: &Module) -> Result<(), Vec<InterfaceViolation>> {
+        let exports = module.exports();
+        let mut violations = Vec::new();
+        let mut find = |name: &str, expected: ExternalKind| match exports
+            .iter()
+            .find(|export| export.name() == name)
+        {
+            Some(export) if export.kind() == expected => Some(export.index()),
+            Some(export) => {
+                violations.push(InterfaceViolation::WrongKind {
+                    name: name.to_string(),
+                    expected,
+                    actual: export.kind(),
+                });
+                None
+            }
+            None => {
+                violations.push(InterfaceViolation::Missing {
+                    name: name.to_string(),
+                    kind: expected,
+                });
+                None
+            }
+        };
+
+        let mut mismatches = Vec::new();
+        for (name, expected) in &self.functions {
+            if let Some(func_idx) = find(name, ExternalKind::Function) {
+                let actual = unsafe {
+                    FunctionType::from_raw(&sys::fizzy_get_function_type(module.as_ptr(), func_idx))
+                };
+                if actual != *expected {
+                    mismatches.push(InterfaceViolation::FunctionMismatch {
+                        name: name.clone(),
+                        expected: expected.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+        for (name, expected) in &self.memories {
+            if find(name, ExternalKind::Memory).is_some() {
+                let actual = memory_limits(module);
+                if actual != *expected {
+                    mismatches.push(InterfaceViolation::MemoryMismatch {
+                        name: name.clone(),
+                        expected: *expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        for (name, expected) in &self.globals {
+            if let Some(global_idx) = find(name, ExternalKind::Global) {
+                let actual = GlobalType::from_raw(&unsafe {
+                    sys::fizzy_get_global_type(module.as_ptr(), global_idx)
+                });
+                if actual != *expected {
+                    mismatches.push(InterfaceViolation::GlobalMismatch {
+                        name: name.clone(),
+                        expected: *expected,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        violations.extend(mismatches);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// The limits of the memory of `module`, imported or defined, which the C API gives only for
+/// imported memories.
+fn memory_limits(module: &Module) -> Limits {
+    let imported = module
+        .imports()
+        .into_iter()
+        .find_map(|import| match import.ty() {
+            ExternalType::Memory(limits) => Some(*limits),
+            _ => None,
+        });
+    // The module has been validated when parsed, and has at most one memory.
+    imported
+        .or_else(|| defined_memory_limits(&module.bytes()[8..]).expect("malformed module"))
+        .expect("module without memory")
+}
+
+fn defined_memory_limits(bytes: &[u8]) -> Result<Option<Limits>, Error> {
+    let mut reader = Reader::new(bytes);
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader::new(reader.bytes(size)?);
+        if id == 5 && section.u32()? > 0 {
+            let has_max = section.u8()? == 0x01;
+            let min = section.u32()?;
+            let max = if has_max { Some(section.u32()?) } else { None };
+            return Ok(Some(Limits::new(min, max)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /* wat2wasm
+    (module
+      (memory (export "memory") 1 16)
+      (global (export "counter") (mut i32) (i32.const 0))
+      (global (export "version") i64 (i64.const 3))
+      (func (export "add") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1)))
+      (func (export "reset"))
+    )
+    */
+    const CONTRACT_WASM: &str = "0061736d01000000010a0260027f7f017f6000000303020001050401010110060b027f0141000b7e0042030b072c05066d656d6f7279020007636f756e74657203000776657273696f6e030103616464000005726573657400010a0c020700200020016a0b02000b";
+
+    /* wat2wasm
+    (module (memory (import "env" "memory") 2) (export "memory" (memory 0)))
+    */
+    const IMPORTED_MEMORY_WASM: &str =
+        "0061736d01000000020f0103656e76066d656d6f7279020002070a01066d656d6f72790200";
+
+    fn interface() -> Interface {
+        Interface {
+            functions: vec![
+                ("add".to_string(), "(i32, i32) -> i32".parse().unwrap()),
+                ("reset".to_string(), "()".parse().unwrap()),
+            ],
+            memories: vec![("memory".to_string(), Limits::new(1, Some(16)))],
+            globals: vec![
+                ("counter".to_string(), GlobalType::new(ValueType::I32, true)),
+                (
+                    "version".to_string(),
+                    GlobalType::new(ValueType::I64, false),
+                ),
+            ],
+        }
+    }
+
+    fn module() -> Module {
+        parse(&hex::decode(CONTRACT_WASM).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn conforming() {
+        assert_eq!(interface().check(&module()), Ok(()));
+        assert_eq!(Interface::default().check(&module()), Ok(()));
+
+        let module = parse(&hex::decode(IMPORTED_MEMORY_WASM).unwrap()).unwrap();
+        let interface = Interface {
+            memories: vec![("memory".to_string(), Limits::new(2, None))],
+            ..Interface::default()
+        };
+        assert_eq!(interface.check(&module), Ok(()));
+    }
+
+    #[test]
+    fn missing() {
+        let mut interface = interface();
+        interface
+            .functions
+            .push(("sub".to_string(), "(i32, i32) -> i32".parse().unwrap()));
+        let violations = interface.check(&module()).unwrap_err();
+        assert_eq!(
+            violations,
+            [InterfaceViolation::Missing {
+                name: "sub".to_string(),
+                kind: ExternalKind::Function
+            }]
+        );
+        assert_eq!(violations[0].to_string(), "missing function export \"sub\"");
+    }
+
+    #[test]
+    fn wrong_kind() {
+        let mut interface = interface();
+        interface.memories[0].0 = "counter".to_string();
+        let violations = interface.check(&module()).unwrap_err();
+        assert_eq!(
+            violations,
+            [InterfaceViolation::WrongKind {
+                name: "counter".to_string(),
+                expected: ExternalKind::Memory,
+                actual: ExternalKind::Global
+            }]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "export \"counter\" is a global instead of a memory"
+        );
+    }
+
+    #[test]
+    fn mismatches() {
+        let mut interface = interface();
+        interface.functions[0].1 = "(i64, i64) -> i64".parse().unwrap();
+        interface.memories[0].1 = Limits::new(1, None);
+        interface.globals[1].1 = GlobalType::new(ValueType::I64, true);
+        interface.globals.push((
+            "missing".to_string(),
+            GlobalType::new(ValueType::I32, false),
+        ));
+        let violations = interface.check(&module()).unwrap_err();
+        assert_eq!(
+            violations,
+            [
+                InterfaceViolation::Missing {
+                    name: "missing".to_string(),
+                    kind: ExternalKind::Global
+                },
+                InterfaceViolation::FunctionMismatch {
+                    name: "add".to_string(),
+                    expected: "(i64, i64) -> i64".parse().unwrap(),
+                    actual: "(i32, i32) -> i32".parse().unwrap()
+                },
+                InterfaceViolation::MemoryMismatch {
+                    name: "memory".to_string(),
+                    expected: Limits::new(1, None),
+                    actual: Limits::new(1, Some(16))
+                },
+                InterfaceViolation::GlobalMismatch {
+                    name: "version".to_string(),
+                    expected: GlobalType::new(ValueType::I64, true),
+                    actual: GlobalType::new(ValueType::I64, false)
+                },
+            ]
+        );
+        assert_eq!(
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "missing global export \"missing\"",
+                "function \"add\" has the type (i32, i32) -> i32 instead of (i64, i64) -> i64",
+                "memory \"memory\" has the limits 1 16 instead of 1",
+                "global \"version\" has the type i64 instead of (mut i64)",
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn from_json() {
+        let parsed: Interface = serde_json::from_str(
+            r#"{
+                "functions": [
+                    ["add", {"inputs": ["i32", "i32"], "output": "i32"}],
+                    ["reset", {"inputs": [], "output": null}]
+                ],
+                "memories": [["memory", {"min": 1, "max": 16}]],
+                "globals": [
+                    ["counter", {"value_type": "i32", "mutable": true}],
+                    ["version", {"value_type": "i64", "mutable": false}]
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(parsed, interface());
+        assert_eq!(parsed.check(&module()), Ok(()));
+    }
+}
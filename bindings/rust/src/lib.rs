@@ -34,11 +34,175 @@
 //!     assert_eq!(result, 66);
 //! }
 //! ```
+//!
+//! With the `text-format` feature, the module can be written in the WebAssembly text format instead.
+//!
+//! ```
+//! # #[cfg(feature = "text-format")]
+//! # {
+//! let module = fizzy::parse_wat(
+//!     r#"(module
+//!       (func (export "sum") (param i32 i32) (result i32)
+//!         (i32.add (local.get 0) (local.get 1))))"#,
+//! )
+//! .expect("parsing failed");
+//! let mut instance = module.instantiate().expect("instantiation failed");
+//! let result = instance
+//!     .execute(
+//!         "sum",
+//!         &[fizzy::TypedValue::U32(42), fizzy::TypedValue::U32(24)],
+//!     )
+//!     .expect("execution failed");
+//! assert_eq!(result.value(), Some(fizzy::TypedValue::U32(66)));
+//! # }
+//! ```
+//!
+//! # Panics
+//!
+//! The safe API does not panic on the inputs which may be untrusted, e.g. the binaries of modules,
+//! the names of exports, the arguments of executions or the offsets in memories, which are reported
+//! as [`Error`]s instead. The remaining panics are violations of invariants of the engine, e.g. a
+//! result of a value type which no valid module can have.
+
+// The dependency is renamed for the `serde` feature. The derives find it by this name with
+// `serde(crate = "serde")`.
+#[cfg(feature = "serde")]
+extern crate serde_crate as serde;
+
+/// Declare an item of the low-level API, which is public with the `raw-api` feature and private to
+/// the crate otherwise, as it exposes the structures of the C API or skips the checks of the safe
+/// API.
+macro_rules! raw_api {
+    ($(#[$attr:meta])* pub $($item:tt)*) => {
+        #[cfg(feature = "raw-api")]
+        $(#[$attr])*
+        pub $($item)*
 
+        #[cfg(not(feature = "raw-api"))]
+        $(#[$attr])*
+        #[allow(dead_code)]
+        pub(crate) $($item)*
+    };
+}
+
+mod allocator;
+mod cache;
+pub mod codegen;
+pub mod codes;
+pub mod compat;
+mod config;
+pub mod contrib;
+mod coverage;
+#[cfg(feature = "diagnostics")]
+mod diagnostics;
+#[cfg(feature = "differential")]
+pub mod differential;
+mod disassembler;
+mod dump;
+pub mod engine;
+mod entry;
+#[cfg(feature = "ethereum")]
+pub mod ethereum;
+mod features;
+#[cfg(feature = "raw-api")]
+pub mod ffi;
+#[cfg(not(feature = "raw-api"))]
+#[allow(dead_code)]
+mod ffi;
+mod growth;
+mod health;
+mod import_stats;
+mod imports;
+pub mod instrument;
+mod interface;
+mod limits;
+mod metering;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod opcodes;
+#[cfg(feature = "paranoid-check")]
+mod paranoid;
+mod pool;
+pub mod prelude;
+mod profile;
+mod raw;
+mod registry;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod report;
+mod reset;
+mod scan;
+mod scheduler;
+#[cfg(feature = "serde")]
+mod serialization;
+mod session;
+mod smoke;
+mod snapshot;
+mod state;
+mod stats;
 mod sys;
+mod telemetry;
+#[cfg(feature = "test-oom")]
+pub mod test_oom;
+#[cfg(feature = "text-format")]
+mod text;
+pub mod timed;
+mod trace;
+mod usage;
+#[cfg(feature = "wasi")]
+pub mod wasi;
+
+pub use allocator::AllocStrategy;
+pub use cache::ModuleCache;
+pub use config::RuntimeConfig;
+pub use coverage::CoverageMap;
+#[cfg(feature = "diagnostics")]
+pub use diagnostics::{validate_detailed, DetailedError};
+pub use dump::ModuleDisplay;
+pub use engine::{InstanceApi as WasmInstance, InstanceApiExt as WasmInstanceExt};
+pub use health::{self_check, SelfCheckReport};
+pub use import_stats::ImportStat;
+pub use imports::{
+    Caller, DynHostFn, ExtraImports, HostError, HostResult, ImportMeta, ImportReport,
+    ImportsBuilder, IntoHostFunction, LogSink, NextHostFn, Trap, WasmParams, WasmResult, WasmType,
+};
+pub use interface::{Interface, InterfaceViolation};
+pub use limits::{limits, EngineLimits};
+pub use metering::{parse_metered, CostSchedule, CostScheduleBuilder};
+#[cfg(feature = "paranoid-check")]
+pub use paranoid::ReferenceImports;
+pub use pool::{InstancePool, PooledInstance, ResetPolicy};
+pub use profile::{FunctionProfile, ProfileReport};
+pub use registry::Registry;
+pub use scan::{parse_with, ParseOptions};
+pub use scheduler::{FinishedTask, Scheduler, TaskId};
+pub use session::{CallCost, MeteredSession, SessionSummary};
+pub use snapshot::{GlobalChange, MemoryChange, StateDiff, StateSnapshot};
+#[cfg(feature = "text-format")]
+pub use text::{parse_wat, run_wat};
+pub use trace::{TraceEvent, TraceSink};
+pub use usage::{estimate_instance_overhead, ResourceUsage};
 
+pub use fizzy_types::{
+    ErrorKind, ExternalKind, FunctionType, GlobalType, Limits, TypeError, TypedValue, ValueType,
+};
+
+use raw::{
+    ExternalKindExt, FunctionTypeExt, GlobalTypeExt, LimitsExt, TypedValueExt, ValueTypeExt,
+};
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
+use std::io::{Read, Write};
+#[cfg(feature = "mmap")]
+use std::path::Path;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::ThreadId;
 
 /// A safe container for handling the low-level FizzyError struct.
 struct FizzyErrorBox(Box<sys::FizzyError>);
@@ -63,7 +227,6 @@ impl FizzyErrorBox {
     }
 
     /// Return the underlying error code.
-    // TODO: represent the errors as proper Rust enums
     fn code(&self) -> u32 {
         self.0.code
     }
@@ -84,8 +247,393 @@ impl std::fmt::Display for FizzyErrorBox {
     }
 }
 
+impl From<FizzyErrorBox> for Error {
+    fn from(err: FizzyErrorBox) -> Self {
+        let message = err.message();
+        match err.code() {
+            sys::FizzyErrorCode_FizzyErrorMalformedModule => Error::MalformedModule(message),
+            sys::FizzyErrorCode_FizzyErrorInvalidModule => Error::InvalidModule(message),
+            sys::FizzyErrorCode_FizzyErrorInstantiationFailed => {
+                Error::InstantiationFailed(message)
+            }
+            sys::FizzyErrorCode_FizzyErrorMemoryAllocationFailed => {
+                Error::MemoryAllocationFailed(message)
+            }
+            _ => Error::Other(message),
+        }
+    }
+}
+
+/// The error type of this crate.
+///
+/// With the `serde` feature, errors are serialized with their kind in snake case, e.g.
+/// `{"error":"invalid_module","message":"invalid type index"}`. I/O errors are restored only with
+/// their messages.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(crate = "serde", into = "serialization::ErrorRepr")
+)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input is not a well-formed WebAssembly binary.
+    MalformedModule(String),
+    /// The input is not a well-formed module in the WebAssembly text format.
+    TextFormat(String),
+    /// The module is not valid according to WebAssembly 1.0 rules.
+    InvalidModule(String),
+    /// The module cannot be instantiated (e.g. imports are mismatching).
+    InstantiationFailed(String),
+    /// The import `module.name` of the kind `kind` is not provided, see
+    /// [`Error::is_missing_import`].
+    MissingImport {
+        module: String,
+        name: String,
+        kind: ExternalKind,
+    },
+    /// Memory allocation has failed.
+    MemoryAllocationFailed(String),
+    /// The function is not exported by the module.
+    FunctionNotFound,
+    /// The number of supplied arguments does not match the function type.
+    ArgumentCountMismatch,
+    /// The types of supplied arguments do not match the function type.
+    ArgumentTypeMismatch,
+    /// The argument at `index` cannot be coerced to the type of its parameter without a loss, see
+    /// [`ExecutionOptions::coerce_arguments`].
+    ArgumentOutOfRange { index: usize },
+    /// The instance has no memory.
+    NoMemoryAvailable,
+    /// The memory range is out of bounds.
+    InvalidMemoryOffsetOrSize,
+    /// The instance is already executing a function. The host functions can execute the functions
+    /// of the calling instance only by [`Caller::execute`].
+    Busy,
+    /// The execution has resulted in a trap.
+    Trapped(TrapInfo),
+    /// An I/O operation, e.g. reading the input, has failed.
+    Io(Arc<std::io::Error>),
+    /// The value type, e.g. `funcref`, is not supported by the engine. The reference types are
+    /// reserved in [`ValueType`] and [`TypedValue`] for when they are supported.
+    UnsupportedType(String),
+    /// The module uses the `feature` beyond WebAssembly 1.0, e.g. `"simd"`, which is the likely
+    /// cause of rejecting it, see [`Module::detect_features`].
+    UnsupportedFeature { feature: &'static str },
+    /// The module exceeds the limit `which` of [`ParseOptions`], e.g. `max_functions`, or the
+    /// option `which` of [`InstantiateOptions`] exceeds the limit of the engine, see [`limits`].
+    LimitExceeded {
+        which: String,
+        limit: u64,
+        actual: u64,
+    },
+    /// The execution has diverged from the reference interpreter, with the outcomes `ours` and
+    /// `theirs`, e.g. `returned i32:1` and `trapped`, see `Instance::execute_checked` with the
+    /// `paranoid-check` feature.
+    DivergenceDetected { ours: String, theirs: String },
+    /// The budget of ticks of a [`MeteredSession`] is exhausted.
+    OutOfTicks,
+    /// Any other error.
+    Other(String),
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::MalformedModule(a), Error::MalformedModule(b))
+            | (Error::TextFormat(a), Error::TextFormat(b))
+            | (Error::InvalidModule(a), Error::InvalidModule(b))
+            | (Error::InstantiationFailed(a), Error::InstantiationFailed(b))
+            | (Error::MemoryAllocationFailed(a), Error::MemoryAllocationFailed(b))
+            | (Error::UnsupportedType(a), Error::UnsupportedType(b))
+            | (Error::Other(a), Error::Other(b)) => a == b,
+            (Error::FunctionNotFound, Error::FunctionNotFound)
+            | (Error::ArgumentCountMismatch, Error::ArgumentCountMismatch)
+            | (Error::ArgumentTypeMismatch, Error::ArgumentTypeMismatch)
+            | (Error::NoMemoryAvailable, Error::NoMemoryAvailable)
+            | (Error::InvalidMemoryOffsetOrSize, Error::InvalidMemoryOffsetOrSize)
+            | (Error::Busy, Error::Busy)
+            | (Error::OutOfTicks, Error::OutOfTicks) => true,
+            (Error::Trapped(a), Error::Trapped(b)) => a == b,
+            (Error::ArgumentOutOfRange { index }, Error::ArgumentOutOfRange { index: other }) => {
+                index == other
+            }
+            (
+                Error::UnsupportedFeature { feature },
+                Error::UnsupportedFeature { feature: other },
+            ) => feature == other,
+            (
+                Error::MissingImport { module, name, kind },
+                Error::MissingImport {
+                    module: other_module,
+                    name: other_name,
+                    kind: other_kind,
+                },
+            ) => module == other_module && name == other_name && kind == other_kind,
+            (
+                Error::LimitExceeded {
+                    which,
+                    limit,
+                    actual,
+                },
+                Error::LimitExceeded {
+                    which: other_which,
+                    limit: other_limit,
+                    actual: other_actual,
+                },
+            ) => which == other_which && limit == other_limit && actual == other_actual,
+            (
+                Error::DivergenceDetected { ours, theirs },
+                Error::DivergenceDetected {
+                    ours: other_ours,
+                    theirs: other_theirs,
+                },
+            ) => ours == other_ours && theirs == other_theirs,
+            // I/O errors are not comparable, only their kinds and messages.
+            (Error::Io(a), Error::Io(b)) => a.kind() == b.kind() && a.to_string() == b.to_string(),
+            _ => false,
+        }
+    }
+}
+
+impl Error {
+    /// True if an imported function, global, table or memory is not provided.
+    pub fn is_missing_import(&self) -> bool {
+        matches!(self, Error::MissingImport { .. })
+    }
+
+    /// True if the input is not a well-formed module, in the binary or the text format.
+    pub fn is_malformed(&self) -> bool {
+        matches!(self, Error::MalformedModule(_) | Error::TextFormat(_))
+    }
+
+    /// True if the module is not valid according to WebAssembly 1.0 rules.
+    pub fn is_invalid(&self) -> bool {
+        matches!(self, Error::InvalidModule(_))
+    }
+
+    /// True if the execution has resulted in a trap, of any [`TrapKind`].
+    pub fn is_trap(&self) -> bool {
+        matches!(self, Error::Trapped(_))
+    }
+
+    /// True if a limit has been exceeded: a limit of [`ParseOptions`], the call depth, which is
+    /// also a trap, the memory available to the engine, or the budget of a [`MeteredSession`].
+    ///
+    /// The memory limit of an instantiation is enforced by the engine, which reports exceeding it
+    /// only by [`Error::InstantiationFailed`].
+    pub fn is_resource_limit(&self) -> bool {
+        match self {
+            Error::LimitExceeded { .. } | Error::MemoryAllocationFailed(_) | Error::OutOfTicks => {
+                true
+            }
+            Error::Trapped(info) => *info.kind() == TrapKind::CallDepthExceeded,
+            _ => false,
+        }
+    }
+
+    /// The kind of the error, which is available also without the standard library in
+    /// `fizzy-types`.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::MalformedModule(_) => ErrorKind::MalformedModule,
+            Error::TextFormat(_) => ErrorKind::TextFormat,
+            Error::InvalidModule(_) => ErrorKind::InvalidModule,
+            Error::InstantiationFailed(_) => ErrorKind::InstantiationFailed,
+            Error::MissingImport { .. } => ErrorKind::MissingImport,
+            Error::MemoryAllocationFailed(_) => ErrorKind::MemoryAllocationFailed,
+            Error::FunctionNotFound => ErrorKind::FunctionNotFound,
+            Error::ArgumentCountMismatch => ErrorKind::ArgumentCountMismatch,
+            Error::ArgumentTypeMismatch => ErrorKind::ArgumentTypeMismatch,
+            Error::ArgumentOutOfRange { .. } => ErrorKind::ArgumentOutOfRange,
+            Error::NoMemoryAvailable => ErrorKind::NoMemoryAvailable,
+            Error::InvalidMemoryOffsetOrSize => ErrorKind::InvalidMemoryOffsetOrSize,
+            Error::Busy => ErrorKind::Busy,
+            Error::Trapped(_) => ErrorKind::Trapped,
+            Error::Io(_) => ErrorKind::Io,
+            Error::UnsupportedType(_) => ErrorKind::UnsupportedType,
+            Error::UnsupportedFeature { .. } => ErrorKind::UnsupportedFeature,
+            Error::LimitExceeded { .. } => ErrorKind::LimitExceeded,
+            Error::DivergenceDetected { .. } => ErrorKind::DivergenceDetected,
+            Error::OutOfTicks => ErrorKind::OutOfTicks,
+            Error::Other(_) => ErrorKind::Other,
+        }
+    }
+
+    /// The error of the import `module.name` of the kind `kind` not provided.
+    pub(crate) fn missing_import(module: &CStr, name: &CStr, kind: ExternalKind) -> Self {
+        Error::MissingImport {
+            module: module.to_string_lossy().into_owned(),
+            name: name.to_string_lossy().into_owned(),
+            kind,
+        }
+    }
+
+    /// The error of type `T` of the host function which has trapped, see
+    /// [`Trap::from_host_error`].
+    pub fn host_error_downcast<T: HostError>(&self) -> Option<&T> {
+        match self {
+            Error::Trapped(info) => info.host_error()?.downcast_ref::<T>(),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(Arc::new(err))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::MalformedModule(message)
+            | Error::TextFormat(message)
+            | Error::InvalidModule(message)
+            | Error::InstantiationFailed(message)
+            | Error::MemoryAllocationFailed(message)
+            | Error::Other(message) => write!(f, "{}", message),
+            Error::FunctionNotFound => write!(f, "function not found"),
+            Error::ArgumentCountMismatch => write!(f, "argument count mismatch"),
+            Error::ArgumentTypeMismatch => write!(f, "argument type mismatch"),
+            Error::ArgumentOutOfRange { index } => {
+                write!(
+                    f,
+                    "argument {} is out of range of its parameter type",
+                    index
+                )
+            }
+            Error::NoMemoryAvailable => write!(f, "no memory is available"),
+            Error::InvalidMemoryOffsetOrSize => write!(f, "invalid offset or size"),
+            Error::Busy => write!(f, "the instance is already executing"),
+            Error::OutOfTicks => write!(f, "the budget of ticks is exhausted"),
+            Error::UnsupportedType(name) => write!(f, "the value type {} is not supported", name),
+            Error::UnsupportedFeature { feature } => {
+                write!(f, "the WebAssembly feature {} is not supported", feature)
+            }
+            Error::MissingImport { module, name, kind } => {
+                let kind = match kind {
+                    ExternalKind::Function => "function",
+                    ExternalKind::Table => "table",
+                    ExternalKind::Memory => "memory",
+                    ExternalKind::Global => "global",
+                };
+                write!(f, "imported {} {}.{} is required", kind, module, name)
+            }
+            Error::Trapped(info) => write!(f, "{}", info),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::LimitExceeded {
+                which,
+                limit,
+                actual,
+            } => write!(f, "limit {} of {} exceeded: {}", which, limit, actual),
+            Error::DivergenceDetected { ours, theirs } => write!(
+                f,
+                "divergence from the reference interpreter: fizzy {}, wasmi {}",
+                ours, theirs
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err.as_ref()),
+            Error::Trapped(info) => info
+                .host_error()
+                .map(|err| err as &(dyn std::error::Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+/// The cause of a trap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde", rename_all = "lowercase")
+)]
+#[non_exhaustive]
+pub enum TrapKind {
+    /// Trap in WebAssembly code (e.g. `unreachable`, division by zero or out of bounds memory access).
+    /// Fizzy does not report the exact cause.
+    Wasm,
+    /// Trap raised by a host function, with its message.
+    Host(String),
+    /// The execution has exceeded the limit of the call depth, see
+    /// [`InstantiateOptions::max_call_depth`].
+    #[cfg_attr(feature = "serde", serde(rename = "call_depth_exceeded"))]
+    CallDepthExceeded,
+}
+
+/// Details of a trap which has terminated an execution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct TrapInfo {
+    function: Option<String>,
+    kind: TrapKind,
+    /// The error of the host function, which is not serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    host_error: Option<imports::SharedHostError>,
+}
+
+impl TrapInfo {
+    /// Describe the trap of `function`, which is raised by a host function if `host_trap` is given.
+    pub(crate) fn new(function: &str, host_trap: Option<Trap>) -> Self {
+        TrapInfo {
+            function: Some(function.to_string()),
+            host_error: host_trap
+                .as_ref()
+                .and_then(|trap| trap.host_error())
+                .cloned(),
+            kind: match host_trap {
+                Some(trap) if trap.is_call_depth_exceeded() => TrapKind::CallDepthExceeded,
+                Some(trap) => TrapKind::Host(trap.message().to_string()),
+                None => TrapKind::Wasm,
+            },
+        }
+    }
+
+    /// The name of the executed function, if known.
+    pub fn function(&self) -> Option<&str> {
+        self.function.as_deref()
+    }
+
+    /// The cause of the trap.
+    pub fn kind(&self) -> &TrapKind {
+        &self.kind
+    }
+
+    /// The error of the host function which has trapped, see [`Trap::from_host_error`].
+    pub fn host_error(&self) -> Option<&(dyn std::error::Error + Send + Sync + 'static)> {
+        self.host_error.as_ref().map(|err| err.get())
+    }
+}
+
+impl std::fmt::Display for TrapInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "trap")?;
+        if let Some(function) = &self.function {
+            write!(f, " in function {}", function)?;
+        }
+        match &self.kind {
+            TrapKind::Wasm => Ok(()),
+            TrapKind::Host(message) => write!(f, ": {}", message),
+            TrapKind::CallDepthExceeded => write!(f, ": call depth exceeded"),
+        }
+    }
+}
+
 /// Parse and validate the input according to WebAssembly 1.0 rules. Returns true if the supplied input is valid.
-pub fn validate<T: AsRef<[u8]>>(input: T) -> Result<(), String> {
+pub fn validate<T: AsRef<[u8]>>(input: T) -> Result<(), Error> {
+    let call = telemetry::Call::validate(input.as_ref().len());
     let mut err = FizzyErrorBox::new();
     let ret = unsafe {
         sys::fizzy_validate(
@@ -99,1223 +647,4393 @@ pub fn validate<T: AsRef<[u8]>>(input: T) -> Result<(), String> {
         Ok(())
     } else {
         debug_assert!(err.code() != 0);
-        Err(err.message())
+        let err = parse_error(input.as_ref(), err);
+        call.failed(&err);
+        Err(err)
+    }
+}
+
+/// Parse and validate each of the inputs according to WebAssembly 1.0 rules, see [`validate`].
+///
+/// The results are in the order of the inputs. With the `rayon` feature the inputs are validated
+/// in parallel, which is safe because validation only reads the input and shares no state.
+pub fn validate_batch<'a>(inputs: impl IntoIterator<Item = &'a [u8]>) -> Vec<Result<(), Error>> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        let inputs: Vec<&[u8]> = inputs.into_iter().collect();
+        inputs.par_iter().map(validate).collect()
     }
+    #[cfg(not(feature = "rayon"))]
+    inputs.into_iter().map(validate).collect()
 }
 
 /// A parsed and validated WebAssembly 1.0 module.
-// NOTE: cannot use NonNull here given this is *const
-pub struct Module(*const sys::FizzyModule);
+///
+/// Clones of a module and its instances share the parsed module, which is freed when the last
+/// of them is dropped. The binary is shared with the module if parsed by [`parse_shared`],
+/// otherwise a copy without the custom sections is kept, e.g. for [`Module::dump`].
+#[derive(Clone)]
+pub struct Module(Arc<ModulePtr>);
 
-impl Drop for Module {
+/// The parsed module shared by [`Module`] clones and instances.
+struct ModulePtr {
+    // NOTE: cannot use NonNull here given this is *const
+    ptr: *const sys::FizzyModule,
+    /// The digest of the binary, see [`Module::digest`].
+    digest: u64,
+    /// The names of functions in the name section, which the engine does not keep.
+    function_names: HashMap<u32, String>,
+    /// The names and sizes of the custom sections, which may not be kept with the binary.
+    custom_sections: Vec<(String, usize)>,
+    /// The binary, for the parts of the module which the engine does not keep.
+    binary: Binary,
+}
+
+/// The binary of a module, possibly without its custom sections.
+enum Binary {
+    /// The whole binary, shared with the caller of [`parse_shared`].
+    Shared(Arc<[u8]>),
+    /// A copy of the binary without the custom sections.
+    Stripped(Vec<u8>),
+}
+
+// The module is not modified after parsing, therefore it can be shared between threads.
+unsafe impl Send for ModulePtr {}
+unsafe impl Sync for ModulePtr {}
+
+impl Drop for ModulePtr {
     fn drop(&mut self) {
-        debug_assert!(!self.0.is_null());
-        unsafe { sys::fizzy_free_module(self.0) }
+        debug_assert!(!self.ptr.is_null());
+        unsafe { sys::fizzy_free_module(self.ptr) }
     }
 }
 
-impl Clone for Module {
-    fn clone(&self) -> Self {
-        debug_assert!(!self.0.is_null());
-        let ptr = unsafe { sys::fizzy_clone_module(self.0) };
-        // TODO: this can be zero in case of memory allocation error, should this be gracefully handled?
-        assert!(!ptr.is_null());
-        Module { 0: ptr }
+impl Module {
+    fn from_ptr(ptr: *const sys::FizzyModule, input: &[u8], shared: Option<Arc<[u8]>>) -> Self {
+        debug_assert!(!ptr.is_null());
+        Module(Arc::new(ModulePtr {
+            ptr,
+            digest: digest(input),
+            function_names: instrument::function_names(input),
+            custom_sections: instrument::custom_sections(input),
+            binary: match shared {
+                Some(bytes) => Binary::Shared(bytes),
+                None => Binary::Stripped(instrument::strip_custom_sections(input)),
+            },
+        }))
+    }
+
+    fn as_ptr(&self) -> *const sys::FizzyModule {
+        self.0.ptr
+    }
+
+    /// The digest of the binary of the module, which identifies it across processes and versions
+    /// of this crate, e.g. when reviving an instance with [`Instance::deserialize_state`].
+    ///
+    /// This is the 64-bit FNV-1a hash, which detects accidental differences, but is not
+    /// a cryptographic hash.
+    pub fn digest(&self) -> u64 {
+        self.0.digest
+    }
+
+    /// The binary of the module, without the custom sections unless parsed by [`parse_shared`].
+    pub(crate) fn bytes(&self) -> &[u8] {
+        match &self.0.binary {
+            Binary::Shared(bytes) => bytes,
+            Binary::Stripped(bytes) => bytes,
+        }
+    }
+
+    /// The names and sizes of the payloads of the custom sections, in their order.
+    pub(crate) fn custom_sections(&self) -> &[(String, usize)] {
+        &self.0.custom_sections
+    }
+
+    /// The name of the function `func_idx` in the name section, if any.
+    pub(crate) fn function_name(&self, func_idx: u32) -> Option<&str> {
+        self.0.function_names.get(&func_idx).map(String::as_str)
     }
 }
 
 /// Parse and validate the input according to WebAssembly 1.0 rules.
-pub fn parse<T: AsRef<[u8]>>(input: &T) -> Result<Module, String> {
+///
+/// The binary is copied with the module without its custom sections, whose names and sizes are
+/// kept, e.g. for [`Module::dump`]. A large binary is shared instead by [`parse_shared`].
+pub fn parse<T: AsRef<[u8]>>(input: &T) -> Result<Module, Error> {
+    let ptr = parse_ptr(input.as_ref())?;
+    Ok(Module::from_ptr(ptr, input.as_ref(), None))
+}
+
+/// Parse and validate the input like [`parse`], keeping the whole binary with the module by
+/// sharing `input` instead of copying it.
+pub fn parse_shared(input: Arc<[u8]>) -> Result<Module, Error> {
+    let ptr = parse_ptr(&input)?;
+    Ok(Module::from_ptr(ptr, &input, Some(input.clone())))
+}
+
+fn parse_ptr(input: &[u8]) -> Result<*const sys::FizzyModule, Error> {
+    let call = telemetry::Call::parse(input.len());
     let mut err = FizzyErrorBox::new();
-    let ptr = unsafe {
-        sys::fizzy_parse(
-            input.as_ref().as_ptr(),
-            input.as_ref().len(),
-            err.as_mut_ptr(),
-        )
-    };
+    let ptr = unsafe { sys::fizzy_parse(input.as_ptr(), input.len(), err.as_mut_ptr()) };
     if ptr.is_null() {
         debug_assert!(err.code() != 0);
-        Err(err.message())
+        let err = parse_error(input, err);
+        call.failed(&err);
+        Err(err)
     } else {
         debug_assert!(err.code() == 0);
-        Ok(Module { 0: ptr })
+        Ok(ptr)
     }
 }
 
-/// An instance of a module.
-pub struct Instance(NonNull<sys::FizzyInstance>);
-
-impl Drop for Instance {
-    fn drop(&mut self) {
-        unsafe { sys::fizzy_free_instance(self.0.as_ptr()) }
+/// The error of parsing `input`, which is [`Error::UnsupportedType`] if the engine has rejected
+/// the module because of a reference type in the types of its functions or locals, or
+/// [`Error::UnsupportedFeature`] if the module uses another feature beyond WebAssembly 1.0.
+fn parse_error(input: &[u8], err: FizzyErrorBox) -> Error {
+    if let Some(value_type) = scan::reference_type(input) {
+        return Error::UnsupportedType(value_type.to_string());
+    }
+    match Module::detect_features(input).first() {
+        Some(feature) => Error::UnsupportedFeature { feature },
+        None => err.into(),
     }
 }
 
-impl Module {
-    /// Create an instance of a module.
-    // TODO: support imported functions
-    pub fn instantiate(self) -> Result<Instance, String> {
-        debug_assert!(!self.0.is_null());
-        let mut err = FizzyErrorBox::new();
-        let ptr = unsafe {
-            sys::fizzy_instantiate(
-                self.0,
-                std::ptr::null(),
-                0,
-                std::ptr::null(),
-                std::ptr::null(),
-                std::ptr::null(),
-                0,
-                sys::FizzyMemoryPagesLimitDefault,
-                err.as_mut_ptr(),
-            )
-        };
-        // Forget Module (and avoid calling drop) because it has been consumed by instantiate (even if it failed).
-        core::mem::forget(self);
-        if ptr.is_null() {
-            debug_assert!(err.code() != 0);
-            Err(err.message())
-        } else {
-            debug_assert!(err.code() == 0);
-            Ok(Instance {
-                0: unsafe { NonNull::new_unchecked(ptr) },
-            })
-        }
-    }
+/// Parse and validate the file at `path` according to WebAssembly 1.0 rules.
+///
+/// The file is memory-mapped where supported instead of being read into a buffer first,
+/// and it must not be modified while being parsed.
+#[cfg(feature = "mmap")]
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Module, Error> {
+    let input = mmap::FileInput::open(path.as_ref())?;
+    parse(&input)
 }
 
-/// A WebAssembly value of i32/i64/f32/f64.
-pub type Value = sys::FizzyValue;
+/// The bytes of a module which have been parsed and validated by [`validate_owned`].
+///
+/// The parsed module is kept, so that [`Module::from_validated`] does not need to validate the bytes again.
+/// The bytes are immutable and shared with the module, which keeps them.
+pub struct ValidatedBytes {
+    bytes: Arc<[u8]>,
+    module: Module,
+}
 
-impl Value {
-    pub fn as_i32(&self) -> i32 {
-        unsafe { self.i32 as i32 }
-    }
-    pub fn as_u32(&self) -> u32 {
-        unsafe { self.i32 }
-    }
-    pub fn as_i64(&self) -> i64 {
-        unsafe { self.i64 as i64 }
-    }
-    pub fn as_u64(&self) -> u64 {
-        unsafe { self.i64 }
+impl ValidatedBytes {
+    /// The validated bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
     }
-    pub fn as_f32(&self) -> f32 {
-        unsafe { self.f32 }
-    }
-    pub fn as_f64(&self) -> f64 {
-        unsafe { self.f64 }
+
+    /// Take a copy of the validated bytes, dropping the parsed module.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes.to_vec()
     }
 }
 
-impl From<i32> for Value {
-    fn from(v: i32) -> Self {
-        Value { i32: v as u32 }
+impl AsRef<[u8]> for ValidatedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
     }
 }
 
-impl From<u32> for Value {
-    fn from(v: u32) -> Self {
-        Value { i32: v }
-    }
+/// The 64-bit FNV-1a hash of `bytes`, which does not depend on the platform.
+fn digest(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
 }
 
-impl From<i64> for Value {
-    fn from(v: i64) -> Self {
-        Value { i64: v as u64 }
-    }
+/// Parse and validate the input according to WebAssembly 1.0 rules, keeping the result for later use
+/// with [`Module::from_validated`].
+pub fn validate_owned(bytes: Vec<u8>) -> Result<ValidatedBytes, Error> {
+    let bytes: Arc<[u8]> = bytes.into();
+    let module = parse_shared(bytes.clone())?;
+    Ok(ValidatedBytes { bytes, module })
 }
 
-impl From<u64> for Value {
-    fn from(v: u64) -> Self {
-        Value { i64: v }
-    }
+/// An instance of a module.
+pub struct Instance {
+    /// The engine instance, shared with the instances importing its memory, see
+    /// [`InstantiateOptions::with_memory_from`].
+    instance: Arc<InstancePtr>,
+    /// The instance owning the imported memory, therefore freed only after this instance, with the
+    /// lock of the memory.
+    memory_owner: Option<Arc<InstancePtr>>,
+    /// The module referenced by the instance, therefore dropped only after it is freed.
+    #[allow(dead_code)]
+    module: Module,
+    /// The contexts of imported host functions. These are referenced by the instance,
+    /// therefore boxed and dropped only after it is freed.
+    #[allow(clippy::vec_box)]
+    host_functions: Vec<Box<imports::HostContext>>,
+    /// The trap raised by a host function during the last execution.
+    host_trap: Arc<imports::TrapSlot>,
+    /// The callback notified of the growth of the memory, see [`Instance::on_memory_grow`].
+    memory_growth: Arc<growth::MemoryGrowth>,
+    /// The gas of the metered execution in progress, see [`Caller::remaining_ticks`].
+    active_gas: Arc<metering::ActiveGas>,
+    /// True while an execution times the calls of host functions, see
+    /// [`ExecutionOptions::time_imports`].
+    import_timing: Arc<AtomicBool>,
+    /// The exported functions looked up by name so far, or all of them when warmed.
+    /// Exports do not change after instantiation, therefore entries are never invalidated.
+    export_cache: RefCell<HashMap<String, (u32, FunctionType)>>,
+    /// The limit of the call depth of executions.
+    max_call_depth: u32,
+    /// True while a function of the instance is executed, see [`Error::Busy`].
+    executing: Arc<AtomicBool>,
+    /// The allocator of the program, see [`Instance::guest_alloc`].
+    alloc_strategy: AllocStrategy,
+    /// The host functions of the reference interpreter, see [`Instance::execute_checked`].
+    #[cfg(feature = "paranoid-check")]
+    reference_imports: ReferenceImports,
 }
-impl From<f32> for Value {
-    fn from(v: f32) -> Self {
-        Value { f32: v }
+
+// The instance is not tied to a thread, and the host functions it refers to are Send.
+unsafe impl Send for Instance {}
+
+/// The engine instance shared by an [`Instance`] and the instances importing its memory, with
+/// the lock of its memory.
+///
+/// The instance is freed when the last of them is dropped. Its host functions may be dropped
+/// before, as only the memory is used by the importing instances.
+struct InstancePtr(NonNull<sys::FizzyInstance>, MemoryLock);
+
+// The instance is used through a single `Instance`, and the memory shared with the importing
+// instances is only used holding its lock, see `Instance::lock_memory`.
+unsafe impl Send for InstancePtr {}
+unsafe impl Sync for InstancePtr {}
+
+impl InstancePtr {
+    fn as_ptr(&self) -> *mut sys::FizzyInstance {
+        self.0.as_ptr()
     }
 }
 
-impl From<f64> for Value {
-    fn from(v: f64) -> Self {
-        Value { f64: v }
+impl Drop for InstancePtr {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_free_instance(self.as_ptr()) }
     }
 }
 
-/// A WebAssembly value i32/i64/f32/f64 with its type specified.
-pub enum TypedValue {
-    U32(u32),
-    U64(u64),
-    F32(f32),
-    F64(f64),
+/// Options for the instantiation of a module, see [`Module::instantiate_with`].
+///
+/// The options can be reused for any number of instantiations, which share the host functions
+/// given by [`InstantiateOptions::imports`].
+#[derive(Clone, Default)]
+pub struct InstantiateOptions {
+    preallocate_max_memory: bool,
+    memory_pages_limit: Option<u32>,
+    max_call_depth: Option<u32>,
+    /// The host functions, or the error of converting them, reported by the instantiation.
+    imports: Option<Result<imports::SharedImports, Error>>,
+    table: Option<sys::FizzyExternalTable>,
+    memory: Option<sys::FizzyExternalMemory>,
+    /// The instance exporting the memory, if given by [`InstantiateOptions::with_memory_from`].
+    memory_owner: Option<Arc<InstancePtr>>,
+    /// The globals by their module and name.
+    globals: Vec<(String, String, sys::FizzyExternalGlobal)>,
 }
 
-impl TypedValue {
-    fn get_type(&self) -> sys::FizzyValueType {
-        match self {
-            TypedValue::U32(_) => sys::FizzyValueTypeI32,
-            TypedValue::U64(_) => sys::FizzyValueTypeI64,
-            TypedValue::F32(_) => sys::FizzyValueTypeF32,
-            TypedValue::F64(_) => sys::FizzyValueTypeF64,
-        }
+impl std::fmt::Debug for InstantiateOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstantiateOptions")
+            .field("preallocate_max_memory", &self.preallocate_max_memory)
+            .field("memory_pages_limit", &self.memory_pages_limit)
+            .field("max_call_depth", &self.max_call_depth)
+            .field(
+                "imports",
+                &self.imports.as_ref().map(|imports| match imports {
+                    Ok(imports) => imports.functions.len(),
+                    Err(_) => 0,
+                }),
+            )
+            .field("table", &self.table.is_some())
+            .field("memory", &self.memory.is_some())
+            .field(
+                "globals",
+                &self
+                    .globals
+                    .iter()
+                    .map(|(module, name, _)| format!("{}.{}", module, name))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
     }
+}
 
-    pub fn as_i32(&self) -> Option<i32> {
-        match self {
-            TypedValue::U32(v) => Some(*v as i32),
-            _ => None,
-        }
+impl InstantiateOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
     }
-    pub fn as_u32(&self) -> Option<u32> {
-        match self {
-            TypedValue::U32(v) => Some(*v),
-            _ => None,
-        }
+
+    /// Resolve the imported functions by name from `imports`, like
+    /// [`Module::instantiate_with_imports`]. The host functions are shared by the instances created
+    /// with these options, which call them one at a time.
+    pub fn imports(mut self, imports: ImportsBuilder) -> Self {
+        self.imports = Some(imports.into_shared());
+        self
     }
-    pub fn as_i64(&self) -> Option<i64> {
-        match self {
-            TypedValue::U64(v) => Some(*v as i64),
-            _ => None,
+
+    raw_api! {
+        /// Provide the imported table of the module, exported by another instance, e.g. with
+        /// [`Instance::exported_table`].
+        ///
+        /// # Safety
+        /// The instance exporting the table must outlive the instances created with these options.
+        pub unsafe fn imported_table(mut self, table: ffi::ExternalTable<'_>) -> Self {
+            self.table = Some(*table.as_raw());
+            self
         }
     }
-    pub fn as_u64(&self) -> Option<u64> {
-        match self {
-            TypedValue::U64(v) => Some(*v),
-            _ => None,
+
+    raw_api! {
+        /// Provide the imported memory of the module, exported by another instance, e.g. with
+        /// [`Instance::exported_memory`].
+        ///
+        /// The limits of the memory are validated against the module and
+        /// [`InstantiateOptions::memory_pages_limit`] during instantiation.
+        ///
+        /// # Safety
+        /// The instance exporting the memory must outlive the instances created with these options.
+        pub unsafe fn imported_memory(mut self, memory: ffi::ExternalMemory<'_>) -> Self {
+            self.memory = Some(*memory.as_raw());
+            self.memory_owner = None;
+            self
         }
     }
-    pub fn as_f32(&self) -> Option<f32> {
-        match self {
-            TypedValue::F32(v) => Some(*v),
-            _ => None,
-        }
+
+    /// Provide the imported memory of the module as the memory `name` exported by `instance`, or
+    /// `None` if it is not exported.
+    ///
+    /// The instances created with these options and `instance` share the memory, therefore
+    /// the writes and the growth of the memory by any of them are visible to all, without copying.
+    /// The memory is kept alive until the last of them is dropped.
+    ///
+    /// The instances may be moved to other threads, where their executions and the accesses to
+    /// the memory, e.g. by [`Instance::memory_get`], wait for those of the others to end, so only
+    /// one of them uses the memory at a time. A host function may execute another of them on its own
+    /// thread, but waiting there for another thread using one of them deadlocks.
+    ///
+    /// The slices of `Instance::checked_memory_slice` (with the `raw-api` feature) alias the memory
+    /// of all those instances, and are invalidated by executions of any of them, which may write to
+    /// or grow the memory.
+    pub fn with_memory_from(mut self, instance: &Instance, name: &str) -> Option<Self> {
+        self.memory = Some(*instance.exported_memory(name)?.as_raw());
+        // The memory exported by an instance importing it is owned by the instance it is
+        // imported from.
+        self.memory_owner = Some(
+            instance
+                .memory_owner
+                .clone()
+                .unwrap_or_else(|| instance.instance.clone()),
+        );
+        Some(self)
     }
-    pub fn as_f64(&self) -> Option<f64> {
-        match self {
-            TypedValue::F64(v) => Some(*v),
-            _ => None,
+
+    raw_api! {
+        /// Provide the imported global `module`.`name`, e.g. exported by another instance with
+        /// [`Instance::exported_global`]. The globals are matched by name, and those not imported
+        /// by the module are ignored.
+        ///
+        /// # Safety
+        /// The value of the global must outlive the instances created with these options.
+        pub unsafe fn imported_global(
+            mut self,
+            module: &str,
+            name: &str,
+            global: ffi::ExternalGlobal<'_>,
+        ) -> Self {
+            self.globals.retain(|(other_module, other_name, _)| {
+                other_module != module || other_name != name
+            });
+            self.globals
+                .push((module.to_string(), name.to_string(), *global.as_raw()));
+            self
         }
     }
-}
 
-impl From<&TypedValue> for sys::FizzyValue {
-    fn from(v: &TypedValue) -> sys::FizzyValue {
-        match v {
-            TypedValue::U32(v) => sys::FizzyValue { i32: *v },
-            TypedValue::U64(v) => sys::FizzyValue { i64: *v },
-            TypedValue::F32(v) => sys::FizzyValue { f32: *v },
-            TypedValue::F64(v) => sys::FizzyValue { f64: *v },
-        }
+    /// Allocate the memory up to the maximum size declared by the module, or the hard limit of
+    /// memory growth if smaller, so that growing the memory during execution does not require
+    /// reallocating and copying it. The size of the memory seen by the module is not changed.
+    ///
+    /// The memory is allocated after the start function of the module has been executed.
+    pub fn preallocate_max_memory(mut self, preallocate: bool) -> Self {
+        self.preallocate_max_memory = preallocate;
+        self
     }
-}
 
-/// The result of an execution.
-pub struct ExecutionResult(sys::FizzyExecutionResult);
+    /// Limit the memory to `max_pages` pages of 64 KiB, instead of the default hard limit of
+    /// memory growth of 4096 pages (256 MiB).
+    ///
+    /// The instantiation fails if the module declares a memory with the minimum or the maximum size
+    /// above the limit, and `memory.grow` beyond it returns -1 during execution. The limit cannot
+    /// be above [`EngineLimits::hard_memory_pages_limit`], see [`InstantiateOptions::validate`].
+    pub fn memory_pages_limit(mut self, max_pages: u32) -> Self {
+        self.memory_pages_limit = Some(max_pages);
+        self
+    }
 
-impl ExecutionResult {
-    /// True if execution has resulted in a trap.
-    pub fn trapped(&self) -> bool {
-        self.0.trapped
+    /// Limit the executions of the instance to `max_depth` calls stacked up, including the
+    /// executed function, instead of the default of 2048 calls, above which the limit cannot be
+    /// raised, see [`EngineLimits::max_call_depth`]. An execution exceeding the limit traps with
+    /// [`TrapKind::CallDepthExceeded`].
+    ///
+    /// The limit can be lowered for a single execution by [`ExecutionOptions::max_call_depth`], and
+    /// the functions executed by host functions with [`Caller::execute`] share the limit of the
+    /// calling execution. The start function is limited by the default.
+    pub fn max_call_depth(mut self, max_depth: u32) -> Self {
+        self.max_call_depth = Some(max_depth);
+        self
     }
 
-    /// The optional return value. Only a single return value is allowed in WebAssembly 1.0.
-    pub fn value(&self) -> Option<Value> {
-        if self.0.has_value {
-            assert!(!self.0.trapped);
-            Some(self.0.value)
-        } else {
-            None
+    /// Check the limits against those of the engine, see [`limits`], failing with
+    /// [`Error::LimitExceeded`] naming the option and the limit of the engine. The instantiation
+    /// checks them before calling the engine.
+    pub fn validate(&self) -> Result<(), Error> {
+        let engine = limits();
+        let checks = [
+            (
+                "memory_pages_limit",
+                self.memory_pages_limit,
+                engine.hard_memory_pages_limit,
+            ),
+            ("max_call_depth", self.max_call_depth, engine.max_call_depth),
+        ];
+        for (which, value, limit) in checks.iter() {
+            if let Some(value) = value.filter(|value| value > limit) {
+                return Err(Error::LimitExceeded {
+                    which: which.to_string(),
+                    limit: u64::from(*limit),
+                    actual: u64::from(value),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The imported globals of `module` in the order of its imports.
+    ///
+    /// Fails if an imported global, table or memory is not provided.
+    fn resolve_globals(
+        &self,
+        module: *const sys::FizzyModule,
+    ) -> Result<Vec<sys::FizzyExternalGlobal>, Error> {
+        let import_count = unsafe { sys::fizzy_get_import_count(module) };
+        let mut resolved = Vec::new();
+        for import_idx in 0..import_count {
+            let import = unsafe { sys::fizzy_get_import_description(module, import_idx) };
+            let (module_name, name) =
+                unsafe { (CStr::from_ptr(import.module), CStr::from_ptr(import.name)) };
+            let kind = ExternalKind::from_raw(import.kind);
+            match kind {
+                ExternalKind::Global => {}
+                ExternalKind::Table if self.table.is_none() => {
+                    return Err(Error::missing_import(module_name, name, kind))
+                }
+                ExternalKind::Memory if self.memory.is_none() => {
+                    return Err(Error::missing_import(module_name, name, kind))
+                }
+                _ => continue,
+            }
+            let global = self
+                .globals
+                .iter()
+                .find(|(other_module, other_name, _)| {
+                    other_module.as_bytes() == module_name.to_bytes()
+                        && other_name.as_bytes() == name.to_bytes()
+                })
+                .ok_or_else(|| Error::missing_import(module_name, name, kind))?;
+            resolved.push(global.2);
         }
+        Ok(resolved)
     }
 }
 
-/// The result of an execution.
-pub struct TypedExecutionResult {
-    result: sys::FizzyExecutionResult,
-    value_type: sys::FizzyValueType,
+/// Options for the execution of a function.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionOptions {
+    cost_schedule: Option<CostSchedule>,
+    gas_limit: Option<u64>,
+    trace: Option<Arc<Mutex<TraceSink>>>,
+    coverage: Option<CoverageMap>,
+    max_call_depth: Option<u32>,
+    collect_stats: bool,
+    coerce_arguments: bool,
+    count_instructions: bool,
+    time_imports: bool,
 }
 
-impl TypedExecutionResult {
-    /// True if execution has resulted in a trap.
-    pub fn trapped(&self) -> bool {
-        self.result.trapped
+impl ExecutionOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// The optional return value. Only a single return value is allowed in WebAssembly 1.0.
-    pub fn value(&self) -> Option<TypedValue> {
-        if self.result.has_value {
-            assert!(!self.result.trapped);
-            assert!(self.value_type != sys::FizzyValueTypeVoid);
-            Some(match self.value_type {
-                sys::FizzyValueTypeI32 => TypedValue::U32(unsafe { self.result.value.i32 }),
-                sys::FizzyValueTypeI64 => TypedValue::U64(unsafe { self.result.value.i64 }),
-                sys::FizzyValueTypeF32 => TypedValue::F32(unsafe { self.result.value.f32 }),
-                sys::FizzyValueTypeF64 => TypedValue::F64(unsafe { self.result.value.f64 }),
-                _ => panic!(),
-            })
-        } else {
-            None
-        }
+    /// Meter the execution with the costs of instructions in `schedule`.
+    ///
+    /// The module must be parsed by [`parse_metered`], as the engine does not meter the execution itself.
+    pub fn cost_schedule(mut self, schedule: &CostSchedule) -> Self {
+        self.cost_schedule = Some(schedule.clone());
+        self
     }
-}
 
-impl Instance {
-    /// Ensure the range is valid according to the currently available memory size.
-    fn checked_memory_range(
-        memory_data: *mut u8,
-        memory_size: usize,
-        offset: u32,
-        size: usize,
-    ) -> Result<core::ops::Range<usize>, String> {
-        // This is safe given usize::BITS >= u32::BITS, see https://doc.rust-lang.org/std/primitive.usize.html.
-        let offset = offset as usize;
-        let has_memory = memory_data != std::ptr::null_mut();
-        if !has_memory {
-            return Err("no memory is available".to_string());
-        }
-        if offset.checked_add(size).is_none() || (offset + size) > memory_size {
-            return Err("invalid offset or size".to_string());
-        }
-        Ok(offset..offset + size)
+    /// Trap when a metered execution would use more than `limit` ticks. By default, the ticks are unlimited.
+    pub fn gas_limit(mut self, limit: u64) -> Self {
+        self.gas_limit = Some(limit);
+        self
     }
 
-    /// Obtain a read-only slice of underlying memory.
+    /// Pass the calls of functions during the execution to `sink`, resolving the names of
+    /// functions from the name section of the module or its exports.
     ///
-    /// # Safety
-    /// These slices turn invalid if the memory is resized (i.e. via the WebAssembly `memory.grow` instruction)
-    pub unsafe fn checked_memory_slice(&self, offset: u32, size: usize) -> Result<&[u8], String> {
-        let memory_data = sys::fizzy_get_instance_memory_data(self.0.as_ptr());
-        let memory_size = sys::fizzy_get_instance_memory_size(self.0.as_ptr());
-        let range = Instance::checked_memory_range(memory_data, memory_size, offset, size)?;
-        // Slices allow empty length, but data must be a valid pointer.
-        debug_assert!(memory_data != std::ptr::null_mut());
-        let memory = std::slice::from_raw_parts(memory_data, memory_size);
-        Ok(&memory[range])
+    /// The clones of the options share the sink. The functions executed by host functions,
+    /// e.g. with [`Caller`], are not traced.
+    pub fn trace(mut self, sink: TraceSink) -> Self {
+        self.trace = Some(Arc::new(Mutex::new(sink)));
+        self
     }
 
-    /// Obtain a mutable slice of underlying memory.
+    /// Mark the functions entered during the execution in `coverage`, which must be of the module
+    /// of the executed instance.
     ///
-    /// # Safety
-    /// These slices turn invalid if the memory is resized (i.e. via the WebAssembly `memory.grow` instruction)
-    pub unsafe fn checked_memory_slice_mut(
-        &mut self,
-        offset: u32,
-        size: usize,
-    ) -> Result<&mut [u8], String> {
-        let memory_data = sys::fizzy_get_instance_memory_data(self.0.as_ptr());
-        let memory_size = sys::fizzy_get_instance_memory_size(self.0.as_ptr());
-        let range = Instance::checked_memory_range(memory_data, memory_size, offset, size)?;
-        // Slices allow empty length, but data must be a valid pointer.
-        debug_assert!(memory_data != std::ptr::null_mut());
-        let memory = std::slice::from_raw_parts_mut(memory_data, memory_size);
-        Ok(&mut memory[range])
+    /// The map is shared with the options, therefore it accumulates the functions of all
+    /// executions with them. The functions executed by host functions are not marked.
+    pub fn coverage(mut self, coverage: &CoverageMap) -> Self {
+        self.coverage = Some(coverage.clone());
+        self
     }
 
-    /// Returns the current memory size, in bytes.
-    pub fn memory_size(&self) -> usize {
-        unsafe { sys::fizzy_get_instance_memory_size(self.0.as_ptr()) }
+    /// Limit the execution to `max_depth` calls stacked up, lowering the limit of the instance
+    /// given by [`InstantiateOptions::max_call_depth`]. A higher limit is reduced to the limit of
+    /// the instance, which cannot be raised for a single execution.
+    pub fn max_call_depth(mut self, max_depth: u32) -> Self {
+        self.max_call_depth = Some(max_depth);
+        self
     }
 
-    /// Copies memory from `offset` to `target`, for the length of `target.len()`.
-    pub fn memory_get(&self, offset: u32, target: &mut [u8]) -> Result<(), String> {
-        let slice = unsafe { self.checked_memory_slice(offset, target.len())? };
-        target.copy_from_slice(slice);
-        Ok(())
+    /// Collect the statistics of the execution reported by the outcome, e.g.
+    /// [`ExecutionOutcome::max_call_depth_reached`], to choose the limits for a module.
+    ///
+    /// The execution is slowed down by counting the calls, as when traced. The height of the
+    /// operand stack is not reported, as the engine does not expose it.
+    pub fn collect_stats(mut self, collect: bool) -> Self {
+        self.collect_stats = collect;
+        self
     }
 
-    /// Copies memory from `source` to `offset`, for the length of `source.len()`.
-    pub fn memory_set(&mut self, offset: u32, source: &[u8]) -> Result<(), String> {
-        let slice = unsafe { self.checked_memory_slice_mut(offset, source.len())? };
-        slice.copy_from_slice(source);
-        Ok(())
+    /// Coerce the numeric arguments to the types of the parameters when they do not match, for
+    /// front-ends which have a single type of numbers. By default, the types must match exactly.
+    ///
+    /// An i32 parameter accepts an i64 argument which fits in 32 bits, as an unsigned or a signed
+    /// number, an i64 parameter accepts an i32 argument, which is zero-extended like
+    /// [`TypedValue::U32`], and an f64 parameter accepts an f32 argument. An i64 argument out of
+    /// the range of i32 fails the execution with [`Error::ArgumentOutOfRange`] instead of being
+    /// truncated, and the other types remain mismatching.
+    pub fn coerce_arguments(mut self, coerce: bool) -> Self {
+        self.coerce_arguments = coerce;
+        self
     }
 
-    /// Get a read-only pointer to the module.
-    unsafe fn get_module(&self) -> *const sys::FizzyModule {
-        sys::fizzy_get_instance_module(self.0.as_ptr())
+    /// Count the instructions executed, reported by [`ExecutionOutcome::instructions_executed`],
+    /// e.g. to track the cost of changes of the code of a module. The count is exact and the same
+    /// on all platforms.
+    ///
+    /// The execution is metered with the cost of 1 for all instructions and no gas limit,
+    /// therefore the module must be parsed by [`parse_metered`], and the count cannot be combined
+    /// with a [`ExecutionOptions::cost_schedule`]. As the costs, the instructions of a block are
+    /// counted when it is entered, including those after a trap in it.
+    pub fn count_instructions(mut self, count: bool) -> Self {
+        self.count_instructions = count;
+        self
     }
 
-    /// Find index of exported function by name.
-    pub fn find_exported_function_index(&self, name: &str) -> Option<u32> {
-        let module = unsafe { self.get_module() };
-        let name = CString::new(name).expect("CString::new failed");
-        let mut func_idx: u32 = 0;
-        let found = unsafe {
-            sys::fizzy_find_exported_function_index(module, name.as_ptr(), &mut func_idx)
-        };
-        if found {
-            Some(func_idx)
-        } else {
-            None
-        }
+    /// Measure the time spent in the imported functions, accumulated by
+    /// [`Instance::import_stats`]. The calls are counted regardless, the time is not by default,
+    /// as reading the clock around each call slows down the executions calling them often.
+    pub fn time_imports(mut self, time: bool) -> Self {
+        self.time_imports = time;
+        self
+    }
+}
+
+impl Module {
+    /// Get the module of the bytes validated by [`validate_owned`] without validating them again.
+    pub fn from_validated(validated: ValidatedBytes) -> Module {
+        validated.module
+    }
+
+    /// The imports of the module, in the order of the import section.
+    pub fn imports(&self) -> Vec<Import> {
+        let module = self.as_ptr();
+        let import_count = unsafe { sys::fizzy_get_import_count(module) };
+        (0..import_count)
+            .map(|import_idx| {
+                let import = unsafe { sys::fizzy_get_import_description(module, import_idx) };
+                // Import names are validated to be UTF-8 when parsing.
+                let module_name = unsafe { CStr::from_ptr(import.module) }
+                    .to_str()
+                    .expect("import module name is not UTF-8");
+                let name = unsafe { CStr::from_ptr(import.name) }
+                    .to_str()
+                    .expect("import name is not UTF-8");
+                // Only the member of the union corresponding to the kind is defined.
+                let ty = unsafe {
+                    match ExternalKind::from_raw(import.kind) {
+                        ExternalKind::Function => ExternalType::Function(FunctionType::from_raw(
+                            &import.desc.function_type,
+                        )),
+                        ExternalKind::Table => {
+                            ExternalType::Table(Limits::from_raw(&import.desc.table_limits))
+                        }
+                        ExternalKind::Memory => {
+                            ExternalType::Memory(Limits::from_raw(&import.desc.memory_limits))
+                        }
+                        ExternalKind::Global => {
+                            ExternalType::Global(GlobalType::from_raw(&import.desc.global_type))
+                        }
+                    }
+                };
+                Import {
+                    module: module_name.to_string(),
+                    name: name.to_string(),
+                    ty,
+                }
+            })
+            .collect()
     }
 
-    /// Unsafe execution of a given function index `func_idx` with the given values `args`.
+    /// The exports of the module, in the order of the export section.
     ///
-    /// An invalid index, invalid inputs, or invalid depth can cause undefined behaviour.
+    /// The exports added by [`parse_metered`] are not included.
+    pub fn exports(&self) -> Vec<Export> {
+        let module = self.as_ptr();
+        let export_count = unsafe { sys::fizzy_get_export_count(module) };
+        (0..export_count)
+            .map(|export_idx| {
+                let export = unsafe { sys::fizzy_get_export_description(module, export_idx) };
+                let name = unsafe { export_name(&export) };
+                Export {
+                    name: name.to_string(),
+                    kind: ExternalKind::from_raw(export.kind),
+                    index: export.index,
+                }
+            })
+            .filter(|export| !instrument::is_metering_export(&export.name))
+            .collect()
+    }
+
+    /// Create an instance of a module.
     ///
-    /// # Safety
-    /// This function expects a valid `func_idx` and appropriate number of `args`.
-    pub unsafe fn unsafe_execute(&mut self, func_idx: u32, args: &[Value]) -> ExecutionResult {
-        ExecutionResult {
-            0: sys::fizzy_execute(self.0.as_ptr(), func_idx, args.as_ptr()),
-        }
+    /// The module is shared with the instance, and can be instantiated again.
+    pub fn instantiate(&self) -> Result<Instance, Error> {
+        self.instantiate_with(&InstantiateOptions::default())
     }
 
-    /// Find function type for a given index. Must be a valid index otherwise behaviour is undefined.
-    unsafe fn get_function_type(&self, func_idx: u32) -> sys::FizzyFunctionType {
-        let module = self.get_module();
-        sys::fizzy_get_function_type(module, func_idx)
+    /// Create an instance of a module, resolving imported functions by name from `imports`.
+    ///
+    /// Host functions which are not imported by the module are ignored.
+    pub fn instantiate_with_imports(&self, imports: ImportsBuilder) -> Result<Instance, Error> {
+        self.instantiate_with(&InstantiateOptions::new().imports(imports))
     }
 
-    /// Execute a given function of `name` with the given values `args`.
+    /// Create an instance of a module like [`Module::instantiate`], with the memory limited to
+    /// `max_pages` pages, see [`InstantiateOptions::memory_pages_limit`].
+    pub fn instantiate_with_limit(&self, max_pages: u32) -> Result<Instance, Error> {
+        self.instantiate_with(&InstantiateOptions::new().memory_pages_limit(max_pages))
+    }
+
+    /// Create an instance of a module like [`Module::instantiate_with_imports`], with `options`.
+    /// The functions of `imports` replace those given by [`InstantiateOptions::imports`].
+    pub fn instantiate_with_options(
+        &self,
+        imports: ImportsBuilder,
+        options: &InstantiateOptions,
+    ) -> Result<Instance, Error> {
+        let functions = imports.resolve(self.as_ptr())?;
+        self.instantiate_resolved(&functions, options)
+    }
+
+    /// Create an instance of a module with `options`.
     ///
-    /// An error is returned if the function can not be found, inappropriate number of arguments are passed,
-    /// or the supplied types are mismatching.
-    // TODO: consider different approach: Result<TypedValue, Error> where Error::Trap is used for traps
-    pub fn execute(
-        &mut self,
-        name: &str,
-        args: &[TypedValue],
-    ) -> Result<TypedExecutionResult, String> {
-        let func_idx = self.find_exported_function_index(&name);
-        if func_idx.is_none() {
-            return Err("function not found".to_string());
-        }
-        let func_idx = func_idx.unwrap();
+    /// The options are validated together, e.g. the imported memory against the memory pages
+    /// limit, and the instantiation fails if they conflict or do not match the imports of the
+    /// module.
+    pub fn instantiate_with(&self, options: &InstantiateOptions) -> Result<Instance, Error> {
+        let functions = self.resolve_functions(options)?;
+        self.instantiate_resolved(&functions, options)
+    }
 
-        let func_type = unsafe { self.get_function_type(func_idx) };
-        if func_type.inputs_size != args.len() {
-            return Err("argument count mismatch".to_string());
+    /// Create `count` instances of a module with `options`, like [`Module::instantiate_with`],
+    /// resolving the imported functions once for all of them.
+    ///
+    /// With the `rayon` feature, the instances are created in parallel unless the options provide
+    /// an imported table, memory or globals, which the instantiations would modify concurrently.
+    /// The host functions are shared by the instances, which call them one at a time, also from
+    /// the start functions.
+    ///
+    /// If an instantiation fails, the error names the index of the instance and the instances
+    /// created so far are dropped.
+    pub fn instantiate_many(
+        &self,
+        count: usize,
+        options: &InstantiateOptions,
+    ) -> Result<Vec<Instance>, Error> {
+        // The options are validated once for all instances.
+        options.validate()?;
+        let functions = self.resolve_functions(options)?;
+        let instantiate = |index| {
+            self.instantiate_resolved_unchecked(&functions, options)
+                .map_err(|err| instance_failed(index, err))
+        };
+        #[cfg(feature = "rayon")]
+        {
+            if options.table.is_none() && options.memory.is_none() && options.globals.is_empty() {
+                use rayon::prelude::*;
+                let options = SharedOptions(options);
+                let functions = &functions;
+                return (0..count)
+                    .into_par_iter()
+                    .map(|index| {
+                        self.instantiate_resolved_unchecked(functions, options.0)
+                            .map_err(|err| instance_failed(index, err))
+                    })
+                    .collect();
+            }
         }
+        (0..count).map(instantiate).collect()
+    }
 
-        // Validate input types.
-        let supplied_types: Vec<sys::FizzyValueType> = args.iter().map(|v| v.get_type()).collect();
-        let expected_types =
-            unsafe { std::slice::from_raw_parts(func_type.inputs, func_type.inputs_size) };
-        if expected_types != supplied_types {
-            return Err("argument type mismatch".to_string());
-        }
+    /// The telemetry of the instantiation with `functions`.
+    fn instantiate_call(&self, functions: &[Arc<imports::SharedHostFunction>]) -> telemetry::Call {
+        telemetry::Call::instantiate(
+            unsafe { sys::fizzy_get_import_count(self.as_ptr()) },
+            functions.len(),
+        )
+    }
 
-        // Translate to untyped raw values.
-        let args: Vec<Value> = args.iter().map(|v| v.into()).collect();
+    /// The host functions of `options` in the order of the imported functions.
+    fn resolve_functions(
+        &self,
+        options: &InstantiateOptions,
+    ) -> Result<Vec<Arc<imports::SharedHostFunction>>, Error> {
+        match &options.imports {
+            Some(Ok(imports)) => imports::resolve_shared(imports, self.as_ptr()),
+            Some(Err(err)) => Err(err.clone()),
+            None => imports::resolve_shared(&Default::default(), self.as_ptr()),
+        }
+    }
 
-        let ret = unsafe { self.unsafe_execute(func_idx, &args) };
-        Ok(TypedExecutionResult {
-            result: ret.0,
-            value_type: func_type.output,
+    /// Resolve imported functions by name from `imports` once, for creating any number of instances
+    /// with [`InstancePre::instantiate`].
+    ///
+    /// Fails in the cases in which [`Module::instantiate_with_imports`] fails to resolve an imported function.
+    pub fn pre_instantiate(&self, imports: ImportsBuilder) -> Result<InstancePre, Error> {
+        let functions = imports.resolve(self.as_ptr())?;
+        Ok(InstancePre {
+            module: self.clone(),
+            functions,
         })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Create an instance with `functions` in the order of the imported functions.
+    fn instantiate_resolved(
+        &self,
+        functions: &[Arc<imports::SharedHostFunction>],
+        options: &InstantiateOptions,
+    ) -> Result<Instance, Error> {
+        if let Err(err) = options.validate() {
+            self.instantiate_call(functions).failed(&err);
+            return Err(err);
+        }
+        self.instantiate_resolved_unchecked(functions, options)
+    }
+
+    /// Create an instance like `instantiate_resolved`, with `options` which have been
+    /// validated already.
+    fn instantiate_resolved_unchecked(
+        &self,
+        functions: &[Arc<imports::SharedHostFunction>],
+        options: &InstantiateOptions,
+    ) -> Result<Instance, Error> {
+        let call = self.instantiate_call(functions);
+        if options.preallocate_max_memory && options.memory.is_some() {
+            let err = Error::InstantiationFailed(
+                "cannot preallocate imported memory owned by another instance".to_string(),
+            );
+            call.failed(&err);
+            return Err(err);
+        }
+        let globals = match options.resolve_globals(self.as_ptr()) {
+            Ok(globals) => globals,
+            Err(err) => {
+                call.failed(&err);
+                return Err(err);
+            }
+        };
+        let host_trap = Arc::new(imports::TrapSlot::default());
+        let memory_growth = Arc::new(growth::MemoryGrowth::default());
+        let active_gas = Arc::new(metering::ActiveGas::default());
+        let import_timing = Arc::new(AtomicBool::new(false));
+        let host_functions: Vec<_> = functions
+            .iter()
+            .map(|function| {
+                imports::HostContext::new(
+                    function.clone(),
+                    &host_trap,
+                    &memory_growth,
+                    &active_gas,
+                    &import_timing,
+                )
+            })
+            .collect();
+        let external_functions: Vec<sys::FizzyExternalFunction> = host_functions
+            .iter()
+            .map(|context| context.external_function())
+            .collect();
 
-    #[test]
-    fn error_box() {
         let mut err = FizzyErrorBox::new();
-        assert_ne!(unsafe { err.as_mut_ptr() }, std::ptr::null_mut());
-        assert_eq!(err.code(), 0);
-        assert_eq!(err.message(), "");
-        assert_eq!(format!("{}", err), "0 []");
+        // The instantiation copies the data segments to the imported memory, and executes the start
+        // function.
+        let _memory = options.memory_owner.as_ref().map(MemoryGuard::lock);
+        let ptr = unsafe {
+            sys::fizzy_instantiate_shared(
+                self.as_ptr(),
+                external_functions.as_ptr(),
+                external_functions.len(),
+                options
+                    .table
+                    .as_ref()
+                    .map_or(std::ptr::null(), |table| table as *const _),
+                options
+                    .memory
+                    .as_ref()
+                    .map_or(std::ptr::null(), |memory| memory as *const _),
+                globals.as_ptr(),
+                globals.len(),
+                options
+                    .memory_pages_limit
+                    .unwrap_or(sys::FizzyMemoryPagesLimitDefault),
+                err.as_mut_ptr(),
+            )
+        };
+        if ptr.is_null() {
+            debug_assert!(err.code() != 0);
+            let err = err.into();
+            call.failed(&err);
+            Err(err)
+        } else {
+            debug_assert!(err.code() == 0);
+            let instance = Instance {
+                instance: Arc::new(InstancePtr(
+                    unsafe { NonNull::new_unchecked(ptr) },
+                    MemoryLock::default(),
+                )),
+                memory_owner: options.memory_owner.clone(),
+                module: self.clone(),
+                host_functions,
+                host_trap,
+                memory_growth,
+                active_gas,
+                import_timing,
+                export_cache: RefCell::new(HashMap::new()),
+                max_call_depth: options
+                    .max_call_depth
+                    .unwrap_or(ffi::DEFAULT_CALL_DEPTH_LIMIT),
+                executing: Arc::new(AtomicBool::new(false)),
+                alloc_strategy: AllocStrategy::default(),
+                #[cfg(feature = "paranoid-check")]
+                reference_imports: ReferenceImports::default(),
+            };
+            if options.preallocate_max_memory
+                && !unsafe { sys::fizzy_reserve_instance_memory(instance.instance.as_ptr()) }
+            {
+                let err = Error::MemoryAllocationFailed("memory preallocation failed".to_string());
+                call.failed(&err);
+                return Err(err);
+            }
+            let memory_size = instance.memory_size();
+            instance.memory_growth.observe((memory_size / 65536) as u32);
+            call.instantiated(memory_size);
+            Ok(instance)
+        }
     }
+}
 
-    #[test]
-    fn value_conversions() {
-        // NOTE: since the underlying type is a union, a conversion or access to other members is undefined
+/// Prefix the message of the failed instantiation of the instance `index` with the index.
+fn instance_failed(index: usize, err: Error) -> Error {
+    match err {
+        Error::InstantiationFailed(message) => {
+            Error::InstantiationFailed(format!("instance {}: {}", index, message))
+        }
+        Error::MemoryAllocationFailed(message) => {
+            Error::MemoryAllocationFailed(format!("instance {}: {}", index, message))
+        }
+        err @ Error::MissingImport { .. } => err,
+        err => Error::InstantiationFailed(format!("instance {}: {}", index, err)),
+    }
+}
 
-        let v: Value = i32::MIN.into();
-        assert_eq!(v.as_i32(), i32::MIN);
-        let v: Value = i32::MAX.into();
-        assert_eq!(v.as_i32(), i32::MAX);
+/// The options of instantiations in parallel.
+#[cfg(feature = "rayon")]
+struct SharedOptions<'a>(&'a InstantiateOptions);
 
-        let v: Value = u32::MIN.into();
-        assert_eq!(v.as_u32(), u32::MIN);
-        let v: Value = u32::MAX.into();
-        assert_eq!(v.as_u32(), u32::MAX);
+// The options are only read, and the pointers to the imported table, memory and globals are not
+// shared between threads, as instantiations with them are not parallel.
+#[cfg(feature = "rayon")]
+unsafe impl Sync for SharedOptions<'_> {}
 
-        let v: Value = i64::MIN.into();
-        assert_eq!(v.as_i64(), i64::MIN);
-        let v: Value = i64::MAX.into();
-        assert_eq!(v.as_i64(), i64::MAX);
+/// A module with resolved imported functions, ready to be instantiated multiple times.
+///
+/// The host functions are shared by all instances, which call them one at a time.
+pub struct InstancePre {
+    module: Module,
+    /// The host functions in the order of the imported functions of the module.
+    functions: Vec<Arc<imports::SharedHostFunction>>,
+}
 
-        let v: Value = u64::MIN.into();
-        assert_eq!(v.as_u64(), u64::MIN);
-        let v: Value = u64::MAX.into();
-        assert_eq!(v.as_u64(), u64::MAX);
+impl InstancePre {
+    /// Create an instance of the module.
+    pub fn instantiate(&self) -> Result<Instance, Error> {
+        self.module
+            .instantiate_resolved(&self.functions, &InstantiateOptions::default())
+    }
+}
 
-        let v: Value = f32::MIN.into();
-        assert_eq!(v.as_f32(), f32::MIN);
-        let v: Value = f32::MAX.into();
-        assert_eq!(v.as_f32(), f32::MAX);
+/// The mark of an instance executing a function, cleared when dropped.
+struct Executing(Arc<AtomicBool>);
 
-        let v: Value = f64::MIN.into();
-        assert_eq!(v.as_f64(), f64::MIN);
-        let v: Value = f64::MAX.into();
-        assert_eq!(v.as_f64(), f64::MAX);
+impl Executing {
+    fn enter(executing: &Arc<AtomicBool>) -> Result<Self, Error> {
+        if executing.swap(true, Ordering::Acquire) {
+            return Err(Error::Busy);
+        }
+        Ok(Executing(executing.clone()))
     }
+}
 
-    #[test]
-    fn typed_value_conversion() {
-        let v = TypedValue::U32(u32::MIN);
-        assert_eq!(v.as_u32().unwrap(), u32::MIN);
-        assert_eq!(v.as_i32().unwrap(), 0);
-        assert!(v.as_u64().is_none());
-        assert!(v.as_i64().is_none());
-        assert!(v.as_f32().is_none());
-        assert!(v.as_f64().is_none());
-        let v = TypedValue::U32(u32::MAX);
-        assert_eq!(v.as_u32().unwrap(), u32::MAX);
-        assert_eq!(v.as_i32().unwrap(), -1);
-        assert!(v.as_u64().is_none());
-        assert!(v.as_i64().is_none());
-        assert!(v.as_f32().is_none());
-        assert!(v.as_f64().is_none());
+impl Drop for Executing {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
 
-        let v = TypedValue::U64(u64::MIN);
-        assert_eq!(v.as_u64().unwrap(), u64::MIN);
-        assert_eq!(v.as_i64().unwrap(), 0);
-        assert!(v.as_u32().is_none());
-        assert!(v.as_i32().is_none());
-        assert!(v.as_f32().is_none());
-        assert!(v.as_f64().is_none());
-        let v = TypedValue::U64(u64::MAX);
-        assert_eq!(v.as_u64().unwrap(), u64::MAX);
-        assert_eq!(v.as_i64().unwrap(), -1);
-        assert!(v.as_u32().is_none());
-        assert!(v.as_i32().is_none());
-        assert!(v.as_f32().is_none());
-        assert!(v.as_f64().is_none());
+/// The lock of a memory shared by instances, held by a thread executing one of them or
+/// accessing the memory, see [`InstantiateOptions::with_memory_from`].
+///
+/// The lock is reentrant, as a host function may access the memory or execute another of the
+/// instances while the thread holds it.
+#[derive(Default)]
+struct MemoryLock {
+    /// The thread holding the lock and the number of its guards.
+    holder: Mutex<Option<(ThreadId, usize)>>,
+    released: Condvar,
+}
 
-        let v = TypedValue::F32(f32::MIN);
-        assert_eq!(v.as_f32().unwrap(), f32::MIN);
-        let v = TypedValue::F32(f32::MAX);
-        assert_eq!(v.as_f32().unwrap(), f32::MAX);
-        assert!(v.as_u32().is_none());
-        assert!(v.as_i32().is_none());
-        assert!(v.as_u64().is_none());
-        assert!(v.as_i64().is_none());
+/// The guard of the lock of the memory of the instance it refers to, see [`Instance::lock_memory`].
+struct MemoryGuard(Arc<InstancePtr>);
 
-        let v = TypedValue::F64(f64::MIN);
-        assert_eq!(v.as_f64().unwrap(), f64::MIN);
-        let v = TypedValue::F64(f64::MAX);
-        assert_eq!(v.as_f64().unwrap(), f64::MAX);
-        assert!(v.as_u32().is_none());
-        assert!(v.as_i32().is_none());
-        assert!(v.as_u64().is_none());
-        assert!(v.as_i64().is_none());
+impl MemoryGuard {
+    fn lock(owner: &Arc<InstancePtr>) -> Self {
+        let lock = &owner.1;
+        let current = std::thread::current().id();
+        let mut holder = lock.holder.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            match holder.as_mut() {
+                Some((thread, depth)) if *thread == current => {
+                    *depth += 1;
+                    break;
+                }
+                Some(_) => {
+                    holder = lock
+                        .released
+                        .wait(holder)
+                        .unwrap_or_else(|e| e.into_inner())
+                }
+                None => {
+                    *holder = Some((current, 1));
+                    break;
+                }
+            }
+        }
+        MemoryGuard(owner.clone())
     }
+}
 
-    #[test]
-    fn typed_execution_result() {
-        let r_fail = sys::FizzyExecutionResult {
-            trapped: true,
-            has_value: false,
-            value: sys::FizzyValue { i32: 0 },
-        };
-        let r_success_void = sys::FizzyExecutionResult {
-            trapped: false,
-            has_value: false,
-            value: sys::FizzyValue { i32: 0 },
-        };
-        let r_success_u32 = sys::FizzyExecutionResult {
-            trapped: false,
-            has_value: true,
-            value: sys::FizzyValue { i32: u32::MAX },
-        };
-        let r_success_u64 = sys::FizzyExecutionResult {
-            trapped: false,
-            has_value: true,
-            value: sys::FizzyValue { i64: u64::MAX },
-        };
-        let r_success_f32 = sys::FizzyExecutionResult {
-            trapped: false,
-            has_value: true,
-            value: sys::FizzyValue { f32: f32::MAX },
-        };
-        let r_success_f64 = sys::FizzyExecutionResult {
-            trapped: false,
-            has_value: true,
-            value: sys::FizzyValue { f64: f64::MAX },
-        };
+impl Drop for MemoryGuard {
+    fn drop(&mut self) {
+        let lock = &(self.0).1;
+        let mut holder = lock.holder.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, depth)) = holder.as_mut() {
+            *depth -= 1;
+            if *depth == 0 {
+                *holder = None;
+                lock.released.notify_one();
+            }
+        }
+    }
+}
 
-        let r = TypedExecutionResult {
-            result: r_fail,
-            value_type: sys::FizzyValueTypeVoid,
-        };
-        assert!(r.trapped());
-        assert!(r.value().is_none());
+/// The location and size of the memory of an instance, to detect if it has been resized.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct MemoryState {
+    data: *mut u8,
+    size: usize,
+}
 
-        let r = TypedExecutionResult {
-            result: r_success_void,
-            value_type: sys::FizzyValueTypeVoid,
-        };
-        assert!(!r.trapped());
-        assert!(r.value().is_none());
+impl MemoryState {
+    unsafe fn of(instance: *mut sys::FizzyInstance) -> Self {
+        MemoryState {
+            data: sys::fizzy_get_instance_memory_data(instance),
+            size: sys::fizzy_get_instance_memory_size(instance),
+        }
+    }
+}
 
-        let r = TypedExecutionResult {
-            result: r_success_u32,
-            value_type: sys::FizzyValueTypeI32,
-        };
-        assert!(!r.trapped());
-        assert!(r.value().is_some());
-        assert_eq!(r.value().unwrap().as_u32().unwrap(), u32::MAX);
+/// A WebAssembly value of i32/i64/f32/f64.
+///
+/// The value is the union of the C API, where only the member of its type is defined: the
+/// 32-bit members overlap the high half of the 64-bit members on big-endian hosts, therefore the
+/// value must be read as the type it was created from.
+pub type Value = sys::FizzyValue;
 
-        let r = TypedExecutionResult {
-            result: r_success_u64,
-            value_type: sys::FizzyValueTypeI64,
-        };
+impl Value {
+    pub fn as_i32(&self) -> i32 {
+        unsafe { self.i32 as i32 }
+    }
+    pub fn as_u32(&self) -> u32 {
+        unsafe { self.i32 }
+    }
+    pub fn as_i64(&self) -> i64 {
+        unsafe { self.i64 as i64 }
+    }
+    pub fn as_u64(&self) -> u64 {
+        unsafe { self.i64 }
+    }
+    pub fn as_f32(&self) -> f32 {
+        unsafe { self.f32 }
+    }
+    pub fn as_f64(&self) -> f64 {
+        unsafe { self.f64 }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value { i32: v as u32 }
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value { i32: v }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value { i64: v as u64 }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(v: u64) -> Self {
+        Value { i64: v }
+    }
+}
+impl From<f32> for Value {
+    fn from(v: f32) -> Self {
+        Value { f32: v }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value { f64: v }
+    }
+}
+
+/// An export of a module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Export {
+    name: String,
+    kind: ExternalKind,
+    index: u32,
+}
+
+impl Export {
+    /// The name of the export.
+    ///
+    /// The names are valid UTF-8, as the modules with other names are malformed, but may contain
+    /// NUL characters.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the export as bytes, see [`Export::name`].
+    pub fn name_bytes(&self) -> &[u8] {
+        self.name.as_bytes()
+    }
+
+    /// The kind of the exported item.
+    pub fn kind(&self) -> ExternalKind {
+        self.kind
+    }
+
+    /// The index of the exported item among the items of its kind, including the imported ones.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// The type of an imported item.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExternalType {
+    Function(FunctionType),
+    Table(Limits),
+    Memory(Limits),
+    Global(GlobalType),
+}
+
+impl ExternalType {
+    /// The kind of the item.
+    pub fn kind(&self) -> ExternalKind {
+        match self {
+            ExternalType::Function(_) => ExternalKind::Function,
+            ExternalType::Table(_) => ExternalKind::Table,
+            ExternalType::Memory(_) => ExternalKind::Memory,
+            ExternalType::Global(_) => ExternalKind::Global,
+        }
+    }
+}
+
+/// An import of a module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Import {
+    module: String,
+    name: String,
+    ty: ExternalType,
+}
+
+impl Import {
+    /// The name of the module of the import.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// The name of the import.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The type of the imported item.
+    pub fn ty(&self) -> &ExternalType {
+        &self.ty
+    }
+}
+
+raw_api! {
+    /// The result of an untyped execution.
+    ///
+    /// It is a part of the low-level API, enabled by the `raw-api` feature.
+    pub struct ExecutionResult(sys::FizzyExecutionResult);
+}
+
+#[cfg_attr(not(feature = "raw-api"), allow(dead_code))]
+impl ExecutionResult {
+    /// True if execution has resulted in a trap.
+    pub fn trapped(&self) -> bool {
+        self.0.trapped
+    }
+
+    /// The optional return value. Only a single return value is allowed in WebAssembly 1.0.
+    pub fn value(&self) -> Option<Value> {
+        if self.0.has_value {
+            assert!(!self.0.trapped);
+            Some(self.0.value)
+        } else {
+            None
+        }
+    }
+
+    /// Convert the result of a function of type `func_type` to its typed value, or `None` if the
+    /// function has no output.
+    ///
+    /// A trap is reported as [`Error::Trapped`] without the name of the function and as
+    /// [`TrapKind::Wasm`], as the result does not tell the cause, and a result not matching the
+    /// output of `func_type` as [`Error::Other`].
+    pub fn into_typed(self, func_type: &FunctionType) -> Result<Option<TypedValue>, Error> {
+        self.check_trapped()?;
+        self.output_value(func_type.output)
+    }
+
+    /// Fail with [`Error::Trapped`] if the execution has trapped.
+    fn check_trapped(&self) -> Result<(), Error> {
+        if self.0.trapped {
+            return Err(Error::Trapped(TrapInfo {
+                function: None,
+                kind: TrapKind::Wasm,
+                host_error: None,
+            }));
+        }
+        Ok(())
+    }
+
+    /// The value of the result of a function returning `output`, which is `None` if the execution
+    /// has trapped. This is the only interpretation of the results of executions.
+    fn output_value(&self, output: Option<ValueType>) -> Result<Option<TypedValue>, Error> {
+        match (self.0.trapped, self.0.has_value, output) {
+            (true, false, _) | (false, false, None) => Ok(None),
+            (false, true, Some(value_type)) => {
+                value_type.check_supported()?;
+                Ok(Some(TypedValue::from_value(self.0.value, value_type)))
+            }
+            (false, true, None) => Err(Error::Other(
+                "the execution has returned a value of a function without output".to_string(),
+            )),
+            (false, false, Some(value_type)) => Err(Error::Other(format!(
+                "the execution has returned no value of type {}",
+                value_type
+            ))),
+            (true, true, _) => Err(Error::Other(
+                "the execution has trapped with a value".to_string(),
+            )),
+        }
+    }
+}
+
+impl TryFrom<ExecutionResult> for Option<Value> {
+    type Error = Error;
+
+    /// The untyped return value, failing with [`Error::Trapped`] like
+    /// [`ExecutionResult::into_typed`] if the execution has trapped.
+    fn try_from(result: ExecutionResult) -> Result<Self, Error> {
+        result.check_trapped()?;
+        Ok(result.value())
+    }
+}
+
+impl std::fmt::Debug for ExecutionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecutionResult")
+            .field("trapped", &self.0.trapped)
+            .field("has_value", &self.0.has_value)
+            .field(
+                "bits",
+                &format_args!("{:#018x}", unsafe { self.0.value.i64 }),
+            )
+            .finish()
+    }
+}
+
+/// The result of an execution.
+///
+/// With the `serde` feature, it is serialized as `{"trapped":false,"value":{"type":"i32","value":42}}`.
+pub struct TypedExecutionResult {
+    result: ExecutionResult,
+    /// The output type of the executed function.
+    output: Option<ValueType>,
+}
+
+impl TypedExecutionResult {
+    /// True if execution has resulted in a trap.
+    pub fn trapped(&self) -> bool {
+        self.result.trapped()
+    }
+
+    /// The optional return value. Only a single return value is allowed in WebAssembly 1.0.
+    pub fn value(&self) -> Option<TypedValue> {
+        self.result
+            .output_value(self.output)
+            .expect("invalid result of an execution")
+    }
+}
+
+/// The result of an execution with [`Instance::execute_with_options`].
+pub struct ExecutionOutcome {
+    result: TypedExecutionResult,
+    ticks_used: Option<u64>,
+    instructions_executed: Option<u64>,
+    gas_exhausted: bool,
+    call_depth_exceeded: bool,
+    max_call_depth_reached: Option<u32>,
+}
+
+impl ExecutionOutcome {
+    /// The result of the execution.
+    pub fn result(&self) -> &TypedExecutionResult {
+        &self.result
+    }
+
+    /// Take the result of the execution.
+    pub fn into_result(self) -> TypedExecutionResult {
+        self.result
+    }
+
+    /// True if execution has resulted in a trap, including running out of ticks.
+    pub fn trapped(&self) -> bool {
+        self.result.trapped()
+    }
+
+    /// The optional return value.
+    pub fn value(&self) -> Option<TypedValue> {
+        self.result.value()
+    }
+
+    /// The ticks used by a metered execution, or `None` if it was not metered.
+    ///
+    /// If the execution has run out of ticks, the costs of the block which exceeded the limit
+    /// are not included.
+    pub fn ticks_used(&self) -> Option<u64> {
+        self.ticks_used
+    }
+
+    /// The number of instructions executed, or `None` unless counted with
+    /// [`ExecutionOptions::count_instructions`].
+    pub fn instructions_executed(&self) -> Option<u64> {
+        self.instructions_executed
+    }
+
+    /// True if the metered execution has trapped because the costs exceeded the gas limit.
+    pub fn gas_exhausted(&self) -> bool {
+        self.gas_exhausted
+    }
+
+    /// True if the execution has trapped because the calls exceeded the limit of the call depth.
+    pub fn call_depth_exceeded(&self) -> bool {
+        self.call_depth_exceeded
+    }
+
+    /// The most calls stacked up during the execution, including the executed function itself and
+    /// the imported functions, or `None` unless collected with
+    /// [`ExecutionOptions::collect_stats`]. This is the lowest limit of
+    /// [`ExecutionOptions::max_call_depth`] allowing the execution. If the execution has exceeded
+    /// the limit, the call exceeding it is counted, making it one more than the limit. The
+    /// functions executed by host functions, e.g. with [`Caller`], are not counted.
+    pub fn max_call_depth_reached(&self) -> Option<u32> {
+        self.max_call_depth_reached
+    }
+}
+
+impl Instance {
+    /// Ensure the range is valid according to the currently available memory size.
+    fn checked_memory_range(
+        memory_data: *mut u8,
+        memory_size: usize,
+        offset: u32,
+        size: usize,
+    ) -> Result<core::ops::Range<usize>, Error> {
+        // This is safe given usize::BITS >= u32::BITS, see https://doc.rust-lang.org/std/primitive.usize.html.
+        let offset = offset as usize;
+        let has_memory = memory_data != std::ptr::null_mut();
+        if !has_memory {
+            return Err(Error::NoMemoryAvailable);
+        }
+        // The sum of an offset and a size near u32::MAX overflows usize of 32-bit hosts.
+        match offset.checked_add(size) {
+            Some(end) if end <= memory_size => Ok(offset..end),
+            _ => Err(Error::InvalidMemoryOffsetOrSize),
+        }
+    }
+
+    /// The offset of the chunk `done` bytes after `offset`, within a range already checked.
+    fn chunk_offset(offset: u32, done: usize) -> Result<u32, Error> {
+        u32::try_from(done)
+            .ok()
+            .and_then(|done| offset.checked_add(done))
+            .ok_or(Error::InvalidMemoryOffsetOrSize)
+    }
+
+    /// Obtain a mutable slice of the memory of the low-level `instance`.
+    ///
+    /// # Safety
+    /// The instance pointer must be valid, and the slice must not outlive it or any resize of the memory.
+    unsafe fn checked_instance_memory<'a>(
+        instance: *mut sys::FizzyInstance,
+        offset: u32,
+        size: usize,
+    ) -> Result<&'a mut [u8], Error> {
+        let memory_data = sys::fizzy_get_instance_memory_data(instance);
+        let memory_size = sys::fizzy_get_instance_memory_size(instance);
+        let range = Instance::checked_memory_range(memory_data, memory_size, offset, size)?;
+        // Slices allow empty length, but data must be a valid pointer.
+        debug_assert!(memory_data != std::ptr::null_mut());
+        let memory = std::slice::from_raw_parts_mut(memory_data, memory_size);
+        Ok(&mut memory[range])
+    }
+
+    raw_api! {
+        /// Obtain a read-only slice of underlying memory.
+        ///
+        /// # Safety
+        /// These slices turn invalid if the memory is resized (i.e. via the WebAssembly `memory.grow` instruction)
+        /// or modified, also by the instances sharing the memory, see [`InstantiateOptions::with_memory_from`].
+        pub unsafe fn checked_memory_slice(&self, offset: u32, size: usize) -> Result<&[u8], Error> {
+            Instance::checked_instance_memory(self.instance.as_ptr(), offset, size)
+                .map(|slice| &*slice)
+        }
+    }
+
+    raw_api! {
+        /// Obtain a mutable slice of underlying memory.
+        ///
+        /// # Safety
+        /// These slices turn invalid if the memory is resized (i.e. via the WebAssembly `memory.grow` instruction)
+        /// or modified, also by the instances sharing the memory, see [`InstantiateOptions::with_memory_from`].
+        pub unsafe fn checked_memory_slice_mut(
+            &mut self,
+            offset: u32,
+            size: usize,
+        ) -> Result<&mut [u8], Error> {
+            Instance::checked_instance_memory(self.instance.as_ptr(), offset, size)
+        }
+    }
+
+    /// Lock the memory shared with other instances, see [`InstantiateOptions::with_memory_from`],
+    /// or `None` if it is not shared.
+    ///
+    /// A memory starts being shared only when imported from this instance, which borrows it, and
+    /// the instance is not `Sync`, therefore it cannot start being shared while checked.
+    pub(crate) fn lock_memory(&self) -> Option<MemoryGuard> {
+        match &self.memory_owner {
+            Some(owner) => Some(MemoryGuard::lock(owner)),
+            None if Arc::strong_count(&self.instance) > 1 => {
+                Some(MemoryGuard::lock(&self.instance))
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the current memory size, in bytes.
+    ///
+    /// The size is within the range of `usize`, as the memory is allocated by the host, therefore
+    /// the memory of 65536 pages (4 GiB) is not available on 32-bit hosts.
+    pub fn memory_size(&self) -> usize {
+        let _memory = self.lock_memory();
+        unsafe { sys::fizzy_get_instance_memory_size(self.instance.as_ptr()) }
+    }
+
+    /// Copies memory from `offset` to `target`, for the length of `target.len()`.
+    pub fn memory_get(&self, offset: u32, target: &mut [u8]) -> Result<(), Error> {
+        let _memory = self.lock_memory();
+        let slice = unsafe { self.checked_memory_slice(offset, target.len())? };
+        target.copy_from_slice(slice);
+        Ok(())
+    }
+
+    /// Copies memory from `source` to `offset`, for the length of `source.len()`.
+    pub fn memory_set(&mut self, offset: u32, source: &[u8]) -> Result<(), Error> {
+        let _memory = self.lock_memory();
+        let slice = unsafe { self.checked_memory_slice_mut(offset, source.len())? };
+        slice.copy_from_slice(source);
+        Ok(())
+    }
+
+    /// Writes the memory from `offset`, for the length of `len`, to `writer` in chunks of at most
+    /// `chunk` bytes, without copying it into an intermediate buffer.
+    ///
+    /// The range is checked before writing anything. If the memory is resized while writing,
+    /// the remaining chunks are not written and an error is returned.
+    ///
+    /// Fails with [`Error::Other`] if `chunk` is 0.
+    pub fn memory_read_into_writer<W: Write + ?Sized>(
+        &self,
+        offset: u32,
+        len: usize,
+        writer: &mut W,
+        chunk: usize,
+    ) -> Result<(), Error> {
+        if chunk == 0 {
+            return Err(Error::Other("chunk size must not be zero".to_string()));
+        }
+        let _memory = self.lock_memory();
+        let instance = self.instance.as_ptr();
+        let memory = unsafe { MemoryState::of(instance) };
+        unsafe { Instance::checked_instance_memory(instance, offset, len)? };
+        let mut done = 0;
+        while done < len {
+            let size = chunk.min(len - done);
+            if unsafe { MemoryState::of(instance) } != memory {
+                return Err(Error::InvalidMemoryOffsetOrSize);
+            }
+            let chunk_offset = Instance::chunk_offset(offset, done)?;
+            let slice = unsafe { Instance::checked_instance_memory(instance, chunk_offset, size)? };
+            writer.write_all(slice)?;
+            done += size;
+        }
+        Ok(())
+    }
+
+    /// Reads exactly `len` bytes from `reader` into the memory at `offset`, in chunks of at most
+    /// `chunk` bytes, without copying them into an intermediate buffer.
+    ///
+    /// The range is checked before reading anything. If the memory is resized while reading,
+    /// the remaining chunks are not read and an error is returned. If `reader` ends early, the bytes
+    /// read so far remain in the memory.
+    ///
+    /// Fails with [`Error::Other`] if `chunk` is 0.
+    pub fn memory_write_from_reader<R: Read + ?Sized>(
+        &mut self,
+        offset: u32,
+        len: usize,
+        reader: &mut R,
+        chunk: usize,
+    ) -> Result<(), Error> {
+        if chunk == 0 {
+            return Err(Error::Other("chunk size must not be zero".to_string()));
+        }
+        let _memory = self.lock_memory();
+        let instance = self.instance.as_ptr();
+        let memory = unsafe { MemoryState::of(instance) };
+        unsafe { Instance::checked_instance_memory(instance, offset, len)? };
+        let mut done = 0;
+        while done < len {
+            let size = chunk.min(len - done);
+            if unsafe { MemoryState::of(instance) } != memory {
+                return Err(Error::InvalidMemoryOffsetOrSize);
+            }
+            let chunk_offset = Instance::chunk_offset(offset, done)?;
+            let slice = unsafe { Instance::checked_instance_memory(instance, chunk_offset, size)? };
+            reader.read_exact(slice)?;
+            done += size;
+        }
+        Ok(())
+    }
+
+    /// Get a read-only pointer to the module.
+    unsafe fn get_module(&self) -> *const sys::FizzyModule {
+        sys::fizzy_get_instance_module(self.instance.as_ptr())
+    }
+
+    /// Find index of exported function by name.
+    pub fn find_exported_function_index(&self, name: &str) -> Option<u32> {
+        self.with_exported_function(name, |func_idx, _| func_idx)
+    }
+
+    /// Find index of exported function by the bytes of its name.
+    ///
+    /// The names of exports are valid UTF-8, therefore no function is found by other bytes.
+    pub fn find_exported_function_index_bytes(&self, name: &[u8]) -> Option<u32> {
+        std::str::from_utf8(name)
+            .ok()
+            .and_then(|name| self.find_exported_function_index(name))
+    }
+
+    /// Call `f` with the index and type of the exported function `name`, if found.
+    ///
+    /// The function is looked up in the module only the first time, then it is cached.
+    fn with_exported_function<R>(
+        &self,
+        name: &str,
+        f: impl FnOnce(u32, &FunctionType) -> R,
+    ) -> Option<R> {
+        let mut cache = self.export_cache.borrow_mut();
+        if let Some((func_idx, func_type)) = cache.get(name) {
+            return Some(f(*func_idx, func_type));
+        }
+        let func_idx = self.find_exported_function_index_uncached(name)?;
+        let func_type = unsafe { FunctionType::from_raw(&self.get_function_type(func_idx)) };
+        let (func_idx, func_type) = cache
+            .entry(name.to_string())
+            .or_insert((func_idx, func_type));
+        Some(f(*func_idx, func_type))
+    }
+
+    /// Cache all exported functions, so that no lookup by name requires a call into the module.
+    ///
+    /// Without this, only the functions looked up so far are cached.
+    pub fn warm_export_cache(&self) {
+        let module = unsafe { self.get_module() };
+        let mut cache = self.export_cache.borrow_mut();
+        let export_count = unsafe { sys::fizzy_get_export_count(module) };
+        for export_idx in 0..export_count {
+            let export = unsafe { sys::fizzy_get_export_description(module, export_idx) };
+            if export.kind != sys::FizzyExternalKind_FizzyExternalKindFunction {
+                continue;
+            }
+            let name = unsafe { export_name(&export) };
+            if !cache.contains_key(name) {
+                let func_type =
+                    unsafe { FunctionType::from_raw(&self.get_function_type(export.index)) };
+                cache.insert(name.to_string(), (export.index, func_type));
+            }
+        }
+    }
+
+    fn find_exported_function_index_uncached(&self, name: &str) -> Option<u32> {
+        find_exported_function_index(unsafe { self.get_module() }, name)
+    }
+
+    /// The exported global `name`, if found.
+    fn find_exported_global(&self, name: &str) -> Option<sys::FizzyExternalGlobal> {
+        let mut global = std::mem::MaybeUninit::<sys::FizzyExternalGlobal>::uninit();
+        let found = find_by_c_str(name, |name| unsafe {
+            sys::fizzy_find_exported_global(
+                self.instance.as_ptr(),
+                name.as_ptr(),
+                global.as_mut_ptr(),
+            )
+        });
+        if found {
+            Some(unsafe { global.assume_init() })
+        } else {
+            None
+        }
+    }
+
+    raw_api! {
+        /// The exported global `name`, to provide to other instances with
+        /// [`InstantiateOptions::imported_global`].
+        pub fn exported_global(&self, name: &str) -> Option<ffi::ExternalGlobal<'_>> {
+            self.find_exported_global(name)
+                .map(ffi::ExternalGlobal::from_raw)
+        }
+    }
+
+    raw_api! {
+        /// The exported memory `name`, to provide to other instances with
+        /// [`InstantiateOptions::imported_memory`].
+        pub fn exported_memory(&self, name: &str) -> Option<ffi::ExternalMemory<'_>> {
+            let mut memory = std::mem::MaybeUninit::<sys::FizzyExternalMemory>::uninit();
+            let found = find_by_c_str(name, |name| unsafe {
+                sys::fizzy_find_exported_memory(
+                    self.instance.as_ptr(),
+                    name.as_ptr(),
+                    memory.as_mut_ptr(),
+                )
+            });
+            if found {
+                Some(ffi::ExternalMemory::from_raw(unsafe {
+                    memory.assume_init()
+                }))
+            } else {
+                None
+            }
+        }
+    }
+
+    raw_api! {
+        /// The exported table `name`, to provide to other instances with
+        /// [`InstantiateOptions::imported_table`].
+        pub fn exported_table(&self, name: &str) -> Option<ffi::ExternalTable<'_>> {
+            let mut table = std::mem::MaybeUninit::<sys::FizzyExternalTable>::uninit();
+            let found = find_by_c_str(name, |name| unsafe {
+                sys::fizzy_find_exported_table(
+                    self.instance.as_ptr(),
+                    name.as_ptr(),
+                    table.as_mut_ptr(),
+                )
+            });
+            if found {
+                Some(ffi::ExternalTable::from_raw(unsafe { table.assume_init() }))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// The value of the exported global `name`, if found.
+    pub fn global_value(&self, name: &str) -> Option<TypedValue> {
+        let global = self.find_exported_global(name)?;
+        let value = unsafe { &*global.value };
+        Some(TypedValue::from_value(
+            *value,
+            GlobalType::from_raw(&global.type_).value_type,
+        ))
+    }
+
+    /// Set the value of the exported mutable global `name`.
+    ///
+    /// An error is returned if the global can not be found or is immutable, or if the type of `value`
+    /// is mismatching.
+    pub fn set_global_value(&mut self, name: &str, value: TypedValue) -> Result<(), Error> {
+        let global = self
+            .find_exported_global(name)
+            .ok_or_else(|| Error::Other(format!("global {} not found", name)))?;
+        let global_type = GlobalType::from_raw(&global.type_);
+        if !global_type.mutable {
+            return Err(Error::Other(format!("global {} is immutable", name)));
+        }
+        value.value_type().check_supported()?;
+        if global_type.value_type != value.value_type() {
+            return Err(Error::ArgumentTypeMismatch);
+        }
+        unsafe { *global.value = (&value).into() };
+        Ok(())
+    }
+
+    raw_api! {
+        /// Unsafe execution of a given function index `func_idx` with the given values `args`.
+        ///
+        /// An invalid index, invalid inputs, or invalid depth can cause undefined behaviour.
+        ///
+        /// # Safety
+        /// This function expects a valid `func_idx` and appropriate number of `args`. Unlike the
+        /// other executions, it is not checked whether the instance is already executing, see
+        /// [`Error::Busy`].
+        pub unsafe fn execute_unchecked(&mut self, func_idx: u32, args: &[Value]) -> ExecutionResult {
+            let _memory = self.lock_memory();
+            let result = self.execute_with_depth_limit(func_idx, args, self.max_call_depth);
+            self.memory_growth.check(self.instance.as_ptr());
+            result
+        }
+    }
+
+    raw_api! {
+        /// Renamed to [`Instance::execute_unchecked`].
+        ///
+        /// # Safety
+        /// See [`Instance::execute_unchecked`].
+        #[deprecated(since = "0.8.0", note = "renamed to `execute_unchecked`")]
+        pub unsafe fn unsafe_execute(&mut self, func_idx: u32, args: &[Value]) -> ExecutionResult {
+            self.execute_unchecked(func_idx, args)
+        }
+    }
+
+    /// Execute the function `func_idx` like [`Instance::execute_unchecked`], limited to `max_depth`
+    /// calls stacked up.
+    unsafe fn execute_with_depth_limit(
+        &mut self,
+        func_idx: u32,
+        args: &[Value],
+        max_depth: u32,
+    ) -> ExecutionResult {
+        self.host_trap.clear();
+        let mut call_depth_exceeded = false;
+        let result = sys::fizzy_execute_with_depth_limit(
+            self.instance.as_ptr(),
+            func_idx,
+            args.as_ptr(),
+            max_depth,
+            &mut call_depth_exceeded,
+        );
+        if call_depth_exceeded {
+            self.host_trap.set(Trap::call_depth_exceeded());
+        }
+        ExecutionResult(result)
+    }
+
+    /// Execute the function `func_idx` like [`Instance::execute_unchecked`] limited to `max_depth`
+    /// calls stacked up, traced if `sink` is given, marking the entered functions if `coverage` is
+    /// given, and counting the depth of calls if `call_depth` is given.
+    ///
+    /// Fails with [`Error::Busy`] if the instance is already executing, which is only possible
+    /// by unsafe code, as the execution borrows the instance mutably.
+    unsafe fn run(
+        &mut self,
+        func_idx: u32,
+        args: &[Value],
+        sink: Option<&mut TraceSink>,
+        coverage: Option<&CoverageMap>,
+        call_depth: Option<&mut stats::CallDepth>,
+        max_depth: u32,
+    ) -> Result<ExecutionResult, Error> {
+        let _executing = Executing::enter(&self.executing)?;
+        let _memory = self.lock_memory();
+        let result = match (sink, coverage, call_depth) {
+            (None, None, None) => Ok(self.execute_with_depth_limit(func_idx, args, max_depth)),
+            (None, coverage, call_depth) => Ok(coverage::execute(
+                self, func_idx, args, coverage, call_depth, max_depth,
+            )),
+            (Some(sink), coverage, call_depth) => {
+                trace::execute(self, func_idx, args, sink, coverage, call_depth, max_depth)
+            }
+        };
+        self.memory_growth.check(self.instance.as_ptr());
+        result
+    }
+
+    /// Find function type for a given index. Must be a valid index otherwise behaviour is undefined.
+    unsafe fn get_function_type(&self, func_idx: u32) -> sys::FizzyFunctionType {
+        let module = self.get_module();
+        sys::fizzy_get_function_type(module, func_idx)
+    }
+
+    /// Take the trap raised by a host function, or for exceeding the limit of the call depth,
+    /// during the last execution, if any.
+    pub(crate) fn take_host_trap(&self) -> Option<Trap> {
+        self.host_trap.take()
+    }
+
+    /// Execute a given function of `name` with the given values `args`.
+    ///
+    /// An error is returned if the function can not be found, inappropriate number of arguments are passed,
+    /// or the supplied types are mismatching.
+    // TODO: consider different approach: Result<TypedValue, Error> where Error::Trap is used for traps
+    pub fn execute(
+        &mut self,
+        name: &str,
+        args: &[TypedValue],
+    ) -> Result<TypedExecutionResult, Error> {
+        self.execute_traced(name, args, false, None, None, None, self.max_call_depth)
+    }
+
+    /// Execute the function of the bytes of its name like [`Instance::execute`].
+    ///
+    /// The names of exports are valid UTF-8, therefore [`Error::FunctionNotFound`] is returned for
+    /// other bytes.
+    pub fn execute_bytes(
+        &mut self,
+        name: &[u8],
+        args: &[TypedValue],
+    ) -> Result<TypedExecutionResult, Error> {
+        match std::str::from_utf8(name) {
+            Ok(name) => self.execute(name, args),
+            Err(_) => Err(Error::FunctionNotFound),
+        }
+    }
+
+    /// Execute a given function of `name` like [`Instance::execute`], passing the events to `sink`.
+    #[allow(clippy::too_many_arguments)]
+    fn execute_traced(
+        &mut self,
+        name: &str,
+        args: &[TypedValue],
+        coerce: bool,
+        sink: Option<&mut TraceSink>,
+        coverage: Option<&CoverageMap>,
+        call_depth: Option<&mut stats::CallDepth>,
+        max_depth: u32,
+    ) -> Result<TypedExecutionResult, Error> {
+        let call = telemetry::Call::execute(name, args.len());
+        let found = self
+            .with_exported_function(name, |func_idx, func_type| {
+                let args = check_args(func_type, args, coerce)?;
+                Ok((func_idx, func_type.output, args))
+            })
+            .unwrap_or(Err(Error::FunctionNotFound));
+        let (func_idx, output, args) = match found {
+            Ok(found) => found,
+            Err(err) => {
+                call.failed(&err);
+                return Err(err);
+            }
+        };
+        call.function_index(func_idx);
+
+        // Translate to untyped raw values, on the stack unless there are many of them.
+        let ret = if args.len() <= STACK_ARGS_SIZE {
+            let mut values = [Value::from(0u64); STACK_ARGS_SIZE];
+            for (value, arg) in values.iter_mut().zip(args.iter()) {
+                *value = arg.into();
+            }
+            unsafe {
+                self.run(
+                    func_idx,
+                    &values[..args.len()],
+                    sink,
+                    coverage,
+                    call_depth,
+                    max_depth,
+                )
+            }
+        } else {
+            let values: Vec<Value> = args.iter().map(|v| v.into()).collect();
+            unsafe { self.run(func_idx, &values, sink, coverage, call_depth, max_depth) }
+        };
+        let ret = match ret {
+            Ok(ret) => ret,
+            Err(err) => {
+                call.failed(&err);
+                return Err(err);
+            }
+        };
+        call.executed(name, &ret, &self.host_trap);
+        Ok(TypedExecutionResult {
+            result: ret,
+            output,
+        })
+    }
+
+    /// Execute a given function of `name` like [`Instance::execute`], with `options`.
+    pub fn execute_with_options(
+        &mut self,
+        name: &str,
+        args: &[TypedValue],
+        options: &ExecutionOptions,
+    ) -> Result<ExecutionOutcome, Error> {
+        let meter = match (&options.cost_schedule, options.count_instructions) {
+            (Some(_), true) => {
+                return Err(Error::Other(
+                    "instructions cannot be counted in a metered execution".to_string(),
+                ))
+            }
+            (Some(schedule), false) => Some(metering::Meter::start(
+                self,
+                schedule,
+                options.gas_limit.unwrap_or(u64::MAX),
+            )?),
+            (None, true) => {
+                let meter = metering::Meter::start(self, &CostSchedule::uniform(1), u64::MAX)?;
+                // The counting is not a metered execution for the host functions.
+                self.active_gas.clear();
+                Some(meter)
+            }
+            (None, false) => None,
+        };
+        let mut sink = options
+            .trace
+            .as_ref()
+            .map(|sink| sink.lock().unwrap_or_else(|e| e.into_inner()));
+        let max_depth = options
+            .max_call_depth
+            .map_or(self.max_call_depth, |max_depth| {
+                max_depth.min(self.max_call_depth)
+            });
+        let mut call_depth = if options.collect_stats {
+            Some(stats::CallDepth::default())
+        } else {
+            None
+        };
+        self.import_timing
+            .store(options.time_imports, Ordering::Relaxed);
+        let result = self.execute_traced(
+            name,
+            args,
+            options.coerce_arguments,
+            sink.as_deref_mut(),
+            options.coverage.as_ref(),
+            call_depth.as_mut(),
+            max_depth,
+        );
+        self.import_timing.store(false, Ordering::Relaxed);
+        let call_depth_exceeded = self
+            .host_trap
+            .with(|trap| trap.map_or(false, Trap::is_call_depth_exceeded));
+        let (ticks_used, gas_exhausted) = match meter.map(metering::Meter::finish) {
+            Some((ticks_used, gas_exhausted)) => (Some(ticks_used), gas_exhausted),
+            None => (None, false),
+        };
+        let (ticks_used, instructions_executed) = if options.count_instructions {
+            (None, ticks_used)
+        } else {
+            (ticks_used, None)
+        };
+        Ok(ExecutionOutcome {
+            result: result?,
+            ticks_used,
+            instructions_executed,
+            gas_exhausted,
+            call_depth_exceeded,
+            max_call_depth_reached: call_depth.as_ref().map(stats::CallDepth::max),
+        })
+    }
+}
+
+/// The number of arguments passed to `Instance::execute` without a heap allocation.
+const STACK_ARGS_SIZE: usize = 8;
+
+/// The length of the names, including the terminating NUL, converted to C strings without a heap allocation.
+const STACK_NAME_SIZE: usize = 64;
+
+/// The name of `export`, which is valid as long as its module.
+///
+/// Export names are validated to be UTF-8 when parsing, but may contain NUL characters.
+pub(crate) unsafe fn export_name(export: &sys::FizzyExportDescription) -> &str {
+    let bytes = std::slice::from_raw_parts(export.name as *const u8, export.name_size);
+    std::str::from_utf8(bytes).expect("export name is not UTF-8")
+}
+
+/// Find the index of the function `name` exported by `module`.
+pub(crate) fn find_exported_function_index(
+    module: *const sys::FizzyModule,
+    name: &str,
+) -> Option<u32> {
+    if name.contains('\0') {
+        // The C API looks up names only up to the first NUL.
+        let export_count = unsafe { sys::fizzy_get_export_count(module) };
+        return (0..export_count)
+            .map(|export_idx| unsafe { sys::fizzy_get_export_description(module, export_idx) })
+            .find(|export| {
+                export.kind == sys::FizzyExternalKind_FizzyExternalKindFunction
+                    && unsafe { export_name(export) } == name
+            })
+            .map(|export| export.index);
+    }
+    let mut func_idx: u32 = 0;
+    let found = find_by_c_str(name, |name| unsafe {
+        sys::fizzy_find_exported_function_index(module, name.as_ptr(), &mut func_idx)
+    });
+    if found {
+        Some(func_idx)
+    } else {
+        None
+    }
+}
+
+/// Check that the number and the types of `args` match `func_type`, and that they are supported.
+///
+/// If `coerce` is set, the mismatching arguments are coerced to the types of the parameters, see
+/// [`ExecutionOptions::coerce_arguments`], and the arguments are copied only if any of them is.
+pub(crate) fn check_args<'a>(
+    func_type: &FunctionType,
+    args: &'a [TypedValue],
+    coerce: bool,
+) -> Result<Cow<'a, [TypedValue]>, Error> {
+    if func_type.inputs.len() != args.len() {
+        return Err(Error::ArgumentCountMismatch);
+    }
+    for arg in args {
+        arg.value_type().check_supported()?;
+    }
+    let mut checked = Cow::Borrowed(args);
+    for (index, (expected, supplied)) in func_type.inputs.iter().zip(args).enumerate() {
+        if *expected == supplied.value_type() {
+            continue;
+        }
+        if !coerce {
+            return Err(Error::ArgumentTypeMismatch);
+        }
+        checked.to_mut()[index] = coerce_arg(*expected, *supplied, index)?;
+    }
+    Ok(checked)
+}
+
+/// Convert the argument `arg` at `index` to the type `expected` without a loss.
+fn coerce_arg(expected: ValueType, arg: TypedValue, index: usize) -> Result<TypedValue, Error> {
+    match (expected, arg) {
+        (ValueType::I32, TypedValue::U64(v)) => u32::try_from(v)
+            .ok()
+            .or_else(|| i32::try_from(v as i64).ok().map(|v| v as u32))
+            .map(TypedValue::U32)
+            .ok_or(Error::ArgumentOutOfRange { index }),
+        (ValueType::I64, TypedValue::U32(v)) => Ok(TypedValue::U64(v.into())),
+        (ValueType::F64, TypedValue::F32(v)) => Ok(TypedValue::F64(v.into())),
+        _ => Err(Error::ArgumentTypeMismatch),
+    }
+}
+
+/// Look up `name` by `f` with the name converted to a C string.
+///
+/// The names containing a NUL byte are not found, without calling `f`, as the C API would look up
+/// only the part before it.
+fn find_by_c_str(name: &str, f: impl FnOnce(&CStr) -> bool) -> bool {
+    let bytes = name.as_bytes();
+    if bytes.contains(&0) {
+        return false;
+    }
+    if bytes.len() < STACK_NAME_SIZE {
+        let mut buf = [0u8; STACK_NAME_SIZE];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        match CStr::from_bytes_with_nul(&buf[..=bytes.len()]) {
+            Ok(name) => f(name),
+            Err(_) => false,
+        }
+    } else {
+        match CString::new(name) {
+            Ok(name) => f(&name),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::{any, prop, ProptestConfig, Strategy};
+    use proptest::{prop_assert_eq, prop_oneof, proptest};
+
+    /* wat2wasm
+    (module
+      (memory (export "mem") 1 2)
+      (global (export "g") (mut i32) (i32.const 0))
+      (func (export "run") (param i32) (result i32)
+        (loop (br_if 0 (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+        (i32.add (i32.load (local.get 0)) (memory.grow (memory.size))))
+      (func (export "set") (param i64 f32)
+        (i64.store (i32.const 8) (local.get 0))
+        (f32.store (i32.const 16) (local.get 1))
+        (global.set 0 (i32.const 1)))
+    )
+    */
+    const ARBITRARY_INPUTS_SEED: &str = "0061736d01000000010b0260017f017f60027e7d0003030200010504010101020606017f0141000b0717040372756e0000037365740001036d656d0200016703000a2f0218000340200041016b22000d000b20002802003f0040006a0b14004108200037030041102001380200410124000b";
+
+    /// The names of the exports of `ARBITRARY_INPUTS_SEED`, and names close to them.
+    const ARBITRARY_INPUTS_NAMES: &[&str] = &[
+        "run", "set", "mem", "g", "", "run\0", "\0", "r\u{fc}n", "missing",
+    ];
+
+    /// The module of `ARBITRARY_INPUTS_SEED` with up to 3 bytes replaced, inserted or truncated at,
+    /// or arbitrary bytes.
+    fn arbitrary_module() -> impl Strategy<Value = Vec<u8>> {
+        let seed = hex::decode(ARBITRARY_INPUTS_SEED).unwrap();
+        let edits =
+            prop::collection::vec((any::<prop::sample::Index>(), 0..3u8, any::<u8>()), 0..4);
+        let mutated = edits.prop_map(move |edits| {
+            let mut input = seed.clone();
+            for (pos, edit, byte) in edits {
+                if input.is_empty() {
+                    break;
+                }
+                let pos = pos.index(input.len());
+                match edit {
+                    0 => input[pos] = byte,
+                    1 => input.truncate(pos),
+                    _ => input.insert(pos, byte),
+                }
+            }
+            input
+        });
+        prop_oneof![mutated, prop::collection::vec(any::<u8>(), 0..64)]
+    }
+
+    fn arbitrary_value() -> impl Strategy<Value = TypedValue> {
+        prop_oneof![
+            any::<u32>().prop_map(TypedValue::U32),
+            any::<u64>().prop_map(TypedValue::U64),
+            any::<u32>().prop_map(|bits| TypedValue::F32(f32::from_bits(bits))),
+            any::<u64>().prop_map(|bits| TypedValue::F64(f64::from_bits(bits))),
+        ]
+    }
+
+    fn arbitrary_name() -> impl Strategy<Value = String> {
+        prop_oneof![
+            prop::sample::select(ARBITRARY_INPUTS_NAMES).prop_map(|name| name.to_string()),
+            any::<String>(),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        // A panic fails the test with the smallest inputs found to cause it.
+        #[test]
+        fn no_panics_with_arbitrary_inputs(
+            input in arbitrary_module(),
+            args in prop::collection::vec(arbitrary_value(), 0..3),
+            name in arbitrary_name(),
+            offset in 0..1u32 << 18,
+            len in 0..1usize << 17,
+            chunk in 0..3usize,
+        ) {
+            prop_assert_eq!(validate(&input).is_ok(), parse(&input).is_ok());
+            let module = match parse_metered(&input) {
+                Ok(module) => module,
+                Err(_) => return Ok(()),
+            };
+            module.exports();
+            let mut instance = match module.instantiate() {
+                Ok(instance) => instance,
+                Err(_) => return Ok(()),
+            };
+            instance.find_exported_function_index(&name);
+            instance.find_exported_function_index_bytes(name.as_bytes());
+            instance.global_value(&name);
+            let _ = instance
+                .set_global_value(&name, args.first().copied().unwrap_or(TypedValue::U32(0)));
+            instance.exported_memory(&name);
+            instance.exported_table(&name);
+            let options = ExecutionOptions::new()
+                .cost_schedule(&CostSchedule::uniform(1))
+                .gas_limit(10_000);
+            let _ = instance.execute_with_options(&name, &args, &options);
+            let _ = instance.execute_with_options(
+                &name,
+                &args,
+                &options.clone().coerce_arguments(true),
+            );
+            let mut buffer = vec![0; len];
+            let _ = instance.memory_get(offset, &mut buffer);
+            let _ = instance.memory_set(offset, &buffer);
+            let _ = instance.memory_read_into_writer(offset, len, &mut std::io::sink(), chunk);
+            let _ = instance.memory_write_from_reader(offset, len, &mut std::io::repeat(0), chunk);
+        }
+    }
+
+    #[test]
+    fn error_box() {
+        let mut err = FizzyErrorBox::new();
+        assert_ne!(unsafe { err.as_mut_ptr() }, std::ptr::null_mut());
+        assert_eq!(err.code(), 0);
+        assert_eq!(err.message(), "");
+        assert_eq!(format!("{}", err), "0 []");
+    }
+
+    #[test]
+    fn error_predicates() {
+        /// The error with its messages replaced, as by another version of the engine.
+        fn reworded(err: &Error) -> Error {
+            let message = "reworded".to_string();
+            match err.clone() {
+                Error::MalformedModule(_) => Error::MalformedModule(message),
+                Error::TextFormat(_) => Error::TextFormat(message),
+                Error::InvalidModule(_) => Error::InvalidModule(message),
+                Error::InstantiationFailed(_) => Error::InstantiationFailed(message),
+                Error::MemoryAllocationFailed(_) => Error::MemoryAllocationFailed(message),
+                Error::Other(_) => Error::Other(message),
+                Error::Trapped(TrapInfo {
+                    function,
+                    kind: TrapKind::Host(_),
+                    host_error,
+                }) => Error::Trapped(TrapInfo {
+                    function,
+                    kind: TrapKind::Host(message),
+                    host_error,
+                }),
+                err => err,
+            }
+        }
+
+        let predicates = |err: &Error| {
+            [
+                err.is_missing_import(),
+                err.is_malformed(),
+                err.is_invalid(),
+                err.is_trap(),
+                err.is_resource_limit(),
+            ]
+        };
+
+        /* wat2wasm --no-check
+        (module (func (result i32)))
+        */
+        let invalid = hex::decode("0061736d010000000105016000017f030201000a040102000b").unwrap();
+        /* wat2wasm
+        (module (func (import "env" "f")))
+        */
+        let importing = hex::decode("0061736d0100000001040160000002090103656e7601660000").unwrap();
+        let errors = [
+            (parse(&[0x00]).err().unwrap(), 1),
+            (parse(&invalid).err().unwrap(), 2),
+            (parse(&importing).unwrap().instantiate().err().unwrap(), 0),
+            (
+                Error::Trapped(TrapInfo::new("run", Some(Trap::new("denied")))),
+                3,
+            ),
+            (
+                Error::LimitExceeded {
+                    which: "max_functions".to_string(),
+                    limit: 1,
+                    actual: 2,
+                },
+                4,
+            ),
+            (
+                Error::MemoryAllocationFailed("out of memory".to_string()),
+                4,
+            ),
+        ];
+        for (err, predicate) in errors.iter() {
+            let mut expected = [false; 5];
+            expected[*predicate] = true;
+            assert_eq!(predicates(err), expected, "{:?}", err);
+            assert_eq!(predicates(&reworded(err)), expected, "{:?}", err);
+            assert_eq!(reworded(err).kind(), err.kind());
+        }
+        assert_eq!(errors[1].0.kind(), ErrorKind::InvalidModule);
+        assert_eq!(errors[2].0.kind(), ErrorKind::MissingImport);
+
+        // Exceeding the call depth is a trap as well as a limit.
+        let err = Error::Trapped(TrapInfo {
+            function: Some("rec".to_string()),
+            kind: TrapKind::CallDepthExceeded,
+            host_error: None,
+        });
+        assert_eq!(predicates(&err), [false, false, false, true, true]);
+        assert!(!Error::InstantiationFailed("reworded".to_string()).is_missing_import());
+    }
+
+    #[test]
+    fn reference_types() {
+        /* wat2wasm
+        (module (func (param funcref)))
+        */
+        let input = hex::decode("0061736d0100000001050160017000030201000a040102000b").unwrap();
+        let unsupported = Err(Error::UnsupportedType("funcref".to_string()));
+        assert_eq!(parse(&input).map(|_| ()), unsupported);
+        assert_eq!(validate(&input), unsupported);
+
+        /* wat2wasm
+        (module (func (export "add") (param i64 i64) (result i64) (i64.add (local.get 0) (local.get 1))))
+        */
+        let input = hex::decode(
+            "0061736d0100000001070160027e7e017e030201000707010361646400000a09010700200020017c0b",
+        )
+        .unwrap();
+        let module = parse(&input).unwrap();
+        let mut instance = module.instantiate().unwrap();
+        assert_eq!(
+            instance
+                .execute("add", &[TypedValue::FuncRef, TypedValue::U64(1)])
+                .map(|_| ()),
+            unsupported
+        );
+
+        let mut imports = ImportsBuilder::new();
+        imports.func_with_type(
+            "env",
+            "f",
+            FunctionType::new(vec![ValueType::ExternRef], None),
+            |_, _| Ok(None),
+        );
+        assert_eq!(
+            module.instantiate_with_imports(imports).map(|_| ()),
+            Err(Error::UnsupportedType("externref".to_string()))
+        );
+        let mut imports = ImportsBuilder::new();
+        assert_eq!(
+            imports
+                .func_dyn("env", "f", "(funcref) -> i32", Box::new(|_, _| Ok(None)))
+                .map(|_| ()),
+            unsupported
+        );
+    }
+
+    #[test]
+    fn value_conversions() {
+        // NOTE: since the underlying type is a union, a conversion or access to other members is undefined
+
+        let v: Value = i32::MIN.into();
+        assert_eq!(v.as_i32(), i32::MIN);
+        let v: Value = i32::MAX.into();
+        assert_eq!(v.as_i32(), i32::MAX);
+
+        let v: Value = u32::MIN.into();
+        assert_eq!(v.as_u32(), u32::MIN);
+        let v: Value = u32::MAX.into();
+        assert_eq!(v.as_u32(), u32::MAX);
+
+        let v: Value = i64::MIN.into();
+        assert_eq!(v.as_i64(), i64::MIN);
+        let v: Value = i64::MAX.into();
+        assert_eq!(v.as_i64(), i64::MAX);
+
+        let v: Value = u64::MIN.into();
+        assert_eq!(v.as_u64(), u64::MIN);
+        let v: Value = u64::MAX.into();
+        assert_eq!(v.as_u64(), u64::MAX);
+
+        let v: Value = f32::MIN.into();
+        assert_eq!(v.as_f32(), f32::MIN);
+        let v: Value = f32::MAX.into();
+        assert_eq!(v.as_f32(), f32::MAX);
+
+        let v: Value = f64::MIN.into();
+        assert_eq!(v.as_f64(), f64::MIN);
+        let v: Value = f64::MAX.into();
+        assert_eq!(v.as_f64(), f64::MAX);
+    }
+
+    #[test]
+    fn value_layout() {
+        // The layout of the union of the C API, whose members all start at its first byte.
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+        let v = Value::from(0x0102_0304_0506_0708u64);
+        let bytes: [u8; 8] = unsafe { std::mem::transmute(v) };
+        assert_eq!(bytes, 0x0102_0304_0506_0708u64.to_ne_bytes());
+        // The 32-bit member overlaps the first 4 bytes, the low half only on little-endian hosts.
+        let expected = if cfg!(target_endian = "little") {
+            0x0506_0708
+        } else {
+            0x0102_0304
+        };
+        assert_eq!(unsafe { v.i32 }, expected);
+    }
+
+    #[test]
+    fn typed_value_conversion() {
+        let v = TypedValue::U32(u32::MIN);
+        assert_eq!(v.as_u32().unwrap(), u32::MIN);
+        assert_eq!(v.as_i32().unwrap(), 0);
+        assert!(v.as_u64().is_none());
+        assert!(v.as_i64().is_none());
+        assert!(v.as_f32().is_none());
+        assert!(v.as_f64().is_none());
+        let v = TypedValue::U32(u32::MAX);
+        assert_eq!(v.as_u32().unwrap(), u32::MAX);
+        assert_eq!(v.as_i32().unwrap(), -1);
+        assert!(v.as_u64().is_none());
+        assert!(v.as_i64().is_none());
+        assert!(v.as_f32().is_none());
+        assert!(v.as_f64().is_none());
+
+        let v = TypedValue::U64(u64::MIN);
+        assert_eq!(v.as_u64().unwrap(), u64::MIN);
+        assert_eq!(v.as_i64().unwrap(), 0);
+        assert!(v.as_u32().is_none());
+        assert!(v.as_i32().is_none());
+        assert!(v.as_f32().is_none());
+        assert!(v.as_f64().is_none());
+        let v = TypedValue::U64(u64::MAX);
+        assert_eq!(v.as_u64().unwrap(), u64::MAX);
+        assert_eq!(v.as_i64().unwrap(), -1);
+        assert!(v.as_u32().is_none());
+        assert!(v.as_i32().is_none());
+        assert!(v.as_f32().is_none());
+        assert!(v.as_f64().is_none());
+
+        let v = TypedValue::F32(f32::MIN);
+        assert_eq!(v.as_f32().unwrap(), f32::MIN);
+        let v = TypedValue::F32(f32::MAX);
+        assert_eq!(v.as_f32().unwrap(), f32::MAX);
+        assert!(v.as_u32().is_none());
+        assert!(v.as_i32().is_none());
+        assert!(v.as_u64().is_none());
+        assert!(v.as_i64().is_none());
+
+        let v = TypedValue::F64(f64::MIN);
+        assert_eq!(v.as_f64().unwrap(), f64::MIN);
+        let v = TypedValue::F64(f64::MAX);
+        assert_eq!(v.as_f64().unwrap(), f64::MAX);
+        assert!(v.as_u32().is_none());
+        assert!(v.as_i32().is_none());
+        assert!(v.as_u64().is_none());
+        assert!(v.as_i64().is_none());
+    }
+
+    #[test]
+    fn typed_value_display() {
+        assert_eq!(TypedValue::U32(u32::MAX).to_string(), "i32:4294967295");
+        assert_eq!(TypedValue::U64(42).to_string(), "i64:42");
+        assert_eq!(TypedValue::F32(1.0).to_string(), "f32:1.0");
+        assert_eq!(TypedValue::F64(-0.5).to_string(), "f64:-0.5");
+        assert_eq!(TypedValue::F64(f64::NAN).to_string(), "f64:NaN");
+    }
+
+    #[test]
+    fn typed_execution_result() {
+        let r_fail = sys::FizzyExecutionResult {
+            trapped: true,
+            has_value: false,
+            value: sys::FizzyValue { i32: 0 },
+        };
+        let r_success_void = sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: false,
+            value: sys::FizzyValue { i32: 0 },
+        };
+        let r_success_u32 = sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: true,
+            value: sys::FizzyValue { i32: u32::MAX },
+        };
+        let r_success_u64 = sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: true,
+            value: sys::FizzyValue { i64: u64::MAX },
+        };
+        let r_success_f32 = sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: true,
+            value: sys::FizzyValue { f32: f32::MAX },
+        };
+        let r_success_f64 = sys::FizzyExecutionResult {
+            trapped: false,
+            has_value: true,
+            value: sys::FizzyValue { f64: f64::MAX },
+        };
+
+        let r = TypedExecutionResult {
+            result: ExecutionResult(r_fail),
+            output: None,
+        };
+        assert!(r.trapped());
+        assert!(r.value().is_none());
+
+        let r = TypedExecutionResult {
+            result: ExecutionResult(r_success_void),
+            output: None,
+        };
+        assert!(!r.trapped());
+        assert!(r.value().is_none());
+
+        let r = TypedExecutionResult {
+            result: ExecutionResult(r_success_u32),
+            output: Some(ValueType::I32),
+        };
+        assert!(!r.trapped());
+        assert!(r.value().is_some());
+        assert_eq!(r.value().unwrap().as_u32().unwrap(), u32::MAX);
+
+        let r = TypedExecutionResult {
+            result: ExecutionResult(r_success_u64),
+            output: Some(ValueType::I64),
+        };
         assert!(!r.trapped());
         assert!(r.value().is_some());
         assert_eq!(r.value().unwrap().as_u64().unwrap(), u64::MAX);
 
-        let r = TypedExecutionResult {
-            result: r_success_f32,
-            value_type: sys::FizzyValueTypeF32,
-        };
-        assert!(!r.trapped());
-        assert!(r.value().is_some());
-        assert_eq!(r.value().unwrap().as_f32().unwrap(), f32::MAX);
+        let r = TypedExecutionResult {
+            result: ExecutionResult(r_success_f32),
+            output: Some(ValueType::F32),
+        };
+        assert!(!r.trapped());
+        assert!(r.value().is_some());
+        assert_eq!(r.value().unwrap().as_f32().unwrap(), f32::MAX);
+
+        let r = TypedExecutionResult {
+            result: ExecutionResult(r_success_f64),
+            output: Some(ValueType::F64),
+        };
+        assert!(!r.trapped());
+        assert!(r.value().is_some());
+        assert_eq!(r.value().unwrap().as_f64().unwrap(), f64::MAX);
+    }
+
+    #[test]
+    fn validate_wasm() {
+        // Empty
+        assert!(validate(&[]).err().unwrap().is_malformed());
+        // Too short
+        assert!(validate(&[0x00]).err().unwrap().is_malformed());
+        // Valid
+        assert!(validate(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).is_ok());
+        // Invalid version
+        assert!(validate(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01])
+            .err()
+            .unwrap()
+            .is_malformed());
+    }
+
+    #[test]
+    fn validate_batch_wasm() {
+        let valid = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        let invalid_version = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01];
+        // The function section declares a function without a body.
+        let missing_code = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+        ];
+        let inputs: Vec<&[u8]> = (0..100)
+            .map(|i| match i % 4 {
+                0 => &valid[..],
+                1 => &invalid_version[..],
+                2 => &missing_code[..],
+                _ => &[][..],
+            })
+            .collect();
+
+        let results = validate_batch(inputs.iter().copied());
+        let expected: Vec<Result<(), Error>> = inputs.iter().map(validate).collect();
+        assert_eq!(results, expected);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_err());
+        assert!(results[3].is_err());
+
+        assert!(validate_batch(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn validate_owned_wasm() {
+        /* wat2wasm
+        (module
+          (func (export "foo") (result i32) (i32.const 42))
+        )
+        */
+        let input =
+            hex::decode("0061736d010000000105016000017f0302010007070103666f6f00000a06010400412a0b")
+                .unwrap();
+
+        assert_eq!(
+            validate_owned(vec![0x00]).err().unwrap(),
+            Error::MalformedModule("invalid wasm module prefix".to_string())
+        );
+
+        let validated = validate_owned(input.clone()).unwrap();
+        assert_eq!(validated.as_bytes(), input.as_slice());
+        assert_eq!(validated.into_bytes(), input);
+
+        let validated = validate_owned(input).unwrap();
+        let bytes = validated.as_bytes().as_ptr();
+        let module = Module::from_validated(validated);
+        // The module keeps the validated bytes instead of a copy.
+        assert_eq!(module.bytes().as_ptr(), bytes);
+        let mut instance = module.instantiate().unwrap();
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(42)));
+    }
+
+    #[test]
+    fn validate_owned_keeps_the_validated_bytes() {
+        /* wat2wasm
+        (module
+          (func (export "foo") (result i32) (i32.const 42))
+        )
+        */
+        let input =
+            hex::decode("0061736d010000000105016000017f0302010007070103666f6f00000a06010400412a0b")
+                .unwrap();
+
+        let validated = validate_owned(input.clone()).unwrap();
+        // A copy of the bytes modified after the validation, returning 43 instead, does not change
+        // the module, which runs the validated bytes.
+        let mut modified = validated.as_bytes().to_vec();
+        let constant = modified.len() - 2;
+        modified[constant] = 43;
+        let mut instance = Module::from_validated(validate_owned(modified).unwrap())
+            .instantiate()
+            .unwrap();
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(43)));
+
+        let module = Module::from_validated(validated);
+        assert_eq!(module.bytes(), input.as_slice());
+        let mut instance = module.instantiate().unwrap();
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(42)));
+    }
+
+    #[test]
+    fn parse_wasm() {
+        assert!(parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).is_ok());
+        assert!(parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01])
+            .err()
+            .unwrap()
+            .is_malformed());
+    }
+
+    #[test]
+    fn module_exports() {
+        /* wat2wasm
+        (module
+          (func $f (import "env" "f"))
+          (func $g (export "g"))
+          (global (export "glob") i32 (i32.const 0))
+          (table (export "tab") 0 funcref)
+          (memory (export "mem") 1)
+          (export "f" (func $f))
+        )
+        */
+        let input = hex::decode("0061736d0100000001040160000002090103656e76016600000302010004040170000005030100010606017f0041000b071c050167000104676c6f620300037461620100036d656d0200016600000a040102000b").unwrap();
+        let module = parse(&input).unwrap();
+        let exports: Vec<_> = module
+            .exports()
+            .iter()
+            .map(|export| (export.name().to_string(), export.kind(), export.index()))
+            .collect();
+        assert_eq!(
+            exports,
+            [
+                ("g".to_string(), ExternalKind::Function, 1),
+                ("glob".to_string(), ExternalKind::Global, 0),
+                ("tab".to_string(), ExternalKind::Table, 0),
+                ("mem".to_string(), ExternalKind::Memory, 0),
+                ("f".to_string(), ExternalKind::Function, 0),
+            ]
+        );
+        assert!(parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00])
+            .unwrap()
+            .exports()
+            .is_empty());
+    }
+
+    #[test]
+    fn module_imports() {
+        /* wat2wasm
+        (module
+          (func (import "env" "f") (param i32 i64) (result f32))
+          (global (import "env" "g") (mut f64))
+          (table (import "env" "t") 1 2 funcref)
+          (memory (import "mem" "m") 1)
+          (func (import "env" "h"))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260027f7e017d600000022e0503656e760166000003656e760167037c0103656e7601740170010102036d656d016d02000103656e7601680001").unwrap();
+        let imports = parse(&input).unwrap().imports();
+        let imports: Vec<_> = imports
+            .iter()
+            .map(|import| (import.module(), import.name(), import.ty().clone()))
+            .collect();
+        assert_eq!(
+            imports,
+            [
+                (
+                    "env",
+                    "f",
+                    ExternalType::Function(FunctionType::new(
+                        vec![ValueType::I32, ValueType::I64],
+                        Some(ValueType::F32)
+                    ))
+                ),
+                (
+                    "env",
+                    "g",
+                    ExternalType::Global(GlobalType::new(ValueType::F64, true))
+                ),
+                ("env", "t", ExternalType::Table(Limits::new(1, Some(2)))),
+                ("mem", "m", ExternalType::Memory(Limits::new(1, None))),
+                (
+                    "env",
+                    "h",
+                    ExternalType::Function(FunctionType::new(vec![], None))
+                ),
+            ]
+        );
+        assert_eq!(imports[1].2.kind(), ExternalKind::Global);
+    }
+
+    #[test]
+    fn instantiate_wasm() {
+        let module = parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+    }
+
+    #[test]
+    fn clone_module() {
+        let module = parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+        assert!(module.is_ok());
+        let module = module.unwrap();
+        let module2 = module.clone();
+        let instance = module.instantiate();
+        assert!(instance.is_ok());
+        let instance = module2.instantiate();
+        assert!(instance.is_ok());
+    }
+
+    #[test]
+    fn export_names_as_bytes() {
+        /* wat2wasm
+        (module
+          (func (export "a\00b") (result i32) (i32.const 1))
+          (func (export "a") (result i32) (i32.const 2))
+        )
+        */
+        let input = hex::decode("0061736d010000000105016000017f0303020000070b02036100620000016100010a0b02040041010b040041020b").unwrap();
+        let module = parse(&input).unwrap();
+        let names: Vec<&[u8]> = vec![&b"a\0b"[..], &b"a"[..]];
+        assert_eq!(
+            module
+                .exports()
+                .iter()
+                .map(Export::name_bytes)
+                .collect::<Vec<_>>(),
+            names
+        );
+        assert_eq!(module.exports()[0].name(), "a\0b");
+
+        // The names are not truncated at NUL.
+        let mut instance = module.instantiate().unwrap();
+        assert_eq!(
+            instance.find_exported_function_index_bytes(b"a\0b"),
+            Some(0)
+        );
+        assert_eq!(instance.find_exported_function_index_bytes(b"a"), Some(1));
+        assert_eq!(instance.find_exported_function_index_bytes(b"a\0"), None);
+        assert_eq!(instance.find_exported_function_index("a\0b"), Some(0));
+        let result = instance.execute_bytes(b"a\0b", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+        let result = instance.execute("a", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(2)));
+        assert_eq!(
+            instance.execute_bytes(b"a\xff", &[]).err(),
+            Some(Error::FunctionNotFound)
+        );
+        assert_eq!(instance.find_exported_function_index_bytes(b"\xff"), None);
+
+        // The names which are not UTF-8 are malformed.
+        // The function is exported by the name of the single byte 0xff.
+        let input =
+            hex::decode("0061736d010000000105016000017f0302010007050101ff00000a0601040041010b")
+                .unwrap();
+        assert_eq!(
+            parse(&input).err(),
+            Some(Error::MalformedModule("invalid UTF-8".to_string()))
+        );
+    }
+
+    #[test]
+    fn export_cache() {
+        /* wat2wasm
+        (module
+          (func $f (export "foo") (result i32) (i32.const 42))
+          (func $g (export "bar") (param i64))
+          (export "baz" (func $f))
+          (global (export "g1") i32 (i32.const 0))
+          (memory (export "mem") 1 2)
+        )
+        */
+        let input = hex::decode("0061736d010000000109026000017f60017e0003030200010504010101020606017f0041000b071e0503666f6f00000362617200010362617a00000267310300036d656d02000a09020400412a0b02000b").unwrap();
+
+        let instance = parse(&input).unwrap().instantiate().unwrap();
+        // Only the functions looked up are cached.
+        assert_eq!(instance.find_exported_function_index(&"baz"), Some(0));
+        assert_eq!(instance.find_exported_function_index(&"g1"), None);
+        assert_eq!(instance.find_exported_function_index(&"mem"), None);
+        assert_eq!(
+            *instance.export_cache.borrow(),
+            vec![(
+                "baz".to_string(),
+                (0, FunctionType::new(vec![], Some(ValueType::I32)))
+            )]
+            .into_iter()
+            .collect()
+        );
+        // Cached lookups give the same results.
+        assert_eq!(instance.find_exported_function_index(&"baz"), Some(0));
+        assert_eq!(instance.export_cache.borrow().len(), 1);
+
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        instance.warm_export_cache();
+        assert_eq!(
+            *instance.export_cache.borrow(),
+            vec![
+                (
+                    "foo".to_string(),
+                    (0, FunctionType::new(vec![], Some(ValueType::I32)))
+                ),
+                (
+                    "bar".to_string(),
+                    (1, FunctionType::new(vec![ValueType::I64], None))
+                ),
+                (
+                    "baz".to_string(),
+                    (0, FunctionType::new(vec![], Some(ValueType::I32)))
+                ),
+            ]
+            .into_iter()
+            .collect()
+        );
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(42)));
+        let result = instance.execute("bar", &[TypedValue::U64(1)]).unwrap();
+        assert_eq!(result.value(), None);
+        let result = instance.execute("bar", &[TypedValue::U32(1)]);
+        assert_eq!(result.err().unwrap(), Error::ArgumentTypeMismatch);
+        assert_eq!(
+            instance.execute("g1", &[]).err().unwrap(),
+            Error::FunctionNotFound
+        );
+    }
+
+    #[test]
+    fn execute_unchecked_wasm() {
+        /* wat2wasm
+          (func)
+          (func (result i32) i32.const 42)
+          (func (param i32 i32) (result i32)
+            (i32.div_u (local.get 0) (local.get 1))
+          )
+          (func unreachable)
+        */
+        let input = hex::decode("0061736d01000000010e036000006000017f60027f7f017f030504000102000a150402000b0400412a0b0700200020016e0b0300000b").unwrap();
+        let module = parse(&input);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+        let mut instance = instance.unwrap();
+
+        let result = unsafe { instance.execute_unchecked(0, &[]) };
+        assert!(!result.trapped());
+        assert!(!result.value().is_some());
+
+        let result = unsafe { instance.execute_unchecked(1, &[]) };
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(result.value().unwrap().as_i32(), 42);
+
+        // Explicit type specification
+        let result =
+            unsafe { instance.execute_unchecked(2, &[(42 as i32).into(), (2 as i32).into()]) };
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(result.value().unwrap().as_i32(), 21);
+
+        // Implicit i64 types (even though the code expects i32)
+        let result = unsafe { instance.execute_unchecked(2, &[42.into(), 2.into()]) };
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(result.value().unwrap().as_i32(), 21);
+
+        let result = unsafe { instance.execute_unchecked(3, &[]) };
+        assert!(result.trapped());
+        assert!(!result.value().is_some());
+    }
+
+    #[test]
+    fn execution_result_conversions() {
+        /* wat2wasm
+        (module
+          (func (export "void"))
+          (func (export "i32") (result i32) i32.const 42)
+          (func (export "f64") (result f64) f64.const -1.5)
+          (func (export "trap") (result i32) unreachable)
+        )
+        */
+        let input = hex::decode("0061736d01000000010c036000006000017f6000017c03050400010201071b0404766f69640000036933320001036636340002047472617000030a190402000b0400412a0b0b0044000000000000f8bf0b0300000b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+
+        let expected = [
+            ("void", Ok(None)),
+            ("i32", Ok(Some(TypedValue::U32(42)))),
+            ("f64", Ok(Some(TypedValue::F64(-1.5)))),
+            ("trap", Err(())),
+        ];
+        for (name, expected) in expected.iter() {
+            let typed = instance.execute(name, &[]).unwrap();
+            let (func_idx, func_type) = instance
+                .with_exported_function(name, |func_idx, func_type| (func_idx, func_type.clone()))
+                .unwrap();
+            let raw = unsafe { instance.execute_unchecked(func_idx, &[]) };
+            assert_eq!(raw.trapped(), typed.trapped());
+            // The high-level and the raw results agree.
+            match (expected, raw.into_typed(&func_type)) {
+                (Ok(value), Ok(raw_value)) => {
+                    assert_eq!(typed.value(), *value);
+                    assert_eq!(raw_value, *value);
+                }
+                (Err(()), Err(err)) => {
+                    assert!(typed.trapped());
+                    assert_eq!(typed.value(), None);
+                    assert_eq!(err.to_string(), "trap");
+                }
+                (_, raw_value) => panic!("unexpected result of {}: {:?}", name, raw_value),
+            }
+        }
+
+        let raw = unsafe { instance.execute_unchecked(1, &[]) };
+        assert!(format!("{:?}", raw)
+            .starts_with("ExecutionResult { trapped: false, has_value: true, bits: 0x"));
+        let value = Option::<Value>::try_from(raw).unwrap();
+        assert_eq!(value.map(|value| value.as_u32()), Some(42));
+        let raw = unsafe { instance.execute_unchecked(0, &[]) };
+        assert!(Option::<Value>::try_from(raw).unwrap().is_none());
+        let raw = unsafe { instance.execute_unchecked(3, &[]) };
+        assert!(format!("{:?}", raw)
+            .starts_with("ExecutionResult { trapped: true, has_value: false, bits: 0x"));
+        assert!(Option::<Value>::try_from(raw).err().unwrap().is_trap());
+
+        // A result not matching the type is rejected.
+        let raw = unsafe { instance.execute_unchecked(1, &[]) };
+        assert_eq!(
+            raw.into_typed(&FunctionType::new(vec![], None)),
+            Err(Error::Other(
+                "the execution has returned a value of a function without output".to_string()
+            ))
+        );
+        let raw = unsafe { instance.execute_unchecked(0, &[]) };
+        assert_eq!(
+            raw.into_typed(&FunctionType::new(vec![], Some(ValueType::I64))),
+            Err(Error::Other(
+                "the execution has returned no value of type i64".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn exported_globals() {
+        /* wat2wasm
+        (module
+          (global (export "counter") (mut i64) (i64.const 42))
+          (global (export "ratio") f64 (f64.const 2.5))
+        )
+        */
+        let input = hex::decode("0061736d010000000612027e01422a0b7c004400000000000004400b07130207636f756e746572030005726174696f0301").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(instance.global_value("counter"), Some(TypedValue::U64(42)));
+        assert_eq!(instance.global_value("ratio"), Some(TypedValue::F64(2.5)));
+        assert_eq!(instance.global_value("none"), None);
+
+        assert!(instance
+            .set_global_value("counter", TypedValue::U64(7))
+            .is_ok());
+        assert_eq!(instance.global_value("counter"), Some(TypedValue::U64(7)));
+        assert_eq!(
+            instance.set_global_value("counter", TypedValue::U32(7)),
+            Err(Error::ArgumentTypeMismatch)
+        );
+        assert_eq!(
+            instance.set_global_value("ratio", TypedValue::F64(3.0)),
+            Err(Error::Other("global ratio is immutable".to_string()))
+        );
+        assert_eq!(
+            instance.set_global_value("none", TypedValue::U32(0)),
+            Err(Error::Other("global none not found".to_string()))
+        );
+    }
+
+    #[test]
+    fn execute_wasm() {
+        /* wat2wasm
+        (module
+          (func (export "foo") (result i32) (i32.const 42))
+          (func (export "bar") (param i32) (param i64) (result i32) (local.get 0) (i32.wrap_i64 (local.get 1)) (i32.add))
+          (func (export "pi32") (param f32) (result f32) (local.get 0) (f32.const 3.14) (f32.div))
+          (func (export "pi64") (param f64) (result f64) (local.get 0) (f64.const 3.14) (f64.div))
+          (global (export "g1") i32 (i32.const 0))
+          (table (export "tab") 0 anyfunc)
+          (memory (export "mem") 1 2)
+        )
+        */
+        let input = hex::decode(
+        "0061736d010000000115046000017f60027f7e017f60017d017d60017c017c030504000102030404017000000504010101020606017f0041000b072c0703666f6f000003626172000104706933320002047069363400030267310300037461620100036d656d02000a29040400412a0b080020002001a76a0b0a00200043c3f54840950b0e002000441f85eb51b81e0940a30b").unwrap();
+
+        let module = parse(&input);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+        let mut instance = instance.unwrap();
+
+        // Successful execution.
+        let result = instance.execute("foo", &[]);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 42);
+
+        // Successful execution with arguments.
+        let result = instance.execute("bar", &[TypedValue::U32(42), TypedValue::U64(24)]);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(result.value().unwrap().as_u32().unwrap(), 66);
+
+        // Successful execution with 32-bit float argument.
+        let result = instance.execute("pi32", &[TypedValue::F32(0.5)]);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(result.value().unwrap().as_f32().unwrap(), 0.15923566);
+
+        // Successful execution with 64-bit float argument.
+        let result = instance.execute("pi64", &[TypedValue::F64(0.5)]);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert!(!result.trapped());
+        assert!(result.value().is_some());
+        assert_eq!(
+            result.value().unwrap().as_f64().unwrap(),
+            0.1592356687898089
+        );
+
+        // Non-function export.
+        let result = instance.execute("g1", &[]);
+        assert_eq!(result.err().unwrap(), Error::FunctionNotFound);
+
+        // Export not found.
+        let result = instance.execute("baz", &[]);
+        assert_eq!(result.err().unwrap(), Error::FunctionNotFound);
+
+        // Passing more arguments than required.
+        let result = instance.execute("foo", &[TypedValue::U32(42)]);
+        assert_eq!(result.err().unwrap(), Error::ArgumentCountMismatch);
+
+        // Passing less arguments than required.
+        let result = instance.execute("bar", &[]);
+        assert_eq!(result.err().unwrap(), Error::ArgumentCountMismatch);
+
+        // Passing mismatched types.
+        let result = instance.execute("bar", &[TypedValue::F32(1.0), TypedValue::F64(2.0)]);
+        assert_eq!(result.err().unwrap(), Error::ArgumentTypeMismatch);
+    }
+
+    #[test]
+    fn execute_wasm_many_args() {
+        /* wat2wasm
+        (module
+          (func (export "sum_of_nine_arguments_exported_with_a_name_longer_than_sixty_four_bytes")
+            (param i32 i32 i32 i32 i32 i32 i32 i32 i64) (result i64)
+            (i64.add
+              (i64.extend_i32_u
+                (i32.add (i32.add (i32.add (local.get 0) (local.get 1)) (i32.add (local.get 2) (local.get 3)))
+                         (i32.add (i32.add (local.get 4) (local.get 5)) (i32.add (local.get 6) (local.get 7)))))
+              (local.get 8)))
+        )
+        */
+        let input = hex::decode("0061736d01000000010e0160097f7f7f7f7f7f7f7f7e017e03020100074b014773756d5f6f665f6e696e655f617267756d656e74735f6578706f727465645f776974685f615f6e616d655f6c6f6e6765725f7468616e5f73697874795f666f75725f627974657300000a1f011d00200020016a200220036a6a200420056a200620076a6a6aad20087c0b").unwrap();
+        let module = parse(&input).unwrap();
+        let mut instance = module.instantiate().unwrap();
+
+        let name = "sum_of_nine_arguments_exported_with_a_name_longer_than_sixty_four_bytes";
+        let mut args: Vec<TypedValue> = (1..=8).map(TypedValue::U32).collect();
+        args.push(TypedValue::U64(9));
+        let result = instance.execute(name, &args).unwrap();
+        assert!(!result.trapped());
+        assert_eq!(result.value().unwrap().as_u64().unwrap(), 45);
+
+        // Passing mismatched types after the eighth argument.
+        args[8] = TypedValue::U32(9);
+        let result = instance.execute(name, &args);
+        assert_eq!(result.err().unwrap(), Error::ArgumentTypeMismatch);
+    }
+
+    #[test]
+    fn no_memory() {
+        /* wat2wasm
+        (module)
+        */
+        let input = hex::decode("0061736d01000000").unwrap();
+
+        let module = parse(&input);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+        let mut instance = instance.unwrap();
+
+        assert_eq!(instance.memory_size(), 0);
+
+        // If there is no memory, do not allow any slice.
+        unsafe {
+            assert_eq!(
+                instance.checked_memory_slice(0, 0).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(0, 0).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice(0, 65536).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(0, 65536).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65535, 1).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65535, 1).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65535, 2).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65535, 2).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65536, 0).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65536, 0).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65536, 1).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65536, 1).err().unwrap(),
+                Error::NoMemoryAvailable
+            );
+        }
+
+        // Set memory via safe helper.
+        assert_eq!(
+            instance.memory_set(0, &[]).err().unwrap(),
+            Error::NoMemoryAvailable
+        );
+        assert_eq!(
+            instance.memory_set(0, &[0x11, 0x22]).err().unwrap(),
+            Error::NoMemoryAvailable
+        );
+        // Get memory via safe helper.
+        let mut dst: Vec<u8> = Vec::new();
+        dst.resize(65536, 0);
+        // Reading empty slice.
+        assert_eq!(
+            instance.memory_get(0, &mut dst[0..0]).err().unwrap(),
+            Error::NoMemoryAvailable
+        );
+        // Reading 65536 bytes.
+        assert_eq!(
+            instance.memory_get(0, &mut dst).err().unwrap(),
+            Error::NoMemoryAvailable
+        );
+    }
+
+    #[test]
+    fn empty_memory() {
+        /* wat2wasm
+        (module
+          ;; Memory is allowed, but no memory is allocated at start.
+          (memory 0)
+        )
+        */
+        let input = hex::decode("0061736d010000000503010000").unwrap();
+
+        let module = parse(&input);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+        let mut instance = instance.unwrap();
+
+        assert_eq!(instance.memory_size(), 0);
+
+        // If there is no memory, do not allow any slice.
+        unsafe {
+            assert!(instance.checked_memory_slice(0, 0).is_ok());
+            assert!(instance.checked_memory_slice_mut(0, 0).is_ok());
+            assert_eq!(
+                instance.checked_memory_slice(0, 65536).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(0, 65536).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65535, 1).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65535, 1).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65535, 2).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65535, 2).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65536, 0).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65536, 0).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65536, 1).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65536, 1).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+        }
+
+        // Set memory via safe helper.
+        assert!(instance.memory_set(0, &[]).is_ok());
+        assert_eq!(
+            instance.memory_set(0, &[0x11, 0x22]).err().unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+        // Get memory via safe helper.
+        let mut dst: Vec<u8> = Vec::new();
+        dst.resize(65536, 0);
+        // Reading empty slice.
+        assert!(instance.memory_get(0, &mut dst[0..0]).is_ok());
+        // Reading 65536 bytes.
+        assert_eq!(
+            instance.memory_get(0, &mut dst).err().unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+    }
+
+    #[test]
+    fn memory() {
+        /* wat2wasm
+        (module
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+          (func (export "peek") (param i32) (result i32) (i32.load (local.get 0)))
+          (func (export "poke") (param i32) (param i32) (i32.store (local.get 0) (local.get 1)))
+          (memory (export "mem") 1 2)
+        )
+        */
+        let input = hex::decode("0061736d01000000010b0260017f017f60027f7f00030403000001050401010102071c040467726f770000047065656b000104706f6b650002036d656d02000a1a030600200040000b070020002802000b0900200020013602000b").unwrap();
+        let module = parse(&input);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+        let mut instance = instance.unwrap();
+
+        assert_eq!(instance.memory_size(), 65536);
+        unsafe {
+            // Allow empty slices.
+            assert!(instance.checked_memory_slice(0, 0).is_ok());
+            assert!(instance.checked_memory_slice_mut(0, 0).is_ok());
+            // Entire memory.
+            assert!(instance.checked_memory_slice(0, 65536).is_ok());
+            assert!(instance.checked_memory_slice_mut(0, 65536).is_ok());
+            // Allow empty slices.
+            assert!(instance.checked_memory_slice(65535, 0).is_ok());
+            assert!(instance.checked_memory_slice_mut(65535, 0).is_ok());
+            assert!(instance.checked_memory_slice(65536, 0).is_ok());
+            assert!(instance.checked_memory_slice_mut(65536, 0).is_ok());
+            // Single byte.
+            assert!(instance.checked_memory_slice(65535, 1).is_ok());
+            assert!(instance.checked_memory_slice_mut(65535, 1).is_ok());
+            // Reading over.
+            assert_eq!(
+                instance.checked_memory_slice(65535, 2).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65535, 2).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice(65536, 1).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65536, 1).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            // Offset overflow.
+            assert_eq!(
+                instance.checked_memory_slice(65537, 0).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+            assert_eq!(
+                instance.checked_memory_slice_mut(65537, 0).err().unwrap(),
+                Error::InvalidMemoryOffsetOrSize
+            );
+        }
+
+        // Grow with a single page.
+        let result = instance
+            .execute("grow", &[TypedValue::U32(1)])
+            .expect("successful execution");
+        assert!(!result.trapped());
+        assert_eq!(
+            result
+                .value()
+                .expect("expected value")
+                .as_u32()
+                .expect("expected u32 result"),
+            1
+        );
+        // Expect new total memory size.
+        assert_eq!(instance.memory_size(), 65536 * 2);
+
+        // Set memory via slices.
+        unsafe {
+            let mem = instance
+                .checked_memory_slice_mut(0, 65536)
+                .expect("valid mutable slice");
+            assert_eq!(mem[0], 0);
+            assert_eq!(mem[1], 0);
+            assert_eq!(mem[2], 0);
+            assert_eq!(mem[3], 0);
+            mem[0] = 42;
+        }
+        unsafe {
+            // Check that const slice matches up.
+            let mem = instance.checked_memory_slice(0, 5).expect("valid slice");
+            assert_eq!(mem[0], 42);
+            assert_eq!(mem[1], 0);
+            assert_eq!(mem[2], 0);
+            assert_eq!(mem[3], 0);
+        }
+        let result = instance
+            .execute("peek", &[TypedValue::U32(0)])
+            .expect("successful execution");
+        assert!(!result.trapped());
+        assert_eq!(
+            result
+                .value()
+                .expect("expected value")
+                .as_u32()
+                .expect("expected u32 result"),
+            42
+        );
 
-        let r = TypedExecutionResult {
-            result: r_success_f64,
-            value_type: sys::FizzyValueTypeF64,
-        };
-        assert!(!r.trapped());
-        assert!(r.value().is_some());
-        assert_eq!(r.value().unwrap().as_f64().unwrap(), f64::MAX);
-    }
+        // Remember, by now we have grown memory to two pages.
 
-    #[test]
-    fn validate_wasm() {
-        // Empty
-        assert_eq!(validate(&[]).err().unwrap(), "invalid wasm module prefix");
-        // Too short
+        // Set memory via safe helper.
+        assert!(instance.memory_set(0, &[]).is_ok());
+        assert!(instance.memory_set(65536 + 65535, &[]).is_ok());
+        assert!(instance.memory_set(65536 + 65536, &[]).is_ok());
         assert_eq!(
-            validate(&[0x00]).err().unwrap(),
-            "invalid wasm module prefix"
+            instance.memory_set(65536 + 65537, &[]).err().unwrap(),
+            Error::InvalidMemoryOffsetOrSize
         );
-        // Valid
-        assert!(validate(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).is_ok());
-        // Invalid version
+        assert!(instance.memory_set(0, &[0x11, 0x22, 0x33, 0x44]).is_ok());
+        assert!(instance
+            .memory_set(65536 + 65532, &[0x11, 0x22, 0x33, 0x44])
+            .is_ok());
         assert_eq!(
-            validate(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01])
+            instance
+                .memory_set(65536 + 65533, &[0x11, 0x22, 0x33, 0x44])
                 .err()
                 .unwrap(),
-            "invalid wasm module prefix"
+            Error::InvalidMemoryOffsetOrSize
         );
-    }
-
-    #[test]
-    fn parse_wasm() {
-        assert!(parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).is_ok());
         assert_eq!(
-            parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x01])
+            instance
+                .memory_set(65536 + 65534, &[0x11, 0x22, 0x33, 0x44])
                 .err()
                 .unwrap(),
-            "invalid wasm module prefix"
+            Error::InvalidMemoryOffsetOrSize
+        );
+        assert_eq!(
+            instance
+                .memory_set(65536 + 65535, &[0x11, 0x22, 0x33, 0x44])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
         );
-    }
-
-    #[test]
-    fn instantiate_wasm() {
-        let module = parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-    }
-
-    #[test]
-    fn instantiate_wasm_missing_import() {
-        /* wat2wasm
-        (module
-          (memory (import "mod" "m") 1)
-        )
-        */
-        let input = hex::decode("0061736d01000000020a01036d6f64016d020001").unwrap();
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
         assert_eq!(
-            instance.err().unwrap(),
-            "module defines an imported memory but none was provided"
+            instance
+                .memory_set(65536 + 65536, &[0x11, 0x22, 0x33, 0x44])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+        assert_eq!(
+            instance
+                .memory_set(65536 + 65537, &[0x11, 0x22, 0x33, 0x44])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
         );
-    }
 
-    #[test]
-    fn clone_module() {
-        let module = parse(&[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
-        assert!(module.is_ok());
-        let module = module.unwrap();
-        let module2 = module.clone();
-        let instance = module.instantiate();
-        assert!(instance.is_ok());
-        let instance = module2.instantiate();
-        assert!(instance.is_ok());
-    }
+        let result = instance
+            .execute("peek", &[TypedValue::U32(0)])
+            .expect("successful execution");
+        assert!(!result.trapped());
+        assert_eq!(
+            result
+                .value()
+                .expect("expected value")
+                .as_u32()
+                .expect("expected u32 result"),
+            0x44332211
+        );
 
-    #[test]
-    fn find_exported_function_index() {
-        /* wat2wasm
-        (module
-          (func $f (export "foo") (result i32) (i32.const 42))
-          (global (export "g1") i32 (i32.const 0))
-          (table (export "tab") 0 anyfunc)
-          (memory (export "mem") 1 2)
-        )
-        */
-        let input = hex::decode(
-        "0061736d010000000105016000017f030201000404017000000504010101020606017f0041000b07180403666f6f00000267310300037461620100036d656d02000a06010400412a0b").unwrap();
+        // Change memory via wasm.
+        let result = instance
+            .execute("poke", &[TypedValue::U32(0), TypedValue::U32(0x88776655)])
+            .expect("successful execution");
+        assert!(!result.trapped());
 
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-        let mut instance = instance.unwrap();
+        // Read memory via safe helper.
+        let mut dst: Vec<u8> = Vec::new();
+        dst.resize(65536, 0);
+        // Reading 65536 bytes.
+        assert!(instance.memory_get(0, &mut dst).is_ok());
+        // Only checking the first 4.
+        assert_eq!(dst[0..4], [0x55, 0x66, 0x77, 0x88]);
 
-        let func_idx = instance.find_exported_function_index(&"foo");
-        assert!(func_idx.is_some());
-        assert_eq!(func_idx.unwrap(), 0);
+        // Read into empty slice.
+        assert!(instance.memory_get(0, &mut dst[0..0]).is_ok());
+        assert!(instance.memory_get(65536 + 65535, &mut dst[0..0]).is_ok());
+        assert!(instance.memory_get(65536 + 65536, &mut dst[0..0]).is_ok());
+        assert_eq!(
+            instance
+                .memory_get(65536 + 65537, &mut dst[0..0])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
 
-        assert!(instance.find_exported_function_index(&"bar").is_none());
-        assert!(instance.find_exported_function_index(&"g1").is_none());
-        assert!(instance.find_exported_function_index(&"tab").is_none());
-        assert!(instance.find_exported_function_index(&"mem").is_none());
+        // Read into short slice.
+        assert!(instance.memory_get(0, &mut dst[0..4]).is_ok());
+        assert!(instance.memory_get(65536 + 65532, &mut dst[0..4]).is_ok());
+        assert_eq!(
+            instance
+                .memory_get(65536 + 65533, &mut dst[0..4])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+        assert_eq!(
+            instance
+                .memory_get(65536 + 65534, &mut dst[0..4])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+        assert_eq!(
+            instance
+                .memory_get(65536 + 65535, &mut dst[0..4])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+        assert_eq!(
+            instance
+                .memory_get(65536 + 65536, &mut dst[0..4])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
+        assert_eq!(
+            instance
+                .memory_get(65536 + 65537, &mut dst[0..4])
+                .err()
+                .unwrap(),
+            Error::InvalidMemoryOffsetOrSize
+        );
     }
 
     #[test]
-    fn unsafe_execute_wasm() {
+    fn memory_range_overflow() {
+        // The sums overflow usize of 32-bit hosts, and must not wrap around.
+        let data = NonNull::<u8>::dangling().as_ptr();
+        let near_overflow = usize::MAX - u32::MAX as usize + 1;
+        assert_eq!(
+            Instance::checked_memory_range(data, usize::MAX, u32::MAX, near_overflow),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            Instance::checked_memory_range(data, usize::MAX, u32::MAX, usize::MAX),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            Instance::checked_memory_range(data, usize::MAX, u32::MAX, near_overflow - 1),
+            Ok(u32::MAX as usize..usize::MAX)
+        );
+        assert_eq!(Instance::chunk_offset(u32::MAX - 1, 1), Ok(u32::MAX));
+        assert_eq!(
+            Instance::chunk_offset(u32::MAX, 1),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            Instance::chunk_offset(0, u32::MAX as usize + 1),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+
         /* wat2wasm
-          (func)
-          (func (result i32) i32.const 42)
-          (func (param i32 i32) (result i32)
-            (i32.div_u (local.get 0) (local.get 1))
-          )
-          (func unreachable)
+        (module (memory 1))
         */
-        let input = hex::decode("0061736d01000000010e036000006000017f60027f7f017f030504000102000a150402000b0400412a0b0700200020016e0b0300000b").unwrap();
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-        let mut instance = instance.unwrap();
-
-        let result = unsafe { instance.unsafe_execute(0, &[]) };
-        assert!(!result.trapped());
-        assert!(!result.value().is_some());
-
-        let result = unsafe { instance.unsafe_execute(1, &[]) };
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
-        assert_eq!(result.value().unwrap().as_i32(), 42);
-
-        // Explicit type specification
-        let result =
-            unsafe { instance.unsafe_execute(2, &[(42 as i32).into(), (2 as i32).into()]) };
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
-        assert_eq!(result.value().unwrap().as_i32(), 21);
-
-        // Implicit i64 types (even though the code expects i32)
-        let result = unsafe { instance.unsafe_execute(2, &[42.into(), 2.into()]) };
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
-        assert_eq!(result.value().unwrap().as_i32(), 21);
-
-        let result = unsafe { instance.unsafe_execute(3, &[]) };
-        assert!(result.trapped());
-        assert!(!result.value().is_some());
+        let input = hex::decode("0061736d010000000503010001").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let mut target = [0; 2];
+        assert_eq!(
+            instance.memory_get(u32::MAX, &mut target),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert_eq!(
+            instance.memory_set(u32::MAX - 1, &[0; 2]),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        unsafe {
+            assert_eq!(
+                instance.checked_memory_slice(u32::MAX, usize::MAX).err(),
+                Some(Error::InvalidMemoryOffsetOrSize)
+            );
+            assert_eq!(
+                instance.checked_memory_slice(1, usize::MAX).err(),
+                Some(Error::InvalidMemoryOffsetOrSize)
+            );
+        }
+        let mut output = Vec::new();
+        assert_eq!(
+            instance.memory_read_into_writer(u32::MAX - 1, usize::MAX, &mut output, 4096),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
+        assert!(output.is_empty());
+        assert_eq!(
+            instance.memory_write_from_reader(1, usize::MAX, &mut &[0u8; 16][..], 4096),
+            Err(Error::InvalidMemoryOffsetOrSize)
+        );
     }
 
     #[test]
-    fn execute_wasm() {
+    fn preallocate_max_memory() {
         /* wat2wasm
         (module
-          (func (export "foo") (result i32) (i32.const 42))
-          (func (export "bar") (param i32) (param i64) (result i32) (local.get 0) (i32.wrap_i64 (local.get 1)) (i32.add))
-          (func (export "pi32") (param f32) (result f32) (local.get 0) (f32.const 3.14) (f32.div))
-          (func (export "pi64") (param f64) (result f64) (local.get 0) (f64.const 3.14) (f64.div))
-          (global (export "g1") i32 (i32.const 0))
-          (table (export "tab") 0 anyfunc)
-          (memory (export "mem") 1 2)
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+          (memory (export "mem") 1 200)
         )
         */
-        let input = hex::decode(
-        "0061736d010000000115046000017f60027f7e017f60017d017d60017c017c030504000102030404017000000504010101020606017f0041000b072c0703666f6f000003626172000104706933320002047069363400030267310300037461620100036d656d02000a29040400412a0b080020002001a76a0b0a00200043c3f54840950b0e002000441f85eb51b81e0940a30b").unwrap();
-
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-        let mut instance = instance.unwrap();
+        let input = hex::decode("0061736d0100000001060160017f017f030201000505010101c801070e020467726f770000036d656d02000a08010600200040000b").unwrap();
+        let options = InstantiateOptions::new().preallocate_max_memory(true);
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(ImportsBuilder::new(), &options)
+            .unwrap();
 
-        // Successful execution.
-        let result = instance.execute("foo", &[]);
-        assert!(result.is_ok());
-        let result = result.unwrap();
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
-        assert_eq!(result.value().unwrap().as_u32().unwrap(), 42);
+        // The size is not changed.
+        assert_eq!(instance.memory_size(), 65536);
+        instance.memory_set(65532, &[1, 2, 3, 4]).unwrap();
+        let data = unsafe { sys::fizzy_get_instance_memory_data(instance.instance.as_ptr()) };
 
-        // Successful execution with arguments.
-        let result = instance.execute("bar", &[TypedValue::U32(42), TypedValue::U64(24)]);
-        assert!(result.is_ok());
-        let result = result.unwrap();
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
-        assert_eq!(result.value().unwrap().as_u32().unwrap(), 66);
+        for pages in 1..200 {
+            let result = instance.execute("grow", &[TypedValue::U32(1)]).unwrap();
+            assert_eq!(result.value(), Some(TypedValue::U32(pages)));
+        }
+        assert_eq!(instance.memory_size(), 200 * 65536);
+        // The memory has not been reallocated.
+        assert_eq!(
+            unsafe { sys::fizzy_get_instance_memory_data(instance.instance.as_ptr()) },
+            data
+        );
+        let mut dst = [0u8; 4];
+        instance.memory_get(65532, &mut dst).unwrap();
+        assert_eq!(dst, [1, 2, 3, 4]);
 
-        // Successful execution with 32-bit float argument.
-        let result = instance.execute("pi32", &[TypedValue::F32(0.5)]);
-        assert!(result.is_ok());
-        let result = result.unwrap();
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
-        assert_eq!(result.value().unwrap().as_f32().unwrap(), 0.15923566);
+        // The maximum is still enforced.
+        let result = instance.execute("grow", &[TypedValue::U32(1)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(u32::MAX)));
+        assert_eq!(instance.memory_size(), 200 * 65536);
+    }
 
-        // Successful execution with 64-bit float argument.
-        let result = instance.execute("pi64", &[TypedValue::F64(0.5)]);
-        assert!(result.is_ok());
-        let result = result.unwrap();
-        assert!(!result.trapped());
-        assert!(result.value().is_some());
+    #[test]
+    fn memory_pages_limit() {
+        /* wat2wasm
+        (module (memory 10))
+        */
+        let input = hex::decode("0061736d01000000050301000a").unwrap();
+        let module = parse(&input).unwrap();
         assert_eq!(
-            result.value().unwrap().as_f64().unwrap(),
-            0.1592356687898089
+            module.instantiate_with_limit(5).err(),
+            Some(Error::InstantiationFailed(
+                "cannot exceed hard memory limit of 327680 bytes".to_string()
+            ))
         );
+        assert_eq!(
+            module.instantiate_with_limit(10).unwrap().memory_size(),
+            10 * 65536
+        );
+        // The limits above those of the engine are rejected before instantiation.
+        assert_eq!(
+            module.instantiate_with_limit(65537).err(),
+            Some(Error::LimitExceeded {
+                which: "memory_pages_limit".to_string(),
+                limit: 65536,
+                actual: 65537,
+            })
+        );
+        assert!(InstantiateOptions::new()
+            .memory_pages_limit(limits().hard_memory_pages_limit)
+            .validate()
+            .is_ok());
 
-        // Non-function export.
-        let result = instance.execute("g1", &[]);
-        assert_eq!(result.err().unwrap(), "function not found");
-
-        // Export not found.
-        let result = instance.execute("baz", &[]);
-        assert_eq!(result.err().unwrap(), "function not found");
+        /* wat2wasm
+        (module
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+          (memory 1)
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f0302010005030100010708010467726f7700000a08010600200040000b").unwrap();
+        let options = InstantiateOptions::new().memory_pages_limit(3);
+        let mut instance = parse(&input)
+            .unwrap()
+            .instantiate_with_options(ImportsBuilder::new(), &options)
+            .unwrap();
+        let result = instance.execute("grow", &[TypedValue::U32(3)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(u32::MAX)));
+        let result = instance.execute("grow", &[TypedValue::U32(2)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+        let result = instance.execute("grow", &[TypedValue::U32(1)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(u32::MAX)));
+        assert_eq!(instance.memory_size(), 3 * 65536);
+    }
 
-        // Passing more arguments than required.
-        let result = instance.execute("foo", &[TypedValue::U32(42)]);
-        assert_eq!(result.err().unwrap(), "argument count mismatch");
+    /// An instance exporting a memory of 1 to 4 pages, a table with the function returning 7 at
+    /// index 1, and the global of 5, and a module importing them and `env.add`.
+    fn exporter_and_importer() -> (Instance, Module) {
+        /* wat2wasm
+        (module
+          (memory (export "mem") 1 4)
+          (table (export "tab") 2 funcref)
+          (global (export "g") (mut i32) (i32.const 5))
+          (elem (i32.const 1) $seven)
+          (func $seven (result i32) (i32.const 7))
+          (func (export "peek") (result i32) (i32.load8_u (i32.const 0)))
+        )
+        */
+        let input = hex::decode("0061736d010000000105016000017f03030200000404017000020504010101040606017f0141050b071804036d656d020003746162010001670300047065656b00010907010041010b01000a0e02040041070b070041002d00000b").unwrap();
+        let exporter = parse(&input).unwrap().instantiate().unwrap();
 
-        // Passing less arguments than required.
-        let result = instance.execute("bar", &[]);
-        assert_eq!(result.err().unwrap(), "argument count mismatch");
+        /* wat2wasm
+        (module
+          (func $add (import "env" "add") (param i32 i32) (result i32))
+          (memory (import "env" "mem") 1)
+          (table (import "env" "tab") 1 funcref)
+          (global $g (import "env" "g") (mut i32))
+          (func (export "run") (result i32)
+            (i32.store8 (i32.const 0) (i32.const 42))
+            (global.set $g (call $add (global.get $g) (call_indirect (result i32) (i32.const 1))))
+            (global.get $g))
+        )
+        */
+        let input = hex::decode("0061736d01000000010b0260027f7f017f6000017f022b0403656e7603616464000003656e76036d656d02000103656e76037461620170000103656e760167037f01030201010707010372756e00010a180116004100412a3a0000230041011101001000240023000b").unwrap();
+        (exporter, parse(&input).unwrap())
+    }
 
-        // Passing mismatched types.
-        let result = instance.execute("bar", &[TypedValue::F32(1.0), TypedValue::F64(2.0)]);
-        assert_eq!(result.err().unwrap(), "argument type mismatch");
+    fn add_imports() -> ImportsBuilder {
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "add",
+            |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+        );
+        imports
     }
 
     #[test]
-    fn no_memory() {
-        /* wat2wasm
-        (module)
-        */
-        let input = hex::decode("0061736d01000000").unwrap();
+    fn instantiate_with() {
+        let (mut exporter, module) = exporter_and_importer();
+        let options = unsafe {
+            InstantiateOptions::new()
+                .imports(add_imports())
+                .imported_memory(exporter.exported_memory("mem").unwrap())
+                .imported_table(exporter.exported_table("tab").unwrap())
+                .imported_global("env", "g", exporter.exported_global("g").unwrap())
+                .memory_pages_limit(4)
+        };
 
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-        let mut instance = instance.unwrap();
+        let mut instance = module.instantiate_with(&options).unwrap();
+        let result = instance.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(12)));
+        let result = exporter.execute("peek", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(42)));
+        assert_eq!(exporter.global_value("g"), Some(TypedValue::U32(12)));
 
-        assert_eq!(instance.memory_size(), 0);
+        // The options are reused for another instance sharing the same imports.
+        let mut other = module.instantiate_with(&options).unwrap();
+        let result = other.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(19)));
+        assert_eq!(other.resource_usage().memory_max_bytes, 4 * 65536);
+        assert_eq!(exporter.global_value("g"), Some(TypedValue::U32(19)));
 
-        // If there is no memory, do not allow any slice.
-        unsafe {
-            assert_eq!(
-                instance.checked_memory_slice(0, 0).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(0, 0).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(0, 65536).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(0, 65536).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65535, 1).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65535, 1).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65535, 2).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65535, 2).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65536, 0).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65536, 0).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65536, 1).err().unwrap(),
-                "no memory is available"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65536, 1).err().unwrap(),
-                "no memory is available"
-            );
-        }
+        // The imports given separately replace those of the options.
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "add",
+            |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a * b) },
+        );
+        let mut instance = module.instantiate_with_options(imports, &options).unwrap();
+        let result = instance.execute("run", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(133)));
+    }
 
-        // Set memory via safe helper.
+    #[test]
+    fn function_type_from_str() {
+        let func_type =
+            FunctionType::new(vec![ValueType::I32, ValueType::I64], Some(ValueType::F64));
+        assert_eq!("(i32,i64)->f64".parse(), Ok(func_type.clone()));
+        assert_eq!(" ( i32, i64 ) -> f64 ".parse(), Ok(func_type.clone()));
+        assert_eq!("F(iI)".parse(), Ok(func_type));
+        let void = FunctionType::new(Vec::new(), None);
+        assert_eq!("()".parse(), Ok(void.clone()));
+        assert_eq!("v()".parse(), Ok(void));
         assert_eq!(
-            instance.memory_set(0, &[]).err().unwrap(),
-            "no memory is available"
+            "f()".parse(),
+            Ok(FunctionType::new(Vec::new(), Some(ValueType::F32)))
         );
         assert_eq!(
-            instance.memory_set(0, &[0x11, 0x22]).err().unwrap(),
-            "no memory is available"
+            "(f32)".parse(),
+            Ok(FunctionType::new(vec![ValueType::F32], None))
         );
-        // Get memory via safe helper.
-        let mut dst: Vec<u8> = Vec::new();
-        dst.resize(65536, 0);
-        // Reading empty slice.
+
+        let invalid = |signature: &str| {
+            signature
+                .parse::<FunctionType>()
+                .map_err(|err| err.to_string())
+        };
         assert_eq!(
-            instance.memory_get(0, &mut dst[0..0]).err().unwrap(),
-            "no memory is available"
+            invalid("i32"),
+            Err(
+                "invalid function signature \"i32\": expected the inputs in parentheses"
+                    .to_string()
+            )
         );
-        // Reading 65536 bytes.
         assert_eq!(
-            instance.memory_get(0, &mut dst).err().unwrap(),
-            "no memory is available"
+            invalid("(i32,)->i32"),
+            Err("invalid function signature \"(i32,)->i32\": unknown value type \"\"".to_string())
+        );
+        assert_eq!(
+            invalid("(i32)->i32->i32"),
+            Err(
+                "invalid function signature \"(i32)->i32->i32\": unexpected output \"->i32->i32\""
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            invalid("ii(i)"),
+            Err("invalid function signature \"ii(i)\": unexpected output \"ii\"".to_string())
+        );
+        assert_eq!(
+            invalid("i(i)i"),
+            Err(
+                "invalid function signature \"i(i)i\": unexpected \"i\" after the inputs"
+                    .to_string()
+            )
         );
+        assert!(invalid("(ii)").is_err());
+        assert!(invalid("(i32").is_err());
     }
 
     #[test]
-    fn empty_memory() {
+    fn instantiate_with_conflicts() {
+        let (exporter, module) = exporter_and_importer();
+        let memory = exporter.exported_memory("mem").unwrap();
+        let table = exporter.exported_table("tab").unwrap();
+        let global = exporter.exported_global("g").unwrap();
+        let options = || unsafe {
+            InstantiateOptions::new()
+                .imports(add_imports())
+                .imported_memory(memory)
+                .imported_table(table)
+                .imported_global("env", "g", global)
+        };
+        assert!(module.instantiate_with(&options()).is_ok());
+
+        // The cap is below the minimum of the imported memory.
+        assert_eq!(
+            module
+                .instantiate_with(&options().memory_pages_limit(0))
+                .err(),
+            Some(Error::InstantiationFailed(
+                "imported memory limits cannot exceed hard memory limit of 0 bytes".to_string()
+            ))
+        );
+        // The cap is below the maximum of the imported memory.
+        assert_eq!(
+            module
+                .instantiate_with(&options().memory_pages_limit(2))
+                .err(),
+            Some(Error::InstantiationFailed(
+                "imported memory limits cannot exceed hard memory limit of 131072 bytes"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            module
+                .instantiate_with(&options().preallocate_max_memory(true))
+                .err(),
+            Some(Error::InstantiationFailed(
+                "cannot preallocate imported memory owned by another instance".to_string()
+            ))
+        );
+
+        let options = unsafe {
+            InstantiateOptions::new()
+                .imports(add_imports())
+                .imported_memory(memory)
+                .imported_table(table)
+        };
+        let err = module.instantiate_with(&options).err().unwrap();
+        assert!(err.is_missing_import());
+        assert_eq!(
+            err,
+            Error::MissingImport {
+                module: "env".to_string(),
+                name: "g".to_string(),
+                kind: ExternalKind::Global,
+            }
+        );
+        assert_eq!(err.to_string(), "imported global env.g is required");
+        let options = unsafe { options.imported_global("env", "g", global) };
+        assert!(module
+            .instantiate_with(&options.clone().imports(ImportsBuilder::new()))
+            .err()
+            .unwrap()
+            .is_missing_import());
+
+        // The imported memory is rejected by a module without one.
+        let options = unsafe { InstantiateOptions::new().imported_memory(memory) };
+        assert_eq!(
+            exporter.module.instantiate_with(&options).err(),
+            Some(Error::InstantiationFailed(
+                "trying to provide imported memory to a module that doesn't define one".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn with_memory_from() {
         /* wat2wasm
         (module
-          ;; Memory is allowed, but no memory is allocated at start.
-          (memory 0)
+          (memory (export "mem") 1 4)
+          (func (export "write") (param i32 i32) (i32.store (local.get 0) (local.get 1)))
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+          (func (export "size") (result i32) (memory.size))
         )
         */
-        let input = hex::decode("0061736d010000000503010000").unwrap();
-
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-        let mut instance = instance.unwrap();
-
-        assert_eq!(instance.memory_size(), 0);
-
-        // If there is no memory, do not allow any slice.
-        unsafe {
-            assert!(instance.checked_memory_slice(0, 0).is_ok());
-            assert!(instance.checked_memory_slice_mut(0, 0).is_ok());
-            assert_eq!(
-                instance.checked_memory_slice(0, 65536).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(0, 65536).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65535, 1).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65535, 1).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65535, 2).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65535, 2).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65536, 0).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65536, 0).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65536, 1).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65536, 1).err().unwrap(),
-                "invalid offset or size"
-            );
-        }
+        let input = hex::decode("0061736d01000000010a0260027f7f006000017f030403000101050401010104071d04036d656d020005777269746500000467726f7700010473697a6500020a17030900200020013602000b0600410140000b04003f000b").unwrap();
+        let mut producer = parse(&input).unwrap().instantiate().unwrap();
+        /* wat2wasm
+        (module
+          (memory (import "env" "mem") 1 4)
+          (func (export "read") (param i32) (result i32) (i32.load (local.get 0)))
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+          (func (export "size") (result i32) (memory.size))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260017f017f6000017f020d0103656e76036d656d02010104030403000101071603047265616400000467726f7700010473697a6500020a1503070020002802000b0600410140000b04003f000b").unwrap();
+        let module = parse(&input).unwrap();
+        assert!(InstantiateOptions::new()
+            .with_memory_from(&producer, "other")
+            .is_none());
+        let options = InstantiateOptions::new()
+            .with_memory_from(&producer, "mem")
+            .unwrap();
+        let mut consumer = module.instantiate_with(&options).unwrap();
 
-        // Set memory via safe helper.
-        assert!(instance.memory_set(0, &[]).is_ok());
+        let value = |instance: &mut Instance, name: &str, args: &[TypedValue]| {
+            instance.execute(name, args).unwrap().value()
+        };
+        value(
+            &mut producer,
+            "write",
+            &[TypedValue::U32(100), TypedValue::U32(42)],
+        );
         assert_eq!(
-            instance.memory_set(0, &[0x11, 0x22]).err().unwrap(),
-            "invalid offset or size"
+            value(&mut consumer, "read", &[TypedValue::U32(100)]),
+            Some(TypedValue::U32(42))
+        );
+
+        // The growth by either instance is visible to both.
+        assert_eq!(value(&mut consumer, "grow", &[]), Some(TypedValue::U32(1)));
+        assert_eq!(value(&mut producer, "size", &[]), Some(TypedValue::U32(2)));
+        assert_eq!(value(&mut producer, "grow", &[]), Some(TypedValue::U32(2)));
+        assert_eq!(value(&mut consumer, "size", &[]), Some(TypedValue::U32(3)));
+        value(
+            &mut producer,
+            "write",
+            &[TypedValue::U32(2 * 65536), TypedValue::U32(7)],
         );
-        // Get memory via safe helper.
-        let mut dst: Vec<u8> = Vec::new();
-        dst.resize(65536, 0);
-        // Reading empty slice.
-        assert!(instance.memory_get(0, &mut dst[0..0]).is_ok());
-        // Reading 65536 bytes.
         assert_eq!(
-            instance.memory_get(0, &mut dst).err().unwrap(),
-            "invalid offset or size"
+            value(&mut consumer, "read", &[TypedValue::U32(2 * 65536)]),
+            Some(TypedValue::U32(7))
+        );
+        assert!(consumer.memory_set(0, &[1, 2]).is_ok());
+        let mut memory = [0; 2];
+        assert!(producer.memory_get(0, &mut memory).is_ok());
+        assert_eq!(memory, [1, 2]);
+
+        // The memory outlives the producer and the options.
+        drop(producer);
+        drop(options);
+        assert_eq!(
+            value(&mut consumer, "read", &[TypedValue::U32(100)]),
+            Some(TypedValue::U32(42))
         );
+        assert_eq!(value(&mut consumer, "grow", &[]), Some(TypedValue::U32(3)));
+        assert_eq!(consumer.memory_size(), 4 * 65536);
     }
 
     #[test]
-    fn memory() {
+    fn with_memory_from_threads() {
         /* wat2wasm
         (module
-          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
-          (func (export "peek") (param i32) (result i32) (i32.load (local.get 0)))
-          (func (export "poke") (param i32) (param i32) (i32.store (local.get 0) (local.get 1)))
-          (memory (export "mem") 1 2)
+          (memory (export "mem") 1 4)
+          (func (export "write") (param i32 i32) (i32.store (local.get 0) (local.get 1)))
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+          (func (export "size") (result i32) (memory.size))
         )
         */
-        let input = hex::decode("0061736d01000000010b0260017f017f60027f7f00030403000001050401010102071c040467726f770000047065656b000104706f6b650002036d656d02000a1a030600200040000b070020002802000b0900200020013602000b").unwrap();
-        let module = parse(&input);
-        assert!(module.is_ok());
-        let instance = module.unwrap().instantiate();
-        assert!(instance.is_ok());
-        let mut instance = instance.unwrap();
+        let input = hex::decode("0061736d01000000010a0260027f7f006000017f030403000101050401010104071d04036d656d020005777269746500000467726f7700010473697a6500020a17030900200020013602000b0600410140000b04003f000b").unwrap();
+        let mut producer = parse(&input).unwrap().instantiate().unwrap();
+        /* wat2wasm
+        (module
+          (memory (import "env" "mem") 1 4)
+          (func (export "read") (param i32) (result i32) (i32.load (local.get 0)))
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+          (func (export "size") (result i32) (memory.size))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260017f017f6000017f020d0103656e76036d656d02010104030403000101071603047265616400000467726f7700010473697a6500020a1503070020002802000b0600410140000b04003f000b").unwrap();
+        let options = InstantiateOptions::new()
+            .with_memory_from(&producer, "mem")
+            .unwrap();
+        let mut consumer = parse(&input).unwrap().instantiate_with(&options).unwrap();
 
-        assert_eq!(instance.memory_size(), 65536);
-        unsafe {
-            // Allow empty slices.
-            assert!(instance.checked_memory_slice(0, 0).is_ok());
-            assert!(instance.checked_memory_slice_mut(0, 0).is_ok());
-            // Entire memory.
-            assert!(instance.checked_memory_slice(0, 65536).is_ok());
-            assert!(instance.checked_memory_slice_mut(0, 65536).is_ok());
-            // Allow empty slices.
-            assert!(instance.checked_memory_slice(65535, 0).is_ok());
-            assert!(instance.checked_memory_slice_mut(65535, 0).is_ok());
-            assert!(instance.checked_memory_slice(65536, 0).is_ok());
-            assert!(instance.checked_memory_slice_mut(65536, 0).is_ok());
-            // Single byte.
-            assert!(instance.checked_memory_slice(65535, 1).is_ok());
-            assert!(instance.checked_memory_slice_mut(65535, 1).is_ok());
-            // Reading over.
-            assert_eq!(
-                instance.checked_memory_slice(65535, 2).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65535, 2).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice(65536, 1).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65536, 1).err().unwrap(),
-                "invalid offset or size"
-            );
-            // Offset overflow.
-            assert_eq!(
-                instance.checked_memory_slice(65537, 0).err().unwrap(),
-                "invalid offset or size"
-            );
-            assert_eq!(
-                instance.checked_memory_slice_mut(65537, 0).err().unwrap(),
-                "invalid offset or size"
-            );
+        // The producer writes and grows the memory on another thread, while the consumer reads it.
+        let writer = std::thread::spawn(move || {
+            for value in 1..=1000u32 {
+                producer
+                    .execute("write", &[TypedValue::U32(0), TypedValue::U32(value)])
+                    .unwrap();
+                if value % 400 == 0 {
+                    producer.execute("grow", &[]).unwrap();
+                }
+            }
+            producer
+        });
+        let mut last = 0;
+        while last < 1000 {
+            let read = consumer
+                .execute("read", &[TypedValue::U32(0)])
+                .unwrap()
+                .value();
+            let mut memory = [0; 4];
+            consumer.memory_get(0, &mut memory).unwrap();
+            let value = match read {
+                Some(TypedValue::U32(value)) => value,
+                other => panic!("unexpected result {:?}", other),
+            };
+            assert!(value >= last);
+            assert!(u32::from_le_bytes(memory) >= value);
+            last = value;
         }
+        let producer = writer.join().unwrap();
+        assert_eq!(producer.memory_size(), 3 * 65536);
+        assert_eq!(consumer.memory_size(), 3 * 65536);
+    }
 
-        // Grow with a single page.
-        let result = instance
-            .execute("grow", &[TypedValue::U32(1)])
-            .expect("successful execution");
-        assert!(!result.trapped());
+    #[test]
+    fn max_call_depth() {
+        /* wat2wasm
+        (module
+          (func $rec (export "rec") (param i32)
+            (if (local.get 0) (then (call $rec (i32.sub (local.get 0) (i32.const 1))))))
+          (func (export "fail") unreachable)
+        )
+        */
+        let input = hex::decode("0061736d0100000001080260017f006000000303020001070e02037265630000046661696c00010a14020e0020000440200041016b10000b0b0300000b").unwrap();
+        let module = parse(&input).unwrap();
+        let err = module
+            .instantiate_with(&InstantiateOptions::new().max_call_depth(2049))
+            .err()
+            .unwrap();
         assert_eq!(
-            result
-                .value()
-                .expect("expected value")
-                .as_u32()
-                .expect("expected u32 result"),
-            1
+            err.to_string(),
+            "limit max_call_depth of 2048 exceeded: 2049"
         );
-        // Expect new total memory size.
-        assert_eq!(instance.memory_size(), 65536 * 2);
+        assert!(InstantiateOptions::new()
+            .max_call_depth(limits().max_call_depth)
+            .validate()
+            .is_ok());
+        let mut instance = module
+            .instantiate_with(&InstantiateOptions::new().max_call_depth(10))
+            .unwrap();
+        // The execution of rec(n) stacks up n + 1 calls.
+        let mut execute = |n: u32, options: &ExecutionOptions| {
+            instance
+                .execute_with_options("rec", &[TypedValue::U32(n)], options)
+                .unwrap()
+        };
 
-        // Set memory via slices.
-        unsafe {
-            let mem = instance
-                .checked_memory_slice_mut(0, 65536)
-                .expect("valid mutable slice");
-            assert_eq!(mem[0], 0);
-            assert_eq!(mem[1], 0);
-            assert_eq!(mem[2], 0);
-            assert_eq!(mem[3], 0);
-            mem[0] = 42;
-        }
-        unsafe {
-            // Check that const slice matches up.
-            let mem = instance.checked_memory_slice(0, 5).expect("valid slice");
-            assert_eq!(mem[0], 42);
-            assert_eq!(mem[1], 0);
-            assert_eq!(mem[2], 0);
-            assert_eq!(mem[3], 0);
-        }
-        let result = instance
-            .execute("peek", &[TypedValue::U32(0)])
-            .expect("successful execution");
-        assert!(!result.trapped());
-        assert_eq!(
-            result
-                .value()
-                .expect("expected value")
-                .as_u32()
-                .expect("expected u32 result"),
-            42
-        );
+        let outcome = execute(9, &ExecutionOptions::new());
+        assert!(!outcome.trapped());
+        assert!(!outcome.call_depth_exceeded());
+        let outcome = execute(11, &ExecutionOptions::new());
+        assert!(outcome.trapped());
+        assert!(outcome.call_depth_exceeded());
 
-        // Remember, by now we have grown memory to two pages.
+        // The limit of the instance cannot be raised.
+        let outcome = execute(11, &ExecutionOptions::new().max_call_depth(100));
+        assert!(outcome.call_depth_exceeded());
+        let outcome = execute(9, &ExecutionOptions::new().max_call_depth(100));
+        assert!(!outcome.trapped());
+        // It can be lowered.
+        let options = ExecutionOptions::new().max_call_depth(5);
+        assert!(!execute(4, &options).trapped());
+        assert!(execute(5, &options).call_depth_exceeded());
+        // The traced executions are limited too.
+        let coverage = CoverageMap::new(&module);
+        let options = ExecutionOptions::new().coverage(&coverage);
+        assert!(execute(11, &options).call_depth_exceeded());
+        let options = options.trace(TraceSink::Callback(Box::new(|_| {})));
+        assert!(!execute(9, &options).trapped());
+        assert!(execute(11, &options).call_depth_exceeded());
 
-        // Set memory via safe helper.
-        assert!(instance.memory_set(0, &[]).is_ok());
-        assert!(instance.memory_set(65536 + 65535, &[]).is_ok());
-        assert!(instance.memory_set(65536 + 65536, &[]).is_ok());
+        let result = instance.execute("rec", &[TypedValue::U32(11)]).unwrap();
+        assert!(result.trapped());
+        let info = TrapInfo::new("rec", instance.take_host_trap());
+        assert_eq!(info.kind(), &TrapKind::CallDepthExceeded);
         assert_eq!(
-            instance.memory_set(65536 + 65537, &[]).err().unwrap(),
-            "invalid offset or size"
+            info.to_string(),
+            "trap in function rec: call depth exceeded"
         );
-        assert!(instance.memory_set(0, &[0x11, 0x22, 0x33, 0x44]).is_ok());
-        assert!(instance
-            .memory_set(65536 + 65532, &[0x11, 0x22, 0x33, 0x44])
-            .is_ok());
-        assert_eq!(
+
+        let outcome = instance
+            .execute_with_options("fail", &[], &ExecutionOptions::new())
+            .unwrap();
+        assert!(outcome.trapped());
+        assert!(!outcome.call_depth_exceeded());
+
+        let mut instance = module.instantiate().unwrap();
+        assert!(!instance
+            .execute("rec", &[TypedValue::U32(11)])
+            .unwrap()
+            .trapped());
+    }
+
+    #[test]
+    fn coerce_arguments() {
+        /* wat2wasm
+        (module
+          (func (export "mix") (param i32 i64 f64) (result f64)
+            (f64.add
+              (f64.add (f64.convert_i32_s (local.get 0)) (f64.convert_i64_u (local.get 1)))
+              (local.get 2)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001080160037f7e7c017c03020100070701036d697800000a0e010c002000b72001baa02002a00b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let mut mix = |args: &[TypedValue], options: &ExecutionOptions| {
             instance
-                .memory_set(65536 + 65533, &[0x11, 0x22, 0x33, 0x44])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
+                .execute_with_options("mix", args, options)
+                .map(|outcome| outcome.value())
+        };
+        let strict = ExecutionOptions::new();
+        let coerce = ExecutionOptions::new().coerce_arguments(true);
+
+        let exact = [TypedValue::U32(1), TypedValue::U64(2), TypedValue::F64(0.5)];
+        assert_eq!(mix(&exact, &strict), Ok(Some(TypedValue::F64(3.5))));
+        assert_eq!(mix(&exact, &coerce), Ok(Some(TypedValue::F64(3.5))));
+
+        // The integers fitting in 32 bits, and the widening conversions.
+        let args = [TypedValue::U64(1), TypedValue::U32(2), TypedValue::F32(0.5)];
+        assert_eq!(mix(&args, &strict), Err(Error::ArgumentTypeMismatch));
+        assert_eq!(mix(&args, &coerce), Ok(Some(TypedValue::F64(3.5))));
+        let args = [
+            TypedValue::U64(-4i64 as u64),
+            TypedValue::U32(u32::MAX),
+            TypedValue::F32(0.25),
+        ];
+        assert_eq!(
+            mix(&args, &coerce),
+            Ok(Some(TypedValue::F64(4294967291.25)))
         );
+        let args = [
+            TypedValue::U64(u64::from(u32::MAX)),
+            TypedValue::U64(0),
+            TypedValue::F64(0.0),
+        ];
+        assert_eq!(mix(&args, &coerce), Ok(Some(TypedValue::F64(-1.0))));
+
+        // The integers not fitting in 32 bits are not truncated.
+        let args = [
+            TypedValue::U64(1 << 32),
+            TypedValue::U64(0),
+            TypedValue::F64(0.0),
+        ];
         assert_eq!(
-            instance
-                .memory_set(65536 + 65534, &[0x11, 0x22, 0x33, 0x44])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
+            mix(&args, &coerce),
+            Err(Error::ArgumentOutOfRange { index: 0 })
         );
+        let args = [
+            TypedValue::U64(-(1i64 << 31) as u64 - 1),
+            TypedValue::U64(0),
+            TypedValue::F64(0.0),
+        ];
         assert_eq!(
-            instance
-                .memory_set(65536 + 65535, &[0x11, 0x22, 0x33, 0x44])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
+            mix(&args, &coerce),
+            Err(Error::ArgumentOutOfRange { index: 0 })
         );
+
+        // The narrowing of floats and the conversions between integers and floats are not coercions.
+        let args = [TypedValue::U32(0), TypedValue::U64(0), TypedValue::U64(0)];
+        assert_eq!(mix(&args, &coerce), Err(Error::ArgumentTypeMismatch));
+        let args = [
+            TypedValue::F32(0.0),
+            TypedValue::U64(0),
+            TypedValue::F64(0.0),
+        ];
+        assert_eq!(mix(&args, &coerce), Err(Error::ArgumentTypeMismatch));
+        assert_eq!(mix(&exact[..2], &coerce), Err(Error::ArgumentCountMismatch));
+    }
+
+    #[test]
+    fn instances_of_shared_module() {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (global $counter (mut i32) (i32.const 0))
+          (data (i32.const 0) "\2a")
+          (func (export "count") (result i32)
+            (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+            (global.get $counter))
+        )
+        */
+        let input = hex::decode("0061736d010000000105016000017f0302010005030100010606017f0141000b07090105636f756e7400000a0d010b00230041016a240023000b0b07010041000b012a").unwrap();
+        let module = parse(&input).unwrap();
+        let mut first = module.instantiate().unwrap();
+        let mut second = module.clone().instantiate().unwrap();
+        // The module is freed only after its last instance.
+        drop(module);
+
+        assert!(first.memory_set(0, &[0x11]).is_ok());
         assert_eq!(
-            instance
-                .memory_set(65536 + 65536, &[0x11, 0x22, 0x33, 0x44])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
+            first.execute("count", &[]).unwrap().value(),
+            Some(TypedValue::U32(1))
         );
         assert_eq!(
-            instance
-                .memory_set(65536 + 65537, &[0x11, 0x22, 0x33, 0x44])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
+            first.execute("count", &[]).unwrap().value(),
+            Some(TypedValue::U32(2))
         );
+        drop(first);
 
-        let result = instance
-            .execute("peek", &[TypedValue::U32(0)])
-            .expect("successful execution");
-        assert!(!result.trapped());
+        let mut memory = [0; 1];
+        assert!(second.memory_get(0, &mut memory).is_ok());
+        assert_eq!(memory, [0x2a]);
         assert_eq!(
-            result
-                .value()
-                .expect("expected value")
-                .as_u32()
-                .expect("expected u32 result"),
-            0x44332211
+            second.execute("count", &[]).unwrap().value(),
+            Some(TypedValue::U32(1))
         );
+    }
 
-        // Change memory via wasm.
-        let result = instance
-            .execute("poke", &[TypedValue::U32(0), TypedValue::U32(0x88776655)])
-            .expect("successful execution");
-        assert!(!result.trapped());
+    #[test]
+    fn instantiate_many() {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (global $counter (mut i32) (i32.const 0))
+          (data (i32.const 0) "\2a")
+          (func (export "count") (result i32)
+            (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+            (global.get $counter))
+        )
+        */
+        let input = hex::decode("0061736d010000000105016000017f0302010005030100010606017f0141000b07090105636f756e7400000a0d010b00230041016a240023000b0b07010041000b012a").unwrap();
+        let module = parse(&input).unwrap();
+        let options = InstantiateOptions::new().memory_pages_limit(2);
+        assert!(module.instantiate_many(0, &options).unwrap().is_empty());
 
-        // Read memory via safe helper.
-        let mut dst: Vec<u8> = Vec::new();
-        dst.resize(65536, 0);
-        // Reading 65536 bytes.
-        assert!(instance.memory_get(0, &mut dst).is_ok());
-        // Only checking the first 4.
-        assert_eq!(dst[0..4], [0x55, 0x66, 0x77, 0x88]);
+        let mut instances = module.instantiate_many(3, &options).unwrap();
+        assert_eq!(instances.len(), 3);
+        assert!(instances[1].memory_set(0, &[0x11]).is_ok());
+        for _ in 0..2 {
+            instances[1].execute("count", &[]).unwrap();
+        }
+        for (index, instance) in instances.iter_mut().enumerate() {
+            assert_eq!(instance.resource_usage().memory_max_bytes, 2 * 65536);
+            let mut memory = [0; 1];
+            assert!(instance.memory_get(0, &mut memory).is_ok());
+            assert_eq!(memory, [if index == 1 { 0x11 } else { 0x2a }]);
+            let count = if index == 1 { 3 } else { 1 };
+            assert_eq!(
+                instance.execute("count", &[]).unwrap().value(),
+                Some(TypedValue::U32(count))
+            );
+        }
+    }
 
-        // Read into empty slice.
-        assert!(instance.memory_get(0, &mut dst[0..0]).is_ok());
-        assert!(instance.memory_get(65536 + 65535, &mut dst[0..0]).is_ok());
-        assert!(instance.memory_get(65536 + 65536, &mut dst[0..0]).is_ok());
-        assert_eq!(
-            instance
-                .memory_get(65536 + 65537, &mut dst[0..0])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
-        );
+    #[test]
+    fn instantiate_many_failed() {
+        /* wat2wasm
+        (module
+          (func $tick (import "env" "tick"))
+          (start $tick)
+        )
+        */
+        let input =
+            hex::decode("0061736d01000000010401600000020c0103656e76047469636b0000080100").unwrap();
+        let module = parse(&input).unwrap();
+        let ticks = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let mut imports = ImportsBuilder::new();
+        let counter = ticks.clone();
+        imports.func("env", "tick", move |_: &mut Caller| -> Result<(), Trap> {
+            match counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                2 => Err(Trap::new("third")),
+                _ => Ok(()),
+            }
+        });
+        let options = InstantiateOptions::new().imports(imports);
 
-        // Read into short slice.
-        assert!(instance.memory_get(0, &mut dst[0..4]).is_ok());
-        assert!(instance.memory_get(65536 + 65532, &mut dst[0..4]).is_ok());
-        assert_eq!(
-            instance
-                .memory_get(65536 + 65533, &mut dst[0..4])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
-        );
-        assert_eq!(
-            instance
-                .memory_get(65536 + 65534, &mut dst[0..4])
-                .err()
-                .unwrap(),
-            "invalid offset or size"
-        );
+        match module.instantiate_many(5, &options) {
+            Err(Error::InstantiationFailed(message)) => {
+                #[cfg(not(feature = "rayon"))]
+                assert!(message.starts_with("instance 2: "), "{}", message);
+                #[cfg(feature = "rayon")]
+                assert!(message.starts_with("instance "), "{}", message);
+            }
+            result => panic!(
+                "unexpected result {:?}",
+                result.map(|instances| instances.len())
+            ),
+        }
+        // The instances created before the failure have been dropped.
+        drop(options);
+        assert_eq!(Arc::strong_count(&module.0), 1);
+        assert_eq!(Arc::strong_count(&ticks), 1);
+    }
+
+    fn streaming_instance() -> Instance {
+        /* wat2wasm
+        (module
+          (memory 2 3)
+          (func (export "grow") (drop (memory.grow (i32.const 1))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000030201000504010102030708010467726f7700000a09010700410140001a0b").unwrap();
+        let options = InstantiateOptions::new().preallocate_max_memory(true);
+        parse(&input)
+            .unwrap()
+            .instantiate_with_options(ImportsBuilder::new(), &options)
+            .unwrap()
+    }
+
+    #[test]
+    fn memory_streaming() {
+        let mut instance = streaming_instance();
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+
+        let mut reader = &data[..];
+        assert!(instance
+            .memory_write_from_reader(7, data.len(), &mut reader, 4096)
+            .is_ok());
+        assert!(reader.is_empty());
+        let mut memory = vec![0; data.len()];
+        assert!(instance.memory_get(7, &mut memory).is_ok());
+        assert_eq!(memory, data);
+
+        let mut written = Vec::new();
+        assert!(instance
+            .memory_read_into_writer(7, data.len(), &mut written, 4096)
+            .is_ok());
+        assert_eq!(written, data);
+
+        // Out of bounds ranges are rejected before streaming anything.
+        let mut written = Vec::new();
         assert_eq!(
             instance
-                .memory_get(65536 + 65535, &mut dst[0..4])
+                .memory_read_into_writer(65536, 65537, &mut written, 4096)
                 .err()
                 .unwrap(),
-            "invalid offset or size"
+            Error::InvalidMemoryOffsetOrSize
         );
+        assert!(written.is_empty());
+        let mut reader = &data[..];
         assert_eq!(
             instance
-                .memory_get(65536 + 65536, &mut dst[0..4])
+                .memory_write_from_reader(131072, 1, &mut reader, 4096)
                 .err()
                 .unwrap(),
-            "invalid offset or size"
+            Error::InvalidMemoryOffsetOrSize
         );
+        assert_eq!(reader.len(), data.len());
+
+        // The reader ending early.
+        let mut reader = &data[..10];
+        assert!(matches!(
+            instance.memory_write_from_reader(0, 20, &mut reader, 4),
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn memory_streaming_resized() {
+        /// A writer growing the memory after the first chunk, which requires unsafe code.
+        struct GrowingWriter {
+            instance: NonNull<sys::FizzyInstance>,
+            written: Vec<u8>,
+        }
+
+        impl Write for GrowingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.written.extend_from_slice(buf);
+                // The memory is preallocated, therefore it stays in place.
+                unsafe { sys::fizzy_execute(self.instance.as_ptr(), 0, std::ptr::null()) };
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let instance = streaming_instance();
+        let mut writer = GrowingWriter {
+            instance: instance.instance.0,
+            written: Vec::new(),
+        };
         assert_eq!(
             instance
-                .memory_get(65536 + 65537, &mut dst[0..4])
+                .memory_read_into_writer(0, 3000, &mut writer, 1000)
                 .err()
                 .unwrap(),
-            "invalid offset or size"
+            Error::InvalidMemoryOffsetOrSize
         );
+        assert_eq!(writer.written.len(), 1000);
+        assert_eq!(instance.memory_size(), 3 * 65536);
     }
 }
@@ -0,0 +1,38 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The limits of the engine, from the constants of the C API.
+
+use crate::sys;
+
+/// The limits of the engine, see [`limits`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EngineLimits {
+    /// The hard limit of memory growth in pages of 64 KiB unless set by
+    /// [`InstantiateOptions::memory_pages_limit`](crate::InstantiateOptions::memory_pages_limit),
+    /// i.e. 256 MiB.
+    pub default_memory_pages_limit: u32,
+    /// The highest limit of memory growth in pages, i.e. the 4 GiB addressable by WebAssembly 1.0.
+    pub hard_memory_pages_limit: u32,
+    /// The highest limit of the call depth, which is also the default, see
+    /// [`InstantiateOptions::max_call_depth`](crate::InstantiateOptions::max_call_depth).
+    pub max_call_depth: u32,
+}
+
+/// The limits of the engine, against which the options of instantiation are validated.
+///
+/// ```
+/// let limits = fizzy::limits();
+/// assert_eq!(limits.default_memory_pages_limit, 4096);
+/// assert_eq!(limits.hard_memory_pages_limit, 65536);
+/// assert_eq!(limits.max_call_depth, 2048);
+/// ```
+pub fn limits() -> EngineLimits {
+    EngineLimits {
+        default_memory_pages_limit: sys::FizzyMemoryPagesLimitDefault,
+        hard_memory_pages_limit: sys::FizzyMemoryPagesLimitMax,
+        max_call_depth: sys::FizzyCallStackLimit,
+    }
+}
@@ -0,0 +1,584 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Metering of the execution with costs of instructions.
+//!
+//! The engine does not meter the execution itself, therefore [`parse_metered`] instruments the
+//! module: each basic block subtracts the costs of its instructions from a gas counter kept in
+//! a global, and traps if they exceed it. The costs are kept in globals too, so that
+//! [`Instance::execute_with_options`] can apply any [`CostSchedule`] to the same instance.
+
+use crate::{instrument, opcodes, parse_shared, sys, validate, Error, Instance, Module};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+/// The costs of instructions, in ticks, by their names in the text format (e.g. `i32.add`).
+///
+/// A schedule covers all instructions of WebAssembly 1.0, either by their costs or by the default cost.
+///
+/// With the `serde` feature, it is serialized as `{"default":1,"costs":{"i32.add":2}}`, and
+/// the schedule is validated when deserialized.
+///
+/// ```
+/// let schedule = fizzy::CostSchedule::builder()
+///     .default_cost(1)
+///     .cost("call", 10)
+///     .build()
+///     .expect("invalid schedule");
+/// assert_eq!(schedule.cost("call"), Some(10));
+/// assert_eq!(schedule.cost("i32.add"), Some(1));
+/// assert_eq!(schedule.cost("i32.extend8_s"), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        crate = "serde",
+        try_from = "crate::serialization::CostScheduleRepr",
+        into = "crate::serialization::CostScheduleRepr"
+    )
+)]
+pub struct CostSchedule {
+    default: Option<u64>,
+    costs: BTreeMap<String, u64>,
+}
+
+impl CostSchedule {
+    /// Start building a schedule.
+    pub fn builder() -> CostScheduleBuilder {
+        CostScheduleBuilder::default()
+    }
+
+    /// A schedule of the same cost for all instructions.
+    pub fn uniform(cost: u64) -> Self {
+        CostSchedule {
+            default: Some(cost),
+            costs: BTreeMap::new(),
+        }
+    }
+
+    /// Create a schedule of `costs` by instruction names, and the `default` cost of the others.
+    ///
+    /// Fails if a name is not of an instruction of WebAssembly 1.0, or if an instruction has no cost
+    /// and there is no default.
+    pub(crate) fn new(default: Option<u64>, costs: BTreeMap<String, u64>) -> Result<Self, Error> {
+        if let Some(name) = costs.keys().find(|name| opcodes::by_name(name).is_none()) {
+            return Err(Error::Other(format!("unknown instruction {}", name)));
+        }
+        if default.is_none() {
+            if let Some(opcode) = opcodes::OPCODES
+                .iter()
+                .find(|opcode| !costs.contains_key(opcode.name))
+            {
+                return Err(Error::Other(format!(
+                    "no cost of instruction {} and no default cost",
+                    opcode.name
+                )));
+            }
+        }
+        Ok(CostSchedule { default, costs })
+    }
+
+    /// The cost of the instruction `name`, or `None` if it is unknown.
+    pub fn cost(&self, name: &str) -> Option<u64> {
+        opcodes::by_name(name)?;
+        self.costs.get(name).copied().or(self.default)
+    }
+
+    /// The default cost of the instructions without their own costs.
+    pub fn default_cost(&self) -> Option<u64> {
+        self.default
+    }
+
+    /// The instructions with their own costs, by their names.
+    pub fn costs(&self) -> &BTreeMap<String, u64> {
+        &self.costs
+    }
+}
+
+/// The builder of a [`CostSchedule`].
+#[derive(Clone, Debug, Default)]
+pub struct CostScheduleBuilder {
+    default: Option<u64>,
+    costs: BTreeMap<String, u64>,
+}
+
+impl CostScheduleBuilder {
+    /// Set the cost of the instructions without their own costs.
+    pub fn default_cost(mut self, cost: u64) -> Self {
+        self.default = Some(cost);
+        self
+    }
+
+    /// Set the cost of the instruction `name`, e.g. `i32.add`.
+    pub fn cost(mut self, name: &str, cost: u64) -> Self {
+        self.costs.insert(name.to_string(), cost);
+        self
+    }
+
+    /// Validate and create the schedule.
+    ///
+    /// Fails if a name is not of an instruction of WebAssembly 1.0, or if an instruction has no cost
+    /// and no default cost is set.
+    pub fn build(self) -> Result<CostSchedule, Error> {
+        CostSchedule::new(self.default, self.costs)
+    }
+}
+
+/// Parse and validate the input according to WebAssembly 1.0 rules, and instrument the module for
+/// the execution with a [`CostSchedule`] by [`Instance::execute_with_options`].
+///
+/// The instrumentation adds globals and exports named with the `fizzy:` prefix, which are not
+/// listed by [`Module::exports`]. Without a schedule, the instrumented module is executed as if
+/// all instructions were free, only slower.
+pub fn parse_metered<T: AsRef<[u8]>>(input: &T) -> Result<Module, Error> {
+    validate(input)?;
+    parse_shared(instrument::add_dynamic_metering(input.as_ref())?.into())
+}
+
+/// The gas globals of an instance during a metered execution.
+pub(crate) struct Meter {
+    gas: *mut sys::FizzyValue,
+    exhausted: *mut sys::FizzyValue,
+    limit: u64,
+    active: Arc<ActiveGas>,
+}
+
+/// The gas globals of an instance while a metered execution is in progress, shared with its host
+/// functions, see [`crate::Caller::remaining_ticks`].
+#[derive(Default)]
+pub(crate) struct ActiveGas {
+    gas: AtomicPtr<sys::FizzyValue>,
+    exhausted: AtomicPtr<sys::FizzyValue>,
+}
+
+impl ActiveGas {
+    /// Mark that no metered execution is in progress.
+    pub(crate) fn clear(&self) {
+        self.gas.store(std::ptr::null_mut(), Ordering::Release);
+        self.exhausted
+            .store(std::ptr::null_mut(), Ordering::Release);
+    }
+
+    /// The ticks left, or `None` if no metered execution is in progress.
+    pub(crate) fn remaining(&self) -> Option<u64> {
+        let gas = self.gas.load(Ordering::Acquire);
+        if gas.is_null() {
+            None
+        } else {
+            Some(unsafe { (*gas).i64 })
+        }
+    }
+
+    /// Charge `cost` ticks to the metered execution in progress, if any, like the costs of a block
+    /// of instructions. Returns false, marking the gas exhausted and keeping the ticks left, if
+    /// fewer ticks are left.
+    pub(crate) fn charge(&self, cost: u64) -> bool {
+        let gas = self.gas.load(Ordering::Acquire);
+        if gas.is_null() || cost == 0 {
+            return true;
+        }
+        unsafe {
+            if (*gas).i64 < cost {
+                (*self.exhausted.load(Ordering::Acquire)).i32 = 1;
+                return false;
+            }
+            (*gas).i64 -= cost;
+        }
+        true
+    }
+}
+
+impl Meter {
+    /// Set the cost globals of `instance` according to `schedule`, and the gas to `limit`.
+    pub(crate) fn start(
+        instance: &mut Instance,
+        schedule: &CostSchedule,
+        limit: u64,
+    ) -> Result<Self, Error> {
+        let (gas, exhausted) = match (
+            instance.find_exported_global(instrument::GAS_EXPORT),
+            instance.find_exported_global(instrument::EXHAUSTED_EXPORT),
+        ) {
+            (Some(gas), Some(exhausted)) => (gas.value, exhausted.value),
+            _ => {
+                return Err(Error::Other(
+                    "the module is not instrumented, see parse_metered".to_string(),
+                ))
+            }
+        };
+        let module = unsafe { instance.get_module() };
+        let export_count = unsafe { sys::fizzy_get_export_count(module) };
+        for export_idx in 0..export_count {
+            let export = unsafe { sys::fizzy_get_export_description(module, export_idx) };
+            let export_name = unsafe { crate::export_name(&export) };
+            if !export_name.starts_with(instrument::COST_EXPORT_PREFIX) {
+                continue;
+            }
+            let name = &export_name[instrument::COST_EXPORT_PREFIX.len()..];
+            let cost = schedule
+                .cost(name)
+                .expect("cost schedule does not cover an instruction");
+            let global = instance
+                .find_exported_global(export_name)
+                .expect("cost global not found");
+            unsafe { (*global.value).i64 = cost };
+        }
+        unsafe {
+            (*gas).i64 = limit;
+            (*exhausted).i32 = 0;
+        }
+        instance.active_gas.gas.store(gas, Ordering::Release);
+        instance
+            .active_gas
+            .exhausted
+            .store(exhausted, Ordering::Release);
+        Ok(Meter {
+            gas,
+            exhausted,
+            limit,
+            active: instance.active_gas.clone(),
+        })
+    }
+
+    /// Return the ticks used since the start, and whether the execution has run out of them,
+    /// then make the gas unlimited again.
+    pub(crate) fn finish(self) -> (u64, bool) {
+        self.active.clear();
+        unsafe {
+            let used = self.limit - (*self.gas).i64;
+            let exhausted = (*self.exhausted).i32 != 0;
+            (*self.gas).i64 = u64::MAX;
+            (*self.exhausted).i32 = 0;
+            (used, exhausted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Caller, ExecutionOptions, ImportsBuilder, Trap, TypedValue};
+    use std::sync::Mutex;
+
+    #[test]
+    fn schedule_validation() {
+        assert_eq!(
+            CostSchedule::builder().build(),
+            Err(Error::Other(
+                "no cost of instruction unreachable and no default cost".to_string()
+            ))
+        );
+        assert_eq!(
+            CostSchedule::builder()
+                .default_cost(1)
+                .cost("i32.foo", 2)
+                .build(),
+            Err(Error::Other("unknown instruction i32.foo".to_string()))
+        );
+
+        let all = opcodes::OPCODES
+            .iter()
+            .fold(CostSchedule::builder(), |builder, opcode| {
+                builder.cost(opcode.name, 2)
+            })
+            .build()
+            .unwrap();
+        assert_eq!(all.default_cost(), None);
+        assert_eq!(all.cost("f64.reinterpret_i64"), Some(2));
+        assert_eq!(all.costs().len(), 172);
+
+        let uniform = CostSchedule::uniform(3);
+        assert_eq!(uniform.cost("nop"), Some(3));
+        assert_eq!(uniform.cost("nope"), None);
+        assert!(uniform.costs().is_empty());
+    }
+
+    fn loop_module() -> Module {
+        /* wat2wasm
+        (module
+          (func (export "loop") (param $n i32) (result i32)
+            (local $sum i32)
+            (block $done
+              (loop $next
+                (br_if $done (i32.eqz (local.get $n)))
+                (local.set $sum (i32.add (local.get $sum) (local.get $n)))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                (br $next)
+              )
+            )
+            (local.get $sum)
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f03020100070801046c6f6f7000000a23012101017f024003402000450d01200120006a2101200041016b21000c000b0b20010b").unwrap();
+        parse_metered(&input).unwrap()
+    }
+
+    fn ticks(instance: &mut Instance, n: u32, schedule: &CostSchedule) -> u64 {
+        let outcome = instance
+            .execute_with_options(
+                "loop",
+                &[TypedValue::U32(n)],
+                &ExecutionOptions::new().cost_schedule(schedule),
+            )
+            .unwrap();
+        assert!(!outcome.gas_exhausted());
+        assert_eq!(outcome.value(), Some(TypedValue::U32(n * (n + 1) / 2)));
+        outcome.ticks_used().unwrap()
+    }
+
+    #[test]
+    fn proportional_schedules() {
+        let module = loop_module();
+        assert_eq!(module.exports().len(), 1);
+        let mut instance = module.instantiate().unwrap();
+
+        let one = CostSchedule::uniform(1);
+        let three = CostSchedule::uniform(3);
+        // The instructions of the block, loop and result, then 12 of each iteration.
+        assert_eq!(ticks(&mut instance, 0, &one), 7);
+        assert_eq!(ticks(&mut instance, 10, &one), 7 + 10 * 12);
+        assert_eq!(
+            ticks(&mut instance, 10, &three),
+            3 * ticks(&mut instance, 10, &one)
+        );
+
+        // An iteration has 2 additions or subtractions.
+        let arithmetic = CostSchedule::builder()
+            .default_cost(1)
+            .cost("i32.add", 11)
+            .cost("i32.sub", 11)
+            .build()
+            .unwrap();
+        assert_eq!(
+            ticks(&mut instance, 10, &arithmetic),
+            ticks(&mut instance, 10, &one) + 10 * 2 * 10
+        );
+
+        // Without a schedule, the execution is not metered.
+        let outcome = instance
+            .execute_with_options("loop", &[TypedValue::U32(4)], &ExecutionOptions::new())
+            .unwrap();
+        assert_eq!(outcome.ticks_used(), None);
+        assert_eq!(outcome.value(), Some(TypedValue::U32(10)));
+        let result = instance.execute("loop", &[TypedValue::U32(4)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(10)));
+    }
+
+    #[test]
+    fn gas_limit() {
+        let mut instance = loop_module().instantiate().unwrap();
+        let options = ExecutionOptions::new()
+            .cost_schedule(&CostSchedule::uniform(1))
+            .gas_limit(7 + 10 * 12);
+        let outcome = instance
+            .execute_with_options("loop", &[TypedValue::U32(10)], &options)
+            .unwrap();
+        assert!(!outcome.gas_exhausted());
+        assert_eq!(outcome.ticks_used(), Some(7 + 10 * 12));
+
+        let outcome = instance
+            .execute_with_options("loop", &[TypedValue::U32(11)], &options)
+            .unwrap();
+        assert!(outcome.gas_exhausted());
+        assert!(outcome.trapped());
+        assert!(outcome.ticks_used().unwrap() <= 7 + 10 * 12);
+
+        // The gas is unlimited again afterwards.
+        let result = instance.execute("loop", &[TypedValue::U32(11)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(66)));
+    }
+
+    #[test]
+    fn count_instructions() {
+        let mut instance = loop_module().instantiate().unwrap();
+        let options = ExecutionOptions::new().count_instructions(true);
+        let mut count = |n: u32| {
+            let outcome = instance
+                .execute_with_options("loop", &[TypedValue::U32(n)], &options)
+                .unwrap();
+            assert_eq!(outcome.value(), Some(TypedValue::U32(n * (n + 1) / 2)));
+            assert_eq!(outcome.ticks_used(), None);
+            outcome.instructions_executed().unwrap()
+        };
+        // The instructions of the block, loop and result, then 12 of each iteration.
+        assert_eq!(count(10), 7 + 10 * 12);
+        assert_eq!(count(10), count(10));
+        assert_eq!(count(11) - count(10), 12);
+        assert_eq!(count(0), 7);
+
+        let outcome = instance
+            .execute_with_options("loop", &[TypedValue::U32(1)], &ExecutionOptions::new())
+            .unwrap();
+        assert_eq!(outcome.instructions_executed(), None);
+        let options = options.cost_schedule(&CostSchedule::uniform(1));
+        assert_eq!(
+            instance
+                .execute_with_options("loop", &[TypedValue::U32(1)], &options)
+                .err(),
+            Some(Error::Other(
+                "instructions cannot be counted in a metered execution".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn remaining_ticks() {
+        /* wat2wasm
+        (module
+          (func $host (import "env" "host"))
+          (func (export "run") (call $host) (call $host))
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000020c0103656e7604686f73740000030201000707010372756e00010a08010600100010000b").unwrap();
+        let remaining = Arc::new(Mutex::new(Vec::new()));
+        let recorded = remaining.clone();
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "host",
+            move |caller: &mut Caller| -> Result<(), Trap> {
+                recorded.lock().unwrap().push(caller.remaining_ticks());
+                Ok(())
+            },
+        );
+        let mut instance = parse_metered(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let mut run = |options: &ExecutionOptions| {
+            let outcome = instance.execute_with_options("run", &[], options).unwrap();
+            assert!(!outcome.trapped());
+            let remaining = std::mem::take(&mut *remaining.lock().unwrap());
+            (outcome.ticks_used(), remaining)
+        };
+
+        // The block of both calls is charged before the first one.
+        let schedule = CostSchedule::uniform(1);
+        let options = ExecutionOptions::new()
+            .cost_schedule(&schedule)
+            .gas_limit(100);
+        let (used, remaining) = run(&options);
+        let left = 100 - used.unwrap();
+        assert_eq!(remaining, [Some(left), Some(left)]);
+        let (used, remaining) = run(&ExecutionOptions::new().cost_schedule(&schedule));
+        let left = u64::MAX - used.unwrap();
+        assert_eq!(remaining, [Some(left), Some(left)]);
+
+        assert_eq!(run(&ExecutionOptions::new()), (None, vec![None, None]));
+        // The counting of instructions is not a metered execution.
+        let (_, remaining) = run(&ExecutionOptions::new().count_instructions(true));
+        assert_eq!(remaining, [None, None]);
+    }
+
+    #[test]
+    fn import_costs() {
+        /* wat2wasm
+        (module
+          (func $host (import "env" "host"))
+          (func (export "run") (param $n i32)
+            (loop $next
+              (call $host)
+              (br_if $next (local.tee $n (i32.sub (local.get $n) (i32.const 1))))
+            )
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001080260000060017f00020c0103656e7604686f73740000030201010707010372756e00010a1201100003401000200041016b22000d000b0b").unwrap();
+        let remaining = Arc::new(Mutex::new(Vec::new()));
+        let recorded = remaining.clone();
+        let mut imports = ImportsBuilder::new();
+        imports.func_with_cost(
+            "env",
+            "host",
+            5,
+            move |caller: &mut Caller| -> Result<(), Trap> {
+                recorded.lock().unwrap().push(caller.remaining_ticks());
+                Ok(())
+            },
+        );
+        let mut instance = parse_metered(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+
+        // Only the calls cost ticks, and the limit allows 3 of them.
+        let schedule = CostSchedule::uniform(0);
+        let options = ExecutionOptions::new()
+            .cost_schedule(&schedule)
+            .gas_limit(15);
+        let outcome = instance
+            .execute_with_options("run", &[TypedValue::U32(3)], &options)
+            .unwrap();
+        assert!(!outcome.trapped());
+        assert_eq!(outcome.ticks_used(), Some(15));
+        assert_eq!(
+            std::mem::take(&mut *remaining.lock().unwrap()),
+            [Some(10), Some(5), Some(0)]
+        );
+
+        // The 4th call traps without calling the function.
+        let outcome = instance
+            .execute_with_options("run", &[TypedValue::U32(4)], &options)
+            .unwrap();
+        assert!(outcome.trapped());
+        assert!(outcome.gas_exhausted());
+        assert_eq!(outcome.ticks_used(), Some(15));
+        assert_eq!(remaining.lock().unwrap().len(), 3);
+
+        // The calls of an execution which is not metered are free.
+        let outcome = instance
+            .execute_with_options("run", &[TypedValue::U32(4)], &ExecutionOptions::new())
+            .unwrap();
+        assert!(!outcome.trapped());
+
+        // The default cost applies to the functions without their own costs.
+        let mut imports = ImportsBuilder::new();
+        imports
+            .default_import_cost(2)
+            .func("env", "host", |_: &mut Caller| -> Result<(), Trap> {
+                Ok(())
+            });
+        let mut instance = parse_metered(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap();
+        let options = ExecutionOptions::new()
+            .cost_schedule(&schedule)
+            .gas_limit(4);
+        let mut run = |n: u32| {
+            instance
+                .execute_with_options("run", &[TypedValue::U32(n)], &options)
+                .unwrap()
+                .gas_exhausted()
+        };
+        assert!(!run(2));
+        assert!(run(3));
+    }
+
+    #[test]
+    fn not_instrumented() {
+        /* wat2wasm
+        (module (func (export "nop")))
+        */
+        let input =
+            hex::decode("0061736d0100000001040160000003020100070701036e6f7000000a040102000b")
+                .unwrap();
+        let mut instance = crate::parse(&input).unwrap().instantiate().unwrap();
+        let options = ExecutionOptions::new().cost_schedule(&CostSchedule::uniform(1));
+        assert_eq!(
+            instance.execute_with_options("nop", &[], &options).err(),
+            Some(Error::Other(
+                "the module is not instrumented, see parse_metered".to_string()
+            ))
+        );
+        assert!(instance
+            .execute_with_options("nop", &[], &ExecutionOptions::new())
+            .is_ok());
+    }
+}
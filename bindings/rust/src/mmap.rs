@@ -0,0 +1,148 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reading input files, memory-mapped where supported.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The contents of an input file.
+pub(crate) enum FileInput {
+    #[cfg(unix)]
+    Mapped(Mapping),
+    Buffered(Vec<u8>),
+}
+
+impl FileInput {
+    /// Map the file at `path` into memory, or read it if it cannot be mapped.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        #[cfg(unix)]
+        {
+            let metadata = file.metadata()?;
+            // Only non-empty regular files can be mapped.
+            if let (true, Ok(len)) = (metadata.is_file(), usize::try_from(metadata.len())) {
+                if len > 0 {
+                    return Mapping::new(&file, len).map(FileInput::Mapped);
+                }
+            }
+        }
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(FileInput::Buffered(contents))
+    }
+}
+
+impl AsRef<[u8]> for FileInput {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            #[cfg(unix)]
+            FileInput::Mapped(mapping) => mapping.as_ref(),
+            FileInput::Buffered(contents) => contents,
+        }
+    }
+}
+
+/// A read-only private mapping of a whole file.
+#[cfg(unix)]
+pub(crate) struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mapping {
+    fn new(file: &File, len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Mapping { ptr, len })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRef<[u8]> for Mapping {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, parse_file, Error, TypedValue};
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("fizzy-mmap-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_file_wasm() {
+        /* wat2wasm
+        (module
+          (func (export "foo") (result i32) (i32.const 42))
+        )
+        */
+        let input =
+            hex::decode("0061736d010000000105016000017f0302010007070103666f6f00000a06010400412a0b")
+                .unwrap();
+        let path = temp_file("valid.wasm", &input);
+
+        let module = parse_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut instance = module.instantiate().unwrap();
+        let result = instance.execute("foo", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(42)));
+    }
+
+    #[test]
+    fn parse_file_same_as_parse() {
+        for (name, input) in &[
+            ("empty.wasm", &b""[..]),
+            ("malformed.wasm", &b"\0asm"[..]),
+            (
+                "invalid.wasm",
+                &b"\0asm\x01\0\0\0\x0a\x04\x01\x02\0\x0b"[..],
+            ),
+        ] {
+            let path = temp_file(name, input);
+            let result = parse_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(result.err().unwrap(), parse(input).err().unwrap());
+        }
+    }
+
+    #[test]
+    fn parse_file_missing() {
+        let path = std::env::temp_dir().join("fizzy-mmap-missing.wasm");
+        match parse_file(&path).err().unwrap() {
+            Error::Io(err) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,924 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The instructions of WebAssembly 1.0.
+
+/// The kind of the immediate arguments of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Immediates {
+    None,
+    BlockType,
+    /// A single index of a label, a function, a local or a global.
+    Index,
+    BrTable,
+    CallIndirect,
+    MemArg,
+    /// The reserved zero byte of memory.size and memory.grow.
+    MemoryIndex,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+/// An instruction, by its opcode and its name in the text format.
+#[derive(Debug)]
+pub(crate) struct Opcode {
+    pub code: u8,
+    pub name: &'static str,
+    pub immediates: Immediates,
+}
+
+/// All instructions, in the order of their opcodes.
+pub(crate) const OPCODES: &[Opcode] = &[
+    Opcode {
+        code: 0x00,
+        name: "unreachable",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x01,
+        name: "nop",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x02,
+        name: "block",
+        immediates: Immediates::BlockType,
+    },
+    Opcode {
+        code: 0x03,
+        name: "loop",
+        immediates: Immediates::BlockType,
+    },
+    Opcode {
+        code: 0x04,
+        name: "if",
+        immediates: Immediates::BlockType,
+    },
+    Opcode {
+        code: 0x05,
+        name: "else",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x0b,
+        name: "end",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x0c,
+        name: "br",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x0d,
+        name: "br_if",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x0e,
+        name: "br_table",
+        immediates: Immediates::BrTable,
+    },
+    Opcode {
+        code: 0x0f,
+        name: "return",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x10,
+        name: "call",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x11,
+        name: "call_indirect",
+        immediates: Immediates::CallIndirect,
+    },
+    Opcode {
+        code: 0x1a,
+        name: "drop",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x1b,
+        name: "select",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x20,
+        name: "local.get",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x21,
+        name: "local.set",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x22,
+        name: "local.tee",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x23,
+        name: "global.get",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x24,
+        name: "global.set",
+        immediates: Immediates::Index,
+    },
+    Opcode {
+        code: 0x28,
+        name: "i32.load",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x29,
+        name: "i64.load",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x2a,
+        name: "f32.load",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x2b,
+        name: "f64.load",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x2c,
+        name: "i32.load8_s",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x2d,
+        name: "i32.load8_u",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x2e,
+        name: "i32.load16_s",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x2f,
+        name: "i32.load16_u",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x30,
+        name: "i64.load8_s",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x31,
+        name: "i64.load8_u",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x32,
+        name: "i64.load16_s",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x33,
+        name: "i64.load16_u",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x34,
+        name: "i64.load32_s",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x35,
+        name: "i64.load32_u",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x36,
+        name: "i32.store",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x37,
+        name: "i64.store",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x38,
+        name: "f32.store",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x39,
+        name: "f64.store",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x3a,
+        name: "i32.store8",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x3b,
+        name: "i32.store16",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x3c,
+        name: "i64.store8",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x3d,
+        name: "i64.store16",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x3e,
+        name: "i64.store32",
+        immediates: Immediates::MemArg,
+    },
+    Opcode {
+        code: 0x3f,
+        name: "memory.size",
+        immediates: Immediates::MemoryIndex,
+    },
+    Opcode {
+        code: 0x40,
+        name: "memory.grow",
+        immediates: Immediates::MemoryIndex,
+    },
+    Opcode {
+        code: 0x41,
+        name: "i32.const",
+        immediates: Immediates::I32,
+    },
+    Opcode {
+        code: 0x42,
+        name: "i64.const",
+        immediates: Immediates::I64,
+    },
+    Opcode {
+        code: 0x43,
+        name: "f32.const",
+        immediates: Immediates::F32,
+    },
+    Opcode {
+        code: 0x44,
+        name: "f64.const",
+        immediates: Immediates::F64,
+    },
+    Opcode {
+        code: 0x45,
+        name: "i32.eqz",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x46,
+        name: "i32.eq",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x47,
+        name: "i32.ne",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x48,
+        name: "i32.lt_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x49,
+        name: "i32.lt_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x4a,
+        name: "i32.gt_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x4b,
+        name: "i32.gt_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x4c,
+        name: "i32.le_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x4d,
+        name: "i32.le_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x4e,
+        name: "i32.ge_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x4f,
+        name: "i32.ge_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x50,
+        name: "i64.eqz",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x51,
+        name: "i64.eq",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x52,
+        name: "i64.ne",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x53,
+        name: "i64.lt_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x54,
+        name: "i64.lt_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x55,
+        name: "i64.gt_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x56,
+        name: "i64.gt_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x57,
+        name: "i64.le_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x58,
+        name: "i64.le_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x59,
+        name: "i64.ge_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x5a,
+        name: "i64.ge_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x5b,
+        name: "f32.eq",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x5c,
+        name: "f32.ne",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x5d,
+        name: "f32.lt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x5e,
+        name: "f32.gt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x5f,
+        name: "f32.le",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x60,
+        name: "f32.ge",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x61,
+        name: "f64.eq",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x62,
+        name: "f64.ne",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x63,
+        name: "f64.lt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x64,
+        name: "f64.gt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x65,
+        name: "f64.le",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x66,
+        name: "f64.ge",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x67,
+        name: "i32.clz",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x68,
+        name: "i32.ctz",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x69,
+        name: "i32.popcnt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x6a,
+        name: "i32.add",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x6b,
+        name: "i32.sub",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x6c,
+        name: "i32.mul",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x6d,
+        name: "i32.div_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x6e,
+        name: "i32.div_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x6f,
+        name: "i32.rem_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x70,
+        name: "i32.rem_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x71,
+        name: "i32.and",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x72,
+        name: "i32.or",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x73,
+        name: "i32.xor",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x74,
+        name: "i32.shl",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x75,
+        name: "i32.shr_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x76,
+        name: "i32.shr_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x77,
+        name: "i32.rotl",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x78,
+        name: "i32.rotr",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x79,
+        name: "i64.clz",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x7a,
+        name: "i64.ctz",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x7b,
+        name: "i64.popcnt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x7c,
+        name: "i64.add",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x7d,
+        name: "i64.sub",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x7e,
+        name: "i64.mul",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x7f,
+        name: "i64.div_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x80,
+        name: "i64.div_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x81,
+        name: "i64.rem_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x82,
+        name: "i64.rem_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x83,
+        name: "i64.and",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x84,
+        name: "i64.or",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x85,
+        name: "i64.xor",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x86,
+        name: "i64.shl",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x87,
+        name: "i64.shr_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x88,
+        name: "i64.shr_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x89,
+        name: "i64.rotl",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x8a,
+        name: "i64.rotr",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x8b,
+        name: "f32.abs",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x8c,
+        name: "f32.neg",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x8d,
+        name: "f32.ceil",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x8e,
+        name: "f32.floor",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x8f,
+        name: "f32.trunc",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x90,
+        name: "f32.nearest",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x91,
+        name: "f32.sqrt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x92,
+        name: "f32.add",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x93,
+        name: "f32.sub",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x94,
+        name: "f32.mul",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x95,
+        name: "f32.div",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x96,
+        name: "f32.min",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x97,
+        name: "f32.max",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x98,
+        name: "f32.copysign",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x99,
+        name: "f64.abs",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x9a,
+        name: "f64.neg",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x9b,
+        name: "f64.ceil",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x9c,
+        name: "f64.floor",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x9d,
+        name: "f64.trunc",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x9e,
+        name: "f64.nearest",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0x9f,
+        name: "f64.sqrt",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa0,
+        name: "f64.add",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa1,
+        name: "f64.sub",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa2,
+        name: "f64.mul",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa3,
+        name: "f64.div",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa4,
+        name: "f64.min",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa5,
+        name: "f64.max",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa6,
+        name: "f64.copysign",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa7,
+        name: "i32.wrap_i64",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa8,
+        name: "i32.trunc_f32_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xa9,
+        name: "i32.trunc_f32_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xaa,
+        name: "i32.trunc_f64_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xab,
+        name: "i32.trunc_f64_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xac,
+        name: "i64.extend_i32_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xad,
+        name: "i64.extend_i32_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xae,
+        name: "i64.trunc_f32_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xaf,
+        name: "i64.trunc_f32_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb0,
+        name: "i64.trunc_f64_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb1,
+        name: "i64.trunc_f64_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb2,
+        name: "f32.convert_i32_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb3,
+        name: "f32.convert_i32_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb4,
+        name: "f32.convert_i64_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb5,
+        name: "f32.convert_i64_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb6,
+        name: "f32.demote_f64",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb7,
+        name: "f64.convert_i32_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb8,
+        name: "f64.convert_i32_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xb9,
+        name: "f64.convert_i64_s",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xba,
+        name: "f64.convert_i64_u",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xbb,
+        name: "f64.promote_f32",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xbc,
+        name: "i32.reinterpret_f32",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xbd,
+        name: "i64.reinterpret_f64",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xbe,
+        name: "f32.reinterpret_i32",
+        immediates: Immediates::None,
+    },
+    Opcode {
+        code: 0xbf,
+        name: "f64.reinterpret_i64",
+        immediates: Immediates::None,
+    },
+];
+
+/// The instruction of the opcode `code`.
+pub(crate) fn by_code(code: u8) -> Option<&'static Opcode> {
+    OPCODES
+        .binary_search_by_key(&code, |opcode| opcode.code)
+        .ok()
+        .map(|index| &OPCODES[index])
+}
+
+/// The instruction of the name `name` in the text format.
+pub(crate) fn by_name(name: &str) -> Option<&'static Opcode> {
+    OPCODES.iter().find(|opcode| opcode.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        assert_eq!(OPCODES.len(), 172);
+        assert_eq!(by_code(0x6a).unwrap().name, "i32.add");
+        assert_eq!(by_name("i32.add").unwrap().code, 0x6a);
+        assert_eq!(by_code(0x0e).unwrap().immediates, Immediates::BrTable);
+        assert!(by_code(0x06).is_none());
+        assert!(by_code(0xc0).is_none());
+        assert!(by_name("i32.extend8_s").is_none());
+    }
+}
@@ -0,0 +1,322 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cross-checking executions against the reference interpreter wasmi, as a runtime guard.
+
+use crate::differential::{from_wasmi, to_wasmi, Outcome};
+use crate::{Error, ExternalKind, Instance, StateSnapshot, Trap, TypedExecutionResult, TypedValue};
+
+use std::fmt;
+
+/// A host function of the reference interpreter.
+type ReferenceFn = Box<dyn FnMut(&[TypedValue]) -> Result<Option<TypedValue>, Trap> + Send>;
+
+/// The host functions imported by the module in the reference interpreter, see
+/// [`Instance::set_reference_imports`].
+///
+/// The host functions of Fizzy cannot be called by wasmi, as they access the calling instance,
+/// therefore they are registered again without it, with the types given by the imports.
+#[derive(Default)]
+pub struct ReferenceImports {
+    functions: Vec<(String, String, ReferenceFn)>,
+}
+
+impl fmt::Debug for ReferenceImports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.functions
+                    .iter()
+                    .map(|(module, name, _)| format!("{}.{}", module, name)),
+            )
+            .finish()
+    }
+}
+
+impl ReferenceImports {
+    /// Create an empty collection.
+    pub fn new() -> Self {
+        ReferenceImports::default()
+    }
+
+    /// Register the host function of `module` and `name`, replacing any registered before.
+    pub fn func(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: impl FnMut(&[TypedValue]) -> Result<Option<TypedValue>, Trap> + Send + 'static,
+    ) -> &mut Self {
+        self.functions
+            .retain(|(other_module, other_name, _)| other_module != module || other_name != name);
+        self.functions
+            .push((module.to_string(), name.to_string(), Box::new(func)));
+        self
+    }
+}
+
+impl wasmi::ImportResolver for ReferenceImports {
+    fn resolve_func(
+        &self,
+        module: &str,
+        name: &str,
+        signature: &wasmi::Signature,
+    ) -> Result<wasmi::FuncRef, wasmi::Error> {
+        match self
+            .functions
+            .iter()
+            .position(|(other_module, other_name, _)| other_module == module && other_name == name)
+        {
+            Some(index) => Ok(wasmi::FuncInstance::alloc_host(signature.clone(), index)),
+            None => Err(wasmi::Error::Instantiation(format!(
+                "imported function {}.{} is required",
+                module, name
+            ))),
+        }
+    }
+
+    fn resolve_global(
+        &self,
+        module: &str,
+        name: &str,
+        _: &wasmi::GlobalDescriptor,
+    ) -> Result<wasmi::GlobalRef, wasmi::Error> {
+        Err(unsupported_import("global", module, name))
+    }
+
+    fn resolve_memory(
+        &self,
+        module: &str,
+        name: &str,
+        _: &wasmi::MemoryDescriptor,
+    ) -> Result<wasmi::MemoryRef, wasmi::Error> {
+        Err(unsupported_import("memory", module, name))
+    }
+
+    fn resolve_table(
+        &self,
+        module: &str,
+        name: &str,
+        _: &wasmi::TableDescriptor,
+    ) -> Result<wasmi::TableRef, wasmi::Error> {
+        Err(unsupported_import("table", module, name))
+    }
+}
+
+fn unsupported_import(kind: &str, module: &str, name: &str) -> wasmi::Error {
+    wasmi::Error::Instantiation(format!(
+        "imported {} {}.{} is not supported by the reference interpreter",
+        kind, module, name
+    ))
+}
+
+impl wasmi::Externals for ReferenceImports {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: wasmi::RuntimeArgs,
+    ) -> Result<Option<wasmi::RuntimeValue>, wasmi::Trap> {
+        let args: Vec<TypedValue> = args.as_ref().iter().map(|arg| from_wasmi(*arg)).collect();
+        match (self.functions[index].2)(&args) {
+            Ok(value) => Ok(value.as_ref().map(to_wasmi)),
+            Err(trap) => Err(wasmi::Trap::new(wasmi::TrapKind::Host(Box::new(trap)))),
+        }
+    }
+}
+
+impl wasmi::HostError for Trap {}
+
+impl Instance {
+    /// Call the host functions in `imports` when executing the module in the reference
+    /// interpreter by [`Instance::execute_checked`].
+    pub fn set_reference_imports(&mut self, imports: ReferenceImports) {
+        self.reference_imports = imports;
+    }
+
+    /// Execute the function `func` like [`Instance::execute`], and execute it again in the
+    /// reference interpreter wasmi, in a new instance with the state of this one before the
+    /// execution, comparing the returned values by their bits and whether the executions trap.
+    ///
+    /// Fails with [`Error::DivergenceDetected`] describing both outcomes if they differ, and with
+    /// [`Error::Other`] if the reference interpreter cannot instantiate the module, e.g. because
+    /// a function of [`Instance::set_reference_imports`] is missing or the module imports other
+    /// kinds than functions. The memory is copied only if the module exports it.
+    ///
+    /// The state is copied for each execution, therefore this is meant for canary deployments and
+    /// tests rather than for the hot paths.
+    pub fn execute_checked(
+        &mut self,
+        func: &str,
+        args: &[TypedValue],
+    ) -> Result<TypedExecutionResult, Error> {
+        let before = self.snapshot();
+        let result = self.execute(func, args)?;
+        let ours = if result.trapped() {
+            Outcome::Trapped
+        } else {
+            Outcome::Value(result.value())
+        };
+        let theirs = self.execute_reference(&before, func, args)?;
+        if !ours.same(&theirs) {
+            return Err(Error::DivergenceDetected {
+                ours: ours.to_string(),
+                theirs: theirs.to_string(),
+            });
+        }
+        Ok(result)
+    }
+
+    /// Execute `func` in a new instance of wasmi restored to the state `before`.
+    fn execute_reference(
+        &mut self,
+        before: &StateSnapshot,
+        func: &str,
+        args: &[TypedValue],
+    ) -> Result<Outcome, Error> {
+        let failed = |err: wasmi::Error| {
+            Error::Other(format!(
+                "the reference interpreter cannot instantiate the module: {}",
+                err
+            ))
+        };
+        let module = wasmi::Module::from_buffer(self.module.bytes()).map_err(failed)?;
+        // The start function is not executed, as the state is restored afterwards.
+        let instance = wasmi::ModuleInstance::new(&module, &self.reference_imports)
+            .map_err(failed)?
+            .not_started_instance()
+            .clone();
+
+        for (global_idx, value) in &before.globals {
+            instance.globals()[*global_idx as usize]
+                .set(to_wasmi(value))
+                .map_err(failed)?;
+        }
+        let memory_export = self
+            .module
+            .exports()
+            .into_iter()
+            .find(|export| export.kind() == ExternalKind::Memory);
+        if let (Some(memory), Some(export)) = (&before.memory, memory_export) {
+            let reference_memory = instance
+                .export_by_name(export.name())
+                .and_then(|export| export.as_memory().cloned())
+                .expect("exported memory");
+            let pages = memory.len() / 65536;
+            let current = reference_memory.current_size().0;
+            if pages > current {
+                reference_memory
+                    .grow(wasmi::memory_units::Pages(pages - current))
+                    .map_err(failed)?;
+            }
+            reference_memory.set(0, memory).map_err(failed)?;
+        }
+
+        let args: Vec<wasmi::RuntimeValue> = args.iter().map(to_wasmi).collect();
+        Ok(
+            match instance.invoke_export(func, &args, &mut self.reference_imports) {
+                Ok(value) => Outcome::Value(value.map(from_wasmi)),
+                Err(wasmi::Error::Trap(_)) => Outcome::Trapped,
+                Err(err) => Outcome::Failed(Error::Other(err.to_string())),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Caller, ImportsBuilder};
+
+    /* wat2wasm
+    (module
+      (func $get (import "env" "get") (result i32))
+      (memory (export "memory") 1)
+      (global $count (mut i32) (i32.const 0))
+      (func (export "bump") (result i32)
+        (global.set $count (i32.add (global.get $count) (i32.const 1)))
+        (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (global.get $count)))
+        (i32.load (i32.const 0)))
+      (func (export "host") (result i32) (call $get))
+      (func (export "div") (param i32) (result i32) (i32.div_u (i32.const 1) (local.get 0)))
+    )
+    */
+    const CHECKED_WASM: &str = "0061736d01000000010a026000017f60017f017f020b0103656e7603676574000003040300000105030100010606017f0141000b071e04066d656d6f727902000462756d70000104686f737400020364697600030a2a031b00230041016a24004100410028020023006a36020041002802000b040010000b0700410120006e0b";
+
+    fn instantiate(reference_get: u32) -> Instance {
+        let mut imports = ImportsBuilder::new();
+        imports.func("env", "get", |_: &mut Caller| -> Result<u32, Trap> {
+            Ok(1)
+        });
+        let module = parse(&hex::decode(CHECKED_WASM).unwrap()).unwrap();
+        let mut instance = module.instantiate_with_imports(imports).unwrap();
+        let mut reference = ReferenceImports::new();
+        reference.func("env", "get", move |_| {
+            Ok(Some(TypedValue::U32(reference_get)))
+        });
+        instance.set_reference_imports(reference);
+        instance
+    }
+
+    #[test]
+    fn same_outcomes() {
+        let mut instance = instantiate(1);
+        // The state of the instance is copied to the reference interpreter.
+        for expected in &[1, 3, 6] {
+            let result = instance.execute_checked("bump", &[]).unwrap();
+            assert_eq!(result.value(), Some(TypedValue::U32(*expected)));
+        }
+        let result = instance.execute_checked("host", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+        let result = instance
+            .execute_checked("div", &[TypedValue::U32(0)])
+            .unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.execute_checked("missing", &[]).err(),
+            Some(Error::FunctionNotFound)
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        let mut instance = instantiate(2);
+        assert_eq!(
+            instance.execute_checked("host", &[]).err(),
+            Some(Error::DivergenceDetected {
+                ours: "returned i32:1".to_string(),
+                theirs: "returned i32:2".to_string(),
+            })
+        );
+        assert_eq!(
+            instance
+                .execute_checked("host", &[])
+                .err()
+                .unwrap()
+                .to_string(),
+            "divergence from the reference interpreter: fizzy returned i32:1, wasmi returned i32:2"
+        );
+
+        let mut reference = ReferenceImports::new();
+        reference.func("env", "get", |_| Err(Trap::new("diverged")));
+        instance.set_reference_imports(reference);
+        assert_eq!(
+            instance.execute_checked("host", &[]).err(),
+            Some(Error::DivergenceDetected {
+                ours: "returned i32:1".to_string(),
+                theirs: "trapped".to_string(),
+            })
+        );
+
+        instance.set_reference_imports(ReferenceImports::new());
+        match instance.execute_checked("host", &[]) {
+            Err(Error::Other(message)) => assert!(message.contains(
+                "the reference interpreter cannot instantiate the module: \
+                 Instantiation: imported function env.get is required"
+            )),
+            result => panic!(
+                "unexpected result: {:?}",
+                result.map(|result| result.value())
+            ),
+        }
+    }
+}
@@ -0,0 +1,288 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pools of reusable instances.
+
+use crate::{Error, Instance, Module};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// How an instance is reset when it is returned to an [`InstancePool`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetPolicy {
+    /// The instance is reused as it is, therefore the state left by one user is visible to the next.
+    None,
+    /// The memory is restored from a snapshot taken right after instantiation.
+    ///
+    /// Globals and tables are not restored. If the memory has grown, the instance is replaced
+    /// by a new one.
+    MemoryFromSnapshot,
+    /// The instance is replaced by a new one.
+    Reinstantiate,
+}
+
+struct PoolState {
+    idle: Vec<Instance>,
+    /// The number of instances which have been dropped and are to be replaced by new ones.
+    missing: usize,
+}
+
+/// A fixed number of instances of a module to be used in turns, e.g. by the workers of a server.
+///
+/// Only modules without imported functions are supported.
+///
+/// ```
+/// use fizzy::{InstancePool, ResetPolicy};
+///
+/// // This wasm binary exports a single sum(u32, u32) -> u32 function.
+/// let wasm = [
+///     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+///     0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x73, 0x75, 0x6d,
+///     0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+/// ];
+/// let module = fizzy::parse(&wasm).expect("parsing failed");
+/// let pool = InstancePool::new(module, 4, ResetPolicy::MemoryFromSnapshot).expect("instantiation failed");
+/// let mut instance = pool.get().expect("instantiation failed");
+/// let result = instance
+///     .execute("sum", &[fizzy::TypedValue::U32(42), fizzy::TypedValue::U32(24)])
+///     .expect("execution failed");
+/// assert_eq!(result.value(), Some(fizzy::TypedValue::U32(66)));
+/// ```
+pub struct InstancePool {
+    module: Module,
+    reset: ResetPolicy,
+    /// The memory right after instantiation, if reset from it.
+    snapshot: Option<Vec<u8>>,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+impl InstancePool {
+    /// Create a pool of `size` instances of `module`, which are reset according to `reset` when returned.
+    ///
+    /// Fails if `module` cannot be instantiated.
+    pub fn new(module: Module, size: usize, reset: ResetPolicy) -> Result<Self, Error> {
+        let idle = (0..size)
+            .map(|_| module.instantiate())
+            .collect::<Result<Vec<_>, _>>()?;
+        let snapshot = match (reset, idle.first()) {
+            (ResetPolicy::MemoryFromSnapshot, Some(instance)) if instance.memory_size() > 0 => {
+                let mut snapshot = vec![0; instance.memory_size()];
+                instance.memory_get(0, &mut snapshot)?;
+                Some(snapshot)
+            }
+            _ => None,
+        };
+        Ok(InstancePool {
+            module,
+            reset,
+            snapshot,
+            state: Mutex::new(PoolState { idle, missing: 0 }),
+            returned: Condvar::new(),
+        })
+    }
+
+    /// Take an instance from the pool, waiting until one is returned if all of them are in use.
+    ///
+    /// Fails if an instance needs to be replaced and the module cannot be instantiated.
+    pub fn get(&self) -> Result<PooledInstance<'_>, Error> {
+        let mut state = self.lock();
+        loop {
+            if let Some(instance) = state.idle.pop() {
+                return Ok(PooledInstance {
+                    pool: self,
+                    instance: Some(instance),
+                });
+            }
+            if state.missing > 0 {
+                state.missing -= 1;
+                break;
+            }
+            state = self.returned.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        drop(state);
+
+        match self.module.instantiate() {
+            Ok(instance) => Ok(PooledInstance {
+                pool: self,
+                instance: Some(instance),
+            }),
+            Err(err) => {
+                self.lock().missing += 1;
+                self.returned.notify_one();
+                Err(err)
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reset `instance` and return it to the pool.
+    fn put(&self, mut instance: Instance) {
+        let reusable = match self.reset {
+            ResetPolicy::None => true,
+            ResetPolicy::MemoryFromSnapshot => match &self.snapshot {
+                Some(snapshot) if instance.memory_size() == snapshot.len() => {
+                    instance.memory_set(0, snapshot).is_ok()
+                }
+                Some(_) => false,
+                None => instance.memory_size() == 0,
+            },
+            ResetPolicy::Reinstantiate => false,
+        };
+        let mut state = self.lock();
+        if reusable {
+            state.idle.push(instance);
+        } else {
+            state.missing += 1;
+        }
+        drop(state);
+        self.returned.notify_one();
+    }
+}
+
+/// An instance taken from an [`InstancePool`], which is returned to the pool when dropped.
+pub struct PooledInstance<'a> {
+    pool: &'a InstancePool,
+    /// Always present until dropped.
+    instance: Option<Instance>,
+}
+
+impl Deref for PooledInstance<'_> {
+    type Target = Instance;
+
+    fn deref(&self) -> &Instance {
+        self.instance
+            .as_ref()
+            .expect("instance present until dropped")
+    }
+}
+
+impl DerefMut for PooledInstance<'_> {
+    fn deref_mut(&mut self) -> &mut Instance {
+        self.instance
+            .as_mut()
+            .expect("instance present until dropped")
+    }
+}
+
+impl Drop for PooledInstance<'_> {
+    fn drop(&mut self) {
+        if let Some(instance) = self.instance.take() {
+            self.pool.put(instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, TypedValue};
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn module() -> Module {
+        /* wat2wasm
+        (module
+          (memory 1 2)
+          (global $calls (mut i32) (i32.const 0))
+          (data (i32.const 0) "\2a")
+          ;; Returns the byte at 0 and then sets it to the argument.
+          (func (export "swap") (param i32) (result i32)
+            (i32.load8_u (i32.const 0))
+            (i32.store8 (i32.const 0) (local.get 0)))
+          ;; Returns the number of calls so far.
+          (func (export "count") (result i32)
+            (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+            (global.get $calls))
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260017f017f6000017f0304030001010504010101020606017f0141000b0717030473776170000005636f756e7400010467726f7700020a23030e0041002d0000410020003a00000b0b00230041016a240023000b0600410140000b0b07010041000b012a").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn call(instance: &mut Instance, name: &str, args: &[TypedValue]) -> u32 {
+        let result = instance.execute(name, args).unwrap();
+        assert!(!result.trapped());
+        result.value().unwrap().as_u32().unwrap()
+    }
+
+    fn pool(reset: ResetPolicy) -> InstancePool {
+        InstancePool::new(module(), 1, reset).unwrap()
+    }
+
+    #[test]
+    fn send_sync() {
+        assert_send_sync::<InstancePool>();
+    }
+
+    #[test]
+    fn reset_none() {
+        let pool = pool(ResetPolicy::None);
+        let mut instance = pool.get().unwrap();
+        assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(1)]), 42);
+        assert_eq!(call(&mut instance, "count", &[]), 1);
+        drop(instance);
+
+        let mut instance = pool.get().unwrap();
+        assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(2)]), 1);
+        assert_eq!(call(&mut instance, "count", &[]), 2);
+    }
+
+    #[test]
+    fn reset_memory_from_snapshot() {
+        let pool = pool(ResetPolicy::MemoryFromSnapshot);
+        let mut instance = pool.get().unwrap();
+        assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(1)]), 42);
+        assert_eq!(call(&mut instance, "count", &[]), 1);
+        drop(instance);
+
+        // The memory is restored, but globals are not.
+        let mut instance = pool.get().unwrap();
+        assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(2)]), 42);
+        assert_eq!(call(&mut instance, "count", &[]), 2);
+        // Growing the memory requires replacing the instance.
+        assert_eq!(call(&mut instance, "grow", &[]), 1);
+        drop(instance);
+
+        let mut instance = pool.get().unwrap();
+        assert_eq!(instance.memory_size(), 65536);
+        assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(3)]), 42);
+        assert_eq!(call(&mut instance, "count", &[]), 1);
+    }
+
+    #[test]
+    fn reset_reinstantiate() {
+        let pool = pool(ResetPolicy::Reinstantiate);
+        for _ in 0..3 {
+            let mut instance = pool.get().unwrap();
+            assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(1)]), 42);
+            assert_eq!(call(&mut instance, "count", &[]), 1);
+        }
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let pool =
+            Arc::new(InstancePool::new(module(), 2, ResetPolicy::MemoryFromSnapshot).unwrap());
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut instance = pool.get().unwrap();
+                        assert_eq!(call(&mut instance, "swap", &[TypedValue::U32(i)]), 42);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
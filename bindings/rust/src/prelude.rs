@@ -0,0 +1,27 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The items needed by most users, to be imported with `use fizzy::prelude::*`.
+//!
+//! ```
+//! use fizzy::prelude::*;
+//!
+//! fn sum(module: &Module) -> Result<Option<TypedValue>, Error> {
+//!     let mut instance = module.instantiate_with_options(
+//!         ImportsBuilder::new(),
+//!         &InstantiateOptions::new().max_call_depth(100),
+//!     )?;
+//!     let outcome = instance.execute_with_options(
+//!         "sum",
+//!         &[TypedValue::U32(1), TypedValue::U32(2)],
+//!         &ExecutionOptions::new(),
+//!     )?;
+//!     Ok(outcome.result().value())
+//! }
+//! ```
+
+pub use crate::{
+    parse, Caller, Error, ExecutionOptions, ImportsBuilder, Instance, InstantiateOptions, Module,
+    Trap, TypedValue, ValueType,
+};
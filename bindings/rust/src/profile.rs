@@ -0,0 +1,313 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The function-level profile of an execution, measured with the trace hooks.
+
+use crate::{Error, ExecutionOptions, Instance, TraceEvent, TraceSink, TrapInfo, TypedValue};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The profile of the calls of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionProfile {
+    index: u32,
+    name: Option<String>,
+    calls: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+    max_depth: usize,
+}
+
+impl FunctionProfile {
+    /// The index of the function in the module.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The name of the function in the name section of the module, or otherwise its export name.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The number of calls of the function.
+    pub fn calls(&self) -> u64 {
+        self.calls
+    }
+
+    /// The time spent in the function, including the functions it has called. The time of
+    /// recursive calls is counted only once, in the outermost call.
+    pub fn inclusive_time(&self) -> Duration {
+        self.inclusive
+    }
+
+    /// The time spent in the function itself, excluding the functions it has called.
+    pub fn exclusive_time(&self) -> Duration {
+        self.exclusive
+    }
+
+    /// The maximum depth of the calls of the function, where the executed function is at depth 1.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+}
+
+/// The profile of an execution with [`Instance::profile`].
+#[derive(Clone, Debug, Default)]
+pub struct ProfileReport {
+    functions: BTreeMap<u32, FunctionProfile>,
+    /// The exclusive time of each stack of function indices, from the executed function.
+    stacks: BTreeMap<Vec<u32>, Duration>,
+}
+
+impl ProfileReport {
+    /// The profiles of the called functions, by their indices.
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionProfile> {
+        self.functions.values()
+    }
+
+    /// The profile of the function `func_idx`, if it has been called.
+    pub fn function(&self, func_idx: u32) -> Option<&FunctionProfile> {
+        self.functions.get(&func_idx)
+    }
+
+    /// Render the stacks in the folded format of flamegraph tools, e.g. `run;fac;fac 1534`, in
+    /// lines of the frames separated by `;` and the exclusive time of the stack in nanoseconds.
+    ///
+    /// The functions without names are written as `func[index]`, and the spaces and semicolons
+    /// in names are replaced by underscores.
+    pub fn to_flamegraph_folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, time) in &self.stacks {
+            for (i, func_idx) in stack.iter().enumerate() {
+                if i != 0 {
+                    out.push(';');
+                }
+                match self.functions.get(func_idx).and_then(FunctionProfile::name) {
+                    Some(name) => out.extend(name.chars().map(|c| {
+                        if c == ';' || c.is_whitespace() {
+                            '_'
+                        } else {
+                            c
+                        }
+                    })),
+                    None => out.push_str(&format!("func[{}]", func_idx)),
+                }
+            }
+            out.push_str(&format!(" {}\n", time.as_nanos()));
+        }
+        out
+    }
+}
+
+/// A call in progress.
+struct Frame {
+    func_idx: u32,
+    start: Instant,
+    /// The time spent in the functions called so far.
+    children: Duration,
+}
+
+#[derive(Default)]
+struct Profiler {
+    report: ProfileReport,
+    stack: Vec<Frame>,
+}
+
+impl Profiler {
+    fn event(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::Enter { index, name, .. } => {
+                let depth = self.stack.len() + 1;
+                let functions = &mut self.report.functions;
+                let function = functions.entry(*index).or_insert_with(|| FunctionProfile {
+                    index: *index,
+                    name: name.map(str::to_string),
+                    calls: 0,
+                    inclusive: Duration::default(),
+                    exclusive: Duration::default(),
+                    max_depth: 0,
+                });
+                function.calls += 1;
+                function.max_depth = function.max_depth.max(depth);
+                self.stack.push(Frame {
+                    func_idx: *index,
+                    start: Instant::now(),
+                    children: Duration::default(),
+                });
+            }
+            TraceEvent::Leave { .. } => {
+                let elapsed = match self.stack.last() {
+                    Some(frame) => frame.start.elapsed(),
+                    None => return,
+                };
+                let path: Vec<u32> = self.stack.iter().map(|frame| frame.func_idx).collect();
+                let frame = self.stack.pop().expect("empty stack");
+                let exclusive = elapsed.checked_sub(frame.children).unwrap_or_default();
+                let recursive = self
+                    .stack
+                    .iter()
+                    .any(|caller| caller.func_idx == frame.func_idx);
+                let function = self
+                    .report
+                    .functions
+                    .get_mut(&frame.func_idx)
+                    .expect("function not entered");
+                function.exclusive += exclusive;
+                if !recursive {
+                    function.inclusive += elapsed;
+                }
+                *self.report.stacks.entry(path).or_default() += exclusive;
+                if let Some(caller) = self.stack.last_mut() {
+                    caller.children += elapsed;
+                }
+            }
+        }
+    }
+}
+
+impl Instance {
+    /// Execute a given function of `name` like [`Instance::execute`], measuring the calls of
+    /// functions with the trace hooks.
+    ///
+    /// Traps are reported as [`Error::Trapped`]. The times include the overhead of the hooks,
+    /// therefore they are comparable between functions, but longer than of an execution without
+    /// profiling. The functions of other instances called via imported tables are profiled by
+    /// their indices in their modules, which may be the same as of the functions of this one.
+    pub fn profile(
+        &mut self,
+        name: &str,
+        args: &[TypedValue],
+    ) -> Result<(Option<TypedValue>, ProfileReport), Error> {
+        let profiler = Arc::new(Mutex::new(Profiler::default()));
+        let recorder = profiler.clone();
+        let options = ExecutionOptions::new().trace(TraceSink::Callback(Box::new(move |event| {
+            recorder
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .event(event)
+        })));
+        let outcome = self.execute_with_options(name, args, &options)?;
+        if outcome.trapped() {
+            return Err(Error::Trapped(TrapInfo::new(name, self.take_host_trap())));
+        }
+        let mut profiler = profiler.lock().unwrap_or_else(|e| e.into_inner());
+        Ok((outcome.value(), std::mem::take(&mut profiler.report)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Caller, ImportsBuilder, Trap};
+
+    fn instantiate() -> Instance {
+        /* wat2wasm --debug-names
+        (module
+          (func $double (import "env" "double") (param i32) (result i32))
+          (func $fac (param i32) (result i32)
+            (if (result i32) (i32.eqz (local.get 0))
+              (then (i32.const 1))
+              (else (i32.mul (local.get 0) (call $fac (i32.sub (local.get 0) (i32.const 1)))))))
+          (func (param i32) (result i32) (call $fac (local.get 0)))
+          (func (export "run") (param i32) (result i32)
+            (call $double (i32.add (call 2 (local.get 0)) (call $fac (i32.const 2)))))
+          (func (export "trap") (unreachable))
+        )
+        */
+        let input = hex::decode("0061736d0100000001090260017f017f600000020e0103656e7606646f75626c65000003050400000001070e020372756e0003047472617000040a30041500200045047f4101052000200041016b10016c0b0b0600200010010b0d0020001002410210016a10000b0300000b0022046e616d65010e020006646f75626c650103666163020b0500000100020003000400").unwrap();
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "double",
+            |_: &mut Caller, x: u32| -> Result<u32, Trap> { Ok(x * 2) },
+        );
+        parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn call_counts() {
+        let mut instance = instantiate();
+        let (value, report) = instance.profile("run", &[TypedValue::U32(3)]).unwrap();
+        assert_eq!(value, Some(TypedValue::U32(16)));
+
+        let functions: Vec<(u32, Option<&str>, u64, usize)> = report
+            .functions()
+            .map(|function| {
+                (
+                    function.index(),
+                    function.name(),
+                    function.calls(),
+                    function.max_depth(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            functions,
+            [
+                (0, Some("double"), 1, 2),
+                (1, Some("fac"), 7, 6),
+                (2, None, 1, 2),
+                (3, Some("run"), 1, 1),
+            ]
+        );
+
+        let run = report.function(3).unwrap();
+        let fac = report.function(1).unwrap();
+        assert!(run.exclusive_time() <= run.inclusive_time());
+        assert!(fac.inclusive_time() <= run.inclusive_time());
+        assert!(fac.exclusive_time() <= run.inclusive_time());
+        assert!(report.function(4).is_none());
+    }
+
+    #[test]
+    fn flamegraph_folded() {
+        let mut instance = instantiate();
+        let (_, report) = instance.profile("run", &[TypedValue::U32(1)]).unwrap();
+        let folded = report.to_flamegraph_folded();
+        let mut stacks = Vec::new();
+        let mut total = 0;
+        for line in folded.lines() {
+            let (stack, time) = line.split_at(line.rfind(' ').unwrap());
+            total += time.trim().parse::<u128>().unwrap();
+            assert!(stack.split(';').all(|frame| !frame.is_empty()));
+            stacks.push(stack);
+        }
+        assert_eq!(
+            stacks,
+            [
+                "run",
+                "run;double",
+                "run;fac",
+                "run;fac;fac",
+                "run;fac;fac;fac",
+                "run;func[2]",
+                "run;func[2];fac",
+                "run;func[2];fac;fac",
+            ]
+        );
+        // The exclusive times add up to the inclusive time of the executed function.
+        assert_eq!(
+            total,
+            report.function(3).unwrap().inclusive_time().as_nanos()
+        );
+    }
+
+    #[test]
+    fn trapped() {
+        let mut instance = instantiate();
+        match instance.profile("trap", &[]) {
+            Err(Error::Trapped(info)) => assert_eq!(info.function(), Some("trap")),
+            _ => panic!("expected a trap"),
+        }
+        assert_eq!(
+            instance.profile("none", &[]).err(),
+            Some(Error::FunctionNotFound)
+        );
+    }
+}
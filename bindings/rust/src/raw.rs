@@ -0,0 +1,179 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversions of the types of `fizzy-types` from and to the low-level representations, which
+//! that crate cannot implement, as it does not depend on the engine.
+
+use crate::{
+    sys, Error, ExternalKind, FunctionType, GlobalType, Limits, TypeError, TypedValue, Value,
+    ValueType,
+};
+
+/// The low-level representations of the reference types, which are their binary encodings like
+/// those of the other types.
+const RAW_FUNCREF: sys::FizzyValueType = 0x70;
+const RAW_EXTERNREF: sys::FizzyValueType = 0x6f;
+
+pub(crate) trait ValueTypeExt: Sized {
+    /// Convert an optional type (where `None` stands for void) to the low-level representation.
+    fn to_raw(value_type: Option<ValueType>) -> sys::FizzyValueType;
+
+    /// Convert from the low-level representation, where `None` stands for void.
+    fn from_raw(value_type: sys::FizzyValueType) -> Option<ValueType>;
+
+    /// Fail with [`Error::UnsupportedType`] for the types the engine does not support, i.e. the
+    /// reference types.
+    fn check_supported(self) -> Result<(), Error>;
+}
+
+impl ValueTypeExt for ValueType {
+    fn to_raw(value_type: Option<ValueType>) -> sys::FizzyValueType {
+        match value_type {
+            Some(ValueType::I32) => sys::FizzyValueTypeI32,
+            Some(ValueType::I64) => sys::FizzyValueTypeI64,
+            Some(ValueType::F32) => sys::FizzyValueTypeF32,
+            Some(ValueType::F64) => sys::FizzyValueTypeF64,
+            Some(ValueType::FuncRef) => RAW_FUNCREF,
+            Some(ValueType::ExternRef) => RAW_EXTERNREF,
+            Some(_) => unreachable!("unsupported value type"),
+            None => sys::FizzyValueTypeVoid,
+        }
+    }
+
+    fn from_raw(value_type: sys::FizzyValueType) -> Option<ValueType> {
+        match value_type {
+            sys::FizzyValueTypeI32 => Some(ValueType::I32),
+            sys::FizzyValueTypeI64 => Some(ValueType::I64),
+            sys::FizzyValueTypeF32 => Some(ValueType::F32),
+            sys::FizzyValueTypeF64 => Some(ValueType::F64),
+            RAW_FUNCREF => Some(ValueType::FuncRef),
+            RAW_EXTERNREF => Some(ValueType::ExternRef),
+            sys::FizzyValueTypeVoid => None,
+            _ => panic!("invalid value type"),
+        }
+    }
+
+    fn check_supported(self) -> Result<(), Error> {
+        if !matches!(
+            self,
+            ValueType::I32 | ValueType::I64 | ValueType::F32 | ValueType::F64
+        ) {
+            return Err(Error::UnsupportedType(self.to_string()));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) trait FunctionTypeExt {
+    /// Convert from the low-level representation.
+    ///
+    /// # Safety
+    /// The inputs of `func_type` must be valid.
+    unsafe fn from_raw(func_type: &sys::FizzyFunctionType) -> Self;
+}
+
+impl FunctionTypeExt for FunctionType {
+    unsafe fn from_raw(func_type: &sys::FizzyFunctionType) -> Self {
+        // The inputs pointer is null for functions without inputs, which is not a valid slice.
+        let inputs = if func_type.inputs_size == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(func_type.inputs, func_type.inputs_size)
+        };
+        FunctionType {
+            inputs: inputs
+                .iter()
+                .map(|value_type| ValueType::from_raw(*value_type).expect("void input type"))
+                .collect(),
+            output: ValueType::from_raw(func_type.output),
+        }
+    }
+}
+
+pub(crate) trait LimitsExt {
+    /// Convert from the low-level representation.
+    fn from_raw(limits: &sys::FizzyLimits) -> Self;
+}
+
+impl LimitsExt for Limits {
+    fn from_raw(limits: &sys::FizzyLimits) -> Self {
+        Limits {
+            min: limits.min,
+            max: if limits.has_max {
+                Some(limits.max)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+pub(crate) trait GlobalTypeExt {
+    /// Convert from the low-level representation.
+    fn from_raw(global_type: &sys::FizzyGlobalType) -> Self;
+}
+
+impl GlobalTypeExt for GlobalType {
+    fn from_raw(global_type: &sys::FizzyGlobalType) -> Self {
+        GlobalType {
+            value_type: ValueType::from_raw(global_type.value_type).expect("void global type"),
+            mutable: global_type.is_mutable,
+        }
+    }
+}
+
+pub(crate) trait ExternalKindExt {
+    /// Convert from the low-level representation.
+    fn from_raw(kind: sys::FizzyExternalKind) -> Self;
+}
+
+impl ExternalKindExt for ExternalKind {
+    fn from_raw(kind: sys::FizzyExternalKind) -> Self {
+        match kind {
+            sys::FizzyExternalKind_FizzyExternalKindFunction => ExternalKind::Function,
+            sys::FizzyExternalKind_FizzyExternalKindTable => ExternalKind::Table,
+            sys::FizzyExternalKind_FizzyExternalKindMemory => ExternalKind::Memory,
+            sys::FizzyExternalKind_FizzyExternalKindGlobal => ExternalKind::Global,
+            _ => panic!("invalid external kind"),
+        }
+    }
+}
+
+pub(crate) trait TypedValueExt {
+    /// Attach the type `value_type` to an untyped `value`.
+    fn from_value(value: Value, value_type: ValueType) -> Self;
+}
+
+impl TypedValueExt for TypedValue {
+    fn from_value(value: Value, value_type: ValueType) -> Self {
+        match value_type {
+            ValueType::I32 => TypedValue::U32(value.as_u32()),
+            ValueType::I64 => TypedValue::U64(value.as_u64()),
+            ValueType::F32 => TypedValue::F32(value.as_f32()),
+            ValueType::F64 => TypedValue::F64(value.as_f64()),
+            ValueType::FuncRef => TypedValue::FuncRef,
+            ValueType::ExternRef => TypedValue::ExternRef,
+            _ => unreachable!("unsupported value type"),
+        }
+    }
+}
+
+impl From<&TypedValue> for sys::FizzyValue {
+    fn from(v: &TypedValue) -> sys::FizzyValue {
+        match v {
+            TypedValue::U32(v) => sys::FizzyValue { i32: *v },
+            TypedValue::U64(v) => sys::FizzyValue { i64: *v },
+            TypedValue::F32(v) => sys::FizzyValue { f32: *v },
+            TypedValue::F64(v) => sys::FizzyValue { f64: *v },
+            // The references are rejected before they are passed to the engine.
+            _ => sys::FizzyValue { i64: 0 },
+        }
+    }
+}
+
+impl From<TypeError> for Error {
+    fn from(err: TypeError) -> Self {
+        Error::Other(err.message().to_string())
+    }
+}
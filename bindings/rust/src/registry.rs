@@ -0,0 +1,342 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Linking instances of multiple modules by the names of their namespaces.
+
+use crate::{
+    Caller, Error, ExternalType, HostResult, ImportsBuilder, Instance, InstantiateOptions, Module,
+    Trap, TrapInfo, TypedValue,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+
+/// The namespaces of instances, whose exported functions are imported by other modules by the
+/// name of the namespace, e.g. `(import "math" "add" (func ...))` from the instance defined as
+/// `math`.
+///
+/// The modules defined with [`Registry::define_module`] are instantiated on the first import
+/// from them, after the modules they import from, and the cyclic imports between them are
+/// reported as errors.
+///
+/// ```ignore
+/// let mut registry = fizzy::Registry::new();
+/// registry.define_instance("math", math.instantiate()?);
+/// registry.define_module("utils", utils);
+/// let mut instance = registry.instantiate(&main)?;
+/// ```
+///
+/// Only the imported functions are resolved, which call the exported functions of the other
+/// instances as host functions. An instance executing a call from another instance cannot be
+/// called again until the call returns, and traps instead.
+#[derive(Default)]
+pub struct Registry {
+    instances: HashMap<String, Arc<Mutex<Instance>>>,
+    /// The modules to be instantiated on the first import.
+    modules: HashMap<String, Module>,
+}
+
+impl Registry {
+    /// Create a registry without namespaces.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Define the namespace `name` of the exports of `instance`, replacing any previous
+    /// definition of `name`.
+    pub fn define_instance(&mut self, name: &str, instance: Instance) -> &mut Self {
+        self.modules.remove(name);
+        self.instances
+            .insert(name.to_string(), Arc::new(Mutex::new(instance)));
+        self
+    }
+
+    /// Define the namespace `name` of the exports of an instance of `module`, which is created
+    /// on the first import from it, replacing any previous definition of `name`.
+    pub fn define_module(&mut self, name: &str, module: Module) -> &mut Self {
+        self.instances.remove(name);
+        self.modules.insert(name.to_string(), module);
+        self
+    }
+
+    /// The instance of the namespace `name`, unless it is not defined or not instantiated yet.
+    pub fn instance(&self, name: &str) -> Option<MutexGuard<'_, Instance>> {
+        self.instances.get(name).map(|instance| lock(instance))
+    }
+
+    /// Create an instance of `module` with the imported functions resolved against the
+    /// namespaces, instantiating the modules it imports from first.
+    ///
+    /// The imports from the namespaces which are not defined are reported as missing, like by
+    /// [`Module::instantiate_with`].
+    pub fn instantiate(&mut self, module: &Module) -> Result<Instance, Error> {
+        let imports = self.resolve(module, &mut Vec::new())?;
+        module.instantiate_with(&InstantiateOptions::new().imports(imports))
+    }
+
+    /// Resolve the imported functions of `module`, which is imported by the namespaces of `path`.
+    fn resolve(
+        &mut self,
+        module: &Module,
+        path: &mut Vec<String>,
+    ) -> Result<ImportsBuilder, Error> {
+        let mut imports = ImportsBuilder::new();
+        for import in module.imports() {
+            let func_type = match import.ty() {
+                ExternalType::Function(func_type) => func_type,
+                _ => continue,
+            };
+            let namespace = import.module();
+            if !self.instances.contains_key(namespace)
+                && !self.define_dependency(namespace, path)?
+            {
+                continue;
+            }
+            let provider = self.instances[namespace].clone();
+            let export_type = lock(&provider)
+                .with_exported_function(import.name(), |_, func_type| func_type.clone());
+            // The functions not exported are reported as missing.
+            if let Some(export_type) = export_type {
+                if export_type != *func_type {
+                    return Err(Error::InstantiationFailed(format!(
+                        "function {}.{} type doesn't match the exported function",
+                        namespace,
+                        import.name()
+                    )));
+                }
+                imports.func_with_type(
+                    namespace,
+                    import.name(),
+                    func_type.clone(),
+                    forward(provider, namespace, import.name()),
+                );
+            }
+        }
+        Ok(imports)
+    }
+
+    /// Instantiate the module of the namespace `name` imported by the namespaces of `path`, if
+    /// it is defined.
+    fn define_dependency(&mut self, name: &str, path: &mut Vec<String>) -> Result<bool, Error> {
+        if let Some(position) = path.iter().position(|other| other == name) {
+            let mut cycle = path[position..].to_vec();
+            cycle.push(name.to_string());
+            return Err(Error::InstantiationFailed(format!(
+                "cyclic imports: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        let module = match self.modules.get(name) {
+            Some(module) => module.clone(),
+            None => return Ok(false),
+        };
+        path.push(name.to_string());
+        let imports = self.resolve(&module, path);
+        path.pop();
+        let instance = module
+            .instantiate_with(&InstantiateOptions::new().imports(imports?))
+            .map_err(|err| match err {
+                Error::InstantiationFailed(message) => {
+                    Error::InstantiationFailed(format!("module {}: {}", name, message))
+                }
+                err => err,
+            })?;
+        self.define_instance(name, instance);
+        Ok(true)
+    }
+}
+
+fn lock(instance: &Mutex<Instance>) -> MutexGuard<'_, Instance> {
+    instance.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The host function calling the exported function `name` of `provider`, the instance of the
+/// namespace `namespace`.
+fn forward(
+    provider: Arc<Mutex<Instance>>,
+    namespace: &str,
+    name: &str,
+) -> impl FnMut(&mut Caller, &[TypedValue]) -> HostResult + Send + 'static {
+    let full_name = format!("{}.{}", namespace, name);
+    let name = name.to_string();
+    move |_: &mut Caller, args: &[TypedValue]| {
+        let mut instance = match provider.try_lock() {
+            Ok(instance) => instance,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                return Err(Trap::new(format!(
+                    "function {} called while its instance is executing",
+                    full_name
+                )))
+            }
+        };
+        let result = instance
+            .execute(&name, args)
+            .map_err(|err| Trap::new(err.to_string()))?;
+        if result.trapped() {
+            let trap = TrapInfo::new(&full_name, instance.take_host_trap());
+            return Err(Trap::new(trap.to_string()));
+        }
+        Ok(result.value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ExternalKind};
+
+    fn math() -> Module {
+        /* wat2wasm
+        (module
+          (global $calls (export "calls") (mut i32) (i32.const 0))
+          (func (export "add") (param i32 i32) (result i32)
+            (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+            (i32.add (local.get 0) (local.get 1)))
+          (func (export "fail") (unreachable))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260027f7f017f60000003030200010606017f0141000b0716030563616c6c730300036164640000046661696c00010a14020e00230041016a2400200020016a0b0300000b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn utils() -> Module {
+        /* wat2wasm
+        (module
+          (func $add (import "math" "add") (param i32 i32) (result i32))
+          (func $fail (import "math" "fail"))
+          (func (export "double") (param i32) (result i32) (call $add (local.get 0) (local.get 0)))
+          (func (export "fail") (call $fail))
+        )
+        */
+        let input = hex::decode("0061736d01000000010f0360027f7f017f60000060017f017f021802046d617468036164640000046d617468046661696c0001030302020107110206646f75626c650002046661696c00030a0f0208002000200010000b040010010b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn main() -> Module {
+        /* wat2wasm
+        (module
+          (func $double (import "utils" "double") (param i32) (result i32))
+          (func $add (import "math" "add") (param i32 i32) (result i32))
+          (func $fail (import "utils" "fail"))
+          (func (export "run") (param i32) (result i32)
+            (call $add (call $double (local.get 0)) (i32.const 1)))
+          (func (export "fail") (call $fail))
+        )
+        */
+        let input = hex::decode("0061736d01000000010f0360017f017f60027f7f017f600000022803057574696c7306646f75626c650000046d617468036164640001057574696c73046661696c00020303020002070e020372756e0003046661696c00040a11020a0020001000410110010b040010020b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn calls(registry: &Registry) -> Option<TypedValue> {
+        registry.instance("math").unwrap().global_value("calls")
+    }
+
+    #[test]
+    fn instantiate() {
+        let mut registry = Registry::new();
+        registry
+            .define_instance("math", math().instantiate().unwrap())
+            .define_module("utils", utils());
+        assert!(registry.instance("utils").is_none());
+        let mut instance = registry.instantiate(&main()).unwrap();
+        assert!(registry.instance("utils").is_some());
+
+        let result = instance.execute("run", &[TypedValue::U32(5)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(11)));
+        assert_eq!(calls(&registry), Some(TypedValue::U32(2)));
+
+        // The trap of math is reported through utils.
+        let result = instance.execute("fail", &[]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            TrapInfo::new("fail", instance.take_host_trap()).to_string(),
+            "trap in function fail: trap in function utils.fail: trap in function math.fail"
+        );
+
+        // Another instance shares the instances of the namespaces.
+        let mut other = registry.instantiate(&main()).unwrap();
+        let result = other.execute("run", &[TypedValue::U32(1)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(3)));
+        assert_eq!(calls(&registry), Some(TypedValue::U32(4)));
+    }
+
+    #[test]
+    fn lazy_dependencies() {
+        let mut registry = Registry::new();
+        registry
+            .define_module("utils", utils())
+            .define_module("math", math());
+        let mut instance = registry.instantiate(&main()).unwrap();
+        // math is instantiated before utils, which imports from it.
+        assert_eq!(calls(&registry), Some(TypedValue::U32(0)));
+        let result = instance.execute("run", &[TypedValue::U32(5)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(11)));
+        assert_eq!(calls(&registry), Some(TypedValue::U32(2)));
+    }
+
+    #[test]
+    fn missing() {
+        let mut registry = Registry::new();
+        registry.define_module("utils", utils());
+        // The missing imports of the modules imported from are reported as they are.
+        assert_eq!(
+            registry.instantiate(&main()).err(),
+            Some(Error::MissingImport {
+                module: "math".to_string(),
+                name: "add".to_string(),
+                kind: ExternalKind::Function,
+            })
+        );
+        /* wat2wasm
+        (module (func (export "add") (param i64 i64) (result i64) (i64.add (local.get 0) (local.get 1))))
+        */
+        let input = hex::decode(
+            "0061736d0100000001070160027e7e017e030201000707010361646400000a09010700200020017c0b",
+        )
+        .unwrap();
+        registry.define_module("math", parse(&input).unwrap());
+        assert_eq!(
+            registry.instantiate(&main()).err(),
+            Some(Error::InstantiationFailed(
+                "function math.add type doesn't match the exported function".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        /* wat2wasm
+        (module
+          (func $g (import "b" "g"))
+          (func (export "f") (call $g))
+        )
+        */
+        let a = hex::decode(
+            "0061736d0100000001040160000002070101620167000003020100070501016600010a0601040010000b",
+        )
+        .unwrap();
+        /* wat2wasm
+        (module
+          (func $f (import "a" "f"))
+          (func (export "g") (call $f))
+        )
+        */
+        let b = hex::decode(
+            "0061736d0100000001040160000002070101610166000003020100070501016700010a0601040010000b",
+        )
+        .unwrap();
+        let mut registry = Registry::new();
+        registry
+            .define_module("a", parse(&a).unwrap())
+            .define_module("b", parse(&b).unwrap());
+        assert_eq!(
+            registry.instantiate(&parse(&a).unwrap()).err(),
+            Some(Error::InstantiationFailed(
+                "cyclic imports: b -> a -> b".to_string()
+            ))
+        );
+        assert!(registry.instance("a").is_none());
+        assert!(registry.instance("b").is_none());
+    }
+}
@@ -0,0 +1,516 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording executions with the results of the host functions they call, and replaying them
+//! without the host, e.g. to reproduce an execution depending on time or randomness.
+//!
+//! ```
+//! use fizzy::replay::{Recorder, Replayer};
+//! use fizzy::{Caller, ImportsBuilder, Trap, TypedValue};
+//!
+//! // This wasm binary exports a function roll() -> u32 returning the result of env.random().
+//! let wasm = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+//!     0x02, 0x0e, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x06, 0x72, 0x61, 0x6e, 0x64, 0x6f, 0x6d, 0x00,
+//!     0x00, 0x03, 0x02, 0x01, 0x00, 0x07, 0x08, 0x01, 0x04, 0x72, 0x6f, 0x6c, 0x6c, 0x00, 0x01,
+//!     0x0a, 0x06, 0x01, 0x04, 0x00, 0x10, 0x00, 0x0b,
+//! ];
+//! let module = fizzy::parse(&wasm).expect("parsing failed");
+//!
+//! let mut imports = ImportsBuilder::new();
+//! imports.func("env", "random", |_: &mut Caller| -> Result<u32, Trap> {
+//!     Ok(std::process::id())
+//! });
+//! let recorder = Recorder::new();
+//! let mut instance = module
+//!     .instantiate_with_imports(recorder.wrap(imports))
+//!     .expect("instantiation failed");
+//! let result = recorder.execute(&mut instance, "roll", &[]).expect("execution failed");
+//!
+//! let recording = recorder.recording();
+//! let outcomes = Replayer::replay(&recording, &module).expect("replay diverged");
+//! assert_eq!(outcomes[0].value(), result.value());
+//! ```
+
+use crate::imports::{self, RawHostFn};
+use crate::raw::TypedValueExt;
+use crate::{
+    Caller, Error, FunctionType, ImportsBuilder, Instance, Module, Trap, TrapInfo,
+    TypedExecutionResult, TypedValue, Value,
+};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A call of a host function.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct HostCall {
+    module: String,
+    name: String,
+    args: Vec<TypedValue>,
+    /// The returned value, or the message of the trap.
+    result: Result<Option<TypedValue>, String>,
+}
+
+impl HostCall {
+    /// The module name of the host function.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// The name of the host function.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The arguments of the call.
+    pub fn args(&self) -> &[TypedValue] {
+        &self.args
+    }
+
+    /// The value returned by the host function, or the message of its trap.
+    pub fn result(&self) -> Result<Option<TypedValue>, &str> {
+        self.result
+            .as_ref()
+            .map(|value| *value)
+            .map_err(String::as_str)
+    }
+
+    fn describe(module: &str, name: &str, args: &[TypedValue]) -> String {
+        let args: Vec<String> = args.iter().map(TypedValue::to_string).collect();
+        format!("{}.{}({})", module, name, args.join(", "))
+    }
+}
+
+/// An execution of an exported function with [`Recorder::execute`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct RecordedExecution {
+    function: String,
+    args: Vec<TypedValue>,
+    trapped: bool,
+    value: Option<TypedValue>,
+}
+
+impl RecordedExecution {
+    /// The name of the executed function.
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// The arguments of the execution.
+    pub fn args(&self) -> &[TypedValue] {
+        &self.args
+    }
+
+    /// True if the execution has trapped.
+    pub fn trapped(&self) -> bool {
+        self.trapped
+    }
+
+    /// The returned value.
+    pub fn value(&self) -> Option<TypedValue> {
+        self.value
+    }
+}
+
+/// The executions and the calls of host functions recorded by a [`Recorder`].
+///
+/// With the `serde` feature, it can be serialized to be replayed elsewhere.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Recording {
+    /// The module names, names and types of all wrapped host functions, also of those not called.
+    functions: Vec<(String, String, FunctionType)>,
+    /// The calls of host functions in their order, including those during instantiation.
+    host_calls: Vec<HostCall>,
+    executions: Vec<RecordedExecution>,
+}
+
+impl Recording {
+    /// The calls of host functions in their order, including those of the start function.
+    pub fn host_calls(&self) -> &[HostCall] {
+        &self.host_calls
+    }
+
+    /// The executions in their order.
+    pub fn executions(&self) -> &[RecordedExecution] {
+        &self.executions
+    }
+}
+
+/// The recorder of the calls of host functions and of the executions of an instance.
+#[derive(Clone, Debug, Default)]
+pub struct Recorder {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Recorder {
+    /// Create a recorder with an empty recording.
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    /// Wrap the host functions registered in `imports` with recording their calls.
+    ///
+    /// The functions registered later in the returned builder are not recorded. A panic of a
+    /// host function is recorded as the trap it is turned into.
+    pub fn wrap(&self, imports: ImportsBuilder) -> ImportsBuilder {
+        imports.wrap_functions(|module, name, func_type, mut func| {
+            let recording = self.recording.clone();
+            lock(&recording).functions.push((
+                module.to_string(),
+                name.to_string(),
+                func_type.clone(),
+            ));
+            let (module, name, func_type) =
+                (module.to_string(), name.to_string(), func_type.clone());
+            Box::new(move |caller: &mut Caller, args: &[Value]| {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| func(caller, args)));
+                let recorded = match &result {
+                    Ok(Ok(value)) => Ok(typed_output(&func_type, *value)),
+                    Ok(Err(trap)) => Err(trap.message().to_string()),
+                    Err(payload) => {
+                        Err(imports::panic_trap(payload.as_ref()).message().to_string())
+                    }
+                };
+                lock(&recording).host_calls.push(HostCall {
+                    module: module.clone(),
+                    name: name.clone(),
+                    args: typed_args(&func_type, args),
+                    result: recorded,
+                });
+                result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+        })
+    }
+
+    /// Execute the function `name` of `instance` like [`Instance::execute`], recording the
+    /// arguments and the result.
+    pub fn execute(
+        &self,
+        instance: &mut Instance,
+        name: &str,
+        args: &[TypedValue],
+    ) -> Result<TypedExecutionResult, Error> {
+        let result = instance.execute(name, args)?;
+        lock(&self.recording).executions.push(RecordedExecution {
+            function: name.to_string(),
+            args: args.to_vec(),
+            trapped: result.trapped(),
+            value: result.value(),
+        });
+        Ok(result)
+    }
+
+    /// The recording so far.
+    pub fn recording(&self) -> Recording {
+        lock(&self.recording).clone()
+    }
+}
+
+/// The replayer of a [`Recording`].
+pub struct Replayer;
+
+/// The calls of a recording yet to be replayed.
+struct Cursor {
+    host_calls: Vec<HostCall>,
+    next: usize,
+}
+
+impl Replayer {
+    /// The host functions of the recording, returning the recorded results in the order of the
+    /// recorded calls.
+    ///
+    /// A call traps with a message starting with `replay diverged` if it is not the next recorded
+    /// call, i.e. if it is of another function or with other arguments, or if the recorded calls
+    /// have run out.
+    pub fn imports(recording: &Recording) -> ImportsBuilder {
+        let cursor = Arc::new(Mutex::new(Cursor {
+            host_calls: recording.host_calls.clone(),
+            next: 0,
+        }));
+        let mut imports = ImportsBuilder::new();
+        for (module, name, func_type) in &recording.functions {
+            let cursor = cursor.clone();
+            let (module_name, function_name, inputs) =
+                (module.clone(), name.clone(), func_type.clone());
+            let func: RawHostFn = Box::new(move |_: &mut Caller, args: &[Value]| {
+                let args = typed_args(&inputs, args);
+                let mut cursor = lock(&cursor);
+                let call = match cursor.host_calls.get(cursor.next) {
+                    Some(call)
+                        if call.module == module_name
+                            && call.name == function_name
+                            && call.args.len() == args.len()
+                            && call.args.iter().zip(&args).all(|(a, b)| a.same_bits(b)) =>
+                    {
+                        call.clone()
+                    }
+                    expected => {
+                        let called = HostCall::describe(&module_name, &function_name, &args);
+                        return Err(Trap::new(match expected {
+                            Some(call) => format!(
+                                "replay diverged: expected {}, called {}",
+                                HostCall::describe(&call.module, &call.name, &call.args),
+                                called
+                            ),
+                            None => format!("replay diverged: unexpected call {}", called),
+                        }));
+                    }
+                };
+                cursor.next += 1;
+                match call.result {
+                    Ok(value) => Ok(value.as_ref().map(Value::from)),
+                    Err(message) => Err(Trap::new(message)),
+                }
+            });
+            imports.raw_func(module, name, func_type.clone(), func);
+        }
+        imports
+    }
+
+    /// Instantiate `module` with the host functions of [`Replayer::imports`], and execute the
+    /// recorded executions in their order.
+    ///
+    /// Fails with [`Error::Trapped`] if a host call diverges, or with [`Error::Other`] if the
+    /// result of an execution differs from the recorded one, comparing the bits of values.
+    pub fn replay(
+        recording: &Recording,
+        module: &Module,
+    ) -> Result<Vec<TypedExecutionResult>, Error> {
+        let mut instance = module.instantiate_with_imports(Replayer::imports(recording))?;
+        let mut outcomes = Vec::new();
+        for execution in &recording.executions {
+            let result = instance.execute(&execution.function, &execution.args)?;
+            if let Some(trap) = instance.take_host_trap() {
+                if trap.message().starts_with("replay diverged") {
+                    return Err(Error::Trapped(TrapInfo::new(
+                        &execution.function,
+                        Some(trap),
+                    )));
+                }
+            }
+            let same = result.trapped() == execution.trapped
+                && match (result.value(), execution.value) {
+                    (Some(a), Some(b)) => a.same_bits(&b),
+                    (a, b) => a.is_none() && b.is_none(),
+                };
+            if !same {
+                return Err(Error::Other(format!(
+                    "replay diverged: different result of {}",
+                    execution.function
+                )));
+            }
+            outcomes.push(result);
+        }
+        Ok(outcomes)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn typed_args(func_type: &FunctionType, args: &[Value]) -> Vec<TypedValue> {
+    args.iter()
+        .zip(&func_type.inputs)
+        .map(|(value, value_type)| TypedValue::from_value(*value, *value_type))
+        .collect()
+}
+
+fn typed_output(func_type: &FunctionType, value: Option<Value>) -> Option<TypedValue> {
+    match (value, func_type.output) {
+        (Some(value), Some(value_type)) => Some(TypedValue::from_value(value, value_type)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, TrapKind};
+
+    fn module() -> Module {
+        /* wat2wasm
+        (module
+          (func $random (import "env" "random") (result i64))
+          (func $seed (import "env" "seed") (param i32) (result f64))
+          (global $last (mut i64) (i64.const 0))
+          (func $start (drop (call $seed (i32.const 7))))
+          (func (export "roll") (param i32) (result i64)
+            (global.set $last (i64.rem_u (call $random) (i64.extend_i32_u (local.get 0))))
+            (global.get $last))
+          (func (export "nan") (result f64) (call $seed (i32.const -1)))
+          (start $start)
+        )
+        */
+        let input = hex::decode("0061736d010000000116056000017e60017f017c60000060017f017e6000017c02190203656e760672616e646f6d000003656e76047365656400010304030203040606017e0142000b070e0204726f6c6c0003036e616e00040801020a1d030700410710011a0b0c0010002000ad82240023000b0600417f10010b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    /// The imports of a "random" generator, of which the first output is `seed`.
+    fn imports(seed: u64) -> ImportsBuilder {
+        let mut state = seed;
+        let mut imports = ImportsBuilder::new();
+        imports
+            .func(
+                "env",
+                "random",
+                move |_: &mut Caller| -> Result<u64, Trap> {
+                    let value = state;
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    Ok(value)
+                },
+            )
+            .func(
+                "env",
+                "seed",
+                |_: &mut Caller, seed: u32| -> Result<f64, Trap> {
+                    match seed {
+                        0 => Err(Trap::new("zero seed")),
+                        // A NaN with a payload, which must be replayed bit by bit.
+                        u32::MAX => Ok(f64::from_bits(0x7ff8_0000_dead_beef)),
+                        _ => Ok(f64::from(seed)),
+                    }
+                },
+            );
+        imports
+    }
+
+    fn record(seed: u64) -> (Recording, Vec<Option<TypedValue>>) {
+        let recorder = Recorder::new();
+        let mut instance = module()
+            .instantiate_with_imports(recorder.wrap(imports(seed)))
+            .unwrap();
+        let mut values = Vec::new();
+        for modulus in &[100, 1000, 6] {
+            let result = recorder
+                .execute(&mut instance, "roll", &[TypedValue::U32(*modulus)])
+                .unwrap();
+            values.push(result.value());
+        }
+        let result = recorder.execute(&mut instance, "nan", &[]).unwrap();
+        values.push(result.value());
+        (recorder.recording(), values)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let (recording, values) = record(1234);
+        let calls: Vec<(&str, &[TypedValue])> = recording
+            .host_calls()
+            .iter()
+            .map(|call| (call.name(), call.args()))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("seed", &[TypedValue::U32(7)][..]),
+                ("random", &[][..]),
+                ("random", &[][..]),
+                ("random", &[][..]),
+                ("seed", &[TypedValue::U32(u32::MAX)][..]),
+            ]
+        );
+        assert_eq!(recording.host_calls()[0].module(), "env");
+        assert_eq!(
+            recording.host_calls()[1].result(),
+            Ok(Some(TypedValue::U64(1234)))
+        );
+        assert_eq!(recording.executions().len(), 4);
+        assert_eq!(recording.executions()[0].function(), "roll");
+        assert_eq!(recording.executions()[0].args(), [TypedValue::U32(100)]);
+        assert_eq!(recording.executions()[0].value(), Some(TypedValue::U64(34)));
+
+        // A different seed gives different results, but the replay does not depend on it.
+        let (_, other_values) = record(5678);
+        assert_ne!(other_values, values);
+        for _ in 0..2 {
+            let outcomes = Replayer::replay(&recording, &module()).unwrap();
+            assert_eq!(outcomes.len(), 4);
+            for (outcome, value) in outcomes.iter().zip(&values) {
+                assert!(!outcome.trapped());
+                match (outcome.value(), value) {
+                    (Some(TypedValue::F64(a)), Some(TypedValue::F64(b))) => {
+                        assert_eq!(a.to_bits(), b.to_bits())
+                    }
+                    (a, b) => assert_eq!(a, *b),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn replay_traps() {
+        let recorder = Recorder::new();
+        let mut instance = module()
+            .instantiate_with_imports(recorder.wrap(imports(1)))
+            .unwrap();
+        // Division by zero traps in the module, without a host trap.
+        let result = recorder
+            .execute(&mut instance, "roll", &[TypedValue::U32(0)])
+            .unwrap();
+        assert!(result.trapped());
+        let recording = recorder.recording();
+        assert!(recording.executions()[0].trapped());
+
+        let outcomes = Replayer::replay(&recording, &module()).unwrap();
+        assert!(outcomes[0].trapped());
+
+        let mut imports = Replayer::imports(&recording);
+        imports.on_warning(|_| {});
+        let mut instance = module().instantiate_with_imports(imports).unwrap();
+        instance.execute("roll", &[TypedValue::U32(1)]).unwrap();
+        // The recorded calls have run out.
+        let result = instance.execute("roll", &[TypedValue::U32(1)]).unwrap();
+        assert!(result.trapped());
+        assert_eq!(
+            instance.take_host_trap(),
+            Some(Trap::new("replay diverged: unexpected call env.random()"))
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        let (mut recording, _) = record(1);
+        recording.executions[3].function = "roll".to_string();
+        recording.executions[3].args = vec![TypedValue::U32(2)];
+        match Replayer::replay(&recording, &module()) {
+            Err(Error::Trapped(info)) => {
+                assert_eq!(info.function(), Some("roll"));
+                assert_eq!(
+                    info.kind(),
+                    &TrapKind::Host(
+                        "replay diverged: expected env.seed(i32:4294967295), called env.random()"
+                            .to_string()
+                    )
+                );
+            }
+            _ => panic!("expected a divergence"),
+        }
+
+        let (mut recording, _) = record(1);
+        recording.executions[1].value = Some(TypedValue::U64(1));
+        assert_eq!(
+            Replayer::replay(&recording, &module()).err(),
+            Some(Error::Other(
+                "replay diverged: different result of roll".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,338 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running suites of test cases against a module, e.g. the test vectors of contracts in CI.
+//!
+//! With the `serde` feature, the [`SuiteReport`] serializes to a machine-readable report, and the
+//! [`TestCase`]s can be loaded from the same format.
+//!
+//! ```
+//! use fizzy::report::{run_suite, Expectation, TestCase};
+//! use fizzy::TypedValue;
+//!
+//! // This wasm binary exports a single sum(u32, u32) -> u32 function.
+//! let wasm = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+//!     0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x73, 0x75, 0x6d,
+//!     0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+//! ];
+//! let module = fizzy::parse(&wasm).expect("parsing failed");
+//! let cases = [TestCase::new(
+//!     "sum",
+//!     &[TypedValue::U32(42), TypedValue::U32(24)],
+//!     Expectation::Value(Some(TypedValue::U32(66))),
+//! )];
+//! let report = run_suite(&module, &cases);
+//! assert_eq!((report.passed, report.failed), (1, 0));
+//! ```
+
+use crate::{Error, ExecutionOptions, Module, TrapInfo, TypedValue};
+use std::time::Instant;
+
+/// The expected outcome of a [`TestCase`].
+///
+/// With the `serde` feature, it is serialized with its kind, e.g.
+/// `{"expect":"value","expected":{"type":"i32","value":66}}`, `{"expect":"trap"}` or
+/// `{"expect":"any"}`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        crate = "serde",
+        tag = "expect",
+        content = "expected",
+        rename_all = "lowercase"
+    )
+)]
+pub enum Expectation {
+    /// The function returns the optional value, compared by its bits, so that NaNs are compared
+    /// by their payloads.
+    Value(Option<TypedValue>),
+    /// The execution traps.
+    Trap,
+    /// The function returns any value or traps, but does not fail otherwise.
+    Any,
+}
+
+/// A call of an exported function with its expected outcome.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct TestCase {
+    pub function: String,
+    pub args: Vec<TypedValue>,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub expect: Expectation,
+}
+
+impl TestCase {
+    /// Create a call of the exported function `function` with `args`, expecting `expect`.
+    pub fn new(function: &str, args: &[TypedValue], expect: Expectation) -> Self {
+        TestCase {
+            function: function.to_string(),
+            args: args.to_vec(),
+            expect,
+        }
+    }
+}
+
+/// The actual outcome of a [`TestCase`].
+///
+/// With the `serde` feature, it is serialized with its kind, e.g.
+/// `{"outcome":"returned","value":null}`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(crate = "serde", tag = "outcome", rename_all = "snake_case")
+)]
+pub enum CaseOutcome {
+    /// The function has returned the optional value.
+    Returned { value: Option<TypedValue> },
+    /// The execution has trapped.
+    Trapped { trap: TrapInfo },
+    /// The case has failed with an error other than a trap, e.g. the function is not found.
+    Failed { error: Error },
+}
+
+impl CaseOutcome {
+    /// True if the outcome is the expected one.
+    fn matches(&self, expect: &Expectation) -> bool {
+        match (self, expect) {
+            (CaseOutcome::Returned { value: Some(a) }, Expectation::Value(Some(b))) => {
+                a.same_bits(b)
+            }
+            (CaseOutcome::Returned { value: None }, Expectation::Value(None))
+            | (CaseOutcome::Trapped { .. }, Expectation::Trap)
+            | (CaseOutcome::Returned { .. }, Expectation::Any)
+            | (CaseOutcome::Trapped { .. }, Expectation::Any) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The report of a [`TestCase`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(crate = "serde"))]
+pub struct CaseReport {
+    pub function: String,
+    pub args: Vec<TypedValue>,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub expect: Expectation,
+    /// True if the outcome is the expected one.
+    pub passed: bool,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub outcome: CaseOutcome,
+    /// The ticks used, if metered with [`ExecutionOptions::cost_schedule`].
+    pub ticks_used: Option<u64>,
+    /// The time of instantiating the module and executing the function, in nanoseconds.
+    pub elapsed_ns: u64,
+}
+
+/// The report of a suite of [`TestCase`]s, in their order.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(crate = "serde"))]
+pub struct SuiteReport {
+    pub passed: usize,
+    pub failed: usize,
+    /// The time of running all cases, in nanoseconds.
+    pub elapsed_ns: u64,
+    pub cases: Vec<CaseReport>,
+}
+
+impl SuiteReport {
+    /// True if all cases have passed.
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Run `cases` against `module`, each in a new instance without imports.
+pub fn run_suite(module: &Module, cases: &[TestCase]) -> SuiteReport {
+    run_suite_with_options(module, cases, &ExecutionOptions::new())
+}
+
+/// Run `cases` against `module` with `options`, each in a new instance without imports, see
+/// [`run_suite`].
+pub fn run_suite_with_options(
+    module: &Module,
+    cases: &[TestCase],
+    options: &ExecutionOptions,
+) -> SuiteReport {
+    let start = Instant::now();
+    let cases: Vec<CaseReport> = cases
+        .iter()
+        .map(|case| run_case(module, case, options))
+        .collect();
+    let passed = cases.iter().filter(|case| case.passed).count();
+    SuiteReport {
+        passed,
+        failed: cases.len() - passed,
+        elapsed_ns: start.elapsed().as_nanos() as u64,
+        cases,
+    }
+}
+
+fn run_case(module: &Module, case: &TestCase, options: &ExecutionOptions) -> CaseReport {
+    let start = Instant::now();
+    let mut ticks_used = None;
+    let outcome = match module.instantiate() {
+        Ok(mut instance) => {
+            match instance.execute_with_options(&case.function, &case.args, options) {
+                Ok(outcome) => {
+                    ticks_used = outcome.ticks_used();
+                    if outcome.trapped() {
+                        CaseOutcome::Trapped {
+                            trap: TrapInfo::new(&case.function, instance.take_host_trap()),
+                        }
+                    } else {
+                        CaseOutcome::Returned {
+                            value: outcome.value(),
+                        }
+                    }
+                }
+                Err(error) => CaseOutcome::Failed { error },
+            }
+        }
+        Err(error) => CaseOutcome::Failed { error },
+    };
+    CaseReport {
+        function: case.function.clone(),
+        args: case.args.clone(),
+        expect: case.expect.clone(),
+        passed: outcome.matches(&case.expect),
+        outcome,
+        ticks_used,
+        elapsed_ns: start.elapsed().as_nanos() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, parse_metered, CostSchedule, TrapKind};
+
+    /* wat2wasm
+    (module
+      (func (export "sum") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+      (func (export "trap") (unreachable))
+      (func (export "nan") (result f32) (f32.const nan:0x200000))
+    )
+    */
+    const CASES_WASM: &str = "0061736d01000000010e0360027f7f017f6000006000017d0304030001020714030373756d000004747261700001036e616e00020a15030700200020016a0b0300000b0700430000a07f0b";
+
+    fn module() -> Module {
+        parse(&hex::decode(CASES_WASM).unwrap()).unwrap()
+    }
+
+    fn cases() -> Vec<TestCase> {
+        vec![
+            TestCase::new(
+                "sum",
+                &[TypedValue::U32(2), TypedValue::U32(3)],
+                Expectation::Value(Some(TypedValue::U32(5))),
+            ),
+            // Compared by the bits, although NaNs are not equal.
+            TestCase::new(
+                "nan",
+                &[],
+                Expectation::Value(Some(TypedValue::F32(f32::from_bits(0x7fa0_0000)))),
+            ),
+            // The deliberate failure.
+            TestCase::new("trap", &[], Expectation::Value(None)),
+        ]
+    }
+
+    #[test]
+    fn suite() {
+        let report = run_suite(&module(), &cases());
+        assert_eq!((report.passed, report.failed), (2, 1));
+        assert!(!report.all_passed());
+        let passed: Vec<bool> = report.cases.iter().map(|case| case.passed).collect();
+        assert_eq!(passed, [true, true, false]);
+        match &report.cases[2].outcome {
+            CaseOutcome::Trapped { trap } => {
+                assert_eq!(trap.function(), Some("trap"));
+                assert_eq!(trap.kind(), &TrapKind::Wasm);
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+        assert!(report.cases.iter().all(|case| case.ticks_used.is_none()));
+
+        let cases = [
+            TestCase::new("trap", &[], Expectation::Trap),
+            TestCase::new("trap", &[], Expectation::Any),
+            TestCase::new("nan", &[], Expectation::Any),
+            TestCase::new("nan", &[], Expectation::Trap),
+            TestCase::new("sum", &[], Expectation::Any),
+            TestCase::new("missing", &[], Expectation::Any),
+        ];
+        let report = run_suite(&module(), &cases);
+        let passed: Vec<bool> = report.cases.iter().map(|case| case.passed).collect();
+        assert_eq!(passed, [true, true, true, false, false, false]);
+        assert_eq!(
+            report.cases[5].outcome,
+            CaseOutcome::Failed {
+                error: Error::FunctionNotFound
+            }
+        );
+    }
+
+    #[test]
+    fn metered() {
+        let options = ExecutionOptions::new().cost_schedule(&CostSchedule::uniform(1));
+        let module = parse_metered(&hex::decode(CASES_WASM).unwrap()).unwrap();
+        let report = run_suite_with_options(&module, &cases(), &options);
+        assert_eq!((report.passed, report.failed), (2, 1));
+        assert!(report.cases.iter().all(|case| case.ticks_used.is_some()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json() {
+        let report = run_suite(&module(), &cases());
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["passed"], 2);
+        assert_eq!(json["failed"], 1);
+        assert!(json["elapsed_ns"].is_u64());
+        let json_cases = json["cases"].as_array().unwrap();
+        assert_eq!(json_cases.len(), 3);
+        for case in json_cases {
+            assert!(case["elapsed_ns"].is_u64());
+            assert!(case["ticks_used"].is_null());
+        }
+        let mut sum = json_cases[0].clone();
+        sum.as_object_mut().unwrap().remove("elapsed_ns");
+        assert_eq!(
+            sum,
+            serde_json::json!({
+                "function": "sum",
+                "args": [{"type": "i32", "value": 2}, {"type": "i32", "value": 3}],
+                "expect": "value",
+                "expected": {"type": "i32", "value": 5},
+                "passed": true,
+                "outcome": "returned",
+                "value": {"type": "i32", "value": 5},
+                "ticks_used": null,
+            })
+        );
+        assert_eq!(json_cases[1]["passed"], true);
+        assert_eq!(json_cases[2]["passed"], false);
+        assert_eq!(json_cases[2]["outcome"], "trapped");
+        assert_eq!(json_cases[2]["trap"]["function"], "trap");
+        assert_eq!(json_cases[2]["trap"]["kind"], "wasm");
+
+        // The cases are loaded from the same format.
+        let loaded: Vec<TestCase> = serde_json::from_str(
+            r#"[{"function":"sum","args":[{"type":"i32","value":2},{"type":"i32","value":3}],"expect":"value","expected":{"type":"i32","value":5}},{"function":"trap","args":[],"expect":"trap"}]"#,
+        )
+        .unwrap();
+        assert_eq!(loaded[0], cases()[0]);
+        assert_eq!(loaded[1], TestCase::new("trap", &[], Expectation::Trap));
+    }
+}
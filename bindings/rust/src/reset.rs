@@ -0,0 +1,269 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resetting an instance to its state right after instantiation.
+
+use crate::instrument::{Reader, DATA_SECTION, GLOBAL_SECTION, START_SECTION};
+use crate::{sys, Error, ExternalKind, Instance, TrapInfo, Value};
+
+/// The opcodes of the constant expressions.
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const F32_CONST: u8 = 0x43;
+const F64_CONST: u8 = 0x44;
+const GLOBAL_GET: u8 = 0x23;
+const END: u8 = 0x0b;
+
+impl Instance {
+    /// Reset the instance to its state right after instantiation, without instantiating the
+    /// module again: the memory is zeroed and the data segments are copied to it, the mutable
+    /// globals defined by the module are set to their initial values, and the start function, if
+    /// any, is executed.
+    ///
+    /// The memory keeps its size if it has grown, as the engine does not shrink memories, and the
+    /// imported globals are not changed. The initial values referring to imported globals are
+    /// evaluated with their current values.
+    ///
+    /// Fails for an instance with an imported memory, which is owned by another instance, and if
+    /// the start function traps, leaving the instance in an unspecified state.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        let mut imported_globals = 0;
+        for import in self.module.imports() {
+            match import.ty.kind() {
+                ExternalKind::Memory => {
+                    return Err(Error::Other(
+                        "cannot reset imported memory owned by another instance".to_string(),
+                    ))
+                }
+                ExternalKind::Global => imported_globals += 1,
+                _ => {}
+            }
+        }
+
+        let _memory = self.lock_memory();
+        self.zero_memory();
+        let module = self.module.clone();
+        let mut start = None;
+        let mut reader = Reader::new(&module.bytes()[8..]);
+        while !reader.is_empty() {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let mut section = Reader::new(reader.bytes(size)?);
+            match id {
+                GLOBAL_SECTION => self.reset_globals(&mut section, imported_globals)?,
+                START_SECTION => start = Some(section.u32()?),
+                DATA_SECTION => self.copy_data_segments(&mut section)?,
+                _ => {}
+            }
+        }
+        if let Some(func_idx) = start {
+            let result = unsafe { self.execute_unchecked(func_idx, &[]) };
+            if result.trapped() {
+                return Err(Error::Trapped(TrapInfo::new(
+                    "start",
+                    self.take_host_trap(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the mutable globals of the global section in `section` to their initial values.
+    fn reset_globals(&mut self, section: &mut Reader, imported_globals: u32) -> Result<(), Error> {
+        for global_idx in imported_globals..imported_globals + section.u32()? {
+            let _value_type = section.u8()?;
+            let mutable = section.u8()? == 0x01;
+            let value = self.constant(section)?;
+            if mutable {
+                unsafe {
+                    *sys::fizzy_get_instance_global(self.instance.as_ptr(), global_idx) = value
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the data segments of the data section in `section` to the memory.
+    fn copy_data_segments(&mut self, section: &mut Reader) -> Result<(), Error> {
+        for _ in 0..section.u32()? {
+            let _memory_idx = section.u32()?;
+            let offset = self.constant(section)?.as_u32();
+            let data = section.name()?;
+            // The segments have been copied in bounds during instantiation, and the memory
+            // cannot have shrunk since.
+            unsafe { self.checked_memory_slice_mut(offset, data.len()) }?.copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    fn zero_memory(&mut self) {
+        let size = self.memory_size();
+        if let Ok(memory) = unsafe { self.checked_memory_slice_mut(0, size) } {
+            for byte in memory.iter_mut() {
+                *byte = 0;
+            }
+        }
+    }
+
+    /// Evaluate the constant expression in `reader`.
+    fn constant(&self, reader: &mut Reader) -> Result<Value, Error> {
+        let value = match reader.u8()? {
+            I32_CONST => Value::from(reader.signed()? as i32),
+            I64_CONST => Value::from(reader.signed()?),
+            F32_CONST => {
+                let mut bits = [0; 4];
+                bits.copy_from_slice(reader.bytes(4)?);
+                Value::from(f32::from_bits(u32::from_le_bytes(bits)))
+            }
+            F64_CONST => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(reader.bytes(8)?);
+                Value::from(f64::from_bits(u64::from_le_bytes(bits)))
+            }
+            GLOBAL_GET => {
+                let global_idx = reader.u32()?;
+                unsafe { *sys::fizzy_get_instance_global(self.instance.as_ptr(), global_idx) }
+            }
+            _ => return Err(malformed()),
+        };
+        if reader.u8()? != END {
+            return Err(malformed());
+        }
+        Ok(value)
+    }
+}
+
+fn malformed() -> Error {
+    Error::MalformedModule("invalid constant expression".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ffi, parse, InstantiateOptions, Module, TypedValue};
+
+    use std::cell::Cell;
+
+    fn module() -> Module {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (global $count (mut i32) (i32.const 7))
+          (global $scale (mut f64) (f64.const 1.5))
+          (global $big (mut i64) (i64.const -2))
+          (data (i32.const 16) "hello")
+          (func (export "store") (param $address i32) (param $value i32)
+            (i32.store8 (local.get $address) (local.get $value)))
+          (func (export "grow") (drop (memory.grow (i32.const 1))))
+          (func (export "bump")
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (global.set $scale (f64.mul (global.get $scale) (f64.const 2)))
+            (global.set $big (i64.const 0)))
+          (func (export "sum") (result i64)
+            (i64.add
+              (i64.add (i64.extend_i32_u (global.get $count)) (global.get $big))
+              (i64.add (i64.trunc_f64_s (global.get $scale)) (i64.load8_u (i32.const 17)))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010d0360027f7f006000006000017e0305040001010205030100010617037f0141070b7c0144000000000000f83f0b7e01427e0b071d040573746f726500000467726f7700010462756d7000020373756d00030a42040900200020013a00000b0700410140001a0b1b00230041016a24002301440000000000000040a22401420024020b12002300ad23027c2301b041113100007c7c0b0b0b010041100b0568656c6c6f").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn store(instance: &mut Instance, address: u32, value: u32) {
+        let result = instance
+            .execute("store", &[TypedValue::U32(address), TypedValue::U32(value)])
+            .unwrap();
+        assert!(!result.trapped());
+    }
+
+    #[test]
+    fn reset() {
+        let module = module();
+        let fresh = module.instantiate().unwrap();
+        let mut instance = module.instantiate().unwrap();
+        let sum = instance.execute("sum", &[]).unwrap().value();
+        assert_eq!(sum, Some(TypedValue::U64(7 - 2 + 1 + u64::from(b'e'))));
+
+        store(&mut instance, 17, 0);
+        store(&mut instance, 100, 1);
+        instance.execute("bump", &[]).unwrap();
+        assert!(!fresh.snapshot().diff(&instance.snapshot()).is_empty());
+
+        instance.reset().unwrap();
+        assert!(fresh.snapshot().diff(&instance.snapshot()).is_empty());
+        assert_eq!(instance.execute("sum", &[]).unwrap().value(), sum);
+
+        // The grown memory keeps its size, zeroed.
+        instance.execute("grow", &[]).unwrap();
+        store(&mut instance, 65536, 1);
+        instance.reset().unwrap();
+        assert_eq!(instance.memory_size(), 2 * 65536);
+        let mut memory = vec![0; 2 * 65536];
+        instance.memory_get(0, &mut memory).unwrap();
+        assert_eq!(&memory[16..21], b"hello");
+        assert!(memory[21..].iter().all(|byte| *byte == 0));
+        assert_eq!(instance.execute("sum", &[]).unwrap().value(), sum);
+    }
+
+    #[test]
+    fn start_and_imported_global() {
+        /* wat2wasm
+        (module
+          (global $base (import "env" "base") i32)
+          (memory 1)
+          (global $count (mut i32) (global.get $base))
+          (data (global.get $base) "\01")
+          (func $start
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (i32.store8 (i32.const 0) (global.get $count)))
+          (start $start)
+          (func (export "count") (result i32) (global.get $count))
+          (func (export "clear") (i32.store8 (i32.const 0) (i32.const 0)))
+        )
+        */
+        let input = hex::decode("0061736d010000000108026000006000017f020d0103656e760462617365037f0003040300010005030100010606017f0123000b07110205636f756e74000105636c65617200020801000a21031000230141016a2401410023013a00000b040023010b0900410041003a00000b0b07010023000b0101").unwrap();
+        let base = Cell::new(sys::FizzyValue { i32: 10 });
+        let global = ffi::ExternalGlobal::new(&base, sys::FizzyValueTypeI32, false);
+        let options = unsafe { InstantiateOptions::new().imported_global("env", "base", global) };
+        let mut instance = parse(&input).unwrap().instantiate_with(&options).unwrap();
+        assert_eq!(
+            instance.execute("count", &[]).unwrap().value(),
+            Some(TypedValue::U32(11))
+        );
+
+        instance.execute("clear", &[]).unwrap();
+        instance.reset().unwrap();
+        // The start function is executed again.
+        assert_eq!(
+            instance.execute("count", &[]).unwrap().value(),
+            Some(TypedValue::U32(11))
+        );
+        let mut memory = [0; 11];
+        instance.memory_get(0, &mut memory).unwrap();
+        assert_eq!(memory, [11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn imported_memory() {
+        /* wat2wasm
+        (module (memory (export "mem") 1))
+        */
+        let input = hex::decode("0061736d010000000503010001070701036d656d0200").unwrap();
+        let exporter = parse(&input).unwrap().instantiate().unwrap();
+        /* wat2wasm
+        (module (memory (import "env" "mem") 1))
+        */
+        let input = hex::decode("0061736d01000000020c0103656e76036d656d020001").unwrap();
+        let options = unsafe {
+            InstantiateOptions::new().imported_memory(exporter.exported_memory("mem").unwrap())
+        };
+        let mut instance = parse(&input).unwrap().instantiate_with(&options).unwrap();
+        assert_eq!(
+            instance.reset(),
+            Err(Error::Other(
+                "cannot reset imported memory owned by another instance".to_string()
+            ))
+        );
+    }
+}
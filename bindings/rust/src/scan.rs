@@ -0,0 +1,379 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The limits of untrusted modules checked before parsing, see [`parse_with`], and the reference
+//! types found after parsing fails.
+
+use crate::instrument::{
+    Reader, CODE_SECTION, FUNCTION_SECTION, IMPORT_SECTION, MEMORY_SECTION, TABLE_SECTION,
+    TYPE_SECTION,
+};
+use crate::{parse, Error, Module, ValueType};
+
+/// The limits of the modules accepted by [`parse_with`], bounding the cost of parsing and
+/// validating crafted modules, e.g. uploaded by untrusted users.
+///
+/// The defaults are the limits of the WebAssembly JavaScript API implemented by web browsers,
+/// which are permissive for any real module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// The size of the binary in bytes.
+    pub max_module_size: usize,
+    /// The number of functions, including the imported functions.
+    pub max_functions: u32,
+    /// The size of the body of each function in bytes.
+    pub max_function_body_size: usize,
+    /// The number of locals of each function, not including its parameters.
+    pub max_locals: u32,
+    /// The minimum size of the table, defined or imported.
+    pub max_table_min: u32,
+    /// The minimum size of the memory in pages, defined or imported.
+    pub max_memory_min_pages: u32,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_module_size: 1024 * 1024 * 1024,
+            max_functions: 1_000_000,
+            max_function_body_size: 7_654_321,
+            max_locals: 50_000,
+            max_table_min: 10_000_000,
+            max_memory_min_pages: 65536,
+        }
+    }
+}
+
+/// Parse and validate the input like [`parse`], failing with [`Error::LimitExceeded`] if it
+/// exceeds any of the `options`.
+///
+/// The size of the input is checked first, and the other limits by a scan of the sections before
+/// the input is parsed. A module which is malformed is reported by the parser instead, unless it
+/// exceeds a limit before the malformed part.
+pub fn parse_with<T: AsRef<[u8]>>(input: &T, options: &ParseOptions) -> Result<Module, Error> {
+    let input = input.as_ref();
+    check(
+        "max_module_size",
+        options.max_module_size as u64,
+        input.len() as u64,
+    )?;
+    // The malformed sections are left to the parser.
+    if let Err(err @ Error::LimitExceeded { .. }) = scan(input, options) {
+        return Err(err);
+    }
+    parse(&input)
+}
+
+fn check(which: &str, limit: u64, actual: u64) -> Result<(), Error> {
+    if actual > limit {
+        return Err(Error::LimitExceeded {
+            which: which.to_string(),
+            limit,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Read the minimum of the limits in `reader`, skipping the maximum.
+fn limits_min(reader: &mut Reader) -> Result<u32, Error> {
+    let has_max = reader.u8()? == 0x01;
+    let min = reader.u32()?;
+    if has_max {
+        reader.u32()?;
+    }
+    Ok(min)
+}
+
+fn scan(input: &[u8], options: &ParseOptions) -> Result<(), Error> {
+    let mut reader = Reader::new(input.get(8..).unwrap_or_default());
+    let mut functions = 0u64;
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader::new(reader.bytes(size)?);
+        match id {
+            IMPORT_SECTION => {
+                for _ in 0..section.u32()? {
+                    section.name()?;
+                    section.name()?;
+                    match section.u8()? {
+                        0x00 => {
+                            section.u32()?;
+                            functions += 1;
+                        }
+                        0x01 => {
+                            section.u8()?;
+                            let min = limits_min(&mut section)?;
+                            check("max_table_min", options.max_table_min.into(), min.into())?;
+                        }
+                        0x02 => {
+                            let min = limits_min(&mut section)?;
+                            check(
+                                "max_memory_min_pages",
+                                options.max_memory_min_pages.into(),
+                                min.into(),
+                            )?;
+                        }
+                        _ => {
+                            section.bytes(2)?;
+                        }
+                    }
+                }
+                check("max_functions", options.max_functions.into(), functions)?;
+            }
+            FUNCTION_SECTION => {
+                functions += u64::from(section.u32()?);
+                check("max_functions", options.max_functions.into(), functions)?;
+            }
+            TABLE_SECTION => {
+                for _ in 0..section.u32()? {
+                    section.u8()?;
+                    let min = limits_min(&mut section)?;
+                    check("max_table_min", options.max_table_min.into(), min.into())?;
+                }
+            }
+            MEMORY_SECTION => {
+                for _ in 0..section.u32()? {
+                    let min = limits_min(&mut section)?;
+                    check(
+                        "max_memory_min_pages",
+                        options.max_memory_min_pages.into(),
+                        min.into(),
+                    )?;
+                }
+            }
+            CODE_SECTION => {
+                for _ in 0..section.u32()? {
+                    let size = section.u32()? as usize;
+                    check(
+                        "max_function_body_size",
+                        options.max_function_body_size as u64,
+                        size as u64,
+                    )?;
+                    let mut body = Reader::new(section.bytes(size)?);
+                    let mut locals = 0u64;
+                    for _ in 0..body.u32()? {
+                        locals += u64::from(body.u32()?);
+                        body.u8()?;
+                        check("max_locals", options.max_locals.into(), locals)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The first reference type in the function types or the locals of `input`, which the engine
+/// rejects as malformed. `None` if there is none or the sections are malformed.
+pub(crate) fn reference_type(input: &[u8]) -> Option<ValueType> {
+    let mut reader = Reader::new(input.get(8..).unwrap_or_default());
+    while !reader.is_empty() {
+        let id = reader.u8().ok()?;
+        let size = reader.u32().ok()? as usize;
+        let mut section = Reader::new(reader.bytes(size).ok()?);
+        let found = match id {
+            TYPE_SECTION => function_types_reference(&mut section),
+            CODE_SECTION => locals_reference(&mut section),
+            _ => Ok(None),
+        };
+        if let Some(value_type) = found.ok()? {
+            return Some(value_type);
+        }
+    }
+    None
+}
+
+fn reference(raw: u8) -> Option<ValueType> {
+    match raw {
+        0x70 => Some(ValueType::FuncRef),
+        0x6f => Some(ValueType::ExternRef),
+        _ => None,
+    }
+}
+
+fn function_types_reference(section: &mut Reader) -> Result<Option<ValueType>, Error> {
+    for _ in 0..section.u32()? {
+        section.u8()?;
+        for _ in 0..2 {
+            let count = section.u32()?;
+            if let Some(value_type) = section
+                .bytes(count as usize)?
+                .iter()
+                .find_map(|raw| reference(*raw))
+            {
+                return Ok(Some(value_type));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn locals_reference(section: &mut Reader) -> Result<Option<ValueType>, Error> {
+    for _ in 0..section.u32()? {
+        let size = section.u32()? as usize;
+        let mut body = Reader::new(section.bytes(size)?);
+        for _ in 0..body.u32()? {
+            body.u32()?;
+            if let Some(value_type) = reference(body.u8()?) {
+                return Ok(Some(value_type));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit_exceeded(which: &str, limit: u64, actual: u64) -> Option<Error> {
+        Some(Error::LimitExceeded {
+            which: which.to_string(),
+            limit,
+            actual,
+        })
+    }
+
+    /* wat2wasm
+    (module
+      (func (import "env" "f"))
+      (table 3 funcref)
+      (memory 2)
+      (func (local i32 i64) (local f32))
+      (func (local i32) (nop) (nop) (nop))
+    )
+    */
+    const MODULE: &str = "0061736d0100000001040160000002090103656e7601660000030302000004040170000305030100020a12020803017f017e017d0b0701017f0101010b";
+
+    #[test]
+    fn within_limits() {
+        let input = hex::decode(MODULE).unwrap();
+        assert!(parse_with(&input, &ParseOptions::default()).is_ok());
+        let options = ParseOptions {
+            max_module_size: input.len(),
+            max_functions: 3,
+            max_function_body_size: 8,
+            max_locals: 3,
+            max_table_min: 3,
+            max_memory_min_pages: 2,
+        };
+        assert!(parse_with(&input, &options).is_ok());
+    }
+
+    #[test]
+    fn each_limit() {
+        let input = hex::decode(MODULE).unwrap();
+        let options = ParseOptions::default();
+        let err = |options: ParseOptions| parse_with(&input, &options).err();
+
+        assert_eq!(
+            err(ParseOptions {
+                max_module_size: 10,
+                ..options
+            }),
+            limit_exceeded("max_module_size", 10, input.len() as u64)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_functions: 2,
+                ..options
+            }),
+            limit_exceeded("max_functions", 2, 3)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_function_body_size: 7,
+                ..options
+            }),
+            limit_exceeded("max_function_body_size", 7, 8)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_locals: 2,
+                ..options
+            }),
+            limit_exceeded("max_locals", 2, 3)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_table_min: 2,
+                ..options
+            }),
+            limit_exceeded("max_table_min", 2, 3)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_memory_min_pages: 1,
+                ..options
+            }),
+            limit_exceeded("max_memory_min_pages", 1, 2)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_memory_min_pages: 1,
+                ..options
+            })
+            .unwrap()
+            .to_string(),
+            "limit max_memory_min_pages of 1 exceeded: 2"
+        );
+    }
+
+    #[test]
+    fn imports() {
+        /* wat2wasm
+        (module
+          (func (import "env" "f"))
+          (func (import "env" "g"))
+          (table (import "env" "t") 5 funcref)
+          (memory (import "env" "m") 3)
+        )
+        */
+        let input = hex::decode("0061736d0100000001040160000002240403656e760166000003656e760167000003656e7601740170000503656e76016d020003").unwrap();
+        let options = ParseOptions::default();
+        let err = |options: ParseOptions| parse_with(&input, &options).err();
+        assert_eq!(
+            err(ParseOptions {
+                max_functions: 1,
+                ..options
+            }),
+            limit_exceeded("max_functions", 1, 2)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_table_min: 4,
+                ..options
+            }),
+            limit_exceeded("max_table_min", 4, 5)
+        );
+        assert_eq!(
+            err(ParseOptions {
+                max_memory_min_pages: 2,
+                ..options
+            }),
+            limit_exceeded("max_memory_min_pages", 2, 3)
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        // The malformed module is reported by the parser.
+        assert_eq!(
+            parse_with(&[0x00, 0x61, 0x73, 0x6d, 0x01], &ParseOptions::default()).err(),
+            Some(Error::MalformedModule(
+                "invalid wasm module prefix".to_string()
+            ))
+        );
+        let input = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x03, 0x05, 0x01,
+        ];
+        assert!(matches!(
+            parse_with(&input, &ParseOptions::default()),
+            Err(Error::MalformedModule(_))
+        ));
+    }
+}
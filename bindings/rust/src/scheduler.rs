@@ -0,0 +1,355 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Time-sliced cooperative scheduling of metered executions over many instances.
+
+use crate::{CostSchedule, Error, ExecutionOptions, Instance, TrapInfo, TypedValue};
+
+use std::collections::VecDeque;
+
+/// The identifier of a task added to a [`Scheduler`], unique within it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(usize);
+
+struct Task {
+    id: TaskId,
+    instance: Instance,
+    func: String,
+    args: Vec<TypedValue>,
+    options: ExecutionOptions,
+    slices: u32,
+    ticks_used: u64,
+}
+
+/// A task which has finished, by returning or trapping.
+pub struct FinishedTask {
+    id: TaskId,
+    instance: Instance,
+    result: Result<Option<TypedValue>, Error>,
+    slices: u32,
+    ticks_used: u64,
+}
+
+impl FinishedTask {
+    /// The identifier returned by [`Scheduler::add_task`].
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// The return value of the function, or the error of its last slice, e.g. [`Error::Trapped`].
+    pub fn result(&self) -> &Result<Option<TypedValue>, Error> {
+        &self.result
+    }
+
+    /// The number of slices the task has been executed for, including the last one.
+    pub fn slices(&self) -> u32 {
+        self.slices
+    }
+
+    /// The ticks used by all slices of the task.
+    pub fn ticks_used(&self) -> u64 {
+        self.ticks_used
+    }
+
+    /// Take back the instance of the task.
+    pub fn into_instance(self) -> Instance {
+        self.instance
+    }
+}
+
+/// Runs many metered tasks in turns, each for a slice of ticks, in strict round-robin order.
+///
+/// The engine cannot suspend an execution, therefore a task which runs out of the ticks of its
+/// slice is executed again from the start in its next turn. The state of its instance is kept, so
+/// the tasks are cooperative: the functions keep their progress in globals or in the memory, and
+/// continue from it when called again. A task finishes when its function returns or traps other
+/// than by running out of ticks.
+///
+/// Given deterministic functions, the order of the slices and of the finished tasks is
+/// reproducible, as it only depends on the order in which the tasks are added and on the ticks
+/// used.
+///
+/// The instances must be of modules parsed by [`parse_metered`](crate::parse_metered).
+pub struct Scheduler {
+    schedule: CostSchedule,
+    queue: VecDeque<Task>,
+    next_id: usize,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler {
+            schedule: CostSchedule::uniform(1),
+            queue: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl Scheduler {
+    /// Create a scheduler without tasks, which meters them with the cost of 1 tick for each
+    /// instruction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Meter the tasks added afterwards with the costs of instructions in `schedule`.
+    pub fn cost_schedule(mut self, schedule: &CostSchedule) -> Self {
+        self.schedule = schedule.clone();
+        self
+    }
+
+    /// Add a task executing the function `func` of `instance` with `args`, for `ticks_per_slice`
+    /// ticks in each turn. The task is queued after the tasks already added.
+    ///
+    /// Fails with [`Error::Other`] if `ticks_per_slice` is 0, as the task could never run.
+    pub fn add_task(
+        &mut self,
+        instance: Instance,
+        func: &str,
+        args: &[TypedValue],
+        ticks_per_slice: u64,
+    ) -> Result<TaskId, Error> {
+        if ticks_per_slice == 0 {
+            return Err(Error::Other(
+                "the ticks per slice must be positive".to_string(),
+            ));
+        }
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.queue.push_back(Task {
+            id,
+            instance,
+            func: func.to_string(),
+            args: args.to_vec(),
+            options: ExecutionOptions::new()
+                .cost_schedule(&self.schedule)
+                .gas_limit(ticks_per_slice),
+            slices: 0,
+            ticks_used: 0,
+        });
+        Ok(id)
+    }
+
+    /// The number of tasks which have not finished.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// True if all tasks have finished.
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Execute the next task for one slice. Returns the task if it has finished, or `None` if it
+    /// has run out of ticks and is queued again after the other tasks, or if there are no tasks.
+    pub fn step(&mut self) -> Option<FinishedTask> {
+        let mut task = self.queue.pop_front()?;
+        task.slices += 1;
+        let result = match task
+            .instance
+            .execute_with_options(&task.func, &task.args, &task.options)
+        {
+            Ok(outcome) => {
+                task.ticks_used += outcome.ticks_used().unwrap_or(0);
+                if outcome.gas_exhausted() {
+                    self.queue.push_back(task);
+                    return None;
+                }
+                if outcome.trapped() {
+                    Err(Error::Trapped(TrapInfo::new(
+                        &task.func,
+                        task.instance.take_host_trap(),
+                    )))
+                } else {
+                    Ok(outcome.value())
+                }
+            }
+            Err(err) => Err(err),
+        };
+        Some(FinishedTask {
+            id: task.id,
+            instance: task.instance,
+            result,
+            slices: task.slices,
+            ticks_used: task.ticks_used,
+        })
+    }
+
+    /// Execute the tasks in turns until all of them have finished, and return them in the order
+    /// they have finished.
+    ///
+    /// A task which makes no progress in its slices, e.g. an infinite loop, is never finished, and
+    /// therefore this does not return; [`Scheduler::step`] executes a single slice instead.
+    pub fn run_until_idle(&mut self) -> Vec<FinishedTask> {
+        let mut finished = Vec::new();
+        while !self.is_idle() {
+            finished.extend(self.step());
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, parse_metered, Module};
+
+    fn count_wasm() -> Vec<u8> {
+        /* wat2wasm
+        (module
+          (global $count (mut i32) (i32.const 0))
+          (func (export "count") (param $n i32) (result i32)
+            (block $done
+              (loop $next
+                (br_if $done (i32.ge_u (global.get $count) (local.get $n)))
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (br $next)))
+            (global.get $count))
+          (func (export "fail") unreachable)
+        )
+        */
+        hex::decode("0061736d0100000001090260017f017f60000003030200010606017f0141000b07100205636f756e740000046661696c00010a20021a0002400340230020004f0d01230041016a24000c000b0b23000b0300000b").unwrap()
+    }
+
+    fn count_module() -> Module {
+        parse_metered(&count_wasm()).unwrap()
+    }
+
+    /// Count to 10, 3 and 6 in turns, returning the finished tasks.
+    fn count(module: &Module) -> (Vec<TaskId>, Vec<FinishedTask>) {
+        let mut scheduler = Scheduler::new();
+        let ids = [10, 3, 6]
+            .iter()
+            .map(|n| {
+                scheduler
+                    .add_task(
+                        module.instantiate().unwrap(),
+                        "count",
+                        &[TypedValue::U32(*n)],
+                        30,
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(scheduler.pending(), 3);
+        let finished = scheduler.run_until_idle();
+        assert!(scheduler.is_idle());
+        (ids, finished)
+    }
+
+    #[test]
+    fn round_robin() {
+        let module = count_module();
+        let (ids, finished) = count(&module);
+
+        let order = finished.iter().map(FinishedTask::id).collect::<Vec<_>>();
+        assert_eq!(order, [ids[1], ids[2], ids[0]]);
+        // Each slice of 30 ticks counts up to 3.
+        let slices = finished
+            .iter()
+            .map(FinishedTask::slices)
+            .collect::<Vec<_>>();
+        assert_eq!(slices, [2, 3, 4]);
+        let values = finished
+            .iter()
+            .map(|task| *task.result().as_ref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                Some(TypedValue::U32(3)),
+                Some(TypedValue::U32(6)),
+                Some(TypedValue::U32(10))
+            ]
+        );
+        for task in &finished {
+            assert!(task.ticks_used() > 0);
+            assert!(task.ticks_used() <= 30 * u64::from(task.slices()));
+        }
+
+        // The progress is kept in the instance.
+        let mut instance = finished.into_iter().last().unwrap().into_instance();
+        let result = instance.execute("count", &[TypedValue::U32(0)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(10)));
+
+        // The same tasks finish in the same order, with the same slices and ticks.
+        let (_, again) = count(&module);
+        let (_, finished) = count(&module);
+        assert_eq!(
+            again
+                .iter()
+                .map(|task| (task.id(), task.slices(), task.ticks_used()))
+                .collect::<Vec<_>>(),
+            finished
+                .iter()
+                .map(|task| (task.id(), task.slices(), task.ticks_used()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn traps_and_errors() {
+        let module = count_module();
+        let mut scheduler = Scheduler::new().cost_schedule(&CostSchedule::uniform(2));
+        let count = scheduler
+            .add_task(
+                module.instantiate().unwrap(),
+                "count",
+                &[TypedValue::U32(5)],
+                40,
+            )
+            .unwrap();
+        let fail = scheduler
+            .add_task(module.instantiate().unwrap(), "fail", &[], 40)
+            .unwrap();
+        let missing = scheduler
+            .add_task(module.instantiate().unwrap(), "missing", &[], 40)
+            .unwrap();
+        assert_eq!(
+            scheduler
+                .add_task(module.instantiate().unwrap(), "fail", &[], 0)
+                .err(),
+            Some(Error::Other(
+                "the ticks per slice must be positive".to_string()
+            ))
+        );
+
+        // The count runs out of ticks, and is queued after the others.
+        assert!(scheduler.step().is_none());
+        let finished = scheduler.step().unwrap();
+        assert_eq!(finished.id(), fail);
+        assert_eq!(finished.slices(), 1);
+        assert!(finished.result().as_ref().unwrap_err().is_trap());
+        let finished = scheduler.step().unwrap();
+        assert_eq!(finished.id(), missing);
+        assert!(finished.result().is_err());
+        assert_eq!(scheduler.pending(), 1);
+
+        let finished = scheduler.run_until_idle();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].id(), count);
+        assert_eq!(finished[0].result(), &Ok(Some(TypedValue::U32(5))));
+        assert_eq!(finished[0].slices(), 3);
+        assert!(scheduler.step().is_none());
+
+        // The instances must be metered.
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .add_task(
+                parse(&count_wasm()).unwrap().instantiate().unwrap(),
+                "fail",
+                &[],
+                10,
+            )
+            .unwrap();
+        let finished = scheduler.run_until_idle();
+        assert_eq!(
+            finished[0].result(),
+            &Err(Error::Other(
+                "the module is not instrumented, see parse_metered".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,471 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The serialized representations of the types which cannot be derived directly.
+
+use crate::{
+    features, sys, CostSchedule, Error, ExecutionResult, ExternalKind, TrapInfo,
+    TypedExecutionResult, TypedValue,
+};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde", tag = "error", rename_all = "snake_case")]
+pub(crate) enum ErrorRepr {
+    MalformedModule {
+        message: String,
+    },
+    TextFormat {
+        message: String,
+    },
+    InvalidModule {
+        message: String,
+    },
+    InstantiationFailed {
+        message: String,
+    },
+    MissingImport {
+        module: String,
+        name: String,
+        kind: ExternalKind,
+    },
+    MemoryAllocationFailed {
+        message: String,
+    },
+    FunctionNotFound,
+    ArgumentCountMismatch,
+    ArgumentTypeMismatch,
+    ArgumentOutOfRange {
+        index: usize,
+    },
+    NoMemoryAvailable,
+    InvalidMemoryOffsetOrSize,
+    Busy,
+    UnsupportedType {
+        name: String,
+    },
+    UnsupportedFeature {
+        feature: String,
+    },
+    Trapped(TrapInfo),
+    Io {
+        message: String,
+    },
+    Other {
+        message: String,
+    },
+    LimitExceeded {
+        which: String,
+        limit: u64,
+        actual: u64,
+    },
+    DivergenceDetected {
+        ours: String,
+        theirs: String,
+    },
+    OutOfTicks,
+}
+
+impl From<Error> for ErrorRepr {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::MalformedModule(message) => ErrorRepr::MalformedModule { message },
+            Error::TextFormat(message) => ErrorRepr::TextFormat { message },
+            Error::InvalidModule(message) => ErrorRepr::InvalidModule { message },
+            Error::InstantiationFailed(message) => ErrorRepr::InstantiationFailed { message },
+            Error::MissingImport { module, name, kind } => {
+                ErrorRepr::MissingImport { module, name, kind }
+            }
+            Error::MemoryAllocationFailed(message) => ErrorRepr::MemoryAllocationFailed { message },
+            Error::FunctionNotFound => ErrorRepr::FunctionNotFound,
+            Error::ArgumentCountMismatch => ErrorRepr::ArgumentCountMismatch,
+            Error::ArgumentTypeMismatch => ErrorRepr::ArgumentTypeMismatch,
+            Error::ArgumentOutOfRange { index } => ErrorRepr::ArgumentOutOfRange { index },
+            Error::NoMemoryAvailable => ErrorRepr::NoMemoryAvailable,
+            Error::InvalidMemoryOffsetOrSize => ErrorRepr::InvalidMemoryOffsetOrSize,
+            Error::Busy => ErrorRepr::Busy,
+            Error::OutOfTicks => ErrorRepr::OutOfTicks,
+            Error::UnsupportedType(name) => ErrorRepr::UnsupportedType { name },
+            Error::UnsupportedFeature { feature } => ErrorRepr::UnsupportedFeature {
+                feature: feature.to_string(),
+            },
+            Error::Trapped(info) => ErrorRepr::Trapped(info),
+            Error::Io(err) => ErrorRepr::Io {
+                message: err.to_string(),
+            },
+            Error::Other(message) => ErrorRepr::Other { message },
+            Error::LimitExceeded {
+                which,
+                limit,
+                actual,
+            } => ErrorRepr::LimitExceeded {
+                which,
+                limit,
+                actual,
+            },
+            Error::DivergenceDetected { ours, theirs } => {
+                ErrorRepr::DivergenceDetected { ours, theirs }
+            }
+        }
+    }
+}
+
+// Not derived, which would require `'de: 'static` for the `&'static str` of the feature.
+impl<'de> Deserialize<'de> for Error {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ErrorRepr::deserialize(deserializer).map(Error::from)
+    }
+}
+
+impl From<ErrorRepr> for Error {
+    fn from(repr: ErrorRepr) -> Self {
+        match repr {
+            ErrorRepr::MalformedModule { message } => Error::MalformedModule(message),
+            ErrorRepr::TextFormat { message } => Error::TextFormat(message),
+            ErrorRepr::InvalidModule { message } => Error::InvalidModule(message),
+            ErrorRepr::InstantiationFailed { message } => Error::InstantiationFailed(message),
+            ErrorRepr::MissingImport { module, name, kind } => {
+                Error::MissingImport { module, name, kind }
+            }
+            ErrorRepr::MemoryAllocationFailed { message } => Error::MemoryAllocationFailed(message),
+            ErrorRepr::FunctionNotFound => Error::FunctionNotFound,
+            ErrorRepr::ArgumentCountMismatch => Error::ArgumentCountMismatch,
+            ErrorRepr::ArgumentTypeMismatch => Error::ArgumentTypeMismatch,
+            ErrorRepr::ArgumentOutOfRange { index } => Error::ArgumentOutOfRange { index },
+            ErrorRepr::NoMemoryAvailable => Error::NoMemoryAvailable,
+            ErrorRepr::InvalidMemoryOffsetOrSize => Error::InvalidMemoryOffsetOrSize,
+            ErrorRepr::Busy => Error::Busy,
+            ErrorRepr::OutOfTicks => Error::OutOfTicks,
+            ErrorRepr::UnsupportedType { name } => Error::UnsupportedType(name),
+            // A feature unknown to this version, e.g. of a newer one, keeps its message.
+            ErrorRepr::UnsupportedFeature { feature } => match features::feature_name(&feature) {
+                Some(feature) => Error::UnsupportedFeature { feature },
+                None => Error::Other(format!(
+                    "the WebAssembly feature {} is not supported",
+                    feature
+                )),
+            },
+            ErrorRepr::Trapped(info) => Error::Trapped(info),
+            ErrorRepr::Io { message } => Error::Io(Arc::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                message,
+            ))),
+            ErrorRepr::Other { message } => Error::Other(message),
+            ErrorRepr::LimitExceeded {
+                which,
+                limit,
+                actual,
+            } => Error::LimitExceeded {
+                which,
+                limit,
+                actual,
+            },
+            ErrorRepr::DivergenceDetected { ours, theirs } => {
+                Error::DivergenceDetected { ours, theirs }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde")]
+struct ExecutionResultRepr {
+    trapped: bool,
+    value: Option<TypedValue>,
+}
+
+impl Serialize for TypedExecutionResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ExecutionResultRepr {
+            trapped: self.trapped(),
+            value: self.value(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TypedExecutionResult {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ExecutionResultRepr::deserialize(deserializer)?;
+        if repr.trapped && repr.value.is_some() {
+            return Err(D::Error::custom("trapped execution result with a value"));
+        }
+        Ok(TypedExecutionResult {
+            result: ExecutionResult(sys::FizzyExecutionResult {
+                trapped: repr.trapped,
+                has_value: repr.value.is_some(),
+                value: match &repr.value {
+                    Some(value) => value.into(),
+                    None => sys::FizzyValue { i64: 0 },
+                },
+            }),
+            output: repr.value.map(|value| value.value_type()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde")]
+pub(crate) struct CostScheduleRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<u64>,
+    #[serde(default)]
+    costs: BTreeMap<String, u64>,
+}
+
+impl From<CostSchedule> for CostScheduleRepr {
+    fn from(schedule: CostSchedule) -> Self {
+        CostScheduleRepr {
+            default: schedule.default_cost(),
+            costs: schedule.costs().clone(),
+        }
+    }
+}
+
+impl TryFrom<CostScheduleRepr> for CostSchedule {
+    type Error = String;
+
+    fn try_from(repr: CostScheduleRepr) -> Result<Self, String> {
+        CostSchedule::new(repr.default, repr.costs).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FunctionType, Limits, TrapKind, ValueType};
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    /// Compare values by their bits, as NaNs are not equal to themselves.
+    fn same_bits(a: TypedValue, b: TypedValue) -> bool {
+        match (a, b) {
+            (TypedValue::F32(a), TypedValue::F32(b)) => a.to_bits() == b.to_bits(),
+            (TypedValue::F64(a), TypedValue::F64(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        }
+    }
+
+    #[test]
+    fn typed_value_representation() {
+        let json = |value: TypedValue| serde_json::to_string(&value).unwrap();
+        assert_eq!(json(TypedValue::U32(42)), r#"{"type":"i32","value":42}"#);
+        assert_eq!(
+            json(TypedValue::U64(u64::max_value())),
+            r#"{"type":"i64","value":"18446744073709551615"}"#
+        );
+        assert_eq!(
+            json(TypedValue::F32(f32::from_bits(0x7fc0_0001))),
+            r#"{"type":"f32","value":2143289345}"#
+        );
+        assert_eq!(
+            json(TypedValue::F64(f64::NEG_INFINITY)),
+            r#"{"type":"f64","value":"18442240474082181120"}"#
+        );
+
+        let value = |json: &str| serde_json::from_str::<TypedValue>(json);
+        assert_eq!(
+            value(r#"{"type":"i64","value":"-1"}"#).unwrap(),
+            TypedValue::U64(u64::max_value())
+        );
+        assert!(value(r#"{"type":"i32","value":-1}"#).is_err());
+        assert!(value(r#"{"type":"i64","value":"0x1"}"#).is_err());
+        assert!(value(r#"{"type":"f64","value":"1.0"}"#).is_err());
+        assert!(value(r#"{"type":"v128","value":0}"#).is_err());
+    }
+
+    #[test]
+    fn typed_value_round_trip() {
+        let values = [
+            TypedValue::U32(0),
+            TypedValue::U32(u32::max_value()),
+            TypedValue::U64((1 << 53) + 1),
+            TypedValue::U64(u64::max_value()),
+            TypedValue::F32(f32::NAN),
+            TypedValue::F32(f32::from_bits(0xffa0_0001)),
+            TypedValue::F32(f32::INFINITY),
+            TypedValue::F32(-0.0),
+            TypedValue::F64(f64::from_bits(0x7ff4_0000_0000_0001)),
+            TypedValue::F64(f64::NEG_INFINITY),
+            TypedValue::F64(f64::MIN_POSITIVE),
+            TypedValue::ExternRef,
+        ];
+        for value in values.iter() {
+            assert!(same_bits(round_trip(value), *value), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn types_round_trip() {
+        assert_eq!(serde_json::to_string(&ValueType::I64).unwrap(), r#""i64""#);
+        let func_type =
+            FunctionType::new(vec![ValueType::I32, ValueType::F64], Some(ValueType::F32));
+        assert_eq!(
+            serde_json::to_string(&func_type).unwrap(),
+            r#"{"inputs":["i32","f64"],"output":"f32"}"#
+        );
+        assert_eq!(round_trip(&func_type), func_type);
+        let func_type = FunctionType::new(vec![], None);
+        assert_eq!(round_trip(&func_type), func_type);
+
+        let limits = Limits::new(1, Some(u32::max_value()));
+        assert_eq!(
+            serde_json::to_string(&limits).unwrap(),
+            r#"{"min":1,"max":4294967295}"#
+        );
+        assert_eq!(round_trip(&limits), limits);
+        assert_eq!(round_trip(&Limits::new(0, None)), Limits::new(0, None));
+    }
+
+    #[test]
+    fn error_round_trip() {
+        assert_eq!(
+            serde_json::to_string(&Error::InvalidModule("invalid type index".to_string())).unwrap(),
+            r#"{"error":"invalid_module","message":"invalid type index"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Error::MissingImport {
+                module: "env".to_string(),
+                name: "mem".to_string(),
+                kind: ExternalKind::Memory,
+            })
+            .unwrap(),
+            r#"{"error":"missing_import","module":"env","name":"mem","kind":"memory"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Error::FunctionNotFound).unwrap(),
+            r#"{"error":"function_not_found"}"#
+        );
+        let trap = Error::Trapped(TrapInfo::new("run", Some(crate::Trap::new("out of gas"))));
+        assert_eq!(
+            serde_json::to_string(&trap).unwrap(),
+            r#"{"error":"trapped","function":"run","kind":{"host":"out of gas"}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&TrapKind::CallDepthExceeded).unwrap(),
+            r#""call_depth_exceeded""#
+        );
+
+        let errors = [
+            Error::MalformedModule("invalid wasm module prefix".to_string()),
+            Error::TextFormat("unexpected token".to_string()),
+            Error::InstantiationFailed("start function failed to execute".to_string()),
+            Error::MissingImport {
+                module: "env".to_string(),
+                name: "add".to_string(),
+                kind: ExternalKind::Function,
+            },
+            Error::MemoryAllocationFailed("out of memory".to_string()),
+            Error::ArgumentCountMismatch,
+            Error::ArgumentTypeMismatch,
+            Error::ArgumentOutOfRange { index: 1 },
+            Error::NoMemoryAvailable,
+            Error::InvalidMemoryOffsetOrSize,
+            Error::Busy,
+            Error::UnsupportedType("funcref".to_string()),
+            Error::UnsupportedFeature { feature: "simd" },
+            trap,
+            Error::Trapped(TrapInfo {
+                function: None,
+                kind: TrapKind::Wasm,
+                host_error: None,
+            }),
+            Error::Trapped(TrapInfo {
+                function: Some("rec".to_string()),
+                kind: TrapKind::CallDepthExceeded,
+                host_error: None,
+            }),
+            Error::Io(Arc::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "broken pipe",
+            ))),
+            Error::Other("unknown".to_string()),
+            Error::LimitExceeded {
+                which: "max_functions".to_string(),
+                limit: 10,
+                actual: 11,
+            },
+            Error::DivergenceDetected {
+                ours: "returned i32:1".to_string(),
+                theirs: "trapped".to_string(),
+            },
+            Error::OutOfTicks,
+        ];
+        for err in errors.iter() {
+            assert_eq!(&round_trip(err), err);
+            // The kind is serialized as the tag of the error.
+            assert_eq!(
+                serde_json::to_value(err).unwrap()["error"],
+                serde_json::to_value(err.kind()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn execution_result_round_trip() {
+        /* wat2wasm
+        (module
+          (func (export "nan") (result f64) (f64.const nan:0x1))
+          (func (export "nop"))
+          (func (export "trap") (unreachable))
+        )
+        */
+        let input = hex::decode("0061736d010000000108026000017c600000030403000101071403036e616e0000036e6f700001047472617000020a14030b0044010000000000f07f0b02000b0300000b").unwrap();
+        let mut instance = crate::parse(&input).unwrap().instantiate().unwrap();
+
+        let result = instance.execute("nan", &[]).unwrap();
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            json,
+            r#"{"trapped":false,"value":{"type":"f64","value":"9218868437227405313"}}"#
+        );
+        let restored: TypedExecutionResult = serde_json::from_str(&json).unwrap();
+        assert!(!restored.trapped());
+        assert!(same_bits(
+            restored.value().unwrap(),
+            result.value().unwrap()
+        ));
+
+        for name in &["nop", "trap"] {
+            let result = instance.execute(name, &[]).unwrap();
+            let restored = round_trip(&result);
+            assert_eq!(restored.trapped(), result.trapped());
+            assert_eq!(restored.value(), None);
+        }
+
+        assert!(serde_json::from_str::<TypedExecutionResult>(
+            r#"{"trapped":true,"value":{"type":"i32","value":1}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn cost_schedule_round_trip() {
+        let schedule = CostSchedule::builder()
+            .default_cost(1)
+            .cost("i32.add", 2)
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert_eq!(json, r#"{"default":1,"costs":{"i32.add":2}}"#);
+        assert_eq!(round_trip(&schedule), schedule);
+
+        let schedule: CostSchedule = serde_json::from_str(r#"{"default":3}"#).unwrap();
+        assert_eq!(schedule, CostSchedule::uniform(3));
+
+        let err = serde_json::from_str::<CostSchedule>(r#"{"costs":{"i32.add":2}}"#).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("no cost of instruction unreachable and no default cost"));
+        assert!(
+            serde_json::from_str::<CostSchedule>(r#"{"default":1,"costs":{"i32.foo":2}}"#).is_err()
+        );
+    }
+}
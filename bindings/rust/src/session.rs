@@ -0,0 +1,284 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sequences of metered executions sharing a budget of ticks.
+
+use crate::{CostSchedule, Error, ExecutionOptions, Instance, TrapInfo, TypedValue};
+
+/// A sequence of executions of an instance of a module parsed by
+/// [`parse_metered`](crate::parse_metered), e.g. the calls of a transaction, whose costs are
+/// charged to a single budget of ticks.
+///
+/// ```no_run
+/// # fn run(instance: &mut fizzy::Instance) -> Result<(), fizzy::Error> {
+/// use fizzy::{MeteredSession, TypedValue};
+///
+/// let mut session = MeteredSession::new(instance, 10_000);
+/// session.call("transfer", &[TypedValue::U32(42)])?;
+/// session.call("commit", &[])?;
+/// println!("{} ticks used", session.finish().consumed());
+/// # Ok(())
+/// # }
+/// ```
+pub struct MeteredSession<'a> {
+    instance: &'a mut Instance,
+    schedule: CostSchedule,
+    budget: u64,
+    consumed: u64,
+    calls: Vec<CallCost>,
+}
+
+/// The cost of a call of a [`MeteredSession`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallCost {
+    name: String,
+    ticks: u64,
+    trapped: bool,
+    out_of_ticks: bool,
+}
+
+impl CallCost {
+    /// The name of the called function.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The ticks charged to the budget for the call.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// True if the call has trapped, including running out of ticks.
+    pub fn trapped(&self) -> bool {
+        self.trapped
+    }
+
+    /// True if the call has exhausted the budget.
+    pub fn out_of_ticks(&self) -> bool {
+        self.out_of_ticks
+    }
+}
+
+/// The costs of the calls of a [`MeteredSession`], returned by [`MeteredSession::finish`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionSummary {
+    budget: u64,
+    consumed: u64,
+    calls: Vec<CallCost>,
+}
+
+impl SessionSummary {
+    /// The budget of the session.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// The ticks charged to the budget.
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// The ticks left of the budget.
+    pub fn remaining(&self) -> u64 {
+        self.budget - self.consumed
+    }
+
+    /// The costs of the executed calls, in their order. The refused calls are not included.
+    pub fn calls(&self) -> &[CallCost] {
+        &self.calls
+    }
+}
+
+impl<'a> MeteredSession<'a> {
+    /// Start a session of `instance` with the budget of `budget` ticks, metered with the cost of 1
+    /// for all instructions.
+    pub fn new(instance: &'a mut Instance, budget: u64) -> Self {
+        Self::with_schedule(instance, budget, &CostSchedule::uniform(1))
+    }
+
+    /// Start a session of `instance` with the budget of `budget` ticks, metered with the costs of
+    /// `schedule`.
+    pub fn with_schedule(instance: &'a mut Instance, budget: u64, schedule: &CostSchedule) -> Self {
+        MeteredSession {
+            instance,
+            schedule: schedule.clone(),
+            budget,
+            consumed: 0,
+            calls: Vec::new(),
+        }
+    }
+
+    /// Execute the function `name` with `args`, within the ticks left of the budget, and return
+    /// its output. A trap is returned as [`Error::Trapped`], and the session can continue.
+    ///
+    /// Fails with [`Error::OutOfTicks`] if the execution would exceed the budget, which is then
+    /// charged entirely, as the costs are charged by blocks of instructions, and without executing
+    /// if the budget is already exhausted. The errors before the execution, e.g.
+    /// [`Error::FunctionNotFound`], charge nothing.
+    pub fn call(&mut self, name: &str, args: &[TypedValue]) -> Result<Option<TypedValue>, Error> {
+        let remaining = self.remaining();
+        if remaining == 0 {
+            return Err(Error::OutOfTicks);
+        }
+        let options = ExecutionOptions::new()
+            .cost_schedule(&self.schedule)
+            .gas_limit(remaining);
+        let outcome = self.instance.execute_with_options(name, args, &options)?;
+        let ticks = if outcome.gas_exhausted() {
+            remaining
+        } else {
+            outcome.ticks_used().expect("metered execution")
+        };
+        self.consumed += ticks;
+        self.calls.push(CallCost {
+            name: name.to_string(),
+            ticks,
+            trapped: outcome.trapped(),
+            out_of_ticks: outcome.gas_exhausted(),
+        });
+
+        if outcome.gas_exhausted() {
+            // The trap raised by the instrumentation carries no information.
+            self.instance.take_host_trap();
+            Err(Error::OutOfTicks)
+        } else if outcome.trapped() {
+            Err(Error::Trapped(TrapInfo::new(
+                name,
+                self.instance.take_host_trap(),
+            )))
+        } else {
+            Ok(outcome.value())
+        }
+    }
+
+    /// The ticks left of the budget.
+    pub fn remaining(&self) -> u64 {
+        self.budget - self.consumed
+    }
+
+    /// The ticks charged to the budget so far.
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// End the session, returning the costs of its calls.
+    pub fn finish(self) -> SessionSummary {
+        SessionSummary {
+            budget: self.budget,
+            consumed: self.consumed,
+            calls: self.calls,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_metered;
+
+    /* wat2wasm
+    (module
+      (func (export "loop") (param $n i32) (result i32)
+        (local $sum i32)
+        (block $done
+          (loop $next
+            (br_if $done (i32.eqz (local.get $n)))
+            (local.set $sum (i32.add (local.get $sum) (local.get $n)))
+            (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+            (br $next)
+          )
+        )
+        (local.get $sum)
+      )
+      (func (export "trap") (unreachable))
+    )
+    */
+    const LOOP_WASM: &str = "0061736d0100000001090260017f017f6000000303020001070f02046c6f6f700000047472617000010a27022101017f024003402000450d01200120006a2101200041016b21000c000b0b20010b0300000b";
+
+    fn instance() -> Instance {
+        parse_metered(&hex::decode(LOOP_WASM).unwrap())
+            .unwrap()
+            .instantiate()
+            .unwrap()
+    }
+
+    fn cost(name: &str, ticks: u64, trapped: bool, out_of_ticks: bool) -> CallCost {
+        CallCost {
+            name: name.to_string(),
+            ticks,
+            trapped,
+            out_of_ticks,
+        }
+    }
+
+    #[test]
+    fn shared_budget() {
+        let mut instance = instance();
+        let mut session = MeteredSession::new(&mut instance, 200);
+        // The instructions of the block, loop and result, then 12 of each iteration.
+        assert_eq!(
+            session.call("loop", &[TypedValue::U32(5)]),
+            Ok(Some(TypedValue::U32(15)))
+        );
+        assert_eq!(session.consumed(), 7 + 5 * 12);
+        assert_eq!(
+            session.call("loop", &[TypedValue::U32(3)]),
+            Ok(Some(TypedValue::U32(6)))
+        );
+        assert_eq!(session.remaining(), 200 - 67 - 43);
+        // The third call needs 127 ticks, more than the 90 left.
+        assert_eq!(
+            session.call("loop", &[TypedValue::U32(10)]),
+            Err(Error::OutOfTicks)
+        );
+        assert_eq!(session.remaining(), 0);
+        // The exhausted budget refuses the calls.
+        assert_eq!(
+            session.call("loop", &[TypedValue::U32(0)]),
+            Err(Error::OutOfTicks)
+        );
+
+        let summary = session.finish();
+        assert_eq!(summary.budget(), 200);
+        assert_eq!(summary.consumed(), 200);
+        assert_eq!(summary.remaining(), 0);
+        assert_eq!(
+            summary.calls(),
+            [
+                cost("loop", 67, false, false),
+                cost("loop", 43, false, false),
+                cost("loop", 90, true, true),
+            ]
+        );
+
+        // The gas is unlimited again afterwards.
+        let result = instance.execute("loop", &[TypedValue::U32(10)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(55)));
+    }
+
+    #[test]
+    fn traps_and_errors() {
+        let mut instance = instance();
+        let schedule = CostSchedule::uniform(2);
+        let mut session = MeteredSession::with_schedule(&mut instance, 1000, &schedule);
+        assert!(matches!(session.call("trap", &[]), Err(Error::Trapped(_))));
+        let trap_ticks = session.consumed();
+        assert!(trap_ticks > 0);
+        assert_eq!(session.call("missing", &[]), Err(Error::FunctionNotFound));
+        assert_eq!(
+            session.call("loop", &[TypedValue::U32(1)]),
+            Ok(Some(TypedValue::U32(1)))
+        );
+        assert_eq!(session.consumed(), trap_ticks + 2 * (7 + 12));
+
+        let summary = session.finish();
+        assert_eq!(
+            summary.calls(),
+            [
+                cost("trap", trap_ticks, true, false),
+                cost("loop", 2 * (7 + 12), false, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Executing all the exported functions without parameters, for a quick triage of unknown modules.
+
+use crate::{Error, ExecutionOptions, ExternalKind, Instance, TrapInfo, TypedValue};
+
+impl Instance {
+    /// Execute each exported function without parameters with `limits`, in the order of the
+    /// exports, and return the name of each with its result, or the error or trap ending it.
+    ///
+    /// The functions with parameters are skipped. A trap does not stop the following calls,
+    /// however each call runs on the state left by the previous calls: take a
+    /// [`StateSnapshot`](crate::StateSnapshot) or [`Instance::reset`] the instance in between
+    /// when they must be independent. As the functions of an unknown module may not terminate,
+    /// `limits` should meter the executions with [`ExecutionOptions::gas_limit`], which applies to
+    /// each call separately, and limit their call depth.
+    pub fn call_all_nullary_exports(
+        &mut self,
+        limits: &ExecutionOptions,
+    ) -> Vec<(String, Result<Option<TypedValue>, Error>)> {
+        let names: Vec<String> = self
+            .module
+            .exports()
+            .into_iter()
+            .filter(|export| export.kind() == ExternalKind::Function)
+            .map(|export| export.name().to_string())
+            .filter(|name| {
+                self.with_exported_function(name, |_, func_type| func_type.inputs.is_empty())
+                    .unwrap_or(false)
+            })
+            .collect();
+        names
+            .into_iter()
+            .map(|name| {
+                let result = self.call_nullary_export(&name, limits);
+                (name, result)
+            })
+            .collect()
+    }
+
+    fn call_nullary_export(
+        &mut self,
+        name: &str,
+        limits: &ExecutionOptions,
+    ) -> Result<Option<TypedValue>, Error> {
+        let outcome = self.execute_with_options(name, &[], limits)?;
+        if outcome.result().trapped() {
+            return Err(Error::Trapped(TrapInfo::new(name, self.take_host_trap())));
+        }
+        Ok(outcome.result().value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Trap};
+
+    #[test]
+    fn call_all_nullary_exports() {
+        /* wat2wasm
+        (module
+          (global $calls (mut i32) (i32.const 0))
+          (func $count (global.set $calls (i32.add (global.get $calls) (i32.const 1))))
+          (func (export "answer") (result i32) (call $count) (i32.const 42))
+          (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+          (func (export "void") (call $count))
+          (func (export "trap") (call $count) (unreachable))
+          (func $recurse (export "recurse") (call $recurse))
+          (func (export "calls") (result i32) (global.get $calls))
+          (global (export "global") i32 (i32.const 0))
+        )
+        */
+        let input = hex::decode("0061736d01000000010e036000006000017f60027f7f017f03080700010200000001060b027f0141000b7f0041000b07390706616e73776572000103616464000204766f6964000304747261700004077265637572736500050563616c6c73000606676c6f62616c03010a2f070900230041016a24000b06001000412a0b0700200020016a0b040010000b05001000000b040010050b040023000b").unwrap();
+        let mut instance = parse(&input).unwrap().instantiate().unwrap();
+        let results =
+            instance.call_all_nullary_exports(&ExecutionOptions::new().max_call_depth(10));
+        let expected = vec![
+            ("answer", Ok(Some(TypedValue::U32(42)))),
+            ("void", Ok(None)),
+            ("trap", Err(Error::Trapped(TrapInfo::new("trap", None)))),
+            (
+                "recurse",
+                Err(Error::Trapped(TrapInfo::new(
+                    "recurse",
+                    Some(Trap::call_depth_exceeded()),
+                ))),
+            ),
+            // The function runs on the state left by the previous calls, including the trapped.
+            ("calls", Ok(Some(TypedValue::U32(3)))),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(name, result)| (name.to_string(), result))
+            .collect();
+        assert_eq!(results, expected);
+    }
+}
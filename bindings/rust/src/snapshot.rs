@@ -0,0 +1,379 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The snapshots of the state of an instance, and the differences between them.
+
+use crate::raw::{GlobalTypeExt, TypedValueExt};
+use crate::{sys, GlobalType, Instance, TypedValue};
+
+use std::fmt;
+
+/// The number of bytes kept of each changed memory range by [`StateSnapshot::diff`].
+const DEFAULT_BYTE_LIMIT: usize = 32;
+
+/// The memory and the values of the mutable globals defined by the module of an instance, taken
+/// with [`Instance::snapshot`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateSnapshot {
+    /// The memory, if the instance has one.
+    pub(crate) memory: Option<Vec<u8>>,
+    /// The indices and values of the mutable globals.
+    pub(crate) globals: Vec<(u32, TypedValue)>,
+}
+
+impl Instance {
+    /// Take a snapshot of the memory and the values of the mutable globals defined by the module,
+    /// i.e. of the state included in [`Instance::serialize_state`].
+    pub fn snapshot(&self) -> StateSnapshot {
+        let _memory = self.lock_memory();
+        let module = self.module.as_ptr();
+        let globals = self
+            .mutable_globals()
+            .into_iter()
+            .map(|global_idx| {
+                let global_type = unsafe { sys::fizzy_get_global_type(module, global_idx) };
+                let value =
+                    unsafe { *sys::fizzy_get_instance_global(self.instance.as_ptr(), global_idx) };
+                let value_type = GlobalType::from_raw(&global_type).value_type;
+                (global_idx, TypedValue::from_value(value, value_type))
+            })
+            .collect();
+        let memory = unsafe { self.checked_memory_slice(0, self.memory_size()) }
+            .ok()
+            .map(<[u8]>::to_vec);
+        StateSnapshot { memory, globals }
+    }
+}
+
+impl StateSnapshot {
+    /// The differences of `other` from this snapshot, keeping up to 32 bytes of each changed
+    /// memory range, see [`StateSnapshot::diff_with_byte_limit`].
+    pub fn diff(&self, other: &StateSnapshot) -> StateDiff {
+        self.diff_with_byte_limit(other, DEFAULT_BYTE_LIMIT)
+    }
+
+    /// The differences of `other` from this snapshot, which are expected to be of instances of
+    /// the same module, keeping up to `byte_limit` bytes from the start of each changed memory
+    /// range.
+    ///
+    /// The changed bytes are coalesced into ranges if they are adjacent. If the memory has grown,
+    /// the new pages are compared to zeros, as they are initialized with.
+    pub fn diff_with_byte_limit(&self, other: &StateSnapshot, byte_limit: usize) -> StateDiff {
+        let empty = Vec::new();
+        let before = self.memory.as_ref().unwrap_or(&empty);
+        let after = other.memory.as_ref().unwrap_or(&empty);
+        let memory_size = if before.len() != after.len() {
+            Some((before.len(), after.len()))
+        } else {
+            None
+        };
+
+        let byte = |memory: &[u8], offset: usize| memory.get(offset).copied().unwrap_or(0);
+        let mut memory = Vec::new();
+        let mut start = None;
+        for offset in 0..=before.len().max(after.len()) {
+            let changed = byte(before, offset) != byte(after, offset);
+            match (start, changed) {
+                (None, true) => start = Some(offset),
+                (Some(first), false) => {
+                    let end = offset.min(first + byte_limit);
+                    memory.push(MemoryChange {
+                        offset: first,
+                        size: offset - first,
+                        before: (first..end).map(|i| byte(before, i)).collect(),
+                        after: (first..end).map(|i| byte(after, i)).collect(),
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        let globals = self
+            .globals
+            .iter()
+            .filter_map(|(index, before)| {
+                let after = other
+                    .globals
+                    .iter()
+                    .find(|(other_index, _)| other_index == index)?
+                    .1;
+                if before.same_bits(&after) {
+                    return None;
+                }
+                Some(GlobalChange {
+                    index: *index,
+                    before: *before,
+                    after,
+                })
+            })
+            .collect();
+
+        StateDiff {
+            memory,
+            globals,
+            memory_size,
+        }
+    }
+}
+
+/// The differences between two snapshots, see [`StateSnapshot::diff`].
+///
+/// It is written with `{}` as a line per change, e.g. `memory 0x10..0x14: 00000000 -> 2a000000`,
+/// where the bytes are in hex and followed by `...` if they are longer than the byte limit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateDiff {
+    memory: Vec<MemoryChange>,
+    globals: Vec<GlobalChange>,
+    memory_size: Option<(usize, usize)>,
+}
+
+impl StateDiff {
+    /// True if the snapshots are the same.
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty() && self.globals.is_empty() && self.memory_size.is_none()
+    }
+
+    /// The changed memory ranges, in the order of their offsets.
+    pub fn memory(&self) -> &[MemoryChange] {
+        &self.memory
+    }
+
+    /// The changed globals, in the order of their indices.
+    pub fn globals(&self) -> &[GlobalChange] {
+        &self.globals
+    }
+
+    /// The sizes of the memory before and after in bytes, if it has changed.
+    pub fn memory_size(&self) -> Option<(usize, usize)> {
+        self.memory_size
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        if let Some((before, after)) = self.memory_size {
+            writeln!(f, "memory size: {} -> {} bytes", before, after)?;
+        }
+        for change in &self.memory {
+            let write_bytes = |f: &mut fmt::Formatter<'_>, bytes: &[u8]| {
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                if bytes.len() < change.size {
+                    write!(f, "...")?;
+                }
+                Ok(())
+            };
+            write!(
+                f,
+                "memory 0x{:x}..0x{:x}: ",
+                change.offset,
+                change.offset + change.size
+            )?;
+            write_bytes(f, &change.before)?;
+            write!(f, " -> ")?;
+            write_bytes(f, &change.after)?;
+            writeln!(f)?;
+        }
+        for change in &self.globals {
+            writeln!(
+                f,
+                "global {}: {} -> {}",
+                change.index, change.before, change.after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A range of changed bytes of the memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryChange {
+    offset: usize,
+    size: usize,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+impl MemoryChange {
+    /// The offset of the first changed byte.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The number of changed bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The bytes before the change, up to the byte limit.
+    pub fn before(&self) -> &[u8] {
+        &self.before
+    }
+
+    /// The bytes after the change, up to the byte limit.
+    pub fn after(&self) -> &[u8] {
+        &self.after
+    }
+}
+
+/// A changed value of a global.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlobalChange {
+    index: u32,
+    before: TypedValue,
+    after: TypedValue,
+}
+
+impl GlobalChange {
+    /// The index of the global in the module.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The value before the change.
+    pub fn before(&self) -> TypedValue {
+        self.before
+    }
+
+    /// The value after the change.
+    pub fn after(&self) -> TypedValue {
+        self.after
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn instantiate() -> Instance {
+        /* wat2wasm
+        (module
+          (memory 1 3)
+          (global $count (mut i32) (i32.const 0))
+          (global $scale (mut f64) (f64.const 1.5))
+          (global i32 (i32.const 7))
+          (func (export "store") (param $address i32) (param $value i32)
+            (i32.store8 (local.get $address) (local.get $value)))
+          (func (export "grow") (drop (memory.grow (i32.const 1))))
+          (func (export "bump") (global.set $count (i32.add (global.get $count) (i32.const 1))))
+          (func (export "nan") (global.set $scale (f64.const nan:0x1)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001090260027f7f00600000030504000101010504010101030617037f0141000b7c0144000000000000f83f0b7f0041070b071d040573746f726500000467726f7700010462756d700002036e616e00030a2b040900200020013a00000b0700410140001a0b0900230041016a24000b0d0044010000000000f07f24010b").unwrap();
+        parse(&input).unwrap().instantiate().unwrap()
+    }
+
+    fn store(instance: &mut Instance, address: u32, value: u32) {
+        let result = instance
+            .execute("store", &[TypedValue::U32(address), TypedValue::U32(value)])
+            .unwrap();
+        assert!(!result.trapped());
+    }
+
+    #[test]
+    fn diff() {
+        let mut instance = instantiate();
+        let before = instance.snapshot();
+        assert!(before.diff(&instance.snapshot()).is_empty());
+        assert_eq!(before.diff(&before).to_string(), "no changes\n");
+
+        for (address, value) in &[(16, 1), (17, 2), (18, 3), (20, 4)] {
+            store(&mut instance, *address, *value);
+        }
+        // Storing the same value is not a change.
+        store(&mut instance, 19, 0);
+        instance.execute("grow", &[]).unwrap();
+        // Across the boundary of the grown page.
+        store(&mut instance, 65535, 5);
+        store(&mut instance, 65536, 6);
+        instance.execute("bump", &[]).unwrap();
+
+        let diff = before.diff(&instance.snapshot());
+        assert_eq!(diff.memory_size(), Some((65536, 131072)));
+        let ranges: Vec<(usize, usize, &[u8], &[u8])> = diff
+            .memory()
+            .iter()
+            .map(|change| {
+                (
+                    change.offset(),
+                    change.size(),
+                    change.before(),
+                    change.after(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                (16, 3, &[0, 0, 0][..], &[1, 2, 3][..]),
+                (20, 1, &[0][..], &[4][..]),
+                (65535, 2, &[0, 0][..], &[5, 6][..]),
+            ]
+        );
+        assert_eq!(diff.globals().len(), 1);
+        assert_eq!(diff.globals()[0].index(), 0);
+        assert_eq!(diff.globals()[0].before(), TypedValue::U32(0));
+        assert_eq!(diff.globals()[0].after(), TypedValue::U32(1));
+        assert_eq!(
+            diff.to_string(),
+            "\
+memory size: 65536 -> 131072 bytes
+memory 0x10..0x13: 000000 -> 010203
+memory 0x14..0x15: 00 -> 04
+memory 0xffff..0x10001: 0000 -> 0506
+global 0: i32:0 -> i32:1
+"
+        );
+
+        // The changes are reversed in the other direction.
+        let diff = instance.snapshot().diff(&before);
+        assert_eq!(diff.memory_size(), Some((131072, 65536)));
+        assert_eq!(diff.memory()[2].offset(), 65535);
+        assert_eq!(diff.memory()[2].after(), [0, 0]);
+    }
+
+    #[test]
+    fn byte_limit() {
+        let mut instance = instantiate();
+        let before = instance.snapshot();
+        for address in 100..140 {
+            store(&mut instance, address, 0xff);
+        }
+        let after = instance.snapshot();
+
+        let diff = before.diff_with_byte_limit(&after, 4);
+        assert_eq!(diff.memory().len(), 1);
+        assert_eq!(diff.memory()[0].size(), 40);
+        assert_eq!(diff.memory()[0].before(), [0; 4]);
+        assert_eq!(diff.memory()[0].after(), [0xff; 4]);
+        assert_eq!(
+            diff.to_string(),
+            "memory 0x64..0x8c: 00000000... -> ffffffff...\n"
+        );
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.memory()[0].after(), [0xff; 32]);
+        assert_eq!(diff.memory_size(), None);
+    }
+
+    #[test]
+    fn nan_global() {
+        let mut instance = instantiate();
+        instance.execute("nan", &[]).unwrap();
+        let before = instance.snapshot();
+        // The same NaN is not a change, although NaNs are not equal.
+        assert!(before.diff(&instance.snapshot()).is_empty());
+
+        let diff = instantiate().snapshot().diff(&before);
+        assert_eq!(diff.globals().len(), 1);
+        assert_eq!(diff.globals()[0].index(), 1);
+        assert_eq!(diff.to_string(), "global 1: f64:1.5 -> f64:NaN\n");
+    }
+}
@@ -0,0 +1,405 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The portable format of the state of an instance.
+
+use crate::raw::GlobalTypeExt;
+use crate::{sys, Error, GlobalType, ImportsBuilder, Instance, Module, Value, ValueType};
+
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 4] = b"FZST";
+const VERSION: u32 = 1;
+const PAGE_SIZE: usize = 65536;
+
+const ZERO_PAGE: u8 = 0;
+const DATA_PAGE: u8 = 1;
+
+impl Instance {
+    /// Serialize the memory and the values of the mutable globals defined by the module, with the
+    /// digest of the module. Tables are not included, as WebAssembly 1.0 instructions cannot modify them.
+    ///
+    /// The format is the same on all platforms, with all numbers little-endian:
+    /// - the header of the magic `b"FZST"`, the u32 version (currently 1), and the u64
+    ///   [`Module::digest`], where readers reject versions they do not know,
+    /// - the u32 count of globals, then the u32 index and u64 bits of the value of each, where
+    ///   the bits of i32 and f32 values are zero-extended,
+    /// - u8 0 if the instance has no memory, otherwise u8 1, the u32 count of pages, and each page
+    ///   as u8 0 if all of its bytes are zeros, or u8 1 followed by its 65536 bytes.
+    pub fn serialize_state(&self) -> Vec<u8> {
+        let _memory = self.lock_memory();
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.module.digest().to_le_bytes());
+
+        let globals = self.mutable_globals();
+        out.extend_from_slice(&(globals.len() as u32).to_le_bytes());
+        for global_idx in globals {
+            let value =
+                unsafe { *sys::fizzy_get_instance_global(self.instance.as_ptr(), global_idx) };
+            out.extend_from_slice(&global_idx.to_le_bytes());
+            let bits = value_bits(value, self.global_value_type(global_idx));
+            out.extend_from_slice(&bits.to_le_bytes());
+        }
+
+        let memory = match unsafe { self.checked_memory_slice(0, self.memory_size()) } {
+            Ok(memory) => memory,
+            Err(_) => {
+                out.push(0);
+                return out;
+            }
+        };
+        out.push(1);
+        out.extend_from_slice(&((memory.len() / PAGE_SIZE) as u32).to_le_bytes());
+        for page in memory.chunks(PAGE_SIZE) {
+            if page.iter().all(|byte| *byte == 0) {
+                out.push(ZERO_PAGE);
+            } else {
+                out.push(DATA_PAGE);
+                out.extend_from_slice(page);
+            }
+        }
+        out
+    }
+
+    /// Create an instance of `module` with the state serialized by [`Instance::serialize_state`].
+    ///
+    /// Fails if the state is of a different module, i.e. of a different digest, or of an unknown
+    /// version. The module is instantiated as usual, including executing its start function,
+    /// before the state is restored.
+    pub fn deserialize_state(module: &Module, bytes: &[u8]) -> Result<Instance, Error> {
+        Instance::deserialize_state_with_imports(module, ImportsBuilder::new(), bytes)
+    }
+
+    /// Create an instance of `module` like [`Instance::deserialize_state`], resolving imported
+    /// functions by name from `imports`.
+    pub fn deserialize_state_with_imports(
+        module: &Module,
+        imports: ImportsBuilder,
+        bytes: &[u8],
+    ) -> Result<Instance, Error> {
+        let mut reader = StateReader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(invalid_state("invalid magic"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(Error::Other(format!(
+                "unsupported instance state version {}",
+                version
+            )));
+        }
+        if reader.u64()? != module.digest() {
+            return Err(Error::Other(
+                "the instance state is of a different module".to_string(),
+            ));
+        }
+
+        let mut instance = module.instantiate_with_imports(imports)?;
+
+        let mutable_globals = instance.mutable_globals();
+        let global_count = reader.u32()?;
+        for _ in 0..global_count {
+            let global_idx = reader.u32()?;
+            let bits = reader.u64()?;
+            if !mutable_globals.contains(&global_idx) {
+                return Err(invalid_state("invalid global index"));
+            }
+            let value = value_from_bits(bits, instance.global_value_type(global_idx));
+            unsafe {
+                *sys::fizzy_get_instance_global(instance.instance.as_ptr(), global_idx) = value
+            };
+        }
+
+        let has_memory = reader.u8()? != 0;
+        if has_memory {
+            let pages = reader.u32()? as usize;
+            if unsafe { sys::fizzy_get_instance_memory_data(instance.instance.as_ptr()) }.is_null()
+            {
+                return Err(invalid_state("the instance has no memory"));
+            }
+            let current_pages = instance.memory_size() / PAGE_SIZE;
+            if pages < current_pages {
+                return Err(invalid_state("memory is smaller than its initial size"));
+            }
+            if pages > current_pages
+                && unsafe {
+                    sys::fizzy_grow_instance_memory(
+                        instance.instance.as_ptr(),
+                        (pages - current_pages) as u32,
+                    )
+                } == u32::MAX
+            {
+                return Err(Error::MemoryAllocationFailed(
+                    "memory growth failed".to_string(),
+                ));
+            }
+            instance.memory_growth.observe(pages as u32);
+            let size = pages
+                .checked_mul(PAGE_SIZE)
+                .ok_or_else(|| invalid_state("memory is too large"))?;
+            let memory = unsafe { instance.checked_memory_slice_mut(0, size)? };
+            for page in memory.chunks_mut(PAGE_SIZE) {
+                match reader.u8()? {
+                    ZERO_PAGE => page.iter_mut().for_each(|byte| *byte = 0),
+                    DATA_PAGE => page.copy_from_slice(reader.take(PAGE_SIZE)?),
+                    _ => return Err(invalid_state("invalid page kind")),
+                }
+            }
+        }
+        if !reader.bytes.is_empty() {
+            return Err(invalid_state("unexpected bytes at the end"));
+        }
+        Ok(instance)
+    }
+
+    /// The indices of the mutable globals defined by the module.
+    pub(crate) fn mutable_globals(&self) -> Vec<u32> {
+        let module = self.module.as_ptr();
+        let global_count = unsafe { sys::fizzy_get_global_count(module) };
+        let import_count = unsafe { sys::fizzy_get_import_count(module) };
+        let imported_globals = (0..import_count)
+            .filter(|import_idx| {
+                unsafe { sys::fizzy_get_import_description(module, *import_idx) }.kind
+                    == sys::FizzyExternalKind_FizzyExternalKindGlobal
+            })
+            .count() as u32;
+        (imported_globals..global_count)
+            .filter(
+                |global_idx| unsafe { sys::fizzy_get_global_type(module, *global_idx) }.is_mutable,
+            )
+            .collect()
+    }
+
+    fn global_value_type(&self, global_idx: u32) -> ValueType {
+        let global_type = unsafe { sys::fizzy_get_global_type(self.module.as_ptr(), global_idx) };
+        GlobalType::from_raw(&global_type).value_type
+    }
+}
+
+/// The bits of `value`, which do not depend on the byte order of the host, unlike the placement
+/// of the 32-bit members in the union.
+fn value_bits(value: Value, value_type: ValueType) -> u64 {
+    match value_type {
+        ValueType::I32 => u64::from(value.as_u32()),
+        ValueType::I64 => value.as_u64(),
+        ValueType::F32 => u64::from(value.as_f32().to_bits()),
+        ValueType::F64 => value.as_f64().to_bits(),
+        // The modules using the reference types fail to parse.
+        _ => unreachable!("unsupported value type"),
+    }
+}
+
+fn value_from_bits(bits: u64, value_type: ValueType) -> Value {
+    match value_type {
+        ValueType::I32 => Value::from(bits as u32),
+        ValueType::I64 => Value::from(bits),
+        ValueType::F32 => Value::from(f32::from_bits(bits as u32)),
+        ValueType::F64 => Value::from(f64::from_bits(bits)),
+        _ => unreachable!("unsupported value type"),
+    }
+}
+
+fn invalid_state(message: &str) -> Error {
+    Error::Other(format!("invalid instance state: {}", message))
+}
+
+struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(invalid_state("unexpected end"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, TypedValue};
+
+    fn counter_module() -> Module {
+        /* wat2wasm
+        (module
+          (memory 1 4)
+          (global $count (mut i32) (i32.const 0))
+          (global $total (mut i64) (i64.const 0))
+          (global $scale f64 (f64.const 2.5))
+          (data (i32.const 16) "fizzy")
+          ;; Grow the memory every 3 calls, and store the incremented count at the given address.
+          (func (export "bump") (param $address i32) (result i32)
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (global.set $total (i64.add (global.get $total) (i64.extend_i32_u (local.get $address))))
+            (if (i32.eqz (i32.rem_u (global.get $count) (i32.const 3)))
+              (then (drop (memory.grow (i32.const 1)))))
+            (i32.store (local.get $address) (global.get $count))
+            (global.get $count)
+          )
+          (func (export "total") (result i64) (global.get $total))
+          (func (export "size") (result i32) (memory.size))
+        )
+        */
+        let input = hex::decode("0061736d01000000010e0360017f017f6000017e6000017f0304030001020504010101040617037f0141000b7e0142000b7c004400000000000004400b0717030462756d70000005746f74616c00010473697a6500020a34032800230041016a240023012000ad7c24012300410370450440410140001a0b2000230036020023000b040023010b04003f000b0b0b010041100b0566697a7a79").unwrap();
+        parse(&input).unwrap()
+    }
+
+    fn run(instance: &mut Instance, address: u32) -> (u32, u64, u32, Vec<u8>) {
+        let count = instance
+            .execute("bump", &[TypedValue::U32(address)])
+            .unwrap()
+            .value()
+            .unwrap()
+            .as_u32()
+            .unwrap();
+        let total = instance.execute("total", &[]).unwrap().value().unwrap();
+        let size = instance.execute("size", &[]).unwrap().value().unwrap();
+        let mut memory = vec![0; instance.memory_size()];
+        instance.memory_get(0, &mut memory).unwrap();
+        (
+            count,
+            total.as_u64().unwrap(),
+            size.as_u32().unwrap(),
+            memory,
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let module = counter_module();
+        let mut original = module.instantiate().unwrap();
+        for address in &[0, 20, 65540] {
+            run(&mut original, *address);
+        }
+        assert_eq!(original.memory_size(), 2 * PAGE_SIZE);
+
+        let state = original.serialize_state();
+        assert_eq!(&state[..4], b"FZST");
+        // The second page has only the count at 65540.
+        assert!(state.len() < 2 * PAGE_SIZE + 100);
+
+        // In a different module, as if in another process.
+        let mut revived = Instance::deserialize_state(&counter_module(), &state).unwrap();
+        assert_eq!(revived.memory_size(), 2 * PAGE_SIZE);
+        assert_eq!(revived.serialize_state(), state);
+        for address in &[8, 24, 131076, 65536] {
+            assert_eq!(run(&mut revived, *address), run(&mut original, *address));
+        }
+        assert_eq!(revived.serialize_state(), original.serialize_state());
+    }
+
+    #[test]
+    fn global_bits() {
+        let module = counter_module();
+        let mut instance = module.instantiate().unwrap();
+        run(&mut instance, 0x0102);
+        let state = instance.serialize_state();
+        // The globals follow the header, in the same bytes on all hosts.
+        assert_eq!(
+            state[16..44],
+            [
+                2, 0, 0, 0, // count
+                0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // $count
+                1, 0, 0, 0, 0x02, 0x01, 0, 0, 0, 0, 0, 0, // $total
+            ]
+        );
+
+        assert_eq!(
+            value_bits(Value::from(0x8000_0001u32), ValueType::I32),
+            0x8000_0001
+        );
+        assert_eq!(
+            value_bits(Value::from(-1.5f32), ValueType::F32),
+            0xbfc0_0000
+        );
+        assert_eq!(
+            value_bits(Value::from(-1.5f64), ValueType::F64),
+            0xbff8_0000_0000_0000
+        );
+        assert_eq!(
+            value_from_bits(0xdead_beef_8000_0001, ValueType::I32).as_u32(),
+            0x8000_0001
+        );
+        assert_eq!(value_from_bits(0xbfc0_0000, ValueType::F32).as_f32(), -1.5);
+        assert_eq!(
+            value_from_bits(0x0102_0304_0506_0708, ValueType::I64).as_u64(),
+            0x0102_0304_0506_0708
+        );
+    }
+
+    #[test]
+    fn zero_pages() {
+        let module = counter_module();
+        let mut instance = module.instantiate().unwrap();
+        // Clear the data segment.
+        instance.memory_set(16, &[0; 5]).unwrap();
+        let state = instance.serialize_state();
+        // Magic, version, digest, 2 globals, memory of 1 page.
+        assert_eq!(state.len(), 4 + 4 + 8 + 4 + 2 * 12 + 1 + 4 + 1);
+
+        // Pages of zeros are cleared, not kept from the data segments.
+        let revived = Instance::deserialize_state(&module, &state).unwrap();
+        let mut data = [1; 5];
+        revived.memory_get(16, &mut data).unwrap();
+        assert_eq!(data, [0; 5]);
+    }
+
+    #[test]
+    fn incompatible() {
+        let module = counter_module();
+        let state = module.instantiate().unwrap().serialize_state();
+
+        /* wat2wasm
+        (module (memory 1))
+        */
+        let input = hex::decode("0061736d010000000503010001").unwrap();
+        let other = parse(&input).unwrap();
+        assert_ne!(other.digest(), module.digest());
+        assert_eq!(
+            Instance::deserialize_state(&other, &state).err(),
+            Some(Error::Other(
+                "the instance state is of a different module".to_string()
+            ))
+        );
+
+        let mut future = state.clone();
+        future[4] = 2;
+        assert_eq!(
+            Instance::deserialize_state(&module, &future).err(),
+            Some(Error::Other(
+                "unsupported instance state version 2".to_string()
+            ))
+        );
+        assert_eq!(
+            Instance::deserialize_state(&module, &state[..state.len() - 1]).err(),
+            Some(Error::Other(
+                "invalid instance state: unexpected end".to_string()
+            ))
+        );
+        assert_eq!(
+            Instance::deserialize_state(&module, b"FZSS").err(),
+            Some(Error::Other(
+                "invalid instance state: invalid magic".to_string()
+            ))
+        );
+    }
+}
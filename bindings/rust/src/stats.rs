@@ -0,0 +1,121 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The statistics of executions, see
+//! [`ExecutionOptions::collect_stats`](crate::ExecutionOptions::collect_stats).
+//!
+//! The depth of calls is counted by the hooks of `fizzy_execute_traced`, like the trace, therefore
+//! the executions without statistics are not slowed down. The height of the operand stack is not
+//! observable by the hooks, therefore it is not reported.
+
+/// The depth of the calls of an execution.
+#[derive(Default)]
+pub(crate) struct CallDepth {
+    depth: u32,
+    /// The maximum depth reached so far.
+    max: u32,
+}
+
+impl CallDepth {
+    pub(crate) fn enter(&mut self) {
+        self.depth += 1;
+        self.max = self.max.max(self.depth);
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub(crate) fn max(&self) -> u32 {
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, Caller, ExecutionOptions, ImportsBuilder, Instance, Trap, TypedValue};
+
+    fn instantiate() -> Instance {
+        /* wat2wasm
+        (module
+          (func $host (import "env" "host"))
+          (func $rec (export "rec") (param i32)
+            (if (local.get 0) (then (call $rec (i32.sub (local.get 0) (i32.const 1))))))
+          (func (export "host_at") (param i32)
+            (if (local.get 0)
+              (then (call 2 (i32.sub (local.get 0) (i32.const 1))))
+              (else (call $host))))
+        )
+        */
+        let input = hex::decode("0061736d0100000001080260000060017f00020c0103656e7604686f73740000030302010107110203726563000107686f73745f617400020a22020e0020000440200041016b10010b0b110020000440200041016b10020510000b0b").unwrap();
+        let mut imports = ImportsBuilder::new();
+        imports.func("env", "host", |_: &mut Caller| -> Result<(), Trap> {
+            Ok(())
+        });
+        parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn max_call_depth() {
+        let mut instance = instantiate();
+        let options = ExecutionOptions::new().collect_stats(true);
+        // rec(n) stacks up n + 1 calls.
+        for n in &[0, 1, 5, 100] {
+            let outcome = instance
+                .execute_with_options("rec", &[TypedValue::U32(*n)], &options)
+                .unwrap();
+            assert!(!outcome.trapped());
+            assert_eq!(outcome.max_call_depth_reached(), Some(n + 1));
+        }
+        // The imported functions are counted.
+        let outcome = instance
+            .execute_with_options("host_at", &[TypedValue::U32(3)], &options)
+            .unwrap();
+        assert_eq!(outcome.max_call_depth_reached(), Some(5));
+
+        // The reached depth is the lowest limit allowing the execution.
+        let outcome = instance
+            .execute_with_options(
+                "rec",
+                &[TypedValue::U32(9)],
+                &options.clone().max_call_depth(10),
+            )
+            .unwrap();
+        assert!(!outcome.trapped());
+        assert_eq!(outcome.max_call_depth_reached(), Some(10));
+        // The call exceeding the limit is counted.
+        let outcome = instance
+            .execute_with_options(
+                "rec",
+                &[TypedValue::U32(9)],
+                &options.clone().max_call_depth(9),
+            )
+            .unwrap();
+        assert!(outcome.call_depth_exceeded());
+        assert_eq!(outcome.max_call_depth_reached(), Some(10));
+
+        let outcome = instance
+            .execute_with_options("rec", &[TypedValue::U32(3)], &ExecutionOptions::new())
+            .unwrap();
+        assert_eq!(outcome.max_call_depth_reached(), None);
+    }
+
+    #[test]
+    fn with_trace_and_coverage() {
+        let mut instance = instantiate();
+        let coverage = crate::CoverageMap::new(&instance.module);
+        let options = ExecutionOptions::new()
+            .collect_stats(true)
+            .coverage(&coverage)
+            .trace(crate::TraceSink::Writer(Box::new(std::io::sink())));
+        let outcome = instance
+            .execute_with_options("host_at", &[TypedValue::U32(2)], &options)
+            .unwrap();
+        assert_eq!(outcome.max_call_depth_reached(), Some(4));
+        assert_eq!(coverage.covered(), [0, 2]);
+    }
+}
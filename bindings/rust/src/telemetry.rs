@@ -0,0 +1,295 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `tracing` spans of the API calls, which are no-ops without the `tracing` feature.
+//!
+//! The fields are numbers and borrowed strings, and the durations are measured only if the span
+//! is enabled, so that nothing is allocated for disabled subscribers.
+
+#[cfg(feature = "tracing")]
+mod spans {
+    use crate::imports::TrapSlot;
+    use crate::{Error, ExecutionResult};
+
+    use std::time::Instant;
+    use tracing::field::{display, Empty};
+
+    /// The span of an API call, with its duration recorded when dropped.
+    pub(crate) struct Call {
+        span: tracing::span::EnteredSpan,
+        start: Option<Instant>,
+    }
+
+    impl Call {
+        fn new(span: tracing::Span) -> Self {
+            let start = if span.is_disabled() {
+                None
+            } else {
+                Some(Instant::now())
+            };
+            Call {
+                span: span.entered(),
+                start,
+            }
+        }
+
+        pub(crate) fn parse(size: usize) -> Self {
+            Call::new(tracing::info_span!(
+                "parse",
+                size = size,
+                duration_us = Empty,
+                error = Empty
+            ))
+        }
+
+        pub(crate) fn validate(size: usize) -> Self {
+            Call::new(tracing::info_span!(
+                "validate",
+                size = size,
+                duration_us = Empty,
+                error = Empty
+            ))
+        }
+
+        pub(crate) fn instantiate(imports: u32, host_functions: usize) -> Self {
+            Call::new(tracing::info_span!(
+                "instantiate",
+                imports = imports,
+                host_functions = host_functions,
+                memory_pages = Empty,
+                duration_us = Empty,
+                error = Empty
+            ))
+        }
+
+        pub(crate) fn execute(function: &str, args: usize) -> Self {
+            Call::new(tracing::info_span!(
+                "execute",
+                function = function,
+                index = Empty,
+                args = args,
+                result = Empty,
+                duration_us = Empty,
+                error = Empty
+            ))
+        }
+
+        pub(crate) fn failed(&self, err: &Error) {
+            self.span.record("error", &display(err));
+        }
+
+        pub(crate) fn instantiated(&self, memory_size: usize) {
+            self.span.record("memory_pages", &(memory_size / 65536));
+        }
+
+        pub(crate) fn function_index(&self, func_idx: u32) {
+            self.span.record("index", &func_idx);
+        }
+
+        /// Record the kind of the result, and a trap event if the function has trapped.
+        pub(crate) fn executed(
+            &self,
+            function: &str,
+            result: &ExecutionResult,
+            host_trap: &TrapSlot,
+        ) {
+            if !result.trapped() {
+                let kind = if result.value().is_some() {
+                    "value"
+                } else {
+                    "void"
+                };
+                self.span.record("result", &kind);
+                return;
+            }
+            self.span.record("result", &"trap");
+            host_trap.with(|trap| match trap {
+                None => tracing::warn!(function = function, kind = "wasm", "trap"),
+                Some(trap) => tracing::warn!(
+                    function = function,
+                    kind = "host",
+                    reason = trap.message(),
+                    "trap"
+                ),
+            });
+        }
+    }
+
+    impl Drop for Call {
+        fn drop(&mut self) {
+            if let Some(start) = self.start {
+                self.span
+                    .record("duration_us", &(start.elapsed().as_micros() as u64));
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod spans {
+    use crate::imports::TrapSlot;
+    use crate::{Error, ExecutionResult};
+
+    pub(crate) struct Call;
+
+    impl Call {
+        pub(crate) fn parse(_size: usize) -> Self {
+            Call
+        }
+
+        pub(crate) fn validate(_size: usize) -> Self {
+            Call
+        }
+
+        pub(crate) fn instantiate(_imports: u32, _host_functions: usize) -> Self {
+            Call
+        }
+
+        pub(crate) fn execute(_function: &str, _args: usize) -> Self {
+            Call
+        }
+
+        pub(crate) fn failed(&self, _err: &Error) {}
+
+        pub(crate) fn instantiated(&self, _memory_size: usize) {}
+
+        pub(crate) fn function_index(&self, _func_idx: u32) {}
+
+        pub(crate) fn executed(
+            &self,
+            _function: &str,
+            _result: &ExecutionResult,
+            _host_trap: &TrapSlot,
+        ) {
+        }
+    }
+}
+
+pub(crate) use spans::Call;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{parse, validate, Caller, ImportsBuilder, Trap, TypedValue};
+
+    use std::fmt::{Debug, Write};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Records the spans and the events as lines of their fields, without the durations.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "duration_us" {
+                write!(self.0, " duration_us").unwrap();
+            } else {
+                write!(self.0, " {}={:?}", field.name(), value).unwrap();
+            }
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            let mut fields = Fields(format!("new {}:", attrs.metadata().name()));
+            attrs.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields(format!("record {}:", ctx.span(id).unwrap().name()));
+            values.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let parent = ctx.lookup_current().unwrap();
+            let mut fields = Fields(format!(
+                "{} in {}:",
+                event.metadata().level(),
+                parent.name()
+            ));
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+
+    #[test]
+    fn spans() {
+        /* wat2wasm
+        (module
+          (func $fail (import "env" "fail"))
+          (memory 2)
+          (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+          (func (export "trap") (unreachable))
+          (func (export "fail") (call $fail))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260000060027f7f017f020c0103656e76046661696c0000030403010000050301000207150303616464000104747261700002046661696c00030a12030700200020016a0b0300000b040010000b").unwrap();
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(validate(&input).is_ok());
+            assert!(validate(&input[..8]).is_ok());
+            assert!(validate(&input[..9]).is_err());
+            let module = parse(&input).unwrap();
+            let mut imports = ImportsBuilder::new();
+            imports.func("env", "fail", |_: &mut Caller| -> Result<(), Trap> {
+                Err(Trap::new("failed"))
+            });
+            let mut instance = module.instantiate_with_imports(imports).unwrap();
+            let result = instance
+                .execute("add", &[TypedValue::U32(1), TypedValue::U32(2)])
+                .unwrap();
+            assert_eq!(result.value(), Some(TypedValue::U32(3)));
+            assert!(instance.execute("trap", &[]).unwrap().trapped());
+            assert!(instance.execute("fail", &[]).unwrap().trapped());
+            assert_eq!(
+                instance.execute("none", &[]).err(),
+                Some(crate::Error::FunctionNotFound)
+            );
+        });
+
+        let size = input.len();
+        let expected = vec![
+            format!("new validate: size={}", size),
+            "record validate: duration_us".to_string(),
+            "new validate: size=8".to_string(),
+            "record validate: duration_us".to_string(),
+            "new validate: size=9".to_string(),
+            "record validate: error=unexpected EOF".to_string(),
+            "record validate: duration_us".to_string(),
+            format!("new parse: size={}", size),
+            "record parse: duration_us".to_string(),
+            "new instantiate: imports=1 host_functions=1".to_string(),
+            "record instantiate: memory_pages=2".to_string(),
+            "record instantiate: duration_us".to_string(),
+            "new execute: function=\"add\" args=2".to_string(),
+            "record execute: index=1".to_string(),
+            "record execute: result=\"value\"".to_string(),
+            "record execute: duration_us".to_string(),
+            "new execute: function=\"trap\" args=0".to_string(),
+            "record execute: index=2".to_string(),
+            "record execute: result=\"trap\"".to_string(),
+            "WARN in execute: message=trap function=\"trap\" kind=\"wasm\"".to_string(),
+            "record execute: duration_us".to_string(),
+            "new execute: function=\"fail\" args=0".to_string(),
+            "record execute: index=3".to_string(),
+            "record execute: result=\"trap\"".to_string(),
+            "WARN in execute: message=trap function=\"fail\" kind=\"host\" reason=\"failed\""
+                .to_string(),
+            "record execute: duration_us".to_string(),
+            "new execute: function=\"none\" args=0".to_string(),
+            "record execute: error=function not found".to_string(),
+            "record execute: duration_us".to_string(),
+        ];
+        assert_eq!(*recorder.0.lock().unwrap(), expected);
+    }
+}
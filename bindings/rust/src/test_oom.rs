@@ -0,0 +1,245 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Simulating allocation failures of the engine, for testing their handling by the programs
+//! embedding it, and the unexpected exceptions of the engine, which are reported as errors
+//! instead of unwinding into Rust.
+//!
+//! The allocation functions of the C++ engine are replaced by those failing on demand, therefore
+//! the `test-oom` feature must not be enabled in production. The allocations of Rust code are not
+//! affected.
+//!
+//! ```ignore
+//! let _failures = fizzy::test_oom::fail_allocations_after(0);
+//! assert!(matches!(fizzy::parse(&wasm), Err(fizzy::Error::MemoryAllocationFailed(_))));
+//! ```
+
+use crate::sys;
+
+/// The allocation failures of the current thread, which end when this is dropped.
+#[must_use = "the allocations do not fail once this is dropped"]
+pub struct AllocationFailures(());
+
+impl Drop for AllocationFailures {
+    fn drop(&mut self) {
+        unsafe { sys::fizzy_test_fail_allocations(-1) }
+    }
+}
+
+/// Make the allocations of the engine on the current thread fail after `count` further
+/// allocations, until the returned value is dropped.
+pub fn fail_allocations_after(count: u32) -> AllocationFailures {
+    unsafe { sys::fizzy_test_fail_allocations(count.into()) }
+    AllocationFailures(())
+}
+
+/// Make the allocations of the engine on the current thread throw an exception of a type unknown
+/// to the engine after `count` further allocations, until the returned value is dropped.
+///
+/// The operations failing then return [`Error::Other`](crate::Error::Other), or the same errors
+/// as for failing allocations where the engine does not report the cause.
+pub fn throw_after(count: u32) -> AllocationFailures {
+    unsafe { sys::fizzy_test_throw_from_allocations(count.into()) }
+    AllocationFailures(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ffi, parse, Error, InstantiateOptions, Module, TypedValue};
+
+    /// The largest number of allocations after which any of the tested operations fails.
+    const MAX_ALLOCATIONS: u32 = 100;
+
+    fn is_allocation_failure<T>(result: &Result<T, Error>) -> bool {
+        matches!(result, Err(Error::MemoryAllocationFailed(_)))
+    }
+
+    fn module() -> Module {
+        /* wat2wasm
+        (module
+          (memory 1)
+          (data (i32.const 0) "data")
+          (func (export "grow") (result i32) (memory.grow (i32.const 1)))
+          (func (export "locals") (param i32) (result i32)
+            (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+            (local.get 0))
+        )
+        */
+        let input = hex::decode("0061736d01000000010a026000017f60017f017f030302000105030100010711020467726f770000066c6f63616c7300010a0f020600410140000b0601147e20000b0b0a010041000b0464617461").unwrap();
+        parse(&input).unwrap()
+    }
+
+    #[test]
+    fn parse_failures() {
+        let input = module().bytes().to_vec();
+        let mut failed = 0;
+        for count in 0..MAX_ALLOCATIONS {
+            let _failures = fail_allocations_after(count);
+            let result = parse(&input);
+            if result.is_ok() {
+                break;
+            }
+            assert!(is_allocation_failure(&result));
+            failed += 1;
+        }
+        assert!(failed > 0 && failed < MAX_ALLOCATIONS);
+        assert_eq!(
+            {
+                let _failures = fail_allocations_after(0);
+                crate::validate(&input)
+            },
+            Err(Error::MemoryAllocationFailed(
+                "memory allocation failed".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn unknown_exceptions() {
+        let module = module();
+        let input = module.bytes().to_vec();
+        let unknown = Err(Error::Other("unknown error".to_string()));
+        let mut failed = 0;
+        for count in 0..MAX_ALLOCATIONS {
+            let result = {
+                let _failures = throw_after(count);
+                parse(&input)
+            };
+            if result.is_ok() {
+                break;
+            }
+            assert_eq!(result.err(), unknown.clone().err());
+            failed += 1;
+        }
+        assert!(failed > 0 && failed < MAX_ALLOCATIONS);
+        assert_eq!(
+            {
+                let _failures = throw_after(0);
+                crate::validate(&input)
+            },
+            unknown
+        );
+
+        // The module copy fails without the cause being reported.
+        failed = 0;
+        for count in 0..MAX_ALLOCATIONS {
+            let result = {
+                let _failures = throw_after(count);
+                module.instantiate()
+            };
+            match result {
+                Ok(_) => break,
+                Err(Error::Other(message)) => assert_eq!(message, "unknown error"),
+                Err(err) => assert_eq!(
+                    err,
+                    Error::MemoryAllocationFailed("module copy failed".to_string())
+                ),
+            }
+            failed += 1;
+        }
+        assert!(failed > 0 && failed < MAX_ALLOCATIONS);
+
+        let instance = ffi::RawInstance::resolve_instantiate(
+            &module,
+            &[],
+            None,
+            None,
+            &[],
+            ffi::DEFAULT_MEMORY_PAGES_LIMIT,
+        )
+        .unwrap();
+        assert!({
+            let _failures = throw_after(0);
+            instance.find_exported_function("grow").is_none()
+        });
+        assert!(instance.find_exported_function("grow").is_some());
+
+        // Nothing is left inconsistent by the failures.
+        let mut instance = module.instantiate().unwrap();
+        let result = {
+            let _failures = throw_after(0);
+            instance.execute("locals", &[TypedValue::U32(7)]).unwrap()
+        };
+        assert!(result.trapped());
+        let result = instance.execute("locals", &[TypedValue::U32(7)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(7)));
+    }
+
+    #[test]
+    fn clone_failures() {
+        let module = module();
+        let result = {
+            let _failures = fail_allocations_after(0);
+            ffi::RawInstance::resolve_instantiate(
+                &module,
+                &[],
+                None,
+                None,
+                &[],
+                ffi::DEFAULT_MEMORY_PAGES_LIMIT,
+            )
+        };
+        assert_eq!(
+            result.err(),
+            Some(Error::MemoryAllocationFailed(
+                "module copy failed".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn instantiate_failures() {
+        let module = module();
+        let mut failed = 0;
+        for count in 0..MAX_ALLOCATIONS {
+            let _failures = fail_allocations_after(count);
+            let result = module.instantiate();
+            if result.is_ok() {
+                break;
+            }
+            assert!(is_allocation_failure(&result));
+            failed += 1;
+        }
+        assert!(failed > 0 && failed < MAX_ALLOCATIONS);
+
+        let options = InstantiateOptions::new().preallocate_max_memory(true);
+        let result = {
+            let _failures = fail_allocations_after(0);
+            module.instantiate_with(&options)
+        };
+        assert!(is_allocation_failure(&result));
+    }
+
+    #[test]
+    fn grow_failure() {
+        let mut instance = module().instantiate().unwrap();
+        let result = {
+            let _failures = fail_allocations_after(0);
+            instance.execute("grow", &[]).unwrap()
+        };
+        // The failed growth is reported to the module, as for exceeding the limit.
+        assert_eq!(result.value(), Some(TypedValue::U32(u32::MAX)));
+        assert_eq!(instance.memory_size(), 65536);
+        let mut data = [0; 4];
+        assert!(instance.memory_get(0, &mut data).is_ok());
+        assert_eq!(&data, b"data");
+
+        let result = instance.execute("grow", &[]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+    }
+
+    #[test]
+    fn execute_failure() {
+        let mut instance = module().instantiate().unwrap();
+        let result = {
+            let _failures = fail_allocations_after(0);
+            instance.execute("locals", &[TypedValue::U32(7)]).unwrap()
+        };
+        // The locals do not fit in the stack preallocated by the engine.
+        assert!(result.trapped());
+        let result = instance.execute("locals", &[TypedValue::U32(7)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(7)));
+    }
+}
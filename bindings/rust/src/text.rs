@@ -0,0 +1,109 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for the WebAssembly text format.
+
+use crate::{parse, Error, Module, TypedExecutionResult, TypedValue};
+
+/// Parse and validate a module in the WebAssembly text format.
+///
+/// Syntax errors are reported as [`Error::TextFormat`], including the line and the column.
+///
+/// ```
+/// let module = fizzy::parse_wat(
+///     r#"(module
+///       (func (export "sum") (param i32 i32) (result i32)
+///         (i32.add (local.get 0) (local.get 1))))"#,
+/// )
+/// .expect("parsing failed");
+/// ```
+pub fn parse_wat(text: &str) -> Result<Module, Error> {
+    let binary = wat::parse_str(text).map_err(|err| Error::TextFormat(err.to_string()))?;
+    parse(&binary)
+}
+
+/// Parse and instantiate a module in the WebAssembly text format, and execute its exported function `name`.
+///
+/// The module cannot have imports.
+pub fn run_wat(text: &str, name: &str, args: &[TypedValue]) -> Result<TypedExecutionResult, Error> {
+    parse_wat(text)?.instantiate()?.execute(name, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExternalKind;
+
+    #[test]
+    fn parse_wat_errors() {
+        match parse_wat("(module\n  (func (result i32) (i32.const)))").err() {
+            Some(Error::TextFormat(message)) => assert!(
+                message.contains("<anon>:2:"),
+                "no position in message: {}",
+                message
+            ),
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert_eq!(
+            parse_wat("(module (func (result i32)))").err().unwrap(),
+            Error::InvalidModule("stack underflow".to_string())
+        );
+    }
+
+    #[test]
+    fn run_wat_sum() {
+        let result = run_wat(
+            r#"(module
+              (func (export "sum") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))))"#,
+            "sum",
+            &[TypedValue::U32(42), TypedValue::U32(24)],
+        )
+        .unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(66)));
+        assert_eq!(
+            run_wat("(module)", "sum", &[]).err().unwrap(),
+            Error::FunctionNotFound
+        );
+    }
+
+    #[test]
+    fn instantiate_wasm_missing_import() {
+        let module = parse_wat(r#"(module (memory (import "mod" "m") 1))"#);
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert_eq!(
+            instance.err().unwrap(),
+            Error::MissingImport {
+                module: "mod".to_string(),
+                name: "m".to_string(),
+                kind: ExternalKind::Memory,
+            }
+        );
+    }
+
+    #[test]
+    fn find_exported_function_index() {
+        let module = parse_wat(
+            r#"(module
+              (func $f (export "foo") (result i32) (i32.const 42))
+              (global (export "g1") i32 (i32.const 0))
+              (table (export "tab") 0 funcref)
+              (memory (export "mem") 1 2))"#,
+        );
+        assert!(module.is_ok());
+        let instance = module.unwrap().instantiate();
+        assert!(instance.is_ok());
+        let instance = instance.unwrap();
+
+        let func_idx = instance.find_exported_function_index("foo");
+        assert!(func_idx.is_some());
+        assert_eq!(func_idx.unwrap(), 0);
+
+        assert!(instance.find_exported_function_index("bar").is_none());
+        assert!(instance.find_exported_function_index("g1").is_none());
+        assert!(instance.find_exported_function_index("tab").is_none());
+        assert!(instance.find_exported_function_index("mem").is_none());
+    }
+}
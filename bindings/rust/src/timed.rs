@@ -0,0 +1,173 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The stages of loading and running a module, with the time spent in each of them.
+//!
+//! The durations are measured with the monotonic clock of [`Instant`].
+//!
+//! ```
+//! // This wasm binary exports a single main() -> u32 function returning 42.
+//! let wasm = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+//!     0x03, 0x02, 0x01, 0x00, 0x07, 0x08, 0x01, 0x04, 0x6d, 0x61, 0x69, 0x6e, 0x00, 0x00, 0x0a,
+//!     0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b,
+//! ];
+//! let (value, timings) = fizzy::timed::run(&wasm, "main", &[]).expect("running failed");
+//! assert_eq!(value, Some(fizzy::TypedValue::U32(42)));
+//! println!(
+//!     "parse: {:?}, instantiate: {:?}, execute: {:?}",
+//!     timings.parse, timings.instantiate, timings.execute
+//! );
+//! ```
+
+use crate::{Error, Instance, Module, TrapInfo, TypedExecutionResult, TypedValue};
+
+use std::time::{Duration, Instant};
+
+/// The time spent in each stage of loading and running a module.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// The time of parsing and validating the module.
+    pub parse: Duration,
+    /// The time of instantiating the module, including its start function.
+    pub instantiate: Duration,
+    /// The time of executing the function.
+    pub execute: Duration,
+}
+
+impl Timings {
+    /// The time of all stages.
+    pub fn total(&self) -> Duration {
+        self.parse + self.instantiate + self.execute
+    }
+}
+
+/// Parse, instantiate and execute the function `name` of the module of `bytes`, like
+/// [`crate::parse`], [`Module::instantiate`] and [`Instance::execute`].
+///
+/// Traps are reported as [`Error::Trapped`].
+pub fn run(
+    bytes: &[u8],
+    name: &str,
+    args: &[TypedValue],
+) -> Result<(Option<TypedValue>, Timings), Error> {
+    let (module, parsed) = parse(bytes)?;
+    let (mut instance, instantiated) = instantiate(&module)?;
+    let (result, executed) = execute(&mut instance, name, args)?;
+    if result.trapped() {
+        return Err(Error::Trapped(TrapInfo::new(
+            name,
+            instance.take_host_trap(),
+        )));
+    }
+    let timings = Timings {
+        parse: parsed.parse,
+        instantiate: instantiated.instantiate,
+        execute: executed.execute,
+    };
+    Ok((result.value(), timings))
+}
+
+/// Parse and validate the module of `bytes` like [`crate::parse`], with only the time of
+/// parsing in the timings.
+pub fn parse(bytes: &[u8]) -> Result<(Module, Timings), Error> {
+    let start = Instant::now();
+    let module = crate::parse(&bytes)?;
+    let timings = Timings {
+        parse: start.elapsed(),
+        ..Timings::default()
+    };
+    Ok((module, timings))
+}
+
+/// Instantiate `module` like [`Module::instantiate`], with only the time of instantiation in
+/// the timings.
+pub fn instantiate(module: &Module) -> Result<(Instance, Timings), Error> {
+    let start = Instant::now();
+    let instance = module.instantiate()?;
+    let timings = Timings {
+        instantiate: start.elapsed(),
+        ..Timings::default()
+    };
+    Ok((instance, timings))
+}
+
+/// Execute the function `name` like [`Instance::execute`], with only the time of execution in
+/// the timings.
+pub fn execute(
+    instance: &mut Instance,
+    name: &str,
+    args: &[TypedValue],
+) -> Result<(TypedExecutionResult, Timings), Error> {
+    let start = Instant::now();
+    let result = instance.execute(name, args)?;
+    let timings = Timings {
+        execute: start.elapsed(),
+        ..Timings::default()
+    };
+    Ok((result, timings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_timings() {
+        /* wat2wasm
+        (module
+          (func (export "count") (param i32) (result i32) (local i32)
+            (loop $continue
+              (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+              (br_if $continue (i32.ne (local.get 1) (local.get 0))))
+            (local.get 1))
+          (func (export "trap") (unreachable))
+        )
+        */
+        let input = hex::decode("0061736d0100000001090260017f017f600000030302000107100205636f756e740000047472617000010a1d021701017f0340200141016a210120012000470d000b20010b0300000b").unwrap();
+        let (value, timings) = run(&input, "count", &[TypedValue::U32(1_000_000)]).unwrap();
+        assert_eq!(value, Some(TypedValue::U32(1_000_000)));
+
+        let zero = Duration::default();
+        assert!(timings.parse > zero);
+        assert!(timings.instantiate > zero);
+        // The execution of the loop is much longer than parsing the module.
+        assert!(timings.execute > timings.parse);
+        assert_eq!(
+            timings.total(),
+            timings.parse + timings.instantiate + timings.execute
+        );
+
+        match run(&input, "trap", &[]) {
+            Err(Error::Trapped(info)) => assert_eq!(info.function(), Some("trap")),
+            _ => panic!("expected a trap"),
+        }
+        assert_eq!(
+            run(&input[..9], "count", &[]).err(),
+            Some(Error::MalformedModule("unexpected EOF".to_string()))
+        );
+    }
+
+    #[test]
+    fn stages() {
+        /* wat2wasm
+        (module (func (export "main")))
+        */
+        let input =
+            hex::decode("0061736d0100000001040160000003020100070801046d61696e00000a040102000b")
+                .unwrap();
+        let (module, parsed) = parse(&input).unwrap();
+        assert_eq!(parsed.instantiate, Duration::default());
+        assert_eq!(parsed.execute, Duration::default());
+        assert_eq!(parsed.total(), parsed.parse);
+
+        let (mut instance, instantiated) = instantiate(&module).unwrap();
+        assert_eq!(instantiated.parse, Duration::default());
+        assert_eq!(instantiated.total(), instantiated.instantiate);
+
+        let (result, executed) = execute(&mut instance, "main", &[]).unwrap();
+        assert!(!result.trapped());
+        assert_eq!(executed.total(), executed.execute);
+    }
+}
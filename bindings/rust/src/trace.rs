@@ -0,0 +1,459 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The trace of the calls of functions during an execution, see
+//! [`ExecutionOptions::trace`](crate::ExecutionOptions::trace).
+//!
+//! The engine calls the hooks only when executing with `fizzy_execute_traced`, therefore
+//! the executions without a sink are not slowed down.
+
+use crate::coverage::{self, CoverageMap};
+use crate::raw::FunctionTypeExt;
+use crate::stats::CallDepth;
+use crate::{
+    sys, Error, ExecutionResult, ExternalKind, FunctionType, Instance, Trap, Value, ValueType,
+};
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// An event of a traced execution.
+#[non_exhaustive]
+pub enum TraceEvent<'a> {
+    /// A function is called, including the executed function itself and imported functions.
+    Enter {
+        /// The index of the function in the module.
+        index: u32,
+        /// The name of the function in the name section of the module, or otherwise its export name.
+        /// The functions of other instances called via imported tables are not named.
+        name: Option<&'a str>,
+        /// The arguments of the function.
+        args: &'a [Value],
+    },
+    /// A function has returned or trapped.
+    Leave {
+        /// The index of the function in the module.
+        index: u32,
+        /// The return value of the function, if it has returned one.
+        result: Option<Value>,
+        /// True if the function has trapped.
+        trapped: bool,
+    },
+}
+
+/// The receiver of the events of the executions with
+/// [`ExecutionOptions::trace`](crate::ExecutionOptions::trace).
+pub enum TraceSink {
+    /// Write the calls as lines of text indented by their depth, e.g. `fac(1)` with the arguments
+    /// and `-> 1` with the result, `-> void` or `-> trap`. The functions without names are written
+    /// as `func[index]`.
+    ///
+    /// The execution fails with [`Error::Io`] if writing fails, and the rest is not traced then.
+    Writer(Box<dyn Write + Send>),
+    /// Call the closure with each event.
+    Callback(Box<dyn FnMut(&TraceEvent) + Send>),
+}
+
+impl fmt::Debug for TraceSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceSink::Writer(_) => f.write_str("TraceSink::Writer"),
+            TraceSink::Callback(_) => f.write_str("TraceSink::Callback"),
+        }
+    }
+}
+
+/// The context of the hooks during a traced execution.
+struct Tracer<'a> {
+    sink: &'a mut TraceSink,
+    coverage: Option<&'a CoverageMap>,
+    call_depth: Option<&'a mut CallDepth>,
+    instance: &'a Instance,
+    /// The export names of functions, used for the functions without names in the name section.
+    export_names: HashMap<u32, String>,
+    depth: usize,
+    /// The first error of writing the trace, after which nothing else is written.
+    error: Option<std::io::Error>,
+    /// The panic of the sink, resumed after the execution. The sink is not called after it.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl Tracer<'_> {
+    /// The function type of `func_idx` in the module of `instance`.
+    unsafe fn function_type(instance: *mut sys::FizzyInstance, func_idx: u32) -> FunctionType {
+        let module = sys::fizzy_get_instance_module(instance);
+        FunctionType::from_raw(&sys::fizzy_get_function_type(module, func_idx))
+    }
+
+    fn name(&self, instance: *mut sys::FizzyInstance, func_idx: u32) -> Option<&str> {
+        if instance != self.instance.instance.as_ptr() {
+            return None;
+        }
+        self.instance
+            .module
+            .function_name(func_idx)
+            .or_else(|| self.export_names.get(&func_idx).map(String::as_str))
+    }
+
+    /// Call `f` unless the sink has panicked, catching its panic.
+    fn guard(&mut self, f: impl FnOnce(&mut Self)) {
+        if self.panic.is_some() {
+            return;
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            self.panic = Some(payload);
+        }
+    }
+
+    fn write(&mut self, f: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) {
+        if self.error.is_some() {
+            return;
+        }
+        if let TraceSink::Writer(out) = self.sink {
+            if let Err(err) = f(out.as_mut()) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    unsafe fn enter(&mut self, instance: *mut sys::FizzyInstance, func_idx: u32, args: &[Value]) {
+        let depth = self.depth;
+        self.depth += 1;
+        let name = self.name(instance, func_idx).map(str::to_string);
+        if let TraceSink::Callback(callback) = self.sink {
+            callback(&TraceEvent::Enter {
+                index: func_idx,
+                name: name.as_deref(),
+                args,
+            });
+            return;
+        }
+        let func_type = Self::function_type(instance, func_idx);
+        self.write(|out| {
+            write!(out, "{:1$}", "", depth * 2)?;
+            match &name {
+                Some(name) => write!(out, "{}(", name)?,
+                None => write!(out, "func[{}](", func_idx)?,
+            }
+            for (i, (arg, value_type)) in args.iter().zip(&func_type.inputs).enumerate() {
+                if i != 0 {
+                    write!(out, ", ")?;
+                }
+                write_value(out, arg, *value_type)?;
+            }
+            writeln!(out, ")")
+        });
+    }
+
+    unsafe fn leave(
+        &mut self,
+        instance: *mut sys::FizzyInstance,
+        func_idx: u32,
+        result: sys::FizzyExecutionResult,
+    ) {
+        self.depth -= 1;
+        let depth = self.depth;
+        let value = if result.has_value {
+            Some(result.value)
+        } else {
+            None
+        };
+        if let TraceSink::Callback(callback) = self.sink {
+            callback(&TraceEvent::Leave {
+                index: func_idx,
+                result: value,
+                trapped: result.trapped,
+            });
+            return;
+        }
+        let output = Self::function_type(instance, func_idx).output;
+        self.write(|out| {
+            write!(out, "{:1$}-> ", "", depth * 2)?;
+            match (result.trapped, value, output) {
+                (true, _, _) => write!(out, "trap")?,
+                (false, Some(value), Some(value_type)) => write_value(out, &value, value_type)?,
+                _ => write!(out, "void")?,
+            }
+            writeln!(out)
+        });
+    }
+}
+
+fn write_value(out: &mut dyn Write, value: &Value, value_type: ValueType) -> std::io::Result<()> {
+    match value_type {
+        ValueType::I32 => write!(out, "{}", value.as_u32()),
+        ValueType::I64 => write!(out, "{}", value.as_u64()),
+        ValueType::F32 => write!(out, "{:?}", value.as_f32()),
+        ValueType::F64 => write!(out, "{:?}", value.as_f64()),
+        _ => write!(out, "{}", value_type),
+    }
+}
+
+unsafe extern "C" fn enter_hook(
+    context: *mut c_void,
+    instance: *mut sys::FizzyInstance,
+    func_idx: u32,
+    args: *const sys::FizzyValue,
+) {
+    let tracer = &mut *(context as *mut Tracer);
+    if let Some(call_depth) = tracer.call_depth.as_mut() {
+        call_depth.enter();
+    }
+    if let Some(coverage) = tracer.coverage {
+        coverage::enter(
+            coverage,
+            tracer.instance.instance.as_ptr(),
+            instance,
+            func_idx,
+        );
+    }
+    tracer.guard(|tracer| {
+        let module = sys::fizzy_get_instance_module(instance);
+        let inputs = sys::fizzy_get_function_type(module, func_idx).inputs_size;
+        let args = if inputs == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(args, inputs)
+        };
+        tracer.enter(instance, func_idx, args)
+    });
+}
+
+unsafe extern "C" fn leave_hook(
+    context: *mut c_void,
+    instance: *mut sys::FizzyInstance,
+    func_idx: u32,
+    result: sys::FizzyExecutionResult,
+) {
+    let tracer = &mut *(context as *mut Tracer);
+    if let Some(call_depth) = tracer.call_depth.as_mut() {
+        call_depth.leave();
+    }
+    tracer.guard(|tracer| tracer.leave(instance, func_idx, result));
+}
+
+/// Execute the function `func_idx` of `instance` like [`Instance::execute_unchecked`], passing the
+/// events to `sink`, marking the entered functions in `coverage` if given, and counting the depth
+/// of calls in `call_depth` if given.
+///
+/// # Safety
+/// This function expects a valid `func_idx` and appropriate number of `args`.
+pub(crate) unsafe fn execute(
+    instance: &mut Instance,
+    func_idx: u32,
+    args: &[Value],
+    sink: &mut TraceSink,
+    coverage: Option<&CoverageMap>,
+    call_depth: Option<&mut CallDepth>,
+    max_depth: u32,
+) -> Result<ExecutionResult, Error> {
+    instance.host_trap.clear();
+    let export_names = instance
+        .module
+        .exports()
+        .into_iter()
+        .filter(|export| export.kind == ExternalKind::Function)
+        .map(|export| (export.index, export.name))
+        .collect();
+    let mut tracer = Tracer {
+        sink,
+        coverage,
+        call_depth,
+        instance: &*instance,
+        export_names,
+        depth: 0,
+        error: None,
+        panic: None,
+    };
+    let hooks = sys::FizzyTraceHooks {
+        enter: Some(enter_hook),
+        leave: Some(leave_hook),
+        context: &mut tracer as *mut Tracer as *mut c_void,
+    };
+    let mut call_depth_exceeded = false;
+    let result = ExecutionResult(sys::fizzy_execute_traced(
+        instance.instance.as_ptr(),
+        func_idx,
+        args.as_ptr(),
+        &hooks,
+        max_depth,
+        &mut call_depth_exceeded,
+    ));
+    if call_depth_exceeded {
+        instance.host_trap.set(Trap::call_depth_exceeded());
+    }
+    if let Some(payload) = tracer.panic {
+        panic::resume_unwind(payload);
+    }
+    tracer.write(|out| out.flush());
+    match tracer.error {
+        Some(err) => Err(Error::Io(Arc::new(err))),
+        None => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, Caller, ExecutionOptions, ImportsBuilder, Trap, TypedValue};
+
+    use super::*;
+    use std::sync::Mutex;
+
+    /// The buffer of the written trace, which stays readable after it is given to the sink.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn instantiate() -> Instance {
+        /* wat2wasm --debug-names
+        (module
+          (func $double (import "env" "double") (param i32) (result i32))
+          (func $fac (param i32) (result i32)
+            (if (result i32) (i32.eqz (local.get 0))
+              (then (i32.const 1))
+              (else (i32.mul (local.get 0) (call $fac (i32.sub (local.get 0) (i32.const 1)))))))
+          (func (export "run") (param i32) (result i32) (call $double (call $fac (local.get 0))))
+          (func (param i64 f64) (unreachable))
+          (func (export "fail") (call 3 (i64.const 7) (f64.const 1.5)))
+        )
+        */
+        let input = hex::decode("0061736d01000000010e0360017f017f60027e7c00600000020e0103656e7606646f75626c65000003050400000102070e020372756e0002046661696c00040a34041500200045047f4101052000200041016b10016c0b0b08002000100110000b0300000b0f00420744000000000000f83f10030b0022046e616d65010e020006646f75626c650103666163020b0500000100020003000400").unwrap();
+        let mut imports = ImportsBuilder::new();
+        imports.func(
+            "env",
+            "double",
+            |_: &mut Caller, x: u32| -> Result<u32, Trap> { Ok(x * 2) },
+        );
+        parse(&input)
+            .unwrap()
+            .instantiate_with_imports(imports)
+            .unwrap()
+    }
+
+    #[test]
+    fn writer() {
+        let mut instance = instantiate();
+        let buffer = Buffer::default();
+        let options = ExecutionOptions::new().trace(TraceSink::Writer(Box::new(buffer.clone())));
+        let outcome = instance
+            .execute_with_options("run", &[TypedValue::U32(3)], &options)
+            .unwrap();
+        assert_eq!(outcome.value(), Some(TypedValue::U32(12)));
+        assert!(instance
+            .execute_with_options("fail", &[], &options)
+            .unwrap()
+            .trapped());
+        // Not traced without the options.
+        instance.execute("run", &[TypedValue::U32(1)]).unwrap();
+
+        let expected = "\
+run(3)
+  fac(3)
+    fac(2)
+      fac(1)
+        fac(0)
+        -> 1
+      -> 1
+    -> 2
+  -> 6
+  double(6)
+  -> 12
+-> 12
+fail()
+  func[3](7, 1.5)
+  -> trap
+-> trap
+";
+        assert_eq!(
+            String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn callback() {
+        let mut instance = instantiate();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let options = ExecutionOptions::new().trace(TraceSink::Callback(Box::new(move |event| {
+            recorded.lock().unwrap().push(match event {
+                TraceEvent::Enter { index, name, args } => {
+                    format!(
+                        "enter {} {:?} {:?}",
+                        index,
+                        name,
+                        args.first().map(Value::as_u32)
+                    )
+                }
+                TraceEvent::Leave {
+                    index,
+                    result,
+                    trapped,
+                } => format!(
+                    "leave {} {:?} {}",
+                    index,
+                    result.as_ref().map(Value::as_u32),
+                    trapped
+                ),
+            })
+        })));
+        instance
+            .execute_with_options("run", &[TypedValue::U32(0)], &options)
+            .unwrap();
+        instance
+            .execute_with_options("fail", &[], &options)
+            .unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "enter 2 Some(\"run\") Some(0)",
+                "enter 1 Some(\"fac\") Some(0)",
+                "leave 1 Some(1) false",
+                "enter 0 Some(\"double\") Some(1)",
+                "leave 0 Some(2) false",
+                "leave 2 Some(2) false",
+                "enter 4 Some(\"fail\") None",
+                "enter 3 None Some(7)",
+                "leave 3 None true",
+                "leave 4 None true",
+            ]
+        );
+    }
+
+    #[test]
+    fn write_error() {
+        struct Failing;
+
+        impl Write for Failing {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::WriteZero.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut instance = instantiate();
+        let options = ExecutionOptions::new().trace(TraceSink::Writer(Box::new(Failing)));
+        match instance.execute_with_options("run", &[TypedValue::U32(3)], &options) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::WriteZero),
+            _ => panic!("expected an I/O error"),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host memory used by instances, for capacity planning.
+
+use crate::{imports, sys, ExternalKind, Instance, Module};
+
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// The approximate size of an instance in the engine, without its memory, table and globals.
+const ENGINE_INSTANCE_SIZE: usize = 152;
+/// The size of an element of a table in the engine.
+const TABLE_ELEMENT_SIZE: usize = 32;
+/// The size of an imported function in the engine, not including the context of a host function.
+const IMPORTED_FUNCTION_SIZE: usize = 72;
+/// The size of an imported global in the engine.
+const IMPORTED_GLOBAL_SIZE: usize = 16;
+
+/// The host memory used by an instance, see [`Instance::resource_usage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The current size of the memory in bytes, or 0 if the instance has no memory.
+    pub memory_bytes: usize,
+    /// The size in bytes up to which the memory can grow, i.e. the maximum size declared by the
+    /// module or [`InstantiateOptions::memory_pages_limit`](crate::InstantiateOptions::memory_pages_limit),
+    /// whichever is smaller.
+    pub memory_max_bytes: usize,
+    /// The largest size of the memory in bytes since instantiation, see
+    /// [`Instance::peak_memory_pages`].
+    pub peak_memory_bytes: usize,
+    /// True if the module is also referenced by other instances or [`Module`] clones, therefore
+    /// its memory is not freed with the instance.
+    pub module_shared: bool,
+}
+
+impl Instance {
+    /// The host memory used by the instance at this point, which changes when the memory grows.
+    ///
+    /// An imported memory is included, although it is shared with other instances.
+    pub fn resource_usage(&self) -> ResourceUsage {
+        ResourceUsage {
+            memory_bytes: self.memory_size(),
+            memory_max_bytes: unsafe {
+                sys::fizzy_get_instance_memory_max_size(self.instance.as_ptr())
+            },
+            peak_memory_bytes: self.peak_memory_pages() as usize * 65536,
+            module_shared: Arc::strong_count(&self.module.0) > 1,
+        }
+    }
+}
+
+/// Estimate the fixed host memory used by an instance of `module` in bytes, besides its memory:
+/// the globals, the table, the imported functions and the bookkeeping of the engine and the
+/// binding. The imported tables and globals are shared, therefore only the references to them are
+/// included.
+pub fn estimate_instance_overhead(module: &Module) -> usize {
+    let mut imported_functions = 0;
+    let mut imported_globals = 0;
+    let mut imported_table = false;
+    for import in module.imports() {
+        match import.ty.kind() {
+            ExternalKind::Function => imported_functions += 1,
+            ExternalKind::Global => imported_globals += 1,
+            ExternalKind::Table => imported_table = true,
+            ExternalKind::Memory => {}
+        }
+    }
+
+    let module = module.as_ptr();
+    let global_count = unsafe { sys::fizzy_get_global_count(module) } as usize;
+    let table_size = if unsafe { sys::fizzy_module_has_table(module) } && !imported_table {
+        unsafe { sys::fizzy_get_table_limits(module) }.min as usize
+    } else {
+        0
+    };
+
+    ENGINE_INSTANCE_SIZE
+        + size_of::<Instance>()
+        + (global_count - imported_globals) * size_of::<sys::FizzyValue>()
+        + imported_globals * IMPORTED_GLOBAL_SIZE
+        + table_size * TABLE_ELEMENT_SIZE
+        + imported_functions * (IMPORTED_FUNCTION_SIZE + size_of::<imports::HostContext>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, TypedValue};
+
+    fn module() -> Module {
+        /* wat2wasm
+        (module
+          (memory 1 4)
+          (global (mut i32) (i32.const 0))
+          (global (mut i64) (i64.const 0))
+          (table 10 funcref)
+          (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001060160017f017f0302010004040170000a050401010104060b027f0141000b7e0142000b0708010467726f7700000a08010600200040000b").unwrap();
+        parse(&input).unwrap()
+    }
+
+    #[test]
+    fn resource_usage() {
+        let mut instance = module().instantiate().unwrap();
+        let usage = instance.resource_usage();
+        assert_eq!(usage.memory_bytes, instance.memory_size());
+        assert_eq!(usage.memory_bytes, 65536);
+        assert_eq!(usage.memory_max_bytes, 4 * 65536);
+        assert_eq!(usage.peak_memory_bytes, 65536);
+        assert!(!usage.module_shared);
+
+        let result = instance.execute("grow", &[TypedValue::U32(2)]).unwrap();
+        assert_eq!(result.value(), Some(TypedValue::U32(1)));
+        let usage = instance.resource_usage();
+        assert_eq!(usage.memory_bytes, instance.memory_size());
+        assert_eq!(usage.memory_bytes, 3 * 65536);
+        assert_eq!(usage.memory_max_bytes, 4 * 65536);
+        assert_eq!(usage.peak_memory_bytes, 3 * 65536);
+
+        let module = module();
+        let instance = module.instantiate().unwrap();
+        assert!(instance.resource_usage().module_shared);
+        drop(module);
+        assert!(!instance.resource_usage().module_shared);
+    }
+
+    #[test]
+    fn peak_memory() {
+        let mut instance = module().instantiate().unwrap();
+        assert_eq!(instance.peak_memory_pages(), 1);
+        for (delta, expected_peak) in &[(1, 2), (0, 2), (1, 3), (5, 3), (1, 4)] {
+            instance
+                .execute("grow", &[TypedValue::U32(*delta)])
+                .unwrap();
+            assert_eq!(instance.peak_memory_pages(), *expected_peak);
+        }
+
+        // The peak persists through the executions not growing the memory, and with a callback.
+        instance.on_memory_grow(|_, _| {});
+        instance.execute("grow", &[TypedValue::U32(1)]).unwrap();
+        instance.execute("grow", &[TypedValue::U32(0)]).unwrap();
+        assert_eq!(instance.peak_memory_pages(), 4);
+        assert_eq!(instance.resource_usage().peak_memory_bytes, 4 * 65536);
+    }
+
+    #[test]
+    fn memory_pages_limit() {
+        /* wat2wasm
+        (module (memory 1))
+        */
+        let input = hex::decode("0061736d010000000503010001").unwrap();
+        let instance = parse(&input).unwrap().instantiate_with_limit(2).unwrap();
+        let usage = instance.resource_usage();
+        assert_eq!(usage.memory_bytes, 65536);
+        assert_eq!(usage.memory_max_bytes, 2 * 65536);
+    }
+
+    #[test]
+    fn without_memory() {
+        /* wat2wasm
+        (module (func (export "f")))
+        */
+        let input =
+            hex::decode("0061736d0100000001040160000003020100070501016600000a040102000b").unwrap();
+        let instance = parse(&input).unwrap().instantiate().unwrap();
+        assert_eq!(instance.resource_usage(), ResourceUsage::default());
+    }
+
+    #[test]
+    fn overhead() {
+        let module = module();
+        let base = ENGINE_INSTANCE_SIZE + size_of::<Instance>();
+        assert_eq!(
+            estimate_instance_overhead(&module),
+            base + 2 * size_of::<sys::FizzyValue>() + 10 * TABLE_ELEMENT_SIZE
+        );
+
+        /* wat2wasm
+        (module
+          (func (import "env" "f"))
+          (global (import "env" "g") i32)
+          (table (import "env" "t") 100 funcref)
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000021c0303656e760166000003656e760167037f0003656e76017401700064").unwrap();
+        let module = parse(&input).unwrap();
+        assert_eq!(
+            estimate_instance_overhead(&module),
+            base + IMPORTED_GLOBAL_SIZE
+                + IMPORTED_FUNCTION_SIZE
+                + size_of::<imports::HostContext>()
+        );
+    }
+}
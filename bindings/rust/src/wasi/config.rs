@@ -0,0 +1,211 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::VirtualFs;
+
+/// The source of the standard input of a WASI program.
+pub enum WasiIn {
+    /// Read from the standard input of the host process.
+    Inherit,
+    /// Read the given bytes, followed by the end of file.
+    Bytes(Vec<u8>),
+    /// Read from the reader. Programs instantiated with the same configuration share the reader.
+    Reader(Box<dyn Read + Send>),
+}
+
+impl fmt::Debug for WasiIn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WasiIn::Inherit => write!(f, "Inherit"),
+            WasiIn::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            WasiIn::Reader(_) => write!(f, "Reader(..)"),
+        }
+    }
+}
+
+/// The configured standard input, in a form which can be used for multiple instantiations.
+#[derive(Clone)]
+pub(crate) enum Stdin {
+    Inherit,
+    Bytes(Arc<[u8]>),
+    Reader(Arc<Mutex<Box<dyn Read + Send>>>),
+}
+
+impl fmt::Debug for Stdin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stdin::Inherit => write!(f, "Inherit"),
+            Stdin::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Stdin::Reader(_) => write!(f, "Reader(..)"),
+        }
+    }
+}
+
+/// The destination of an output stream of a WASI program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WasiOut {
+    /// Write to the corresponding stream of the host process.
+    Inherit,
+    /// Capture the output in memory, see [`WasiRun`](super::WasiRun).
+    Buffer,
+}
+
+/// The source of the bytes returned by `random_get`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomSource {
+    /// The random number generator of the operating system.
+    Os,
+    /// A deterministic stream derived from the seed, identical on all platforms.
+    ///
+    /// The stream is the ChaCha20 keystream with the seed as the key, a zero nonce and a block counter starting at 0.
+    /// It is not shared between instances, each starts from its beginning.
+    Deterministic { seed: [u8; 32] },
+}
+
+/// The source of the time returned by `clock_time_get`, for all clocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The clocks of the host: the realtime clock and the time elapsed since instantiation otherwise.
+    Real,
+    /// Always the given time in nanoseconds.
+    Fixed(u64),
+    /// The time in nanoseconds `start + n * step` on the `n`-th call (counting from 0), wrapping around on overflow.
+    Logical { start: u64, step: u64 },
+}
+
+/// A directory accessible to the program.
+#[derive(Debug)]
+pub(crate) enum Preopen {
+    /// The host path and the guest path.
+    Host(PathBuf, String),
+    /// The virtual filesystem and the guest path.
+    Virtual(VirtualFs, String),
+}
+
+/// The configuration of the environment of a WASI program.
+///
+/// ```
+/// let config = fizzy::wasi::WasiConfig::new()
+///     .arg("prog")
+///     .arg("--flag")
+///     .env("KEY", "VALUE")
+///     .preopen_dir("/tmp", "/sandbox");
+/// ```
+#[derive(Debug)]
+pub struct WasiConfig {
+    pub(crate) args: Vec<String>,
+    pub(crate) env: Vec<(String, String)>,
+    pub(crate) preopens: Vec<Preopen>,
+    pub(crate) stdin: Stdin,
+    pub(crate) stdout: WasiOut,
+    pub(crate) stderr: WasiOut,
+    pub(crate) random_source: RandomSource,
+    pub(crate) clock: ClockSource,
+}
+
+impl Default for WasiConfig {
+    fn default() -> Self {
+        WasiConfig {
+            args: Vec::new(),
+            env: Vec::new(),
+            preopens: Vec::new(),
+            stdin: Stdin::Inherit,
+            stdout: WasiOut::Inherit,
+            stderr: WasiOut::Inherit,
+            random_source: RandomSource::Os,
+            clock: ClockSource::Real,
+        }
+    }
+}
+
+impl WasiConfig {
+    /// Create a configuration without arguments, environment variables and preopened directories,
+    /// inheriting the standard streams and using the random number generator and the clocks of the host.
+    pub fn new() -> Self {
+        WasiConfig::default()
+    }
+
+    /// Append an argument. The first argument is the program name by convention.
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Append multiple arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set an environment variable.
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Give the program access to the host directory `host_path` (including its subdirectories),
+    /// visible to the program as `guest_path`.
+    pub fn preopen_dir<P: AsRef<Path>, S: Into<String>>(
+        mut self,
+        host_path: P,
+        guest_path: S,
+    ) -> Self {
+        self.preopens.push(Preopen::Host(
+            host_path.as_ref().to_path_buf(),
+            guest_path.into(),
+        ));
+        self
+    }
+
+    /// Give the program access to the in-memory filesystem `fs`, visible to the program as `/`.
+    ///
+    /// The preopened directories are numbered in the order of configuration.
+    pub fn filesystem(mut self, fs: VirtualFs) -> Self {
+        self.preopens.push(Preopen::Virtual(fs, "/".to_string()));
+        self
+    }
+
+    /// Set the source of the standard input.
+    pub fn stdin(mut self, input: WasiIn) -> Self {
+        self.stdin = match input {
+            WasiIn::Inherit => Stdin::Inherit,
+            WasiIn::Bytes(bytes) => Stdin::Bytes(bytes.into()),
+            WasiIn::Reader(reader) => Stdin::Reader(Arc::new(Mutex::new(reader))),
+        };
+        self
+    }
+
+    /// Set the destination of the standard output.
+    pub fn stdout(mut self, out: WasiOut) -> Self {
+        self.stdout = out;
+        self
+    }
+
+    /// Set the destination of the standard error.
+    pub fn stderr(mut self, out: WasiOut) -> Self {
+        self.stderr = out;
+        self
+    }
+
+    /// Set the source of the bytes returned by `random_get`.
+    pub fn random_source(mut self, source: RandomSource) -> Self {
+        self.random_source = source;
+        self
+    }
+
+    /// Set the source of the time returned by `clock_time_get`.
+    pub fn clock(mut self, clock: ClockSource) -> Self {
+        self.clock = clock;
+        self
+    }
+}
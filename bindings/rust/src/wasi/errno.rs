@@ -0,0 +1,35 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The WASI error numbers.
+
+/// A WASI error number.
+pub(crate) type Errno = u32;
+
+pub(crate) const ESUCCESS: Errno = 0;
+pub(crate) const EACCES: Errno = 2;
+pub(crate) const EBADF: Errno = 8;
+pub(crate) const EEXIST: Errno = 20;
+pub(crate) const EFAULT: Errno = 21;
+pub(crate) const EILSEQ: Errno = 25;
+pub(crate) const EINVAL: Errno = 28;
+pub(crate) const EIO: Errno = 29;
+pub(crate) const EISDIR: Errno = 31;
+pub(crate) const ENOENT: Errno = 44;
+pub(crate) const ENOSYS: Errno = 52;
+pub(crate) const ENOTDIR: Errno = 54;
+pub(crate) const ENOTEMPTY: Errno = 55;
+pub(crate) const ESPIPE: Errno = 70;
+pub(crate) const ENOTCAPABLE: Errno = 76;
+
+/// Map a host I/O error to the closest WASI error number.
+pub(crate) fn from_io_error(err: &std::io::Error) -> Errno {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => ENOENT,
+        std::io::ErrorKind::PermissionDenied => EACCES,
+        std::io::ErrorKind::AlreadyExists => EEXIST,
+        std::io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
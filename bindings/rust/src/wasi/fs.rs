@@ -0,0 +1,463 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The file descriptor table and the access to preopened directories.
+
+use super::config::Preopen;
+use super::errno::*;
+use super::vfs::{VirtualFile, VirtualFs};
+use crate::Error;
+
+use std::collections::BTreeMap;
+use std::fs::{Metadata, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const FILETYPE_UNKNOWN: u8 = 0;
+pub(crate) const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+pub(crate) const FILETYPE_DIRECTORY: u8 = 3;
+pub(crate) const FILETYPE_REGULAR_FILE: u8 = 4;
+pub(crate) const FILETYPE_SYMBOLIC_LINK: u8 = 7;
+
+/// The attributes of a file, as in the WASI `filestat` structure. The times are in nanoseconds since the Unix epoch.
+#[derive(Debug, Default)]
+pub(crate) struct Filestat {
+    pub(crate) dev: u64,
+    pub(crate) ino: u64,
+    pub(crate) filetype: u8,
+    pub(crate) nlink: u64,
+    pub(crate) size: u64,
+    pub(crate) atim: u64,
+    pub(crate) mtim: u64,
+    pub(crate) ctim: u64,
+}
+
+impl Filestat {
+    fn from_metadata(metadata: &Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let filetype = if file_type.is_dir() {
+            FILETYPE_DIRECTORY
+        } else if file_type.is_file() {
+            FILETYPE_REGULAR_FILE
+        } else if file_type.is_symlink() {
+            FILETYPE_SYMBOLIC_LINK
+        } else {
+            FILETYPE_UNKNOWN
+        };
+        let modified = timestamp(metadata.modified());
+        #[allow(unused_mut)]
+        let mut filestat = Filestat {
+            filetype,
+            nlink: 1,
+            size: metadata.len(),
+            atim: timestamp(metadata.accessed()),
+            mtim: modified,
+            ctim: modified,
+            ..Filestat::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            filestat.dev = metadata.dev();
+            filestat.ino = metadata.ino();
+            filestat.nlink = metadata.nlink();
+        }
+        filestat
+    }
+}
+
+/// The nanoseconds since the Unix epoch of a file time, 0 if not available.
+fn timestamp(time: std::io::Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+/// The way to open a path.
+#[derive(Debug, Default)]
+pub(crate) struct OpenFlags {
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+    pub(crate) append: bool,
+    pub(crate) create: bool,
+    pub(crate) exclusive: bool,
+    pub(crate) truncate: bool,
+    /// Fail unless the path is a directory.
+    pub(crate) directory: bool,
+}
+
+/// An open file descriptor.
+pub(crate) enum Descriptor {
+    Input(Box<dyn Read + Send>),
+    Output(Box<dyn Write + Send>),
+    Directory(Directory),
+    File(File),
+}
+
+/// An open regular file.
+pub(crate) enum File {
+    Host(std::fs::File),
+    Virtual(VirtualFile),
+}
+
+impl File {
+    pub(crate) fn filestat(&self) -> Result<Filestat, Errno> {
+        match self {
+            File::Host(file) => file
+                .metadata()
+                .map(|metadata| Filestat::from_metadata(&metadata))
+                .map_err(|err| from_io_error(&err)),
+            File::Virtual(file) => Ok(file.filestat()),
+        }
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            File::Host(file) => file.read(buf),
+            File::Virtual(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            File::Host(file) => file.write(buf),
+            File::Virtual(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            File::Host(file) => file.flush(),
+            File::Virtual(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            File::Host(file) => file.seek(position),
+            File::Virtual(file) => file.seek(position),
+        }
+    }
+}
+
+/// A directory within a preopened directory.
+pub(crate) struct Directory {
+    location: Location,
+    /// The guest path, if this is the preopened directory itself.
+    pub(crate) preopen: Option<String>,
+}
+
+enum Location {
+    Host {
+        /// The canonical host path of the preopened directory.
+        root: PathBuf,
+        /// The path relative to the preopened directory.
+        relative: PathBuf,
+    },
+    Virtual {
+        fs: VirtualFs,
+        /// The normalized path within the virtual filesystem.
+        path: String,
+    },
+}
+
+/// Resolve the guest `path` relative to the directory `relative` within the host directory `root`
+/// to a host path and the path relative to `root`.
+///
+/// The resolved path must stay within `root`, also when following symbolic links.
+fn resolve_host(root: &Path, relative: &Path, path: &str) -> Result<(PathBuf, PathBuf), Errno> {
+    if path.starts_with('/') {
+        return Err(ENOTCAPABLE);
+    }
+    let mut relative = relative.to_path_buf();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if !relative.pop() {
+                    return Err(ENOTCAPABLE);
+                }
+            }
+            name => {
+                // Reject anything the host would interpret as more than a single file name.
+                let mut components = Path::new(name).components();
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(_)), None) => relative.push(name),
+                    _ => return Err(ENOTCAPABLE),
+                }
+            }
+        }
+    }
+    let host_path = root.join(&relative);
+
+    // Symbolic links may point outside, therefore check the canonical path of the nearest
+    // existing ancestor (the path itself is created later, if it does not exist).
+    let mut existing = host_path.as_path();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            if !canonical.starts_with(root) {
+                return Err(ENOTCAPABLE);
+            }
+            break;
+        }
+        existing = existing.parent().ok_or(ENOTCAPABLE)?;
+    }
+    Ok((host_path, relative))
+}
+
+impl Directory {
+    /// Open the host directory `host_path` for access as `guest_path`.
+    fn preopen_host(host_path: &Path, guest_path: &str) -> Result<Self, Error> {
+        let error = |reason: String| {
+            Error::InstantiationFailed(format!(
+                "cannot preopen directory {}: {}",
+                host_path.display(),
+                reason
+            ))
+        };
+        let root = host_path
+            .canonicalize()
+            .map_err(|err| error(err.to_string()))?;
+        if !root.is_dir() {
+            return Err(error("not a directory".to_string()));
+        }
+        Ok(Directory {
+            location: Location::Host {
+                root,
+                relative: PathBuf::new(),
+            },
+            preopen: Some(guest_path.to_string()),
+        })
+    }
+
+    /// Open the root of the virtual filesystem `fs` for access as `guest_path`.
+    fn preopen_virtual(fs: &VirtualFs, guest_path: &str) -> Self {
+        Directory {
+            location: Location::Virtual {
+                fs: fs.clone(),
+                path: String::new(),
+            },
+            preopen: Some(guest_path.to_string()),
+        }
+    }
+
+    /// The attributes of the directory itself.
+    pub(crate) fn filestat(&self) -> Result<Filestat, Errno> {
+        self.path_filestat(".", true)
+    }
+
+    /// The attributes of the file at `path` relative to this directory.
+    pub(crate) fn path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Errno> {
+        match &self.location {
+            Location::Host { root, relative } => {
+                let (host_path, _) = resolve_host(root, relative, path)?;
+                let metadata = if follow_symlinks {
+                    std::fs::metadata(&host_path)
+                } else {
+                    std::fs::symlink_metadata(&host_path)
+                };
+                metadata
+                    .map(|metadata| Filestat::from_metadata(&metadata))
+                    .map_err(|err| from_io_error(&err))
+            }
+            Location::Virtual { fs, path: base } => fs.filestat(&VirtualFs::join(base, path)),
+        }
+    }
+
+    /// Open the file or directory at `path` relative to this directory.
+    pub(crate) fn open(&self, path: &str, flags: &OpenFlags) -> Result<Descriptor, Errno> {
+        match &self.location {
+            Location::Host { root, relative } => {
+                let (host_path, relative) = resolve_host(root, relative, path)?;
+                if flags.directory || (!flags.create && host_path.is_dir()) {
+                    if !host_path.exists() {
+                        return Err(ENOENT);
+                    }
+                    if !host_path.is_dir() {
+                        return Err(ENOTDIR);
+                    }
+                    return Ok(Descriptor::Directory(Directory {
+                        location: Location::Host {
+                            root: root.clone(),
+                            relative,
+                        },
+                        preopen: None,
+                    }));
+                }
+                let file = OpenOptions::new()
+                    .read(flags.read)
+                    .write(flags.write)
+                    .append(flags.append)
+                    .create(flags.create)
+                    .create_new(flags.create && flags.exclusive)
+                    .truncate(flags.truncate)
+                    .open(&host_path)
+                    .map_err(|err| from_io_error(&err))?;
+                Ok(Descriptor::File(File::Host(file)))
+            }
+            Location::Virtual { fs, path: base } => {
+                let path = VirtualFs::join(base, path);
+                match fs.open(&path, flags)? {
+                    Some(file) => Ok(Descriptor::File(File::Virtual(file))),
+                    None => Ok(Descriptor::Directory(Directory {
+                        location: Location::Virtual {
+                            fs: fs.clone(),
+                            path,
+                        },
+                        preopen: None,
+                    })),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn create_directory(&self, path: &str) -> Result<(), Errno> {
+        match &self.location {
+            Location::Host { root, relative } => {
+                let (host_path, _) = resolve_host(root, relative, path)?;
+                std::fs::create_dir(host_path).map_err(|err| from_io_error(&err))
+            }
+            Location::Virtual { fs, path: base } => {
+                fs.create_directory(&VirtualFs::join(base, path))
+            }
+        }
+    }
+
+    pub(crate) fn remove_directory(&self, path: &str) -> Result<(), Errno> {
+        match &self.location {
+            Location::Host { root, relative } => {
+                let (host_path, _) = resolve_host(root, relative, path)?;
+                if !host_path.is_dir() {
+                    return Err(ENOTDIR);
+                }
+                std::fs::remove_dir(host_path).map_err(|err| from_io_error(&err))
+            }
+            Location::Virtual { fs, path: base } => {
+                fs.remove_directory(&VirtualFs::join(base, path))
+            }
+        }
+    }
+
+    pub(crate) fn unlink_file(&self, path: &str) -> Result<(), Errno> {
+        match &self.location {
+            Location::Host { root, relative } => {
+                let (host_path, _) = resolve_host(root, relative, path)?;
+                if host_path.is_dir() {
+                    return Err(EISDIR);
+                }
+                std::fs::remove_file(host_path).map_err(|err| from_io_error(&err))
+            }
+            Location::Virtual { fs, path: base } => fs.unlink_file(&VirtualFs::join(base, path)),
+        }
+    }
+}
+
+/// The table of open file descriptors of a program.
+pub(crate) struct FdTable {
+    fds: BTreeMap<u32, Descriptor>,
+}
+
+impl FdTable {
+    /// Create the table of the standard streams (as 0, 1 and 2) followed by the preopened directories.
+    pub(crate) fn new(
+        stdin: Box<dyn Read + Send>,
+        stdout: Box<dyn Write + Send>,
+        stderr: Box<dyn Write + Send>,
+        preopens: &[Preopen],
+    ) -> Result<Self, Error> {
+        let mut fds = BTreeMap::new();
+        fds.insert(0, Descriptor::Input(stdin));
+        fds.insert(1, Descriptor::Output(stdout));
+        fds.insert(2, Descriptor::Output(stderr));
+        let mut table = FdTable { fds };
+        for preopen in preopens {
+            let directory = match preopen {
+                Preopen::Host(host_path, guest_path) => {
+                    Directory::preopen_host(host_path, guest_path)?
+                }
+                Preopen::Virtual(fs, guest_path) => Directory::preopen_virtual(fs, guest_path),
+            };
+            table.insert(Descriptor::Directory(directory));
+        }
+        Ok(table)
+    }
+
+    pub(crate) fn get(&mut self, fd: u32) -> Result<&mut Descriptor, Errno> {
+        self.fds.get_mut(&fd).ok_or(EBADF)
+    }
+
+    pub(crate) fn directory(&mut self, fd: u32) -> Result<&mut Directory, Errno> {
+        match self.get(fd)? {
+            Descriptor::Directory(directory) => Ok(directory),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    /// Insert the descriptor under the lowest free number.
+    pub(crate) fn insert(&mut self, descriptor: Descriptor) -> u32 {
+        let fd = (0..)
+            .zip(self.fds.keys())
+            .find(|(expected, fd)| expected != *fd)
+            .map_or(self.fds.len() as u32, |(expected, _)| expected);
+        self.fds.insert(fd, descriptor);
+        fd
+    }
+
+    pub(crate) fn remove(&mut self, fd: u32) -> Result<Descriptor, Errno> {
+        self.fds.remove(&fd).ok_or(EBADF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_host_sandbox() {
+        let dir = std::env::temp_dir().join(format!("fizzy-wasi-resolve-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("root/sub")).unwrap();
+        let root = dir.join("root").canonicalize().unwrap();
+        let relative = Path::new("sub");
+
+        let resolved = resolve_host(&root, relative, "../file.txt").unwrap();
+        assert_eq!(resolved, (root.join("file.txt"), PathBuf::from("file.txt")));
+        assert_eq!(
+            resolve_host(&root, relative, "new/file.txt").unwrap().1,
+            PathBuf::from("sub/new/file.txt")
+        );
+        for path in &["../../outside", "/etc/passwd", "sub/../../.."] {
+            assert_eq!(resolve_host(&root, relative, path), Err(ENOTCAPABLE));
+        }
+
+        #[cfg(unix)]
+        {
+            // Symbolic links are followed only within the root.
+            std::os::unix::fs::symlink(&dir, root.join("escape")).unwrap();
+            std::os::unix::fs::symlink(root.join("sub"), root.join("inside")).unwrap();
+            assert_eq!(
+                resolve_host(&root, Path::new(""), "escape"),
+                Err(ENOTCAPABLE)
+            );
+            assert_eq!(
+                resolve_host(&root, Path::new(""), "escape/new.txt"),
+                Err(ENOTCAPABLE)
+            );
+            assert!(resolve_host(&root, Path::new(""), "inside/new.txt").is_ok());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
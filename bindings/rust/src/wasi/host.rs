@@ -0,0 +1,792 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host functions of `wasi_snapshot_preview1`.
+
+use super::config::Stdin;
+use super::errno::*;
+use super::fs::{
+    Descriptor, FdTable, Filestat, OpenFlags, FILETYPE_CHARACTER_DEVICE, FILETYPE_DIRECTORY,
+    FILETYPE_REGULAR_FILE,
+};
+use super::random::ChaCha20;
+use super::{ClockSource, RandomSource, WasiConfig, WasiOut};
+use crate::{Caller, Error, FunctionType, ImportsBuilder, Trap, TypedValue, ValueType};
+
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The module name of the WASI imports.
+pub(crate) const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+const RIGHTS_FD_READ: u64 = 1 << 1;
+const RIGHTS_FD_WRITE: u64 = 1 << 6;
+/// All rights defined by `wasi_snapshot_preview1`.
+const RIGHTS_ALL: u64 = (1 << 29) - 1;
+
+const LOOKUP_SYMLINK_FOLLOW: u32 = 1;
+
+const OFLAGS_CREAT: u32 = 1;
+const OFLAGS_DIRECTORY: u32 = 2;
+const OFLAGS_EXCL: u32 = 4;
+const OFLAGS_TRUNC: u32 = 8;
+
+const FDFLAGS_APPEND: u32 = 1;
+
+const PREOPENTYPE_DIR: u8 = 0;
+
+/// The state shared by the host functions of a single instance.
+pub(crate) struct WasiState {
+    /// The arguments, each terminated by NUL.
+    args: Vec<Vec<u8>>,
+    /// The environment variables as `KEY=VALUE`, each terminated by NUL.
+    env: Vec<Vec<u8>>,
+    fds: FdTable,
+    clock: ClockSource,
+    /// The number of calls of `clock_time_get`, used by the logical clock.
+    clock_calls: u64,
+    /// The origin of the monotonic clock.
+    start: Instant,
+    /// The deterministic random number generator, if configured.
+    random: Option<ChaCha20>,
+}
+
+impl WasiState {
+    /// Create the state of a program configured by `config`, together with its captured output.
+    pub(crate) fn new(config: &WasiConfig) -> Result<(Self, CapturedOutput), Error> {
+        let args = config
+            .args
+            .iter()
+            .map(|arg| {
+                nul_terminated(arg).ok_or_else(|| {
+                    Error::InstantiationFailed("WASI argument contains a NUL byte".to_string())
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let env = config
+            .env
+            .iter()
+            .map(|(key, value)| {
+                Some(key)
+                    .filter(|key| !key.is_empty() && !key.contains('='))
+                    .and_then(|key| nul_terminated(&format!("{}={}", key, value)))
+                    .ok_or_else(|| {
+                        Error::InstantiationFailed(format!(
+                            "invalid WASI environment variable {:?}",
+                            key
+                        ))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        let (stdout, stdout_buffer) = output(config.stdout, || Box::new(std::io::stdout()));
+        let (stderr, stderr_buffer) = output(config.stderr, || Box::new(std::io::stderr()));
+        let fds = FdTable::new(input(&config.stdin), stdout, stderr, &config.preopens)?;
+        let state = WasiState {
+            args,
+            env,
+            fds,
+            clock: config.clock,
+            clock_calls: 0,
+            start: Instant::now(),
+            random: match &config.random_source {
+                RandomSource::Os => None,
+                RandomSource::Deterministic { seed } => Some(ChaCha20::new(seed)),
+            },
+        };
+        let captured = CapturedOutput {
+            stdout: stdout_buffer,
+            stderr: stderr_buffer,
+        };
+        Ok((state, captured))
+    }
+}
+
+/// A reader of a shared reader.
+struct SharedReader(Arc<Mutex<Box<dyn Read + Send>>>);
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        lock(&self.0).read(buf)
+    }
+}
+
+/// A reader of shared bytes.
+struct BytesReader {
+    bytes: Arc<[u8]>,
+    position: usize,
+}
+
+impl Read for BytesReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = (&self.bytes[self.position..]).read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Create the input stream for `stdin`.
+fn input(stdin: &Stdin) -> Box<dyn Read + Send> {
+    match stdin {
+        Stdin::Inherit => Box::new(std::io::stdin()),
+        Stdin::Bytes(bytes) => Box::new(BytesReader {
+            bytes: bytes.clone(),
+            position: 0,
+        }),
+        Stdin::Reader(reader) => Box::new(SharedReader(reader.clone())),
+    }
+}
+
+type Buffer = Arc<Mutex<Vec<u8>>>;
+
+/// A writer appending to a shared buffer.
+struct BufferWriter(Buffer);
+
+impl Write for BufferWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        lock(&self.0).extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Create the output stream for `out`, with the buffer if it is captured.
+fn output(
+    out: WasiOut,
+    inherit: impl FnOnce() -> Box<dyn Write + Send>,
+) -> (Box<dyn Write + Send>, Option<Buffer>) {
+    match out {
+        WasiOut::Inherit => (inherit(), None),
+        WasiOut::Buffer => {
+            let buffer = Buffer::default();
+            (Box::new(BufferWriter(buffer.clone())), Some(buffer))
+        }
+    }
+}
+
+/// The buffers of the output streams captured during the execution.
+pub(crate) struct CapturedOutput {
+    stdout: Option<Buffer>,
+    stderr: Option<Buffer>,
+}
+
+impl CapturedOutput {
+    fn take(buffer: &Option<Buffer>) -> Vec<u8> {
+        buffer
+            .as_ref()
+            .map_or_else(Vec::new, |buffer| std::mem::take(&mut *lock(buffer)))
+    }
+
+    /// Take the bytes written to the standard output so far, empty if it is not captured.
+    pub(crate) fn take_stdout(&self) -> Vec<u8> {
+        Self::take(&self.stdout)
+    }
+
+    /// Take the bytes written to the standard error so far, empty if it is not captured.
+    pub(crate) fn take_stderr(&self) -> Vec<u8> {
+        Self::take(&self.stderr)
+    }
+}
+
+/// Convert `s` to bytes terminated by NUL, unless `s` contains one already.
+fn nul_terminated(s: &str) -> Option<Vec<u8>> {
+    if s.contains('\0') {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(s.len() + 1);
+    bytes.extend_from_slice(s.as_bytes());
+    bytes.push(0);
+    Some(bytes)
+}
+
+/// Lock the shared data, ignoring poisoning: a panic of a host function is turned into a trap,
+/// which does not leave the data inconsistent.
+pub(crate) fn lock<T>(data: &Mutex<T>) -> MutexGuard<'_, T> {
+    data.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Compute the guest address `base + offset`.
+fn address(base: u32, offset: usize) -> Result<u32, Errno> {
+    (base as usize)
+        .checked_add(offset)
+        .and_then(|address| u32::try_from(address).ok())
+        .ok_or(EFAULT)
+}
+
+/// Check that `size` bytes at `ptr` are within the guest memory.
+fn check_range(caller: &Caller, ptr: u32, size: usize) -> Result<(), Errno> {
+    match (ptr as usize).checked_add(size) {
+        Some(end) if end <= caller.memory_size() => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
+
+fn read_u32(caller: &Caller, ptr: u32) -> Result<u32, Errno> {
+    let mut bytes = [0u8; 4];
+    caller.memory_get(ptr, &mut bytes).map_err(|_| EFAULT)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_bytes(caller: &mut Caller, ptr: u32, bytes: &[u8]) -> Result<(), Errno> {
+    caller.memory_set(ptr, bytes).map_err(|_| EFAULT)
+}
+
+fn write_u32(caller: &mut Caller, ptr: u32, value: u32) -> Result<(), Errno> {
+    write_bytes(caller, ptr, &value.to_le_bytes())
+}
+
+fn write_u64(caller: &mut Caller, ptr: u32, value: u64) -> Result<(), Errno> {
+    write_bytes(caller, ptr, &value.to_le_bytes())
+}
+
+/// Read the (buffer, length) pairs of an iovec array.
+fn read_iovecs(caller: &Caller, iovs: u32, iovs_len: u32) -> Result<Vec<(u32, usize)>, Errno> {
+    (0..iovs_len as usize)
+        .map(|i| {
+            let iov = address(iovs, i.checked_mul(8).ok_or(EFAULT)?)?;
+            let buf = read_u32(caller, iov)?;
+            let len = read_u32(caller, address(iov, 4)?)? as usize;
+            check_range(caller, buf, len)?;
+            Ok((buf, len))
+        })
+        .collect()
+}
+
+/// Read a guest string of `len` bytes at `ptr`.
+fn read_string(caller: &Caller, ptr: u32, len: u32) -> Result<String, Errno> {
+    check_range(caller, ptr, len as usize)?;
+    let mut bytes = vec![0u8; len as usize];
+    caller.memory_get(ptr, &mut bytes).map_err(|_| EFAULT)?;
+    String::from_utf8(bytes).map_err(|_| EILSEQ)
+}
+
+/// Convert the outcome of a host function implementation to the returned errno.
+fn errno(result: Result<(), Errno>) -> Result<u32, Trap> {
+    Ok(result.err().unwrap_or(ESUCCESS))
+}
+
+/// Write the `filestat` structure.
+fn write_filestat(caller: &mut Caller, ptr: u32, filestat: &Filestat) -> Result<(), Errno> {
+    // The layout of filestat: u64 device, u64 inode, u8 filetype at 16, u64 link count at 24,
+    // u64 size at 32, u64 access, modification and status change times at 40, 48 and 56.
+    let mut bytes = [0u8; 64];
+    bytes[0..8].copy_from_slice(&filestat.dev.to_le_bytes());
+    bytes[8..16].copy_from_slice(&filestat.ino.to_le_bytes());
+    bytes[16] = filestat.filetype;
+    bytes[24..32].copy_from_slice(&filestat.nlink.to_le_bytes());
+    bytes[32..40].copy_from_slice(&filestat.size.to_le_bytes());
+    bytes[40..48].copy_from_slice(&filestat.atim.to_le_bytes());
+    bytes[48..56].copy_from_slice(&filestat.mtim.to_le_bytes());
+    bytes[56..64].copy_from_slice(&filestat.ctim.to_le_bytes());
+    write_bytes(caller, ptr, &bytes)
+}
+
+fn string_list_sizes(
+    caller: &mut Caller,
+    list: &[Vec<u8>],
+    count_ptr: u32,
+    buf_size_ptr: u32,
+) -> Result<(), Errno> {
+    let buf_size: usize = list.iter().map(Vec::len).sum();
+    check_range(caller, count_ptr, 4)?;
+    check_range(caller, buf_size_ptr, 4)?;
+    write_u32(caller, count_ptr, list.len() as u32)?;
+    write_u32(caller, buf_size_ptr, buf_size as u32)
+}
+
+fn string_list_get(
+    caller: &mut Caller,
+    list: &[Vec<u8>],
+    ptrs_ptr: u32,
+    buf_ptr: u32,
+) -> Result<(), Errno> {
+    check_range(caller, ptrs_ptr, list.len() * 4)?;
+    check_range(caller, buf_ptr, list.iter().map(Vec::len).sum())?;
+    let mut offset = 0;
+    for (i, s) in list.iter().enumerate() {
+        let s_ptr = address(buf_ptr, offset)?;
+        write_u32(caller, address(ptrs_ptr, i * 4)?, s_ptr)?;
+        write_bytes(caller, s_ptr, s)?;
+        offset += s.len();
+    }
+    Ok(())
+}
+
+fn args_sizes_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    argc_ptr: u32,
+    buf_size_ptr: u32,
+) -> Result<(), Errno> {
+    string_list_sizes(caller, &state.args, argc_ptr, buf_size_ptr)
+}
+
+fn args_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    argv_ptr: u32,
+    buf_ptr: u32,
+) -> Result<(), Errno> {
+    string_list_get(caller, &state.args, argv_ptr, buf_ptr)
+}
+
+fn environ_sizes_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    count_ptr: u32,
+    buf_size_ptr: u32,
+) -> Result<(), Errno> {
+    string_list_sizes(caller, &state.env, count_ptr, buf_size_ptr)
+}
+
+fn environ_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    environ_ptr: u32,
+    buf_ptr: u32,
+) -> Result<(), Errno> {
+    string_list_get(caller, &state.env, environ_ptr, buf_ptr)
+}
+
+fn fd_write(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    iovs: u32,
+    iovs_len: u32,
+    nwritten_ptr: u32,
+) -> Result<(), Errno> {
+    // Validate all guest pointers before writing anything.
+    let iovecs = read_iovecs(caller, iovs, iovs_len)?;
+    check_range(caller, nwritten_ptr, 4)?;
+    let nwritten = iovecs
+        .iter()
+        .try_fold(0u32, |sum, (_, len)| {
+            sum.checked_add(u32::try_from(*len).ok()?)
+        })
+        .ok_or(EINVAL)?;
+    let output: &mut dyn Write = match state.fds.get(fd)? {
+        Descriptor::Output(output) => output,
+        Descriptor::File(file) => file,
+        Descriptor::Directory(_) => return Err(EISDIR),
+        Descriptor::Input(_) => return Err(EBADF),
+    };
+    let mut data = Vec::new();
+    for (buf, len) in iovecs {
+        data.resize(len, 0);
+        caller.memory_get(buf, &mut data).map_err(|_| EFAULT)?;
+        output.write_all(&data).map_err(|err| from_io_error(&err))?;
+    }
+    output.flush().map_err(|err| from_io_error(&err))?;
+    write_u32(caller, nwritten_ptr, nwritten)
+}
+
+fn fd_read(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    iovs: u32,
+    iovs_len: u32,
+    nread_ptr: u32,
+) -> Result<(), Errno> {
+    // Validate all guest pointers before consuming any input.
+    let iovecs = read_iovecs(caller, iovs, iovs_len)?;
+    check_range(caller, nread_ptr, 4)?;
+    let input: &mut dyn Read = match state.fds.get(fd)? {
+        Descriptor::Input(input) => input,
+        Descriptor::File(file) => file,
+        Descriptor::Directory(_) => return Err(EISDIR),
+        Descriptor::Output(_) => return Err(EBADF),
+    };
+    let mut nread: u32 = 0;
+    for (buf, len) in iovecs {
+        let mut data = vec![0u8; len];
+        let n = input.read(&mut data).map_err(|err| from_io_error(&err))?;
+        write_bytes(caller, buf, &data[..n])?;
+        nread = nread.checked_add(n as u32).ok_or(EINVAL)?;
+        if n < len {
+            break;
+        }
+    }
+    write_u32(caller, nread_ptr, nread)
+}
+
+fn fd_seek(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    offset: i64,
+    whence: u32,
+    newoffset_ptr: u32,
+) -> Result<(), Errno> {
+    let file = match state.fds.get(fd)? {
+        Descriptor::File(file) => file,
+        Descriptor::Directory(_) => return Err(EISDIR),
+        _ => return Err(ESPIPE),
+    };
+    check_range(caller, newoffset_ptr, 8)?;
+    let position = match whence {
+        0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| EINVAL)?),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return Err(EINVAL),
+    };
+    let newoffset = file.seek(position).map_err(|err| from_io_error(&err))?;
+    write_u64(caller, newoffset_ptr, newoffset)
+}
+
+fn fd_tell(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    offset_ptr: u32,
+) -> Result<(), Errno> {
+    fd_seek(caller, state, fd, 0, 1, offset_ptr)
+}
+
+fn fd_close(_: &mut Caller, state: &mut WasiState, fd: u32) -> Result<(), Errno> {
+    state.fds.remove(fd).map(|_| ())
+}
+
+fn fd_fdstat_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    fdstat_ptr: u32,
+) -> Result<(), Errno> {
+    let (filetype, rights) = match state.fds.get(fd)? {
+        Descriptor::Input(_) => (FILETYPE_CHARACTER_DEVICE, RIGHTS_FD_READ),
+        Descriptor::Output(_) => (FILETYPE_CHARACTER_DEVICE, RIGHTS_FD_WRITE),
+        Descriptor::Directory(_) => (FILETYPE_DIRECTORY, RIGHTS_ALL),
+        Descriptor::File(_) => (FILETYPE_REGULAR_FILE, RIGHTS_ALL),
+    };
+    // The layout of fdstat: u8 filetype, u16 flags at 2, u64 base rights at 8, u64 inheriting rights at 16.
+    let mut fdstat = [0u8; 24];
+    fdstat[0] = filetype;
+    fdstat[8..16].copy_from_slice(&rights.to_le_bytes());
+    fdstat[16..24].copy_from_slice(&rights.to_le_bytes());
+    write_bytes(caller, fdstat_ptr, &fdstat)
+}
+
+fn fd_filestat_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    filestat_ptr: u32,
+) -> Result<(), Errno> {
+    let filestat = match state.fds.get(fd)? {
+        Descriptor::File(file) => file.filestat()?,
+        Descriptor::Directory(directory) => directory.filestat()?,
+        _ => Filestat {
+            filetype: FILETYPE_CHARACTER_DEVICE,
+            ..Filestat::default()
+        },
+    };
+    write_filestat(caller, filestat_ptr, &filestat)
+}
+
+fn fd_prestat_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    prestat_ptr: u32,
+) -> Result<(), Errno> {
+    let guest_path = state
+        .fds
+        .directory(fd)
+        .map_err(|_| EBADF)?
+        .preopen
+        .as_ref()
+        .ok_or(EBADF)?;
+    // The layout of prestat: u8 type, u32 name length at 4.
+    let mut prestat = [0u8; 8];
+    prestat[0] = PREOPENTYPE_DIR;
+    prestat[4..8].copy_from_slice(&(guest_path.len() as u32).to_le_bytes());
+    write_bytes(caller, prestat_ptr, &prestat)
+}
+
+fn fd_prestat_dir_name(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    fd: u32,
+    path_ptr: u32,
+    path_len: u32,
+) -> Result<(), Errno> {
+    let guest_path = state
+        .fds
+        .directory(fd)
+        .map_err(|_| EBADF)?
+        .preopen
+        .as_ref()
+        .ok_or(EBADF)?;
+    if (path_len as usize) < guest_path.len() {
+        return Err(EINVAL);
+    }
+    write_bytes(caller, path_ptr, guest_path.as_bytes())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn path_open(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    dirfd: u32,
+    _dirflags: u32,
+    path_ptr: u32,
+    path_len: u32,
+    oflags: u32,
+    rights_base: u64,
+    _rights_inheriting: u64,
+    fdflags: u32,
+    fd_ptr: u32,
+) -> Result<(), Errno> {
+    let path = read_string(caller, path_ptr, path_len)?;
+    let write = rights_base & RIGHTS_FD_WRITE != 0 || oflags & OFLAGS_TRUNC != 0;
+    let flags = OpenFlags {
+        read: rights_base & RIGHTS_FD_READ != 0 || !write,
+        write,
+        append: fdflags & FDFLAGS_APPEND != 0,
+        create: oflags & OFLAGS_CREAT != 0,
+        exclusive: oflags & OFLAGS_EXCL != 0,
+        truncate: oflags & OFLAGS_TRUNC != 0,
+        directory: oflags & OFLAGS_DIRECTORY != 0,
+    };
+    let descriptor = state.fds.directory(dirfd)?.open(&path, &flags)?;
+    // Check the output pointer before opening the descriptor, so that it does not leak.
+    check_range(caller, fd_ptr, 4)?;
+    let fd = state.fds.insert(descriptor);
+    write_u32(caller, fd_ptr, fd)
+}
+
+fn path_filestat_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    dirfd: u32,
+    flags: u32,
+    path_ptr: u32,
+    path_len: u32,
+    filestat_ptr: u32,
+) -> Result<(), Errno> {
+    let path = read_string(caller, path_ptr, path_len)?;
+    let follow_symlinks = flags & LOOKUP_SYMLINK_FOLLOW != 0;
+    let filestat = state
+        .fds
+        .directory(dirfd)?
+        .path_filestat(&path, follow_symlinks)?;
+    write_filestat(caller, filestat_ptr, &filestat)
+}
+
+fn path_create_directory(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    dirfd: u32,
+    path_ptr: u32,
+    path_len: u32,
+) -> Result<(), Errno> {
+    let path = read_string(caller, path_ptr, path_len)?;
+    state.fds.directory(dirfd)?.create_directory(&path)
+}
+
+fn path_remove_directory(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    dirfd: u32,
+    path_ptr: u32,
+    path_len: u32,
+) -> Result<(), Errno> {
+    let path = read_string(caller, path_ptr, path_len)?;
+    state.fds.directory(dirfd)?.remove_directory(&path)
+}
+
+fn path_unlink_file(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    dirfd: u32,
+    path_ptr: u32,
+    path_len: u32,
+) -> Result<(), Errno> {
+    let path = read_string(caller, path_ptr, path_len)?;
+    state.fds.directory(dirfd)?.unlink_file(&path)
+}
+
+fn random_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    buf: u32,
+    buf_len: u32,
+) -> Result<(), Errno> {
+    check_range(caller, buf, buf_len as usize)?;
+    let mut data = vec![0u8; buf_len as usize];
+    match &mut state.random {
+        Some(random) => random.fill(&mut data),
+        None => fill_os_random(&mut data)?,
+    }
+    write_bytes(caller, buf, &data)
+}
+
+#[cfg(unix)]
+fn fill_os_random(data: &mut [u8]) -> Result<(), Errno> {
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(data))
+        .map_err(|_| EIO)
+}
+
+#[cfg(not(unix))]
+fn fill_os_random(_: &mut [u8]) -> Result<(), Errno> {
+    Err(ENOSYS)
+}
+
+fn clock_time_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    clock_id: u32,
+    _precision: u64,
+    time_ptr: u32,
+) -> Result<(), Errno> {
+    if clock_id > 3 {
+        return Err(EINVAL);
+    }
+    check_range(caller, time_ptr, 8)?;
+    let time = match state.clock {
+        // Realtime.
+        ClockSource::Real if clock_id == 0 => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| EIO)?
+            .as_nanos() as u64,
+        // Monotonic, process and thread CPU time, approximated by the time since instantiation.
+        ClockSource::Real => state.start.elapsed().as_nanos() as u64,
+        ClockSource::Fixed(time) => time,
+        ClockSource::Logical { start, step } => {
+            start.wrapping_add(state.clock_calls.wrapping_mul(step))
+        }
+    };
+    state.clock_calls += 1;
+    write_u64(caller, time_ptr, time)
+}
+
+fn clock_res_get(
+    caller: &mut Caller,
+    state: &mut WasiState,
+    clock_id: u32,
+    resolution_ptr: u32,
+) -> Result<(), Errno> {
+    if clock_id > 3 {
+        return Err(EINVAL);
+    }
+    let resolution = match state.clock {
+        ClockSource::Logical { step, .. } => step.max(1),
+        ClockSource::Real | ClockSource::Fixed(_) => 1,
+    };
+    write_u64(caller, resolution_ptr, resolution)
+}
+
+fn sched_yield(_: &mut Caller, _: &mut WasiState) -> Result<(), Errno> {
+    std::thread::yield_now();
+    Ok(())
+}
+
+/// The functions of `wasi_snapshot_preview1` which are not supported, with their inputs.
+/// These are provided to allow instantiation and return `ENOSYS`.
+const UNSUPPORTED_FUNCTIONS: &[(&str, &[ValueType])] = {
+    use ValueType::{I32, I64};
+    &[
+        ("fd_advise", &[I32, I64, I64, I32]),
+        ("fd_allocate", &[I32, I64, I64]),
+        ("fd_datasync", &[I32]),
+        ("fd_fdstat_set_flags", &[I32, I32]),
+        ("fd_fdstat_set_rights", &[I32, I64, I64]),
+        ("fd_filestat_set_size", &[I32, I64]),
+        ("fd_filestat_set_times", &[I32, I64, I64, I32]),
+        ("fd_pread", &[I32, I32, I32, I64, I32]),
+        ("fd_pwrite", &[I32, I32, I32, I64, I32]),
+        ("fd_readdir", &[I32, I32, I32, I64, I32]),
+        ("fd_renumber", &[I32, I32]),
+        ("fd_sync", &[I32]),
+        (
+            "path_filestat_set_times",
+            &[I32, I32, I32, I32, I64, I64, I32],
+        ),
+        ("path_link", &[I32, I32, I32, I32, I32, I32, I32]),
+        ("path_readlink", &[I32, I32, I32, I32, I32, I32]),
+        ("path_rename", &[I32, I32, I32, I32, I32, I32]),
+        ("path_symlink", &[I32, I32, I32, I32, I32]),
+        ("poll_oneoff", &[I32, I32, I32, I32]),
+        ("proc_raise", &[I32]),
+        ("sock_recv", &[I32, I32, I32, I32, I32, I32]),
+        ("sock_send", &[I32, I32, I32, I32, I32]),
+        ("sock_shutdown", &[I32, I32]),
+    ]
+};
+
+/// Register the host function `$func` of the same name, operating on the shared `$state`.
+macro_rules! add_function {
+    ($imports:ident, $state:ident, $func:ident($($arg:ident: $t:ty),*)) => {
+        let state = $state.clone();
+        $imports.func(
+            WASI_MODULE,
+            stringify!($func),
+            move |caller: &mut Caller, $($arg: $t),*| errno($func(caller, &mut lock(&state), $($arg),*)),
+        );
+    };
+}
+
+/// Register all WASI host functions operating on `state` in `imports`.
+pub(crate) fn add_functions(imports: &mut ImportsBuilder, state: WasiState) {
+    let state = Arc::new(Mutex::new(state));
+    add_function!(imports, state, args_sizes_get(argc_ptr: u32, buf_size_ptr: u32));
+    add_function!(imports, state, args_get(argv_ptr: u32, buf_ptr: u32));
+    add_function!(imports, state, environ_sizes_get(count_ptr: u32, buf_size_ptr: u32));
+    add_function!(imports, state, environ_get(environ_ptr: u32, buf_ptr: u32));
+    add_function!(imports, state, fd_write(fd: u32, iovs: u32, iovs_len: u32, nwritten_ptr: u32));
+    add_function!(imports, state, fd_read(fd: u32, iovs: u32, iovs_len: u32, nread_ptr: u32));
+    add_function!(imports, state, fd_seek(fd: u32, offset: i64, whence: u32, newoffset_ptr: u32));
+    add_function!(imports, state, fd_tell(fd: u32, offset_ptr: u32));
+    add_function!(imports, state, fd_close(fd: u32));
+    add_function!(imports, state, fd_fdstat_get(fd: u32, fdstat_ptr: u32));
+    add_function!(imports, state, fd_filestat_get(fd: u32, filestat_ptr: u32));
+    add_function!(imports, state, fd_prestat_get(fd: u32, prestat_ptr: u32));
+    add_function!(imports, state, fd_prestat_dir_name(fd: u32, path_ptr: u32, path_len: u32));
+    add_function!(
+        imports,
+        state,
+        path_open(
+            dirfd: u32,
+            dirflags: u32,
+            path_ptr: u32,
+            path_len: u32,
+            oflags: u32,
+            rights_base: u64,
+            rights_inheriting: u64,
+            fdflags: u32,
+            fd_ptr: u32
+        )
+    );
+    add_function!(
+        imports,
+        state,
+        path_filestat_get(dirfd: u32, flags: u32, path_ptr: u32, path_len: u32, filestat_ptr: u32)
+    );
+    add_function!(imports, state, path_create_directory(dirfd: u32, path_ptr: u32, path_len: u32));
+    add_function!(imports, state, path_remove_directory(dirfd: u32, path_ptr: u32, path_len: u32));
+    add_function!(imports, state, path_unlink_file(dirfd: u32, path_ptr: u32, path_len: u32));
+    add_function!(imports, state, random_get(buf: u32, buf_len: u32));
+    add_function!(imports, state, clock_time_get(clock_id: u32, precision: u64, time_ptr: u32));
+    add_function!(imports, state, clock_res_get(clock_id: u32, resolution_ptr: u32));
+    add_function!(imports, state, sched_yield());
+    imports.func(
+        WASI_MODULE,
+        "proc_exit",
+        |_: &mut Caller, code: u32| -> Result<(), Trap> { Err(Trap::exit(code)) },
+    );
+
+    for (name, inputs) in UNSUPPORTED_FUNCTIONS {
+        let func_type = FunctionType::new(inputs.to_vec(), Some(ValueType::I32));
+        imports.func_with_type(WASI_MODULE, name, func_type, |_, _| {
+            Ok(Some(TypedValue::U32(ENOSYS)))
+        });
+    }
+}
@@ -0,0 +1,977 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for running [WASI](https://wasi.dev) (`wasi_snapshot_preview1`) programs.
+//!
+//! The WASI functions are implemented in Rust as host functions.
+//!
+//! The C++ `fizzy-wasi` tool is not built on: it is a separate CMake project outside of the
+//! library archive and its C API, it fetches uvwasi and libuv at configure time, and it only
+//! provides `proc_exit`, `fd_read`, `fd_write` and the stubs of the prestat and environment
+//! functions. The Rust implementation adds the arguments, the environment, the preopened
+//! directories, the in-memory filesystem, and the deterministic clocks and random sources,
+//! without native dependencies beyond the engine, which matters for the static musl builds.
+//!
+//! The paths of the guest are resolved within the preopened directories: absolute paths and
+//! `..` leaving the directory are rejected with `ENOTCAPABLE`, and so are symbolic links
+//! pointing outside of it. The check is not atomic with the access, therefore a directory
+//! concurrently modified by another process is not a sandbox.
+//!
+//! ```no_run
+//! # use fizzy::wasi::WasiOutcome;
+//! let wasm = std::fs::read("hello.wasm").unwrap();
+//! match fizzy::wasi::run(&wasm, &["hello", "world"]) {
+//!     Ok(WasiOutcome::Finished) => println!("finished"),
+//!     Ok(WasiOutcome::Exited(code)) => println!("exited with code {}", code),
+//!     Ok(WasiOutcome::Trapped(info)) => println!("trapped: {}", info),
+//!     Err(err) => println!("failed to start: {}", err),
+//! }
+//! ```
+
+mod config;
+mod errno;
+mod fs;
+mod host;
+mod random;
+mod vfs;
+
+pub use config::{ClockSource, RandomSource, WasiConfig, WasiIn, WasiOut};
+pub use vfs::VirtualFs;
+
+use crate::{parse, Error, ImportsBuilder, Instance, Module, TrapInfo};
+
+/// The way the execution of a WASI program has ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WasiOutcome {
+    /// `_start` has returned.
+    Finished,
+    /// `proc_exit` has been called with the exit code.
+    Exited(u32),
+    /// The execution has trapped, either in WebAssembly code or in a host function.
+    Trapped(TrapInfo),
+}
+
+impl WasiOutcome {
+    /// The exit code, which is 0 if `_start` has returned, and `None` if the execution has trapped.
+    pub fn exit_code(&self) -> Option<u32> {
+        match self {
+            WasiOutcome::Finished => Some(0),
+            WasiOutcome::Exited(code) => Some(*code),
+            WasiOutcome::Trapped(_) => None,
+        }
+    }
+}
+
+/// The result of running a WASI program with [`run_with_config`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct WasiRun {
+    outcome: WasiOutcome,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl WasiRun {
+    /// The way the execution has ended.
+    pub fn outcome(&self) -> &WasiOutcome {
+        &self.outcome
+    }
+
+    /// The bytes written to the standard output, if configured as [`WasiOut::Buffer`].
+    pub fn stdout_bytes(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// The bytes written to the standard error, if configured as [`WasiOut::Buffer`].
+    pub fn stderr_bytes(&self) -> &[u8] {
+        &self.stderr
+    }
+}
+
+fn add_capturing(
+    builder: &mut ImportsBuilder,
+    config: &WasiConfig,
+) -> Result<host::CapturedOutput, Error> {
+    let (state, captured) = host::WasiState::new(config)?;
+    builder.provide("WASI", |builder| host::add_functions(builder, state));
+    Ok(captured)
+}
+
+/// Register the WASI functions for the environment described by `config` in `builder`,
+/// so that they can be combined with other host functions.
+///
+/// Functions registered explicitly in `builder` under the same names override the WASI functions,
+/// see [`ImportsBuilder::on_warning`] to be notified about this.
+///
+/// ```
+/// use fizzy::{Caller, ImportsBuilder, Trap};
+/// use fizzy::wasi::WasiConfig;
+///
+/// let mut imports = ImportsBuilder::new();
+/// fizzy::wasi::add_to_builder(&mut imports, &WasiConfig::new().arg("prog")).unwrap();
+/// imports.func("env", "host_add", |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> {
+///     Ok(a.wrapping_add(b))
+/// });
+/// ```
+pub fn add_to_builder(builder: &mut ImportsBuilder, config: &WasiConfig) -> Result<(), Error> {
+    add_capturing(builder, config).map(|_| ())
+}
+
+fn instantiate_capturing(
+    module: Module,
+    config: &WasiConfig,
+) -> Result<(Instance, host::CapturedOutput), Error> {
+    let mut imports = ImportsBuilder::new();
+    let captured = add_capturing(&mut imports, config)?;
+    Ok((module.instantiate_with_imports(imports)?, captured))
+}
+
+/// Instantiate the WASI program `module` in the environment described by `config`.
+///
+/// No guest code is run, so besides running a command with [`start`], the exports of a reactor
+/// program can be called with [`Instance::execute`], after calling its `_initialize` export if present.
+/// The memory of the instance remains accessible after the execution.
+///
+/// Fails before running any guest code if the configuration is invalid, e.g. a preopened
+/// directory does not exist. Output captured with [`WasiOut::Buffer`] is only accessible
+/// when running the program with [`run_with_config`].
+pub fn instantiate(module: Module, config: &WasiConfig) -> Result<Instance, Error> {
+    instantiate_capturing(module, config).map(|(instance, _)| instance)
+}
+
+/// Run the `_start` function of an instantiated WASI program.
+///
+/// An exit via `proc_exit` is not considered a trap. Fails only if `_start` cannot be executed.
+pub fn start(instance: &mut Instance) -> Result<WasiOutcome, Error> {
+    let result = instance.execute("_start", &[])?;
+    if !result.trapped() {
+        return Ok(WasiOutcome::Finished);
+    }
+    let trap = instance.take_host_trap();
+    if let Some(code) = trap.as_ref().and_then(|trap| trap.exit_code()) {
+        return Ok(WasiOutcome::Exited(code));
+    }
+    Ok(WasiOutcome::Trapped(TrapInfo::new("_start", trap)))
+}
+
+/// Instantiate the WASI program `wasm` and run its `_start` function, passing `args` as its arguments.
+///
+/// The first argument is the program name by convention. An exit via `proc_exit` is not considered a trap.
+/// Fails if the program cannot be parsed, instantiated or started.
+pub fn run(wasm: &[u8], args: &[&str]) -> Result<WasiOutcome, Error> {
+    let config = WasiConfig::new().args(args.iter().copied());
+    run_with_config(wasm, &config).map(|run| run.outcome)
+}
+
+/// Instantiate the WASI program `wasm` in the environment described by `config` and run its
+/// `_start` function.
+///
+/// ```
+/// # use fizzy::wasi::{WasiConfig, WasiOut};
+/// # fn main() -> Result<(), fizzy::Error> {
+/// # let wasm = [];
+/// let config = WasiConfig::new().arg("prog").stdout(WasiOut::Buffer);
+/// # if false {
+/// let run = fizzy::wasi::run_with_config(&wasm, &config)?;
+/// println!("{}", String::from_utf8_lossy(run.stdout_bytes()));
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_with_config(wasm: &[u8], config: &WasiConfig) -> Result<WasiRun, Error> {
+    let module = parse(&wasm)?;
+    let (mut instance, captured) = instantiate_capturing(module, config)?;
+    let outcome = start(&mut instance)?;
+    Ok(WasiRun {
+        outcome,
+        stdout: captured.take_stdout(),
+        stderr: captured.take_stderr(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Caller, Trap, TrapKind, TypedValue};
+
+    /// Create a new empty directory for a test.
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("fizzy-wasi-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn exit_zero() {
+        /* wat2wasm
+        (module
+          (func $proc_exit (import "wasi_snapshot_preview1" "proc_exit") (param i32))
+          (memory (export "memory") 1)
+          (func (export "_start") (call $proc_exit (i32.const 0)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001080260017f0060000002240116776173695f736e617073686f745f70726576696577310970726f635f657869740000030201010503010001071302066d656d6f72790200065f737461727400010a08010600410010000b").unwrap();
+        assert_eq!(run(&input, &["prog"]), Ok(WasiOutcome::Exited(0)));
+    }
+
+    #[test]
+    fn exit_code() {
+        /* wat2wasm
+        (module
+          (func $proc_exit (import "wasi_snapshot_preview1" "proc_exit") (param i32))
+          (memory (export "memory") 1)
+          (func (export "_start") (call $proc_exit (i32.const 3)) unreachable)
+        )
+        */
+        let input = hex::decode("0061736d0100000001080260017f0060000002240116776173695f736e617073686f745f70726576696577310970726f635f657869740000030201010503010001071302066d656d6f72790200065f737461727400010a0901070041031000000b").unwrap();
+        let outcome = run(&input, &["prog"]).unwrap();
+        assert_eq!(outcome, WasiOutcome::Exited(3));
+        assert_eq!(outcome.exit_code(), Some(3));
+    }
+
+    #[test]
+    fn start_returns() {
+        /* wat2wasm
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start"))
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000030201000503010001071302066d656d6f72790200065f737461727400000a040102000b").unwrap();
+        let outcome = run(&input, &[]).unwrap();
+        assert_eq!(outcome, WasiOutcome::Finished);
+        assert_eq!(outcome.exit_code(), Some(0));
+    }
+
+    #[test]
+    fn trap() {
+        /* wat2wasm
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start") unreachable)
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000030201000503010001071302066d656d6f72790200065f737461727400000a05010300000b").unwrap();
+        let outcome = run(&input, &["prog"]).unwrap();
+        assert_eq!(outcome.exit_code(), None);
+        match outcome {
+            WasiOutcome::Trapped(info) => {
+                assert_eq!(info.function(), Some("_start"));
+                assert_eq!(info.kind(), &TrapKind::Wasm);
+            }
+            _ => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn host_trap() {
+        /* wat2wasm
+        (module
+          (func $fd_read (import "wasi_snapshot_preview1" "fd_read") (param i32 i32 i32 i32) (result i32))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "\10\00\00\00\10\00\00\00")
+          (func (export "_start")
+            (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010c0260047f7f7f7f017f60000002220116776173695f736e617073686f745f70726576696577310766645f726561640000030201010503010001071302066d656d6f72790200065f737461727400010a0f010d00410041004101410810001a0b0b0e010041000b081000000010000000").unwrap();
+
+        struct FailingReader;
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                panic!("stdin is broken")
+            }
+        }
+        let config = WasiConfig::new().stdin(WasiIn::Reader(Box::new(FailingReader)));
+        let run = run_with_config(&input, &config).unwrap();
+        match run.outcome() {
+            WasiOutcome::Trapped(info) => {
+                assert_eq!(info.function(), Some("_start"));
+                assert_eq!(
+                    info.kind(),
+                    &TrapKind::Host("host function panicked: stdin is broken".to_string())
+                );
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn args() {
+        /* wat2wasm
+        (module
+          (func $args_sizes_get (import "wasi_snapshot_preview1" "args_sizes_get") (param i32 i32) (result i32))
+          (func $args_get (import "wasi_snapshot_preview1" "args_get") (param i32 i32) (result i32))
+          (func $proc_exit (import "wasi_snapshot_preview1" "proc_exit") (param i32))
+          (memory (export "memory") 1)
+          ;; Exits with argc << 16 | args buffer size << 8 | the first byte of the last argument.
+          (func (export "_start")
+            (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+            (drop (call $args_get (i32.const 16) (i32.const 256)))
+            (call $proc_exit
+              (i32.or
+                (i32.or
+                  (i32.shl (i32.load (i32.const 0)) (i32.const 16))
+                  (i32.shl (i32.load (i32.const 4)) (i32.const 8)))
+                (i32.load8_u
+                  (i32.load offset=12 (i32.shl (i32.load (i32.const 0)) (i32.const 2))))))
+          )
+        )
+        */
+        let input = hex::decode("0061736d01000000010e0360027f7f017f60017f00600000026e0316776173695f736e617073686f745f70726576696577310e617267735f73697a65735f676574000016776173695f736e617073686f745f707265766965773108617267735f676574000016776173695f736e617073686f745f70726576696577310970726f635f657869740001030201020503010001071302066d656d6f72790200065f737461727400030a350133004100410410001a411041800210011a4100280200411074410428020041087472410028020041027428020c2d00007210020b").unwrap();
+        assert_eq!(
+            run(&input, &["prog"]),
+            Ok(WasiOutcome::Exited(1 << 16 | 5 << 8 | b'p' as u32))
+        );
+        assert_eq!(
+            run(&input, &["prog", "", "xyz"]),
+            Ok(WasiOutcome::Exited(3 << 16 | 10 << 8 | b'x' as u32))
+        );
+        // Non-ASCII arguments are passed as UTF-8.
+        assert_eq!(
+            run(&input, &["prog", "\u{e9}"]),
+            Ok(WasiOutcome::Exited(2 << 16 | 8 << 8 | 0xc3))
+        );
+        assert_eq!(
+            run(&input, &["prog", "a\0b"]),
+            Err(Error::InstantiationFailed(
+                "WASI argument contains a NUL byte".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn args_and_env() {
+        /* wat2wasm
+        (module
+          (func $args_sizes_get (import "wasi_snapshot_preview1" "args_sizes_get") (param i32 i32) (result i32))
+          (func $args_get (import "wasi_snapshot_preview1" "args_get") (param i32 i32) (result i32))
+          (func $environ_sizes_get (import "wasi_snapshot_preview1" "environ_sizes_get") (param i32 i32) (result i32))
+          (func $environ_get (import "wasi_snapshot_preview1" "environ_get") (param i32 i32) (result i32))
+          (memory (export "memory") 1)
+          ;; Stores the counts and buffer sizes at 0..16, the pointers at 16..64 and 64..128,
+          ;; the strings at 256 and 512.
+          (func (export "_start")
+            (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+            (drop (call $args_get (i32.const 16) (i32.const 256)))
+            (drop (call $environ_sizes_get (i32.const 8) (i32.const 12)))
+            (drop (call $environ_get (i32.const 64) (i32.const 512)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d01000000010a0260027f7f017f600000029b010416776173695f736e617073686f745f70726576696577310e617267735f73697a65735f676574000016776173695f736e617073686f745f707265766965773108617267735f676574000016776173695f736e617073686f745f707265766965773111656e7669726f6e5f73697a65735f676574000016776173695f736e617073686f745f70726576696577310b656e7669726f6e5f6765740000030201010503010001071302066d656d6f72790200065f737461727400040a230121004100410410001a411041800210011a4108410c10021a41c00041800410031a0b").unwrap();
+        let config = WasiConfig::new()
+            .arg("prog")
+            .arg("--flag")
+            .env("KEY", "VALUE")
+            .env("EMPTY", "");
+        let mut instance = instantiate(parse(&input).unwrap(), &config).unwrap();
+        assert_eq!(start(&mut instance), Ok(WasiOutcome::Finished));
+
+        let read_u32 = |offset| {
+            let mut bytes = [0u8; 4];
+            instance.memory_get(offset, &mut bytes).unwrap();
+            u32::from_le_bytes(bytes)
+        };
+        assert_eq!(read_u32(0), 2);
+        assert_eq!(read_u32(4), 12);
+        assert_eq!(read_u32(8), 2);
+        assert_eq!(read_u32(12), 17);
+        assert_eq!(read_u32(16), 256);
+        assert_eq!(read_u32(20), 261);
+        assert_eq!(read_u32(64), 512);
+        assert_eq!(read_u32(68), 522);
+
+        let mut args = [0u8; 12];
+        instance.memory_get(256, &mut args).unwrap();
+        assert_eq!(&args, b"prog\0--flag\0");
+        let mut env = [0u8; 17];
+        instance.memory_get(512, &mut env).unwrap();
+        assert_eq!(&env, b"KEY=VALUE\0EMPTY=\0");
+    }
+
+    #[test]
+    fn invalid_config() {
+        /* wat2wasm
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start") unreachable)
+        )
+        */
+        let input = hex::decode("0061736d01000000010401600000030201000503010001071302066d656d6f72790200065f737461727400000a05010300000b").unwrap();
+        let instantiate_with =
+            |config: &WasiConfig| instantiate(parse(&input).unwrap(), config).err().unwrap();
+
+        assert_eq!(
+            instantiate_with(&WasiConfig::new().env("A=B", "C")),
+            Error::InstantiationFailed("invalid WASI environment variable \"A=B\"".to_string())
+        );
+        assert_eq!(
+            instantiate_with(&WasiConfig::new().env("A", "\0")),
+            Error::InstantiationFailed("invalid WASI environment variable \"A\"".to_string())
+        );
+
+        let missing = temp_dir("invalid-config").join("missing");
+        match instantiate_with(&WasiConfig::new().preopen_dir(&missing, "/sandbox")) {
+            Error::InstantiationFailed(message) => assert!(
+                message.starts_with(&format!("cannot preopen directory {}: ", missing.display())),
+                "unexpected message: {}",
+                message
+            ),
+            err => panic!("unexpected error: {}", err),
+        }
+        std::fs::remove_dir_all(missing.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn preopened_dir() {
+        /* wat2wasm
+        (module
+          (func $fd_prestat_get (import "wasi_snapshot_preview1" "fd_prestat_get") (param i32 i32) (result i32))
+          (func $fd_prestat_dir_name (import "wasi_snapshot_preview1" "fd_prestat_dir_name") (param i32 i32 i32) (result i32))
+          (func $path_open (import "wasi_snapshot_preview1" "path_open") (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32))
+          (func $fd_read (import "wasi_snapshot_preview1" "fd_read") (param i32 i32 i32 i32) (result i32))
+          (func $fd_close (import "wasi_snapshot_preview1" "fd_close") (param i32) (result i32))
+          (memory (export "memory") 1)
+          (data (i32.const 128) "hello.txt../x")
+          ;; Stores the errnos at 0..24, the prestat at 32, the directory name at 48,
+          ;; the opened fd at 64, the iovec at 72, the number of bytes read at 80, the contents at 256.
+          (func (export "_start")
+            (i32.store (i32.const 0) (call $fd_prestat_get (i32.const 3) (i32.const 32)))
+            (i32.store (i32.const 4) (call $fd_prestat_dir_name (i32.const 3) (i32.const 48) (i32.const 16)))
+            (i32.store (i32.const 8)
+              (call $path_open (i32.const 3) (i32.const 0) (i32.const 128) (i32.const 9) (i32.const 0)
+                (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 64)))
+            (i32.store (i32.const 72) (i32.const 256))
+            (i32.store (i32.const 76) (i32.const 64))
+            (i32.store (i32.const 12) (call $fd_read (i32.load (i32.const 64)) (i32.const 72) (i32.const 1) (i32.const 80)))
+            (i32.store (i32.const 16) (call $fd_close (i32.load (i32.const 64))))
+            (i32.store (i32.const 20)
+              (call $path_open (i32.const 3) (i32.const 0) (i32.const 137) (i32.const 4) (i32.const 0)
+                (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 64)))
+            (i32.store (i32.const 24) (call $fd_prestat_get (i32.const 4) (i32.const 32)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d01000000012b0660027f7f017f60037f7f7f017f60097f7f7f7f7f7e7e7f7f017f60047f7f7f7f017f60017f017f60000002bc010516776173695f736e617073686f745f70726576696577310e66645f707265737461745f676574000016776173695f736e617073686f745f70726576696577311366645f707265737461745f6469725f6e616d65000116776173695f736e617073686f745f707265766965773109706174685f6f70656e000216776173695f736e617073686f745f70726576696577310766645f72656164000316776173695f736e617073686f745f70726576696577310866645f636c6f73650004030201050503010001071302066d656d6f72790200065f737461727400050a9201018f01004100410341201000360200410441034130411010013602004108410341004180014109410042024200410041c000100236020041c80041800236020041cc0041c000360200410c41c00028020041c800410141d0001003360200411041c00028020010043602004114410341004189014104410042024200410041c000100236020041184104412010003602000b0b1401004180010b0d68656c6c6f2e7478742e2e2f78").unwrap();
+        let dir = temp_dir("preopened-dir");
+        std::fs::write(dir.join("hello.txt"), "Hello, WASI!").unwrap();
+
+        let config = WasiConfig::new().preopen_dir(&dir, "/sandbox");
+        let mut instance = instantiate(parse(&input).unwrap(), &config).unwrap();
+        assert_eq!(start(&mut instance), Ok(WasiOutcome::Finished));
+
+        let read_u32 = |offset| {
+            let mut bytes = [0u8; 4];
+            instance.memory_get(offset, &mut bytes).unwrap();
+            u32::from_le_bytes(bytes)
+        };
+        let errnos: Vec<u32> = (0..7).map(|i| read_u32(i * 4)).collect();
+        // fd_read and fd_close of the opened file succeed, of "../x" ENOTCAPABLE, fd 4 is not open.
+        assert_eq!(errnos, [0, 0, 0, 0, 0, 76, 8]);
+        // The prestat of a directory with a name of 8 bytes.
+        assert_eq!(read_u32(32), 0);
+        assert_eq!(read_u32(36), 8);
+        let mut name = [0u8; 8];
+        instance.memory_get(48, &mut name).unwrap();
+        assert_eq!(&name, b"/sandbox");
+        // The file is opened as the lowest free descriptor.
+        assert_eq!(read_u32(64), 4);
+        assert_eq!(read_u32(80), 12);
+        let mut contents = [0u8; 12];
+        instance.memory_get(256, &mut contents).unwrap();
+        assert_eq!(&contents, b"Hello, WASI!");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn captured_output() {
+        /* wat2wasm
+        (module
+          (func $fd_write (import "wasi_snapshot_preview1" "fd_write") (param i32 i32 i32 i32) (result i32))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "\00\01\00\00\06\00\00\00")
+          (data (i32.const 8) "\06\01\00\00\07\00\00\00")
+          (data (i32.const 16) "\10\01\00\00\03\00\00\00")
+          (data (i32.const 256) "Hello,")
+          (data (i32.const 262) " world!")
+          (data (i32.const 272) "\ff\fe\00")
+          ;; Writes "Hello, world!" to stdout in two calls with three iovecs, and 3 bytes of invalid UTF-8 to stderr.
+          (func (export "_start")
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 64)))
+            (drop (call $fd_write (i32.const 2) (i32.const 16) (i32.const 1) (i32.const 64)))
+            (drop (call $fd_write (i32.const 1) (i32.const 8) (i32.const 1) (i32.const 64)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d01000000010c0260047f7f7f7f017f60000002230116776173695f736e617073686f745f70726576696577310866645f77726974650000030201010503010001071302066d656d6f72790200065f737461727400010a2801260041014100410141c00010001a41024110410141c00010001a41014108410141c00010001a0b0b4a060041000b0800010000060000000041080b0806010000070000000041100b081001000003000000004180020b0648656c6c6f2c004186020b0720776f726c6421004190020b03fffe00").unwrap();
+
+        let config = WasiConfig::new()
+            .stdout(WasiOut::Buffer)
+            .stderr(WasiOut::Buffer);
+        let run = run_with_config(&input, &config).unwrap();
+        assert_eq!(run.outcome(), &WasiOutcome::Finished);
+        assert_eq!(run.stdout_bytes(), b"Hello, world!");
+        assert_eq!(run.stderr_bytes(), b"\xff\xfe\x00");
+
+        let run = run_with_config(&input, &WasiConfig::new().stderr(WasiOut::Buffer)).unwrap();
+        assert_eq!(run.stdout_bytes(), b"");
+        assert_eq!(run.stderr_bytes(), b"\xff\xfe\x00");
+    }
+
+    #[test]
+    fn stdin() {
+        /* wat2wasm
+        (module
+          (func $fd_read (import "wasi_snapshot_preview1" "fd_read") (param i32 i32 i32 i32) (result i32))
+          (func $fd_write (import "wasi_snapshot_preview1" "fd_write") (param i32 i32 i32 i32) (result i32))
+          (func $proc_exit (import "wasi_snapshot_preview1" "proc_exit") (param i32))
+          (memory (export "memory") 1)
+          ;; Copies stdin to stdout in chunks of at most 16 bytes, exits with the errno of a failed call.
+          (func (export "_start") (local $errno i32)
+            (i32.store (i32.const 0) (i32.const 256))
+            (loop $copy
+              (i32.store (i32.const 4) (i32.const 16))
+              (local.set $errno (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 4)))
+              (if (local.get $errno) (then (call $proc_exit (local.get $errno))))
+              (if (i32.load (i32.const 4))
+                (then
+                  (local.set $errno (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                  (if (local.get $errno) (then (call $proc_exit (local.get $errno))))
+                  (br $copy))))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001100360047f7f7f7f017f60017f0060000002670316776173695f736e617073686f745f70726576696577310766645f72656164000016776173695f736e617073686f745f70726576696577310866645f7772697465000016776173695f736e617073686f745f70726576696577310970726f635f657869740001030201020503010001071302066d656d6f72790200065f737461727400030a4c014a01017f410041800236020003404104411036020041004100410141041000210020000440200010020b4104280200044041014100410141081001210020000440200010020b0c010b0b0b").unwrap();
+        let echo = |stdin: WasiIn| {
+            let config = WasiConfig::new().stdin(stdin).stdout(WasiOut::Buffer);
+            let run = run_with_config(&input, &config).unwrap();
+            assert_eq!(run.outcome(), &WasiOutcome::Finished);
+            run.stdout_bytes().to_vec()
+        };
+
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        assert_eq!(echo(WasiIn::Bytes(data.clone())), data);
+        assert_eq!(echo(WasiIn::Bytes(Vec::new())), b"");
+
+        /// A reader returning at most 3 bytes at a time, like a pipe.
+        struct Trickle(std::io::Cursor<Vec<u8>>);
+        impl std::io::Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.read(&mut buf[..len])
+            }
+        }
+        let reader = Trickle(std::io::Cursor::new(data.clone()));
+        assert_eq!(echo(WasiIn::Reader(Box::new(reader))), data);
+
+        // A configuration with a reader shares it, so it is exhausted after the first run.
+        let config = WasiConfig::new()
+            .stdin(WasiIn::Reader(Box::new(&b"once"[..])))
+            .stdout(WasiOut::Buffer);
+        let run = run_with_config(&input, &config).unwrap();
+        assert_eq!(run.stdout_bytes(), b"once");
+        let run = run_with_config(&input, &config).unwrap();
+        assert_eq!(run.stdout_bytes(), b"");
+    }
+
+    #[test]
+    fn deterministic_random() {
+        /* wat2wasm
+        (module
+          (func $random_get (import "wasi_snapshot_preview1" "random_get") (param i32 i32) (result i32))
+          (func $fd_write (import "wasi_snapshot_preview1" "fd_write") (param i32 i32 i32 i32) (result i32))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "\00\01\00\00\30\00\00\00")
+          ;; Writes 16 and then 32 random bytes to stdout.
+          (func (export "_start")
+            (drop (call $random_get (i32.const 256) (i32.const 16)))
+            (drop (call $random_get (i32.const 272) (i32.const 32)))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001120360027f7f017f60047f7f7f7f017f60000002470216776173695f736e617073686f745f70726576696577310a72616e646f6d5f676574000016776173695f736e617073686f745f70726576696577310866645f77726974650001030201020503010001071302066d656d6f72790200065f737461727400020a1f011d00418002411010001a419002412010001a410141004101410810011a0b0b0e010041000b080001000030000000").unwrap();
+        let random_bytes = |source: RandomSource| {
+            let config = WasiConfig::new()
+                .random_source(source)
+                .stdout(WasiOut::Buffer);
+            run_with_config(&input, &config)
+                .unwrap()
+                .stdout_bytes()
+                .to_vec()
+        };
+
+        let seed = RandomSource::Deterministic { seed: [0; 32] };
+        let bytes = random_bytes(seed);
+        // The beginning of the ChaCha20 keystream of the zero key.
+        assert_eq!(
+            hex::encode(&bytes),
+            "76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7da41597c5157488d7724e03fb8d84a37"
+        );
+        assert_eq!(random_bytes(seed), bytes);
+
+        let mut other_seed = [0; 32];
+        other_seed[31] = 1;
+        let other_bytes = random_bytes(RandomSource::Deterministic { seed: other_seed });
+        assert_eq!(other_bytes.len(), 48);
+        assert_ne!(other_bytes, bytes);
+        assert_eq!(
+            random_bytes(RandomSource::Deterministic { seed: other_seed }),
+            other_bytes
+        );
+
+        assert_eq!(random_bytes(RandomSource::Os).len(), 48);
+    }
+
+    #[test]
+    fn synthetic_clocks() {
+        /* wat2wasm
+        (module
+          (func $clock_time_get (import "wasi_snapshot_preview1" "clock_time_get") (param i32 i64 i32) (result i32))
+          (func $clock_res_get (import "wasi_snapshot_preview1" "clock_res_get") (param i32 i32) (result i32))
+          (memory (export "memory") 1)
+          ;; Stores the resolution of the monotonic clock at 0, then samples the realtime and the monotonic
+          ;; clocks alternately 4 times into 8..40.
+          (func (export "_start") (local $i i32)
+            (drop (call $clock_res_get (i32.const 1) (i32.const 0)))
+            (loop $sample
+              (drop (call $clock_time_get (i32.and (local.get $i) (i32.const 1)) (i64.const 0)
+                (i32.add (i32.const 8) (i32.shl (local.get $i) (i32.const 3)))))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br_if $sample (i32.lt_u (local.get $i) (i32.const 4))))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001110360037f7e7f017f60027f7f017f60000002500216776173695f736e617073686f745f70726576696577310e636c6f636b5f74696d655f676574000016776173695f736e617073686f745f70726576696577310d636c6f636b5f7265735f6765740001030201020503010001071302066d656d6f72790200065f737461727400020a30012e01017f4101410010011a034020004101714200410820004103746a10001a200041016a210020004104490d000b0b").unwrap();
+        let sample = |clock: ClockSource| {
+            let mut instance =
+                instantiate(parse(&input).unwrap(), &WasiConfig::new().clock(clock)).unwrap();
+            assert_eq!(start(&mut instance), Ok(WasiOutcome::Finished));
+            let mut bytes = [0u8; 40];
+            instance.memory_get(0, &mut bytes).unwrap();
+            bytes
+                .chunks_exact(8)
+                .map(|chunk| {
+                    let mut value = [0u8; 8];
+                    value.copy_from_slice(chunk);
+                    u64::from_le_bytes(value)
+                })
+                .collect::<Vec<u64>>()
+        };
+
+        assert_eq!(
+            sample(ClockSource::Logical {
+                start: 1_000_000_000,
+                step: 1000
+            }),
+            [
+                1000,
+                1_000_000_000,
+                1_000_001_000,
+                1_000_002_000,
+                1_000_003_000
+            ]
+        );
+        assert_eq!(
+            sample(ClockSource::Logical {
+                start: u64::MAX,
+                step: 0
+            }),
+            [1, u64::MAX, u64::MAX, u64::MAX, u64::MAX]
+        );
+        assert_eq!(sample(ClockSource::Fixed(42)), [1, 42, 42, 42, 42]);
+
+        let real = sample(ClockSource::Real);
+        assert_eq!(real[0], 1);
+        assert!(real[1] > 0 && real[3] >= real[1]);
+        assert!(real[4] >= real[2]);
+    }
+
+    #[test]
+    fn out_of_bounds_pointers() {
+        /* wat2wasm
+        (module
+          (func $fd_write (import "wasi_snapshot_preview1" "fd_write") (param i32 i32 i32 i32) (result i32))
+          (func $fd_read (import "wasi_snapshot_preview1" "fd_read") (param i32 i32 i32 i32) (result i32))
+          (func $args_sizes_get (import "wasi_snapshot_preview1" "args_sizes_get") (param i32 i32) (result i32))
+          (func $args_get (import "wasi_snapshot_preview1" "args_get") (param i32 i32) (result i32))
+          (memory (export "memory") 1)
+          ;; The iovec at 0 is valid, at 16 crosses the end of the memory, at 24 wraps around the address space.
+          (data (i32.const 0) "\08\00\00\00\05\00\00\00hello")
+          (data (i32.const 16) "\00\ff\00\00\00\02\00\00")
+          (data (i32.const 24) "\00\00\ff\ff\00\00\01\00")
+          (func (export "write") (param i32 i32 i32) (result i32)
+            (call $fd_write (i32.const 1) (local.get 0) (local.get 1) (local.get 2)))
+          (func (export "read") (param i32 i32 i32) (result i32)
+            (call $fd_read (i32.const 0) (local.get 0) (local.get 1) (local.get 2)))
+          (func (export "args_sizes_get") (param i32 i32) (result i32)
+            (call $args_sizes_get (local.get 0) (local.get 1)))
+          (func (export "args_get") (param i32 i32) (result i32)
+            (call $args_get (local.get 0) (local.get 1)))
+        )
+        */
+        let input = hex::decode("0061736d0100000001160360047f7f7f7f017f60027f7f017f60037f7f7f017f028e010416776173695f736e617073686f745f70726576696577310866645f7772697465000016776173695f736e617073686f745f70726576696577310766645f72656164000016776173695f736e617073686f745f70726576696577310e617267735f73697a65735f676574000116776173695f736e617073686f745f707265766965773108617267735f6765740001030504020201010503010001073505066d656d6f727902000577726974650004047265616400050e617267735f73697a65735f676574000608617267735f67657400070a2d040c00410120002001200210000b0c00410020002001200210010b08002000200110020b08002000200110030b0b2d030041000b0d080000000500000068656c6c6f0041100b0800ff0000000200000041180b080000ffff00000100").unwrap();
+        let config = WasiConfig::new()
+            .arg("prog")
+            .stdin(WasiIn::Bytes(b"abc".to_vec()))
+            .stdout(WasiOut::Buffer);
+        let (mut instance, captured) =
+            instantiate_capturing(parse(&input).unwrap(), &config).unwrap();
+        fn call(instance: &mut Instance, name: &str, args: &[u32]) -> u32 {
+            let args: Vec<TypedValue> = args.iter().map(|arg| TypedValue::U32(*arg)).collect();
+            let result = instance.execute(name, &args).unwrap();
+            assert!(!result.trapped());
+            result.value().unwrap().as_u32().unwrap()
+        }
+        const EFAULT: u32 = 21;
+
+        // The iovec array is out of bounds.
+        assert_eq!(call(&mut instance, "write", &[65536, 1, 100]), EFAULT);
+        assert_eq!(call(&mut instance, "write", &[65532, 1, 100]), EFAULT);
+        assert_eq!(call(&mut instance, "write", &[0, 0x2000_0000, 100]), EFAULT);
+        assert_eq!(call(&mut instance, "write", &[0xffff_fffc, 1, 100]), EFAULT);
+        // The buffers are out of bounds.
+        assert_eq!(call(&mut instance, "write", &[16, 1, 100]), EFAULT);
+        assert_eq!(call(&mut instance, "write", &[24, 1, 100]), EFAULT);
+        assert_eq!(call(&mut instance, "write", &[0, 2, 100]), EFAULT);
+        // The output pointer is out of bounds, nothing is written.
+        assert_eq!(call(&mut instance, "write", &[0, 1, 65533]), EFAULT);
+        assert_eq!(call(&mut instance, "write", &[0, 1, 0xffff_ffff]), EFAULT);
+        assert_eq!(captured.take_stdout(), b"");
+        assert_eq!(call(&mut instance, "write", &[0, 1, 100]), 0);
+        assert_eq!(captured.take_stdout(), b"hello");
+
+        // No input is consumed if the output pointer is out of bounds.
+        assert_eq!(call(&mut instance, "read", &[0, 1, 65533]), EFAULT);
+        assert_eq!(call(&mut instance, "read", &[16, 1, 100]), EFAULT);
+        assert_eq!(call(&mut instance, "read", &[0, 1, 100]), 0);
+        let mut data = [0u8; 5];
+        instance.memory_get(8, &mut data).unwrap();
+        assert_eq!(&data, b"abclo");
+
+        assert_eq!(call(&mut instance, "args_sizes_get", &[200, 65533]), EFAULT);
+        assert_eq!(call(&mut instance, "args_sizes_get", &[65536, 200]), EFAULT);
+        assert_eq!(call(&mut instance, "args_get", &[65533, 200]), EFAULT);
+        assert_eq!(call(&mut instance, "args_get", &[200, 65532]), EFAULT);
+        // Nothing is written by the failed calls.
+        let mut data = [0u8; 8];
+        instance.memory_get(200, &mut data).unwrap();
+        assert_eq!(data, [0; 8]);
+        assert_eq!(call(&mut instance, "args_get", &[200, 65531]), 0);
+        instance.memory_get(65531, &mut data[..5]).unwrap();
+        assert_eq!(&data[..5], b"prog\0");
+    }
+
+    #[test]
+    fn virtual_filesystem() {
+        /* wat2wasm
+        (module
+          (func $path_open (import "wasi_snapshot_preview1" "path_open") (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32))
+          (func $path_create_directory (import "wasi_snapshot_preview1" "path_create_directory") (param i32 i32 i32) (result i32))
+          (func $fd_read (import "wasi_snapshot_preview1" "fd_read") (param i32 i32 i32 i32) (result i32))
+          (func $fd_write (import "wasi_snapshot_preview1" "fd_write") (param i32 i32 i32 i32) (result i32))
+          (func $fd_close (import "wasi_snapshot_preview1" "fd_close") (param i32) (result i32))
+          (memory (export "memory") 1)
+          (data (i32.const 32) "\00\01\00\00\40\00\00\00")
+          (data (i32.const 512) "input/data.bin")
+          (data (i32.const 528) "output")
+          (data (i32.const 544) "output/result.json")
+          (data (i32.const 576) "../../etc/passwd")
+          ;; Copies input/data.bin to output/result.json, storing the errnos at 0..24.
+          (func (export "_start")
+            (i32.store (i32.const 0)
+              (call $path_open (i32.const 3) (i32.const 0) (i32.const 512) (i32.const 14) (i32.const 0)
+                (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 64)))
+            (i32.store (i32.const 4) (call $fd_read (i32.load (i32.const 64)) (i32.const 32) (i32.const 1) (i32.const 44)))
+            (i32.store (i32.const 8) (call $path_create_directory (i32.const 3) (i32.const 528) (i32.const 6)))
+            ;; O_CREAT | O_TRUNC with the right to write.
+            (i32.store (i32.const 12)
+              (call $path_open (i32.const 3) (i32.const 0) (i32.const 544) (i32.const 18) (i32.const 9)
+                (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 68)))
+            (i32.store (i32.const 40) (i32.const 256))
+            (i32.store (i32.const 16) (call $fd_write (i32.load (i32.const 68)) (i32.const 40) (i32.const 1) (i32.const 48)))
+            (i32.store (i32.const 20)
+              (call $path_open (i32.const 3) (i32.const 0) (i32.const 576) (i32.const 16) (i32.const 0)
+                (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 72)))
+            (drop (call $fd_close (i32.load (i32.const 64))))
+            (drop (call $fd_close (i32.load (i32.const 68))))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001250560097f7f7f7f7f7e7e7f7f017f60037f7f7f017f60047f7f7f7f017f60017f017f60000002b8010516776173695f736e617073686f745f707265766965773109706174685f6f70656e000016776173695f736e617073686f745f707265766965773115706174685f6372656174655f6469726563746f7279000116776173695f736e617073686f745f70726576696577310766645f72656164000216776173695f736e617073686f745f70726576696577310866645f7772697465000216776173695f736e617073686f745f70726576696577310866645f636c6f73650003030201040503010001071302066d656d6f72790200065f737461727400050aa50101a20100410041034100418004410e410042024200410041c0001000360200410441c00028020041204101412c10023602004108410341900441061001360200410c4103410041a0044112410942c0004200410041c40010003602004128418002360200411041c400280200412841014130100336020041144103410041c0044110410042024200410041c800100036020041c00028020010041a41c40028020010041a0b0b5c050041200b080001000040000000004180040b0e696e7075742f646174612e62696e004190040b066f75747075740041a0040b126f75747075742f726573756c742e6a736f6e0041c0040b102e2e2f2e2e2f6574632f706173737764").unwrap();
+        let vfs = VirtualFs::new();
+        vfs.add_file("/input/data.bin", "{\"answer\": 42}").unwrap();
+
+        let config = WasiConfig::new().filesystem(vfs.clone());
+        let mut instance = instantiate(parse(&input).unwrap(), &config).unwrap();
+        assert_eq!(start(&mut instance), Ok(WasiOutcome::Finished));
+
+        let mut bytes = [0u8; 24];
+        instance.memory_get(0, &mut bytes).unwrap();
+        let errnos: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from(chunk[0]) | u32::from(chunk[1]) << 8)
+            .collect();
+        // The path outside the filesystem does not exist (ENOENT).
+        assert_eq!(errnos, [0, 0, 0, 0, 0, 44]);
+        assert_eq!(
+            vfs.read("/output/result.json"),
+            Some(b"{\"answer\": 42}".to_vec())
+        );
+        assert_eq!(
+            vfs.read("/input/data.bin"),
+            Some(b"{\"answer\": 42}".to_vec())
+        );
+    }
+
+    #[test]
+    fn custom_imports() {
+        /* wat2wasm
+        (module
+          (func $host_add (import "env" "host_add") (param i32 i32) (result i32))
+          (func $fd_write (import "wasi_snapshot_preview1" "fd_write") (param i32 i32 i32 i32) (result i32))
+          (func $random_get (import "wasi_snapshot_preview1" "random_get") (param i32 i32) (result i32))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "\00\01\00\00\02\00\00\00")
+          ;; Writes the digit host_add(2, 3) and a random byte to stdout.
+          (func (export "_start")
+            (i32.store8 (i32.const 256) (i32.add (i32.const 48) (call $host_add (i32.const 2) (i32.const 3))))
+            (drop (call $random_get (i32.const 257) (i32.const 1)))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+          )
+        )
+        */
+        let input = hex::decode("0061736d0100000001120360027f7f017f60047f7f7f7f017f60000002560303656e7608686f73745f616464000016776173695f736e617073686f745f70726576696577310866645f7772697465000116776173695f736e617073686f745f70726576696577310a72616e646f6d5f6765740000030201020503010001071302066d656d6f72790200065f737461727400030a2601240041800241304102410310006a3a0000418102410110021a410141004101410810011a0b0b0e010041000b080001000002000000").unwrap();
+        let config = WasiConfig::new().stdout(WasiOut::Buffer);
+        let run_with = |random_first: bool| {
+            let warnings = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+            let mut imports = ImportsBuilder::new();
+            let add_random = |imports: &mut ImportsBuilder| {
+                imports.func(
+                    "wasi_snapshot_preview1",
+                    "random_get",
+                    |caller: &mut Caller, buf: u32, len: u32| -> Result<u32, Trap> {
+                        caller.memory_set(buf, &vec![b'x'; len as usize]).unwrap();
+                        Ok(0)
+                    },
+                );
+            };
+            if random_first {
+                add_random(&mut imports);
+            }
+            let captured = add_capturing(&mut imports, &config).unwrap();
+            if !random_first {
+                add_random(&mut imports);
+            }
+            imports.func(
+                "env",
+                "host_add",
+                |_: &mut Caller, a: u32, b: u32| -> Result<u32, Trap> { Ok(a + b) },
+            );
+            let hook_warnings = warnings.clone();
+            imports.on_warning(move |warning| hook_warnings.borrow_mut().push(warning.to_string()));
+
+            let module = parse(&input).unwrap();
+            let mut instance = module.instantiate_with_imports(imports).unwrap();
+            assert_eq!(start(&mut instance), Ok(WasiOutcome::Finished));
+            let warnings = warnings.borrow().clone();
+            (captured.take_stdout(), warnings)
+        };
+
+        let expected_warnings = vec![
+            "host function wasi_snapshot_preview1.random_get overrides the function provided by WASI".to_string(),
+        ];
+        assert_eq!(run_with(false), (b"5x".to_vec(), expected_warnings.clone()));
+        assert_eq!(run_with(true), (b"5x".to_vec(), expected_warnings));
+    }
+
+    #[test]
+    fn reactor() {
+        /* wat2wasm
+        (module
+          (func $args_sizes_get (import "wasi_snapshot_preview1" "args_sizes_get") (param i32 i32) (result i32))
+          (memory (export "memory") 1)
+          ;; Stores the number of arguments at 0.
+          (func (export "_initialize")
+            (drop (call $args_sizes_get (i32.const 0) (i32.const 4))))
+          ;; Adds the argument to the total at 8 and returns the number of calls.
+          (func (export "add") (param i32) (result i32)
+            (i32.store (i32.const 8) (i32.add (i32.load (i32.const 8)) (local.get 0)))
+            (i32.store (i32.const 12) (i32.add (i32.load (i32.const 12)) (i32.const 1)))
+            (i32.load (i32.const 12))
+          )
+        )
+        */
+        let input = hex::decode("0061736d01000000010f0360027f7f017f60000060017f017f02290116776173695f736e617073686f745f70726576696577310e617267735f73697a65735f676574000003030201020503010001071e03066d656d6f727902000b5f696e697469616c697a6500010361646400020a2d0209004100410410001a0b21004108410828020020006a360200410c410c28020041016a360200410c2802000b").unwrap();
+        let config = WasiConfig::new().arg("prog").arg("arg");
+        let mut instance = instantiate(parse(&input).unwrap(), &config).unwrap();
+        let read_u32 = |instance: &Instance, offset: u32| {
+            let mut bytes = [0u8; 4];
+            instance.memory_get(offset, &mut bytes).unwrap();
+            u32::from_le_bytes(bytes)
+        };
+
+        let result = instance.execute("_initialize", &[]).unwrap();
+        assert!(!result.trapped());
+        assert_eq!(read_u32(&instance, 0), 2);
+
+        let result = instance.execute("add", &[TypedValue::U32(40)]).unwrap();
+        assert_eq!(result.value().unwrap().as_u32(), Some(1));
+        assert_eq!(read_u32(&instance, 8), 40);
+
+        let result = instance.execute("add", &[TypedValue::U32(2)]).unwrap();
+        assert_eq!(result.value().unwrap().as_u32(), Some(2));
+        assert_eq!(read_u32(&instance, 8), 42);
+
+        // A reactor has no _start.
+        assert_eq!(start(&mut instance), Err(Error::FunctionNotFound));
+    }
+
+    #[test]
+    fn unsupported_function() {
+        /* wat2wasm
+        (module
+          (func $sock_shutdown (import "wasi_snapshot_preview1" "sock_shutdown") (param i32 i32) (result i32))
+          (func $proc_exit (import "wasi_snapshot_preview1" "proc_exit") (param i32))
+          (memory (export "memory") 1)
+          (func (export "_start") (call $proc_exit (call $sock_shutdown (i32.const 0) (i32.const 0))))
+        )
+        */
+        let input = hex::decode("0061736d01000000010e0360027f7f017f60017f00600000024b0216776173695f736e617073686f745f70726576696577310d736f636b5f73687574646f776e000016776173695f736e617073686f745f70726576696577310970726f635f657869740001030201020503010001071302066d656d6f72790200065f737461727400020a0c010a0041004100100010010b").unwrap();
+        // ENOSYS
+        assert_eq!(run(&input, &[]), Ok(WasiOutcome::Exited(52)));
+    }
+
+    #[test]
+    fn invalid_program() {
+        assert_eq!(
+            run(&[], &[]),
+            Err(Error::MalformedModule(
+                "invalid wasm module prefix".to_string()
+            ))
+        );
+
+        /* wat2wasm
+        (module
+          (func (import "wasi_snapshot_preview1" "proc_exit") (param i64))
+          (memory (export "memory") 1)
+        )
+        */
+        let input = hex::decode("0061736d0100000001050160017e0002240116776173695f736e617073686f745f70726576696577310970726f635f6578697400000503010001070a01066d656d6f72790200").unwrap();
+        assert_eq!(
+            run(&input, &[]),
+            Err(Error::InstantiationFailed("function wasi_snapshot_preview1.proc_exit input types don't match imported function in module".to_string()))
+        );
+
+        /* wat2wasm
+        (module
+          (memory (export "memory") 1)
+        )
+        */
+        let input = hex::decode("0061736d010000000503010001070a01066d656d6f72790200").unwrap();
+        assert_eq!(run(&input, &[]), Err(Error::FunctionNotFound));
+    }
+}
@@ -0,0 +1,105 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The deterministic random number generator.
+
+use std::convert::TryInto;
+
+/// The ChaCha20 keystream for a 256-bit key, a zero nonce and a 64-bit block counter starting at 0.
+///
+/// This is specified independently of the platform, therefore the same seed produces the same bytes everywhere.
+pub(crate) struct ChaCha20 {
+    key: [u32; 8],
+    counter: u64,
+    block: [u8; 64],
+    /// The number of bytes of `block` already used.
+    position: usize,
+}
+
+impl ChaCha20 {
+    pub(crate) fn new(seed: &[u8; 32]) -> Self {
+        let mut key = [0u32; 8];
+        for (word, bytes) in key.iter_mut().zip(seed.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        ChaCha20 {
+            key,
+            counter: 0,
+            block: [0; 64],
+            position: 64,
+        }
+    }
+
+    /// Fill `buf` with the next bytes of the keystream.
+    pub(crate) fn fill(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            if self.position == self.block.len() {
+                self.block = self.next_block();
+                self.position = 0;
+            }
+            *byte = self.block[self.position];
+            self.position += 1;
+        }
+    }
+
+    fn next_block(&mut self) -> [u8; 64] {
+        let mut input = [0u32; 16];
+        input[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = self.counter as u32;
+        input[13] = (self.counter >> 32) as u32;
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut x = input;
+        for _ in 0..10 {
+            quarter_round(&mut x, 0, 4, 8, 12);
+            quarter_round(&mut x, 1, 5, 9, 13);
+            quarter_round(&mut x, 2, 6, 10, 14);
+            quarter_round(&mut x, 3, 7, 11, 15);
+            quarter_round(&mut x, 0, 5, 10, 15);
+            quarter_round(&mut x, 1, 6, 11, 12);
+            quarter_round(&mut x, 2, 7, 8, 13);
+            quarter_round(&mut x, 3, 4, 9, 14);
+        }
+
+        let mut block = [0u8; 64];
+        for (i, bytes) in block.chunks_exact_mut(4).enumerate() {
+            bytes.copy_from_slice(&x[i].wrapping_add(input[i]).to_le_bytes());
+        }
+        block
+    }
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keystream() {
+        // The test vector #1 of RFC 7539, section A.1, followed by the next block.
+        let mut chacha = ChaCha20::new(&[0; 32]);
+        let mut bytes = [0u8; 80];
+        chacha.fill(&mut bytes[..10]);
+        chacha.fill(&mut bytes[10..]);
+        assert_eq!(
+            hex::encode(&bytes[..64]),
+            "76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7da41597c5157488d7724e03fb8d84a376a43b8f41518a11cc387b669b2ee6586"
+        );
+        assert_eq!(
+            hex::encode(&bytes[64..]),
+            "9f07e7be5551387a98ba977c732d080d"
+        );
+    }
+}
@@ -0,0 +1,393 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The in-memory filesystem.
+
+use super::errno::*;
+use super::fs::{Filestat, OpenFlags, FILETYPE_DIRECTORY, FILETYPE_REGULAR_FILE};
+use super::host::lock;
+use crate::Error;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+/// The maximum size of a file in a virtual filesystem.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+type Contents = Arc<Mutex<Vec<u8>>>;
+
+#[derive(Debug)]
+enum Entry {
+    Directory,
+    File(Contents),
+}
+
+/// An in-memory filesystem, visible to WASI programs as the preopened directory `/`.
+///
+/// Paths use `/` as the separator. A `..` at the root refers to the root itself, so programs cannot access
+/// anything outside the filesystem. Clones refer to the same filesystem, which allows inspecting the files
+/// written by a program after running it.
+///
+/// ```
+/// let vfs = fizzy::wasi::VirtualFs::new();
+/// vfs.add_file("/input/data.bin", vec![1, 2, 3]).unwrap();
+/// let config = fizzy::wasi::WasiConfig::new().filesystem(vfs.clone());
+/// // Run the program with the configuration, then:
+/// let output = vfs.read("/output/result.json");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct VirtualFs {
+    /// The files and directories (except the root) by their normalized path.
+    entries: Arc<Mutex<BTreeMap<String, Entry>>>,
+}
+
+impl VirtualFs {
+    /// Create an empty filesystem.
+    pub fn new() -> Self {
+        VirtualFs::default()
+    }
+
+    /// Add the file at `path` with the `contents`, replacing an existing file.
+    /// Missing parent directories are created.
+    pub fn add_file<B: Into<Vec<u8>>>(&self, path: &str, contents: B) -> Result<(), Error> {
+        let normalized = VirtualFs::join("", path);
+        let error = |reason: String| {
+            Error::Other(format!(
+                "cannot add file {} to the virtual filesystem: {}",
+                path, reason
+            ))
+        };
+        let mut entries = lock(&self.entries);
+        add_directories(&mut entries, parent(&normalized)).map_err(error)?;
+        match entries.get(&normalized) {
+            _ if normalized.is_empty() => Err(error("is a directory".to_string())),
+            Some(Entry::Directory) => Err(error("is a directory".to_string())),
+            Some(Entry::File(existing)) => {
+                *lock(existing) = contents.into();
+                Ok(())
+            }
+            None => {
+                let contents = Arc::new(Mutex::new(contents.into()));
+                entries.insert(normalized, Entry::File(contents));
+                Ok(())
+            }
+        }
+    }
+
+    /// Add the directory at `path`, including missing parent directories.
+    pub fn add_dir(&self, path: &str) -> Result<(), Error> {
+        add_directories(&mut lock(&self.entries), &VirtualFs::join("", path)).map_err(|reason| {
+            Error::Other(format!(
+                "cannot add directory {} to the virtual filesystem: {}",
+                path, reason
+            ))
+        })
+    }
+
+    /// The contents of the file at `path`, `None` if there is no such file.
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        match lock(&self.entries).get(&VirtualFs::join("", path)) {
+            Some(Entry::File(contents)) => Some(lock(contents).clone()),
+            _ => None,
+        }
+    }
+
+    /// Normalize `path` relative to the normalized path `base`.
+    pub(crate) fn join(base: &str, path: &str) -> String {
+        let mut components: Vec<&str> = if path.starts_with('/') || base.is_empty() {
+            Vec::new()
+        } else {
+            base.split('/').collect()
+        };
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                name => components.push(name),
+            }
+        }
+        components.join("/")
+    }
+
+    /// Check that the parent directory of the normalized `path` exists.
+    fn check_parent(entries: &BTreeMap<String, Entry>, path: &str) -> Result<(), Errno> {
+        let parent = parent(path);
+        match entries.get(parent) {
+            _ if parent.is_empty() => Ok(()),
+            Some(Entry::Directory) => Ok(()),
+            Some(Entry::File(_)) => Err(ENOTDIR),
+            None => Err(ENOENT),
+        }
+    }
+
+    pub(crate) fn filestat(&self, path: &str) -> Result<Filestat, Errno> {
+        let entries = lock(&self.entries);
+        match entries.get(path) {
+            Some(Entry::File(contents)) => Ok(file_filestat(contents)),
+            Some(Entry::Directory) => Ok(directory_filestat()),
+            None if path.is_empty() => Ok(directory_filestat()),
+            None => {
+                VirtualFs::check_parent(&entries, path)?;
+                Err(ENOENT)
+            }
+        }
+    }
+
+    /// Open the file at the normalized `path`, or `None` if it is a directory.
+    pub(crate) fn open(&self, path: &str, flags: &OpenFlags) -> Result<Option<VirtualFile>, Errno> {
+        let mut entries = lock(&self.entries);
+        let contents = match entries.get(path) {
+            None if path.is_empty() => None,
+            Some(Entry::Directory) => None,
+            Some(Entry::File(contents)) => Some(contents.clone()),
+            None => {
+                VirtualFs::check_parent(&entries, path)?;
+                if !flags.create || flags.directory {
+                    return Err(ENOENT);
+                }
+                let contents = Contents::default();
+                entries.insert(path.to_string(), Entry::File(contents.clone()));
+                return Ok(Some(VirtualFile::new(contents, flags)));
+            }
+        };
+        match contents {
+            None if flags.write || flags.truncate => Err(EISDIR),
+            None => Ok(None),
+            Some(_) if flags.directory => Err(ENOTDIR),
+            Some(_) if flags.create && flags.exclusive => Err(EEXIST),
+            Some(contents) => {
+                if flags.truncate {
+                    lock(&contents).clear();
+                }
+                Ok(Some(VirtualFile::new(contents, flags)))
+            }
+        }
+    }
+
+    pub(crate) fn create_directory(&self, path: &str) -> Result<(), Errno> {
+        let mut entries = lock(&self.entries);
+        if path.is_empty() || entries.contains_key(path) {
+            return Err(EEXIST);
+        }
+        VirtualFs::check_parent(&entries, path)?;
+        entries.insert(path.to_string(), Entry::Directory);
+        Ok(())
+    }
+
+    pub(crate) fn remove_directory(&self, path: &str) -> Result<(), Errno> {
+        let mut entries = lock(&self.entries);
+        match entries.get(path) {
+            _ if path.is_empty() => Err(EACCES),
+            Some(Entry::File(_)) => Err(ENOTDIR),
+            None => Err(ENOENT),
+            Some(Entry::Directory) => {
+                let prefix = format!("{}/", path);
+                let has_children = entries
+                    .range(prefix.clone()..)
+                    .take(1)
+                    .any(|(child, _)| child.starts_with(&prefix));
+                if has_children {
+                    return Err(ENOTEMPTY);
+                }
+                entries.remove(path);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn unlink_file(&self, path: &str) -> Result<(), Errno> {
+        let mut entries = lock(&self.entries);
+        match entries.get(path) {
+            _ if path.is_empty() => Err(EISDIR),
+            Some(Entry::Directory) => Err(EISDIR),
+            None => Err(ENOENT),
+            Some(Entry::File(_)) => {
+                entries.remove(path);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The parent of the normalized `path`, empty for the root.
+fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |end| &path[..end])
+}
+
+/// Add the directory at the normalized `path` and its missing parents.
+fn add_directories(entries: &mut BTreeMap<String, Entry>, path: &str) -> Result<(), String> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    let ends = path
+        .match_indices('/')
+        .map(|(end, _)| end)
+        .chain(std::iter::once(path.len()));
+    for end in ends {
+        match entries.get(&path[..end]) {
+            Some(Entry::Directory) => {}
+            Some(Entry::File(_)) => return Err(format!("{} is a file", &path[..end])),
+            None => {
+                entries.insert(path[..end].to_string(), Entry::Directory);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn directory_filestat() -> Filestat {
+    Filestat {
+        filetype: FILETYPE_DIRECTORY,
+        nlink: 1,
+        ..Filestat::default()
+    }
+}
+
+fn file_filestat(contents: &Contents) -> Filestat {
+    Filestat {
+        filetype: FILETYPE_REGULAR_FILE,
+        nlink: 1,
+        size: lock(contents).len() as u64,
+        ..Filestat::default()
+    }
+}
+
+/// An open file of a virtual filesystem. It remains accessible after the file is removed.
+pub(crate) struct VirtualFile {
+    contents: Contents,
+    position: u64,
+    readable: bool,
+    writable: bool,
+    append: bool,
+}
+
+impl VirtualFile {
+    fn new(contents: Contents, flags: &OpenFlags) -> Self {
+        VirtualFile {
+            contents,
+            position: 0,
+            readable: flags.read,
+            writable: flags.write,
+            append: flags.append,
+        }
+    }
+
+    pub(crate) fn filestat(&self) -> Filestat {
+        file_filestat(&self.contents)
+    }
+}
+
+impl Read for VirtualFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.readable {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        let contents = lock(&self.contents);
+        let start = usize::try_from(self.position)
+            .unwrap_or(usize::MAX)
+            .min(contents.len());
+        let n = (&contents[start..]).read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for VirtualFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.writable {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        let mut contents = lock(&self.contents);
+        if self.append {
+            self.position = contents.len() as u64;
+        }
+        let end = self.position + buf.len() as u64;
+        if end > MAX_FILE_SIZE {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        let (start, end) = (self.position as usize, end as usize);
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[start..end].copy_from_slice(buf);
+        self.position = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for VirtualFile {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => (0, i128::from(offset)),
+            SeekFrom::Current(offset) => (self.position, i128::from(offset)),
+            SeekFrom::End(offset) => (lock(&self.contents).len() as u64, i128::from(offset)),
+        };
+        let position = i128::from(base) + offset;
+        if position < 0 || position > i128::from(MAX_FILE_SIZE) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join() {
+        assert_eq!(VirtualFs::join("", ""), "");
+        assert_eq!(VirtualFs::join("", "/a//b/./c/"), "a/b/c");
+        assert_eq!(VirtualFs::join("a/b", "c"), "a/b/c");
+        assert_eq!(VirtualFs::join("a/b", "../c"), "a/c");
+        assert_eq!(VirtualFs::join("a/b", "/c"), "c");
+        assert_eq!(VirtualFs::join("a", "../../../etc/passwd"), "etc/passwd");
+    }
+
+    #[test]
+    fn add_and_read() {
+        let vfs = VirtualFs::new();
+        vfs.add_file("/input/data.bin", vec![1, 2, 3]).unwrap();
+        vfs.add_file("top", "top").unwrap();
+        vfs.add_dir("/output/nested").unwrap();
+        assert_eq!(vfs.read("input/data.bin"), Some(vec![1, 2, 3]));
+        assert_eq!(vfs.read("/input/../top"), Some(b"top".to_vec()));
+        assert_eq!(vfs.read("/input"), None);
+        assert_eq!(vfs.read("/missing"), None);
+
+        vfs.add_file("/input/data.bin", "replaced").unwrap();
+        assert_eq!(
+            vfs.clone().read("/input/data.bin"),
+            Some(b"replaced".to_vec())
+        );
+
+        assert_eq!(
+            vfs.add_file("/top/file", ""),
+            Err(Error::Other(
+                "cannot add file /top/file to the virtual filesystem: top is a file".to_string()
+            ))
+        );
+        assert_eq!(
+            vfs.add_file("/output", ""),
+            Err(Error::Other(
+                "cannot add file /output to the virtual filesystem: is a directory".to_string()
+            ))
+        );
+        assert_eq!(
+            vfs.add_dir("/top"),
+            Err(Error::Other(
+                "cannot add directory /top to the virtual filesystem: top is a file".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,82 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks of heap allocations, using a global allocator counting them.
+//! This file contains a single test, because the count is shared by all threads.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fizzy::TypedValue;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_of<F: FnMut()>(mut f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    f();
+    ALLOCATIONS.load(Ordering::SeqCst) - before
+}
+
+#[test]
+fn cached_execute_does_not_allocate() {
+    /* wat2wasm
+    (module
+      (func (export "foo") (result i32) (i32.const 42))
+      (func (export "bar") (param i32) (param i64) (result i32) (local.get 0) (i32.wrap_i64 (local.get 1)) (i32.add))
+    )
+    */
+    let input = hex::decode("0061736d01000000010b026000017f60027f7e017f0303020001070d0203666f6f00000362617200010a0f020400412a0b080020002001a76a0b").unwrap();
+    let mut instance = fizzy::parse(&input).unwrap().instantiate().unwrap();
+    let args = [TypedValue::U32(1), TypedValue::U64(2)];
+
+    // The first call looks the function up and caches it.
+    assert_ne!(
+        allocations_of(|| {
+            instance.execute("bar", &args).unwrap();
+        }),
+        0
+    );
+    assert_eq!(
+        allocations_of(|| {
+            for _ in 0..100 {
+                let result = instance.execute("bar", &args).unwrap();
+                assert_eq!(result.value(), Some(TypedValue::U32(3)));
+            }
+        }),
+        0
+    );
+
+    #[cfg(feature = "raw-api")]
+    {
+        let func_idx = instance.find_exported_function_index("foo").unwrap();
+        assert_eq!(
+            allocations_of(|| {
+                let result = unsafe { instance.execute_unchecked(func_idx, &[]) };
+                assert_eq!(result.value().unwrap().as_i32(), 42);
+            }),
+            0
+        );
+    }
+}
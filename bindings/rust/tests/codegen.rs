@@ -0,0 +1,39 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+use fizzy::ImportsBuilder;
+
+/// The stubs generated for the module of `fixture`.
+mod stubs {
+    include!("codegen/stubs.rs");
+}
+
+fn fixture() -> fizzy::Module {
+    /* wat2wasm
+    (module
+      (func (import "env" "add") (param i32 i32) (result i32))
+      (func (import "env" "log") (param i64 f32 f64))
+      (func (import "env" "add") (param i32 i32) (result i32))
+      (func (import "host" "sqrt-f64") (param f64) (result f64))
+      (func (import "wasi_snapshot_preview1" "proc_exit") (param i32))
+    )
+    */
+    let input = hex::decode("0061736d0100000001160460027f7f017f60037e7d7c0060017c017c60017f0002520503656e7603616464000003656e76036c6f67000103656e7603616464000004686f737408737172742d663634000216776173695f736e617073686f745f70726576696577310970726f635f657869740003").unwrap();
+    fizzy::parse(&input).unwrap()
+}
+
+#[test]
+fn stubs_up_to_date() {
+    assert_eq!(
+        fizzy::codegen::host_stubs(&fixture()),
+        include_str!("codegen/stubs.rs")
+    );
+}
+
+#[test]
+fn stubs_registered() {
+    let mut imports = ImportsBuilder::new();
+    stubs::register_all(&mut imports);
+    assert!(fixture().instantiate_with_imports(imports).is_ok());
+}
@@ -0,0 +1,31 @@
+// Host functions of the imports, generated by fizzy::codegen::host_stubs.
+
+use fizzy::{Caller, ImportsBuilder, Trap};
+
+/// The host function of the import "env"."add".
+pub fn env_add(_caller: &mut Caller, _arg0: i32, _arg1: i32) -> Result<i32, Trap> {
+    todo!()
+}
+
+/// The host function of the import "env"."log".
+pub fn env_log(_caller: &mut Caller, _arg0: i64, _arg1: f32, _arg2: f64) -> Result<(), Trap> {
+    todo!()
+}
+
+/// The host function of the import "host"."sqrt-f64".
+pub fn host_sqrt_f64(_caller: &mut Caller, _arg0: f64) -> Result<f64, Trap> {
+    todo!()
+}
+
+/// The host function of the import "wasi_snapshot_preview1"."proc_exit".
+pub fn wasi_snapshot_preview1_proc_exit(_caller: &mut Caller, _arg0: i32) -> Result<(), Trap> {
+    todo!()
+}
+
+/// Register the host functions of all imports.
+pub fn register_all(builder: &mut ImportsBuilder) {
+    builder.func("env", "add", env_add);
+    builder.func("env", "log", env_log);
+    builder.func("host", "sqrt-f64", host_sqrt_f64);
+    builder.func("wasi_snapshot_preview1", "proc_exit", wasi_snapshot_preview1_proc_exit);
+}
@@ -0,0 +1,87 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks of the heap allocations of `parse_file`, using a global allocator counting the bytes.
+//! This file contains a single test, because the count is shared by all threads.
+
+#![cfg(all(unix, feature = "mmap"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn push_u32(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[test]
+fn parse_file_does_not_copy_the_custom_sections() {
+    /* wat2wasm
+    (module
+      (memory 1)
+      (func (export "foo") (result i32) (i32.const 42))
+    )
+    */
+    let mut input = hex::decode(
+        "0061736d010000000105016000017f03020100050301000107070103666f6f00000a06010400412a0b",
+    )
+    .unwrap();
+    // A custom section of 1 MiB.
+    let name = b"blob";
+    let size = 1 << 20;
+    input.push(0);
+    push_u32(&mut input, 1 + name.len() + size);
+    input.push(name.len() as u8);
+    input.extend_from_slice(name);
+    input.resize(input.len() + size, 0xaa);
+
+    let path = std::env::temp_dir().join(format!("fizzy-parse-file-{}.wasm", std::process::id()));
+    std::fs::write(&path, &input).unwrap();
+
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let module = fizzy::parse_file(&path).unwrap();
+    let allocated = ALLOCATED.load(Ordering::SeqCst) - before;
+    std::fs::remove_file(&path).unwrap();
+
+    // The engine allocates with its own allocator, therefore only the bytes kept by the binding
+    // are counted, which are far less than the input.
+    assert!(
+        allocated < size / 16,
+        "{} bytes allocated parsing {} bytes",
+        allocated,
+        input.len()
+    );
+    let result = module.instantiate().unwrap().execute("foo", &[]).unwrap();
+    assert_eq!(result.value(), Some(fizzy::TypedValue::U32(42)));
+}
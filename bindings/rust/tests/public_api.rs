@@ -0,0 +1,116 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A snapshot of the public API, which fails to compile when an item is removed or renamed, or a
+//! signature is changed. The list is maintained by hand: a change here is a breaking change.
+
+#![allow(unused_imports)]
+
+use fizzy::{codegen, codes, compat, contrib, engine, instrument, prelude, report, timed};
+use fizzy::{
+    estimate_instance_overhead, limits, parse, parse_metered, parse_shared, parse_with, self_check,
+    validate, validate_batch, validate_owned, AllocStrategy, CallCost, Caller, CostSchedule,
+    CostScheduleBuilder, CoverageMap, DynHostFn, EngineLimits, Error, ErrorKind, ExecutionOptions,
+    ExecutionOutcome, Export, ExternalKind, ExternalType, ExtraImports, FinishedTask,
+    FunctionProfile, FunctionType, GlobalChange, GlobalType, HostError, HostResult, Import,
+    ImportMeta, ImportReport, ImportStat, ImportsBuilder, Instance, InstancePool, InstancePre,
+    InstantiateOptions, Interface, InterfaceViolation, IntoHostFunction, Limits, LogSink,
+    MemoryChange, MeteredSession, Module, ModuleCache, ModuleDisplay, NextHostFn, ParseOptions,
+    PooledInstance, ProfileReport, Registry, ResetPolicy, ResourceUsage, RuntimeConfig, Scheduler,
+    SelfCheckReport, SessionSummary, StateDiff, StateSnapshot, TaskId, TraceEvent, TraceSink, Trap,
+    TrapInfo, TrapKind, TypeError, TypedExecutionResult, TypedValue, ValidatedBytes, Value,
+    ValueType, WasmInstance, WasmInstanceExt, WasmParams, WasmResult, WasmType,
+};
+
+#[test]
+fn prelude() {
+    use fizzy::prelude::*;
+
+    let _: fn(&Vec<u8>) -> Result<Module, Error> = parse;
+    let _: fn() -> ImportsBuilder = ImportsBuilder::new;
+    let _: fn() -> InstantiateOptions = InstantiateOptions::new;
+    let _: fn() -> ExecutionOptions = ExecutionOptions::new;
+    let _: Option<(Instance, TypedValue, ValueType, Trap)> = None;
+    let _: Option<Caller> = None;
+}
+
+#[test]
+fn signatures() {
+    let _: fn(&Module) -> Result<Instance, Error> = Module::instantiate;
+    let _: fn(&Module, ImportsBuilder) -> Result<Instance, Error> =
+        Module::instantiate_with_imports;
+    let _: fn(&Module, ImportsBuilder, &InstantiateOptions) -> Result<Instance, Error> =
+        Module::instantiate_with_options;
+    let _: fn(&Module) -> Vec<Export> = Module::exports;
+    let _: fn(&Module) -> Vec<Import> = Module::imports;
+    let _: fn(&[u8]) -> Vec<&'static str> = Module::detect_features;
+
+    let _: fn(&mut Instance, &str, &[TypedValue]) -> Result<TypedExecutionResult, Error> =
+        Instance::execute;
+    let _: fn(
+        &mut Instance,
+        &str,
+        &[TypedValue],
+        &ExecutionOptions,
+    ) -> Result<ExecutionOutcome, Error> = Instance::execute_with_options;
+    let _: fn(&Instance) -> usize = Instance::memory_size;
+    let _: fn(&Instance) -> u32 = Instance::peak_memory_pages;
+    let _: fn(&Instance) -> Vec<ImportStat> = Instance::import_stats;
+    let _: fn(&mut Instance) = Instance::reset_import_stats;
+    let _: fn(ExecutionOptions, bool) -> ExecutionOptions = ExecutionOptions::time_imports;
+    let _: fn(&Instance, u32, &mut [u8]) -> Result<(), Error> = Instance::memory_get;
+    let _: fn(&mut Instance, u32, &[u8]) -> Result<(), Error> = Instance::memory_set;
+    let _: fn(&Instance, &str) -> Option<TypedValue> = Instance::global_value;
+    let _: fn(&mut Instance, &str, TypedValue) -> Result<(), Error> = Instance::set_global_value;
+    let _: fn(&Instance, &str) -> Option<u32> = Instance::find_exported_function_index;
+    let _: fn(&mut Instance, u32) -> Result<u32, Error> = Instance::guest_alloc;
+    let _: fn(&mut Instance, u32, u32) -> Result<(), Error> = Instance::guest_free;
+    let _: fn(&mut Instance, &[u8]) -> Result<u32, Error> = Instance::copy_in;
+    let _: fn(&mut Instance, AllocStrategy) = Instance::set_alloc_strategy;
+
+    let _: fn(&TypedExecutionResult) -> bool = TypedExecutionResult::trapped;
+    let _: fn(&TypedExecutionResult) -> Option<TypedValue> = TypedExecutionResult::value;
+    let _: fn(&Error) -> ErrorKind = Error::kind;
+    let _: fn(&Error) -> u32 = Error::stable_code;
+    let _: fn(u32, &str) -> Error = Error::from_stable_code;
+    let _: fn(&TrapKind) -> u32 = TrapKind::stable_code;
+    let _: fn(&TypedValue) -> ValueType = TypedValue::value_type;
+    let _: fn() -> EngineLimits = limits;
+    let _: fn() -> Result<SelfCheckReport, Error> = self_check;
+    let _: fn(&InstantiateOptions) -> Result<(), Error> = InstantiateOptions::validate;
+    // The lifetime of the session is not generic in its methods, which therefore are called.
+    let _ = |instance: &mut Instance| {
+        let mut session: MeteredSession = MeteredSession::new(instance, 1);
+        let _: Result<Option<TypedValue>, Error> = session.call("main", &[]);
+        let _: SessionSummary = session.finish();
+    };
+    let _: fn(&Interface, &Module) -> Result<(), Vec<InterfaceViolation>> = Interface::check;
+}
+
+#[cfg(feature = "paranoid-check")]
+#[test]
+fn paranoid_check() {
+    use fizzy::ReferenceImports;
+
+    let _: fn(&mut Instance, &str, &[TypedValue]) -> Result<TypedExecutionResult, Error> =
+        Instance::execute_checked;
+    let _: fn(&mut Instance, ReferenceImports) = Instance::set_reference_imports;
+}
+
+#[cfg(feature = "raw-api")]
+#[test]
+fn raw_api() {
+    use fizzy::{ffi, ExecutionResult};
+
+    let _: unsafe fn(&mut Instance, u32, &[Value]) -> ExecutionResult = Instance::execute_unchecked;
+    let _: for<'a> unsafe fn(&'a Instance, u32, usize) -> Result<&'a [u8], Error> =
+        Instance::checked_memory_slice;
+    let _: for<'a> unsafe fn(&'a mut Instance, u32, usize) -> Result<&'a mut [u8], Error> =
+        Instance::checked_memory_slice_mut;
+    let _: for<'a> fn(&'a Instance, &str) -> Option<ffi::ExternalMemory<'a>> =
+        Instance::exported_memory;
+    let _: fn(ExecutionResult, &FunctionType) -> Result<Option<TypedValue>, Error> =
+        ExecutionResult::into_typed;
+    let _: u32 = ffi::DEFAULT_CALL_DEPTH_LIMIT;
+}
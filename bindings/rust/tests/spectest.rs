@@ -0,0 +1,400 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A runner of WebAssembly spec test scripts (`.wast`), executing them through the public API.
+//!
+//! The scripts in `tests/spectests` and the smoke test of the C++ spectests runner are run with:
+//! ```sh
+//! cargo test --test spectest -- --ignored --nocapture
+//! ```
+//! A checkout of the full testsuite is run by pointing `FIZZY_SPECTESTS_DIR` to its directory
+//! of `.wast` files, e.g. `test/core` of https://github.com/WebAssembly/spec.
+//!
+//! Directives which cannot be run through the API, such as those of modules importing globals,
+//! tables, memories or functions of other modules, and those requiring proposals beyond
+//! WebAssembly 1.0, are skipped and counted.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use fizzy::{Caller, Error, ImportsBuilder, Instance, Trap, TypedValue};
+use wast::parser::{self, ParseBuffer};
+use wast::{
+    AssertExpression, Expression, Instruction, NanPattern, QuoteModule, Wast, WastDirective,
+    WastExecute, WastInvoke,
+};
+
+#[derive(Default)]
+struct Stats {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+/// The outcome of a directive.
+enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+/// The host functions of the `spectest` module used by the testsuite.
+fn spectest_imports() -> ImportsBuilder {
+    let mut imports = ImportsBuilder::new();
+    imports
+        .func("spectest", "print", |_: &mut Caller| -> Result<(), Trap> {
+            Ok(())
+        })
+        .func(
+            "spectest",
+            "print_i32",
+            |_: &mut Caller, _: u32| -> Result<(), Trap> { Ok(()) },
+        )
+        .func(
+            "spectest",
+            "print_i64",
+            |_: &mut Caller, _: u64| -> Result<(), Trap> { Ok(()) },
+        )
+        .func(
+            "spectest",
+            "print_f32",
+            |_: &mut Caller, _: f32| -> Result<(), Trap> { Ok(()) },
+        )
+        .func(
+            "spectest",
+            "print_f64",
+            |_: &mut Caller, _: f64| -> Result<(), Trap> { Ok(()) },
+        )
+        .func(
+            "spectest",
+            "print_i32_f32",
+            |_: &mut Caller, _: u32, _: f32| -> Result<(), Trap> { Ok(()) },
+        )
+        .func(
+            "spectest",
+            "print_f64_f64",
+            |_: &mut Caller, _: f64, _: f64| -> Result<(), Trap> { Ok(()) },
+        );
+    imports
+}
+
+/// Whether instantiation failed because of imports which cannot be provided through the API.
+fn unsupported_imports(err: &Error) -> bool {
+    match err {
+        // The imports of mismatching types are reported only by the messages.
+        Error::InstantiationFailed(message) => message.contains("import"),
+        err => err.is_missing_import(),
+    }
+}
+
+fn parse_binary(module: &mut wast::Module) -> Result<fizzy::Module, Outcome> {
+    let binary = module
+        .encode()
+        .map_err(|err| Outcome::Failed(format!("cannot encode module: {}", err)))?;
+    fizzy::parse(&binary).map_err(|err| Outcome::Failed(format!("parsing failed: {}", err)))
+}
+
+fn to_typed_value(expr: &Expression) -> Option<TypedValue> {
+    match &expr.instrs[..] {
+        [Instruction::I32Const(value)] => Some(TypedValue::U32(*value as u32)),
+        [Instruction::I64Const(value)] => Some(TypedValue::U64(*value as u64)),
+        [Instruction::F32Const(value)] => Some(TypedValue::F32(f32::from_bits(value.bits))),
+        [Instruction::F64Const(value)] => Some(TypedValue::F64(f64::from_bits(value.bits))),
+        _ => None,
+    }
+}
+
+fn is_canonical_nan_f32(bits: u32) -> bool {
+    bits & 0x7fff_ffff == 0x7fc0_0000
+}
+
+fn is_arithmetic_nan_f32(bits: u32) -> bool {
+    bits & 0x7fc0_0000 == 0x7fc0_0000
+}
+
+fn is_canonical_nan_f64(bits: u64) -> bool {
+    bits & 0x7fff_ffff_ffff_ffff == 0x7ff8_0000_0000_0000
+}
+
+fn is_arithmetic_nan_f64(bits: u64) -> bool {
+    bits & 0x7ff8_0000_0000_0000 == 0x7ff8_0000_0000_0000
+}
+
+/// Check `actual` against the expected result. Returns None if the expectation is not supported.
+fn matches(actual: Option<TypedValue>, expected: &[AssertExpression]) -> Option<bool> {
+    let expected = match expected {
+        [] => return Some(actual.is_none()),
+        [expected] => expected,
+        // Multi-value.
+        _ => return None,
+    };
+    let matched = match (expected, actual) {
+        (AssertExpression::I32(value), Some(TypedValue::U32(actual))) => *value as u32 == actual,
+        (AssertExpression::I64(value), Some(TypedValue::U64(actual))) => *value as u64 == actual,
+        (AssertExpression::F32(pattern), Some(TypedValue::F32(actual))) => {
+            let bits = actual.to_bits();
+            match pattern {
+                NanPattern::CanonicalNan => is_canonical_nan_f32(bits),
+                NanPattern::ArithmeticNan => is_arithmetic_nan_f32(bits),
+                NanPattern::Value(value) => value.bits == bits,
+            }
+        }
+        (AssertExpression::F64(pattern), Some(TypedValue::F64(actual))) => {
+            let bits = actual.to_bits();
+            match pattern {
+                NanPattern::CanonicalNan => is_canonical_nan_f64(bits),
+                NanPattern::ArithmeticNan => is_arithmetic_nan_f64(bits),
+                NanPattern::Value(value) => value.bits == bits,
+            }
+        }
+        (AssertExpression::LegacyCanonicalNaN, Some(TypedValue::F32(actual))) => {
+            is_canonical_nan_f32(actual.to_bits())
+        }
+        (AssertExpression::LegacyCanonicalNaN, Some(TypedValue::F64(actual))) => {
+            is_canonical_nan_f64(actual.to_bits())
+        }
+        (AssertExpression::LegacyArithmeticNaN, Some(TypedValue::F32(actual))) => {
+            is_arithmetic_nan_f32(actual.to_bits())
+        }
+        (AssertExpression::LegacyArithmeticNaN, Some(TypedValue::F64(actual))) => {
+            is_arithmetic_nan_f64(actual.to_bits())
+        }
+        (AssertExpression::I32(_), _)
+        | (AssertExpression::I64(_), _)
+        | (AssertExpression::F32(_), _)
+        | (AssertExpression::F64(_), _)
+        | (AssertExpression::LegacyCanonicalNaN, _)
+        | (AssertExpression::LegacyArithmeticNaN, _) => false,
+        // Reference types and SIMD.
+        _ => return None,
+    };
+    Some(matched)
+}
+
+/// The state of running a single script.
+#[derive(Default)]
+struct Runner {
+    /// The instances of the modules so far, None if a module is not available.
+    instances: Vec<Option<Instance>>,
+    /// The indices of named modules in `instances`.
+    names: HashMap<String, usize>,
+}
+
+impl Runner {
+    fn add_module(&mut self, module: &mut wast::Module) -> Outcome {
+        let name = module.id.map(|id| id.name().to_string());
+        let (instance, outcome) = match parse_binary(module) {
+            Ok(module) => match module.instantiate_with_imports(spectest_imports()) {
+                Ok(instance) => (Some(instance), Outcome::Passed),
+                Err(err) if unsupported_imports(&err) => (None, Outcome::Skipped),
+                Err(err) => (
+                    None,
+                    Outcome::Failed(format!("instantiation failed: {}", err)),
+                ),
+            },
+            Err(outcome) => (None, outcome),
+        };
+        if let Some(name) = name {
+            self.names.insert(name, self.instances.len());
+        }
+        self.instances.push(instance);
+        outcome
+    }
+
+    /// The instance of the named module, or of the last module.
+    fn instance(&mut self, name: Option<wast::Id>) -> Option<&mut Instance> {
+        let index = match name {
+            Some(id) => *self.names.get(id.name())?,
+            None => self.instances.len().checked_sub(1)?,
+        };
+        self.instances[index].as_mut()
+    }
+
+    /// Invoke the function. Returns None if this is not supported.
+    fn invoke(
+        &mut self,
+        invoke: &WastInvoke,
+    ) -> Option<Result<fizzy::TypedExecutionResult, Error>> {
+        let args = invoke
+            .args
+            .iter()
+            .map(to_typed_value)
+            .collect::<Option<Vec<_>>>()?;
+        let instance = self.instance(invoke.module)?;
+        Some(instance.execute(invoke.name, &args))
+    }
+
+    fn run(&mut self, directive: WastDirective) -> Outcome {
+        match directive {
+            WastDirective::Module(mut module) => self.add_module(&mut module),
+            WastDirective::Invoke(invoke) => match self.invoke(&invoke) {
+                Some(Ok(result)) if !result.trapped() => Outcome::Passed,
+                Some(Ok(_)) => Outcome::Failed("unexpected trap".to_string()),
+                Some(Err(err)) => Outcome::Failed(format!("execution failed: {}", err)),
+                None => Outcome::Skipped,
+            },
+            WastDirective::AssertReturn {
+                exec: WastExecute::Invoke(invoke),
+                results,
+                ..
+            } => match self.invoke(&invoke) {
+                Some(Ok(result)) if result.trapped() => {
+                    Outcome::Failed("unexpected trap".to_string())
+                }
+                Some(Ok(result)) => match matches(result.value(), &results) {
+                    Some(true) => Outcome::Passed,
+                    Some(false) => Outcome::Failed(format!(
+                        "unexpected result of {}: {:?}",
+                        invoke.name,
+                        result.value()
+                    )),
+                    None => Outcome::Skipped,
+                },
+                Some(Err(err)) => Outcome::Failed(format!("execution failed: {}", err)),
+                None => Outcome::Skipped,
+            },
+            WastDirective::AssertTrap {
+                exec: WastExecute::Invoke(invoke),
+                ..
+            }
+            | WastDirective::AssertExhaustion { call: invoke, .. } => match self.invoke(&invoke) {
+                Some(Ok(result)) if result.trapped() => Outcome::Passed,
+                Some(Ok(_)) => Outcome::Failed(format!("{} did not trap", invoke.name)),
+                Some(Err(err)) => Outcome::Failed(format!("execution failed: {}", err)),
+                None => Outcome::Skipped,
+            },
+            WastDirective::AssertTrap {
+                exec: WastExecute::Module(mut module),
+                ..
+            }
+            | WastDirective::AssertUnlinkable { mut module, .. } => {
+                match parse_binary(&mut module) {
+                    Ok(module) => match module.instantiate_with_imports(spectest_imports()) {
+                        Ok(_) => Outcome::Failed("instantiation succeeded".to_string()),
+                        Err(_) => Outcome::Passed,
+                    },
+                    Err(outcome) => outcome,
+                }
+            }
+            WastDirective::AssertInvalid { mut module, .. } => match module.encode() {
+                Ok(binary) => match fizzy::parse(&binary) {
+                    Err(err) if err.is_invalid() => Outcome::Passed,
+                    Err(err) => Outcome::Failed(format!("unexpected error: {}", err)),
+                    Ok(_) => Outcome::Failed("validation succeeded".to_string()),
+                },
+                Err(err) => Outcome::Failed(format!("cannot encode module: {}", err)),
+            },
+            WastDirective::AssertMalformed {
+                module: QuoteModule::Module(mut module),
+                ..
+            } => match module.encode() {
+                Ok(binary) => match fizzy::parse(&binary) {
+                    Err(err) if err.is_malformed() => Outcome::Passed,
+                    Err(err) => Outcome::Failed(format!("unexpected error: {}", err)),
+                    Ok(_) => Outcome::Failed("parsing succeeded".to_string()),
+                },
+                Err(err) => Outcome::Failed(format!("cannot encode module: {}", err)),
+            },
+            // Registered modules can only be imported from, which is not supported.
+            WastDirective::Register { .. } => Outcome::Skipped,
+            // Modules in the text format test the text parser, not fizzy. The values of globals
+            // are not available through the API.
+            _ => Outcome::Skipped,
+        }
+    }
+}
+
+/// The line of the directive at `span` in `text`, counting from 1.
+fn line(span: wast::Span, text: &str) -> usize {
+    span.linecol_in(text).0 + 1
+}
+
+fn directive_span(directive: &WastDirective) -> wast::Span {
+    match directive {
+        WastDirective::Module(module) => module.span,
+        WastDirective::QuoteModule { span, .. }
+        | WastDirective::AssertMalformed { span, .. }
+        | WastDirective::AssertInvalid { span, .. }
+        | WastDirective::Register { span, .. }
+        | WastDirective::AssertTrap { span, .. }
+        | WastDirective::AssertReturn { span, .. }
+        | WastDirective::AssertExhaustion { span, .. }
+        | WastDirective::AssertUnlinkable { span, .. } => *span,
+        WastDirective::Invoke(invoke) => invoke.span,
+        _ => wast::Span::from_offset(0),
+    }
+}
+
+fn run_file(path: &Path) -> Stats {
+    let text = std::fs::read_to_string(path).unwrap();
+    let mut stats = Stats::default();
+    let buffer = ParseBuffer::new(&text).unwrap();
+    let script = match parser::parse::<Wast>(&buffer) {
+        Ok(script) => script,
+        Err(err) => {
+            println!("{}: cannot parse: {}", path.display(), err);
+            stats.failed += 1;
+            return stats;
+        }
+    };
+
+    let mut runner = Runner::default();
+    for directive in script.directives {
+        let line = line(directive_span(&directive), &text);
+        match runner.run(directive) {
+            Outcome::Passed => stats.passed += 1,
+            Outcome::Failed(message) => {
+                println!("{}:{}: FAILED: {}", path.display(), line, message);
+                stats.failed += 1;
+            }
+            Outcome::Skipped => stats.skipped += 1,
+        }
+    }
+    stats
+}
+
+fn wast_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("cannot read {}: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new("wast")))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+#[ignore]
+fn spectests() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let files = match std::env::var_os("FIZZY_SPECTESTS_DIR") {
+        Some(dir) => wast_files(Path::new(&dir)),
+        None => {
+            let mut files = wast_files(&manifest_dir.join("tests/spectests"));
+            files.push(manifest_dir.join("../../test/smoketests/spectests/default/smoketest.wast"));
+            files
+        }
+    };
+
+    let mut total = Stats::default();
+    for path in &files {
+        let stats = run_file(path);
+        println!(
+            "{}: PASSED {}, FAILED {}, SKIPPED {}.",
+            path.display(),
+            stats.passed,
+            stats.failed,
+            stats.skipped
+        );
+        total.passed += stats.passed;
+        total.failed += stats.failed;
+        total.skipped += stats.skipped;
+    }
+    println!(
+        "TOTAL: PASSED {}, FAILED {}, SKIPPED {}.",
+        total.passed, total.failed, total.skipped
+    );
+    assert_eq!(total.failed, 0);
+}
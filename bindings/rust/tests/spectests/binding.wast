@@ -0,0 +1,57 @@
+;; Values passed across the boundary of the binding.
+
+(module
+  (func (export "i32.id") (param i32) (result i32) (local.get 0))
+  (func (export "i64.id") (param i64) (result i64) (local.get 0))
+  (func (export "f32.id") (param f32) (result f32) (local.get 0))
+  (func (export "f64.id") (param f64) (result f64) (local.get 0))
+  (func (export "f32.add") (param f32 f32) (result f32) (f32.add (local.get 0) (local.get 1)))
+  (func (export "f64.add") (param f64 f64) (result f64) (f64.add (local.get 0) (local.get 1)))
+  (func (export "i64.extend_i32_s") (param i32) (result i64) (i64.extend_i32_s (local.get 0)))
+  (func (export "i32.trunc_f64_s") (param f64) (result i32) (i32.trunc_f64_s (local.get 0)))
+  (func (export "nop"))
+)
+
+(assert_return (invoke "i32.id" (i32.const -1)) (i32.const -1))
+(assert_return (invoke "i32.id" (i32.const 0x80000000)) (i32.const 0x80000000))
+(assert_return (invoke "i64.id" (i64.const 0x20000000000001)) (i64.const 0x20000000000001))
+(assert_return (invoke "i64.id" (i64.const -1)) (i64.const -1))
+(assert_return (invoke "f32.id" (f32.const nan:0x200000)) (f32.const nan:0x200000))
+(assert_return (invoke "f32.id" (f32.const -nan:0x1)) (f32.const -nan:0x1))
+(assert_return (invoke "f64.id" (f64.const nan:0x4000000000000)) (f64.const nan:0x4000000000000))
+(assert_return (invoke "f64.id" (f64.const -inf)) (f64.const -inf))
+(assert_return (invoke "f32.add" (f32.const inf) (f32.const -inf)) (f32.const nan:canonical))
+(assert_return (invoke "f64.add" (f64.const nan:0x1) (f64.const 1)) (f64.const nan:arithmetic))
+(assert_return (invoke "i64.extend_i32_s" (i32.const -2)) (i64.const -2))
+(assert_return (invoke "nop"))
+(invoke "nop")
+
+(assert_trap (invoke "i32.trunc_f64_s" (f64.const nan)) "invalid conversion to integer")
+(assert_trap (invoke "i32.trunc_f64_s" (f64.const 0x1p31)) "integer overflow")
+
+;; Traps and host functions.
+
+(module $host
+  (import "spectest" "print_i32" (func $print_i32 (param i32)))
+  (memory 1)
+  (func (export "print") (param i32) (call $print_i32 (local.get 0)))
+  (func (export "load") (param i32) (result i32) (i32.load (local.get 0)))
+  (func $recurse (export "recurse") (call $recurse))
+  (func (export "unreachable") (unreachable))
+)
+
+(invoke $host "print" (i32.const 42))
+(assert_return (invoke $host "load" (i32.const 65532)) (i32.const 0))
+(assert_trap (invoke $host "load" (i32.const 65533)) "out of bounds memory access")
+(assert_trap (invoke $host "unreachable") "unreachable")
+(assert_exhaustion (invoke $host "recurse") "call stack exhausted")
+
+(assert_trap (module (func $start (unreachable)) (start $start)) "unreachable")
+(assert_unlinkable (module (import "spectest" "missing" (func))) "unknown import")
+
+;; Errors of parsing and validation.
+
+(assert_invalid (module (func (result i32))) "type mismatch")
+(assert_invalid (module (func (local.get 0))) "unknown local")
+(assert_malformed (module binary "\00asm\02\00\00\00") "unknown binary version")
+(assert_malformed (module binary "\00asm\01\00\00\00\01\05\01") "unexpected end")
@@ -0,0 +1,318 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversions of values and types from and to those of
+//! [wasmtime](https://github.com/bytecodealliance/wasmtime), with the `interop-wasmtime` feature,
+//! and of [wasmi](https://github.com/paritytech/wasmi), with the `interop-wasmi` feature.
+//!
+//! Floating-point values are converted by their bits, preserving NaN payloads. The conversions
+//! from wasmtime fail for the values and types Fizzy does not support, i.e. `v128` and non-null
+//! references, and for the functions with multiple results. The reserved reference types of Fizzy
+//! are converted to the wasmtime ones, and their values to null references. wasmi has no reference
+//! types, therefore the conversions to wasmi panic for them.
+
+#[cfg(feature = "interop-wasmi")]
+mod wasmi_types {
+    use crate::{FunctionType, TypedValue, ValueType};
+    use wasmi::nan_preserving_float::{F32, F64};
+
+    impl From<TypedValue> for wasmi::RuntimeValue {
+        fn from(value: TypedValue) -> Self {
+            match value {
+                TypedValue::U32(v) => wasmi::RuntimeValue::I32(v as i32),
+                TypedValue::U64(v) => wasmi::RuntimeValue::I64(v as i64),
+                TypedValue::F32(v) => wasmi::RuntimeValue::F32(F32::from_bits(v.to_bits())),
+                TypedValue::F64(v) => wasmi::RuntimeValue::F64(F64::from_bits(v.to_bits())),
+                TypedValue::FuncRef | TypedValue::ExternRef => {
+                    panic!("wasmi does not support the reference types")
+                }
+            }
+        }
+    }
+
+    impl From<wasmi::RuntimeValue> for TypedValue {
+        fn from(value: wasmi::RuntimeValue) -> Self {
+            match value {
+                wasmi::RuntimeValue::I32(v) => TypedValue::U32(v as u32),
+                wasmi::RuntimeValue::I64(v) => TypedValue::U64(v as u64),
+                wasmi::RuntimeValue::F32(v) => TypedValue::F32(f32::from_bits(v.to_bits())),
+                wasmi::RuntimeValue::F64(v) => TypedValue::F64(f64::from_bits(v.to_bits())),
+            }
+        }
+    }
+
+    impl From<ValueType> for wasmi::ValueType {
+        fn from(value_type: ValueType) -> Self {
+            match value_type {
+                ValueType::I32 => wasmi::ValueType::I32,
+                ValueType::I64 => wasmi::ValueType::I64,
+                ValueType::F32 => wasmi::ValueType::F32,
+                ValueType::F64 => wasmi::ValueType::F64,
+                ValueType::FuncRef | ValueType::ExternRef => {
+                    panic!("wasmi does not support the reference types")
+                }
+            }
+        }
+    }
+
+    impl From<wasmi::ValueType> for ValueType {
+        fn from(value_type: wasmi::ValueType) -> Self {
+            match value_type {
+                wasmi::ValueType::I32 => ValueType::I32,
+                wasmi::ValueType::I64 => ValueType::I64,
+                wasmi::ValueType::F32 => ValueType::F32,
+                wasmi::ValueType::F64 => ValueType::F64,
+            }
+        }
+    }
+
+    impl From<&FunctionType> for wasmi::Signature {
+        fn from(func_type: &FunctionType) -> Self {
+            let params: Vec<wasmi::ValueType> =
+                func_type.inputs.iter().map(|&input| input.into()).collect();
+            wasmi::Signature::new(params, func_type.output.map(Into::into))
+        }
+    }
+
+    impl From<&wasmi::Signature> for FunctionType {
+        fn from(signature: &wasmi::Signature) -> Self {
+            FunctionType::new(
+                signature
+                    .params()
+                    .iter()
+                    .map(|&param| param.into())
+                    .collect(),
+                signature.return_type().map(Into::into),
+            )
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn values() {
+            let values = [
+                TypedValue::U32(u32::MAX),
+                TypedValue::U64(1 << 63),
+                TypedValue::F32(f32::from_bits(0x7fa0_0001)),
+                TypedValue::F64(f64::from_bits(0xfff0_0000_0000_0bad)),
+            ];
+            for &value in &values {
+                let converted = wasmi::RuntimeValue::from(value);
+                assert_eq!(converted.value_type(), value.value_type().into());
+                assert!(TypedValue::from(converted).same_bits(&value), "{:?}", value);
+            }
+            assert_eq!(
+                wasmi::RuntimeValue::from(TypedValue::U32(u32::MAX)),
+                wasmi::RuntimeValue::I32(-1)
+            );
+        }
+
+        #[test]
+        fn function_types() {
+            let func_type = FunctionType::new(
+                vec![
+                    ValueType::I32,
+                    ValueType::F64,
+                    ValueType::I64,
+                    ValueType::F32,
+                ],
+                Some(ValueType::F64),
+            );
+            let signature = wasmi::Signature::from(&func_type);
+            assert_eq!(
+                signature.params(),
+                [
+                    wasmi::ValueType::I32,
+                    wasmi::ValueType::F64,
+                    wasmi::ValueType::I64,
+                    wasmi::ValueType::F32
+                ]
+            );
+            assert_eq!(signature.return_type(), Some(wasmi::ValueType::F64));
+            assert_eq!(FunctionType::from(&signature), func_type);
+
+            let void = FunctionType::new(Vec::new(), None);
+            assert_eq!(FunctionType::from(&wasmi::Signature::from(&void)), void);
+        }
+    }
+}
+
+#[cfg(feature = "interop-wasmtime")]
+mod wasmtime_types {
+    use crate::{FunctionType, TypeError, TypedValue, ValueType};
+    use std::convert::{TryFrom, TryInto};
+
+    impl From<TypedValue> for wasmtime::Val {
+        fn from(value: TypedValue) -> Self {
+            match value {
+                TypedValue::U32(v) => wasmtime::Val::I32(v as i32),
+                TypedValue::U64(v) => wasmtime::Val::I64(v as i64),
+                TypedValue::F32(v) => wasmtime::Val::F32(v.to_bits()),
+                TypedValue::F64(v) => wasmtime::Val::F64(v.to_bits()),
+                TypedValue::FuncRef => wasmtime::Val::FuncRef(None),
+                TypedValue::ExternRef => wasmtime::Val::ExternRef(None),
+            }
+        }
+    }
+
+    impl TryFrom<wasmtime::Val> for TypedValue {
+        type Error = TypeError;
+
+        fn try_from(value: wasmtime::Val) -> Result<Self, TypeError> {
+            match value {
+                wasmtime::Val::I32(v) => Ok(TypedValue::U32(v as u32)),
+                wasmtime::Val::I64(v) => Ok(TypedValue::U64(v as u64)),
+                wasmtime::Val::F32(v) => Ok(TypedValue::F32(f32::from_bits(v))),
+                wasmtime::Val::F64(v) => Ok(TypedValue::F64(f64::from_bits(v))),
+                wasmtime::Val::FuncRef(None) => Ok(TypedValue::FuncRef),
+                wasmtime::Val::ExternRef(None) => Ok(TypedValue::ExternRef),
+                other => Err(unsupported(&other.ty())),
+            }
+        }
+    }
+
+    impl From<ValueType> for wasmtime::ValType {
+        fn from(value_type: ValueType) -> Self {
+            match value_type {
+                ValueType::I32 => wasmtime::ValType::I32,
+                ValueType::I64 => wasmtime::ValType::I64,
+                ValueType::F32 => wasmtime::ValType::F32,
+                ValueType::F64 => wasmtime::ValType::F64,
+                ValueType::FuncRef => wasmtime::ValType::FuncRef,
+                ValueType::ExternRef => wasmtime::ValType::ExternRef,
+            }
+        }
+    }
+
+    impl TryFrom<wasmtime::ValType> for ValueType {
+        type Error = TypeError;
+
+        fn try_from(value_type: wasmtime::ValType) -> Result<Self, TypeError> {
+            match value_type {
+                wasmtime::ValType::I32 => Ok(ValueType::I32),
+                wasmtime::ValType::I64 => Ok(ValueType::I64),
+                wasmtime::ValType::F32 => Ok(ValueType::F32),
+                wasmtime::ValType::F64 => Ok(ValueType::F64),
+                wasmtime::ValType::FuncRef => Ok(ValueType::FuncRef),
+                wasmtime::ValType::ExternRef => Ok(ValueType::ExternRef),
+                other => Err(unsupported(&other)),
+            }
+        }
+    }
+
+    impl From<&FunctionType> for wasmtime::FuncType {
+        fn from(func_type: &FunctionType) -> Self {
+            wasmtime::FuncType::new(
+                func_type.inputs.iter().map(|&input| input.into()),
+                func_type.output.map(Into::into),
+            )
+        }
+    }
+
+    impl TryFrom<&wasmtime::FuncType> for FunctionType {
+        type Error = TypeError;
+
+        fn try_from(func_type: &wasmtime::FuncType) -> Result<Self, TypeError> {
+            let inputs = func_type
+                .params()
+                .map(TryInto::try_into)
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut results = func_type.results();
+            if results.len() > 1 {
+                return Err(TypeError(
+                    "functions with multiple results are not supported by Fizzy".to_string(),
+                ));
+            }
+            let output = results.next().map(TryInto::try_into).transpose()?;
+            Ok(FunctionType::new(inputs, output))
+        }
+    }
+
+    fn unsupported(value_type: &wasmtime::ValType) -> TypeError {
+        TypeError(format!(
+            "the value type {} is not supported by Fizzy",
+            value_type
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn values() {
+            let values = [
+                TypedValue::U32(u32::MAX),
+                TypedValue::U64(1 << 63),
+                TypedValue::F32(f32::from_bits(0x7fa0_0001)),
+                TypedValue::F64(f64::from_bits(0xfff0_0000_0000_0bad)),
+                TypedValue::FuncRef,
+                TypedValue::ExternRef,
+            ];
+            for &value in &values {
+                let converted = wasmtime::Val::from(value);
+                assert_eq!(
+                    ValueType::try_from(converted.ty()).unwrap(),
+                    value.value_type()
+                );
+                assert!(
+                    TypedValue::try_from(converted).unwrap().same_bits(&value),
+                    "{:?}",
+                    value
+                );
+            }
+            assert!(matches!(
+                wasmtime::Val::from(TypedValue::F32(f32::from_bits(0x7fa0_0001))),
+                wasmtime::Val::F32(0x7fa0_0001)
+            ));
+
+            assert_eq!(
+                ValueType::try_from(wasmtime::ValType::V128)
+                    .unwrap_err()
+                    .message(),
+                "the value type v128 is not supported by Fizzy"
+            );
+        }
+
+        #[test]
+        fn function_types() {
+            let func_type = FunctionType::new(
+                vec![
+                    ValueType::I32,
+                    ValueType::F64,
+                    ValueType::I64,
+                    ValueType::F32,
+                ],
+                Some(ValueType::F64),
+            );
+            let converted = wasmtime::FuncType::from(&func_type);
+            assert_eq!(
+                converted.params().collect::<Vec<_>>(),
+                [
+                    wasmtime::ValType::I32,
+                    wasmtime::ValType::F64,
+                    wasmtime::ValType::I64,
+                    wasmtime::ValType::F32
+                ]
+            );
+            assert_eq!(
+                converted.results().collect::<Vec<_>>(),
+                [wasmtime::ValType::F64]
+            );
+            assert_eq!(FunctionType::try_from(&converted), Ok(func_type));
+
+            let multi_value = wasmtime::FuncType::new(
+                vec![wasmtime::ValType::I32],
+                vec![wasmtime::ValType::I32, wasmtime::ValType::I64],
+            );
+            assert_eq!(
+                FunctionType::try_from(&multi_value).unwrap_err().message(),
+                "functions with multiple results are not supported by Fizzy"
+            );
+        }
+    }
+}
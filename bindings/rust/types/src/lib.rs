@@ -0,0 +1,401 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The value and function types of the [Fizzy](https://crates.io/crates/fizzy) bindings, which
+//! re-exports them unchanged.
+//!
+//! Without the default `std` feature, the crate is `no_std` and requires only `alloc`, e.g. to
+//! share the types across a protocol boundary with an environment which cannot run the engine.
+//! The `serde` feature implements `Serialize` and `Deserialize` for the types, in the same
+//! representations as the bindings.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(any(feature = "interop-wasmi", feature = "interop-wasmtime"))]
+mod interop;
+#[cfg(feature = "serde")]
+mod serialization;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A WebAssembly value type.
+///
+/// The reference types are reserved for when the engine supports them. Until then, the modules
+/// using them fail to parse, and the host functions using them fail to instantiate, with the
+/// error of the kind [`ErrorKind::UnsupportedType`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[non_exhaustive]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+    FuncRef,
+    ExternRef,
+}
+
+/// Writes the name of the type in the text format, e.g. `i32` or `funcref`.
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+            ValueType::FuncRef => "funcref",
+            ValueType::ExternRef => "externref",
+        })
+    }
+}
+
+impl ValueType {
+    /// The type of the `name` used by the text format, e.g. `i32`.
+    fn from_name(name: &str) -> Option<ValueType> {
+        match name {
+            "i32" => Some(ValueType::I32),
+            "i64" => Some(ValueType::I64),
+            "f32" => Some(ValueType::F32),
+            "f64" => Some(ValueType::F64),
+            "funcref" => Some(ValueType::FuncRef),
+            "externref" => Some(ValueType::ExternRef),
+            _ => None,
+        }
+    }
+}
+
+/// A WebAssembly function type. Only a single output is allowed in WebAssembly 1.0.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionType {
+    pub inputs: Vec<ValueType>,
+    pub output: Option<ValueType>,
+}
+
+impl FunctionType {
+    /// Create a function type of the given `inputs` and `output`.
+    pub fn new(inputs: Vec<ValueType>, output: Option<ValueType>) -> Self {
+        FunctionType { inputs, output }
+    }
+}
+
+impl core::str::FromStr for FunctionType {
+    type Err = TypeError;
+
+    /// Parse a function type written as `(i32, i64) -> f64`, or compactly as `F(iI)` with the
+    /// output before the inputs, where `i`, `I`, `f` and `F` stand for `i32`, `i64`, `f32` and
+    /// `f64`, and `v` for no output. Whitespace is ignored.
+    fn from_str(signature: &str) -> Result<Self, TypeError> {
+        let invalid = |reason: String| {
+            TypeError(format!(
+                "invalid function signature {:?}: {}",
+                signature, reason
+            ))
+        };
+        let signature: String = signature.chars().filter(|c| !c.is_whitespace()).collect();
+        let (open, close) = match (signature.find('('), signature.find(')')) {
+            (Some(open), Some(close)) if open < close => (open, close),
+            _ => return Err(invalid("expected the inputs in parentheses".to_string())),
+        };
+        let (prefix, inputs, suffix) = (
+            &signature[..open],
+            &signature[open + 1..close],
+            &signature[close + 1..],
+        );
+        if prefix.is_empty() {
+            let inputs = if inputs.is_empty() {
+                Vec::new()
+            } else {
+                inputs
+                    .split(',')
+                    .map(|name| {
+                        ValueType::from_name(name)
+                            .ok_or_else(|| invalid(format!("unknown value type {:?}", name)))
+                    })
+                    .collect::<Result<_, _>>()?
+            };
+            let output = match suffix {
+                "" => None,
+                _ => match suffix.strip_prefix("->").map(ValueType::from_name) {
+                    Some(Some(output)) => Some(output),
+                    _ => return Err(invalid(format!("unexpected output {:?}", suffix))),
+                },
+            };
+            return Ok(FunctionType::new(inputs, output));
+        }
+
+        let from_letter = |letter: char| match letter {
+            'i' => Ok(ValueType::I32),
+            'I' => Ok(ValueType::I64),
+            'f' => Ok(ValueType::F32),
+            'F' => Ok(ValueType::F64),
+            _ => Err(invalid(format!("unknown value type {:?}", letter))),
+        };
+        if !suffix.is_empty() {
+            return Err(invalid(format!("unexpected {:?} after the inputs", suffix)));
+        }
+        let output = match prefix {
+            "v" => None,
+            _ if prefix.chars().count() == 1 => Some(from_letter(prefix.chars().next().unwrap())?),
+            _ => return Err(invalid(format!("unexpected output {:?}", prefix))),
+        };
+        let inputs = inputs.chars().map(from_letter).collect::<Result<_, _>>()?;
+        Ok(FunctionType::new(inputs, output))
+    }
+}
+
+/// The limits of a memory (in pages) or a table (in elements).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+impl Limits {
+    /// Create limits of the given `min` and optional `max`.
+    pub fn new(min: u32, max: Option<u32>) -> Self {
+        Limits { min, max }
+    }
+}
+
+/// The type of a global.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GlobalType {
+    pub value_type: ValueType,
+    pub mutable: bool,
+}
+
+impl GlobalType {
+    /// Create a global type of the given `value_type` and mutability.
+    pub fn new(value_type: ValueType, mutable: bool) -> Self {
+        GlobalType {
+            value_type,
+            mutable,
+        }
+    }
+}
+
+/// The kind of an import or an export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ExternalKind {
+    Function,
+    Table,
+    Memory,
+    Global,
+}
+
+/// A WebAssembly value i32/i64/f32/f64 with its type specified.
+///
+/// The reference values are reserved for when the engine supports the reference types, and carry
+/// no reference yet. Executions with them fail with the error of the kind
+/// [`ErrorKind::UnsupportedType`].
+///
+/// # Serialization
+///
+/// With the `serde` feature, the value is serialized with its type, e.g. `{"type":"i32","value":42}`.
+/// i32 values are serialized as unsigned numbers. i64 values are serialized as strings of decimal
+/// numbers, because JSON numbers cannot represent all of them exactly. Negative i64 values are
+/// also accepted when deserializing.
+///
+/// Floating-point values are serialized by their bits, to preserve NaN payloads, as a number for
+/// f32 and as a string of a decimal number for f64, e.g. `{"type":"f64","value":"9221120237041090560"}`
+/// for the canonical NaN.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        try_from = "serialization::TypedValueRepr",
+        into = "serialization::TypedValueRepr"
+    )
+)]
+#[non_exhaustive]
+pub enum TypedValue {
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    FuncRef,
+    ExternRef,
+}
+
+impl TypedValue {
+    /// The type of the value.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            TypedValue::U32(_) => ValueType::I32,
+            TypedValue::U64(_) => ValueType::I64,
+            TypedValue::F32(_) => ValueType::F32,
+            TypedValue::F64(_) => ValueType::F64,
+            TypedValue::FuncRef => ValueType::FuncRef,
+            TypedValue::ExternRef => ValueType::ExternRef,
+        }
+    }
+
+    /// True if `other` is of the same type and bits, so that NaNs are compared by their payloads.
+    pub fn same_bits(&self, other: &TypedValue) -> bool {
+        match (self, other) {
+            (TypedValue::U32(a), TypedValue::U32(b)) => a == b,
+            (TypedValue::U64(a), TypedValue::U64(b)) => a == b,
+            (TypedValue::F32(a), TypedValue::F32(b)) => a.to_bits() == b.to_bits(),
+            (TypedValue::F64(a), TypedValue::F64(b)) => a.to_bits() == b.to_bits(),
+            (TypedValue::FuncRef, TypedValue::FuncRef)
+            | (TypedValue::ExternRef, TypedValue::ExternRef) => true,
+            _ => false,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            TypedValue::U32(v) => Some(*v as i32),
+            _ => None,
+        }
+    }
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            TypedValue::U32(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            TypedValue::U64(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            TypedValue::U64(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            TypedValue::F32(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            TypedValue::F64(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the value with its type, e.g. `i32:42` or `f64:-0.5`. The integers are written as
+/// unsigned, and the floating-point values distinguish `1.0` from `1`.
+impl fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypedValue::U32(v) => write!(f, "i32:{}", v),
+            TypedValue::U64(v) => write!(f, "i64:{}", v),
+            TypedValue::F32(v) => write!(f, "f32:{:?}", v),
+            TypedValue::F64(v) => write!(f, "f64:{:?}", v),
+            TypedValue::FuncRef => write!(f, "funcref"),
+            TypedValue::ExternRef => write!(f, "externref"),
+        }
+    }
+}
+
+/// The kind of an error of the bindings, without its details, e.g. the message or the trap.
+///
+/// With the `serde` feature, the kind is serialized as the tag of the serialized error, e.g.
+/// `"invalid_module"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    MalformedModule,
+    TextFormat,
+    InvalidModule,
+    InstantiationFailed,
+    MissingImport,
+    MemoryAllocationFailed,
+    FunctionNotFound,
+    ArgumentCountMismatch,
+    ArgumentTypeMismatch,
+    ArgumentOutOfRange,
+    NoMemoryAvailable,
+    InvalidMemoryOffsetOrSize,
+    Busy,
+    Trapped,
+    Io,
+    UnsupportedType,
+    LimitExceeded,
+    DivergenceDetected,
+    OutOfTicks,
+    UnsupportedFeature,
+    Other,
+}
+
+/// The error of parsing a type, or of converting it from the type of another runtime.
+///
+/// The bindings convert it to their error of the kind [`ErrorKind::Other`] with the same message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeError(String);
+
+impl TypeError {
+    /// The message of the error.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TypeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn function_type_from_str() {
+        let func_type =
+            FunctionType::new(vec![ValueType::I32, ValueType::I64], Some(ValueType::F64));
+        assert_eq!("(i32,i64)->f64".parse(), Ok(func_type.clone()));
+        assert_eq!("F(iI)".parse(), Ok(func_type));
+        assert_eq!(
+            "(i32,v128)".parse::<FunctionType>().unwrap_err().message(),
+            "invalid function signature \"(i32,v128)\": unknown value type \"v128\""
+        );
+    }
+
+    #[test]
+    fn same_bits() {
+        let nan = TypedValue::F64(f64::from_bits(0x7ff4_0000_0000_0001));
+        assert!(nan.same_bits(&nan));
+        assert_ne!(nan, nan);
+        assert!(!nan.same_bits(&TypedValue::F64(f64::NAN)));
+        assert!(!TypedValue::U32(0).same_bits(&TypedValue::U64(0)));
+        assert!(TypedValue::ExternRef.same_bits(&TypedValue::ExternRef));
+    }
+}
@@ -0,0 +1,60 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The serialized representations of the types which cannot be derived directly.
+
+use crate::TypedValue;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::convert::TryFrom;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub(crate) enum TypedValueRepr {
+    I32(u32),
+    /// The decimal number.
+    I64(String),
+    /// The bits.
+    F32(u32),
+    /// The decimal number of the bits.
+    F64(String),
+    FuncRef,
+    ExternRef,
+}
+
+impl From<TypedValue> for TypedValueRepr {
+    fn from(value: TypedValue) -> Self {
+        match value {
+            TypedValue::U32(v) => TypedValueRepr::I32(v),
+            TypedValue::U64(v) => TypedValueRepr::I64(v.to_string()),
+            TypedValue::F32(v) => TypedValueRepr::F32(v.to_bits()),
+            TypedValue::F64(v) => TypedValueRepr::F64(v.to_bits().to_string()),
+            TypedValue::FuncRef => TypedValueRepr::FuncRef,
+            TypedValue::ExternRef => TypedValueRepr::ExternRef,
+        }
+    }
+}
+
+impl TryFrom<TypedValueRepr> for TypedValue {
+    type Error = String;
+
+    fn try_from(repr: TypedValueRepr) -> Result<Self, String> {
+        Ok(match repr {
+            TypedValueRepr::I32(v) => TypedValue::U32(v),
+            TypedValueRepr::I64(v) => TypedValue::U64(
+                v.parse::<u64>()
+                    .or_else(|_| v.parse::<i64>().map(|v| v as u64))
+                    .map_err(|_| format!("invalid i64 value: {}", v))?,
+            ),
+            TypedValueRepr::F32(v) => TypedValue::F32(f32::from_bits(v)),
+            TypedValueRepr::F64(v) => TypedValue::F64(f64::from_bits(
+                v.parse::<u64>()
+                    .map_err(|_| format!("invalid f64 bits: {}", v))?,
+            )),
+            TypedValueRepr::FuncRef => TypedValue::FuncRef,
+            TypedValueRepr::ExternRef => TypedValue::ExternRef,
+        })
+    }
+}
@@ -42,7 +42,15 @@ enum
 {
     /// Default hard limit of the memory size (256MB) to call fizzy_instantiate() and
     /// fizzy_resolve_instantiate().
-    FizzyMemoryPagesLimitDefault = 4096
+    FizzyMemoryPagesLimitDefault = 4096,
+
+    /// Maximum hard limit of the memory size (4GB), above which fizzy_instantiate() and
+    /// fizzy_resolve_instantiate() fail.
+    FizzyMemoryPagesLimitMax = 65536,
+
+    /// Limit of the call depth, i.e. of the calls stacked up in a single execution, which is
+    /// the maximum depth accepted by fizzy_execute_with_depth_limit().
+    FizzyCallStackLimit = 2048
 };
 
 /// Error information.
@@ -233,6 +241,9 @@ typedef struct FizzyExportDescription
     /// Index of exported function or table or memory or global.
     /// #kind determines what is this the index of.
     uint32_t index;
+    /// Size of the export name in bytes, not including the terminating NUL.
+    /// The name may contain NUL characters, therefore #name is complete only up to this size.
+    size_t name_size;
 } FizzyExportDescription;
 
 /// Imported function.
@@ -334,6 +345,12 @@ uint32_t fizzy_get_import_count(const FizzyModule* module) FIZZY_NOEXCEPT;
 FizzyImportDescription fizzy_get_import_description(
     const FizzyModule* module, uint32_t import_idx) FIZZY_NOEXCEPT;
 
+/// Get number of functions in the module, including imported functions.
+///
+/// @param  module    Pointer to module. Cannot be NULL.
+/// @return           Number of functions in the module.
+uint32_t fizzy_get_function_count(const FizzyModule* module) FIZZY_NOEXCEPT;
+
 /// Get type of the function defined in the module.
 ///
 /// @param  module      Pointer to module. Cannot be NULL.
@@ -352,6 +369,14 @@ FizzyFunctionType fizzy_get_function_type(
 /// @return                 true if module has a table definition, false otherwise.
 bool fizzy_module_has_table(const FizzyModule* module) FIZZY_NOEXCEPT;
 
+/// Get limits of the table of a module.
+///
+/// @param  module          Pointer to module. Cannot be NULL.
+/// @return                 Limits of the table, either defined or imported by module.
+///
+/// @note  Behaviour is undefined if module doesn't have a table, see fizzy_module_has_table().
+FizzyLimits fizzy_get_table_limits(const FizzyModule* module) FIZZY_NOEXCEPT;
+
 /// Check whether module has a memory.
 ///
 /// @param  module          Pointer to module. Cannot be NULL.
@@ -457,6 +482,19 @@ FizzyInstance* fizzy_instantiate(const FizzyModule* module,
     const FizzyExternalGlobal* imported_globals, size_t imported_globals_size,
     uint32_t memory_pages_limit, FizzyError* error) FIZZY_NOEXCEPT;
 
+/// Instantiate a module without taking ownership of it.
+///
+/// Unlike fizzy_instantiate(), the module is not consumed and can be instantiated any number of
+/// times, without making a copy of it with fizzy_clone_module(). It must not be freed before all of
+/// its instances are freed.
+///
+/// The parameters and the return value are the same as those of fizzy_instantiate().
+FizzyInstance* fizzy_instantiate_shared(const FizzyModule* module,
+    const FizzyExternalFunction* imported_functions, size_t imported_functions_size,
+    const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memory,
+    const FizzyExternalGlobal* imported_globals, size_t imported_globals_size,
+    uint32_t memory_pages_limit, FizzyError* error) FIZZY_NOEXCEPT;
+
 /// Instantiate a module resolving imported functions.
 ///
 /// The instance takes ownership of the module, i.e. fizzy_free_module() must not be called on the
@@ -542,6 +580,51 @@ uint8_t* fizzy_get_instance_memory_data(FizzyInstance* instance) FIZZY_NOEXCEPT;
 /// @note    Function returns memory size regardless of whether memory is exported or not.
 size_t fizzy_get_instance_memory_size(FizzyInstance* instance) FIZZY_NOEXCEPT;
 
+/// Get maximum size of memory of an instance.
+///
+/// This is the maximum size declared by the module or the hard limit of memory growth, whichever
+/// is smaller, i.e. the size up to which memory.grow may succeed.
+///
+/// @param  instance    Pointer to instance. Cannot be NULL.
+/// @return             Maximum size of memory in bytes or 0 in case instance doesn't have any
+///                     memory.
+size_t fizzy_get_instance_memory_max_size(FizzyInstance* instance) FIZZY_NOEXCEPT;
+
+/// Reserve memory of an instance up to its maximum size.
+///
+/// The memory is allocated up to the maximum size declared by the module or the hard limit of
+/// memory growth, whichever is smaller. Growing the memory within this size then doesn't require
+/// reallocating it. The size of the memory is not changed.
+///
+/// @param  instance    Pointer to instance. Cannot be NULL.
+/// @return             true if memory has been reserved or instance doesn't have any memory,
+///                     false if memory allocation failed.
+///
+/// @note    Pointers to memory data obtained before this call are invalidated.
+bool fizzy_reserve_instance_memory(FizzyInstance* instance) FIZZY_NOEXCEPT;
+
+/// Grow memory of an instance like the memory.grow instruction.
+///
+/// @param  instance       Pointer to instance. Cannot be NULL.
+/// @param  delta_pages    The number of pages to add to the memory.
+/// @return                The previous size of the memory in pages, or 2^32-1 if the instance
+///                        doesn't have any memory, the new size is above the maximum size or the
+///                        hard limit of memory growth, or memory allocation failed.
+///
+/// @note    Pointers to memory data obtained before this call are invalidated.
+uint32_t fizzy_grow_instance_memory(FizzyInstance* instance, uint32_t delta_pages) FIZZY_NOEXCEPT;
+
+/// Get pointer to the value of a global of an instance.
+///
+/// @param  instance      Pointer to instance. Cannot be NULL.
+/// @param  global_idx    Global index in the global index space, including imported globals.
+///                       Behaviour is undefined if index is not valid.
+/// @return               Pointer to the value of the global, valid as long as the instance (or
+///                       the imported global) is alive.
+///
+/// @note    Modifying the value of an immutable global is undefined behaviour.
+FizzyValue* fizzy_get_instance_global(FizzyInstance* instance, uint32_t global_idx) FIZZY_NOEXCEPT;
+
 /// Find exported function by name.
 ///
 /// @param  instance        Pointer to instance. Cannot be NULL.
@@ -552,7 +635,8 @@ size_t fizzy_get_instance_memory_size(FizzyInstance* instance) FIZZY_NOEXCEPT;
 ///                         be destroyed with fizzy_free_exported_function() afterwards.
 ///                         When function is not found (false returned), this @p out_function is not
 ///                         modified, and fizzy_free_exported_function() must not be called.
-/// @return                 true if function was found, false otherwise.
+/// @return                 true if function was found, false otherwise, including when the memory
+///                         allocation of the context failed.
 bool fizzy_find_exported_function(
     FizzyInstance* instance, const char* name, FizzyExternalFunction* out_function) FIZZY_NOEXCEPT;
 
@@ -609,6 +693,113 @@ bool fizzy_find_exported_global(
 FizzyExecutionResult fizzy_execute(
     FizzyInstance* instance, uint32_t func_idx, const FizzyValue* args) FIZZY_NOEXCEPT;
 
+/// Execute module function like fizzy_execute(), with the call depth limited to @p max_depth.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @param  args        Pointer to the argument array. Can be NULL if function has 0 inputs.
+/// @param  max_depth   Maximum number of calls stacked up in the execution, including the executed
+///                     function. Values above FizzyCallStackLimit are reduced to it, therefore
+///                     FizzyCallStackLimit executes like fizzy_execute().
+/// @param  out_call_depth_exceeded  Pointer to the flag set to true if the execution has trapped
+///                                  for exceeding @p max_depth, and to false otherwise. Can be NULL.
+/// @return             Result of execution, trapped if the call depth exceeds @p max_depth.
+FizzyExecutionResult fizzy_execute_with_depth_limit(FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args, uint32_t max_depth, bool* out_call_depth_exceeded) FIZZY_NOEXCEPT;
+
+/// Execute module function from an external function, in the execution context passed to it.
+///
+/// The execution continues at the call depth of the execution calling the external function,
+/// therefore it shares its limit of the call depth.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @param  args        Pointer to the argument array. Can be NULL if function has 0 inputs.
+/// @param  ctx         Pointer to the execution context passed to the external function. Cannot be
+///                     NULL.
+/// @param  out_call_depth_exceeded  Pointer to the flag set to true if the execution has trapped
+///                                  for exceeding the limit of the call depth, and to false
+///                                  otherwise. Can be NULL.
+/// @return             Result of execution.
+FizzyExecutionResult fizzy_execute_in_context(FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args, FizzyExecutionContext* ctx,
+    bool* out_call_depth_exceeded) FIZZY_NOEXCEPT;
+
+/// Get the call depth of the execution calling an external function.
+///
+/// @param  ctx  Pointer to the execution context passed to the external function. Cannot be NULL.
+/// @return      Number of calls of module functions stacked up in the execution, including those
+///              of the executions by fizzy_execute_in_context() in the same context. The depth
+///              does not depend on the limit of the call depth of the execution.
+uint32_t fizzy_get_execution_depth(const FizzyExecutionContext* ctx) FIZZY_NOEXCEPT;
+
+/// The function called by fizzy_execute_traced() before a function is executed.
+///
+/// @param  context     Opaque pointer given in FizzyTraceHooks::context.
+/// @param  instance    Pointer to the instance of the function, which is different from the traced
+///                     instance for the functions of other instances called via imported tables.
+/// @param  func_idx    The index of the function in the module of @p instance.
+/// @param  args        Pointer to the arguments of the function.
+typedef void (*FizzyTraceEnterFn)(void* context, FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args) FIZZY_NOEXCEPT;
+
+/// The function called by fizzy_execute_traced() after a function has been executed.
+///
+/// @param  context     Opaque pointer given in FizzyTraceHooks::context.
+/// @param  instance    Pointer to the instance of the function.
+/// @param  func_idx    The index of the function in the module of @p instance.
+/// @param  result      The result of the function.
+typedef void (*FizzyTraceLeaveFn)(void* context, FizzyInstance* instance, uint32_t func_idx,
+    FizzyExecutionResult result) FIZZY_NOEXCEPT;
+
+/// The functions called by fizzy_execute_traced() on entering and leaving each executed function.
+typedef struct FizzyTraceHooks
+{
+    /// Function called before each function is executed. Cannot be NULL.
+    FizzyTraceEnterFn enter;
+    /// Function called after each function has been executed. Cannot be NULL.
+    FizzyTraceLeaveFn leave;
+    /// Opaque pointer passed to the hooks.
+    void* context;
+} FizzyTraceHooks;
+
+/// Execute module function like fizzy_execute(), calling the hooks for it and for every function
+/// it calls, including imported functions.
+///
+/// @param  instance    Pointer to module instance. Cannot be NULL.
+/// @param  args        Pointer to the argument array. Can be NULL if function has 0 inputs.
+/// @param  hooks       Pointer to the hooks. Cannot be NULL.
+/// @param  max_depth   Maximum number of calls stacked up in the execution, like in
+///                     fizzy_execute_with_depth_limit().
+/// @param  out_call_depth_exceeded  Pointer to the flag set to true if the execution has trapped
+///                                  for exceeding @p max_depth, and to false otherwise. Can be NULL.
+/// @return             Result of execution.
+///
+/// @note
+/// The execution with fizzy_execute() does not check for hooks, therefore it is not slowed down by
+/// the support of tracing. The functions executed by host functions are not traced.
+FizzyExecutionResult fizzy_execute_traced(FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args, const FizzyTraceHooks* hooks, uint32_t max_depth,
+    bool* out_call_depth_exceeded) FIZZY_NOEXCEPT;
+
+#ifdef FIZZY_TEST_OOM
+/// Make the allocations of the calling thread fail after @p count further allocations, or never
+/// if @p count is negative.
+///
+/// @note
+/// Only available in the builds with the FIZZY_TEST_OOM option, for testing the handling of
+/// allocation failures. The allocation functions of the whole program are replaced in these builds.
+void fizzy_test_fail_allocations(int64_t count) FIZZY_NOEXCEPT;
+
+/// Make the allocations of the calling thread throw an exception of a type unknown to Fizzy after
+/// @p count further allocations, or never if @p count is negative, as if thrown by an unexpected
+/// path. The allocations not allowed to throw fail instead.
+///
+/// @note
+/// Only available in the builds with the FIZZY_TEST_OOM option, for testing that no exception
+/// escapes the functions of the API. The allocations fail with std::bad_alloc again after calling
+/// fizzy_test_fail_allocations().
+void fizzy_test_throw_from_allocations(int64_t count) FIZZY_NOEXCEPT;
+#endif
+
 #ifdef __cplusplus
 }
 #endif
@@ -7,8 +7,10 @@
 #include "instantiate.hpp"
 #include "parser.hpp"
 #include <fizzy/fizzy.h>
+#include <algorithm>
 #include <cstring>
 #include <memory>
+#include <new>
 
 namespace
 {
@@ -168,6 +170,11 @@ inline fizzy::ExecutionContext& unwrap(FizzyExecutionContext* ctx) noexcept
     return *reinterpret_cast<fizzy::ExecutionContext*>(ctx);
 }
 
+inline const fizzy::ExecutionContext& unwrap(const FizzyExecutionContext* ctx) noexcept
+{
+    return *reinterpret_cast<const fizzy::ExecutionContext*>(ctx);
+}
+
 inline FizzyInstance* wrap(fizzy::Instance* instance) noexcept
 {
     return reinterpret_cast<FizzyInstance*>(instance);
@@ -215,7 +222,11 @@ inline FizzyExternalFunction wrap(fizzy::ExternalFunction external_func)
         // It must be allocated on heap otherwise the stack will explode in recursive calls.
         std::unique_ptr<fizzy::ExecutionContext> new_ctx;
         if (c_ctx == nullptr)
-            new_ctx = std::make_unique<fizzy::ExecutionContext>();
+        {
+            new_ctx.reset(new (std::nothrow) fizzy::ExecutionContext{});
+            if (!new_ctx)
+                return wrap(fizzy::Trap);
+        }
         auto& ctx = new_ctx ? *new_ctx : unwrap(c_ctx);
 
         auto* func = static_cast<fizzy::ExternalFunction*>(host_ctx);
@@ -428,7 +439,25 @@ inline FizzyImportDescription wrap(
 
 inline FizzyExportDescription wrap(const fizzy::Export& exp) noexcept
 {
-    return {exp.name.c_str(), wrap(exp.kind), exp.index};
+    return {exp.name.c_str(), wrap(exp.kind), exp.index, exp.name.size()};
+}
+
+/// Create the context of an execution limited to max_depth calls stacked up.
+inline fizzy::ExecutionContext context_with_depth_limit(uint32_t max_depth) noexcept
+{
+    // The engine traps at the depth of CallStackLimit, therefore the execution starts closer to it.
+    fizzy::ExecutionContext ctx;
+    ctx.depth = fizzy::CallStackLimit -
+                static_cast<int>(std::min(max_depth, uint32_t{fizzy::CallStackLimit}));
+    ctx.initial_depth = ctx.depth;
+    return ctx;
+}
+
+inline void report_call_depth_exceeded(
+    const fizzy::ExecutionContext& ctx, bool* out_call_depth_exceeded) noexcept
+{
+    if (out_call_depth_exceeded != nullptr)
+        *out_call_depth_exceeded = ctx.call_depth_exceeded;
 }
 }  // namespace
 
@@ -505,6 +534,11 @@ FizzyImportDescription fizzy_get_import_description(
     return wrap(module->importsec[import_idx], *module);
 }
 
+uint32_t fizzy_get_function_count(const FizzyModule* module) noexcept
+{
+    return static_cast<uint32_t>(unwrap(module)->get_function_count());
+}
+
 FizzyFunctionType fizzy_get_function_type(const FizzyModule* module, uint32_t func_idx) noexcept
 {
     return wrap(unwrap(module)->get_function_type(func_idx));
@@ -515,6 +549,13 @@ bool fizzy_module_has_table(const FizzyModule* module) noexcept
     return unwrap(module)->has_table();
 }
 
+FizzyLimits fizzy_get_table_limits(const FizzyModule* module) noexcept
+{
+    const auto* const m = unwrap(module);
+    assert(m->has_table());
+    return wrap(m->tablesec.empty() ? m->imported_table_types[0].limits : m->tablesec[0].limits);
+}
+
 bool fizzy_module_has_memory(const FizzyModule* module) noexcept
 {
     return unwrap(module)->has_memory();
@@ -555,12 +596,12 @@ bool fizzy_find_exported_function_index(
 bool fizzy_find_exported_function(
     FizzyInstance* instance, const char* name, FizzyExternalFunction* out_function) noexcept
 {
-    auto optional_func = fizzy::find_exported_function(*unwrap(instance), name);
-    if (!optional_func)
-        return false;
-
     try
     {
+        auto optional_func = fizzy::find_exported_function(*unwrap(instance), name);
+        if (!optional_func)
+            return false;
+
         *out_function = wrap(std::move(*optional_func));
         return true;
     }
@@ -640,6 +681,35 @@ FizzyInstance* fizzy_instantiate(const FizzyModule* module,
     }
 }
 
+FizzyInstance* fizzy_instantiate_shared(const FizzyModule* module,
+    const FizzyExternalFunction* imported_functions, size_t imported_functions_size,
+    const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memory,
+    const FizzyExternalGlobal* imported_globals, size_t imported_globals_size,
+    uint32_t memory_pages_limit, FizzyError* error) noexcept
+{
+    try
+    {
+        auto functions = unwrap(imported_functions, imported_functions_size);
+        auto table = unwrap(imported_table);
+        auto memory = unwrap(imported_memory);
+        auto globals = unwrap(imported_globals, imported_globals_size);
+
+        // The module is owned by the caller.
+        auto instance = fizzy::instantiate(
+            std::shared_ptr<const fizzy::Module>(unwrap(module), [](const fizzy::Module*) {}),
+            std::move(functions), std::move(table), std::move(memory), std::move(globals),
+            memory_pages_limit);
+
+        set_success(error);
+        return wrap(instance.release());
+    }
+    catch (...)
+    {
+        set_error_from_current_exception(error);
+        return nullptr;
+    }
+}
+
 FizzyInstance* fizzy_resolve_instantiate(const FizzyModule* c_module,
     const FizzyImportedFunction* c_imported_functions, size_t imported_functions_size,
     const FizzyExternalTable* imported_table, const FizzyExternalMemory* imported_memory,
@@ -698,6 +768,62 @@ size_t fizzy_get_instance_memory_size(FizzyInstance* instance) noexcept
     return memory->size();
 }
 
+size_t fizzy_get_instance_memory_max_size(FizzyInstance* instance) noexcept
+{
+    if (!unwrap(instance)->memory)
+        return 0;
+
+    return size_t{unwrap(instance)->memory_pages_limit} * fizzy::PageSize;
+}
+
+bool fizzy_reserve_instance_memory(FizzyInstance* instance) noexcept
+{
+    auto& memory = unwrap(instance)->memory;
+    if (!memory)
+        return true;
+
+    try
+    {
+        memory->reserve(size_t{unwrap(instance)->memory_pages_limit} * fizzy::PageSize);
+        return true;
+    }
+    catch (...)
+    {
+        return false;
+    }
+}
+
+uint32_t fizzy_grow_instance_memory(FizzyInstance* instance, uint32_t delta_pages) noexcept
+{
+    auto& memory = unwrap(instance)->memory;
+    if (!memory)
+        return static_cast<uint32_t>(-1);
+
+    const auto cur_pages = memory->size() / fizzy::PageSize;
+    const auto new_pages = uint64_t{cur_pages} + delta_pages;
+    if (new_pages > unwrap(instance)->memory_pages_limit)
+        return static_cast<uint32_t>(-1);
+
+    try
+    {
+        memory->resize(static_cast<size_t>(new_pages) * fizzy::PageSize);
+        return static_cast<uint32_t>(cur_pages);
+    }
+    catch (...)
+    {
+        return static_cast<uint32_t>(-1);
+    }
+}
+
+FizzyValue* fizzy_get_instance_global(FizzyInstance* instance, uint32_t global_idx) noexcept
+{
+    auto& imported_globals = unwrap(instance)->imported_globals;
+    if (global_idx < imported_globals.size())
+        return wrap(imported_globals[global_idx].value);
+
+    return wrap(&unwrap(instance)->globals[global_idx - imported_globals.size()]);
+}
+
 FizzyExecutionResult fizzy_execute(
     FizzyInstance* instance, uint32_t func_idx, const FizzyValue* args) noexcept
 {
@@ -705,4 +831,57 @@ FizzyExecutionResult fizzy_execute(
     return wrap(result);
 }
 
+FizzyExecutionResult fizzy_execute_with_depth_limit(FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args, uint32_t max_depth, bool* out_call_depth_exceeded) noexcept
+{
+    auto ctx = context_with_depth_limit(max_depth);
+    const auto result = fizzy::execute(*unwrap(instance), func_idx, unwrap(args), ctx);
+    report_call_depth_exceeded(ctx, out_call_depth_exceeded);
+    return wrap(result);
+}
+
+FizzyExecutionResult fizzy_execute_in_context(FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args, FizzyExecutionContext* c_ctx, bool* out_call_depth_exceeded) noexcept
+{
+    auto& ctx = unwrap(c_ctx);
+    // The flag reports only this execution, and the calling execution fails for exceeding the
+    // limit only if it does itself.
+    const auto call_depth_exceeded = ctx.call_depth_exceeded;
+    ctx.call_depth_exceeded = false;
+    const auto result = fizzy::execute(*unwrap(instance), func_idx, unwrap(args), ctx);
+    report_call_depth_exceeded(ctx, out_call_depth_exceeded);
+    ctx.call_depth_exceeded = call_depth_exceeded;
+    return wrap(result);
+}
+
+uint32_t fizzy_get_execution_depth(const FizzyExecutionContext* c_ctx) noexcept
+{
+    const auto& ctx = unwrap(c_ctx);
+    return static_cast<uint32_t>(ctx.depth - ctx.initial_depth);
+}
+
+FizzyExecutionResult fizzy_execute_traced(FizzyInstance* instance, uint32_t func_idx,
+    const FizzyValue* args, const FizzyTraceHooks* c_hooks, uint32_t max_depth,
+    bool* out_call_depth_exceeded) noexcept
+{
+    const fizzy::TraceHooks hooks{
+        [](void* context, fizzy::Instance& traced_instance, fizzy::FuncIdx traced_func_idx,
+            const fizzy::Value* traced_args) noexcept {
+            const auto* trace = static_cast<const FizzyTraceHooks*>(context);
+            trace->enter(
+                trace->context, wrap(&traced_instance), traced_func_idx, wrap(traced_args));
+        },
+        [](void* context, fizzy::Instance& traced_instance, fizzy::FuncIdx traced_func_idx,
+            fizzy::ExecutionResult result) noexcept {
+            const auto* trace = static_cast<const FizzyTraceHooks*>(context);
+            trace->leave(trace->context, wrap(&traced_instance), traced_func_idx, wrap(result));
+        },
+        const_cast<FizzyTraceHooks*>(c_hooks)};
+    auto ctx = context_with_depth_limit(max_depth);
+    ctx.trace_hooks = &hooks;
+    const auto result = fizzy::execute_traced(*unwrap(instance), func_idx, unwrap(args), ctx);
+    report_call_depth_exceeded(ctx, out_call_depth_exceeded);
+    return wrap(result);
+}
+
 }  // extern "C"
@@ -530,6 +530,7 @@ void branch(const Code& code, OperandStack& stack, const uint8_t*& pc, uint32_t
         stack.drop(stack_drop);
 }
 
+template <bool Traced>
 inline bool invoke_function(const FuncType& func_type, uint32_t func_idx, Instance& instance,
     OperandStack& stack, ExecutionContext& ctx) noexcept
 {
@@ -537,7 +538,8 @@ inline bool invoke_function(const FuncType& func_type, uint32_t func_idx, Instan
     assert(stack.size() >= num_args);
     const auto call_args = stack.rend() - num_args;
 
-    const auto ret = execute(instance, func_idx, call_args, ctx);
+    const auto ret = Traced ? execute_traced(instance, func_idx, call_args, ctx) :
+                              execute(instance, func_idx, call_args, ctx);
     // Bubble up traps
     if (ret.trapped)
         return false;
@@ -555,14 +557,18 @@ inline bool invoke_function(const FuncType& func_type, uint32_t func_idx, Instan
     return true;
 }
 
-}  // namespace
-
-ExecutionResult execute(
+/// The interpreter, calling execute_traced() instead of execute() for the called functions if
+/// Traced.
+template <bool Traced>
+ExecutionResult execute_function(
     Instance& instance, FuncIdx func_idx, const Value* args, ExecutionContext& ctx) noexcept
 {
     assert(ctx.depth >= 0);
     if (ctx.depth >= CallStackLimit)
+    {
+        ctx.call_depth_exceeded = true;
         return Trap;
+    }
 
     const auto& func_type = instance.module->get_function_type(func_idx);
 
@@ -577,6 +583,10 @@ ExecutionResult execute(
 
     OperandStack stack(args, func_type.inputs.size(), code.local_count,
         static_cast<size_t>(code.max_stack_height));
+    // The failed allocation of the stack is a trap instead of an exception, which cannot be
+    // thrown from the execution.
+    if (!stack.allocated())
+        return Trap;
 
     const uint8_t* pc = code.instructions.data();
 
@@ -653,7 +663,8 @@ ExecutionResult execute(
             const auto called_func_idx = read<uint32_t>(pc);
             const auto& called_func_type = instance.module->get_function_type(called_func_idx);
 
-            if (!invoke_function(called_func_type, called_func_idx, instance, stack, ctx))
+            if (!invoke_function<Traced>(
+                    called_func_type, called_func_idx, instance, stack, ctx))
                 goto trap;
             break;
         }
@@ -679,7 +690,7 @@ ExecutionResult execute(
             if (expected_type != actual_type)
                 goto trap;
 
-            if (!invoke_function(
+            if (!invoke_function<Traced>(
                     actual_type, called_func.func_idx, *called_func.instance, stack, ctx))
                 goto trap;
             break;
@@ -1591,4 +1602,22 @@ end:
 trap:
     return Trap;
 }
+}  // namespace
+
+ExecutionResult execute(
+    Instance& instance, FuncIdx func_idx, const Value* args, ExecutionContext& ctx) noexcept
+{
+    return execute_function<false>(instance, func_idx, args, ctx);
+}
+
+ExecutionResult execute_traced(
+    Instance& instance, FuncIdx func_idx, const Value* args, ExecutionContext& ctx) noexcept
+{
+    assert(ctx.trace_hooks != nullptr);
+    const auto& hooks = *ctx.trace_hooks;
+    hooks.enter(hooks.context, instance, func_idx, args);
+    const auto result = execute_function<true>(instance, func_idx, args, ctx);
+    hooks.leave(hooks.context, instance, func_idx, result);
+    return result;
+}
 }  // namespace fizzy
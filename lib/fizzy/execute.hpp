@@ -56,6 +56,30 @@ constexpr ExecutionResult Trap{false};
 ExecutionResult execute(
     Instance& instance, FuncIdx func_idx, const Value* args, ExecutionContext& ctx) noexcept;
 
+/// The functions called by execute_traced() on entering and leaving each executed function.
+struct TraceHooks
+{
+    /// Called with the instance, the function index and the arguments before the function is
+    /// executed.
+    void (*enter)(void* context, Instance& instance, FuncIdx func_idx, const Value* args) noexcept;
+
+    /// Called with the instance, the function index and the result after the function has been
+    /// executed.
+    void (*leave)(
+        void* context, Instance& instance, FuncIdx func_idx, ExecutionResult result) noexcept;
+
+    /// The context passed to the hooks.
+    void* context;
+};
+
+/// Execute a function from an instance like execute(), calling the hooks of
+/// ExecutionContext::trace_hooks, which must not be null, for this function and all functions
+/// it calls.
+///
+/// The other functions are not affected, i.e. execute() does not check for the hooks.
+ExecutionResult execute_traced(
+    Instance& instance, FuncIdx func_idx, const Value* args, ExecutionContext& ctx) noexcept;
+
 /// Execute a function from an instance with execution context starting with default depth of 0.
 /// Arguments and behavior is the same as in the other execute().
 inline ExecutionResult execute(Instance& instance, FuncIdx func_idx, const Value* args) noexcept
@@ -6,6 +6,8 @@
 
 namespace fizzy
 {
+struct TraceHooks;
+
 /// The storage for information shared by calls in the same execution "thread".
 /// Users may decide how to allocate the execution context, but some good defaults are available.
 class ExecutionContext
@@ -34,6 +36,16 @@ class ExecutionContext
 public:
     int depth = 0;  ///< Current call depth.
 
+    /// The call depth the execution has started at, which is above 0 for the executions limited
+    /// to fewer calls than CallStackLimit.
+    int initial_depth = 0;
+
+    /// Set when a call has trapped for exceeding CallStackLimit.
+    bool call_depth_exceeded = false;
+
+    /// The hooks called by execute_traced(), which must be set for it.
+    const TraceHooks* trace_hooks = nullptr;
+
     /// Increments the call depth and returns the local call context which
     /// decrements the call depth back to the original value when going out of scope.
     LocalContext create_local_context() noexcept { return LocalContext{*this}; }
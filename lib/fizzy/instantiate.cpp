@@ -326,7 +326,7 @@ ExecutionResult ExecuteFunction::operator()(
         return m_host_function(m_host_context, instance, args, ctx);
 }
 
-std::unique_ptr<Instance> instantiate(std::unique_ptr<const Module> module,
+std::unique_ptr<Instance> instantiate(std::shared_ptr<const Module> module,
     std::vector<ExternalFunction> imported_functions, std::vector<ExternalTable> imported_tables,
     std::vector<ExternalMemory> imported_memories, std::vector<ExternalGlobal> imported_globals,
     uint32_t memory_pages_limit /*= DefaultMemoryPagesLimit*/)
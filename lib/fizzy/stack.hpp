@@ -9,6 +9,7 @@
 #include <cassert>
 #include <cstdint>
 #include <memory>
+#include <new>
 #include <vector>
 
 namespace fizzy
@@ -89,6 +90,7 @@ public:
     /// Based on required storage space decides to use small pre-allocated
     /// storage or allocate large storage.
     /// Sets the top stack operand pointer to below the operand stack bottom.
+    /// If the large storage cannot be allocated, the stack is left unusable, see allocated().
     ///
     /// @param  args                   Function arguments. Values are copied at the beginning of the
     ///                                storage space.
@@ -97,8 +99,8 @@ public:
     ///                                space after the arguments.
     /// @param  max_stack_height       The maximum operand stack height in the function. This
     ///                                excludes @a args and @a num_local_variables.
-    OperandStack(
-        const Value* args, size_t num_args, size_t num_local_variables, size_t max_stack_height)
+    OperandStack(const Value* args, size_t num_args, size_t num_local_variables,
+        size_t max_stack_height) noexcept
     {
         const auto num_locals = num_args + num_local_variables;
         // To avoid potential UB when there are no locals and the stack pointer is set to
@@ -112,7 +114,12 @@ public:
         }
         else
         {
-            m_large_storage = std::make_unique<Value[]>(storage_size_required);
+            m_large_storage.reset(new (std::nothrow) Value[storage_size_required]());
+            if (!m_large_storage)
+            {
+                m_top = m_locals = m_bottom = nullptr;
+                return;
+            }
             m_locals = &m_large_storage[0];
         }
 
@@ -126,6 +133,10 @@ public:
     OperandStack(const OperandStack&) = delete;
     OperandStack& operator=(const OperandStack&) = delete;
 
+    /// Returns false if the storage could not be allocated, in which case no other method can be
+    /// called.
+    bool allocated() const noexcept { return m_locals != nullptr; }
+
     Value& local(size_t index) noexcept
     {
         assert(m_locals + index < m_bottom);
@@ -0,0 +1,91 @@
+// Fizzy: A fast WebAssembly interpreter
+// Copyright 2019-2020 The Fizzy Authors.
+// SPDX-License-Identifier: Apache-2.0
+
+// The replacements of the global allocation functions failing on demand, for testing the handling
+// of allocation failures and unexpected exceptions. Only built with FIZZY_TEST_OOM, as they replace
+// the allocation functions of the whole program.
+
+#include <fizzy/fizzy.h>
+#include <cstdlib>
+#include <new>
+
+namespace
+{
+/// The number of allocations which succeed before the allocations fail, or -1 if they never fail.
+/// Counted per thread, so that tests running in parallel are not affected.
+thread_local int64_t allocations_before_failure = -1;
+
+/// Whether the failed allocations throw an exception of an unknown type instead of std::bad_alloc.
+thread_local bool throw_unknown_exception = false;
+
+/// The exception of a type not derived from std::exception, as if thrown by an unexpected path.
+struct UnknownException
+{
+};
+
+void* allocate(std::size_t size) noexcept
+{
+    if (allocations_before_failure == 0)
+        return nullptr;
+    if (allocations_before_failure > 0)
+        --allocations_before_failure;
+    return std::malloc(size != 0 ? size : 1);
+}
+}  // namespace
+
+void* operator new(std::size_t size)
+{
+    if (auto* ptr = allocate(size); ptr != nullptr)
+        return ptr;
+    if (throw_unknown_exception)
+        throw UnknownException{};
+    throw std::bad_alloc{};
+}
+
+void* operator new[](std::size_t size)
+{
+    return operator new(size);
+}
+
+void* operator new(std::size_t size, const std::nothrow_t&) noexcept
+{
+    return allocate(size);
+}
+
+void* operator new[](std::size_t size, const std::nothrow_t&) noexcept
+{
+    return allocate(size);
+}
+
+void operator delete(void* ptr) noexcept
+{
+    std::free(ptr);
+}
+
+void operator delete[](void* ptr) noexcept
+{
+    std::free(ptr);
+}
+
+void operator delete(void* ptr, std::size_t) noexcept
+{
+    std::free(ptr);
+}
+
+void operator delete[](void* ptr, std::size_t) noexcept
+{
+    std::free(ptr);
+}
+
+void fizzy_test_fail_allocations(int64_t count) noexcept
+{
+    allocations_before_failure = count;
+    throw_unknown_exception = false;
+}
+
+void fizzy_test_throw_from_allocations(int64_t count) noexcept
+{
+    allocations_before_failure = count;
+    throw_unknown_exception = true;
+}
@@ -10,6 +10,8 @@
 using namespace fizzy::test;
 
 static_assert(FizzyMemoryPagesLimitDefault == fizzy::DefaultMemoryPagesLimit);
+static_assert(FizzyMemoryPagesLimitMax == fizzy::MaxMemoryPagesLimit);
+static_assert(FizzyCallStackLimit == fizzy::CallStackLimit);
 
 /// Represents an invalid/mocked pointer to a host function for tests without execution.
 static constexpr FizzyExternalFn NullFn = nullptr;
@@ -206,6 +208,32 @@ TEST(capi, clone_module)
     fizzy_free_module(module1);
 }
 
+TEST(capi, get_function_count)
+{
+    /* wat2wasm
+      (module)
+    */
+    const auto wasm_empty = from_hex("0061736d01000000");
+    const auto* module_empty = fizzy_parse(wasm_empty.data(), wasm_empty.size(), nullptr);
+    ASSERT_NE(module_empty, nullptr);
+
+    EXPECT_EQ(fizzy_get_function_count(module_empty), 0);
+    fizzy_free_module(module_empty);
+
+    /* wat2wasm
+      (func (import "mod" "f"))
+      (func)
+      (func (param i32))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001080260000060017f00020901036d6f640166000003030200010a070202000b02000b");
+    const auto* module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    EXPECT_EQ(fizzy_get_function_count(module), 3);
+    fizzy_free_module(module);
+}
+
 TEST(capi, get_function_type)
 {
     /* wat2wasm
@@ -264,6 +292,9 @@ TEST(capi, has_table)
     ASSERT_NE(module_table, nullptr);
 
     EXPECT_TRUE(fizzy_module_has_table(module_table));
+    const auto limits = fizzy_get_table_limits(module_table);
+    EXPECT_EQ(limits.min, 0);
+    EXPECT_FALSE(limits.has_max);
 
     fizzy_free_module(module_table);
 
@@ -276,6 +307,10 @@ TEST(capi, has_table)
     ASSERT_NE(module_imported_table, nullptr);
 
     EXPECT_TRUE(fizzy_module_has_table(module_imported_table));
+    const auto imported_limits = fizzy_get_table_limits(module_imported_table);
+    EXPECT_EQ(imported_limits.min, 10);
+    EXPECT_TRUE(imported_limits.has_max);
+    EXPECT_EQ(imported_limits.max, 30);
 
     fizzy_free_module(module_imported_table);
 }
@@ -398,6 +433,32 @@ TEST(capi, get_export_description)
     EXPECT_STREQ(export3.name, "glob");
     EXPECT_EQ(export3.kind, FizzyExternalKindGlobal);
     EXPECT_EQ(export3.index, 2);
+    EXPECT_EQ(export3.name_size, 4);
+
+    fizzy_free_module(module);
+}
+
+TEST(capi, get_export_description_name_with_nul)
+{
+    /* wat2wasm
+      (func (export "a\00b") (result i32) (i32.const 1))
+      (func (export "a") (result i32) (i32.const 2))
+    */
+    const auto wasm = from_hex(
+        "0061736d010000000105016000017f0303020000070b02036100620000016100010a0b02040041010b040041020"
+        "b");
+
+    const auto* module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+    ASSERT_EQ(fizzy_get_export_count(module), 2);
+
+    const auto export0 = fizzy_get_export_description(module, 0);
+    EXPECT_EQ(std::string(export0.name, export0.name_size), std::string("a\0b", 3));
+    EXPECT_EQ(export0.index, 0);
+
+    const auto export1 = fizzy_get_export_description(module, 1);
+    EXPECT_EQ(std::string(export1.name, export1.name_size), "a");
+    EXPECT_EQ(export1.index, 1);
 
     fizzy_free_module(module);
 }
@@ -703,6 +764,38 @@ TEST(capi, instantiate)
     fizzy_free_instance(instance);
 }
 
+TEST(capi, instantiate_shared)
+{
+    /* wat2wasm
+      (memory 1)
+      (data (i32.const 0) "\2a")
+    */
+    const auto wasm = from_hex("0061736d0100000005030100010b07010041000b012a");
+    const auto* module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    FizzyError error;
+    auto instance1 = fizzy_instantiate_shared(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, &error);
+    ASSERT_NE(instance1, nullptr);
+    EXPECT_EQ(error.code, FizzySuccess);
+    auto instance2 = fizzy_instantiate_shared(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance2, nullptr);
+    EXPECT_EQ(fizzy_get_instance_module(instance1), module);
+    EXPECT_EQ(fizzy_get_instance_module(instance2), module);
+
+    // The instances are independent.
+    fizzy_get_instance_memory_data(instance1)[0] = 0x11;
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance2)[0], 0x2a);
+
+    fizzy_free_instance(instance1);
+    // The module is still usable after freeing one of its instances.
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance2)[0], 0x2a);
+    fizzy_free_instance(instance2);
+    fizzy_free_module(module);
+}
+
 TEST(capi, instantiate_imported_function)
 {
     /* wat2wasm
@@ -1383,6 +1476,176 @@ TEST(capi, imported_memory_access)
     fizzy_free_instance(instance_memory);
 }
 
+TEST(capi, get_instance_memory_max_size)
+{
+    /* wat2wasm
+      (memory 1 3)
+    */
+    const auto wasm = from_hex("0061736d01000000050401010103");
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(fizzy_get_instance_memory_max_size(instance), 3 * 65536);
+    fizzy_free_instance(instance);
+
+    /* wat2wasm
+      (memory 1)
+    */
+    const auto wasm_no_max = from_hex("0061736d010000000503010001");
+    module = fizzy_parse(wasm_no_max.data(), wasm_no_max.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    instance = fizzy_instantiate(module, nullptr, 0, nullptr, nullptr, nullptr, 0, 4, nullptr);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(fizzy_get_instance_memory_max_size(instance), 4 * 65536);
+    fizzy_free_instance(instance);
+
+    /* wat2wasm
+      (func)
+    */
+    const auto wasm_no_memory = from_hex("0061736d01000000010401600000030201000a040102000b");
+    module = fizzy_parse(wasm_no_memory.data(), wasm_no_memory.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    instance = fizzy_instantiate(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+    EXPECT_EQ(fizzy_get_instance_memory_max_size(instance), 0);
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, reserve_instance_memory)
+{
+    /* wat2wasm
+      (memory 1 3)
+      (data (i32.const 1) "\11\22")
+      (func (param i32) (result i32)
+        local.get 0
+        memory.grow
+      )
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001060160017f017f030201000504010101030a08010600200040000b0b08010041010b0211"
+        "22");
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    EXPECT_TRUE(fizzy_reserve_instance_memory(instance));
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 65536);
+    uint8_t* memory = fizzy_get_instance_memory_data(instance);
+    ASSERT_NE(memory, nullptr);
+    EXPECT_EQ(memory[1], 0x11);
+    EXPECT_EQ(memory[2], 0x22);
+
+    FizzyValue arg = {2};
+    EXPECT_THAT(fizzy_execute(instance, 0, &arg), CResult(1_u32));
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 3 * 65536);
+    // Growing within the reserved size doesn't move the memory.
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance), memory);
+    EXPECT_EQ(memory[1], 0x11);
+    EXPECT_EQ(memory[2], 0x22);
+
+    // The maximum size is still enforced.
+    arg.i32 = 1;
+    EXPECT_THAT(fizzy_execute(instance, 0, &arg), CResult(0xffffffff_u32));
+
+    fizzy_free_instance(instance);
+
+    /* wat2wasm
+      (func)
+    */
+    const auto wasm_no_memory = from_hex("0061736d01000000010401600000030201000a040102000b");
+    auto module_no_memory = fizzy_parse(wasm_no_memory.data(), wasm_no_memory.size(), nullptr);
+    ASSERT_NE(module_no_memory, nullptr);
+
+    auto instance_no_memory = fizzy_instantiate(module_no_memory, nullptr, 0, nullptr, nullptr,
+        nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance_no_memory, nullptr);
+
+    EXPECT_TRUE(fizzy_reserve_instance_memory(instance_no_memory));
+    EXPECT_EQ(fizzy_get_instance_memory_data(instance_no_memory), nullptr);
+
+    fizzy_free_instance(instance_no_memory);
+}
+
+TEST(capi, grow_instance_memory)
+{
+    /* wat2wasm
+      (memory 1 3)
+      (data (i32.const 1) "\11\22")
+    */
+    const auto wasm = from_hex("0061736d010000000504010101030b08010041010b021122");
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    EXPECT_EQ(fizzy_grow_instance_memory(instance, 0), 1);
+    EXPECT_EQ(fizzy_grow_instance_memory(instance, 2), 1);
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 3 * 65536);
+    const uint8_t* memory = fizzy_get_instance_memory_data(instance);
+    EXPECT_EQ(memory[1], 0x11);
+    EXPECT_EQ(memory[2], 0x22);
+    EXPECT_EQ(memory[65536], 0);
+
+    // The maximum size is enforced.
+    EXPECT_EQ(fizzy_grow_instance_memory(instance, 1), 0xffffffff);
+    EXPECT_EQ(fizzy_get_instance_memory_size(instance), 3 * 65536);
+
+    fizzy_free_instance(instance);
+
+    /* wat2wasm
+      (func)
+    */
+    const auto wasm_no_memory = from_hex("0061736d01000000010401600000030201000a040102000b");
+    auto module_no_memory = fizzy_parse(wasm_no_memory.data(), wasm_no_memory.size(), nullptr);
+    ASSERT_NE(module_no_memory, nullptr);
+
+    auto instance_no_memory = fizzy_instantiate(module_no_memory, nullptr, 0, nullptr, nullptr,
+        nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance_no_memory, nullptr);
+    EXPECT_EQ(fizzy_grow_instance_memory(instance_no_memory, 0), 0xffffffff);
+
+    fizzy_free_instance(instance_no_memory);
+}
+
+TEST(capi, get_instance_global)
+{
+    /* wat2wasm
+      (global (import "env" "g") (mut i32))
+      (global (mut i64) (i64.const 42))
+      (global f32 (f32.const 1.5))
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000020a0103656e760167037f01060e027e01422a0b7d00430000c03f0b");
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    FizzyValue imported_value = {7};
+    const FizzyExternalGlobal imported_global = {&imported_value, {FizzyValueTypeI32, true}};
+    auto instance = fizzy_instantiate(module, nullptr, 0, nullptr, nullptr, &imported_global, 1,
+        FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    EXPECT_EQ(fizzy_get_instance_global(instance, 0), &imported_value);
+    EXPECT_EQ(fizzy_get_instance_global(instance, 1)->i64, 42);
+    EXPECT_EQ(fizzy_get_instance_global(instance, 2)->f32, 1.5f);
+
+    fizzy_get_instance_global(instance, 1)->i64 = 43;
+    EXPECT_EQ(fizzy_get_instance_global(instance, 1)->i64, 43);
+
+    fizzy_free_instance(instance);
+}
+
 TEST(capi, execute)
 {
     /* wat2wasm
@@ -1413,6 +1676,201 @@ TEST(capi, execute)
     fizzy_free_instance(instance);
 }
 
+TEST(capi, execute_with_depth_limit)
+{
+    /* wat2wasm
+      (func $rec (param i32)
+        (if (local.get 0) (then (call $rec (i32.sub (local.get 0) (i32.const 1))))))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001050160017f00030201000a10010e0020000440200041016b10000b0b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    // The execution of rec(n) stacks up n + 1 calls.
+    bool call_depth_exceeded = true;
+    FizzyValue args[] = {{9}};
+    EXPECT_THAT(
+        fizzy_execute_with_depth_limit(instance, 0, args, 10, &call_depth_exceeded), CResult());
+    EXPECT_FALSE(call_depth_exceeded);
+    args[0].i32 = 10;
+    EXPECT_THAT(
+        fizzy_execute_with_depth_limit(instance, 0, args, 10, &call_depth_exceeded), CTraps());
+    EXPECT_TRUE(call_depth_exceeded);
+    EXPECT_THAT(fizzy_execute(instance, 0, args), CResult());
+    args[0].i32 = 0;
+    EXPECT_THAT(fizzy_execute_with_depth_limit(instance, 0, args, 0, nullptr), CTraps());
+
+    constexpr auto unlimited = std::numeric_limits<uint32_t>::max();
+    args[0].i32 = FizzyCallStackLimit - 1;
+    EXPECT_THAT(fizzy_execute_with_depth_limit(instance, 0, args, unlimited, nullptr), CResult());
+    args[0].i32 = FizzyCallStackLimit;
+    EXPECT_THAT(fizzy_execute_with_depth_limit(instance, 0, args, unlimited, nullptr), CTraps());
+    EXPECT_THAT(fizzy_execute(instance, 0, args), CTraps());
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, execute_in_context)
+{
+    /* wat2wasm
+      (func $host (import "env" "host") (param i32))
+      (func $rec (param i32)
+        (if (local.get 0) (then (call $rec (i32.sub (local.get 0) (i32.const 1))))))
+      (func (param i32) (call $host (local.get 0)))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001050160017f00020c0103656e7604686f7374000003030200000a17020e00200004402000"
+        "41016b10010b0b0600200010000b");
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    // The host function executes rec(n) in the execution context of its call.
+    struct Host
+    {
+        FizzyInstance* instance = nullptr;
+        bool call_depth_exceeded = false;
+    } host;
+    const FizzyValueType inputs[] = {FizzyValueTypeI32};
+    FizzyExternalFunction host_funcs[] = {{{FizzyValueTypeVoid, &inputs[0], 1},
+        [](void* context, FizzyInstance*, const FizzyValue* args,
+            FizzyExecutionContext* ctx) noexcept {
+            auto& h = *static_cast<Host*>(context);
+            return fizzy_execute_in_context(h.instance, 1, args, ctx, &h.call_depth_exceeded);
+        },
+        &host}};
+
+    auto instance = fizzy_instantiate(
+        module, host_funcs, 1, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+    host.instance = instance;
+
+    // The calling function and rec(n) stack up n + 2 calls.
+    bool call_depth_exceeded = true;
+    FizzyValue args[] = {{8}};
+    EXPECT_THAT(
+        fizzy_execute_with_depth_limit(instance, 2, args, 10, &call_depth_exceeded), CResult());
+    EXPECT_FALSE(call_depth_exceeded);
+    EXPECT_FALSE(host.call_depth_exceeded);
+
+    // The trap of the host function is reported instead of the nested execution.
+    args[0].i32 = 9;
+    EXPECT_THAT(
+        fizzy_execute_with_depth_limit(instance, 2, args, 10, &call_depth_exceeded), CTraps());
+    EXPECT_FALSE(call_depth_exceeded);
+    EXPECT_TRUE(host.call_depth_exceeded);
+
+    EXPECT_THAT(fizzy_execute(instance, 2, args), CResult());
+    EXPECT_FALSE(host.call_depth_exceeded);
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, get_execution_depth)
+{
+    /* wat2wasm
+      (func $host (import "env" "host"))
+      (func (call $host))
+      (func (call 1))
+    */
+    const auto wasm = from_hex(
+        "0061736d01000000010401600000020c0103656e7604686f7374000003030200000a0b02040010000b04001001"
+        "0b");
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    std::vector<uint32_t> depths;
+    FizzyExternalFunction host_funcs[] = {{{FizzyValueTypeVoid, nullptr, 0},
+        [](void* context, FizzyInstance*, const FizzyValue*, FizzyExecutionContext* ctx) noexcept {
+            auto& d = *static_cast<std::vector<uint32_t>*>(context);
+            d.push_back(fizzy_get_execution_depth(ctx));
+            return FizzyExecutionResult{false, false, {0}};
+        },
+        &depths}};
+
+    auto instance = fizzy_instantiate(
+        module, host_funcs, 1, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    // The host function is called at the depth of the calling functions, whatever the limit.
+    EXPECT_THAT(fizzy_execute(instance, 0, nullptr), CResult());
+    EXPECT_THAT(fizzy_execute(instance, 1, nullptr), CResult());
+    EXPECT_THAT(fizzy_execute_with_depth_limit(instance, 2, nullptr, 10, nullptr), CResult());
+    EXPECT_THAT(fizzy_execute_with_depth_limit(instance, 2, nullptr, 3, nullptr), CResult());
+    EXPECT_EQ(depths, (std::vector<uint32_t>{0, 1, 2, 2}));
+
+    fizzy_free_instance(instance);
+}
+
+TEST(capi, execute_traced)
+{
+    /* wat2wasm
+      (func $fac (param i32) (result i32)
+        (if (result i32) (i32.eqz (local.get 0))
+          (then (i32.const 1))
+          (else (i32.mul (local.get 0) (call $fac (i32.sub (local.get 0) (i32.const 1)))))))
+      (func (drop (call $fac (i32.const 1))) (unreachable))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001090260017f017f60000003030200010a20021500200045047f4101052000200041016b10"
+        "006c0b0b0800410110001a000b");
+
+    auto module = fizzy_parse(wasm.data(), wasm.size(), nullptr);
+    ASSERT_NE(module, nullptr);
+
+    auto instance = fizzy_instantiate(
+        module, nullptr, 0, nullptr, nullptr, nullptr, 0, FizzyMemoryPagesLimitDefault, nullptr);
+    ASSERT_NE(instance, nullptr);
+
+    struct Trace
+    {
+        FizzyInstance* instance = nullptr;
+        std::vector<uint32_t> entered;
+        std::vector<bool> trapped;
+    } trace;
+    const FizzyTraceHooks hooks{
+        [](void* context, FizzyInstance* traced_instance, uint32_t func_idx,
+            const FizzyValue*) noexcept {
+            auto& t = *static_cast<Trace*>(context);
+            EXPECT_EQ(traced_instance, t.instance);
+            t.entered.push_back(func_idx);
+        },
+        [](void* context, FizzyInstance*, uint32_t, FizzyExecutionResult result) noexcept {
+            static_cast<Trace*>(context)->trapped.push_back(result.trapped);
+        },
+        &trace};
+    trace.instance = instance;
+
+    FizzyValue args[] = {{2}};
+    EXPECT_THAT(fizzy_execute_traced(instance, 0, args, &hooks, FizzyCallStackLimit, nullptr),
+        CResult(2_u32));
+    EXPECT_EQ(trace.entered, (std::vector<uint32_t>{0, 0, 0}));
+    EXPECT_EQ(trace.trapped, (std::vector<bool>{false, false, false}));
+
+    trace.entered.clear();
+    trace.trapped.clear();
+    EXPECT_THAT(fizzy_execute_traced(instance, 1, nullptr, &hooks, FizzyCallStackLimit, nullptr),
+        CTraps());
+    EXPECT_EQ(trace.entered, (std::vector<uint32_t>{1, 0, 0}));
+    EXPECT_EQ(trace.trapped, (std::vector<bool>{false, false, true}));
+
+    // The call of fac(0) exceeds the limit.
+    trace.entered.clear();
+    trace.trapped.clear();
+    bool call_depth_exceeded = false;
+    EXPECT_THAT(fizzy_execute_traced(instance, 0, args, &hooks, 2, &call_depth_exceeded), CTraps());
+    EXPECT_TRUE(call_depth_exceeded);
+    EXPECT_EQ(trace.entered, (std::vector<uint32_t>{0, 0, 0}));
+    EXPECT_EQ(trace.trapped, (std::vector<bool>{true, true, true}));
+
+    fizzy_free_instance(instance);
+}
+
 TEST(capi, execute_with_host_function)
 {
     /* wat2wasm
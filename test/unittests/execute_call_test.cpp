@@ -890,3 +890,50 @@ TEST(execute_call, drop_call_result)
     auto instance = instantiate(*module);
     EXPECT_THAT(execute(*instance, *func_idx, {}), Result());
 }
+
+TEST(execute_call, execute_traced)
+{
+    /* wat2wasm
+    (func $fac (param i32) (result i32)
+      (if (result i32) (i32.eqz (local.get 0))
+        (then (i32.const 1))
+        (else (i32.mul (local.get 0) (call $fac (i32.sub (local.get 0) (i32.const 1)))))))
+    (func (drop (call $fac (i32.const 1))) (unreachable))
+    */
+    const auto wasm = from_hex(
+        "0061736d0100000001090260017f017f60000003030200010a20021500200045047f4101052000200041016b10"
+        "006c0b0b0800410110001a000b");
+    auto instance = instantiate(parse(wasm));
+
+    std::vector<std::string> trace;
+    const TraceHooks hooks{
+        [](void* context, Instance&, FuncIdx func_idx, const Value* args) noexcept {
+            auto& events = *static_cast<std::vector<std::string>*>(context);
+            events.push_back("enter " + std::to_string(func_idx) +
+                             (func_idx == 0 ? " " + std::to_string(args[0].i32) : ""));
+        },
+        [](void* context, Instance&, FuncIdx func_idx, ExecutionResult result) noexcept {
+            auto& events = *static_cast<std::vector<std::string>*>(context);
+            events.push_back("leave " + std::to_string(func_idx) + " " +
+                             (result.trapped ? "trap" : std::to_string(result.value.i32)));
+        },
+        &trace};
+    ExecutionContext ctx;
+    ctx.trace_hooks = &hooks;
+
+    const Value args[] = {2};
+    EXPECT_THAT(execute_traced(*instance, 0, args, ctx), Result(2));
+    EXPECT_EQ(trace, (std::vector<std::string>{"enter 0 2", "enter 0 1", "enter 0 0", "leave 0 1",
+                         "leave 0 1", "leave 0 2"}));
+    EXPECT_EQ(ctx.depth, 0);
+
+    trace.clear();
+    EXPECT_THAT(execute_traced(*instance, 1, nullptr, ctx), Traps());
+    EXPECT_EQ(trace, (std::vector<std::string>{"enter 1", "enter 0 1", "enter 0 0", "leave 0 1",
+                         "leave 0 1", "leave 1 trap"}));
+
+    // Untraced execution does not call the hooks.
+    trace.clear();
+    EXPECT_THAT(execute(*instance, 0, args, ctx), Result(2));
+    EXPECT_TRUE(trace.empty());
+}